@@ -0,0 +1,13 @@
+#version 450
+
+layout(location = 0) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+void main() {
+    outColor = fragColor;
+    // The lines are not objects, so they have the object id of the pixels without any object
+    outObjectId = uvec2(0, 0);
+}
\ No newline at end of file
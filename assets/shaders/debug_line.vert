@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 inColor;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+layout(location = 0) out vec4 fragColor;
+
+void main() {
+    gl_Position = globalFrameData.viewProj * vec4(inPosition, 1.0);
+    fragColor = inColor;
+}
\ No newline at end of file
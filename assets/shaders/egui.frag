@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+layout(set = 1, binding = 0) uniform sampler2D eguiTexture;
+
+void main() {
+    outColor = fragColor * texture(eguiTexture, fragTexCoord);
+    // So the objects can still be picked through the empty parts of the meshes, like the space around the glyphs
+    if (outColor.a == 0.0) {
+        discard;
+    }
+    outObjectId = uvec2(0, 0);
+}
\ No newline at end of file
@@ -0,0 +1,34 @@
+#version 450
+
+// In pixels from the top left of the render area
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inTexCoord;
+// sRGB with premultiplied alpha, like egui gives it
+layout(location = 2) in vec4 inColor;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+
+// The scene is drawn in linear color, and the textures are converted by their sRGB format when they are sampled
+vec3 linearFromSrgb(vec3 srgb) {
+    bvec3 isLow = lessThan(srgb, vec3(0.04045));
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, isLow);
+}
+
+void main() {
+    gl_Position = vec4(inPosition / globalFrameData.viewportSize * 2.0 - 1.0, 0.0, 1.0);
+    fragTexCoord = inTexCoord;
+    fragColor = vec4(linearFromSrgb(inColor.rgb), inColor.a);
+}
\ No newline at end of file
@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) flat in uint fragInstanceIndex;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+layout(push_constant) uniform PickingData {
+    uint drawId;
+} pickingData;
+
+void main() {
+    // Magenta, so it's obvious that the object is drawn without its own shaders
+    outColor = vec4(1.0, 0.0, 1.0, 1.0);
+    outObjectId = uvec2(pickingData.drawId, fragInstanceIndex);
+}
\ No newline at end of file
@@ -0,0 +1,22 @@
+#version 450
+
+// Only the position is read, so it works with any vertex that has it at location 0
+layout(location = 0) in vec3 inPosition;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+layout(location = 0) flat out uint fragInstanceIndex;
+
+void main() {
+    // The model matrix can be anywhere in the object's resources, so the object is drawn where its vertices are
+    gl_Position = globalFrameData.viewProj * vec4(inPosition, 1.0);
+    fragInstanceIndex = gl_InstanceIndex;
+}
\ No newline at end of file
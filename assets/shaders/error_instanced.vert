@@ -0,0 +1,26 @@
+#version 450
+
+// Only the position is read, so it works with any vertex that has it at location 0
+layout(location = 0) in vec3 inPosition;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+// Used when the object's binding 0 is a storage buffer, which is where the objects keep their model matrices
+layout(set = 1, binding = 0) readonly buffer InstanceData {
+    mat4 model[];
+} instanceData;
+
+layout(location = 0) flat out uint fragInstanceIndex;
+
+void main() {
+    gl_Position = globalFrameData.viewProj * instanceData.model[gl_InstanceIndex] * vec4(inPosition, 1.0);
+    fragInstanceIndex = gl_InstanceIndex;
+}
\ No newline at end of file
@@ -0,0 +1,43 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+const float FXAA_SPAN_MAX = 8.0;
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+
+void main() {
+    vec2 texelSize = 1.0 / vec2(textureSize(inputImage, 0));
+
+    float lumaNW = dot(texture(inputImage, fragTexCoord + vec2(-1.0, -1.0) * texelSize).rgb, LUMA);
+    float lumaNE = dot(texture(inputImage, fragTexCoord + vec2(1.0, -1.0) * texelSize).rgb, LUMA);
+    float lumaSW = dot(texture(inputImage, fragTexCoord + vec2(-1.0, 1.0) * texelSize).rgb, LUMA);
+    float lumaSE = dot(texture(inputImage, fragTexCoord + vec2(1.0, 1.0) * texelSize).rgb, LUMA);
+    vec4 center = texture(inputImage, fragTexCoord);
+    float lumaM = dot(center.rgb, LUMA);
+
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    // The direction is along the edge, so the samples blend across it
+    vec2 direction = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float directionReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    float inverseMinDirection = 1.0 / (min(abs(direction.x), abs(direction.y)) + directionReduce);
+    direction = clamp(direction * inverseMinDirection, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * texelSize;
+
+    vec3 colorA = 0.5 * (texture(inputImage, fragTexCoord + direction * (1.0 / 3.0 - 0.5)).rgb + texture(inputImage, fragTexCoord + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 colorB = colorA * 0.5 + 0.25 * (texture(inputImage, fragTexCoord + direction * -0.5).rgb + texture(inputImage, fragTexCoord + direction * 0.5).rgb);
+    float lumaB = dot(colorB, LUMA);
+
+    // The wider blend went past the edge, so only the closer samples are used
+    if (lumaB < lumaMin || lumaB > lumaMax) {
+        outColor = vec4(colorA, center.a);
+    } else {
+        outColor = vec4(colorB, center.a);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+// The sRGB transfer function, for swapchain formats that don't encode on write
+vec3 encodeSrgb(vec3 linearColor) {
+    vec3 color = clamp(linearColor, 0.0, 1.0);
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(vec3(0.0031308), color));
+}
+
+void main() {
+    vec4 linearColor = texture(inputImage, fragTexCoord);
+    outColor = vec4(encodeSrgb(linearColor.rgb), linearColor.a);
+}
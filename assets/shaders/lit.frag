@@ -0,0 +1,78 @@
+#version 450
+
+const uint MAX_LIGHTS = 16;
+const uint DIRECTIONAL_LIGHT = 0;
+const float AMBIENT = 0.05;
+const float SPECULAR_STRENGTH = 0.5;
+const float SHININESS = 32.0;
+
+struct Light {
+    // xyz is the direction the light travels in for directional lights and the position for point lights, w is the type
+    vec4 positionAndType;
+    // rgb is the color and w is the intensity
+    vec4 colorAndIntensity;
+    // x is the radius of point lights
+    vec4 parameters;
+};
+
+layout(location = 0) in vec3 fragWorldPosition;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragTexCoord;
+layout(location = 3) flat in uint fragInstanceIndex;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+layout(set = 0, binding = 1) uniform LightData {
+    uint lightCount;
+    Light lights[MAX_LIGHTS];
+} lightData;
+
+layout(set = 1, binding = 1) uniform sampler2D texSampler;
+
+layout(push_constant) uniform PickingData {
+    uint drawId;
+} pickingData;
+
+void main() {
+    vec4 albedo = texture(texSampler, fragTexCoord);
+    vec3 normal = normalize(fragNormal);
+    vec3 viewDirection = normalize(globalFrameData.cameraPosition.xyz - fragWorldPosition);
+
+    vec3 color = AMBIENT * albedo.rgb;
+    for (uint i = 0; i < lightData.lightCount; i++) {
+        Light light = lightData.lights[i];
+        vec3 lightDirection;
+        float attenuation;
+        if (uint(light.positionAndType.w) == DIRECTIONAL_LIGHT) {
+            lightDirection = normalize(-light.positionAndType.xyz);
+            attenuation = 1.0;
+        } else {
+            vec3 toLight = light.positionAndType.xyz - fragWorldPosition;
+            lightDirection = normalize(toLight);
+            // Reaches zero at the radius, so the light has no effect past it
+            float falloff = clamp(1.0 - pow(length(toLight) / light.parameters.x, 2.0), 0.0, 1.0);
+            attenuation = falloff * falloff;
+        }
+        vec3 radiance = light.colorAndIntensity.rgb * light.colorAndIntensity.w * attenuation;
+
+        float diffuse = max(dot(normal, lightDirection), 0.0);
+        vec3 halfway = normalize(lightDirection + viewDirection);
+        float specular = diffuse > 0.0 ? pow(max(dot(normal, halfway), 0.0), SHININESS) : 0.0;
+        color += radiance * (diffuse * albedo.rgb + SPECULAR_STRENGTH * specular);
+    }
+
+    outColor = vec4(color, albedo.a);
+    outObjectId = uvec2(pickingData.drawId, fragInstanceIndex);
+}
\ No newline at end of file
@@ -0,0 +1,35 @@
+#version 450
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inTexCoord;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+layout(set = 1, binding = 0) readonly buffer InstanceData {
+    mat4 model[];
+} instanceData;
+
+layout(location = 0) out vec3 fragWorldPosition;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec2 fragTexCoord;
+layout(location = 3) flat out uint fragInstanceIndex;
+
+void main() {
+    mat4 model = instanceData.model[gl_InstanceIndex];
+    vec4 worldPosition = model * vec4(inPosition, 1.0);
+    gl_Position = globalFrameData.viewProj * worldPosition;
+    fragWorldPosition = worldPosition.xyz;
+    // The inverse transpose keeps the normals perpendicular to the surface when the model is scaled unevenly
+    fragNormal = mat3(transpose(inverse(model))) * inNormal;
+    fragTexCoord = inTexCoord;
+    fragInstanceIndex = gl_InstanceIndex;
+}
\ No newline at end of file
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+layout(push_constant) uniform PickingData {
+    uint drawId;
+} pickingData;
+
+layout(set = 1, binding = 0) uniform samplerCube skybox;
+
+void main() {
+    outColor = texture(skybox, fragDirection);
+    outObjectId = uvec2(pickingData.drawId, 0);
+}
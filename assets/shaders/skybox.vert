@@ -0,0 +1,26 @@
+#version 450
+
+// A corner of the cube, which is also the direction the cube map is sampled in
+layout(location = 0) in vec3 inPosition;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+layout(location = 0) out vec3 fragDirection;
+
+// As far away as possible while still passing the LESS depth test against the cleared depth of 1
+const float SKY_DEPTH = 0.99999;
+
+void main() {
+    // Only the rotation of the view is used, so the sky never gets closer
+    vec4 position = globalFrameData.proj * mat4(mat3(globalFrameData.view)) * vec4(inPosition, 1.0);
+    gl_Position = vec4(position.xy, position.w * SKY_DEPTH, position.w);
+    fragDirection = inPosition;
+}
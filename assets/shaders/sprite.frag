@@ -0,0 +1,23 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) flat in uint fragInstanceIndex;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+layout(push_constant) uniform PickingData {
+    uint drawId;
+} pickingData;
+
+layout(set = 1, binding = 1) uniform sampler2D texSampler;
+
+void main() {
+    outColor = texture(texSampler, fragTexCoord);
+    // Fully transparent pixels would still write the depth and hide the sprites behind them
+    if (outColor.a == 0.0) {
+        discard;
+    }
+    outObjectId = uvec2(pickingData.drawId, fragInstanceIndex);
+}
\ No newline at end of file
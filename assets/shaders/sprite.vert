@@ -0,0 +1,44 @@
+#version 450
+
+// The quad's corner, from (0, 0) at the top left to (1, 1) at the bottom right
+layout(location = 0) in vec2 inCorner;
+
+layout(set = 0, binding = 0) uniform GlobalFrameData {
+    mat4 view;
+    mat4 proj;
+    mat4 viewProj;
+    vec4 cameraPosition;
+    float time;
+    float deltaTime;
+    vec2 viewportSize;
+} globalFrameData;
+
+struct SpriteInstance {
+    vec2 position;
+    vec2 size;
+    vec4 uvRect;
+    float depth;
+    float rotation;
+    vec2 scale;
+};
+
+layout(set = 1, binding = 0) readonly buffer InstanceData {
+    SpriteInstance sprites[];
+} instanceData;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) flat out uint fragInstanceIndex;
+
+void main() {
+    SpriteInstance sprite = instanceData.sprites[gl_InstanceIndex];
+    // Rotated and scaled around the center, so the position is still the top left of the unrotated sprite
+    vec2 halfSize = sprite.size * 0.5;
+    vec2 fromCenter = (inCorner - 0.5) * sprite.size * sprite.scale;
+    float s = sin(sprite.rotation);
+    float c = cos(sprite.rotation);
+    vec2 pixelPosition = sprite.position + halfSize + vec2(c * fromCenter.x - s * fromCenter.y, s * fromCenter.x + c * fromCenter.y);
+    // Vulkan's NDC has y pointing down like the pixels, so the origin is at the top left
+    gl_Position = vec4(pixelPosition / globalFrameData.viewportSize * 2.0 - 1.0, sprite.depth, 1.0);
+    fragTexCoord = sprite.uvRect.xy + inCorner * sprite.uvRect.zw;
+    fragInstanceIndex = gl_InstanceIndex;
+}
\ No newline at end of file
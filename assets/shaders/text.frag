@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
+
+layout(set = 1, binding = 0) uniform sampler2D fontAtlas;
+
+void main() {
+    outColor = fragColor * texture(fontAtlas, fragTexCoord);
+    // The space around the glyphs would still write the depth and hide the text behind it
+    if (outColor.a == 0.0) {
+        discard;
+    }
+    // Text is not an object, so it has the object id of the pixels without any object
+    outObjectId = uvec2(0, 0);
+}
\ No newline at end of file
@@ -0,0 +1,29 @@
+#version 450
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+layout(push_constant) uniform Tonemap {
+    float exposure;
+    // 0 is Reinhard and 1 is ACES
+    uint operator;
+} tonemap;
+
+vec3 aces(vec3 color) {
+    // Narkowicz's fit of the ACES curve
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec4 hdrColor = texture(inputImage, fragTexCoord);
+    vec3 color = hdrColor.rgb * tonemap.exposure;
+    if (tonemap.operator == 1) {
+        color = aces(color);
+    } else {
+        color = color / (1.0 + color);
+    }
+    outColor = vec4(color, hdrColor.a);
+}
\ No newline at end of file
@@ -2,11 +2,19 @@
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) flat in uint fragInstanceIndex;
 
 layout(location = 0) out vec4 outColor;
+// Only has an attachment when picking is enabled
+layout(location = 1) out uvec2 outObjectId;
 
-layout(binding = 2) uniform sampler2D texSampler;
+layout(push_constant) uniform PickingData {
+    uint drawId;
+} pickingData;
+
+layout(set = 1, binding = 2) uniform sampler2D texSampler;
 
 void main() {
     outColor = texture(texSampler, fragTexCoord);
+    outObjectId = uvec2(pickingData.drawId, fragInstanceIndex);
 }
\ No newline at end of file
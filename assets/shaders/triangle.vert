@@ -5,19 +5,21 @@ layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 texCoord;
 
-layout(set = 0, binding = 0) buffer InstanceData {
+layout(set = 1, binding = 0) buffer InstanceData {
     mat4 model[];
 } instanceData;
 
-layout(set = 0, binding = 1) uniform ObjectTypeData {
+layout(set = 1, binding = 1) uniform ObjectTypeData {
     mat4 view_proj;
 } objectTypeData;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) flat out uint fragInstanceIndex;
 
 void main() {
     gl_Position = objectTypeData.view_proj * instanceData.model[gl_InstanceIndex] * vec4(inPosition, 1.0);
     fragColor = inColor;
     fragTexCoord = texCoord;
+    fragInstanceIndex = gl_InstanceIndex;
 }
\ No newline at end of file
@@ -0,0 +1,24 @@
+use artewald_engine_2::vertex::{generate_circle_type_one, generate_circle_type_three, generate_circle_type_two};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+// Even and at least 6, so every type of circle can be generated with them
+const NUM_POINTS: [usize; 4] = [12, 96, 768, 6144];
+
+fn circle_generation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("circle_generation");
+    for num_points in NUM_POINTS {
+        group.bench_with_input(BenchmarkId::new("type_one", num_points), &num_points, |b, &num_points| {
+            b.iter(|| generate_circle_type_one(black_box(1.0), num_points).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("type_two", num_points), &num_points, |b, &num_points| {
+            b.iter(|| generate_circle_type_two(black_box(1.0), num_points).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("type_three", num_points), &num_points, |b, &num_points| {
+            b.iter(|| generate_circle_type_three(black_box(1.0), num_points).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, circle_generation);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use std::{env, fs, path::Path};
+
+use shaderc::{Compiler, ShaderKind};
+
+// The engine's own shaders, which are compiled to SPIR-V here and embedded in the binary by `src/builtin_shaders.rs`
+const BUILTIN_SHADERS: &[(&str, ShaderKind)] = &[
+    ("copy.frag", ShaderKind::Fragment),
+    ("debug_line.frag", ShaderKind::Fragment),
+    ("debug_line.vert", ShaderKind::Vertex),
+    ("egui.frag", ShaderKind::Fragment),
+    ("egui.vert", ShaderKind::Vertex),
+    ("error.frag", ShaderKind::Fragment),
+    ("error.vert", ShaderKind::Vertex),
+    ("error_instanced.vert", ShaderKind::Vertex),
+    ("fullscreen.vert", ShaderKind::Vertex),
+    ("fxaa.frag", ShaderKind::Fragment),
+    ("gamma_encode.frag", ShaderKind::Fragment),
+    ("lit.frag", ShaderKind::Fragment),
+    ("lit.vert", ShaderKind::Vertex),
+    ("skybox.frag", ShaderKind::Fragment),
+    ("skybox.vert", ShaderKind::Vertex),
+    ("sprite.frag", ShaderKind::Fragment),
+    ("sprite.vert", ShaderKind::Vertex),
+    ("text.frag", ShaderKind::Fragment),
+    ("text.vert", ShaderKind::Vertex),
+    ("tonemap.frag", ShaderKind::Fragment),
+];
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let compiler = Compiler::new().unwrap();
+    for (file_name, shader_kind) in BUILTIN_SHADERS {
+        let path = Path::new("assets/shaders").join(file_name);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let source = fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read the built-in shader {}: {}", path.display(), e));
+        let artifact = compiler.compile_into_spirv(&source, *shader_kind, file_name, "main", None).unwrap_or_else(|e| panic!("Failed to compile the built-in shader {}: {}", file_name, e));
+        fs::write(Path::new(&out_dir).join(format!("{}.spv", file_name)), artifact.as_binary_u8()).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+//! Two rotating viking rooms, each drawn in its own half of the window with its own camera.
+
+use std::{collections::HashSet, path::Path, sync::{Arc, RwLock}};
+
+use artewald_engine_2::{graphics_objects::{GraphicsObject, LitRenderableObject}, lighting::{Light, LitVertex}, vk_controller::{ViewportRegion, VkController, VkControllerGraphicsObjectsControl}};
+use winit::{event::{Event, WindowEvent}, event_loop::{ControlFlow, EventLoop}, window::WindowBuilder};
+use nalgebra_glm as glm;
+
+fn main() {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new().with_title("Split screen").build(&event_loop).unwrap();
+    let mut vk_controller = VkController::new(window, "Split screen");
+
+    let (vertices, indices) = load_model(&vk_controller.get_asset_resolver().resolve(Path::new("objects/viking_room.obj")).unwrap());
+    let image = vk_controller.get_asset_resolver().load_image(Path::new("images/viking_room.png")).unwrap();
+    let rooms = [-1.5, 1.5].map(|x| Arc::new(RwLock::new(LitRenderableObject::new(vertices.clone(), indices.clone(), image.clone(), get_model_matrix(x, 0.0)))));
+    let object_ids = vk_controller.add_objects_to_render(rooms.iter().map(|room| room.clone() as Arc<RwLock<dyn GraphicsObject<LitVertex>>>).collect()).unwrap().into_iter().map(|(object_id, _)| object_id).collect::<Vec<_>>();
+    vk_controller.add_light(Light::Directional { dir: glm::vec3(-1.0, -1.0, -1.0), color: glm::vec3(1.0, 1.0, 1.0), intensity: 1.0 }).unwrap();
+
+    // Each half looks at its own room, from the other side than the other half
+    let eyes = [glm::vec3(-1.5, 2.0, 2.0), glm::vec3(1.5, 2.0, -2.0)];
+    let cameras = eyes.map(|eye| vk_controller.add_camera(glm::look_at(&eye, &glm::vec3(eye.x, 0.0, 0.0), &glm::vec3(0.0, 1.0, 0.0)), glm::identity()));
+    vk_controller.set_viewports(vec![
+        ViewportRegion { rect: (0.0, 0.0, 0.5, 1.0), camera: cameras[0], object_filter: Some(HashSet::from([object_ids[0]])) },
+        ViewportRegion { rect: (0.5, 0.0, 0.5, 1.0), camera: cameras[1], object_filter: Some(HashSet::from([object_ids[1]])) },
+    ]).unwrap();
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+
+        match event {
+            Event::WindowEvent { event, .. } if vk_controller.handle_window_event(&event) => {},
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                *control_flow = ControlFlow::Exit;
+            },
+            Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
+                vk_controller.frame_buffer_resized = true;
+            },
+            Event::LoopDestroyed => {
+                vk_controller.cleanup();
+                return;
+            },
+            _ => {}
+        }
+
+        // The regions follow the window, but the aspect ratio of the projections has to be updated for the new size of a half
+        let swapchain_extent = vk_controller.get_swapchain_extent();
+        let mut projection = glm::perspective(swapchain_extent.width as f32 * 0.5 / swapchain_extent.height.max(1) as f32, 90.0_f32.to_radians(), 0.1, 10.0);
+        projection[(1, 1)] *= -1.0;
+        for (camera, eye) in cameras.iter().zip(eyes) {
+            vk_controller.set_camera(*camera, glm::look_at(&eye, &glm::vec3(eye.x, 0.0, 0.0), &glm::vec3(0.0, 1.0, 0.0)), projection).unwrap();
+        }
+
+        let rotation = vk_controller.time().elapsed_seconds() * std::f32::consts::PI * 0.25;
+        for ((room, object_id), x) in rooms.iter().zip(object_ids.iter()).zip([-1.5, 1.5]) {
+            room.write().unwrap().model_matrix.write().unwrap().buffer = get_model_matrix(x, rotation);
+            vk_controller.mark_instance_dirty(*object_id).unwrap();
+        }
+
+        vk_controller.try_to_draw_frame();
+    });
+}
+
+fn get_model_matrix(x: f32, rotation: f32) -> glm::Mat4 {
+    glm::translate(&glm::identity(), &glm::vec3(x, 0.0, 0.0)) * glm::rotate(&glm::identity(), rotation, &glm::vec3(0.0, 1.0, 0.0)) * glm::rotate(&glm::identity(), -90.0_f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0))
+}
+
+fn load_model(path: &Path) -> (Vec<LitVertex>, Vec<u32>) {
+    let (models, _) = tobj::load_obj(path, &tobj::LoadOptions { single_index: true, triangulate: true, ..Default::default() }).unwrap();
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for model in models {
+        let mesh = model.mesh;
+        let first_vertex = vertices.len() as u32;
+        vertices.extend((0..mesh.positions.len() / 3).map(|i| LitVertex {
+            position: glm::vec3(mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]),
+            normal: glm::vec3(mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]),
+            tex_coord: glm::vec2(mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]),
+        }));
+        indices.extend(mesh.indices.iter().map(|index| first_vertex + index));
+    }
+    (vertices, indices)
+}
@@ -0,0 +1,273 @@
+use std::{borrow::Cow, fmt::{Display, Formatter}, path::{Path, PathBuf}};
+
+/// The sources of the shaders the engine draws with itself, so they can be read even when none of the roots on disk has them.
+/// The engine uses the compiled ones from [`crate::builtin_shaders::BuiltinShader`], these are for shaders that start from them.
+const BUILT_IN_ASSETS: &[(&str, &[u8])] = &[
+    ("shaders/copy.frag", include_bytes!("../assets/shaders/copy.frag")),
+    ("shaders/debug_line.frag", include_bytes!("../assets/shaders/debug_line.frag")),
+    ("shaders/debug_line.vert", include_bytes!("../assets/shaders/debug_line.vert")),
+    ("shaders/error.frag", include_bytes!("../assets/shaders/error.frag")),
+    ("shaders/error.vert", include_bytes!("../assets/shaders/error.vert")),
+    ("shaders/error_instanced.vert", include_bytes!("../assets/shaders/error_instanced.vert")),
+    ("shaders/fullscreen.vert", include_bytes!("../assets/shaders/fullscreen.vert")),
+    ("shaders/fxaa.frag", include_bytes!("../assets/shaders/fxaa.frag")),
+    ("shaders/lit.frag", include_bytes!("../assets/shaders/lit.frag")),
+    ("shaders/lit.vert", include_bytes!("../assets/shaders/lit.vert")),
+    ("shaders/skybox.frag", include_bytes!("../assets/shaders/skybox.frag")),
+    ("shaders/skybox.vert", include_bytes!("../assets/shaders/skybox.vert")),
+    ("shaders/sprite.frag", include_bytes!("../assets/shaders/sprite.frag")),
+    ("shaders/sprite.vert", include_bytes!("../assets/shaders/sprite.vert")),
+    ("shaders/text.frag", include_bytes!("../assets/shaders/text.frag")),
+    ("shaders/text.vert", include_bytes!("../assets/shaders/text.vert")),
+    ("shaders/tonemap.frag", include_bytes!("../assets/shaders/tonemap.frag")),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetRoot {
+    Directory(PathBuf),
+    /// The assets compiled into the engine, which only has the engine's own shaders
+    BuiltIn,
+}
+
+impl Display for AssetRoot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetRoot::Directory(path) => write!(f, "{}", path.display()),
+            AssetRoot::BuiltIn => write!(f, "<engine built-in assets>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetError {
+    pub path: PathBuf,
+    pub searched_roots: Vec<AssetRoot>,
+    // Set when the asset was found but could not be read
+    pub io_error: Option<String>,
+}
+
+impl Display for AssetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(io_error) = &self.io_error {
+            return write!(f, "Failed to read the asset {}: {}", self.path.display(), io_error);
+        }
+        write!(f, "Could not find the asset {}, searched in:", self.path.display())?;
+        for root in &self.searched_roots {
+            write!(f, "\n    {}", root)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<AssetError> for Cow<'static, str> {
+    fn from(error: AssetError) -> Self {
+        Cow::Owned(error.to_string())
+    }
+}
+
+/// Finds the assets relative to the search roots, which are searched in order. By default that is the `assets` directory next to the executable,
+/// the `assets` directory in the working directory and then the engine's built-in assets.
+pub struct AssetResolver {
+    roots: Vec<AssetRoot>,
+}
+
+impl AssetResolver {
+    pub const ASSET_DIRECTORY_NAME: &'static str = "assets";
+
+    pub fn new() -> Self {
+        let mut roots = Vec::new();
+        if let Some(executable_dir) = std::env::current_exe().ok().and_then(|path| path.parent().map(Path::to_path_buf)) {
+            roots.push(AssetRoot::Directory(executable_dir.join(Self::ASSET_DIRECTORY_NAME)));
+        }
+        if let Ok(working_dir) = std::env::current_dir() {
+            let root = AssetRoot::Directory(working_dir.join(Self::ASSET_DIRECTORY_NAME));
+            // When it runs from the directory it is in, the working directory root is the same as the executable's
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        roots.push(AssetRoot::BuiltIn);
+        Self { roots }
+    }
+
+    /// Replaces the roots on disk with this one. The built-in assets are still searched after it.
+    pub fn set_root(&mut self, root: PathBuf) {
+        self.roots = vec![AssetRoot::Directory(root), AssetRoot::BuiltIn];
+    }
+
+    /// Searches the root after the other roots on disk, but before the built-in assets.
+    pub fn add_root(&mut self, root: PathBuf) {
+        let index = self.roots.iter().position(|root| *root == AssetRoot::BuiltIn).unwrap_or(self.roots.len());
+        self.roots.insert(index, AssetRoot::Directory(root));
+    }
+
+    pub fn get_roots(&self) -> &[AssetRoot] {
+        &self.roots
+    }
+
+    /// The path of the asset in the first root on disk that has it. Absolute paths are used as they are.
+    /// Built-in assets have no path, so use [`AssetResolver::read`] for the assets that can be built in.
+    pub fn resolve(&self, relative: &Path) -> Result<PathBuf, AssetError> {
+        if relative.is_absolute() {
+            return if relative.is_file() { Ok(relative.to_path_buf()) } else { Err(self.not_found(relative, Vec::new())) };
+        }
+        self.roots.iter().find_map(|root| match root {
+            AssetRoot::Directory(directory) => Some(directory.join(relative)).filter(|path| path.is_file()),
+            AssetRoot::BuiltIn => None,
+        }).ok_or_else(|| self.not_found(relative, self.roots.iter().filter(|root| **root != AssetRoot::BuiltIn).cloned().collect()))
+    }
+
+    /// The contents of the asset in the first root that has it, including the built-in assets.
+    pub fn read(&self, relative: &Path) -> Result<Cow<'static, [u8]>, AssetError> {
+        let path = if relative.is_absolute() {
+            Some(relative.to_path_buf()).filter(|path| path.is_file())
+        } else {
+            let mut found = None;
+            for root in &self.roots {
+                match root {
+                    AssetRoot::Directory(directory) if directory.join(relative).is_file() => {
+                        found = Some(directory.join(relative));
+                        break;
+                    },
+                    AssetRoot::BuiltIn => {
+                        if let Some((_, bytes)) = BUILT_IN_ASSETS.iter().find(|(name, _)| Path::new(name) == relative) {
+                            return Ok(Cow::Borrowed(bytes));
+                        }
+                    },
+                    _ => (),
+                }
+            }
+            found
+        };
+        let path = path.ok_or_else(|| self.not_found(relative, if relative.is_absolute() { Vec::new() } else { self.roots.clone() }))?;
+        std::fs::read(&path).map(Cow::Owned).map_err(|e| AssetError {
+            path,
+            searched_roots: Vec::new(),
+            io_error: Some(e.to_string()),
+        })
+    }
+
+    pub fn read_to_string(&self, relative: &Path) -> Result<String, AssetError> {
+        let bytes = self.read(relative)?;
+        String::from_utf8(bytes.into_owned()).map_err(|e| AssetError {
+            path: relative.to_path_buf(),
+            searched_roots: Vec::new(),
+            io_error: Some(e.to_string()),
+        })
+    }
+
+    pub fn load_image(&self, relative: &Path) -> Result<image::DynamicImage, AssetError> {
+        let bytes = self.read(relative)?;
+        image::load_from_memory(&bytes).map_err(|e| AssetError {
+            path: relative.to_path_buf(),
+            searched_roots: Vec::new(),
+            io_error: Some(e.to_string()),
+        })
+    }
+
+    fn not_found(&self, relative: &Path, searched_roots: Vec<AssetRoot>) -> AssetError {
+        AssetError {
+            path: relative.to_path_buf(),
+            searched_roots,
+            io_error: None,
+        }
+    }
+}
+
+impl Default for AssetResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory in the temp directory that is removed again when the test ends
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("artewald-engine-2-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, relative: &str, contents: &str) {
+            let path = self.0.join(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get_resolver(roots: &[&TempDir]) -> AssetResolver {
+        let mut resolver = AssetResolver::new();
+        resolver.set_root(roots[0].0.clone());
+        for root in &roots[1..] {
+            resolver.add_root(root.0.clone());
+        }
+        resolver
+    }
+
+    #[test]
+    fn roots_are_searched_in_order() {
+        let first = TempDir::new("order-first");
+        let second = TempDir::new("order-second");
+        first.write("models/both.obj", "first");
+        second.write("models/both.obj", "second");
+        second.write("models/second_only.obj", "second only");
+        let resolver = get_resolver(&[&first, &second]);
+
+        assert_eq!(resolver.get_roots(), [AssetRoot::Directory(first.0.clone()), AssetRoot::Directory(second.0.clone()), AssetRoot::BuiltIn]);
+        assert_eq!(resolver.resolve(Path::new("models/both.obj")).unwrap(), first.0.join("models/both.obj"));
+        assert_eq!(resolver.read_to_string(Path::new("models/both.obj")).unwrap(), "first");
+        assert_eq!(resolver.resolve(Path::new("models/second_only.obj")).unwrap(), second.0.join("models/second_only.obj"));
+        assert_eq!(resolver.read_to_string(Path::new("models/second_only.obj")).unwrap(), "second only");
+    }
+
+    #[test]
+    fn roots_on_disk_come_before_the_built_in_assets() {
+        let root = TempDir::new("built-in");
+        let resolver = get_resolver(&[&root]);
+        let built_in = BUILT_IN_ASSETS.iter().find(|(name, _)| *name == "shaders/copy.frag").unwrap().1;
+        assert_eq!(&*resolver.read(Path::new("shaders/copy.frag")).unwrap(), built_in);
+        // Built-in assets have no path
+        assert!(resolver.resolve(Path::new("shaders/copy.frag")).is_err());
+
+        root.write("shaders/copy.frag", "overridden");
+        assert_eq!(resolver.read_to_string(Path::new("shaders/copy.frag")).unwrap(), "overridden");
+        assert_eq!(resolver.resolve(Path::new("shaders/copy.frag")).unwrap(), root.0.join("shaders/copy.frag"));
+    }
+
+    #[test]
+    fn missing_asset_error_lists_the_searched_roots() {
+        let first = TempDir::new("missing-first");
+        let second = TempDir::new("missing-second");
+        let resolver = get_resolver(&[&first, &second]);
+
+        let read_error = resolver.read(Path::new("textures/missing.png")).unwrap_err();
+        assert_eq!(read_error.to_string(), format!("Could not find the asset textures/missing.png, searched in:\n    {}\n    {}\n    <engine built-in assets>", first.0.display(), second.0.display()));
+        // Resolving never looks in the built-in assets, so they aren't listed
+        let resolve_error = resolver.resolve(Path::new("textures/missing.png")).unwrap_err();
+        assert_eq!(resolve_error.to_string(), format!("Could not find the asset textures/missing.png, searched in:\n    {}\n    {}", first.0.display(), second.0.display()));
+    }
+
+    #[test]
+    fn missing_absolute_path_searches_no_roots() {
+        let root = TempDir::new("absolute");
+        let resolver = get_resolver(&[&root]);
+        let path = root.0.join("missing.png");
+        assert_eq!(resolver.read(&path).unwrap_err().to_string(), format!("Could not find the asset {}, searched in:", path.display()));
+
+        root.write("found.png", "absolute");
+        assert_eq!(resolver.read_to_string(&root.0.join("found.png")).unwrap(), "absolute");
+    }
+}
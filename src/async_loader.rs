@@ -0,0 +1,306 @@
+//! Objects that are added without blocking, see [`crate::vk_controller::VkControllerGraphicsObjectsControl::add_objects_async`].
+//! A background thread reads, hashes and checks the meshes. The main thread then adds the objects, which submits their uploads, and keeps them hidden until the GPU has finished copying them.
+//! The allocator and the object manager never leave the main thread. The background thread only gets the objects and sends back plain bytes, and the rest happens in draw_frame or when a load is polled.
+
+use std::{collections::HashMap, sync::{mpsc::{self, Receiver, TryRecvError}, Arc, RwLock}};
+
+use ash::vk;
+
+use crate::{error::EngineError, graphics_objects::{GraphicsObject, MaterialKey, Renderable, ResourceID, Submesh, UniformBufferResource}, object_manager::ObjectManager, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectTypeGraphicsResource, ShaderInfo, Vertex}, sprite::SpriteInstanceData, vk_allocator::UploadMarker, vk_controller::{ObjectID, VerticesIndicesHash}};
+
+type PreparedMeshes = Result<Vec<Arc<PreparedMesh>>, EngineError>;
+type PreparedObjects = Result<Vec<Box<dyn Renderable>>, EngineError>;
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct LoadHandle(pub u64);
+
+#[derive(Debug)]
+pub enum LoadStatus {
+    /// The meshes are being read on the background thread.
+    Preparing,
+    /// The objects have been added, but they are hidden until the GPU has copied their data.
+    Uploading,
+    /// The objects are drawn. The ids are in the same order as the objects were given, and the frame index is the one of the first frame they are drawn in, see [`crate::vk_controller::Time::frame_index`].
+    Ready(Vec<ObjectID>, u64),
+    Failed(EngineError),
+}
+
+/// What is read from a mesh off the main thread. Objects with the same mesh share it, so it is only read once.
+pub struct PreparedMesh {
+    vertices_and_indices_hash: VerticesIndicesHash,
+    vertex_byte_data: Vec<u8>,
+    indices: Vec<u32>,
+    is_validated: bool,
+}
+
+impl PreparedMesh {
+    /// Checks the mesh when `is_mesh_validation_enabled` is set, so the object manager doesn't check it again.
+    pub fn read<T: Vertex>(object: Arc<RwLock<dyn GraphicsObject<T>>>, vertices_and_indices_hash: VerticesIndicesHash, is_mesh_validation_enabled: bool) -> Result<Arc<Self>, EngineError> {
+        let mesh = Arc::new(PreparedMesh {
+            vertices_and_indices_hash,
+            vertex_byte_data: object.get_vertex_byte_data(),
+            indices: object.get_indices(),
+            is_validated: is_mesh_validation_enabled,
+        });
+        if is_mesh_validation_enabled {
+            ObjectManager::validate_mesh(&PreparedRenderable::new(Box::new(object), mesh.clone()))?;
+        }
+        Ok(mesh)
+    }
+
+    pub fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        self.vertices_and_indices_hash
+    }
+
+    pub fn get_vertex_byte_data(&self) -> &[u8] {
+        &self.vertex_byte_data
+    }
+
+    pub fn get_indices(&self) -> &[u32] {
+        &self.indices
+    }
+}
+
+/// Answers the mesh queries from what was read off the main thread, so the object manager doesn't read and hash the mesh again for every use.
+pub struct PreparedRenderable {
+    object: Box<dyn Renderable>,
+    mesh: Arc<PreparedMesh>,
+}
+
+impl PreparedRenderable {
+    pub fn new(object: Box<dyn Renderable>, mesh: Arc<PreparedMesh>) -> Self {
+        Self { object, mesh }
+    }
+}
+
+impl Renderable for PreparedRenderable {
+    fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        self.mesh.vertices_and_indices_hash
+    }
+
+    fn get_vertex_byte_data(&self) -> Vec<u8> {
+        self.mesh.vertex_byte_data.clone()
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        self.mesh.indices.clone()
+    }
+
+    fn get_index_type(&self) -> vk::IndexType {
+        self.object.get_index_type()
+    }
+
+    fn get_object_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)> {
+        self.object.get_object_instance_resources()
+    }
+
+    fn get_vertex_binding_info(&self) -> vk::VertexInputBindingDescription {
+        self.object.get_vertex_binding_info()
+    }
+
+    fn get_vertex_attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.object.get_vertex_attribute_descriptions()
+    }
+
+    fn get_shader_infos(&self) -> Vec<ShaderInfo> {
+        self.object.get_shader_infos()
+    }
+
+    fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)> {
+        self.object.get_type_resources()
+    }
+
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        self.object.get_sprite_instance_data()
+    }
+
+    fn get_material_key(&self) -> MaterialKey {
+        self.object.get_material_key()
+    }
+
+    fn is_mesh_validated(&self) -> bool {
+        self.mesh.is_validated
+    }
+
+    fn get_submeshes(&self) -> Vec<Submesh> {
+        self.object.get_submeshes()
+    }
+}
+
+enum LoadState {
+    Preparing { objects: Vec<Box<dyn Renderable>>, receiver: Receiver<PreparedMeshes> },
+    Uploading { object_ids: Vec<ObjectID>, upload_marker: UploadMarker },
+    // Kept until the status has been polled
+    Finished(LoadStatus),
+}
+
+pub struct AsyncLoader {
+    loads: HashMap<LoadHandle, LoadState>,
+    next_handle: u64,
+}
+
+impl AsyncLoader {
+    pub fn new() -> Self {
+        Self {
+            loads: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Reads the meshes on a new thread. The objects are only added by [`AsyncLoader::take_prepared_loads`], on the main thread.
+    pub fn start<T: Vertex>(&mut self, objects: Vec<Arc<RwLock<dyn GraphicsObject<T> + Send + Sync>>>, is_mesh_validation_enabled: bool) -> LoadHandle {
+        let handle = LoadHandle(self.next_handle);
+        self.next_handle += 1;
+
+        let renderables = objects.iter().map(|object| Box::new(object.clone() as Arc<RwLock<dyn GraphicsObject<T>>>) as Box<dyn Renderable>).collect();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // The receiver is gone when the controller was dropped first, and then nobody needs the meshes
+            let _ = sender.send(Self::prepare_meshes(objects, is_mesh_validation_enabled));
+        });
+        self.loads.insert(handle, LoadState::Preparing { objects: renderables, receiver });
+        handle
+    }
+
+    fn prepare_meshes<T: Vertex>(objects: Vec<Arc<RwLock<dyn GraphicsObject<T> + Send + Sync>>>, is_mesh_validation_enabled: bool) -> PreparedMeshes {
+        let mut meshes: HashMap<VerticesIndicesHash, Arc<PreparedMesh>> = HashMap::new();
+        objects.into_iter().enumerate().map(|(i, object)| {
+            let object = object as Arc<RwLock<dyn GraphicsObject<T>>>;
+            let vertices_and_indices_hash = object.get_vertices_and_indices_hash();
+            if let Some(mesh) = meshes.get(&vertices_and_indices_hash) {
+                return Ok(mesh.clone());
+            }
+
+            let mesh = PreparedMesh::read(object, vertices_and_indices_hash, is_mesh_validation_enabled).map_err(|e| EngineError::from(format!("The mesh of object {} in the load is invalid: {}", i, e)))?;
+            meshes.insert(vertices_and_indices_hash, mesh.clone());
+            Ok(mesh)
+        }).collect()
+    }
+
+    /// The loads whose meshes have been read, with their objects ready to be added. The caller sets their next state.
+    pub fn take_prepared_loads(&mut self) -> Vec<(LoadHandle, PreparedObjects)> {
+        let prepared_handles = self.loads.iter().filter_map(|(handle, state)| match state {
+            LoadState::Preparing { receiver, .. } => match receiver.try_recv() {
+                Ok(meshes) => Some((*handle, meshes)),
+                Err(TryRecvError::Disconnected) => Some((*handle, Err(EngineError::from("The thread reading the meshes panicked")))),
+                Err(TryRecvError::Empty) => None,
+            },
+            _ => None,
+        }).collect::<Vec<_>>();
+
+        prepared_handles.into_iter().map(|(handle, meshes)| {
+            let Some(LoadState::Preparing { objects, .. }) = self.loads.remove(&handle) else {
+                unreachable!();
+            };
+            let objects = meshes.map(|meshes| objects.into_iter().zip(meshes).map(|(object, mesh)| Box::new(PreparedRenderable::new(object, mesh)) as Box<dyn Renderable>).collect());
+            (handle, objects)
+        }).collect()
+    }
+
+    pub fn set_uploading(&mut self, handle: LoadHandle, object_ids: Vec<ObjectID>, upload_marker: UploadMarker) {
+        self.loads.insert(handle, LoadState::Uploading { object_ids, upload_marker });
+    }
+
+    pub fn set_failed(&mut self, handle: LoadHandle, error: EngineError) {
+        self.loads.insert(handle, LoadState::Finished(LoadStatus::Failed(error)));
+    }
+
+    /// Marks the loads whose uploads have finished as ready from the given frame, and returns their objects so they can be shown.
+    pub fn take_uploaded_objects(&mut self, frame_index: u64, mut is_upload_finished: impl FnMut(UploadMarker) -> bool) -> Vec<ObjectID> {
+        let mut uploaded_objects = Vec::new();
+        for state in self.loads.values_mut() {
+            if let LoadState::Uploading { object_ids, upload_marker } = state {
+                if is_upload_finished(*upload_marker) {
+                    uploaded_objects.extend(object_ids.iter().copied());
+                    *state = LoadState::Finished(LoadStatus::Ready(std::mem::take(object_ids), frame_index));
+                }
+            }
+        }
+        uploaded_objects
+    }
+
+    /// A finished load is forgotten once its status has been returned, so the next poll of it returns None.
+    pub fn poll(&mut self, handle: LoadHandle) -> Option<LoadStatus> {
+        match self.loads.get(&handle)? {
+            LoadState::Preparing { .. } => Some(LoadStatus::Preparing),
+            LoadState::Uploading { .. } => Some(LoadStatus::Uploading),
+            LoadState::Finished(_) => match self.loads.remove(&handle) {
+                Some(LoadState::Finished(status)) => Some(status),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+impl Default for AsyncLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use nalgebra_glm as glm;
+
+    use crate::{graphics_objects::LitRenderableObject, lighting::LitVertex};
+
+    use super::*;
+
+    fn get_lit_object(indices: Vec<u32>) -> Arc<RwLock<dyn GraphicsObject<LitVertex> + Send + Sync>> {
+        let vertices = [glm::vec3(0.0, 0.5, 0.0), glm::vec3(-0.5, -0.5, 0.0), glm::vec3(0.5, -0.5, 0.0)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+        Arc::new(RwLock::new(LitRenderableObject::new(vertices.to_vec(), indices, image::DynamicImage::new_rgba8(2, 2), glm::Mat4::identity())))
+    }
+
+    // Polls the loader like draw_frame does, until the background thread has read the meshes
+    fn wait_for_prepared_load(async_loader: &mut AsyncLoader, handle: LoadHandle) -> PreparedObjects {
+        let start = Instant::now();
+        loop {
+            if let Some((prepared_handle, objects)) = async_loader.take_prepared_loads().into_iter().next() {
+                assert_eq!(prepared_handle, handle);
+                return objects;
+            }
+            assert!(matches!(async_loader.poll(handle), Some(LoadStatus::Preparing)));
+            assert!(start.elapsed() < Duration::from_secs(10), "The meshes were never read");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn load_is_ready_in_the_frame_its_uploads_have_finished() {
+        let mut async_loader = AsyncLoader::new();
+        let handle = async_loader.start(vec![get_lit_object(vec![0, 1, 2]), get_lit_object(vec![0, 1, 2])], true);
+        let objects = wait_for_prepared_load(&mut async_loader, handle).unwrap();
+        // The objects share the mesh, so it was only read once
+        assert_eq!(objects.len(), 2);
+
+        let object_ids = vec![ObjectID(4), ObjectID(5)];
+        async_loader.set_uploading(handle, object_ids.clone(), UploadMarker(3));
+        assert!(matches!(async_loader.poll(handle), Some(LoadStatus::Uploading)));
+
+        // The frames before the GPU has copied the data leave the objects hidden
+        let finished_upload = UploadMarker(3);
+        for (frame_index, last_finished_upload) in [(1, UploadMarker(1)), (2, UploadMarker(2))] {
+            assert!(async_loader.take_uploaded_objects(frame_index, |upload_marker| upload_marker <= last_finished_upload).is_empty());
+            assert!(matches!(async_loader.poll(handle), Some(LoadStatus::Uploading)));
+        }
+        assert_eq!(async_loader.take_uploaded_objects(3, |upload_marker| upload_marker <= finished_upload), object_ids);
+        assert!(matches!(async_loader.poll(handle), Some(LoadStatus::Ready(ids, 3)) if ids == object_ids));
+        // A finished load is only returned once
+        assert!(async_loader.poll(handle).is_none());
+        assert!(async_loader.take_uploaded_objects(4, |_| true).is_empty());
+    }
+
+    #[test]
+    fn load_with_an_invalid_mesh_fails() {
+        let mut async_loader = AsyncLoader::new();
+        let handle = async_loader.start(vec![get_lit_object(vec![0, 1, 2]), get_lit_object(vec![0, 1, 7])], true);
+        let error = wait_for_prepared_load(&mut async_loader, handle).err().unwrap();
+        assert!(error.to_string().contains("object 1"), "{}", error);
+
+        async_loader.set_failed(handle, error);
+        assert!(matches!(async_loader.poll(handle), Some(LoadStatus::Failed(_))));
+        assert!(async_loader.poll(handle).is_none());
+    }
+}
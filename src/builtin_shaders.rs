@@ -0,0 +1,103 @@
+use std::ffi::CString;
+
+use ash::vk;
+
+use crate::pipeline_manager::{ShaderInfo, ShaderSource};
+
+/// The shaders the engine draws with itself. They are compiled to SPIR-V by the build script and embedded in the binary, so they are never read from the asset roots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinShader {
+    CopyFragment,
+    DebugLineFragment,
+    DebugLineVertex,
+    EguiFragment,
+    EguiVertex,
+    /// Draws the objects whose own pipeline failed in magenta, without a model matrix
+    ErrorVertex,
+    /// Like [`BuiltinShader::ErrorVertex`], but with the model matrices from the storage buffer at binding 0 of the object's set
+    ErrorInstancedVertex,
+    ErrorFragment,
+    FullscreenVertex,
+    FxaaFragment,
+    GammaEncodeFragment,
+    LitFragment,
+    LitVertex,
+    SkyboxFragment,
+    SkyboxVertex,
+    SpriteFragment,
+    SpriteVertex,
+    TextFragment,
+    TextVertex,
+    TonemapFragment,
+}
+
+impl BuiltinShader {
+    /// The name of the source in `assets/shaders`.
+    pub fn get_file_name(self) -> &'static str {
+        match self {
+            BuiltinShader::CopyFragment => "copy.frag",
+            BuiltinShader::DebugLineFragment => "debug_line.frag",
+            BuiltinShader::DebugLineVertex => "debug_line.vert",
+            BuiltinShader::EguiFragment => "egui.frag",
+            BuiltinShader::EguiVertex => "egui.vert",
+            BuiltinShader::ErrorVertex => "error.vert",
+            BuiltinShader::ErrorInstancedVertex => "error_instanced.vert",
+            BuiltinShader::ErrorFragment => "error.frag",
+            BuiltinShader::FullscreenVertex => "fullscreen.vert",
+            BuiltinShader::FxaaFragment => "fxaa.frag",
+            BuiltinShader::GammaEncodeFragment => "gamma_encode.frag",
+            BuiltinShader::LitFragment => "lit.frag",
+            BuiltinShader::LitVertex => "lit.vert",
+            BuiltinShader::SkyboxFragment => "skybox.frag",
+            BuiltinShader::SkyboxVertex => "skybox.vert",
+            BuiltinShader::SpriteFragment => "sprite.frag",
+            BuiltinShader::SpriteVertex => "sprite.vert",
+            BuiltinShader::TextFragment => "text.frag",
+            BuiltinShader::TextVertex => "text.vert",
+            BuiltinShader::TonemapFragment => "tonemap.frag",
+        }
+    }
+
+    pub fn get_shader_stage_flag(self) -> vk::ShaderStageFlags {
+        if self.get_file_name().ends_with(".vert") {
+            vk::ShaderStageFlags::VERTEX
+        } else {
+            vk::ShaderStageFlags::FRAGMENT
+        }
+    }
+
+    pub fn get_spirv(self) -> Vec<u32> {
+        let bytes: &[u8] = match self {
+            BuiltinShader::CopyFragment => include_bytes!(concat!(env!("OUT_DIR"), "/copy.frag.spv")),
+            BuiltinShader::DebugLineFragment => include_bytes!(concat!(env!("OUT_DIR"), "/debug_line.frag.spv")),
+            BuiltinShader::DebugLineVertex => include_bytes!(concat!(env!("OUT_DIR"), "/debug_line.vert.spv")),
+            BuiltinShader::EguiFragment => include_bytes!(concat!(env!("OUT_DIR"), "/egui.frag.spv")),
+            BuiltinShader::EguiVertex => include_bytes!(concat!(env!("OUT_DIR"), "/egui.vert.spv")),
+            BuiltinShader::ErrorVertex => include_bytes!(concat!(env!("OUT_DIR"), "/error.vert.spv")),
+            BuiltinShader::ErrorInstancedVertex => include_bytes!(concat!(env!("OUT_DIR"), "/error_instanced.vert.spv")),
+            BuiltinShader::ErrorFragment => include_bytes!(concat!(env!("OUT_DIR"), "/error.frag.spv")),
+            BuiltinShader::FullscreenVertex => include_bytes!(concat!(env!("OUT_DIR"), "/fullscreen.vert.spv")),
+            BuiltinShader::FxaaFragment => include_bytes!(concat!(env!("OUT_DIR"), "/fxaa.frag.spv")),
+            BuiltinShader::GammaEncodeFragment => include_bytes!(concat!(env!("OUT_DIR"), "/gamma_encode.frag.spv")),
+            BuiltinShader::LitFragment => include_bytes!(concat!(env!("OUT_DIR"), "/lit.frag.spv")),
+            BuiltinShader::LitVertex => include_bytes!(concat!(env!("OUT_DIR"), "/lit.vert.spv")),
+            BuiltinShader::SkyboxFragment => include_bytes!(concat!(env!("OUT_DIR"), "/skybox.frag.spv")),
+            BuiltinShader::SkyboxVertex => include_bytes!(concat!(env!("OUT_DIR"), "/skybox.vert.spv")),
+            BuiltinShader::SpriteFragment => include_bytes!(concat!(env!("OUT_DIR"), "/sprite.frag.spv")),
+            BuiltinShader::SpriteVertex => include_bytes!(concat!(env!("OUT_DIR"), "/sprite.vert.spv")),
+            BuiltinShader::TextFragment => include_bytes!(concat!(env!("OUT_DIR"), "/text.frag.spv")),
+            BuiltinShader::TextVertex => include_bytes!(concat!(env!("OUT_DIR"), "/text.vert.spv")),
+            BuiltinShader::TonemapFragment => include_bytes!(concat!(env!("OUT_DIR"), "/tonemap.frag.spv")),
+        };
+        // The bytes are not guaranteed to be aligned for u32, so they are copied
+        bytes.chunks_exact(std::mem::size_of::<u32>()).map(|word| u32::from_ne_bytes(word.try_into().unwrap())).collect()
+    }
+
+    pub fn get_shader_info(self) -> ShaderInfo {
+        ShaderInfo {
+            source: ShaderSource::Builtin(self),
+            shader_stage_flag: self.get_shader_stage_flag(),
+            entry_point: CString::new("main").unwrap(),
+        }
+    }
+}
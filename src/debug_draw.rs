@@ -0,0 +1,171 @@
+use std::{borrow::Cow, hash::{Hash, Hasher}};
+
+use ash::{vk, Device};
+use memoffset::offset_of;
+use nalgebra_glm as glm;
+
+use crate::{logging, builtin_shaders::BuiltinShader, pipeline_manager::{PipelineConfig, PipelineManager, Vertex}, vk_allocator::{AllocationInfo, Serializable, VkAllocator}, vk_controller::VkController};
+
+/// An end of a debug line in world space.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct DebugLineVertex {
+    pub position: glm::Vec3,
+    pub color: glm::Vec4,
+}
+
+impl Vertex for DebugLineVertex {
+    fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, position) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(Self, color) as u32,
+            },
+        ]
+    }
+}
+
+impl Hash for DebugLineVertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.position.iter()
+            .chain(self.color.iter())
+            .for_each(|&i| i.to_bits().hash(state));
+    }
+}
+
+impl Serializable for DebugLineVertex {
+    fn to_u8(&self) -> Vec<u8> {
+        self.position.iter()
+            .chain(self.color.iter())
+            .flat_map(|x| x.to_ne_bytes())
+            .collect()
+    }
+}
+
+/// Collects the lines drawn with [`VkController::debug_line`] and the other debug shapes, and draws them in every view after the objects.
+/// The lines are hidden behind the objects, but they don't write the depth, so they never hide anything.
+pub struct DebugDrawer {
+    pipeline_config: PipelineConfig,
+    // One part for each frame in flight, since the vertices of the earlier frames might still be read
+    vertex_buffers: AllocationInfo,
+    num_vertices: [u32; VkController::MAX_FRAMES_IN_FLIGHT],
+    queued_vertices: Vec<DebugLineVertex>,
+}
+
+impl DebugDrawer {
+    /// The most lines that are drawn in one frame, the rest are skipped.
+    pub const MAX_LINES_PER_FRAME: usize = 16384;
+    // The number of lines in each of the three circles a sphere is drawn with
+    const SPHERE_SEGMENTS: usize = 32;
+
+    pub fn new(device: &Device, pipeline_manager: &mut PipelineManager, msaa_samples: vk::SampleCountFlags, color_format: vk::Format, depth_format: vk::Format, swapchain_extent: &vk::Extent2D, allocator: &VkAllocator) -> Result<Self, Cow<'static, str>> {
+        let shaders = vec![
+            BuiltinShader::DebugLineVertex.get_shader_info(),
+            BuiltinShader::DebugLineFragment.get_shader_info(),
+        ];
+        let mut pipeline_config = PipelineConfig::new(device, shaders, DebugLineVertex::get_input_binding_description(), DebugLineVertex::get_attribute_descriptions(), &[], msaa_samples, color_format, depth_format, allocator)?
+            .with_topology(vk::PrimitiveTopology::LINE_LIST)
+            .with_depth_write(false);
+        pipeline_manager.get_or_create_pipeline(&mut pipeline_config, device, swapchain_extent, allocator)?;
+
+        let vertex_buffers = allocator.create_vertex_buffers(Self::MAX_LINES_PER_FRAME * 2 * std::mem::size_of::<DebugLineVertex>(), VkController::MAX_FRAMES_IN_FLIGHT)?;
+
+        Ok(Self {
+            pipeline_config,
+            vertex_buffers,
+            num_vertices: [0; VkController::MAX_FRAMES_IN_FLIGHT],
+            queued_vertices: Vec::new(),
+        })
+    }
+
+    pub fn queue_line(&mut self, a: glm::Vec3, b: glm::Vec3, color: glm::Vec4) {
+        self.queued_vertices.push(DebugLineVertex { position: a, color });
+        self.queued_vertices.push(DebugLineVertex { position: b, color });
+    }
+
+    /// The twelve edges of the axis aligned box.
+    pub fn queue_box(&mut self, min: glm::Vec3, max: glm::Vec3, color: glm::Vec4) {
+        // Corner i has the max x, y and z coordinate when bit 0, 1 and 2 of i is set
+        let corner = |i: usize| glm::Vec3::new(if i & 1 != 0 { max.x } else { min.x }, if i & 2 != 0 { max.y } else { min.y }, if i & 4 != 0 { max.z } else { min.z });
+        for i in 0..8 {
+            // Each edge goes from the corner with the bit cleared to the corner with it set
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.queue_line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// A circle around each of the axes, which is enough to see the size and position of the sphere.
+    pub fn queue_sphere(&mut self, center: glm::Vec3, radius: f32, color: glm::Vec4) {
+        let point = |axis: usize, segment: usize| {
+            let angle = segment as f32 / Self::SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            let mut offset = glm::Vec3::zeros();
+            offset[(axis + 1) % 3] = cos * radius;
+            offset[(axis + 2) % 3] = sin * radius;
+            center + offset
+        };
+        for axis in 0..3 {
+            for segment in 0..Self::SPHERE_SEGMENTS {
+                self.queue_line(point(axis, segment), point(axis, segment + 1), color);
+            }
+        }
+    }
+
+    /// Writes the queued lines to the vertex buffer of the frame. The queue is kept until [`DebugDrawer::clear_queued_lines`] is called.
+    pub fn upload_queued_lines(&mut self, current_frame: usize) {
+        let max_vertices = Self::MAX_LINES_PER_FRAME * 2;
+        if self.queued_vertices.len() > max_vertices {
+            log::trace!(target: logging::RENDERER, "Only the first {} of the {} debug lines are drawn", Self::MAX_LINES_PER_FRAME, self.queued_vertices.len() / 2);
+        }
+        let vertices = &self.queued_vertices[..self.queued_vertices.len().min(max_vertices)];
+        let data = vertices.iter().flat_map(|vertex| vertex.to_u8()).collect::<Vec<u8>>();
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.vertex_buffers.get_uniform_pointers()[current_frame].cast::<u8>(), data.len());
+        }
+        self.num_vertices[current_frame] = vertices.len() as u32;
+    }
+
+    pub fn clear_queued_lines(&mut self) {
+        self.queued_vertices.clear();
+    }
+
+    pub fn get_pipeline_config(&self) -> &PipelineConfig {
+        &self.pipeline_config
+    }
+
+    /// The vertex buffer and the offset of the frame's vertices in it.
+    pub fn get_vertex_buffer(&self, current_frame: usize) -> (vk::Buffer, vk::DeviceSize) {
+        let offset = unsafe { self.vertex_buffers.get_uniform_pointers()[current_frame].offset_from(self.vertex_buffers.get_uniform_pointers()[0]) } as vk::DeviceSize;
+        (self.vertex_buffers.get_buffer().unwrap(), offset)
+    }
+
+    pub fn get_num_vertices(&self, current_frame: usize) -> u32 {
+        self.num_vertices[current_frame]
+    }
+
+    /// The pipeline is owned by the pipeline manager, so it is destroyed with the other pipelines.
+    pub fn destroy(self, allocator: &VkAllocator) {
+        if let Err(e) = allocator.free_memory_allocation(self.vertex_buffers) {
+            log::error!(target: logging::RENDERER, "Failed to free the debug line vertex buffers: {}", e);
+        }
+    }
+}
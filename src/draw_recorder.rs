@@ -0,0 +1,72 @@
+use std::ffi::CString;
+
+use ash::{extensions::ext::DebugUtils, vk::{self, StructureType}, Device};
+
+use crate::vk_controller::VkController;
+
+/// One command of the object draws. The draws are recorded through a [`DrawRecorder`], so the same recording can go into a command buffer or into a trace that is compared without a device.
+#[derive(Clone, PartialEq)]
+pub enum DrawCommand {
+    BeginLabel(String),
+    EndLabel,
+    BindPipeline(vk::Pipeline),
+    // The viewport covers the rect, with the full depth range
+    SetViewport(vk::Rect2D),
+    SetScissor(vk::Rect2D),
+    BindDescriptorSet { pipeline_layout: vk::PipelineLayout, set_index: u32, descriptor_set: vk::DescriptorSet, dynamic_offsets: Vec<u32> },
+    BindVertexBuffer(vk::Buffer),
+    BindIndexBuffer(vk::Buffer, vk::IndexType),
+    // The draw id is pushed to the fragment shader, which writes it to the object id attachment
+    PushDrawId { pipeline_layout: vk::PipelineLayout, draw_id: u32 },
+    Draw { num_vertices: u32, num_instances: u32, first_vertex: u32, first_instance: u32 },
+    DrawIndexed { num_indices: u32, num_instances: u32, first_index: u32, vertex_offset: i32, first_instance: u32 },
+}
+
+pub trait DrawRecorder {
+    fn record(&mut self, command: DrawCommand);
+}
+
+/// Records the commands into a command buffer. The labels are only recorded when there is a debug utils loader.
+pub struct CommandBufferRecorder<'a> {
+    pub device: &'a Device,
+    pub command_buffer: vk::CommandBuffer,
+    pub debug_utils_loader: Option<&'a DebugUtils>,
+}
+
+impl DrawRecorder for CommandBufferRecorder<'_> {
+    fn record(&mut self, command: DrawCommand) {
+        let command_buffer = self.command_buffer;
+        unsafe {
+            match command {
+                DrawCommand::BeginLabel(name) => if let Some(debug_utils_loader) = self.debug_utils_loader {
+                    let label_name = CString::new(name).unwrap();
+                    let label = vk::DebugUtilsLabelEXT {
+                        s_type: StructureType::DEBUG_UTILS_LABEL_EXT,
+                        p_label_name: label_name.as_ptr(),
+                        ..Default::default()
+                    };
+                    debug_utils_loader.cmd_begin_debug_utils_label(command_buffer, &label);
+                },
+                DrawCommand::EndLabel => if let Some(debug_utils_loader) = self.debug_utils_loader {
+                    debug_utils_loader.cmd_end_debug_utils_label(command_buffer);
+                },
+                DrawCommand::BindPipeline(pipeline) => self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline),
+                DrawCommand::SetViewport(rect) => self.device.cmd_set_viewport(command_buffer, 0, &[VkController::get_viewport(&rect)]),
+                DrawCommand::SetScissor(rect) => self.device.cmd_set_scissor(command_buffer, 0, &[rect]),
+                DrawCommand::BindDescriptorSet { pipeline_layout, set_index, descriptor_set, dynamic_offsets } => self.device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, set_index, &[descriptor_set], &dynamic_offsets),
+                DrawCommand::BindVertexBuffer(buffer) => self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0]),
+                DrawCommand::BindIndexBuffer(buffer, index_type) => self.device.cmd_bind_index_buffer(command_buffer, buffer, 0, index_type),
+                DrawCommand::PushDrawId { pipeline_layout, draw_id } => self.device.cmd_push_constants(command_buffer, pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, &draw_id.to_ne_bytes()),
+                DrawCommand::Draw { num_vertices, num_instances, first_vertex, first_instance } => self.device.cmd_draw(command_buffer, num_vertices, num_instances, first_vertex, first_instance),
+                DrawCommand::DrawIndexed { num_indices, num_instances, first_index, vertex_offset, first_instance } => self.device.cmd_draw_indexed(command_buffer, num_indices, num_instances, first_index, vertex_offset, first_instance),
+            }
+        }
+    }
+}
+
+/// The trace of the recorded commands.
+impl DrawRecorder for Vec<DrawCommand> {
+    fn record(&mut self, command: DrawCommand) {
+        self.push(command);
+    }
+}
@@ -0,0 +1,483 @@
+//! Draws the output of egui over the scene, so tools can be built with egui without any Vulkan of their own.
+//! The renderer only exists with the `egui` feature. Without it [`EguiRenderer`] can't be created, so the frame is recorded the same way either way.
+
+use ash::{vk, Device};
+
+use crate::{pipeline_manager::PipelineManager, vk_allocator::VkAllocator};
+
+#[cfg(feature = "egui")]
+pub use backend::{EguiRenderer, EguiVertex};
+
+#[cfg(not(feature = "egui"))]
+pub enum EguiRenderer {}
+
+#[cfg(not(feature = "egui"))]
+impl EguiRenderer {
+    pub fn upload_queued_primitives(&mut self, _current_frame: usize, _device: &Device, _allocator: &VkAllocator) {
+        match *self {}
+    }
+
+    pub fn record_draw(&self, _device: &Device, _command_buffer: &vk::CommandBuffer, _global_descriptor_set: vk::DescriptorSet, _render_rect: &vk::Rect2D, _swapchain_extent: &vk::Extent2D, _pipeline_manager: &mut PipelineManager, _current_frame: usize, _allocator: &VkAllocator) -> usize {
+        match *self {}
+    }
+
+    pub fn clear_queued_primitives(&mut self) {
+        match *self {}
+    }
+
+    pub fn destroy(self, _device: &Device, _allocator: &VkAllocator) {
+        match self {}
+    }
+}
+
+#[cfg(feature = "egui")]
+mod backend {
+    use std::{borrow::Cow, collections::HashMap, hash::{Hash, Hasher}};
+
+    use ash::{vk::{CommandPool, DescriptorPool, DescriptorSet, PhysicalDevice, Queue, StructureType}, Instance};
+    use egui::{epaint::{ImageData, Primitive}, ClippedPrimitive, TextureFilter, TextureId, TexturesDelta};
+    use image::{DynamicImage, RgbaImage};
+    use memoffset::offset_of;
+    use nalgebra_glm as glm;
+
+    use crate::{logging, builtin_shaders::BuiltinShader, free_allocations_add_error_string, pipeline_manager::{PipelineConfig, Vertex}, sampler_manager::{SamplerConfig, SamplerManager}, vk_allocator::{AllocationInfo, Serializable}, vk_controller::VkController};
+
+    use super::*;
+
+    /// A vertex of an egui mesh, positioned in pixels from the top left of the render area.
+    #[derive(Debug, Clone, Copy, Default)]
+    #[repr(C)]
+    pub struct EguiVertex {
+        pub position_px: glm::Vec2,
+        pub tex_coord: glm::Vec2,
+        // sRGB with premultiplied alpha
+        pub color: [u8; 4],
+    }
+
+    impl Vertex for EguiVertex {
+        fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+            vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<Self>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }
+        }
+
+        fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+            vec![
+                vk::VertexInputAttributeDescription {
+                    binding: 0,
+                    location: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: offset_of!(Self, position_px) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    binding: 0,
+                    location: 1,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: offset_of!(Self, tex_coord) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    binding: 0,
+                    location: 2,
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    offset: offset_of!(Self, color) as u32,
+                },
+            ]
+        }
+    }
+
+    impl Hash for EguiVertex {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.position_px.iter()
+                .chain(self.tex_coord.iter())
+                .for_each(|&i| i.to_bits().hash(state));
+            self.color.hash(state);
+        }
+    }
+
+    impl Serializable for EguiVertex {
+        fn to_u8(&self) -> Vec<u8> {
+            self.position_px.iter()
+                .chain(self.tex_coord.iter())
+                .flat_map(|x| x.to_ne_bytes())
+                .chain(self.color)
+                .collect()
+        }
+    }
+
+    // A texture with its own descriptor set. The pixels are kept, since egui sends the changes to a texture as patches and the whole image is uploaded again
+    struct EguiTexture {
+        pixels: RgbaImage,
+        image: AllocationInfo,
+        descriptor_pool: DescriptorPool,
+        descriptor_set: DescriptorSet,
+    }
+
+    impl EguiTexture {
+        fn destroy(self, device: &Device, allocator: &VkAllocator, error_str: &mut String) {
+            unsafe {
+                device.destroy_descriptor_pool(self.descriptor_pool, allocator.get_allocation_callbacks());
+            }
+            free_allocations_add_error_string!(allocator, [self.image], error_str);
+        }
+    }
+
+    struct QueuedMesh {
+        clip_rect_px: egui::Rect,
+        texture_id: TextureId,
+        vertices: Vec<EguiVertex>,
+        indices: Vec<u32>,
+    }
+
+    struct EguiDraw {
+        clip_rect_px: egui::Rect,
+        descriptor_set: DescriptorSet,
+        first_index: u32,
+        vertex_offset: i32,
+        num_indices: u32,
+    }
+
+    /// Draws the meshes egui tessellated for a frame with an indexed draw for each, clipped by the scissor to the clip rect of the mesh.
+    /// The meshes are drawn over the scene without the depth test, and they go through the post effects like the text.
+    pub struct EguiRenderer {
+        pipeline_config: PipelineConfig,
+        textures: HashMap<TextureId, EguiTexture>,
+        // The textures that have been freed or replaced, with the number of frames left until the frames in flight no longer draw with them
+        retired_textures: Vec<(usize, EguiTexture)>,
+        // Freed after the frame they are freed in has been recorded, since its meshes can still use them
+        pending_freed_textures: Vec<TextureId>,
+        // One part for each frame in flight, since the vertices and indices of the earlier frames might still be read
+        vertex_buffers: AllocationInfo,
+        index_buffers: AllocationInfo,
+        draws: [Vec<EguiDraw>; VkController::MAX_FRAMES_IN_FLIGHT],
+        queued_meshes: Vec<QueuedMesh>,
+    }
+
+    impl EguiRenderer {
+        /// The most vertices that are drawn in one frame, the meshes after them are skipped.
+        pub const MAX_VERTICES_PER_FRAME: usize = 1 << 18;
+        /// The most indices that are drawn in one frame, the meshes after them are skipped.
+        pub const MAX_INDICES_PER_FRAME: usize = 1 << 19;
+
+        pub fn new(device: &Device, pipeline_manager: &mut PipelineManager, msaa_samples: vk::SampleCountFlags, color_format: vk::Format, depth_format: vk::Format, swapchain_extent: &vk::Extent2D, allocator: &VkAllocator) -> Result<Self, Cow<'static, str>> {
+            let shaders = vec![
+                BuiltinShader::EguiVertex.get_shader_info(),
+                BuiltinShader::EguiFragment.get_shader_info(),
+            ];
+            let layout_bindings = [vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: std::ptr::null(),
+            }];
+            // egui doesn't keep the winding of its triangles the same, so nothing is culled
+            let mut pipeline_config = PipelineConfig::new(device, shaders, EguiVertex::get_input_binding_description(), EguiVertex::get_attribute_descriptions(), &layout_bindings, msaa_samples, color_format, depth_format, allocator)?
+                .with_depth_test(false)
+                .with_depth_write(false)
+                .with_cull_mode(vk::CullModeFlags::NONE)
+                .with_premultiplied_alpha(true);
+            // Creating the pipeline also creates the descriptor set layout the textures are written to
+            pipeline_manager.get_or_create_pipeline(&mut pipeline_config, device, swapchain_extent, allocator)?;
+
+            let vertex_buffers = allocator.create_vertex_buffers(Self::MAX_VERTICES_PER_FRAME * std::mem::size_of::<EguiVertex>(), VkController::MAX_FRAMES_IN_FLIGHT)?;
+            let index_buffers = match allocator.create_index_buffers(Self::MAX_INDICES_PER_FRAME * std::mem::size_of::<u32>(), VkController::MAX_FRAMES_IN_FLIGHT) {
+                Ok(index_buffers) => index_buffers,
+                Err(e) => {
+                    let mut error_str = e.to_string();
+                    free_allocations_add_error_string!(allocator, [vertex_buffers], error_str);
+                    return Err(Cow::from(error_str));
+                },
+            };
+
+            Ok(Self {
+                pipeline_config,
+                textures: HashMap::new(),
+                retired_textures: Vec::new(),
+                pending_freed_textures: Vec::new(),
+                vertex_buffers,
+                index_buffers,
+                draws: std::array::from_fn(|_| Vec::new()),
+                queued_meshes: Vec::new(),
+            })
+        }
+
+        /// Creates and patches the textures of the delta. The textures it frees are kept until the frames that draw with them are done.
+        pub fn update_textures(&mut self, textures_delta: &TexturesDelta, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &CommandPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, allocator: &VkAllocator) -> Result<(), Cow<'static, str>> {
+            for (texture_id, image_delta) in textures_delta.set.iter() {
+                let (size, patch) = match &image_delta.image {
+                    ImageData::Color(image) => (image.size, image.pixels.iter().flat_map(|pixel| pixel.to_array()).collect::<Vec<u8>>()),
+                    ImageData::Font(image) => (image.size, image.srgba_pixels(None).flat_map(|pixel| pixel.to_array()).collect::<Vec<u8>>()),
+                };
+                let patch = RgbaImage::from_raw(size[0] as u32, size[1] as u32, patch).unwrap();
+                let pixels = match (image_delta.pos, self.textures.get(texture_id)) {
+                    (Some(pos), Some(texture)) => {
+                        let mut pixels = texture.pixels.clone();
+                        image::imageops::replace(&mut pixels, &patch, pos[0] as i64, pos[1] as i64);
+                        pixels
+                    },
+                    (Some(_), None) => return Err(Cow::Owned(format!("egui patched the texture {:?}, which has not been created", texture_id))),
+                    (None, _) => patch,
+                };
+                let filter = |texture_filter: TextureFilter| match texture_filter {
+                    TextureFilter::Nearest => vk::Filter::NEAREST,
+                    TextureFilter::Linear => vk::Filter::LINEAR,
+                };
+                let texture = self.create_texture(pixels, filter(image_delta.options.magnification), filter(image_delta.options.minification), device, instance, physical_device, command_pool, graphics_queue, sampler_manager, allocator)?;
+                if let Some(old_texture) = self.textures.insert(*texture_id, texture) {
+                    self.retired_textures.push((VkController::MAX_FRAMES_IN_FLIGHT, old_texture));
+                }
+            }
+            self.pending_freed_textures.extend(textures_delta.free.iter().copied());
+            Ok(())
+        }
+
+        /// Adds the meshes of the tessellated primitives to the next frame. Callbacks can't be drawn by the engine, so they are skipped.
+        pub fn queue_primitives(&mut self, primitives: Vec<ClippedPrimitive>, pixels_per_point: f32) {
+            for ClippedPrimitive { clip_rect, primitive } in primitives {
+                let Primitive::Mesh(mesh) = primitive else {
+                    log::trace!(target: logging::RENDERER, "Skipped an egui paint callback, which are not supported");
+                    continue;
+                };
+                if mesh.is_empty() {
+                    continue;
+                }
+                self.queued_meshes.push(QueuedMesh {
+                    clip_rect_px: egui::Rect::from_min_max((clip_rect.min.to_vec2() * pixels_per_point).to_pos2(), (clip_rect.max.to_vec2() * pixels_per_point).to_pos2()),
+                    texture_id: mesh.texture_id,
+                    vertices: mesh.vertices.iter().map(|vertex| EguiVertex {
+                        position_px: glm::Vec2::new(vertex.pos.x, vertex.pos.y) * pixels_per_point,
+                        tex_coord: glm::Vec2::new(vertex.uv.x, vertex.uv.y),
+                        color: vertex.color.to_array(),
+                    }).collect(),
+                    indices: mesh.indices,
+                });
+            }
+        }
+
+        /// Writes the queued meshes to the buffers of the frame and frees the textures the frame no longer draws with.
+        pub fn upload_queued_primitives(&mut self, current_frame: usize, device: &Device, allocator: &VkAllocator) {
+            let mut error_str = String::new();
+            for (num_frames_left, _) in self.retired_textures.iter_mut() {
+                *num_frames_left -= 1;
+            }
+            for (_, texture) in self.retired_textures.extract_if(.., |(num_frames_left, _)| *num_frames_left == 0) {
+                texture.destroy(device, allocator, &mut error_str);
+            }
+
+            let mut vertex_data = Vec::new();
+            let mut index_data = Vec::new();
+            let mut num_vertices = 0;
+            let mut num_indices = 0;
+            self.draws[current_frame].clear();
+            for mesh in self.queued_meshes.iter() {
+                let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                    log::trace!(target: logging::RENDERER, "Skipped an egui mesh with the texture {:?}, which has not been set", mesh.texture_id);
+                    continue;
+                };
+                if num_vertices + mesh.vertices.len() > Self::MAX_VERTICES_PER_FRAME || num_indices + mesh.indices.len() > Self::MAX_INDICES_PER_FRAME {
+                    log::trace!(target: logging::RENDERER, "Only the first {} of the {} egui meshes are drawn", self.draws[current_frame].len(), self.queued_meshes.len());
+                    break;
+                }
+                self.draws[current_frame].push(EguiDraw {
+                    clip_rect_px: mesh.clip_rect_px,
+                    descriptor_set: texture.descriptor_set,
+                    first_index: num_indices as u32,
+                    vertex_offset: num_vertices as i32,
+                    num_indices: mesh.indices.len() as u32,
+                });
+                vertex_data.extend(mesh.vertices.iter().flat_map(|vertex| vertex.to_u8()));
+                index_data.extend(mesh.indices.iter().flat_map(|index| index.to_ne_bytes()));
+                num_vertices += mesh.vertices.len();
+                num_indices += mesh.indices.len();
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(vertex_data.as_ptr(), self.vertex_buffers.get_uniform_pointers()[current_frame].cast::<u8>(), vertex_data.len());
+                std::ptr::copy_nonoverlapping(index_data.as_ptr(), self.index_buffers.get_uniform_pointers()[current_frame].cast::<u8>(), index_data.len());
+            }
+
+            for texture_id in self.pending_freed_textures.drain(..) {
+                if let Some(texture) = self.textures.remove(&texture_id) {
+                    self.retired_textures.push((VkController::MAX_FRAMES_IN_FLIGHT, texture));
+                }
+            }
+            if !error_str.is_empty() {
+                log::error!(target: logging::RENDERER, "Failed to free the retired egui textures: {}", error_str);
+            }
+        }
+
+        /// Draws the meshes of the frame in the render rect. Returns the number of recorded commands.
+        pub fn record_draw(&self, device: &Device, command_buffer: &vk::CommandBuffer, global_descriptor_set: vk::DescriptorSet, render_rect: &vk::Rect2D, swapchain_extent: &vk::Extent2D, pipeline_manager: &mut PipelineManager, current_frame: usize, allocator: &VkAllocator) -> usize {
+            if self.draws[current_frame].is_empty() {
+                return 0;
+            }
+            let mut p_c = self.pipeline_config.clone();
+            let pipeline = pipeline_manager.get_or_create_pipeline(&mut p_c, device, swapchain_extent, allocator).unwrap();
+            let pipeline_layout = p_c.get_pipeline_layout().unwrap();
+            let (vertex_buffer, vertex_buffer_offset) = Self::get_frame_buffer(&self.vertex_buffers, current_frame);
+            let (index_buffer, index_buffer_offset) = Self::get_frame_buffer(&self.index_buffers, current_frame);
+            let render_rect_min = egui::Pos2::new(render_rect.offset.x as f32, render_rect.offset.y as f32);
+            let render_rect_px = egui::Rect::from_min_size(render_rect_min, egui::Vec2::new(render_rect.extent.width as f32, render_rect.extent.height as f32));
+            let mut num_recorded_commands = 0;
+            unsafe {
+                device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                device.cmd_set_viewport(*command_buffer, 0, &[VkController::get_viewport(render_rect)]);
+                device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 0, &[global_descriptor_set], &[0]);
+                device.cmd_bind_vertex_buffers(*command_buffer, 0, &[vertex_buffer], &[vertex_buffer_offset]);
+                device.cmd_bind_index_buffer(*command_buffer, index_buffer, index_buffer_offset, vk::IndexType::UINT32);
+                num_recorded_commands += 5;
+                let mut bound_descriptor_set = None;
+                for draw in self.draws[current_frame].iter() {
+                    // The clip rect is relative to the render rect, and the scissor can't be outside of it
+                    let clip_rect = draw.clip_rect_px.translate(render_rect_min.to_vec2()).intersect(render_rect_px);
+                    if clip_rect.width() < 1.0 || clip_rect.height() < 1.0 {
+                        continue;
+                    }
+                    let scissor = vk::Rect2D {
+                        offset: vk::Offset2D { x: clip_rect.min.x.round() as i32, y: clip_rect.min.y.round() as i32 },
+                        extent: vk::Extent2D { width: clip_rect.width().round() as u32, height: clip_rect.height().round() as u32 },
+                    };
+                    device.cmd_set_scissor(*command_buffer, 0, &[scissor]);
+                    if bound_descriptor_set != Some(draw.descriptor_set) {
+                        device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 1, &[draw.descriptor_set], &[]);
+                        bound_descriptor_set = Some(draw.descriptor_set);
+                        num_recorded_commands += 1;
+                    }
+                    device.cmd_draw_indexed(*command_buffer, draw.num_indices, 1, draw.first_index, draw.vertex_offset, 0);
+                    num_recorded_commands += 2;
+                }
+            }
+            num_recorded_commands
+        }
+
+        pub fn clear_queued_primitives(&mut self) {
+            self.queued_meshes.clear();
+        }
+
+        /// The pipeline is owned by the pipeline manager, so it is destroyed with the other pipelines.
+        pub fn destroy(self, device: &Device, allocator: &VkAllocator) {
+            let mut error_str = String::new();
+            for texture in self.textures.into_values().chain(self.retired_textures.into_iter().map(|(_, texture)| texture)) {
+                texture.destroy(device, allocator, &mut error_str);
+            }
+            free_allocations_add_error_string!(allocator, [self.vertex_buffers, self.index_buffers], error_str);
+            if !error_str.is_empty() {
+                log::error!(target: logging::RENDERER, "Failed to free the egui renderer: {}", error_str);
+            }
+        }
+
+        // The buffer and the offset of the frame's part in it
+        fn get_frame_buffer(buffers: &AllocationInfo, current_frame: usize) -> (vk::Buffer, vk::DeviceSize) {
+            let offset = unsafe { buffers.get_uniform_pointers()[current_frame].offset_from(buffers.get_uniform_pointers()[0]) } as vk::DeviceSize;
+            (buffers.get_buffer().unwrap(), offset)
+        }
+
+        fn create_texture(&self, pixels: RgbaImage, mag_filter: vk::Filter, min_filter: vk::Filter, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &CommandPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, allocator: &VkAllocator) -> Result<EguiTexture, Cow<'static, str>> {
+            let mut image = allocator.create_device_local_image(DynamicImage::ImageRgba8(pixels.clone()), command_pool, graphics_queue, 1, vk::SampleCountFlags::TYPE_1, false)?;
+            // The format needs to be the same as the format read in [`VkAllocator::create_device_local_image`]
+            if let Err(e) = allocator.create_image_view(&mut image, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D) {
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [image], error_str);
+                return Err(Cow::from(error_str));
+            }
+
+            let sampler_config = SamplerConfig {
+                s_type: StructureType::SAMPLER_CREATE_INFO,
+                mag_filter,
+                min_filter,
+                address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                anisotropy_enable: vk::FALSE,
+                max_anisotropy: None,
+                border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+                unnormalized_coordinates: vk::FALSE,
+                compare_enable: vk::FALSE,
+                compare_op: vk::CompareOp::ALWAYS,
+                mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+                mip_lod_bias: 0.0,
+                min_lod: 0.0,
+                max_lod: 0.0,
+            };
+            let sampler = match sampler_manager.get_or_create_sampler(device, instance, physical_device, sampler_config, allocator) {
+                Ok(sampler) => sampler,
+                Err(e) => {
+                    let mut error_str = e.to_string();
+                    free_allocations_add_error_string!(allocator, [image], error_str);
+                    return Err(Cow::from(error_str));
+                },
+            };
+
+            let (descriptor_pool, descriptor_set) = match Self::create_descriptor_set(device, *self.pipeline_config.borrow_descriptor_set_layout().unwrap(), allocator) {
+                Ok(descriptor) => descriptor,
+                Err(e) => {
+                    let mut error_str = e.to_string();
+                    free_allocations_add_error_string!(allocator, [image], error_str);
+                    return Err(Cow::from(error_str));
+                },
+            };
+            let image_info = vk::DescriptorImageInfo {
+                sampler,
+                image_view: image.get_image_view().unwrap(),
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            let descriptor_write = vk::WriteDescriptorSet {
+                s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                dst_set: descriptor_set,
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                p_image_info: &image_info,
+                ..Default::default()
+            };
+            unsafe {
+                device.update_descriptor_sets(&[descriptor_write], &[]);
+            }
+
+            Ok(EguiTexture {
+                pixels,
+                image,
+                descriptor_pool,
+                descriptor_set,
+            })
+        }
+
+        fn create_descriptor_set(device: &Device, descriptor_set_layout: vk::DescriptorSetLayout, allocator: &VkAllocator) -> Result<(DescriptorPool, DescriptorSet), Cow<'static, str>> {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                },
+            ];
+            let pool_info = vk::DescriptorPoolCreateInfo {
+                s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+                pool_size_count: pool_sizes.len() as u32,
+                p_pool_sizes: pool_sizes.as_ptr(),
+                max_sets: 1,
+                ..Default::default()
+            };
+            let descriptor_pool = unsafe {
+                device.create_descriptor_pool(&pool_info, allocator.get_allocation_callbacks())
+            }.map_err(|err| Cow::Owned(format!("Failed to create an egui texture descriptor pool: {}", err)))?;
+
+            let alloc_info = vk::DescriptorSetAllocateInfo {
+                s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+                descriptor_pool,
+                descriptor_set_count: 1,
+                p_set_layouts: &descriptor_set_layout,
+                ..Default::default()
+            };
+            match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+                Ok(descriptor_sets) => Ok((descriptor_pool, descriptor_sets[0])),
+                Err(err) => {
+                    unsafe {
+                        device.destroy_descriptor_pool(descriptor_pool, allocator.get_allocation_callbacks());
+                    }
+                    Err(Cow::Owned(format!("Failed to allocate an egui texture descriptor set: {}", err)))
+                },
+            }
+        }
+    }
+}
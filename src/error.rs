@@ -0,0 +1,120 @@
+use std::{borrow::Cow, fmt::{Display, Formatter}};
+
+use ash::vk;
+
+use crate::{logging, asset_resolver::AssetError, pipeline_manager::ShaderSource, vk_controller::ObjectID};
+
+/// The errors of the engine, so they can be handled by what went wrong instead of by the message.
+/// Errors that aren't worth handling on their own are [`EngineError::Other`] with the message.
+#[derive(Debug)]
+pub enum EngineError {
+    /// A Vulkan call failed. `context` says what the engine was doing when it failed.
+    VulkanApi { context: Cow<'static, str>, result: vk::Result },
+    /// The device memory ran out, or the allocation would have taken the heap past the part of its budget the allocator may use. Freeing objects or textures and trying again can work.
+    OutOfDeviceMemory { heap_index: u32, bytes_requested: u64 },
+    /// The object hasn't been added, or has already been removed.
+    ObjectNotFound(ObjectID),
+    /// The shader couldn't be read or compiled. `log` is the compiler's output.
+    PipelineCreation { shader: ShaderSource, log: String },
+    Asset(AssetError),
+    AssetIo(std::io::Error),
+    Other(Cow<'static, str>),
+}
+
+impl EngineError {
+    pub fn vulkan(context: impl Into<Cow<'static, str>>, result: vk::Result) -> Self {
+        EngineError::VulkanApi { context: context.into(), result }
+    }
+
+    /// The device was lost, so nothing but recreating the renderer can recover from it.
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self, EngineError::VulkanApi { result: vk::Result::ERROR_DEVICE_LOST, .. })
+    }
+
+    pub fn is_out_of_device_memory(&self) -> bool {
+        matches!(self, EngineError::OutOfDeviceMemory { .. } | EngineError::VulkanApi { result: vk::Result::ERROR_OUT_OF_DEVICE_MEMORY, .. })
+    }
+
+    /// Reports the errors from freeing what was allocated before the error, and keeps the error itself so it can still be handled.
+    pub fn with_cleanup_errors(self, cleanup_errors: String) -> Self {
+        if !cleanup_errors.is_empty() {
+            log::error!(target: logging::ALLOCATOR, "Failed to free the allocations after the error \"{}\":{}", self, cleanup_errors);
+        }
+        self
+    }
+
+    /// For the code that still uses the message as the error. This will be removed when everything uses [`EngineError`].
+    pub fn to_cow(&self) -> Cow<'static, str> {
+        match self {
+            EngineError::Other(message) => message.clone(),
+            _ => Cow::Owned(self.to_string()),
+        }
+    }
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::VulkanApi { context, result } => write!(f, "{} because: {}", context, result),
+            EngineError::OutOfDeviceMemory { heap_index, bytes_requested } => write!(f, "Ran out of device memory in heap {} when allocating {} bytes", heap_index, bytes_requested),
+            EngineError::ObjectNotFound(object_id) => write!(f, "The object with id {:?} has not been added", object_id),
+            EngineError::PipelineCreation { shader, log } => write!(f, "Failed to compile the shader {}: {}", shader, log),
+            EngineError::Asset(error) => write!(f, "{}", error),
+            EngineError::AssetIo(error) => write!(f, "Failed to read an asset: {}", error),
+            EngineError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::VulkanApi { result, .. } => Some(result),
+            EngineError::AssetIo(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<vk::Result> for EngineError {
+    fn from(result: vk::Result) -> Self {
+        EngineError::vulkan("A Vulkan call failed", result)
+    }
+}
+
+impl From<AssetError> for EngineError {
+    fn from(error: AssetError) -> Self {
+        EngineError::Asset(error)
+    }
+}
+
+impl From<std::io::Error> for EngineError {
+    fn from(error: std::io::Error) -> Self {
+        EngineError::AssetIo(error)
+    }
+}
+
+impl From<Cow<'static, str>> for EngineError {
+    fn from(message: Cow<'static, str>) -> Self {
+        EngineError::Other(message)
+    }
+}
+
+impl From<&'static str> for EngineError {
+    fn from(message: &'static str) -> Self {
+        EngineError::Other(Cow::Borrowed(message))
+    }
+}
+
+impl From<String> for EngineError {
+    fn from(message: String) -> Self {
+        EngineError::Other(Cow::Owned(message))
+    }
+}
+
+// So the modules that still return the message can use ? on the functions that return an EngineError
+impl From<EngineError> for Cow<'static, str> {
+    fn from(error: EngineError) -> Self {
+        error.to_cow()
+    }
+}
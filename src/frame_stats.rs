@@ -0,0 +1,91 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+/// Frame time statistics over the last frames, for showing the FPS. Call [`FrameStats::tick`] once per drawn frame.
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    // The newest frame is at the back
+    frame_times: VecDeque<Duration>,
+    window_size: usize,
+    last_tick: Option<Instant>,
+}
+
+impl FrameStats {
+    /// `window_size` is the number of frames the averages, min, max and 1% low are over.
+    pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(1);
+        Self {
+            frame_times: VecDeque::with_capacity(window_size),
+            window_size,
+            last_tick: None,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.tick_at(Instant::now());
+    }
+
+    /// The first tick only starts the first frame, so there is no frame time until the second tick.
+    pub fn tick_at(&mut self, now: Instant) {
+        if let Some(last_tick) = self.last_tick {
+            if self.frame_times.len() == self.window_size {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(now.saturating_duration_since(last_tick));
+        }
+        self.last_tick = Some(now);
+    }
+
+    pub fn last_frame_time(&self) -> Option<Duration> {
+        self.frame_times.back().copied()
+    }
+
+    /// The FPS of the last frame alone, which jumps around a lot.
+    pub fn fps(&self) -> Option<f32> {
+        self.last_frame_time().map(Self::to_fps)
+    }
+
+    pub fn average_frame_time(&self) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        Some(self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32)
+    }
+
+    /// The number of frames divided by the time they took, so long frames count for as long as they took.
+    pub fn average_fps(&self) -> Option<f32> {
+        self.average_frame_time().map(Self::to_fps)
+    }
+
+    pub fn min_frame_time(&self) -> Option<Duration> {
+        self.frame_times.iter().min().copied()
+    }
+
+    pub fn max_frame_time(&self) -> Option<Duration> {
+        self.frame_times.iter().max().copied()
+    }
+
+    /// The average FPS of the slowest 1% of the frames, or the slowest frame when there are fewer than 100. This shows stutters that the average hides.
+    pub fn one_percent_low_fps(&self) -> Option<f32> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let mut frame_times = self.frame_times.iter().copied().collect::<Vec<_>>();
+        frame_times.sort_unstable_by(|a, b| b.cmp(a));
+        let slowest = &frame_times[..frame_times.len().div_ceil(100)];
+        Some(Self::to_fps(slowest.iter().sum::<Duration>() / slowest.len() as u32))
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// Forgets the frames, for example after a loading screen that would drag the statistics down.
+    pub fn reset(&mut self) {
+        self.frame_times.clear();
+        self.last_tick = None;
+    }
+
+    fn to_fps(frame_time: Duration) -> f32 {
+        if frame_time.is_zero() { f32::INFINITY } else { 1.0 / frame_time.as_secs_f32() }
+    }
+}
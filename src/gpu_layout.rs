@@ -0,0 +1,270 @@
+//! The std140 and std430 layouts of the data that is given to the shaders in buffers, see [`GpuLayout`].
+//! Uniform blocks use std140 and storage blocks use std430, so the same struct can have other offsets in the two.
+
+use nalgebra_glm as glm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockLayout {
+    Std140,
+    Std430,
+}
+
+impl BlockLayout {
+    pub const fn of<T: GpuLayout + ?Sized>(self) -> TypeLayout {
+        match self {
+            BlockLayout::Std140 => T::STD140,
+            BlockLayout::Std430 => T::STD430,
+        }
+    }
+}
+
+/// The alignment and the size in bytes of a type as a member of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub alignment: usize,
+    pub size: usize,
+}
+
+/// Data with the layout the shaders read it with, instead of the layout Rust gives it. A vec3 is aligned like a vec4 and the columns of a mat3 are 16 bytes apart, so copying the Rust struct only works by luck.
+/// Structs get it with [`crate::impl_gpu_layout`].
+pub trait GpuLayout {
+    const STD140: TypeLayout;
+    const STD430: TypeLayout;
+
+    /// Writes to the start of the bytes, which are at least as many as the size of the layout. The padding is left as it is.
+    fn write_bytes(&self, layout: BlockLayout, bytes: &mut [u8]);
+
+    fn std140_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; Self::STD140.size];
+        self.write_bytes(BlockLayout::Std140, &mut bytes);
+        bytes
+    }
+
+    fn std430_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; Self::STD430.size];
+        self.write_bytes(BlockLayout::Std430, &mut bytes);
+        bytes
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// The layout of a struct with the given members in order. std140 rounds the alignment of a struct up to 16 bytes.
+pub const fn get_struct_layout(layout: BlockLayout, members: &[TypeLayout]) -> TypeLayout {
+    let mut alignment = match layout {
+        BlockLayout::Std140 => 16,
+        BlockLayout::Std430 => 1,
+    };
+    let mut offset: usize = 0;
+    let mut i = 0;
+    while i < members.len() {
+        alignment = max(alignment, members[i].alignment);
+        offset = offset.next_multiple_of(members[i].alignment) + members[i].size;
+        i += 1;
+    }
+    TypeLayout { alignment, size: offset.next_multiple_of(alignment) }
+}
+
+/// Fails to compile when used in a constant, like `const _: () = assert_std140_size::<MyData>(80);`, if the std140 size of the type isn't the expected one.
+pub const fn assert_std140_size<T: GpuLayout>(expected: usize) {
+    if T::STD140.size != expected {
+        panic!("The std140 size of the type isn't the expected size");
+    }
+}
+
+/// Like [`assert_std140_size`], for the std430 layout of storage buffers.
+pub const fn assert_std430_size<T: GpuLayout>(expected: usize) {
+    if T::STD430.size != expected {
+        panic!("The std430 size of the type isn't the expected size");
+    }
+}
+
+/// Implements [`GpuLayout`] for a struct whose members are laid out in the order they are listed, which has to be the order of the members in the shader.
+/// For example `impl_gpu_layout!(LightData { position: glm::Vec3, intensity: f32, color: glm::Vec4 });`.
+#[macro_export]
+macro_rules! impl_gpu_layout {
+    ($struct_name: ty { $($field: ident: $field_type: ty),+ $(,)? }) => {
+        impl $crate::gpu_layout::GpuLayout for $struct_name {
+            const STD140: $crate::gpu_layout::TypeLayout = $crate::gpu_layout::get_struct_layout($crate::gpu_layout::BlockLayout::Std140, &[$(<$field_type as $crate::gpu_layout::GpuLayout>::STD140),+]);
+            const STD430: $crate::gpu_layout::TypeLayout = $crate::gpu_layout::get_struct_layout($crate::gpu_layout::BlockLayout::Std430, &[$(<$field_type as $crate::gpu_layout::GpuLayout>::STD430),+]);
+
+            fn write_bytes(&self, layout: $crate::gpu_layout::BlockLayout, bytes: &mut [u8]) {
+                let mut offset: usize = 0;
+                $(
+                    let member_layout = layout.of::<$field_type>();
+                    offset = offset.next_multiple_of(member_layout.alignment);
+                    <$field_type as $crate::gpu_layout::GpuLayout>::write_bytes(&self.$field, layout, &mut bytes[offset..]);
+                    offset += member_layout.size;
+                )+
+                let _ = offset;
+            }
+        }
+    };
+}
+
+macro_rules! impl_gpu_layout_for_scalar {
+    ($scalar_type: ty) => {
+        impl GpuLayout for $scalar_type {
+            const STD140: TypeLayout = TypeLayout { alignment: 4, size: 4 };
+            const STD430: TypeLayout = TypeLayout { alignment: 4, size: 4 };
+
+            fn write_bytes(&self, _layout: BlockLayout, bytes: &mut [u8]) {
+                bytes[..4].copy_from_slice(&self.to_ne_bytes());
+            }
+        }
+    };
+}
+
+impl_gpu_layout_for_scalar!(f32);
+impl_gpu_layout_for_scalar!(u32);
+impl_gpu_layout_for_scalar!(i32);
+
+macro_rules! impl_gpu_layout_for_vector {
+    ($vector_type: ty, $alignment: expr, $size: expr) => {
+        impl GpuLayout for $vector_type {
+            const STD140: TypeLayout = TypeLayout { alignment: $alignment, size: $size };
+            const STD430: TypeLayout = TypeLayout { alignment: $alignment, size: $size };
+
+            fn write_bytes(&self, layout: BlockLayout, bytes: &mut [u8]) {
+                for (i, component) in self.iter().enumerate() {
+                    component.write_bytes(layout, &mut bytes[i * 4..]);
+                }
+            }
+        }
+    };
+}
+
+impl_gpu_layout_for_vector!(glm::Vec2, 8, 8);
+impl_gpu_layout_for_vector!(glm::Vec3, 16, 12);
+impl_gpu_layout_for_vector!(glm::Vec4, 16, 16);
+impl_gpu_layout_for_vector!(glm::UVec2, 8, 8);
+impl_gpu_layout_for_vector!(glm::UVec3, 16, 12);
+impl_gpu_layout_for_vector!(glm::UVec4, 16, 16);
+impl_gpu_layout_for_vector!(glm::IVec2, 8, 8);
+impl_gpu_layout_for_vector!(glm::IVec3, 16, 12);
+impl_gpu_layout_for_vector!(glm::IVec4, 16, 16);
+
+// The matrices are column major, and the columns of these are as far apart as vec4s in both layouts
+macro_rules! impl_gpu_layout_for_matrix {
+    ($matrix_type: ty, $num_columns: expr) => {
+        impl GpuLayout for $matrix_type {
+            const STD140: TypeLayout = TypeLayout { alignment: 16, size: 16 * $num_columns };
+            const STD430: TypeLayout = TypeLayout { alignment: 16, size: 16 * $num_columns };
+
+            fn write_bytes(&self, layout: BlockLayout, bytes: &mut [u8]) {
+                for (i, column) in self.column_iter().enumerate() {
+                    for (j, component) in column.iter().enumerate() {
+                        component.write_bytes(layout, &mut bytes[i * 16 + j * 4..]);
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_gpu_layout_for_matrix!(glm::Mat3, 3);
+impl_gpu_layout_for_matrix!(glm::Mat4, 4);
+
+/// std140 rounds the stride of the elements up to 16 bytes, so a `float[4]` is 64 bytes in a uniform block and 16 in a storage block.
+impl<T: GpuLayout, const N: usize> GpuLayout for [T; N] {
+    const STD140: TypeLayout = TypeLayout { alignment: max(T::STD140.alignment, 16), size: N * T::STD140.size.next_multiple_of(max(T::STD140.alignment, 16)) };
+    const STD430: TypeLayout = TypeLayout { alignment: T::STD430.alignment, size: N * T::STD430.size.next_multiple_of(T::STD430.alignment) };
+
+    fn write_bytes(&self, layout: BlockLayout, bytes: &mut [u8]) {
+        let element_layout = layout.of::<T>();
+        let stride = match layout {
+            BlockLayout::Std140 => element_layout.size.next_multiple_of(max(element_layout.alignment, 16)),
+            BlockLayout::Std430 => element_layout.size.next_multiple_of(element_layout.alignment),
+        };
+        for (i, element) in self.iter().enumerate() {
+            element.write_bytes(layout, &mut bytes[i * stride..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A float after a vec3 fills its last 4 bytes, but a vec3 after a float starts at the next 16 bytes
+    struct PackedData {
+        position: glm::Vec3,
+        intensity: f32,
+    }
+
+    crate::impl_gpu_layout!(PackedData { position: glm::Vec3, intensity: f32 });
+
+    struct PaddedData {
+        intensity: f32,
+        position: glm::Vec3,
+        weights: [f32; 2],
+        transform: glm::Mat4,
+    }
+
+    crate::impl_gpu_layout!(PaddedData { intensity: f32, position: glm::Vec3, weights: [f32; 2], transform: glm::Mat4 });
+
+    const _: () = assert_std140_size::<glm::Vec3>(12);
+    const _: () = assert_std430_size::<glm::Vec3>(12);
+    const _: () = assert_std140_size::<glm::Mat3>(48);
+    const _: () = assert_std140_size::<glm::Mat4>(64);
+    const _: () = assert_std430_size::<glm::Mat4>(64);
+    const _: () = assert_std140_size::<[f32; 4]>(64);
+    const _: () = assert_std430_size::<[f32; 4]>(16);
+    const _: () = assert_std140_size::<[glm::Vec3; 2]>(32);
+    const _: () = assert_std430_size::<[glm::Vec3; 2]>(32);
+    const _: () = assert_std140_size::<[glm::Vec2; 3]>(48);
+    const _: () = assert_std430_size::<[glm::Vec2; 3]>(24);
+    const _: () = assert_std140_size::<PackedData>(16);
+    const _: () = assert_std430_size::<PackedData>(16);
+    // 4 + 12 padding, 12 + 4 padding, 2 * 16 for the std140 array, and 64
+    const _: () = assert_std140_size::<PaddedData>(128);
+    // 4 + 12 padding, 12 that the float array follows right after, 2 * 4 + 12 padding to align the mat4, and 64
+    const _: () = assert_std430_size::<PaddedData>(112);
+
+    fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn vec3_and_arrays_are_aligned_like_the_shaders_read_them() {
+        assert_eq!(glm::Vec3::STD140, TypeLayout { alignment: 16, size: 12 });
+        assert_eq!(<[f32; 4]>::STD140, TypeLayout { alignment: 16, size: 64 });
+        assert_eq!(<[f32; 4]>::STD430, TypeLayout { alignment: 4, size: 16 });
+
+        let array = [1.0f32, 2.0, 3.0, 4.0];
+        let std140_bytes = array.std140_bytes();
+        let std430_bytes = array.std430_bytes();
+        for (i, value) in array.iter().enumerate() {
+            assert_eq!(read_f32(&std140_bytes, i * 16), *value);
+            assert_eq!(read_f32(&std430_bytes, i * 4), *value);
+        }
+    }
+
+    #[test]
+    fn mat4_is_written_column_by_column() {
+        let matrix = glm::Mat4::from_fn(|row, column| (row + 4 * column) as f32);
+        let bytes = matrix.std140_bytes();
+        assert_eq!(bytes, matrix.std430_bytes());
+        for column in 0..4 {
+            for row in 0..4 {
+                assert_eq!(read_f32(&bytes, column * 16 + row * 4), (row + 4 * column) as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn struct_members_are_at_their_std140_and_std430_offsets() {
+        let packed_data = PackedData { position: glm::vec3(1.0, 2.0, 3.0), intensity: 4.0 };
+        let bytes = packed_data.std140_bytes();
+        assert_eq!(read_f32(&bytes, 8), 3.0);
+        assert_eq!(read_f32(&bytes, 12), 4.0);
+
+        let padded_data = PaddedData { intensity: 1.0, position: glm::vec3(2.0, 3.0, 4.0), weights: [5.0, 6.0], transform: glm::Mat4::from_element(7.0) };
+        let std140_bytes = padded_data.std140_bytes();
+        assert_eq!([0, 16, 20, 24, 32, 48, 64, 124].map(|offset| read_f32(&std140_bytes, offset)), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 7.0]);
+        let std430_bytes = padded_data.std430_bytes();
+        assert_eq!([0, 16, 20, 24, 28, 32, 48, 108].map(|offset| read_f32(&std430_bytes, offset)), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 7.0]);
+    }
+}
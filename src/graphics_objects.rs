@@ -1,9 +1,10 @@
-use std::{borrow::Cow, collections::{hash_map, HashMap}, fmt::Formatter, path::PathBuf, sync::{Arc, RwLock}, time::Instant};
+use std::{borrow::Cow, hash::{DefaultHasher, Hash, Hasher}, path::PathBuf, sync::{Arc, Mutex, PoisonError, RwLock}};
 
 use ash::{vk::{self, CommandPool, DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorType, PhysicalDevice, Queue, Sampler, StructureType, WriteDescriptorSet}, Device, Instance};
 use image::DynamicImage;
+use nalgebra_glm as glm;
 
-use crate::{pipeline_manager::{ObjectInstanceGraphicsResource, ObjectInstanceGraphicsResourceType, ObjectTypeGraphicsResource, ObjectTypeGraphicsResourceType, PipelineConfig, PipelineManager, ShaderInfo, Vertex}, sampler_manager::{SamplerConfig, SamplerManager}, vertex::SimpleVertex, vk_allocator::{AllocationInfo, Serializable, VkAllocator}, vk_controller::{self, IndexAllocation, VertexAllocation, VerticesIndicesHash, VkController}};
+use crate::{builtin_shaders::BuiltinShader, gpu_layout::{BlockLayout, GpuLayout, TypeLayout}, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectInstanceGraphicsResourceType, ObjectTypeGraphicsResource, ObjectTypeGraphicsResourceType, PipelineConfig, PipelineManager, ResourceLoadState, ShaderInfo, Vertex}, lighting::LitVertex, render_target::RenderTargetId, sampler_manager::{SamplerConfig, SamplerManager}, sprite::SpriteInstanceData, vertex::SimpleVertex, vk_allocator::{AllocationInfo, Serializable, VkAllocator}, vk_controller::{self, IndexAllocation, VertexAllocation, VerticesIndicesHash, VkController}};
 
 #[macro_export]
 macro_rules! free_allocations_add_error_string {
@@ -20,19 +21,80 @@ macro_rules! free_allocations_add_error_string {
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
 pub struct ResourceID(pub u32);
 
+/// Objects with the same mesh share one object type and its type resources, unless their material keys differ.
+/// The default key gives the object type of the mesh alone.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Default)]
+pub struct MaterialKey(pub u64);
+
+impl MaterialKey {
+    /// A key for each resource, so that objects with different textures get their own object types.
+    pub fn from_resource<T: ?Sized>(resource: &Arc<T>) -> Self {
+        MaterialKey(Arc::as_ptr(resource) as *const () as usize as u64)
+    }
+}
+
+/// A range of the indices of a mesh that is drawn with its own type resources, like one material group of an imported model.
+/// The submeshes share the vertex and index buffers of the mesh, and each one is drawn with its own descriptor set.
+#[derive(Clone)]
+pub struct Submesh {
+    pub first_index: u32,
+    pub num_indices: u32,
+    pub type_resources: Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)>,
+}
+
+/// The index of a texture in the bindless texture array, which shaders declare as `layout(set = 2, binding = 0) uniform sampler2D textures[]`.
+/// It can be given to the instances through a storage buffer to select a texture per instance.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub struct TextureHandle(pub u32);
+
+impl Serializable for TextureHandle {
+    fn to_u8(&self) -> Vec<u8> {
+        self.0.to_ne_bytes().to_vec()
+    }
+}
+
+impl GpuLayout for TextureHandle {
+    const STD140: TypeLayout = u32::STD140;
+    const STD430: TypeLayout = u32::STD430;
+
+    fn write_bytes(&self, layout: BlockLayout, bytes: &mut [u8]) {
+        self.0.write_bytes(layout, bytes);
+    }
+}
+
+impl Serializable for glm::Mat4 {
+    fn to_u8(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(std::mem::size_of::<glm::Mat4>());
+        for value in self.iter() {
+            result.extend_from_slice(&value.to_ne_bytes());
+        }
+
+        result
+    }
+}
+
 #[derive(Clone)]
 pub struct UniformBufferResource<T: Clone> {
     pub buffer: T,
     pub binding: u32,
 }
 
+/// Per-instance data that shaders read as a single `uniform` block instead of indexing an array in a storage buffer.
+/// Every instance is drawn with its own draw call, with a dynamic offset to its part of the buffer.
+#[derive(Clone)]
+pub struct DynamicUniformBufferResource<T: Clone> {
+    pub buffer: T,
+    pub binding: u32,
+}
+
 #[derive(Clone)]
 pub struct StorageBufferResource<T: Clone> {
     pub buffer: T,
     pub binding: u32,
 }
 
-impl<T: Clone + Serializable> ObjectTypeGraphicsResource for UniformBufferResource<T> {
+/// The buffer is written with the std140 layout as a type resource, and as one std430 element of the storage buffer array as an instance resource.
+impl<T: Clone + GpuLayout> ObjectTypeGraphicsResource for UniformBufferResource<T> {
     fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
         vk::DescriptorSetLayoutBinding {
             binding: self.binding,
@@ -44,11 +106,11 @@ impl<T: Clone + Serializable> ObjectTypeGraphicsResource for UniformBufferResour
     }
 
     fn get_resource(&self) -> crate::pipeline_manager::ObjectTypeGraphicsResourceType {
-        ObjectTypeGraphicsResourceType::UniformBuffer(self.buffer.to_u8())
+        ObjectTypeGraphicsResourceType::UniformBuffer(self.buffer.std140_bytes())
     }
 }
 
-impl<T:Clone + Serializable> ObjectInstanceGraphicsResource for UniformBufferResource<T> {
+impl<T: Clone + GpuLayout> ObjectInstanceGraphicsResource for UniformBufferResource<T> {
     fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
         vk::DescriptorSetLayoutBinding {
             binding: self.binding,
@@ -60,7 +122,23 @@ impl<T:Clone + Serializable> ObjectInstanceGraphicsResource for UniformBufferRes
     }
 
     fn get_resource(&self) -> crate::pipeline_manager::ObjectInstanceGraphicsResourceType {
-        ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(self.buffer.to_u8())
+        ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(self.buffer.std430_bytes())
+    }
+}
+
+impl<T: Clone + GpuLayout> ObjectInstanceGraphicsResource for DynamicUniformBufferResource<T> {
+    fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding: self.binding,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            p_immutable_samplers: std::ptr::null(),
+        }
+    }
+
+    fn get_resource(&self) -> ObjectInstanceGraphicsResourceType {
+        ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(self.buffer.std140_bytes())
     }
 }
 
@@ -68,9 +146,77 @@ pub struct TextureResource {
     pub image: DynamicImage,
     pub binding: u32,
     pub stage: vk::ShaderStageFlags,
+    /// Textures with the same key share one image on the GPU. When None the image bytes are hashed instead.
+    pub asset_key: Option<String>,
+    // Set by from_path_async, the image is then the one in the load instead
+    load: Option<TextureLoad>,
     // pub sampler: Sampler,
 }
 
+enum TextureLoadState {
+    Loading,
+    Loaded(DynamicImage),
+    Failed(String),
+}
+
+/// An image that is read and decoded on a background thread.
+#[derive(Clone)]
+pub struct TextureLoad(Arc<Mutex<TextureLoadState>>);
+
+impl TextureLoad {
+    pub fn get_state(&self) -> ResourceLoadState {
+        match &*self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            TextureLoadState::Loading => ResourceLoadState::Loading,
+            TextureLoadState::Loaded(_) => ResourceLoadState::Ready,
+            TextureLoadState::Failed(reason) => ResourceLoadState::Failed(reason.clone()),
+        }
+    }
+
+    fn get_image(&self) -> Option<DynamicImage> {
+        match &*self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            TextureLoadState::Loaded(image) => Some(image.clone()),
+            TextureLoadState::Loading | TextureLoadState::Failed(_) => None,
+        }
+    }
+}
+
+impl TextureResource {
+    pub fn new(image: DynamicImage, binding: u32, stage: vk::ShaderStageFlags, asset_key: Option<String>) -> Self {
+        Self {
+            image,
+            binding,
+            stage,
+            asset_key,
+            load: None,
+        }
+    }
+
+    /// Returns right away and reads the image on the rayon thread pool, so the disk IO and decoding don't block the caller. The path is the asset key, so textures of the same path share the image on the GPU.
+    /// The texture is drawn with a grey placeholder until the image has been read, see [`crate::vk_controller::VkController::pending_texture_loads`].
+    /// When it can't be read it is drawn as the missing texture, and the reason is in [`crate::vk_controller::VkController::get_resource_errors`].
+    pub fn from_path_async(path: impl Into<PathBuf>, binding: u32, stage: vk::ShaderStageFlags) -> Self {
+        let path = path.into();
+        let load = TextureLoad(Arc::new(Mutex::new(TextureLoadState::Loading)));
+        let thread_load = load.clone();
+        let thread_path = path.clone();
+        rayon::spawn(move || {
+            let state = match image::open(&thread_path) {
+                Ok(image) => TextureLoadState::Loaded(image),
+                Err(e) => TextureLoadState::Failed(format!("{} could not be read: {}", thread_path.display(), e)),
+            };
+            *thread_load.0.lock().unwrap_or_else(PoisonError::into_inner) = state;
+        });
+
+        Self {
+            image: DynamicImage::new_rgba8(0, 0),
+            binding,
+            stage,
+            asset_key: Some(path.to_string_lossy().into_owned()),
+            load: Some(load),
+        }
+    }
+}
+
 impl ObjectTypeGraphicsResource for TextureResource {
     fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
         vk::DescriptorSetLayoutBinding {
@@ -83,28 +229,158 @@ impl ObjectTypeGraphicsResource for TextureResource {
     }
 
     fn get_resource(&self) -> ObjectTypeGraphicsResourceType {
-        ObjectTypeGraphicsResourceType::Texture(self.image.clone())
+        let image = self.load.as_ref().and_then(TextureLoad::get_image).unwrap_or_else(|| self.image.clone());
+        ObjectTypeGraphicsResourceType::Texture(image, self.asset_key.clone())
+    }
+
+    fn get_load_state(&self) -> ResourceLoadState {
+        self.load.as_ref().map_or(ResourceLoadState::Ready, TextureLoad::get_state)
+    }
+}
+
+/// Shaders read it as a `samplerCube`.
+pub struct CubeMapResource {
+    /// +X, -X, +Y, -Y, +Z and -Z. The faces have to be square and the same size.
+    pub faces: [DynamicImage; 6],
+    pub binding: u32,
+    pub stage: vk::ShaderStageFlags,
+    pub asset_key: Option<String>,
+}
+
+impl ObjectTypeGraphicsResource for CubeMapResource {
+    fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding: self.binding,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: self.stage,
+            p_immutable_samplers: std::ptr::null(),
+        }
+    }
+
+    fn get_resource(&self) -> ObjectTypeGraphicsResourceType {
+        ObjectTypeGraphicsResourceType::CubeMap(self.faces.clone(), self.asset_key.clone())
+    }
+}
+
+/// Binds the color image of a render target, which shaders read as a `sampler2D` like a texture.
+/// An object should not sample a render target it is drawn into.
+pub struct RenderTargetResource {
+    pub render_target: RenderTargetId,
+    pub binding: u32,
+    pub stage: vk::ShaderStageFlags,
+}
+
+impl ObjectTypeGraphicsResource for RenderTargetResource {
+    fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding: self.binding,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: self.stage,
+            p_immutable_samplers: std::ptr::null(),
+        }
+    }
+
+    fn get_resource(&self) -> ObjectTypeGraphicsResourceType {
+        ObjectTypeGraphicsResourceType::RenderTarget(self.render_target)
     }
 }
 
 pub trait GraphicsObject<T: Vertex> {
     fn get_vertices(&self) -> Vec<T>;
     fn get_indices(&self) -> Vec<u32>;
+    /// `UINT16` halves the index memory of small meshes. The indices are stored as 32-bit when any of them doesn't fit in 16 bits.
+    fn get_index_type(&self) -> vk::IndexType {
+        vk::IndexType::UINT32
+    }
     fn get_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)>;
     fn get_shader_infos(&self) -> Vec<ShaderInfo>;
     fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash;
     fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<(dyn ObjectTypeGraphicsResource + 'static)>>)>;
+    /// See [`MaterialKey`], objects with the same mesh and key have to return the same type resources.
+    fn get_material_key(&self) -> MaterialKey {
+        MaterialKey::default()
+    }
+    /// Only [`crate::sprite::Sprite`] returns the data, so the engine can write the UV rect of its animation.
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        None
+    }
+    /// When it isn't empty, the object is drawn once per submesh with the submesh's type resources instead of the ones from [`GraphicsObject::get_type_resources`].
+    /// The object is still added, hidden and removed as one object, and the submeshes share its instance resources.
+    fn get_submeshes(&self) -> Vec<Submesh> {
+        Vec::new()
+    }
+}
+
+/// Several meshes of the same logical object, ordered from the most to the least detailed. Only one level is drawn each frame, chosen by the distance from the camera to that level's model matrix.
+/// Each level is its own object, so the levels have to be moved together.
+pub struct LodGroup<T: Vertex> {
+    pub levels: Vec<Arc<RwLock<dyn GraphicsObject<T>>>>,
+    // Ascending, level i is drawn until the distance passes switch_distances[i], so there is one less than there are levels
+    pub switch_distances: Vec<f32>,
+    // How far past a switch distance the camera has to be before the level changes, as a fraction of the switch distance. This keeps the levels from popping back and forth
+    pub hysteresis: f32,
+}
+
+impl<T: Vertex> LodGroup<T> {
+    pub const DEFAULT_HYSTERESIS: f32 = 0.1;
+
+    pub fn new(levels: Vec<Arc<RwLock<dyn GraphicsObject<T>>>>, switch_distances: Vec<f32>) -> Self {
+        Self {
+            levels,
+            switch_distances,
+            hysteresis: Self::DEFAULT_HYSTERESIS,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Cow<'static, str>> {
+        if self.levels.is_empty() {
+            return Err(Cow::Borrowed("A LOD group needs at least one level"));
+        }
+        if self.switch_distances.len() != self.levels.len() - 1 {
+            return Err(Cow::Owned(format!("A LOD group with {} levels needs {} switch distances, but it has {}", self.levels.len(), self.levels.len() - 1, self.switch_distances.len())));
+        }
+        if self.switch_distances.windows(2).any(|distances| distances[0] > distances[1]) {
+            return Err(Cow::Borrowed("The switch distances of a LOD group have to be ascending"));
+        }
+        if !(0.0..1.0).contains(&self.hysteresis) {
+            return Err(Cow::Owned(format!("The hysteresis of a LOD group has to be in 0..1, but it is {}", self.hysteresis)));
+        }
+        Ok(())
+    }
 }
 
 pub trait Renderable {
     fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash;
     fn get_vertex_byte_data(&self) -> Vec<u8>;
     fn get_indices(&self) -> Vec<u32>;
+    fn get_index_type(&self) -> vk::IndexType;
     fn get_object_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)>;
     fn get_vertex_binding_info(&self) -> vk::VertexInputBindingDescription;
     fn get_vertex_attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription>;
     fn get_shader_infos(&self) -> Vec<ShaderInfo>;
     fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<(dyn ObjectTypeGraphicsResource + 'static)>>)>;
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>>;
+    fn get_material_key(&self) -> MaterialKey {
+        MaterialKey::default()
+    }
+    /// True when the mesh has already been checked, so it isn't checked again when the object is added.
+    fn is_mesh_validated(&self) -> bool {
+        false
+    }
+    fn get_submeshes(&self) -> Vec<Submesh> {
+        Vec::new()
+    }
+    /// The first index and the number of indices that are drawn, when the object is one of the submeshes of another object. None draws all the indices.
+    fn get_submesh_indices(&self) -> Option<(u32, u32)> {
+        None
+    }
+    /// Reads what the vertex bytes are made from now, and makes the bytes when the job is called. The job may run on another thread, so the meshes of many objects can be serialized in parallel.
+    fn get_vertex_byte_data_job(&self) -> Box<dyn FnOnce() -> Vec<u8> + Send> {
+        let vertex_data = self.get_vertex_byte_data();
+        Box::new(move || vertex_data)
+    }
 }
 
 impl<T: Vertex> Renderable for Arc<RwLock<dyn GraphicsObject<T>>> {
@@ -118,13 +394,23 @@ impl<T: Vertex> Renderable for Arc<RwLock<dyn GraphicsObject<T>>> {
         let vertex_data = vertices.iter().map(|v| v.to_u8()).flatten().collect::<Vec<u8>>();
         vertex_data
     }
+
+    fn get_vertex_byte_data_job(&self) -> Box<dyn FnOnce() -> Vec<u8> + Send> {
+        let vertices = self.read().unwrap().get_vertices();
+        Box::new(move || vertices.iter().flat_map(|v| v.to_u8()).collect())
+    }
     
     fn get_indices(&self) -> Vec<u32> {
         self.read().unwrap().get_indices()
     }
+
+    fn get_index_type(&self) -> vk::IndexType {
+        self.read().unwrap().get_index_type()
+    }
     
+    // Read every frame, and a poisoned object still hands out the same resources, whose own locks are checked when they are read
     fn get_object_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<(dyn ObjectInstanceGraphicsResource + 'static)>>)> {
-        self.read().unwrap().get_instance_resources()
+        self.read().unwrap_or_else(PoisonError::into_inner).get_instance_resources()
     }
     
     fn get_shader_infos(&self) -> Vec<ShaderInfo> {
@@ -140,8 +426,74 @@ impl<T: Vertex> Renderable for Arc<RwLock<dyn GraphicsObject<T>>> {
     }
     
     fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<(dyn ObjectTypeGraphicsResource + 'static)>>)> {
-        self.read().unwrap().get_type_resources()
+        self.read().unwrap_or_else(PoisonError::into_inner).get_type_resources()
     }
-    
-    
-}
\ No newline at end of file
+
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        self.read().unwrap_or_else(PoisonError::into_inner).get_sprite_instance_data()
+    }
+
+    fn get_material_key(&self) -> MaterialKey {
+        self.read().unwrap().get_material_key()
+    }
+
+    fn get_submeshes(&self) -> Vec<Submesh> {
+        self.read().unwrap_or_else(PoisonError::into_inner).get_submeshes()
+    }
+}
+
+/// A textured mesh with Blinn-Phong shading from the lights added with [`VkController::add_light`].
+/// The shaders read the model matrix at binding 0 and the texture at binding 1 of the object type's descriptor set.
+pub struct LitRenderableObject {
+    pub vertices: Vec<LitVertex>,
+    pub indices: Vec<u32>,
+    pub model_matrix: Arc<RwLock<UniformBufferResource<glm::Mat4>>>,
+    pub texture: Arc<RwLock<TextureResource>>,
+}
+
+impl LitRenderableObject {
+
+    pub fn new(vertices: Vec<LitVertex>, indices: Vec<u32>, image: DynamicImage, model_matrix: glm::Mat4) -> Self {
+        Self {
+            vertices,
+            indices,
+            model_matrix: Arc::new(RwLock::new(UniformBufferResource {
+                buffer: model_matrix,
+                binding: 0,
+            })),
+            texture: Arc::new(RwLock::new(TextureResource::new(image, 1, vk::ShaderStageFlags::FRAGMENT, None))),
+        }
+    }
+}
+
+impl GraphicsObject<LitVertex> for LitRenderableObject {
+    fn get_vertices(&self) -> Vec<LitVertex> {
+        self.vertices.clone()
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        self.indices.clone()
+    }
+
+    fn get_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)> {
+        vec![(ResourceID(0), self.model_matrix.clone())]
+    }
+
+    fn get_shader_infos(&self) -> Vec<ShaderInfo> {
+        vec![
+            BuiltinShader::LitVertex.get_shader_info(),
+            BuiltinShader::LitFragment.get_shader_info(),
+        ]
+    }
+
+    fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        let mut hasher = DefaultHasher::new();
+        self.vertices.iter().for_each(|vertex| vertex.hash(&mut hasher));
+        self.indices.iter().for_each(|index| index.hash(&mut hasher));
+        VerticesIndicesHash(hasher.finish())
+    }
+
+    fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)> {
+        vec![(ResourceID(1), self.texture.clone())]
+    }
+}
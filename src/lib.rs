@@ -1,10 +1,31 @@
 use winit::{event_loop::EventLoop, window::WindowBuilder};
 
+pub mod asset_resolver;
+pub mod async_loader;
+pub mod builtin_shaders;
+pub mod debug_draw;
+mod draw_recorder;
+pub mod egui_renderer;
+pub mod error;
+pub mod frame_stats;
+pub mod gpu_layout;
 pub mod graphics_objects;
+pub mod lighting;
+pub mod logging;
+pub mod material;
 mod object_manager;
 pub mod pipeline_manager;
+pub mod post_process;
+pub mod render_target;
+pub mod renderdoc;
 mod sampler_manager;
-mod vertex;
+mod shader_reflection;
+pub mod skybox;
+pub mod sprite;
+pub mod text;
+mod texture_manager;
+pub mod upload_context;
+pub mod vertex;
 mod vk_allocator;
 pub mod vk_controller;
 
@@ -0,0 +1,263 @@
+use std::{borrow::Cow, hash::{Hash, Hasher}};
+
+use ash::vk;
+use memoffset::offset_of;
+use nalgebra_glm as glm;
+
+use crate::{pipeline_manager::Vertex, vk_allocator::{AllocationInfo, Serializable, VkAllocator}, vk_controller::VkController};
+
+/// A vertex with the normal the lit shaders need for the shading.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct LitVertex {
+    pub position: glm::Vec3,
+    pub normal: glm::Vec3,
+    pub tex_coord: glm::Vec2,
+}
+
+impl Vertex for LitVertex {
+    fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, position) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, normal) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, tex_coord) as u32,
+            },
+        ]
+    }
+}
+
+impl Hash for LitVertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.position.iter()
+            .chain(self.normal.iter())
+            .chain(self.tex_coord.iter())
+            .for_each(|&i| i.to_bits().hash(state));
+    }
+}
+
+impl Serializable for LitVertex {
+    fn to_u8(&self) -> Vec<u8> {
+        self.position.iter()
+            .chain(self.normal.iter())
+            .chain(self.tex_coord.iter())
+            .flat_map(|x| x.to_ne_bytes())
+            .collect()
+    }
+}
+
+/// A [`LitVertex`] with the tangent for normal mapping at location 3, see [`crate::vertex::compute_tangents`].
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct LitTangentVertex {
+    pub position: glm::Vec3,
+    pub normal: glm::Vec3,
+    pub tex_coord: glm::Vec2,
+    // The handedness of the bitangent is in w
+    pub tangent: glm::Vec4,
+}
+
+impl LitTangentVertex {
+    pub fn new(vertex: LitVertex, tangent: glm::Vec4) -> Self {
+        Self {
+            position: vertex.position,
+            normal: vertex.normal,
+            tex_coord: vertex.tex_coord,
+            tangent,
+        }
+    }
+
+    /// The vertices with the tangents computed from their normals and UVs.
+    pub fn from_lit_vertices(vertices: &[LitVertex], indices: &[u32]) -> Vec<Self> {
+        vertices.iter().zip(crate::vertex::compute_tangents(vertices, indices)).map(|(&vertex, tangent)| Self::new(vertex, tangent)).collect()
+    }
+}
+
+impl Vertex for LitTangentVertex {
+    fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, position) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, normal) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, tex_coord) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 3,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(Self, tangent) as u32,
+            },
+        ]
+    }
+}
+
+impl Hash for LitTangentVertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.position.iter()
+            .chain(self.normal.iter())
+            .chain(self.tex_coord.iter())
+            .chain(self.tangent.iter())
+            .for_each(|&i| i.to_bits().hash(state));
+    }
+}
+
+impl Serializable for LitTangentVertex {
+    fn to_u8(&self) -> Vec<u8> {
+        self.position.iter()
+            .chain(self.normal.iter())
+            .chain(self.tex_coord.iter())
+            .chain(self.tangent.iter())
+            .flat_map(|x| x.to_ne_bytes())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Lights everything from the same direction, like the sun. `dir` is the direction the light travels in.
+    Directional { dir: glm::Vec3, color: glm::Vec3, intensity: f32 },
+    /// Fades out smoothly and has no effect past the radius.
+    Point { pos: glm::Vec3, color: glm::Vec3, radius: f32 },
+}
+
+impl Light {
+    // Each light is three vec4s: the position or direction with the type in w, the color with the intensity in w, and the radius in x
+    fn to_u8(self) -> Vec<u8> {
+        let (position, kind, color, intensity, radius) = match self {
+            Light::Directional { dir, color, intensity } => (dir, 0.0, color, intensity, 0.0),
+            Light::Point { pos, color, radius } => (pos, 1.0, color, 1.0, radius),
+        };
+        position.iter().chain(&[kind])
+            .chain(color.iter()).chain(&[intensity])
+            .chain(&[radius, 0.0, 0.0, 0.0])
+            .flat_map(|x| x.to_ne_bytes())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LightId(pub usize);
+
+/// The lights shaders read from `layout(set = 0, binding = 1)`, see `assets/shaders/lit.frag` for the block.
+pub struct LightManager {
+    lights: Vec<(LightId, Light)>,
+    next_id: usize,
+    // One part for each frame in flight, since the lights of the earlier frames might still be read. It is freed with the other allocations like the per-frame data
+    light_data_allocation: AllocationInfo,
+    // The frames whose part of the buffer still has the old lights, so the buffer is only written after the lights change
+    outdated_frames: [bool; VkController::MAX_FRAMES_IN_FLIGHT],
+}
+
+impl LightManager {
+    pub const MAX_LIGHTS: usize = 16;
+    const LIGHT_SIZE: usize = 3 * 16;
+    // The light count padded to 16 bytes, followed by the lights, laid out with std140
+    pub const LIGHT_DATA_SIZE: usize = 16 + Self::MAX_LIGHTS * Self::LIGHT_SIZE;
+
+    pub fn new(allocator: &VkAllocator) -> Result<Self, Cow<'static, str>> {
+        Ok(Self {
+            lights: Vec::new(),
+            next_id: 0,
+            light_data_allocation: allocator.create_uniform_buffers(Self::LIGHT_DATA_SIZE, VkController::MAX_FRAMES_IN_FLIGHT)?,
+            outdated_frames: [true; VkController::MAX_FRAMES_IN_FLIGHT],
+        })
+    }
+
+    pub fn add_light(&mut self, light: Light) -> Result<LightId, Cow<'static, str>> {
+        if self.lights.len() >= Self::MAX_LIGHTS {
+            return Err(Cow::Owned(format!("There can be at most {} lights", Self::MAX_LIGHTS)));
+        }
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        self.outdated_frames = [true; VkController::MAX_FRAMES_IN_FLIGHT];
+        Ok(id)
+    }
+
+    pub fn update_light(&mut self, id: LightId, light: Light) -> Result<(), Cow<'static, str>> {
+        let (_, old_light) = self.lights.iter_mut().find(|(light_id, _)| *light_id == id).ok_or(Cow::Owned(format!("The light {:?} does not exist", id)))?;
+        if *old_light != light {
+            *old_light = light;
+            self.outdated_frames = [true; VkController::MAX_FRAMES_IN_FLIGHT];
+        }
+        Ok(())
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Result<(), Cow<'static, str>> {
+        let index = self.lights.iter().position(|(light_id, _)| *light_id == id).ok_or(Cow::Owned(format!("The light {:?} does not exist", id)))?;
+        self.lights.remove(index);
+        self.outdated_frames = [true; VkController::MAX_FRAMES_IN_FLIGHT];
+        Ok(())
+    }
+
+    pub fn get_light(&self, id: LightId) -> Option<Light> {
+        self.lights.iter().find(|(light_id, _)| *light_id == id).map(|(_, light)| *light)
+    }
+
+    /// Has to be called after the fence of the frame has been waited on, since the frame's part of the buffer is written.
+    pub fn upload_if_outdated(&mut self, current_frame: usize) {
+        if !self.outdated_frames[current_frame] {
+            return;
+        }
+        let data = (self.lights.len() as u32).to_ne_bytes().into_iter()
+            .chain([0; 12])
+            .chain(self.lights.iter().flat_map(|(_, light)| light.to_u8()))
+            .collect::<Vec<u8>>();
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.light_data_allocation.get_uniform_pointers()[current_frame].cast::<u8>(), data.len());
+        }
+        self.outdated_frames[current_frame] = false;
+    }
+
+    /// The frame's part of the light buffer, for the global descriptor set of the frame.
+    pub fn get_buffer_info(&self, frame: usize) -> vk::DescriptorBufferInfo {
+        let offset = unsafe { self.light_data_allocation.get_uniform_pointers()[frame].offset_from(self.light_data_allocation.get_uniform_pointers()[0]) } as vk::DeviceSize;
+        vk::DescriptorBufferInfo {
+            buffer: self.light_data_allocation.get_buffer().unwrap(),
+            offset,
+            range: Self::LIGHT_DATA_SIZE as vk::DeviceSize,
+        }
+    }
+}
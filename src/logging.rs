@@ -0,0 +1,109 @@
+//! The engine logs through the `log` crate, so the application chooses the logger and what is shown.
+//! The messages have one of the targets below, so they can be filtered by what they are about.
+//! Trace is compiled out of release builds, so the messages that can come every frame are trace.
+
+pub const ALLOCATOR: &str = "artewald::allocator";
+pub const OBJECTS: &str = "artewald::objects";
+pub const PIPELINES: &str = "artewald::pipelines";
+pub const RENDERER: &str = "artewald::renderer";
+/// The messages from the Vulkan validation layers
+pub const VALIDATION: &str = "artewald::validation";
+
+#[cfg(feature = "default-logger")]
+pub use default_logger::{init_default_logger, LEVEL_VARIABLE};
+
+#[cfg(feature = "default-logger")]
+mod default_logger {
+    use std::io::Write;
+
+    use log::{LevelFilter, Log, Metadata, Record};
+
+    /// The variable the level is read from, like `ARTEWALD_LOG=debug`.
+    pub const LEVEL_VARIABLE: &str = "ARTEWALD_LOG";
+
+    struct StderrLogger {
+        level: LevelFilter,
+    }
+
+    impl Log for StderrLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                let _ = writeln!(std::io::stderr().lock(), "[{}][{}] {}", record.level(), record.target(), record.args());
+            }
+        }
+
+        fn flush(&self) {
+            let _ = std::io::stderr().flush();
+        }
+    }
+
+    /// Writes the messages to stderr, for applications that don't set a logger themselves. The level is warn unless [`LEVEL_VARIABLE`] says otherwise.
+    /// Does nothing if a logger has already been set.
+    pub fn init_default_logger() {
+        let level = std::env::var(LEVEL_VARIABLE).ok().and_then(|level| level.parse().ok()).unwrap_or(LevelFilter::Warn);
+        if log::set_boxed_logger(Box::new(StderrLogger { level })).is_ok() {
+            log::set_max_level(level);
+        }
+    }
+}
+
+/// Collects the messages logged on the test's own thread, so a test can check what was logged while the other tests run in parallel.
+#[cfg(test)]
+pub(crate) mod test_logger {
+    use std::{cell::RefCell, sync::Once};
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    pub struct CapturedLog {
+        pub level: Level,
+        pub target: String,
+        pub message: String,
+    }
+
+    thread_local! {
+        static CAPTURED_LOGS: RefCell<Option<Vec<CapturedLog>>> = const { RefCell::new(None) };
+    }
+
+    struct TestLogger;
+
+    impl Log for TestLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED_LOGS.with(|captured_logs| {
+                if let Some(captured_logs) = captured_logs.borrow_mut().as_mut() {
+                    captured_logs.push(CapturedLog { level: record.level(), target: record.target().to_string(), message: record.args().to_string() });
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static TEST_LOGGER: TestLogger = TestLogger;
+    static INIT: Once = Once::new();
+
+    /// Runs `f` and returns what it logged on this thread.
+    pub fn capture_logs<R>(f: impl FnOnce() -> R) -> (R, Vec<CapturedLog>) {
+        INIT.call_once(|| {
+            if log::set_logger(&TEST_LOGGER).is_ok() {
+                log::set_max_level(LevelFilter::Trace);
+            }
+        });
+        CAPTURED_LOGS.with(|captured_logs| *captured_logs.borrow_mut() = Some(Vec::new()));
+        let result = f();
+        let captured_logs = CAPTURED_LOGS.with(|captured_logs| captured_logs.borrow_mut().take()).unwrap_or_default();
+        (result, captured_logs)
+    }
+
+    /// Whether one of the logs is at `level`, on `target`, and contains `text`.
+    pub fn has_log(captured_logs: &[CapturedLog], level: Level, target: &str, text: &str) -> bool {
+        captured_logs.iter().any(|captured_log| captured_log.level == level && captured_log.target == target && captured_log.message.contains(text))
+    }
+}
@@ -1,31 +1,24 @@
-use std::{borrow::BorrowMut, collections::{hash_map, HashMap}, ffi::CString, sync::{Arc, RwLock}, time::Instant};
+use std::{collections::{hash_map, HashMap}, ffi::CString, path::Path, sync::{Arc, RwLock}};
 
 use ash::vk;
-use graphics_objects::{TextureResource, UniformBufferResource};
-use pipeline_manager::ShaderInfo;
+use artewald_engine_2::{frame_stats::FrameStats, graphics_objects::{TextureResource, UniformBufferResource}, pipeline_manager::{ShaderInfo, ShaderSource}, vertex::{generate_circle_type_three, SimpleVertex}, vk_controller::{VkController, VkControllerGraphicsObjectsControl}};
 use test_objects::{SimpleRenderableObject, TwoDPositionSimpleRenderableObject};
-use vertex::{generate_circle_type_one, generate_circle_type_three, generate_circle_type_two, SimpleVertex};
-use vk_controller::{VkController, VkControllerGraphicsObjectsControl};
 use winit::{event_loop::{EventLoop, ControlFlow}, window::WindowBuilder, event::{Event, WindowEvent, ElementState, KeyboardInput}};
 use nalgebra_glm as glm;
 
-mod vk_controller;
-mod vertex;
-mod graphics_objects;
-mod vk_allocator;
-mod pipeline_manager;
-mod sampler_manager;
+// The demo uses the engine through the library, like an application would
 mod test_objects;
-mod object_manager;
 
 fn main() {
+    #[cfg(feature = "default-logger")]
+    artewald_engine_2::logging::init_default_logger();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().with_title("Artewald Engine 2").build(&event_loop).unwrap();
 
     let mut vk_controller = VkController::new(window, "Artewald Engine 2");
     let mut swapchain_extent = vk_controller.get_swapchain_extent();
 
-    let (vertices, indices) = load_model("./assets/objects/viking_room.obj");
+    let (vertices, indices) = load_model(&vk_controller.get_asset_resolver().resolve(Path::new("objects/viking_room.obj")).unwrap());
     
     let mod1 = glm::translate(&glm::identity(), &glm::Vec3::new(-1.5, 0.0, 0.0)) * glm::rotate(&glm::identity(), 0f32 * std::f32::consts::PI * 0.25, &glm::vec3(0.0, 0.0, 1.0)) * glm::rotate(&glm::identity(), 0f32 * std::f32::consts::PI * 0.25, &glm::vec3(1.0, 0.0, 0.0));
 
@@ -38,11 +31,8 @@ fn main() {
         binding: 1,
     }));
 
-    let texture = Arc::new(RwLock::new(TextureResource {
-        image: image::open("./assets/images/viking_room.png").unwrap(),
-        binding: 2,
-        stage: vk::ShaderStageFlags::FRAGMENT,
-    }));
+    let image = vk_controller.get_asset_resolver().load_image(Path::new("images/viking_room.png")).unwrap();
+    let texture = Arc::new(RwLock::new(TextureResource::new(image, 2, vk::ShaderStageFlags::FRAGMENT, Some("viking_room".to_string()))));
 
     let obj1 = Arc::new(RwLock::new(SimpleRenderableObject {
         vertices: vertices.clone(),
@@ -50,12 +40,12 @@ fn main() {
         model_matrix: Arc::new(RwLock::new(UniformBufferResource { buffer: mod1, binding: 0 })),
         shaders: vec![
             ShaderInfo {
-                path: std::path::PathBuf::from("./assets/shaders/triangle.vert"),
+                source: ShaderSource::File(std::path::PathBuf::from("shaders/triangle.vert")),
                 shader_stage_flag: vk::ShaderStageFlags::VERTEX,
                 entry_point: CString::new("main").unwrap(),
             },
             ShaderInfo {
-                path: std::path::PathBuf::from("./assets/shaders/triangle.frag"),
+                source: ShaderSource::File(std::path::PathBuf::from("shaders/triangle.frag")),
                 shader_stage_flag: vk::ShaderStageFlags::FRAGMENT,
                 entry_point: CString::new("main").unwrap(),
             }
@@ -70,12 +60,12 @@ fn main() {
         model_matrix: Arc::new(RwLock::new(UniformBufferResource { buffer: mod2, binding: 0 })),
         shaders: vec![
             ShaderInfo {
-                path: std::path::PathBuf::from("./assets/shaders/triangle.vert"),
+                source: ShaderSource::File(std::path::PathBuf::from("shaders/triangle.vert")),
                 shader_stage_flag: vk::ShaderStageFlags::VERTEX,
                 entry_point: CString::new("main").unwrap(),
             },
             ShaderInfo {
-                path: std::path::PathBuf::from("./assets/shaders/triangle.frag"),
+                source: ShaderSource::File(std::path::PathBuf::from("shaders/triangle.frag")),
                 shader_stage_flag: vk::ShaderStageFlags::FRAGMENT,
                 entry_point: CString::new("main").unwrap(),
             }
@@ -85,6 +75,12 @@ fn main() {
     }));
 
     // let object_ids = vk_controller.add_objects_to_render(vec![obj1.clone(), obj2.clone()]).unwrap();
+    // The skybox uses the view and projection set on the controller, so set them to the camera of the viking rooms
+    // vk_controller.set_view(glm::look_at(&glm::vec3(0.0, 2.0, 2.0), &glm::vec3(0.0, 0.0, 0.0), &glm::vec3(0.0, 1.0, 0.0)));
+    // vk_controller.set_projection(proj);
+    // let skybox_faces = ["px", "nx", "py", "ny", "pz", "nz"].map(|face| vk_controller.get_asset_resolver().load_image(Path::new(&format!("images/skybox/{}.png", face))).unwrap());
+    // let skybox: Arc<RwLock<dyn GraphicsObject<SkyboxVertex>>> = Arc::new(RwLock::new(Skybox::new(skybox_faces, Some("skybox".to_string()))));
+    // let skybox_ids = vk_controller.add_objects_to_render(vec![skybox]).unwrap();
     
     let num_vertices = 49152*32;//12;//
 
@@ -95,7 +91,7 @@ fn main() {
     // println!("3");
     // let start_time = Instant::now();
     // println!("Start time: {:?}", start_time.elapsed().as_secs_f32());
-    let (vertices_three, indices_three) = generate_circle_type_three(1.0, num_vertices);
+    let (vertices_three, indices_three) = generate_circle_type_three(1.0, num_vertices).unwrap();
     // println!("End time: {:?}", start_time.elapsed().as_secs_f32());
     // println!("4");
 
@@ -104,12 +100,12 @@ fn main() {
     //     indices: indices_one,
     //     shaders: vec![
     //         ShaderInfo {
-    //             path: std::path::PathBuf::from("./assets/shaders/circle.vert"),
+    //             source: ShaderSource::File(std::path::PathBuf::from("shaders/circle.vert")),
     //             shader_stage_flag: vk::ShaderStageFlags::VERTEX,
     //             entry_point: CString::new("main").unwrap(),
     //         },
     //         ShaderInfo {
-    //             path: std::path::PathBuf::from("./assets/shaders/circle.frag"),
+    //             source: ShaderSource::File(std::path::PathBuf::from("shaders/circle.frag")),
     //             shader_stage_flag: vk::ShaderStageFlags::FRAGMENT,
     //             entry_point: CString::new("main").unwrap(),
     //         }
@@ -122,12 +118,12 @@ fn main() {
     //     indices: indices_two,
     //     shaders: vec![
     //         ShaderInfo {
-    //             path: std::path::PathBuf::from("./assets/shaders/circle.vert"),
+    //             source: ShaderSource::File(std::path::PathBuf::from("shaders/circle.vert")),
     //             shader_stage_flag: vk::ShaderStageFlags::VERTEX,
     //             entry_point: CString::new("main").unwrap(),
     //         },
     //         ShaderInfo {
-    //             path: std::path::PathBuf::from("./assets/shaders/circle.frag"),
+    //             source: ShaderSource::File(std::path::PathBuf::from("shaders/circle.frag")),
     //             shader_stage_flag: vk::ShaderStageFlags::FRAGMENT,
     //             entry_point: CString::new("main").unwrap(),
     //         }
@@ -140,12 +136,12 @@ fn main() {
         indices: indices_three,
         shaders: vec![
             ShaderInfo {
-                path: std::path::PathBuf::from("./assets/shaders/circle.vert"),
+                source: ShaderSource::File(std::path::PathBuf::from("shaders/circle.vert")),
                 shader_stage_flag: vk::ShaderStageFlags::VERTEX,
                 entry_point: CString::new("main").unwrap(),
             },
             ShaderInfo {
-                path: std::path::PathBuf::from("./assets/shaders/circle.frag"),
+                source: ShaderSource::File(std::path::PathBuf::from("shaders/circle.frag")),
                 shader_stage_flag: vk::ShaderStageFlags::FRAGMENT,
                 entry_point: CString::new("main").unwrap(),
             }
@@ -156,9 +152,8 @@ fn main() {
 
     // let mut current_object_id = vk_controller.add_object_to_render(obj_three.clone()).unwrap();
 
-    let mut frame_count = 0;
+    let mut frame_stats = FrameStats::new(240);
     let mut last_fps_print = std::time::Instant::now();
-    let start_time = Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -166,6 +161,7 @@ fn main() {
         let mut close = false;
 
         match event {
+            Event::WindowEvent { event, .. } if vk_controller.handle_window_event(&event) => {},
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => {
                     *control_flow = ControlFlow::Exit;
@@ -213,21 +209,23 @@ fn main() {
             return;
         }
         
-        obj1.write().unwrap().model_matrix.write().unwrap().buffer = glm::translate(&glm::identity(), &glm::Vec3::new(-1.5, 1.0, 0.0)) * glm::rotate(&glm::identity(), start_time.elapsed().as_secs_f32() * std::f32::consts::PI * 0.25, &glm::vec3(0.0, 1.0, 0.0)) * glm::rotate(&glm::identity(), -90.0f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
-        obj2.write().unwrap().model_matrix.write().unwrap().buffer = glm::translate(&glm::identity(), &glm::Vec3::new(1.5, 1.0, 0.0)) * glm::rotate(&glm::identity(), start_time.elapsed().as_secs_f32() * std::f32::consts::PI * 0.25, &glm::vec3(0.0, 1.0, 0.0)) * glm::rotate(&glm::identity(), -90.0f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+        obj1.write().unwrap().model_matrix.write().unwrap().buffer = glm::translate(&glm::identity(), &glm::Vec3::new(-1.5, 1.0, 0.0)) * glm::rotate(&glm::identity(), vk_controller.time().elapsed_seconds() * std::f32::consts::PI * 0.25, &glm::vec3(0.0, 1.0, 0.0)) * glm::rotate(&glm::identity(), -90.0f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+        obj2.write().unwrap().model_matrix.write().unwrap().buffer = glm::translate(&glm::identity(), &glm::Vec3::new(1.5, 1.0, 0.0)) * glm::rotate(&glm::identity(), vk_controller.time().elapsed_seconds() * std::f32::consts::PI * 0.25, &glm::vec3(0.0, 1.0, 0.0)) * glm::rotate(&glm::identity(), -90.0f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
 
         if vk_controller.try_to_draw_frame() {
-            frame_count += 1;
-            if last_fps_print.elapsed().as_secs_f32() > 1.0 {
-                println!("FPS: {}", frame_count as f32 / last_fps_print.elapsed().as_secs_f32());
-                frame_count = 0;
+            frame_stats.tick();
+            if let (true, Some(fps), Some(one_percent_low_fps)) = (last_fps_print.elapsed().as_secs_f32() > 1.0, frame_stats.average_fps(), frame_stats.one_percent_low_fps()) {
+                match vk_controller.last_frame_gpu_time_ms() {
+                    Some(gpu_time_ms) => vk_controller.set_window_title(&format!("Artewald Engine 2 - FPS: {:.0}, 1% low: {:.0}, GPU: {:.2} ms", fps, one_percent_low_fps, gpu_time_ms)),
+                    None => vk_controller.set_window_title(&format!("Artewald Engine 2 - FPS: {:.0}, 1% low: {:.0}", fps, one_percent_low_fps)),
+                }
                 last_fps_print = std::time::Instant::now();
             }
         }
     });
 }
 
-fn load_model(path: &str) -> (Vec<SimpleVertex>, Vec<u32>) {
+fn load_model(path: &Path) -> (Vec<SimpleVertex>, Vec<u32>) {
     let (models, _) = tobj::load_obj(path, &tobj::LoadOptions::default()).unwrap();
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
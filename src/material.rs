@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+
+use ash::vk;
+use nalgebra_glm as glm;
+
+use crate::{pipeline_manager::{ObjectTypeGraphicsResource, ObjectTypeGraphicsResourceType}, vk_allocator::Serializable};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaterialValue {
+    Float(f32),
+    Vec2(glm::Vec2),
+    Vec3(glm::Vec3),
+    Vec4(glm::Vec4),
+    Mat4(glm::Mat4),
+}
+
+impl MaterialValue {
+    /// The std140 alignment and size in bytes. A vec3 is aligned like a vec4, but a float can be put in its last 4 bytes.
+    fn get_alignment_and_size(&self) -> (usize, usize) {
+        match self {
+            MaterialValue::Float(_) => (4, 4),
+            MaterialValue::Vec2(_) => (8, 8),
+            MaterialValue::Vec3(_) => (16, 12),
+            MaterialValue::Vec4(_) => (16, 16),
+            MaterialValue::Mat4(_) => (16, 64),
+        }
+    }
+
+    fn get_glsl_type(&self) -> &'static str {
+        match self {
+            MaterialValue::Float(_) => "float",
+            MaterialValue::Vec2(_) => "vec2",
+            MaterialValue::Vec3(_) => "vec3",
+            MaterialValue::Vec4(_) => "vec4",
+            MaterialValue::Mat4(_) => "mat4",
+        }
+    }
+
+    fn is_same_type(&self, other: &MaterialValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        let values = match self {
+            MaterialValue::Float(value) => std::slice::from_ref(value),
+            MaterialValue::Vec2(value) => value.as_slice(),
+            MaterialValue::Vec3(value) => value.as_slice(),
+            MaterialValue::Vec4(value) => value.as_slice(),
+            // Column major, which is what std140 uses for a mat4 by default
+            MaterialValue::Mat4(value) => value.as_slice(),
+        };
+        for (i, value) in values.iter().enumerate() {
+            bytes[i * 4..(i + 1) * 4].copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MaterialField {
+    name: String,
+    offset: usize,
+    value: MaterialValue,
+}
+
+/// Named fields that are laid out like a std140 `uniform` block with the fields in the same order, so the bytes always match the block from [`Material::get_glsl_declaration`].
+/// It is a type resource, so objects that share the mesh share one material unless their material keys differ.
+#[derive(Clone)]
+pub struct Material {
+    fields: Vec<MaterialField>,
+    byte_size: usize,
+    binding: u32,
+    stage: vk::ShaderStageFlags,
+}
+
+impl Material {
+    pub fn builder(binding: u32) -> MaterialBuilder {
+        MaterialBuilder::new(binding)
+    }
+
+    /// The value has to have the same type as the field was built with.
+    pub fn set(&mut self, name: &str, value: MaterialValue) -> Result<(), Cow<'static, str>> {
+        let Some(field) = self.fields.iter_mut().find(|field| field.name == name) else {
+            return Err(Cow::Owned(format!("The material has no field named {}", name)));
+        };
+        if !field.value.is_same_type(&value) {
+            return Err(Cow::Owned(format!("The material field {} is a {}, but it was set to a {}", name, field.value.get_glsl_type(), value.get_glsl_type())));
+        }
+        field.value = value;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<MaterialValue> {
+        self.fields.iter().find(|field| field.name == name).map(|field| field.value)
+    }
+
+    /// The offset of the field in the bytes, which is the std140 offset of the member in the block.
+    pub fn get_offset(&self, name: &str) -> Option<usize> {
+        self.fields.iter().find(|field| field.name == name).map(|field| field.offset)
+    }
+
+    /// Padded to a multiple of 16 bytes, like the size of a std140 block.
+    pub fn get_byte_size(&self) -> usize {
+        self.byte_size
+    }
+
+    pub fn get_binding(&self) -> u32 {
+        self.binding
+    }
+
+    /// The block to declare in the shader, for example `layout(set = 1, binding = 1) uniform Material { float roughness; vec3 albedo; } material;`.
+    pub fn get_glsl_declaration(&self, block_name: &str, instance_name: &str) -> String {
+        let members = self.fields.iter().map(|field| format!("    {} {};\n", field.value.get_glsl_type(), field.name)).collect::<String>();
+        format!("layout(set = 1, binding = {}) uniform {} {{\n{}}} {};\n", self.binding, block_name, members, instance_name)
+    }
+}
+
+impl Serializable for Material {
+    fn to_u8(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.byte_size];
+        for field in self.fields.iter() {
+            field.value.write(&mut bytes[field.offset..]);
+        }
+        bytes
+    }
+}
+
+impl ObjectTypeGraphicsResource for Material {
+    fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding: self.binding,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags: self.stage,
+            p_immutable_samplers: std::ptr::null(),
+        }
+    }
+
+    fn get_resource(&self) -> ObjectTypeGraphicsResourceType {
+        ObjectTypeGraphicsResourceType::UniformBuffer(self.to_u8())
+    }
+}
+
+/// The fields are laid out in the order they are added.
+pub struct MaterialBuilder {
+    fields: Vec<(String, MaterialValue)>,
+    binding: u32,
+    stage: vk::ShaderStageFlags,
+}
+
+impl MaterialBuilder {
+    pub fn new(binding: u32) -> Self {
+        Self {
+            fields: Vec::new(),
+            binding,
+            stage: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        }
+    }
+
+    /// The shader stages that read the material. Defaults to the vertex and fragment stages.
+    pub fn stage(mut self, stage: vk::ShaderStageFlags) -> Self {
+        self.stage = stage;
+        self
+    }
+
+    pub fn float(self, name: &str, value: f32) -> Self {
+        self.field(name, MaterialValue::Float(value))
+    }
+
+    pub fn vec2(self, name: &str, value: glm::Vec2) -> Self {
+        self.field(name, MaterialValue::Vec2(value))
+    }
+
+    pub fn vec3(self, name: &str, value: glm::Vec3) -> Self {
+        self.field(name, MaterialValue::Vec3(value))
+    }
+
+    pub fn vec4(self, name: &str, value: glm::Vec4) -> Self {
+        self.field(name, MaterialValue::Vec4(value))
+    }
+
+    pub fn mat4(self, name: &str, value: glm::Mat4) -> Self {
+        self.field(name, MaterialValue::Mat4(value))
+    }
+
+    pub fn field(mut self, name: &str, value: MaterialValue) -> Self {
+        self.fields.push((name.to_string(), value));
+        self
+    }
+
+    pub fn build(self) -> Result<Material, Cow<'static, str>> {
+        if self.fields.is_empty() {
+            return Err(Cow::Borrowed("A material needs at least one field"));
+        }
+        let mut fields: Vec<MaterialField> = Vec::with_capacity(self.fields.len());
+        let mut offset: usize = 0;
+        for (name, value) in self.fields {
+            if fields.iter().any(|field| field.name == name) {
+                return Err(Cow::Owned(format!("The material has more than one field named {}", name)));
+            }
+            let (alignment, size) = value.get_alignment_and_size();
+            let field_offset = offset.next_multiple_of(alignment);
+            offset = field_offset + size;
+            fields.push(MaterialField { name, offset: field_offset, value });
+        }
+
+        Ok(Material {
+            fields,
+            byte_size: offset.next_multiple_of(16),
+            binding: self.binding,
+            stage: self.stage,
+        })
+    }
+}
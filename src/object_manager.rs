@@ -1,19 +1,23 @@
-use std::{borrow::Cow, collections::{hash_map::Entry, HashMap, HashSet}, hash::{DefaultHasher, Hash, Hasher}};
+use std::{borrow::Cow, collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet}, hash::{DefaultHasher, Hash, Hasher}, sync::{Arc, PoisonError, RwLock}};
 
-use ash::{vk::{self, DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorType, Extent2D, PhysicalDevice, Queue, Sampler, StructureType, WriteDescriptorSet}, Device, Instance};
+use ash::{vk::{self, DescriptorBufferInfo, Handle, DescriptorImageInfo, DescriptorPool, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorType, Extent2D, PhysicalDevice, Queue, Sampler, StructureType, WriteDescriptorSet}, Device, Instance};
 use image::DynamicImage;
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use nalgebra_glm as glm;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-use crate::{free_allocations_add_error_string, graphics_objects::{Renderable, ResourceID}, pipeline_manager::{ObjectInstanceGraphicsResourceType, ObjectTypeGraphicsResourceType, PipelineConfig, PipelineManager}, sampler_manager::{SamplerConfig, SamplerManager}, vk_allocator::{AllocationInfo, VkAllocator}, vk_controller::{ObjectID, ReferenceObjectID, VerticesIndicesHash, VkController}};
+use crate::{logging, error::EngineError, free_allocations_add_error_string, graphics_objects::{MaterialKey, Renderable, ResourceID, Submesh, UniformBufferResource}, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectInstanceGraphicsResourceType, ObjectTypeGraphicsResource, ObjectTypeGraphicsResourceType, PipelineConfig, PipelineManager, ResourceLoadState, ShaderInfo}, render_target::RenderTargetId, sampler_manager::{SamplerConfig, SamplerManager}, sprite::{SpriteAnimation, SpriteInstanceData}, upload_context::{PreparedMeshBuffers, PreparedTexture}, vk_allocator::{AllocationInfo, VkAllocator}, vk_controller::{DrawBatch, DrawOrder, ObjectID, ObjectTypeReport, ReferenceObjectID, RemovalHandle, RemovalStatus, RenderableDataVersion, ResourceError, TextureCacheStats, TextureQuality, VerticesIndicesHash, VkController}};
 
 enum DataToRemove {
     Allocation(AllocationInfo),
     DescriptorSets(Vec<DescriptorSet>),
+    // Queued after the data of the removed objects, so the removal is freed when it is taken out of the queue with them
+    Removal(RemovalHandle),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Inclusive(pub usize);
 
+// An empty range is represented by an Exclusive equal to its Inclusive
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Exclusive(pub usize);
 
@@ -33,50 +37,764 @@ impl Counter {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-struct LastFrameIndex(pub usize);
+struct LastFrameIndex(pub u64);
+
+type TypeResources = Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)>;
+type ObjectsToAdd = Vec<(ObjectID, Box<dyn Renderable>)>;
+// The objects with the ids of their submeshes after the first one
+type SubmeshObjectIDs = Vec<(ObjectID, Vec<ObjectID>)>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ObjectType(VerticesIndicesHash);
 
+impl ObjectType {
+    /// The hash of the mesh, combined with the material key when it isn't the default one
+    pub fn of(object: &dyn Renderable) -> Self {
+        Self::of_mesh_and_key(object.get_vertices_and_indices_hash(), object.get_material_key())
+    }
+
+    fn of_mesh_and_key(vertices_indices_hash: VerticesIndicesHash, material_key: MaterialKey) -> Self {
+        if material_key == MaterialKey::default() {
+            return ObjectType(vertices_indices_hash);
+        }
+        let mut hasher = DefaultHasher::new();
+        vertices_indices_hash.hash(&mut hasher);
+        material_key.hash(&mut hasher);
+        ObjectType(VerticesIndicesHash(hasher.finish()))
+    }
+
+    pub fn get_hash(&self) -> VerticesIndicesHash {
+        self.0
+    }
+
+    #[cfg(test)]
+    pub fn from_hash(vertices_indices_hash: VerticesIndicesHash) -> Self {
+        ObjectType(vertices_indices_hash)
+    }
+}
+
+/// One submesh of an object, which is added as its own object so it gets its own object type and descriptor set. It shares the mesh and the instance resources of the object.
+struct SubmeshRenderable {
+    object: Arc<dyn Renderable>,
+    submesh: Submesh,
+    material_key: MaterialKey,
+}
+
+impl SubmeshRenderable {
+    fn new(object: Arc<dyn Renderable>, submesh: Submesh, submesh_index: usize) -> Self {
+        // The objects with the same mesh get the same object types for their submeshes, unless their own material keys differ
+        let mut hasher = DefaultHasher::new();
+        object.get_material_key().hash(&mut hasher);
+        submesh_index.hash(&mut hasher);
+        Self {
+            object,
+            submesh,
+            material_key: MaterialKey(hasher.finish()),
+        }
+    }
+}
+
+/// An object with a material key made from the identities of its type resources, so objects with the same mesh only share an object type when they also share the type resources.
+/// Used when the object types are unique per type resources, see [`ObjectManager::set_unique_object_types`].
+struct UniqueTypeRenderable {
+    object: Box<dyn Renderable>,
+    material_key: MaterialKey,
+}
+
+impl UniqueTypeRenderable {
+    fn new(object: Box<dyn Renderable>) -> Self {
+        let material_key = Self::get_material_key_of(object.as_ref());
+        Self { object, material_key }
+    }
+
+    fn get_material_key_of(object: &dyn Renderable) -> MaterialKey {
+        let mut type_resources = object.get_type_resources();
+        type_resources.sort_by_key(|(resource_id, _)| *resource_id);
+        let mut hasher = DefaultHasher::new();
+        object.get_material_key().hash(&mut hasher);
+        for (resource_id, resource) in type_resources.iter() {
+            resource_id.hash(&mut hasher);
+            (Arc::as_ptr(resource) as *const () as usize).hash(&mut hasher);
+        }
+        MaterialKey(hasher.finish())
+    }
+}
+
+impl Renderable for UniqueTypeRenderable {
+    fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        self.object.get_vertices_and_indices_hash()
+    }
+
+    fn get_vertex_byte_data(&self) -> Vec<u8> {
+        self.object.get_vertex_byte_data()
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        self.object.get_indices()
+    }
+
+    fn get_index_type(&self) -> vk::IndexType {
+        self.object.get_index_type()
+    }
+
+    fn get_object_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)> {
+        self.object.get_object_instance_resources()
+    }
+
+    fn get_vertex_binding_info(&self) -> vk::VertexInputBindingDescription {
+        self.object.get_vertex_binding_info()
+    }
+
+    fn get_vertex_attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.object.get_vertex_attribute_descriptions()
+    }
+
+    fn get_shader_infos(&self) -> Vec<ShaderInfo> {
+        self.object.get_shader_infos()
+    }
+
+    fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)> {
+        self.object.get_type_resources()
+    }
+
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        self.object.get_sprite_instance_data()
+    }
+
+    fn get_material_key(&self) -> MaterialKey {
+        self.material_key
+    }
+
+    fn is_mesh_validated(&self) -> bool {
+        self.object.is_mesh_validated()
+    }
+
+    fn get_submesh_indices(&self) -> Option<(u32, u32)> {
+        self.object.get_submesh_indices()
+    }
+
+    fn get_vertex_byte_data_job(&self) -> Box<dyn FnOnce() -> Vec<u8> + Send> {
+        self.object.get_vertex_byte_data_job()
+    }
+}
+
+impl Renderable for SubmeshRenderable {
+    fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        self.object.get_vertices_and_indices_hash()
+    }
+
+    fn get_vertex_byte_data(&self) -> Vec<u8> {
+        self.object.get_vertex_byte_data()
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        self.object.get_indices()
+    }
+
+    fn get_index_type(&self) -> vk::IndexType {
+        self.object.get_index_type()
+    }
+
+    fn get_object_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)> {
+        self.object.get_object_instance_resources()
+    }
+
+    fn get_vertex_binding_info(&self) -> vk::VertexInputBindingDescription {
+        self.object.get_vertex_binding_info()
+    }
+
+    fn get_vertex_attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.object.get_vertex_attribute_descriptions()
+    }
+
+    fn get_shader_infos(&self) -> Vec<ShaderInfo> {
+        self.object.get_shader_infos()
+    }
+
+    fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)> {
+        self.submesh.type_resources.clone()
+    }
+
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        None
+    }
+
+    fn get_material_key(&self) -> MaterialKey {
+        self.material_key
+    }
+
+    fn is_mesh_validated(&self) -> bool {
+        self.object.is_mesh_validated()
+    }
+
+    fn get_submesh_indices(&self) -> Option<(u32, u32)> {
+        Some((self.submesh.first_index, self.submesh.num_indices))
+    }
+
+    fn get_vertex_byte_data_job(&self) -> Box<dyn FnOnce() -> Vec<u8> + Send> {
+        self.object.get_vertex_byte_data_job()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TextureCacheKey {
+    AssetKey(String),
+    ContentHash(u64),
+}
+
+struct CachedTexture {
+    allocation: AllocationInfo,
+    sampler: Sampler,
+    references: usize,
+    // What the texture would have taken with full quality and a full mip chain
+    full_quality_bytes: u64,
+}
+
+/// Why a texture is drawn with another image than its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FallbackTexture {
+    /// The placeholder is used while the image is loading.
+    Loading,
+    /// The missing texture is used, since the image can't be uploaded.
+    Missing(Cow<'static, str>),
+}
+
+/// Shares identical textures between object types. Every entry counts how many object types use it, and the image is only freed when that reaches zero.
+/// It also holds the texture quality settings, and textures uploaded with different settings are never shared.
+pub struct TextureCache {
+    textures: HashMap<(TextureCacheKey, TextureQuality, u32), CachedTexture>,
+    // The samplers of the removed entries, which are given back to the sampler manager before the next frame
+    released_samplers: Vec<Sampler>,
+    hits: usize,
+    quality: TextureQuality,
+    max_mip_levels: u32,
+}
+
+impl TextureCache {
+    const MISSING_TEXTURE_ASSET_KEY: &'static str = "engine:missing_texture";
+    const MISSING_CUBE_MAP_ASSET_KEY: &'static str = "engine:missing_cube_map";
+    const PLACEHOLDER_TEXTURE_ASSET_KEY: &'static str = "engine:placeholder_texture";
+    const PLACEHOLDER_CUBE_MAP_ASSET_KEY: &'static str = "engine:placeholder_cube_map";
+
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+            released_samplers: Vec::new(),
+            hits: 0,
+            quality: TextureQuality::Full,
+            max_mip_levels: u32::MAX,
+        }
+    }
+
+    fn get_key(&self, images: &[DynamicImage], asset_key: Option<String>) -> (TextureCacheKey, TextureQuality, u32) {
+        Self::get_key_for_quality(images, asset_key, self.quality, self.max_mip_levels)
+    }
+
+    /// The key the images get in a cache with these quality settings.
+    pub fn get_key_for_quality(images: &[DynamicImage], asset_key: Option<String>, quality: TextureQuality, max_mip_levels: u32) -> (TextureCacheKey, TextureQuality, u32) {
+        let key = match asset_key {
+            Some(asset_key) => TextureCacheKey::AssetKey(asset_key),
+            None => {
+                let mut hasher = DefaultHasher::new();
+                for image in images {
+                    image.width().hash(&mut hasher);
+                    image.height().hash(&mut hasher);
+                    image.as_bytes().hash(&mut hasher);
+                }
+                TextureCacheKey::ContentHash(hasher.finish())
+            },
+        };
+        (key, quality, max_mip_levels)
+    }
+
+    // Uploaded once and shared by every texture that is missing, since they all have the same asset key
+    fn get_missing_texture(is_cube_map: bool) -> (Vec<DynamicImage>, Option<String>) {
+        let checker = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 4, |x, y| {
+            if (x + y).is_multiple_of(2) { image::Rgba([255, 0, 255, 255]) } else { image::Rgba([0, 0, 0, 255]) }
+        }));
+        if is_cube_map {
+            (vec![checker; 6], Some(Self::MISSING_CUBE_MAP_ASSET_KEY.to_string()))
+        } else {
+            (vec![checker], Some(Self::MISSING_TEXTURE_ASSET_KEY.to_string()))
+        }
+    }
+
+    // A grey pixel that is shared like the missing texture, and doesn't stand out while the images load
+    fn get_placeholder_texture(is_cube_map: bool) -> (Vec<DynamicImage>, Option<String>) {
+        let grey = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 128, 255])));
+        if is_cube_map {
+            (vec![grey; 6], Some(Self::PLACEHOLDER_CUBE_MAP_ASSET_KEY.to_string()))
+        } else {
+            (vec![grey], Some(Self::PLACEHOLDER_TEXTURE_ASSET_KEY.to_string()))
+        }
+    }
+
+    fn acquire(&mut self, key: &(TextureCacheKey, TextureQuality, u32)) -> Option<(AllocationInfo, Sampler)> {
+        let cached = self.textures.get_mut(key)?;
+        // Only prepared textures are in the cache without references, and their first use isn't a hit
+        if cached.references > 0 {
+            self.hits += 1;
+        }
+        cached.references += 1;
+        // The cache keeps its copy until the last reference is released, and only that copy is freed
+        Some((unsafe { cached.allocation.duplicate_handle() }, cached.sampler))
+    }
+
+    fn insert(&mut self, key: (TextureCacheKey, TextureQuality, u32), allocation: AllocationInfo, sampler: Sampler, full_quality_bytes: u64) {
+        self.textures.insert(key, CachedTexture { allocation, sampler, references: 1, full_quality_bytes });
+    }
+
+    /// Adds a texture that was uploaded by an [`UploadContext`] without any references, so the next object type that uses it acquires it instead of uploading it.
+    /// Returns the allocation when the cache already has the texture, so that the caller can free it.
+    fn insert_prepared(&mut self, texture: PreparedTexture, sampler: Sampler) -> Option<AllocationInfo> {
+        if self.textures.contains_key(&texture.key) {
+            self.released_samplers.push(sampler);
+            return Some(texture.allocation);
+        }
+        self.textures.insert(texture.key, CachedTexture { allocation: texture.allocation, sampler, references: 0, full_quality_bytes: texture.full_quality_bytes });
+        None
+    }
+
+    // Takes out the prepared textures that no object type ended up using
+    fn release_unused(&mut self, keys: &[(TextureCacheKey, TextureQuality, u32)]) -> Vec<AllocationInfo> {
+        let unused_textures = keys.iter().filter_map(|key| match self.textures.entry(key.clone()) {
+            Entry::Occupied(cached) if cached.get().references == 0 => Some(cached.remove()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        unused_textures.into_iter().map(|cached| {
+            self.released_samplers.push(cached.sampler);
+            cached.allocation
+        }).collect()
+    }
+
+    /// Drops one reference to the texture. Returns the allocation when it was the last reference, so that the caller can free it.
+    fn release(&mut self, allocation: AllocationInfo) -> Option<AllocationInfo> {
+        let key = self.textures.iter().find(|(_, cached)| cached.allocation.get_image() == allocation.get_image()).map(|(key, _)| key.clone());
+        let Some(key) = key else {
+            log::warn!(target: logging::OBJECTS, "Texture {:?} is not in the texture cache. So it is freed directly.", allocation.get_image());
+            return Some(allocation);
+        };
+        let cached = self.textures.get_mut(&key).unwrap();
+        cached.references -= 1;
+        if cached.references == 0 {
+            let cached = self.textures.remove(&key).unwrap();
+            self.released_samplers.push(cached.sampler);
+            return Some(cached.allocation);
+        }
+        None
+    }
+
+    fn take_released_samplers(&mut self) -> Vec<Sampler> {
+        std::mem::take(&mut self.released_samplers)
+    }
+
+    // Scales the image down for the texture quality, which is the same as skipping the largest mip levels
+    fn prepare_image(image: DynamicImage, quality: TextureQuality) -> DynamicImage {
+        let skipped_levels = quality.get_skipped_mip_levels();
+        if skipped_levels == 0 || (image.width() == 1 && image.height() == 1) {
+            return image;
+        }
+        let width = (image.width() >> skipped_levels).max(1);
+        let height = (image.height() >> skipped_levels).max(1);
+        image.resize_exact(width, height, image::imageops::FilterType::Triangle)
+    }
+
+    /// Uploads the images with the quality settings and creates the view of the texture. One image is a 2D texture and six images are the faces of a cube map.
+    pub fn upload_texture(images: Vec<DynamicImage>, quality: TextureQuality, max_mip_levels: u32, command_pool: &vk::CommandPool, graphics_queue: &Queue, allocator: &VkAllocator) -> Result<AllocationInfo, EngineError> {
+        let images = images.into_iter().map(|image| Self::prepare_image(image, quality)).collect::<Vec<_>>();
+        let mut allocation = match <[DynamicImage; 6]>::try_from(images) {
+            Ok(faces) => allocator.create_device_local_cube_image(faces, command_pool, graphics_queue, max_mip_levels, false)?,
+            Err(mut images) => allocator.create_device_local_image(images.remove(0), command_pool, graphics_queue, max_mip_levels, vk::SampleCountFlags::TYPE_1, false)?,
+        };
+        let mip_levels = allocation.get_mip_levels().unwrap();
+        let view_type = if allocation.get_array_layers() == 6 { vk::ImageViewType::CUBE } else { vk::ImageViewType::TYPE_2D };
+        // The format needs to be the same as the format read in [`VkAllocator::create_device_local_image`]
+        if let Err(e) = allocator.create_image_view(&mut allocation, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, mip_levels, view_type) {
+            let mut error_str = String::new();
+            free_allocations_add_error_string!(allocator, [allocation], error_str);
+            return Err(e.with_cleanup_errors(error_str));
+        }
+        Ok(allocation)
+    }
+
+    // Cube maps are clamped, since repeating would blend the opposite edge of a face into the seams
+    fn get_sampler_config(allocation: &AllocationInfo) -> SamplerConfig {
+        let address_mode = if allocation.get_array_layers() == 6 { vk::SamplerAddressMode::CLAMP_TO_EDGE } else { vk::SamplerAddressMode::REPEAT };
+        SamplerConfig {
+            s_type: StructureType::SAMPLER_CREATE_INFO,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            anisotropy_enable: vk::TRUE,
+            max_anisotropy: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: allocation.get_mip_levels().unwrap() as f32,
+        }
+    }
+
+    // The RGBA8 size of the whole mip chain
+    pub fn get_full_quality_bytes(image: &DynamicImage) -> u64 {
+        let (mut width, mut height) = (image.width() as u64, image.height() as u64);
+        let mut bytes = width * height * 4;
+        while width > 1 || height > 1 {
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+            bytes += width * height * 4;
+        }
+        bytes
+    }
+
+    pub fn get_stats(&self) -> TextureCacheStats {
+        let total_bytes = self.textures.values().map(|cached| cached.allocation.get_size()).sum();
+        TextureCacheStats {
+            unique_textures: self.textures.len(),
+            total_bytes,
+            hits: self.hits,
+            estimated_saved_bytes: self.textures.values().map(|cached| cached.full_quality_bytes).sum::<u64>().saturating_sub(total_bytes),
+        }
+    }
+}
+
+// The bytes a mesh is uploaded with
+struct SerializedMesh {
+    vertex_data: Vec<u8>,
+    index_type: vk::IndexType,
+    index_data: Vec<u8>,
+}
+
+struct RegisteredMesh {
+    // The allocations are None when the mesh has no vertices or no indices
+    vertices: (Option<AllocationInfo>, Vec<u8>),
+    indices: (Option<AllocationInfo>, Vec<u8>),
+    index_type: vk::IndexType,
+    references: usize,
+}
+
+/// Owns the vertex and index buffers of every mesh, so a mesh is only uploaded once even when object types in different pipelines use it.
+/// Every mesh counts how many object types use it, and its buffers are only freed when that reaches zero.
+pub struct MeshRegistry {
+    meshes: HashMap<VerticesIndicesHash, RegisteredMesh>,
+}
+
+impl MeshRegistry {
+    pub fn new() -> Self {
+        Self {
+            meshes: HashMap::new(),
+        }
+    }
+
+    /// Serializes the meshes of the objects that aren't registered yet, each on its own rayon task. The objects aren't Send, so their data is read here and only the serialization runs in parallel.
+    fn serialize_new_meshes<'a>(&self, objects: impl Iterator<Item = &'a dyn Renderable>) -> HashMap<VerticesIndicesHash, SerializedMesh> {
+        let mut new_meshes = HashMap::new();
+        for object in objects {
+            let mesh = object.get_vertices_and_indices_hash();
+            if !self.meshes.contains_key(&mesh) {
+                new_meshes.entry(mesh).or_insert_with(|| (object.get_vertex_byte_data_job(), object.get_indices(), object.get_index_type()));
+            }
+        }
+        new_meshes.into_par_iter().map(|(mesh, (vertex_data_job, indices, index_type))| {
+            let (index_type, index_data) = Self::get_index_data(mesh, &indices, index_type);
+            (mesh, SerializedMesh { vertex_data: vertex_data_job(), index_type, index_data })
+        }).collect()
+    }
+
+    /// Uploads the mesh the first time it is used, from the bytes made by [`MeshRegistry::serialize_new_meshes`]. Otherwise it only adds a reference to it.
+    fn acquire(&mut self, mesh: VerticesIndicesHash, serialized_meshes: &mut HashMap<VerticesIndicesHash, SerializedMesh>, command_pool: &vk::CommandPool, graphics_queue: &Queue, allocator: &VkAllocator) -> Result<VerticesIndicesHash, EngineError> {
+        if let Some(registered_mesh) = self.meshes.get_mut(&mesh) {
+            registered_mesh.references += 1;
+            return Ok(mesh);
+        }
+
+        let Some(SerializedMesh { vertex_data: vertices_data, index_type, index_data: indices_data }) = serialized_meshes.remove(&mesh) else {
+            return Err(EngineError::from(format!("The mesh {:?} was not serialized before it was acquired", mesh)));
+        };
+
+        let vertex_allocation = Self::create_geometry_buffer(&vertices_data, vk::BufferUsageFlags::VERTEX_BUFFER, command_pool, graphics_queue, allocator)?;
+        let index_allocation = match Self::create_geometry_buffer(&indices_data, vk::BufferUsageFlags::INDEX_BUFFER, command_pool, graphics_queue, allocator) {
+            Ok(alloc) => alloc,
+            Err(e) => {
+                let mut error_str = String::new();
+                if let Some(vertex_allocation) = vertex_allocation {
+                    free_allocations_add_error_string!(allocator, [vertex_allocation], error_str);
+                }
+                return Err(e.with_cleanup_errors(error_str));
+            },
+        };
+        self.meshes.insert(mesh, RegisteredMesh {
+            vertices: (vertex_allocation, vertices_data),
+            indices: (index_allocation, indices_data),
+            index_type,
+            references: 1,
+        });
+        Ok(mesh)
+    }
+
+    /// The index type the mesh is uploaded with and its index bytes. 16-bit indices fall back to 32 bits when an index doesn't fit.
+    pub fn get_index_data(mesh: VerticesIndicesHash, indices: &[u32], mut index_type: vk::IndexType) -> (vk::IndexType, Vec<u8>) {
+        if index_type == vk::IndexType::UINT16 && indices.iter().any(|index| *index > u16::MAX as u32) {
+            log::warn!(target: logging::OBJECTS, "The mesh {:?} uses 16-bit indices, but some of its indices are larger than {}. 32-bit indices are used instead", mesh, u16::MAX);
+            index_type = vk::IndexType::UINT32;
+        }
+        let indices_data = match index_type {
+            vk::IndexType::UINT16 => indices.iter().flat_map(|x| (*x as u16).to_ne_bytes()).collect::<Vec<u8>>(),
+            _ => indices.iter().flat_map(|x| x.to_ne_bytes()).collect::<Vec<u8>>(),
+        };
+        (index_type, indices_data)
+    }
+
+    /// Adds a mesh that was uploaded by an [`UploadContext`] without any references, so the next object type that uses it acquires it instead of uploading it.
+    /// Returns its allocations when the mesh is already registered, so that the caller can free them.
+    fn insert_prepared(&mut self, mesh: VerticesIndicesHash, buffers: PreparedMeshBuffers) -> Vec<AllocationInfo> {
+        if self.meshes.contains_key(&mesh) {
+            return buffers.vertices.0.into_iter().chain(buffers.indices.0).collect();
+        }
+        self.meshes.insert(mesh, RegisteredMesh {
+            vertices: buffers.vertices,
+            indices: buffers.indices,
+            index_type: buffers.index_type,
+            references: 0,
+        });
+        Vec::new()
+    }
+
+    // Takes out the prepared meshes that no object type ended up using
+    fn release_unused(&mut self, meshes: &[VerticesIndicesHash]) -> Vec<AllocationInfo> {
+        meshes.iter().filter_map(|mesh| match self.meshes.entry(*mesh) {
+            Entry::Occupied(registered_mesh) if registered_mesh.get().references == 0 => Some(registered_mesh.remove()),
+            _ => None,
+        }).flat_map(|registered_mesh| registered_mesh.vertices.0.into_iter().chain(registered_mesh.indices.0)).collect()
+    }
+
+    /// Drops one reference to the mesh. Returns its allocations when it was the last reference, so that the caller can free them.
+    fn release(&mut self, mesh: VerticesIndicesHash) -> Vec<AllocationInfo> {
+        let Some(registered_mesh) = self.meshes.get_mut(&mesh) else {
+            log::warn!(target: logging::OBJECTS, "The mesh {:?} is not in the mesh registry. So there is nothing to release.", mesh);
+            return Vec::new();
+        };
+        registered_mesh.references -= 1;
+        if registered_mesh.references > 0 {
+            return Vec::new();
+        }
+        let registered_mesh = self.meshes.remove(&mesh).unwrap();
+        registered_mesh.vertices.0.into_iter().chain(registered_mesh.indices.0).collect()
+    }
+
+    fn get(&self, mesh: VerticesIndicesHash) -> Option<&RegisteredMesh> {
+        self.meshes.get(&mesh)
+    }
+
+    // Like acquire, but a new mesh gets a buffer handle without memory instead of being uploaded
+    #[cfg(test)]
+    fn acquire_without_gpu(&mut self, object: &dyn Renderable) -> VerticesIndicesHash {
+        let mesh = object.get_vertices_and_indices_hash();
+        if let Some(SerializedMesh { vertex_data, index_type, index_data }) = self.serialize_new_meshes(std::iter::once(object)).remove(&mesh) {
+            let vertex_buffer = vk::Buffer::from_raw(self.meshes.len() as u64 + 1);
+            self.insert_prepared(mesh, PreparedMeshBuffers { vertices: (Some(AllocationInfo::with_buffer(vertex_buffer)), vertex_data), indices: (None, index_data), index_type });
+        }
+        self.meshes.get_mut(&mesh).unwrap().references += 1;
+        mesh
+    }
+
+    pub fn create_geometry_buffer(data: &[u8], buffer_usage: vk::BufferUsageFlags, command_pool: &vk::CommandPool, graphics_queue: &Queue, allocator: &VkAllocator) -> Result<Option<AllocationInfo>, EngineError> {
+        // Vulkan does not allow empty buffers, and nothing is drawn from an empty buffer anyway
+        if data.is_empty() {
+            return Ok(None);
+        }
+        allocator.create_device_local_buffer(command_pool, graphics_queue, data, buffer_usage, false).map(Some)
+    }
+
+    #[cfg(test)]
+    pub fn get_num_meshes(&self) -> usize {
+        self.meshes.len()
+    }
+
+    /// The total size in bytes of the vertex and index data of all the meshes.
+    pub fn get_buffer_sizes(&self) -> (usize, usize) {
+        self.meshes.values().fold((0, 0), |(vertex_bytes, index_bytes), registered_mesh| {
+            (vertex_bytes + registered_mesh.vertices.1.len(), index_bytes + registered_mesh.indices.1.len())
+        })
+    }
+
+    fn get_allocations_mut(&mut self) -> Vec<&mut AllocationInfo> {
+        self.meshes.values_mut().flat_map(|registered_mesh| registered_mesh.vertices.0.iter_mut().chain(registered_mesh.indices.0.iter_mut())).collect()
+    }
+
+    fn get_debug_handles(&self, mesh: VerticesIndicesHash) -> Vec<(vk::ObjectType, u64, String)> {
+        let mut handles = Vec::new();
+        let Some(registered_mesh) = self.meshes.get(&mesh) else {
+            return handles;
+        };
+        if let Some(vertex_allocation) = registered_mesh.vertices.0.as_ref() {
+            handles.push((vk::ObjectType::BUFFER, vertex_allocation.get_buffer().unwrap().as_raw(), "vertex buffer".to_string()));
+        }
+        if let Some(index_allocation) = registered_mesh.indices.0.as_ref() {
+            handles.push((vk::ObjectType::BUFFER, index_allocation.get_buffer().unwrap().as_raw(), "index buffer".to_string()));
+        }
+        handles
+    }
+}
+
+/// The levels of one [`crate::graphics_objects::LodGroup`]. Only the current level is visible, the others are hidden.
+struct LodGroupState {
+    object_ids: Vec<ObjectID>,
+    // When there is a distance for the last level too, no level is drawn past it and the current level is then object_ids.len()
+    switch_distances: Vec<f32>,
+    hysteresis: f32,
+    current_level: usize,
+}
+
+impl LodGroupState {
+    fn select_level(&self, distance: f32) -> usize {
+        let mut level = self.current_level;
+        while level < self.switch_distances.len() && distance > self.switch_distances[level] * (1.0 + self.hysteresis) {
+            level += 1;
+        }
+        while level > 0 && distance < self.switch_distances[level - 1] * (1.0 - self.hysteresis) {
+            level -= 1;
+        }
+        level
+    }
+}
+
+/// The animation of a sprite, its UV rect is written to the sprite's instance data before the storage buffers are copied.
+struct SpriteAnimationState {
+    instance_data: Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>,
+    animation: SpriteAnimation,
+    // Seconds played, which doesn't advance while paused
+    elapsed: f32,
+    is_paused: bool,
+    is_finished: bool,
+}
+
+impl SpriteAnimationState {
+    fn advance(&mut self, delta_time: f32) {
+        if !self.is_paused && !self.is_finished {
+            self.elapsed += delta_time;
+        }
+        let (frame_index, is_finished) = self.animation.get_frame_index(self.elapsed);
+        self.is_finished = is_finished;
+        // A poisoned instance is left as it is, the snapshot logs it and keeps drawing its last data
+        if let Ok(mut instance_data) = self.instance_data.write() {
+            instance_data.buffer.uv_rect = self.animation.frames[frame_index];
+        }
+    }
+}
+
 pub struct ObjectManager {
     data_used_in_shader: HashMap<PipelineConfig, DataUsedInShader>,
     pipeline_config_hash_to_pipeline_config: HashMap<u64, PipelineConfig>,
     object_type_to_pipeline_hash: HashMap<ObjectType, u64>,
     object_id_to_pipeline_hash: HashMap<ObjectID, u64>,
+    // HashMap iteration order is not stable, so the draw order is tracked separately
+    pipeline_draw_order: Vec<u64>,
+    draw_order: DrawOrder,
+    draw_order_keys: HashMap<ObjectType, i32>,
+    texture_cache: TextureCache,
+    mesh_registry: MeshRegistry,
+    lod_groups: Vec<LodGroupState>,
+    // Used for debugging, every LOD group draws this level, or its last level if it has fewer
+    forced_lod_level: Option<usize>,
+    sprite_animations: HashMap<ObjectID, SpriteAnimationState>,
+    // Set when the texture quality has changed, the textures are then uploaded again before the next frame
+    are_textures_outdated: bool,
+    // Checks the vertices and indices of the added objects before anything is allocated for them
+    is_mesh_validation_enabled: bool,
+    // Gathered from the pipelines and their textures when they change, so they can be borrowed as a slice
+    resource_errors: Vec<ResourceError>,
+    // Set when buffers, images or descriptor sets have been created since the debug names were last taken
+    are_debug_names_outdated: bool,
+    // How many times the resource data of the objects has been snapshotted for drawing
+    data_version: u64,
+    // Only the instances marked dirty are read and copied to the storage buffers every frame, instead of all of them
+    is_partial_instance_upload_enabled: bool,
+    // The removals that haven't been polled as freed yet. They are pending while a pipeline still has them queued with the data to remove
+    removals: HashSet<RemovalHandle>,
+    next_removal_handle: u64,
+    // Read from the controller's settings when it is created
+    frames_in_flight: usize,
+    // The objects that were added for the submeshes after the first one, which has the id of the object itself
+    submesh_objects: HashMap<ObjectID, Vec<ObjectID>>,
+    submesh_parents: HashMap<ObjectID, ObjectID>,
+    // The type resources are part of the object type, instead of only the mesh and the material key
+    are_object_types_unique: bool,
 }
 
 impl ObjectManager {
-    pub fn new() -> Self {
+    pub fn new(frames_in_flight: usize) -> Self {
         Self {
             data_used_in_shader: HashMap::new(),
             pipeline_config_hash_to_pipeline_config: HashMap::new(),
             object_id_to_pipeline_hash: HashMap::new(),
             object_type_to_pipeline_hash: HashMap::new(),
+            pipeline_draw_order: Vec::new(),
+            draw_order: DrawOrder::InsertionOrder,
+            draw_order_keys: HashMap::new(),
+            texture_cache: TextureCache::new(),
+            mesh_registry: MeshRegistry::new(),
+            lod_groups: Vec::new(),
+            forced_lod_level: None,
+            sprite_animations: HashMap::new(),
+            are_textures_outdated: false,
+            is_mesh_validation_enabled: cfg!(debug_assertions),
+            resource_errors: Vec::new(),
+            are_debug_names_outdated: false,
+            data_version: 0,
+            is_partial_instance_upload_enabled: false,
+            removals: HashSet::new(),
+            next_removal_handle: 0,
+            frames_in_flight,
+            submesh_objects: HashMap::new(),
+            submesh_parents: HashMap::new(),
+            are_object_types_unique: false,
         }
     }
 
-    pub fn add_objects(&mut self, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, msaa_samples: vk::SampleCountFlags, swapchain_format: vk::Format, depth_format: vk::Format, swapchain_extent: &Extent2D, current_frame: usize, pipeline_manager: &mut PipelineManager, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
+    pub fn add_objects(&mut self, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, msaa_samples: vk::SampleCountFlags, swapchain_format: vk::Format, depth_format: vk::Format, swapchain_extent: &Extent2D, current_frame: usize, pipeline_manager: &mut PipelineManager, allocator: &VkAllocator) -> Result<(), EngineError> {
+        let (mut objects_to_add, submesh_objects) = self.split_submeshes(objects_to_add)?;
+        if self.are_object_types_unique {
+            objects_to_add = objects_to_add.into_iter().map(|(object_id, object)| (object_id, Box::new(UniqueTypeRenderable::new(object)) as Box<dyn Renderable>)).collect();
+        }
+        if self.is_mesh_validation_enabled {
+            let mut validated_object_types = HashSet::new();
+            for (object_id, object) in objects_to_add.iter() {
+                // Objects with the same type have the same mesh, so it only has to be checked once
+                if !object.is_mesh_validated() && validated_object_types.insert(ObjectType::of(object.as_ref())) {
+                    Self::validate_mesh(object.as_ref()).map_err(|e| Cow::Owned(format!("The mesh of object {:?} is invalid: {}", object_id, e)))?;
+                }
+            }
+        }
+
         let all_object_types_including_new_ones = self.get_object_types();
         
         if all_object_types_including_new_ones.len() > VkController::MAX_OBJECT_TYPES {
-            return Err(Cow::from(format!("The maximum number of object types is {}. If you add the given objects you would have {} object types, which is not supported (this is related to how many descriptor sets that are in the descriptor set pool).", VkController::MAX_OBJECT_TYPES, all_object_types_including_new_ones.len())));
+            return Err(EngineError::from(format!("The maximum number of object types is {}. If you add the given objects you would have {} object types, which is not supported (this is related to how many descriptor sets that are in the descriptor set pool).", VkController::MAX_OBJECT_TYPES, all_object_types_including_new_ones.len())));
         }
 
         let mut object_type_resource_callbacks = HashMap::new();
-        for (_, object) in objects_to_add.iter() {
-            let object_type = ObjectType(object.get_vertices_and_indices_hash());
-            let object_type_resource_callbacks = object_type_resource_callbacks.entry(object_type).or_insert_with(Vec::new);
-            object_type_resource_callbacks.sort_by_key(|(x, _)| *x);
+        for (object_id, object) in objects_to_add.iter() {
+            let object_type = ObjectType::of(object.as_ref());
             let mut new_callbacks = object.get_type_resources();
             new_callbacks.sort_by_key(|(x, _)| *x);
-            if object_type_resource_callbacks.is_empty() {
-                object_type_resource_callbacks.extend(new_callbacks);
-            } else if object_type_resource_callbacks.len() != new_callbacks.len() {
-                return Err(Cow::from(format!("Object type {:?} has multiple different {} callbacks. Which is not supported. It has to be the same for all objects with the same type.", object_type, std::any::type_name::<ObjectTypeGraphicsResourceType>()))); 
-            } else if !object_type_resource_callbacks.iter().zip(new_callbacks.iter()).all(|(a, b)| a.0 == b.0) {
-                println!("Object type {:?} got new {} callbacks. It will therefor overwrite the old ones chosen. Remember that you only can have one set of callbacks for a object type!", object_type, std::any::type_name::<ObjectTypeGraphicsResourceType>());
-                object_type_resource_callbacks.clear();
-                object_type_resource_callbacks.extend(new_callbacks);
+            // The objects that have already been added decide the resources of their type, otherwise the first new object does
+            let object_type_resource_callbacks = match object_type_resource_callbacks.entry(object_type) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let mut type_resources = self.get_type_resources(object_type).unwrap_or_else(|| new_callbacks.clone());
+                    type_resources.sort_by_key(|(x, _)| *x);
+                    entry.insert(type_resources)
+                },
+            };
+            let is_same_resources = object_type_resource_callbacks.len() == new_callbacks.len() && object_type_resource_callbacks.iter().zip(new_callbacks.iter()).all(|(a, b)| a.0 == b.0 && Arc::as_ptr(&a.1) as *const () == Arc::as_ptr(&b.1) as *const ());
+            if !is_same_resources {
+                return Err(EngineError::from(format!("Object {:?} has other {} resources than the objects of its type {:?}. Objects with the same mesh share the type resources, so give it its own material key with Renderable::get_material_key or enable unique object types to use other resources.", object_id, std::any::type_name::<ObjectTypeGraphicsResourceType>(), object_type)));
             }
         }
 
@@ -86,8 +804,8 @@ impl ObjectManager {
             object_type_to_pipeline.insert(object_type.clone(), pipeline_config);
         });
 
-        for (_, object) in objects_to_add.iter() {
-            let object_type = ObjectType(object.get_vertices_and_indices_hash());
+        for (object_id, object) in objects_to_add.iter() {
+            let object_type = ObjectType::of(object.as_ref());
 
             if object_type_to_pipeline.contains_key(&object_type) {
                 continue;
@@ -97,17 +815,17 @@ impl ObjectManager {
             let mut descriptor_set_layout_bindings = Vec::new();
             for (resource_id, resource) in object_type_resource_callbacks.get(&object_type).unwrap() {
                 if resource_ids.contains(&resource_id) {
-                    return Err(Cow::from(format!("Resource id {:?} is used multiple times for the same object. This is not allowed.", resource_id)));
+                    return Err(EngineError::from(format!("Resource id {:?} is used multiple times for the same object. This is not allowed.", resource_id)));
                 }
                 resource_ids.push(resource_id);
-                descriptor_set_layout_bindings.push(resource.read().unwrap().get_descriptor_set_layout_binding());
+                descriptor_set_layout_bindings.push(resource.read().unwrap_or_else(PoisonError::into_inner).get_descriptor_set_layout_binding());
             }
             for (resource_id, resource) in object.get_object_instance_resources().iter() {
                 if resource_ids.contains(&resource_id) {
-                    return Err(Cow::from(format!("Resource id {:?} is used multiple times for the same object. This is not allowed.", resource_id)));
+                    return Err(EngineError::from(format!("Resource id {:?} is used multiple times for the same object. This is not allowed.", resource_id)));
                 }
                 resource_ids.push(resource_id);
-                let layout_binding = resource.read().unwrap().get_descriptor_set_layout_binding();
+                let layout_binding = resource.read().unwrap_or_else(PoisonError::into_inner).get_descriptor_set_layout_binding();
                 descriptor_set_layout_bindings.push(layout_binding);
             }
 
@@ -121,194 +839,925 @@ impl ObjectManager {
                 swapchain_format,
                 depth_format,
                 allocator
-            ).expect(format!("Failed to create pipeline config for object with type {:?}", object_type).as_str());
+            )?;
             
-            let _ = pipeline_manager.get_or_create_pipeline(&mut pipeline_config, device, swapchain_extent, allocator);
+            // Nothing has been added yet, so the objects are left out when even the error pipeline can't be created
+            pipeline_manager.get_or_create_pipeline(&mut pipeline_config, device, swapchain_extent, allocator)?;
+            pipeline_config.validate_descriptor_set_layout_bindings().map_err(|e| EngineError::from(format!("The bindings of object {:?} don't match its shaders: {}", object_id, e)))?;
+            // The objects of a type have the same resources, so the first one is enough to check the buffer sizes
+            Self::validate_buffer_sizes(object.as_ref(), object_type_resource_callbacks.get(&object_type).unwrap(), &pipeline_config).map_err(|e| EngineError::from(format!("The buffers of object {:?} don't match its shaders: {}", object_id, e)))?;
 
             object_type_to_pipeline.insert(object_type, pipeline_config);
         }
 
-        let mut pipeline_objects: HashMap<PipelineConfig, Vec<(ObjectID, Box<dyn Renderable>)>> = HashMap::new();
-        for (id, object) in objects_to_add {
-            let pipeline_config = object_type_to_pipeline.get(&ObjectType(object.get_vertices_and_indices_hash())).expect("Object type not found in object manager. This should never happen!").clone();
-            let e = pipeline_objects.entry(pipeline_config).or_insert_with(Vec::new);
-            e.push((id, object));
+        // A Vec is used instead of a HashMap so that the pipelines are added in the order they first appear
+        let mut pipeline_objects: Vec<(PipelineConfig, Vec<(ObjectID, Box<dyn Renderable>)>)> = Vec::new();
+        for (id, object) in objects_to_add {
+            let pipeline_config = object_type_to_pipeline.get(&ObjectType::of(object.as_ref())).expect("Object type not found in object manager. This should never happen!").clone();
+            match pipeline_objects.iter_mut().find(|(config, _)| *config == pipeline_config) {
+                Some((_, objects)) => objects.push((id, object)),
+                None => pipeline_objects.push((pipeline_config, vec![(id, object)])),
+            }
+        }
+        
+        for (pipeline_config, objects_with_pipeline_to_add) in pipeline_objects {
+            let mut hasher = DefaultHasher::new();
+            pipeline_config.hash(&mut hasher);
+            let pipeline_hash = hasher.finish();
+
+            let object_ids = objects_with_pipeline_to_add.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+            if let Entry::Occupied(mut data_used_in_shader) = self.data_used_in_shader.entry(pipeline_config.clone()) {
+                data_used_in_shader.get_mut().add_objects(&pipeline_config, objects_with_pipeline_to_add, device, instance, physical_device, command_pool, descriptor_pool, graphics_queue, sampler_manager, &mut self.texture_cache, &mut self.mesh_registry, current_frame, allocator)?;
+            } else {
+                let data_used_in_shader = DataUsedInShader::new(&pipeline_config, objects_with_pipeline_to_add, device, instance, physical_device, command_pool, descriptor_pool, graphics_queue, sampler_manager, &mut self.texture_cache, &mut self.mesh_registry, current_frame, self.frames_in_flight, allocator)?;
+                self.data_used_in_shader.insert(pipeline_config.clone(), data_used_in_shader);
+                self.pipeline_config_hash_to_pipeline_config.insert(pipeline_hash, pipeline_config.clone());
+                self.pipeline_draw_order.push(pipeline_hash);
+            }
+            object_ids.iter().for_each(|id| {
+                self.object_id_to_pipeline_hash.insert(*id, pipeline_hash);
+            });
+        }
+        self.refresh_resource_errors(pipeline_manager);
+        self.are_debug_names_outdated = true;
+
+        object_type_to_pipeline.iter().for_each(|(object_type, pipeline_config)| {
+            let mut hasher = DefaultHasher::new();
+            pipeline_config.hash(&mut hasher);
+            let pipeline_hash = hasher.finish();
+            self.object_type_to_pipeline_hash.insert(object_type.clone(), pipeline_hash);
+        });
+        for (object_id, submesh_object_ids) in submesh_objects {
+            self.submesh_parents.extend(submesh_object_ids.iter().map(|submesh_object_id| (*submesh_object_id, object_id)));
+            self.submesh_objects.insert(object_id, submesh_object_ids);
+        }
+        Ok(())
+    }
+
+    // Every submesh of an object is added as its own object, see SubmeshRenderable. Returns the objects to add and the ids of the submeshes after the first one by object
+    fn split_submeshes(&self, objects: ObjectsToAdd) -> Result<(ObjectsToAdd, SubmeshObjectIDs), EngineError> {
+        let mut objects_to_add = Vec::with_capacity(objects.len());
+        let mut submesh_objects = Vec::new();
+        for (object_id, object) in objects {
+            let submeshes = object.get_submeshes();
+            if submeshes.is_empty() {
+                objects_to_add.push((object_id, object));
+                continue;
+            }
+
+            let num_indices = object.get_indices().len();
+            for (i, submesh) in submeshes.iter().enumerate() {
+                if submesh.num_indices == 0 || submesh.first_index as usize + submesh.num_indices as usize > num_indices {
+                    return Err(EngineError::from(format!("Submesh {} of object {:?} draws {} indices from index {}, but the mesh has {} indices", i, object_id, submesh.num_indices, submesh.first_index, num_indices)));
+                }
+            }
+            let submesh_object_ids = self.generate_currently_unused_ids(submeshes.len() - 1)?;
+            let object: Arc<dyn Renderable> = Arc::from(object);
+            let object_ids = std::iter::once(object_id).chain(submesh_object_ids.iter().copied());
+            for (i, (id, submesh)) in object_ids.zip(submeshes).enumerate() {
+                objects_to_add.push((id, Box::new(SubmeshRenderable::new(object.clone(), submesh, i)) as Box<dyn Renderable>));
+            }
+            submesh_objects.push((object_id, submesh_object_ids));
+        }
+        Ok((objects_to_add, submesh_objects))
+    }
+
+    // The objects with the objects of their submeshes
+    // Checks every id before anything is changed, so an unknown id leaves all the objects as they were
+    fn get_objects_by_pipeline_config(&self, object_ids: &[ObjectID]) -> Result<HashMap<PipelineConfig, Vec<ObjectID>>, EngineError> {
+        let mut pipeline_objects: HashMap<PipelineConfig, Vec<ObjectID>> = HashMap::new();
+        for &object_id in object_ids {
+            let Some(pipeline_hash) = self.object_id_to_pipeline_hash.get(&object_id) else {
+                log::warn!(target: logging::OBJECTS, "Object with id {:?} not found in object manager. So none of the objects are removed.", object_id);
+                return Err(EngineError::ObjectNotFound(object_id));
+            };
+            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!").clone();
+            pipeline_objects.entry(pipeline_config).or_default().push(object_id);
+        }
+        Ok(pipeline_objects)
+    }
+
+    fn with_submesh_objects(&self, object_ids: impl IntoIterator<Item = ObjectID>) -> Vec<ObjectID> {
+        object_ids.into_iter().flat_map(|object_id| std::iter::once(object_id).chain(self.submesh_objects.get(&object_id).into_iter().flatten().copied())).collect()
+    }
+
+    /// Fails with [`EngineError::ObjectNotFound`] when one of the objects hasn't been added or was already removed, in which case none of them are removed.
+    pub fn remove_objects(&mut self, object_ids_to_remove: Vec<ObjectID>) -> Result<(), EngineError> {
+        self.remove_objects_with_removal(object_ids_to_remove, None)
+    }
+
+    // The removal is queued in every pipeline the objects are removed from
+    fn remove_objects_with_removal(&mut self, object_ids_to_remove: Vec<ObjectID>, removal: Option<RemovalHandle>) -> Result<(), EngineError> {
+        let object_ids_to_remove = self.with_submesh_objects(object_ids_to_remove);
+        let pipeline_objects = self.get_objects_by_pipeline_config(&object_ids_to_remove)?;
+        for object_id in object_ids_to_remove.iter() {
+            self.submesh_objects.remove(object_id);
+            self.submesh_parents.remove(object_id);
+            self.object_id_to_pipeline_hash.remove(object_id);
+        }
+        // The remaining levels of a LOD group keep the visibility they had, but they are no longer switched
+        self.lod_groups.retain(|lod_group| !lod_group.object_ids.iter().any(|object_id| object_ids_to_remove.contains(object_id)));
+        self.sprite_animations.retain(|object_id, _| !object_ids_to_remove.contains(object_id));
+
+        for (pipeline_config, object_ids_to_remove) in pipeline_objects {
+            if let Entry::Occupied(mut data_used_in_shader) = self.data_used_in_shader.entry(pipeline_config.clone()) {
+                data_used_in_shader.get_mut().remove_objects(object_ids_to_remove, &mut self.texture_cache, &mut self.mesh_registry)?;
+                if let Some(removal) = removal {
+                    data_used_in_shader.get_mut().queue_removal(removal);
+                }
+                self.are_debug_names_outdated = true;
+            } else {
+                log::warn!(target: logging::OBJECTS, "Could not remove objects with ids {:?}. Because it could not find any data used for the shaders with the pipeline config for the following shaders {:?}", object_ids_to_remove, pipeline_config.get_shader_paths());
+            }
+        }
+
+        Ok(())
+    }
+    
+    /// Removes the objects and tracks when the data they used is freed, see [`ObjectManager::poll_removal`].
+    pub fn remove_objects_tracked(&mut self, object_ids_to_remove: Vec<ObjectID>) -> Result<RemovalHandle, EngineError> {
+        let handle = RemovalHandle(self.next_removal_handle);
+        self.remove_objects_with_removal(object_ids_to_remove, Some(handle))?;
+        self.next_removal_handle += 1;
+        self.removals.insert(handle);
+        Ok(handle)
+    }
+
+    /// A freed removal is forgotten once it has been returned, so the next poll of it returns None.
+    pub fn poll_removal(&mut self, handle: RemovalHandle) -> Option<RemovalStatus> {
+        if !self.removals.contains(&handle) {
+            return None;
+        }
+        if self.data_used_in_shader.values().any(|data_used_in_shader| data_used_in_shader.is_removal_queued(handle)) {
+            return Some(RemovalStatus::Pending);
+        }
+        self.removals.remove(&handle);
+        Some(RemovalStatus::Freed)
+    }
+
+    pub fn destroy_all_objects(&mut self, device: &Device, descriptor_pool: &DescriptorPool, allocator: &VkAllocator) {
+        for (_, data_used_in_shader) in self.data_used_in_shader.drain() {
+            data_used_in_shader.destroy(device, descriptor_pool, &mut self.texture_cache, &mut self.mesh_registry, allocator);
+        }
+        self.data_used_in_shader = HashMap::new();
+        self.pipeline_config_hash_to_pipeline_config = HashMap::new();
+        self.object_id_to_pipeline_hash = HashMap::new();
+        self.pipeline_draw_order = Vec::new();
+        self.submesh_objects = HashMap::new();
+        self.submesh_parents = HashMap::new();
+    }
+
+    /// Returns the draw of every object type that should be drawn, in the order it should be drawn in.
+    /// With [`DrawOrder::InsertionOrder`] this is the order the pipelines and object types were first added,
+    /// with [`DrawOrder::SortKey`] the same order is stable sorted by the keys set with [`ObjectManager::set_draw_order_key`].
+    pub fn get_draws_in_order(&self, current_frame: usize) -> Vec<(&PipelineConfig, DrawBatch)> {
+        let mut draws = Vec::new();
+        for pipeline_hash in self.pipeline_draw_order.iter() {
+            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!");
+            let data_used_in_shader = self.data_used_in_shader.get(pipeline_config).expect("Pipeline config not found in object manager. This should never happen!");
+            draws.extend(data_used_in_shader.draw_batches(current_frame, &self.mesh_registry).map(|draw_batch| (pipeline_config, draw_batch)));
+        }
+
+        if self.draw_order == DrawOrder::SortKey {
+            // The sort is stable, so object types with the same key keep their insertion order
+            draws.sort_by_key(|(_, draw_batch)| self.draw_order_keys.get(&draw_batch.object_type).copied().unwrap_or(0));
+        }
+
+        draws
+    }
+
+    /// The same draws as [`ObjectManager::get_draws_in_order`], but only with the instances of the given visible objects. Each run of instances that come after each other is its own draw.
+    pub fn get_draws_of_objects(&self, object_ids: &[ObjectID], current_frame: usize) -> Vec<(&PipelineConfig, DrawBatch)> {
+        self.get_indexed_draws_of_objects(object_ids, current_frame).into_iter().map(|(_, pipeline_config, draw_batch)| (pipeline_config, draw_batch)).collect()
+    }
+
+    /// Like [`ObjectManager::get_draws_of_objects`], with the index in [`ObjectManager::get_draws_in_order`] of the draw each one is part of.
+    pub fn get_indexed_draws_of_objects(&self, object_ids: &[ObjectID], current_frame: usize) -> Vec<(usize, &PipelineConfig, DrawBatch)> {
+        let object_ids = self.with_submesh_objects(object_ids.iter().copied()).into_iter().collect::<HashSet<_>>();
+        self.get_draws_in_order(current_frame).into_iter().enumerate().flat_map(|(draw_index, (pipeline_config, draw_batch))| {
+            let data_used_in_shader = self.data_used_in_shader.get(pipeline_config).expect("Pipeline config not found in object manager. This should never happen!");
+            // The runs of the objects are inside the runs of all the visible objects, so each one belongs to exactly one of the draws
+            let draw_instances = draw_batch.first_instance..draw_batch.first_instance + draw_batch.num_instances;
+            data_used_in_shader.get_instance_runs(draw_batch.object_type, |object_id| object_ids.contains(object_id)).into_iter().filter(move |(first_instance, _)| draw_instances.contains(first_instance)).map(move |(first_instance, num_instances)| (draw_index, pipeline_config, DrawBatch { first_instance, num_instances, ..draw_batch }))
+        }).collect()
+    }
+
+    pub fn set_draw_order(&mut self, draw_order: DrawOrder) {
+        self.draw_order = draw_order;
+    }
+
+    pub fn set_draw_order_key(&mut self, object_type: ObjectType, key: i32) {
+        self.draw_order_keys.insert(object_type, key);
+    }
+
+    pub fn generate_currently_unused_ids(&self, num_ids: usize) -> Result<Vec<ObjectID>, EngineError> {
+        let mut ids = Vec::with_capacity(num_ids);
+        for _ in 0..num_ids {
+            let mut object_id = rand::random::<usize>();
+            let mut counter = 0;
+            while self.object_id_to_pipeline_hash.contains_key(&ObjectID(object_id)) {
+                object_id = rand::random::<usize>();
+                counter += 1;
+                if counter > 1000 {
+                    return Err("Failed to generate a unique object ID!".into());
+                }
+            }
+            ids.push(ObjectID(object_id));
+        }
+        Ok(ids)
+    }
+
+    /// Returns the Vulkan object type, raw handle and a description of every resource used to render the object, or None if the object has not been added.
+    pub fn get_object_debug_handles(&self, object_id: ObjectID) -> Option<Vec<(vk::ObjectType, u64, String)>> {
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_object_debug_handles(object_id, &self.mesh_registry)
+    }
+
+    // The type resources of an object type that has already been added, from its reference object
+    fn get_type_resources(&self, object_type: ObjectType) -> Option<TypeResources> {
+        let pipeline_hash = self.object_type_to_pipeline_hash.get(&object_type)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        let data_used_in_shader = self.data_used_in_shader.get(pipeline_config)?;
+        let reference_id = data_used_in_shader.object_type_references.get(&object_type)?;
+        Some(data_used_in_shader.objects.get(&reference_id.0)?.1.get_type_resources())
+    }
+
+    pub fn set_object_visible(&mut self, object_id: ObjectID, is_visible: bool) -> Result<(), EngineError> {
+        if !self.object_id_to_pipeline_hash.contains_key(&object_id) {
+            return Err(EngineError::ObjectNotFound(object_id));
+        }
+        for object_id in self.with_submesh_objects([object_id]) {
+            let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id).expect("Submesh object not found in object manager. This should never happen!");
+            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!");
+            self.data_used_in_shader.get_mut(pipeline_config).expect("Pipeline config not found in object manager. This should never happen!").set_object_visible(object_id, is_visible);
+        }
+        Ok(())
+    }
+
+    pub fn is_object_visible(&self, object_id: ObjectID) -> Option<bool> {
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.is_object_visible(object_id)
+    }
+
+    pub fn mark_instance_dirty(&mut self, object_id: ObjectID) -> Result<(), EngineError> {
+        if !self.object_id_to_pipeline_hash.contains_key(&object_id) {
+            return Err(EngineError::ObjectNotFound(object_id));
+        }
+        // The submeshes share the instance resources of the object, so they all have to be uploaded again
+        for object_id in self.with_submesh_objects([object_id]) {
+            let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id).expect("Submesh object not found in object manager. This should never happen!");
+            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!");
+            self.data_used_in_shader.get_mut(pipeline_config).expect("Pipeline config not found in object manager. This should never happen!").mark_instance_dirty(object_id);
+        }
+        Ok(())
+    }
+
+    pub fn set_partial_instance_upload(&mut self, is_partial_instance_upload_enabled: bool) {
+        self.is_partial_instance_upload_enabled = is_partial_instance_upload_enabled;
+    }
+
+    pub fn is_partial_instance_upload_enabled(&self) -> bool {
+        self.is_partial_instance_upload_enabled
+    }
+
+    /// Only changes the object types of the objects that are added after it.
+    pub fn set_unique_object_types(&mut self, are_object_types_unique: bool) {
+        self.are_object_types_unique = are_object_types_unique;
+    }
+
+    pub fn are_object_types_unique(&self) -> bool {
+        self.are_object_types_unique
+    }
+
+    /// The object type the object gets when it is added now.
+    pub fn get_object_type(&self, object: &dyn Renderable) -> ObjectType {
+        if self.are_object_types_unique {
+            return ObjectType::of_mesh_and_key(object.get_vertices_and_indices_hash(), UniqueTypeRenderable::get_material_key_of(object));
+        }
+        ObjectType::of(object)
+    }
+
+    pub fn get_instance_slot(&self, object_id: ObjectID) -> Option<u32> {
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_instance_slot(object_id)
+    }
+
+    #[cfg(test)]
+    pub fn get_instance_data(&self, object_id: ObjectID, resource_id: ResourceID) -> Option<&[u8]> {
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_instance_data(object_id, resource_id)
+    }
+
+    #[cfg(test)]
+    pub fn get_descriptor_sets(&self, object_id: ObjectID) -> Option<&[DescriptorSet]> {
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_descriptor_sets(object_id)
+    }
+
+    // Adds the objects to a new pipeline the way add_objects does, without creating anything on the GPU
+    #[cfg(test)]
+    fn add_objects_without_gpu(&mut self, pipeline_config: PipelineConfig, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>) {
+        let mut hasher = DefaultHasher::new();
+        pipeline_config.hash(&mut hasher);
+        let pipeline_hash = hasher.finish();
+        for (object_id, object) in objects_to_add.iter() {
+            self.object_id_to_pipeline_hash.insert(*object_id, pipeline_hash);
+            self.object_type_to_pipeline_hash.insert(ObjectType::of(object.as_ref()), pipeline_hash);
+        }
+        let data_used_in_shader = DataUsedInShader::without_gpu(objects_to_add, &mut self.mesh_registry, self.frames_in_flight);
+        assert!(self.data_used_in_shader.insert(pipeline_config.clone(), data_used_in_shader).is_none(), "The objects are only added to new pipelines");
+        self.pipeline_config_hash_to_pipeline_config.insert(pipeline_hash, pipeline_config);
+        self.pipeline_draw_order.push(pipeline_hash);
+    }
+
+    /// The samplers of the textures that were freed since the last call, which the caller releases in the sampler manager.
+    pub fn take_released_samplers(&mut self) -> Vec<Sampler> {
+        self.texture_cache.take_released_samplers()
+    }
+
+    pub fn get_texture_cache_stats(&self) -> TextureCacheStats {
+        self.texture_cache.get_stats()
+    }
+
+    /// The textures of the object types are uploaded again with the new settings before the next frame.
+    pub fn set_texture_quality(&mut self, quality: TextureQuality, max_mip_levels: u32) {
+        let max_mip_levels = max_mip_levels.max(1);
+        if self.texture_cache.quality == quality && self.texture_cache.max_mip_levels == max_mip_levels {
+            return;
+        }
+        self.texture_cache.quality = quality;
+        self.texture_cache.max_mip_levels = max_mip_levels;
+        self.are_textures_outdated = true;
+    }
+
+    pub fn get_texture_quality(&self) -> (TextureQuality, u32) {
+        (self.texture_cache.quality, self.texture_cache.max_mip_levels)
+    }
+
+    /// Uploads the textures again if the texture quality has changed since they were uploaded, or a missing or loading texture has an image now. The old textures and descriptor sets are freed when the frames in flight are done with them.
+    pub fn reupload_outdated_textures(&mut self, pipeline_manager: &PipelineManager, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, allocator: &VkAllocator) -> Result<(), EngineError> {
+        if !self.are_textures_outdated {
+            return Ok(());
+        }
+        // Not retried every frame when it fails
+        self.are_textures_outdated = false;
+        for (pipeline_config, data_used_in_shader) in self.data_used_in_shader.iter_mut() {
+            data_used_in_shader.reupload_textures(pipeline_config, device, instance, physical_device, command_pool, descriptor_pool, graphics_queue, sampler_manager, &mut self.texture_cache, allocator)?;
+        }
+        self.refresh_resource_errors(pipeline_manager);
+        self.are_debug_names_outdated = true;
+        Ok(())
+    }
+
+    /// The vertex and index buffers, which are read through their handles when drawing, so they can be moved without updating any descriptor sets.
+    /// Gives the buffers and textures of prepared objects to the mesh registry and the texture cache without references, so adding the objects acquires them instead of uploading them.
+    /// Returns the ones that are already there, which the caller frees.
+    pub fn insert_prepared_resources(&mut self, meshes: Vec<(VerticesIndicesHash, PreparedMeshBuffers)>, textures: Vec<PreparedTexture>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, sampler_manager: &mut SamplerManager, allocator: &VkAllocator) -> Vec<AllocationInfo> {
+        let mut allocations_to_free = Vec::new();
+        for (mesh, buffers) in meshes {
+            allocations_to_free.extend(self.mesh_registry.insert_prepared(mesh, buffers));
+        }
+        for texture in textures {
+            match sampler_manager.get_or_create_sampler(device, instance, physical_device, TextureCache::get_sampler_config(&texture.allocation), allocator) {
+                Ok(sampler) => allocations_to_free.extend(self.texture_cache.insert_prepared(texture, sampler)),
+                Err(e) => {
+                    log::warn!(target: logging::OBJECTS, "Failed to create the sampler of a prepared texture, so it is uploaded again when it's used: {}", e);
+                    allocations_to_free.push(texture.allocation);
+                },
+            }
+        }
+        allocations_to_free
+    }
+
+    /// Takes back the prepared meshes and textures that no object type ended up using, so that the caller can free them.
+    pub fn release_unused_prepared_resources(&mut self, meshes: &[VerticesIndicesHash], texture_keys: &[(TextureCacheKey, TextureQuality, u32)]) -> Vec<AllocationInfo> {
+        let mut allocations = self.mesh_registry.release_unused(meshes);
+        allocations.extend(self.texture_cache.release_unused(texture_keys));
+        allocations
+    }
+
+    pub fn get_geometry_allocations_mut(&mut self) -> Vec<&mut AllocationInfo> {
+        self.mesh_registry.get_allocations_mut()
+    }
+
+    pub fn get_object_type_report(&self, object_type: ObjectType) -> Option<ObjectTypeReport> {
+        let pipeline_hash = self.object_type_to_pipeline_hash.get(&object_type)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_object_type_report(object_type, &self.mesh_registry)
+    }
+
+    pub fn get_num_instances(&self, object_type: ObjectType) -> Option<usize> {
+        self.data_used_in_shader.values().find_map(|data_used_in_shader| data_used_in_shader.get_num_instances(object_type))
+    }
+
+    /// Returns the closest object the ray hits and how far along `direction` the hit is, or None when no object is hit.
+    pub fn raycast(&self, origin: &glm::Vec3, direction: &glm::Vec3) -> Option<(ObjectID, f32)> {
+        let (object_id, distance) = self.data_used_in_shader.values().filter_map(|data_used_in_shader| data_used_in_shader.raycast(origin, direction, &self.mesh_registry)).min_by(|a, b| a.1.total_cmp(&b.1))?;
+        Some((self.get_submesh_parent(object_id), distance))
+    }
+
+    // The object a submesh was added for, or the object itself when it isn't a submesh after the first one
+    fn get_submesh_parent(&self, object_id: ObjectID) -> ObjectID {
+        self.submesh_parents.get(&object_id).copied().unwrap_or(object_id)
+    }
+
+    pub fn get_object_id_at_instance(&self, object_type: ObjectType, instance_index: usize) -> Option<ObjectID> {
+        let pipeline_hash = self.object_type_to_pipeline_hash.get(&object_type)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_object_id_at_instance(object_type, instance_index).map(|object_id| self.get_submesh_parent(object_id))
+    }
+
+    /// The total size in bytes of the vertex and index data of all the meshes. A mesh used by several object types is only counted once.
+    pub fn get_geometry_buffer_sizes(&self) -> (usize, usize) {
+        self.mesh_registry.get_buffer_sizes()
+    }
+
+    // The meshes are shared between the pipelines, so this can be fewer than the object types
+    #[cfg(test)]
+    pub fn get_num_meshes(&self) -> usize {
+        self.mesh_registry.get_num_meshes()
+    }
+
+    /// The objects have to be added already. All the levels except the first one are hidden until the levels are selected for the next frame.
+    /// `switch_distances` has one distance less than there are levels, or as many when the group isn't drawn at all past the last one.
+    pub fn add_lod_group(&mut self, object_ids: Vec<ObjectID>, switch_distances: Vec<f32>, hysteresis: f32) -> Result<(), EngineError> {
+        if let Some(object_id) = object_ids.iter().find(|object_id| self.lod_groups.iter().any(|lod_group| lod_group.object_ids.contains(object_id))) {
+            return Err(EngineError::from(format!("The object {:?} is already a level of another LOD group", object_id)));
+        }
+        for object_id in object_ids.iter() {
+            if !self.object_id_to_pipeline_hash.contains_key(object_id) {
+                return Err(EngineError::from(format!("The object {:?} of the LOD group has not been added", object_id)));
+            }
+        }
+        for object_id in object_ids.iter().skip(1) {
+            self.set_object_visible(*object_id, false)?;
+        }
+        self.lod_groups.push(LodGroupState {
+            object_ids,
+            switch_distances,
+            hysteresis,
+            current_level: 0,
+        });
+        Ok(())
+    }
+
+    pub fn set_forced_lod_level(&mut self, forced_lod_level: Option<usize>) {
+        self.forced_lod_level = forced_lod_level;
+    }
+
+    pub fn set_mesh_validation(&mut self, is_mesh_validation_enabled: bool) {
+        self.is_mesh_validation_enabled = is_mesh_validation_enabled;
+    }
+
+    pub fn is_mesh_validation_enabled(&self) -> bool {
+        self.is_mesh_validation_enabled
+    }
+
+    /// The default debug names of the Vulkan resources the objects use, when resources have been created since the last call.
+    /// Names set with [`crate::vk_controller::VkController::set_object_debug_name`] are overwritten when their resources are created again.
+    pub fn take_outdated_debug_names(&mut self) -> Option<Vec<(vk::ObjectType, u64, String)>> {
+        if !std::mem::take(&mut self.are_debug_names_outdated) {
+            return None;
+        }
+        let mut names = Vec::new();
+        for mesh in self.mesh_registry.meshes.keys() {
+            names.extend(self.mesh_registry.get_debug_handles(*mesh).into_iter().map(|(vk_object_type, handle, description)| (vk_object_type, handle, format!("Mesh({}) {}", mesh.0, description))));
+        }
+        for pipeline_hash in self.pipeline_draw_order.iter() {
+            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!");
+            let data_used_in_shader = self.data_used_in_shader.get(pipeline_config).expect("Pipeline config not found in object manager. This should never happen!");
+            for object_type in data_used_in_shader.object_type_draw_order.iter() {
+                names.extend(data_used_in_shader.get_object_type_debug_handles(*object_type).into_iter().map(|(vk_object_type, handle, description)| (vk_object_type, handle, format!("ObjectType({}) {}", object_type.0.0, description))));
+            }
+        }
+        Some(names)
+    }
+
+    pub fn get_resource_errors(&self) -> &[ResourceError] {
+        &self.resource_errors
+    }
+
+    /// The textures of the added object types that are drawn with the placeholder while their images load.
+    pub fn get_num_pending_texture_loads(&self) -> usize {
+        self.data_used_in_shader.values().map(|data_used_in_shader| data_used_in_shader.fallback_textures.values().filter(|fallback_texture| **fallback_texture == FallbackTexture::Loading).count()).sum()
+    }
+
+    /// Tries the failed pipelines again when their shaders have changed, and uploads the textures again before the next frame when a texture that was missing or loading has an image now.
+    pub fn update_fallback_resources(&mut self, pipeline_manager: &mut PipelineManager, device: &Device, swapchain_extent: &Extent2D, allocator: &VkAllocator) {
+        let num_fixed_pipelines = pipeline_manager.retry_failed_pipelines(device, swapchain_extent, allocator);
+        if self.data_used_in_shader.values().any(|data_used_in_shader| data_used_in_shader.has_fixed_fallback_texture()) {
+            self.are_textures_outdated = true;
+        }
+        if num_fixed_pipelines > 0 {
+            self.refresh_resource_errors(pipeline_manager);
+        }
+    }
+
+    fn refresh_resource_errors(&mut self, pipeline_manager: &PipelineManager) {
+        self.resource_errors.clear();
+        for pipeline_hash in self.pipeline_draw_order.iter() {
+            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!");
+            let data_used_in_shader = self.data_used_in_shader.get(pipeline_config).expect("Pipeline config not found in object manager. This should never happen!");
+            if let Some(reason) = pipeline_manager.get_pipeline_error(pipeline_config) {
+                self.resource_errors.extend(data_used_in_shader.object_type_draw_order.iter().map(|object_type| ResourceError::FailedPipeline {
+                    object_type: object_type.0,
+                    shaders: pipeline_config.get_shader_paths(),
+                    reason: reason.to_string(),
+                }));
+            }
+            // The textures that are still loading aren't errors
+            let mut fallback_textures = data_used_in_shader.fallback_textures.iter().filter_map(|(key, fallback_texture)| match fallback_texture {
+                FallbackTexture::Missing(reason) => Some((key, reason)),
+                FallbackTexture::Loading => None,
+            }).collect::<Vec<_>>();
+            fallback_textures.sort_by_key(|((object_type, resource_id), _)| (data_used_in_shader.object_type_draw_order.iter().position(|x| x == object_type), *resource_id));
+            self.resource_errors.extend(fallback_textures.into_iter().map(|((object_type, resource_id), reason)| ResourceError::MissingTexture {
+                object_type: object_type.0,
+                resource_id: *resource_id,
+                reason: reason.to_string(),
+            }));
+        }
+    }
+
+    /// Compares the bytes of the uniform and storage buffers with the sizes of their blocks in the shaders.
+    /// A uniform block may be given padded up to a multiple of 16 bytes, and each instance has to give one element of a storage block that ends with an array without a length.
+    pub fn validate_buffer_sizes(object: &dyn Renderable, type_resources: &[(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)], pipeline_config: &PipelineConfig) -> Result<(), EngineError> {
+        let binding_sizes = pipeline_config.get_buffer_binding_sizes();
+        if binding_sizes.is_empty() {
+            return Ok(());
         }
-        
-        for (pipeline_config, objects_with_pipeline_to_add) in pipeline_objects {
-            let mut hasher = DefaultHasher::new();
-            pipeline_config.hash(&mut hasher);
-            let pipeline_hash = hasher.finish();
+        let check_block_size = |resource_id: ResourceID, binding: u32, num_bytes: usize, is_instance_element: bool| {
+            let Some(expected) = binding_sizes.get(&binding) else {
+                return Ok(());
+            };
+            let num_bytes = num_bytes as u64;
+            match expected.runtime_array_stride {
+                Some(stride) if is_instance_element => if num_bytes != stride {
+                    return Err(EngineError::from(format!("Resource {:?} at binding {} is {} bytes per instance, but an element of the array in the shader is {} bytes", resource_id, binding, num_bytes, stride)));
+                },
+                _ => if num_bytes < expected.size || num_bytes > expected.size.next_multiple_of(16) {
+                    return Err(EngineError::from(format!("Resource {:?} at binding {} is {} bytes, but the block in the shader is {} bytes", resource_id, binding, num_bytes, expected.size)));
+                },
+            }
+            Ok(())
+        };
 
-            let object_ids = objects_with_pipeline_to_add.iter().map(|(id, _)| *id).collect::<Vec<_>>();
-            if let Entry::Occupied(mut data_used_in_shader) = self.data_used_in_shader.entry(pipeline_config.clone()) {
-                data_used_in_shader.get_mut().add_objects(&pipeline_config, objects_with_pipeline_to_add, device, instance, physical_device, command_pool, descriptor_pool, graphics_queue, sampler_manager, current_frame, allocator)?;
-            } else {
-                let data_used_in_shader = DataUsedInShader::new(&pipeline_config, objects_with_pipeline_to_add, device, instance, physical_device, command_pool, descriptor_pool, graphics_queue, sampler_manager, current_frame, allocator)?;
-                self.data_used_in_shader.insert(pipeline_config.clone(), data_used_in_shader);
-                self.pipeline_config_hash_to_pipeline_config.insert(pipeline_hash, pipeline_config.clone());
+        for (resource_id, resource) in type_resources {
+            let resource = resource.read().unwrap_or_else(PoisonError::into_inner);
+            let layout_binding = resource.get_descriptor_set_layout_binding();
+            // Only buffers are read, so the textures aren't copied just to be skipped
+            if layout_binding.descriptor_type != DescriptorType::UNIFORM_BUFFER {
+                continue;
+            }
+            if let ObjectTypeGraphicsResourceType::UniformBuffer(bytes) = resource.get_resource() {
+                check_block_size(*resource_id, layout_binding.binding, bytes.len(), false)?;
+            }
+        }
+        for (resource_id, resource) in object.get_object_instance_resources() {
+            let resource = resource.read().unwrap_or_else(PoisonError::into_inner);
+            let binding = resource.get_descriptor_set_layout_binding().binding;
+            match resource.get_resource() {
+                ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(bytes) => check_block_size(resource_id, binding, bytes.len(), true)?,
+                ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(bytes) => check_block_size(resource_id, binding, bytes.len(), false)?,
             }
-            object_ids.iter().for_each(|id| {
-                self.object_id_to_pipeline_hash.insert(*id, pipeline_hash);
-            });
         }
-
-
-        object_type_to_pipeline.iter().for_each(|(object_type, pipeline_config)| {
-            let mut hasher = DefaultHasher::new();
-            pipeline_config.hash(&mut hasher);
-            let pipeline_hash = hasher.finish();
-            self.object_type_to_pipeline_hash.insert(object_type.clone(), pipeline_hash);
-        });
         Ok(())
     }
 
-    pub fn remove_objects(&mut self, object_ids_to_remove: Vec<ObjectID>, command_pool: &vk::CommandPool, graphics_queue: &Queue, current_frame: usize, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
-        let mut pipeline_objects: HashMap<PipelineConfig, Vec<ObjectID>> = HashMap::new();
-        for id in object_ids_to_remove {
-            let pipeline_hash = self.object_id_to_pipeline_hash.get(&id).expect("Object id not found in object manager. This should never happen!").clone();
-            let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(&pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!").clone();
-            let e = pipeline_objects.entry(pipeline_config).or_insert_with(Vec::new);
-            e.push(id);
+    // The position is the float attribute at location 0, the other attributes can't be checked without knowing what they are
+    pub fn validate_mesh(object: &dyn Renderable) -> Result<(), EngineError> {
+        let vertex_data = object.get_vertex_byte_data();
+        let indices = object.get_indices();
+        if vertex_data.is_empty() || indices.is_empty() {
+            return Err(EngineError::from("It has no vertices or no indices"));
         }
 
-        for (pipeline_config, object_ids_to_remove) in pipeline_objects {
-            if let Entry::Occupied(mut data_used_in_shader) = self.data_used_in_shader.entry(pipeline_config.clone()) {
-                data_used_in_shader.get_mut().remove_objects(object_ids_to_remove, command_pool, graphics_queue, current_frame, allocator)?;
-            } else {
-                eprintln!("Could not remove objects with ids {:?}. Because it could not find any data used for the shaders with the pipeline config for the following shaders {:?}", object_ids_to_remove, pipeline_config.get_shader_paths());
+        let stride = object.get_vertex_binding_info().stride as usize;
+        if stride == 0 || !vertex_data.len().is_multiple_of(stride) {
+            return Err(EngineError::from(format!("The vertex data is {} bytes, which is not a multiple of the vertex stride {}", vertex_data.len(), stride)));
+        }
+        let vertex_count = vertex_data.len() / stride;
+        if let Some((i, index)) = indices.iter().enumerate().find(|(_, &index)| index as usize >= vertex_count) {
+            return Err(EngineError::from(format!("Index {} is {}, but there are only {} vertices", i, index, vertex_count)));
+        }
+
+        let position_attribute = object.get_vertex_attribute_descriptions().into_iter().find(|attribute| attribute.location == 0);
+        let num_components = match position_attribute.map(|attribute| attribute.format) {
+            Some(vk::Format::R32_SFLOAT) => 1,
+            Some(vk::Format::R32G32_SFLOAT) => 2,
+            Some(vk::Format::R32G32B32_SFLOAT) => 3,
+            Some(vk::Format::R32G32B32A32_SFLOAT) => 4,
+            _ => return Ok(()),
+        };
+        let offset = position_attribute.unwrap().offset as usize;
+        for (i, vertex) in vertex_data.chunks_exact(stride).enumerate() {
+            let Some(position_data) = vertex.get(offset..offset + num_components * std::mem::size_of::<f32>()) else {
+                return Err(EngineError::from(format!("The position at offset {} doesn't fit in the vertex stride {}", offset, stride)));
+            };
+            let position = position_data.chunks_exact(std::mem::size_of::<f32>()).map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap())).collect::<Vec<_>>();
+            if position.iter().any(|component| !component.is_finite()) {
+                return Err(EngineError::from(format!("Vertex {} has the position {:?}, which is not finite", i, position)));
             }
         }
+        Ok(())
+    }
 
+    /// Replaces the sprite's current animation and starts the new one from its first frame.
+    pub fn set_sprite_animation(&mut self, object_id: ObjectID, animation: SpriteAnimation) -> Result<(), EngineError> {
+        animation.validate()?;
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id).ok_or(EngineError::ObjectNotFound(object_id))?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash).expect("Pipeline hash not found in object manager. This should never happen!");
+        let instance_data = self.data_used_in_shader.get(pipeline_config).and_then(|data_used_in_shader| data_used_in_shader.get_sprite_instance_data(object_id))
+            .ok_or(EngineError::from(format!("The object with id {:?} is not a sprite", object_id)))?;
+        let mut sprite_animation = SpriteAnimationState {
+            instance_data,
+            animation,
+            elapsed: 0.0,
+            is_paused: false,
+            is_finished: false,
+        };
+        sprite_animation.advance(0.0);
+        self.sprite_animations.insert(object_id, sprite_animation);
         Ok(())
     }
-    
-    pub fn destroy_all_objects(&mut self, device: &Device, descriptor_pool: &DescriptorPool, allocator: &mut VkAllocator) {
-        for (_, data_used_in_shader) in self.data_used_in_shader.drain() {
-            data_used_in_shader.destroy(device, descriptor_pool, allocator);
-        }
-        self.data_used_in_shader = HashMap::new();
-        self.pipeline_config_hash_to_pipeline_config = HashMap::new();
-        self.object_id_to_pipeline_hash = HashMap::new();
+
+    pub fn set_sprite_animation_paused(&mut self, object_id: ObjectID, is_paused: bool) -> Result<(), EngineError> {
+        let sprite_animation = self.sprite_animations.get_mut(&object_id).ok_or(EngineError::from(format!("The object with id {:?} has no sprite animation", object_id)))?;
+        sprite_animation.is_paused = is_paused;
+        Ok(())
     }
 
-    pub fn borrow_objects_to_render(&self) -> &HashMap<PipelineConfig, DataUsedInShader> {
-        &self.data_used_in_shader
+    /// None when the object has no sprite animation. Looping animations never finish.
+    pub fn is_sprite_animation_finished(&self, object_id: ObjectID) -> Option<bool> {
+        self.sprite_animations.get(&object_id).map(|sprite_animation| sprite_animation.is_finished)
     }
 
-    pub fn generate_currently_unused_ids(&self, num_ids: usize) -> Result<Vec<ObjectID>, Cow<'static, str>> {
-        let mut ids = Vec::with_capacity(num_ids);
-        for _ in 0..num_ids {
-            let mut object_id = rand::random::<usize>();
-            let mut counter = 0;
-            while self.object_id_to_pipeline_hash.contains_key(&ObjectID(object_id)) {
-                object_id = rand::random::<usize>();
-                counter += 1;
-                if counter > 1000 {
-                    return Err("Failed to generate a unique object ID!".into());
+    /// None when the object has no sprite animation.
+    pub fn get_sprite_animation_frame(&self, object_id: ObjectID) -> Option<usize> {
+        self.sprite_animations.get(&object_id).map(|sprite_animation| sprite_animation.animation.get_frame_index(sprite_animation.elapsed).0)
+    }
+
+    fn update_sprite_animations(&mut self, delta_time: f32) {
+        self.sprite_animations.values_mut().for_each(|sprite_animation| sprite_animation.advance(delta_time));
+    }
+
+    // Only the visibility changes, so the instances of the affected object types are ordered again once before the storage buffers are copied
+    fn update_lod_levels(&mut self, camera_position: &glm::Vec3) {
+        let mut lod_groups = std::mem::take(&mut self.lod_groups);
+        for lod_group in lod_groups.iter_mut() {
+            let level = match self.forced_lod_level {
+                Some(forced_lod_level) => forced_lod_level.min(lod_group.object_ids.len() - 1),
+                None => {
+                    // The levels are moved together, so any of them gives the position also when none is drawn
+                    let Some(position) = self.get_object_position(lod_group.object_ids[lod_group.current_level.min(lod_group.object_ids.len() - 1)]) else {
+                        continue;
+                    };
+                    lod_group.select_level(glm::distance(camera_position, &position))
+                },
+            };
+            if level != lod_group.current_level {
+                if let Some(object_id) = lod_group.object_ids.get(lod_group.current_level) {
+                    self.set_object_visible(*object_id, false).unwrap();
                 }
+                if let Some(object_id) = lod_group.object_ids.get(level) {
+                    self.set_object_visible(*object_id, true).unwrap();
+                }
+                lod_group.current_level = level;
             }
-            ids.push(ObjectID(object_id));
         }
-        Ok(ids)
+        self.lod_groups = lod_groups;
+    }
+
+    fn get_object_position(&self, object_id: ObjectID) -> Option<glm::Vec3> {
+        let pipeline_hash = self.object_id_to_pipeline_hash.get(&object_id)?;
+        let pipeline_config = self.pipeline_config_hash_to_pipeline_config.get(pipeline_hash)?;
+        self.data_used_in_shader.get(pipeline_config)?.get_object_position(object_id)
     }
 
-    pub fn update_objects(&mut self, device: &Device,descriptor_pool: &DescriptorPool, current_frame: usize, allocator: &mut VkAllocator) {
+    /// `frame_index` is the number of the frame from [`crate::vk_controller::Time::frame_index`], which unlike `current_frame` changes every frame, also with a single frame in flight.
+    pub fn update_objects(&mut self, device: &Device,descriptor_pool: &DescriptorPool, render_target_textures: &HashMap<RenderTargetId, (vk::ImageView, Sampler)>, camera_position: &glm::Vec3, delta_time: f32, current_frame: usize, frame_index: u64, allocator: &VkAllocator) {
+        self.update_lod_levels(camera_position);
+        self.update_sprite_animations(delta_time);
+        if self.is_partial_instance_upload_enabled {
+            // The animations write the instance data of their sprites every frame
+            let animated_object_ids = self.sprite_animations.keys().copied().collect::<Vec<_>>();
+            for object_id in animated_object_ids {
+                let _ = self.mark_instance_dirty(object_id);
+            }
+        }
+        // The GPU copies below are made from the snapshot, so they don't take the resource locks. A poisoned lock keeps the data of the last snapshot
+        let is_partial_instance_upload_enabled = self.is_partial_instance_upload_enabled;
+        self.data_used_in_shader.values_mut().for_each(|data_used_in_shader| data_used_in_shader.snapshot_resource_data(is_partial_instance_upload_enabled));
+        self.data_version += 1;
         self.data_used_in_shader.iter_mut().for_each(|(_, data_used_in_shader)| {
-            data_used_in_shader.update(device, descriptor_pool, current_frame, allocator)
+            data_used_in_shader.update(device, render_target_textures, is_partial_instance_upload_enabled, current_frame);
+            data_used_in_shader.update_allocation_to_remove_counter_and_free_allocations_that_are_not_used(device, descriptor_pool, frame_index, allocator);
         });
     }
 
+    pub fn get_data_version(&self) -> RenderableDataVersion {
+        RenderableDataVersion(self.data_version)
+    }
+
+    pub fn is_render_target_used(&self, render_target_id: RenderTargetId) -> bool {
+        self.data_used_in_shader.values().any(|data_used_in_shader| data_used_in_shader.is_render_target_used(render_target_id))
+    }
+
     fn get_object_types(&self) -> HashSet<ObjectType> {
         self.data_used_in_shader.iter().map(|(_, data_used_in_shader)| data_used_in_shader.get_object_types()).flatten().collect()
     }
 
 }
 
+/// The instance slots of one object type. The slot of an object is its `gl_InstanceIndex`, and it doesn't change while the object exists.
+/// The slots of removed objects are reused first, lowest first, so new slots are only added at the end when there are no free ones.
+#[derive(Clone, Default)]
+struct InstanceSlots {
+    objects: BTreeMap<u32, ObjectID>,
+    free_slots: BTreeSet<u32>,
+}
+
+impl InstanceSlots {
+    fn insert(&mut self, object_id: ObjectID) -> u32 {
+        let slot = self.free_slots.pop_first().unwrap_or(self.get_num_slots() as u32);
+        self.objects.insert(slot, object_id);
+        slot
+    }
+
+    fn remove(&mut self, slot: u32) {
+        self.objects.remove(&slot);
+        self.free_slots.insert(slot);
+        // The free slots at the end are dropped, so the draws stop at the last object. The buffers keep their capacity for the next objects
+        while let Some(last_free_slot) = self.free_slots.last().copied() {
+            if last_free_slot as usize + 1 != self.get_num_slots() {
+                break;
+            }
+            self.free_slots.pop_last();
+        }
+    }
+
+    // The instance buffers have room for every slot, including the free ones
+    fn get_num_slots(&self) -> usize {
+        self.objects.len() + self.free_slots.len()
+    }
+}
+
 pub struct DataUsedInShader {
     objects: HashMap<ObjectID, (ObjectType, Box<dyn Renderable>)>,
-    pub object_type_num_instances: HashMap<ObjectType, (NumInstances, NumIndices)>,
-    pub object_type_vertices_bytes_indices: HashMap<ObjectType, (Inclusive, Exclusive)>,
-    pub object_type_indices_bytes_indices: HashMap<ObjectType, (Inclusive, Exclusive)>,
+    object_type_num_instances: HashMap<ObjectType, (NumInstances, NumIndices)>,
+    // The meshes are owned by the mesh registry, which shares them with the object types of the other pipelines
+    object_type_meshes: HashMap<ObjectType, VerticesIndicesHash>,
     object_id_storage_buffer_bytes_indices: HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>,
-    pub vertices: (AllocationInfo, Vec<u8>),
-    pub indices: (AllocationInfo, Vec<u8>),
+    object_slots: HashMap<ObjectID, u32>,
+    object_type_slots: HashMap<ObjectType, InstanceSlots>,
+    // How many slots the instance buffers of the object type have room for. The buffers are only made again when the slots don't fit anymore
+    instance_capacities: HashMap<ObjectType, usize>,
+    // The hidden objects keep their slots, the draws skip them like the free slots
+    hidden_objects: HashSet<ObjectID>,
+    object_type_num_hidden_instances: HashMap<ObjectType, usize>,
     textures: HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>,
-    pub object_type_references: HashMap<ObjectType, ReferenceObjectID>,
+    // The textures that are drawn with the placeholder or the missing texture instead of their own image
+    fallback_textures: HashMap<(ObjectType, ResourceID), FallbackTexture>,
+    // The render targets and the bindings they are sampled from. The render targets own the images, so their descriptors are written before every frame instead of when the descriptor sets are made
+    render_target_bindings: HashMap<(ObjectType, ResourceID), (RenderTargetId, u32)>,
+    object_type_references: HashMap<ObjectType, ReferenceObjectID>,
+    object_type_draw_order: Vec<ObjectType>,
     // TODO: textures_dynamic: Vec<u32>,
     uniform_buffers: HashMap<(ObjectType, ResourceID), AllocationInfo>,
+    // The bytes of the uniform buffers from the last snapshot, which are copied to the GPU
+    uniform_buffer_data: HashMap<(ObjectType, ResourceID), Vec<u8>>,
+    // Also holds the dynamic uniform buffers, which are per-instance data like the storage buffers
     storage_buffers: HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>,
+    // The bytes between the instances in the object type's dynamic uniform buffers
+    dynamic_uniform_buffer_strides: HashMap<ObjectType, usize>,
     descriptor_type_data: Vec<(ResourceID, DescriptorType, DescriptorSetLayoutBinding)>,
-    pub descriptor_sets: HashMap<ObjectType, Vec<DescriptorSet>>,
+    descriptor_sets: HashMap<ObjectType, Vec<DescriptorSet>>,
     allocations_and_descriptor_sets_to_remove: (LastFrameIndex, Vec<(Counter, DataToRemove)>),
+    // The addresses of the resources whose locks were found poisoned, so it is only logged once for each
+    poisoned_resources: HashSet<usize>,
+    // The objects whose instance data has changed, and how many more frames in flight their bytes have to be copied to
+    dirty_objects: HashMap<ObjectID, u32>,
+    // How many more frames in flight need all of the storage buffers copied, since objects were added or removed
+    num_full_upload_frames: u32,
+    // How many copies of the buffers and descriptor sets there are, and how many frames the removed data is kept
+    frames_in_flight: usize,
 }
 
 impl DataUsedInShader {
+    const DYNAMIC_UNIFORM_BUFFER_ALIGNMENT: usize = 256;
 
-    fn new(pipeline_config: &PipelineConfig, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, current_frame: usize, allocator: &mut VkAllocator) -> Result<Self, Cow<'static, str>> {
+    fn new(pipeline_config: &PipelineConfig, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, texture_cache: &mut TextureCache, mesh_registry: &mut MeshRegistry, current_frame: usize, frames_in_flight: usize, allocator: &VkAllocator) -> Result<Self, EngineError> {
         let mut textures = HashMap::new();
         let mut uniform_buffers = HashMap::new();
         let mut storage_uniform_buffers: HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)> = HashMap::new();
+        let mut render_target_bindings = HashMap::new();
+        let mut fallback_textures = HashMap::new();
+        let mut dynamic_uniform_buffer_strides = HashMap::new();
         let mut object_id_storage_buffer_bytes_indices = HashMap::new();
-        let mut object_type_vertices_bytes_indices = HashMap::new();
-        let mut object_type_indices_bytes_indices = HashMap::new();
         let mut descriptor_type_data = Vec::new();
         let mut object_types = HashSet::new();
         let mut objects = HashMap::new();
-        let mut vertices_data = Vec::new();
-        let mut indices_data = Vec::new();
+        let mut poisoned_resources = HashSet::new();
 
         let (object_type_references, object_type_num_instances) = Self::get_object_type_data_and_num_instances(&objects_to_add);
+        let object_type_draw_order = Self::get_object_types_in_insertion_order(&objects_to_add);
+        let mut object_type_slots: HashMap<ObjectType, InstanceSlots> = HashMap::new();
+        let object_slots = objects_to_add.iter().map(|(object_id, object)| (*object_id, object_type_slots.entry(ObjectType::of(object.as_ref())).or_default().insert(*object_id))).collect::<HashMap<_, _>>();
 
         Self::process_descriptor_type_data(&objects_to_add, &mut descriptor_type_data);
 
-        Self::process_object_types(&objects_to_add, &object_type_num_instances, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, &mut object_id_storage_buffer_bytes_indices, &mut object_type_vertices_bytes_indices, &mut object_type_indices_bytes_indices, &mut descriptor_type_data, &mut object_types, &mut vertices_data, &mut indices_data, allocator)?;
+        Self::process_object_types(&objects_to_add, &object_type_num_instances, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, &mut dynamic_uniform_buffer_strides, &mut descriptor_type_data, texture_cache, frames_in_flight, allocator)?;
                 
-        Self::insert_new_objects(objects_to_add, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, &mut object_types, &mut objects, &mut object_type_vertices_bytes_indices, &mut object_type_indices_bytes_indices, &mut vertices_data, &mut indices_data, device, instance, physical_device, command_pool, graphics_queue, sampler_manager, current_frame, allocator)?;
+        Self::insert_new_objects(objects_to_add, &mut textures, &mut fallback_textures, &mut uniform_buffers, &mut storage_uniform_buffers, &mut render_target_bindings, &mut object_types, &mut objects, device, instance, physical_device, command_pool, graphics_queue, sampler_manager, texture_cache, current_frame, frames_in_flight, allocator)?;
         
-        let all_objects = objects.iter().map(|(id, obj)| (id, obj)).collect::<Vec<_>>(); 
-        Self::create_storage_buffer_byte_indices(&all_objects, &mut object_id_storage_buffer_bytes_indices);
+        Self::create_storage_buffer_byte_indices(objects.iter(), &object_slots, &mut object_id_storage_buffer_bytes_indices);
         
-        Self::copy_storage_buffer_data_to_gpu(&objects, &mut storage_uniform_buffers, &object_id_storage_buffer_bytes_indices, current_frame as usize);
+        Self::copy_storage_buffer_data_to_gpu(objects.iter(), &mut storage_uniform_buffers, &object_id_storage_buffer_bytes_indices, &mut poisoned_resources, current_frame as usize);
         
-        let vertex_allocation = match allocator.create_device_local_buffer(command_pool, graphics_queue, &vertices_data, vk::BufferUsageFlags::VERTEX_BUFFER, false) {
-            Ok(alloc) => alloc,
-            Err(e) => return Err(Cow::from(e)),
-        };
-        let index_allocation = match allocator.create_device_local_buffer(command_pool, graphics_queue, &indices_data, vk::BufferUsageFlags::INDEX_BUFFER, false) {
-            Ok(alloc) => alloc,
-            Err(e) => {
-                let mut error_str = e.to_string();
-                free_allocations_add_error_string!(allocator, vec![vertex_allocation], error_str);
-                return Err(Cow::from(error_str));
-            },
-        };
+        let object_type_meshes = Self::acquire_meshes(object_type_references.iter().map(|(object_type, reference)| (*object_type, objects.get(&reference.0).unwrap().1.as_ref())), mesh_registry, command_pool, graphics_queue, allocator)?;
 
-        let descriptor_sets = Self::create_descriptor_sets(device, descriptor_pool, pipeline_config.borrow_descriptor_set_layout().unwrap(), &object_types, &descriptor_type_data, &uniform_buffers, &textures, &storage_uniform_buffers, VkController::MAX_FRAMES_IN_FLIGHT as u32);
+        let descriptor_sets = Self::create_descriptor_sets(device, descriptor_pool, pipeline_config.borrow_descriptor_set_layout().unwrap(), &object_types, &descriptor_type_data, &uniform_buffers, &textures, &storage_uniform_buffers, &dynamic_uniform_buffer_strides, frames_in_flight as u32)?;
 
         Ok(Self {
             objects,
             object_type_num_instances,
-            object_type_vertices_bytes_indices,
-            object_type_indices_bytes_indices,
+            object_type_meshes,
             object_id_storage_buffer_bytes_indices,
-            vertices: (vertex_allocation, vertices_data),
-            indices: (index_allocation, indices_data),
+            object_slots,
+            instance_capacities: object_type_slots.iter().map(|(object_type, instance_slots)| (*object_type, instance_slots.get_num_slots())).collect(),
+            object_type_slots,
+            hidden_objects: HashSet::new(),
+            object_type_num_hidden_instances: HashMap::new(),
             textures,
+            fallback_textures,
+            render_target_bindings,
             object_type_references,
+            object_type_draw_order,
             uniform_buffers,
+            uniform_buffer_data: HashMap::new(),
             storage_buffers: storage_uniform_buffers,
+            dynamic_uniform_buffer_strides,
             descriptor_type_data,
             descriptor_sets,
-            allocations_and_descriptor_sets_to_remove: (LastFrameIndex(current_frame as usize), Vec::new()),
+            // Nothing is queued yet, so the frame index only has to stop the counters from advancing twice in a frame
+            allocations_and_descriptor_sets_to_remove: (LastFrameIndex(0), Vec::new()),
+            poisoned_resources,
+            dirty_objects: HashMap::new(),
+            num_full_upload_frames: frames_in_flight as u32,
+            frames_in_flight,
         })
     }
 
+    // The objects with their slots and meshes, but without any buffers or textures. Each object type gets a descriptor set handle for every frame in flight
+    #[cfg(test)]
+    fn without_gpu(objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, mesh_registry: &mut MeshRegistry, frames_in_flight: usize) -> Self {
+        let (object_type_references, object_type_num_instances) = Self::get_object_type_data_and_num_instances(&objects_to_add);
+        let object_type_draw_order = Self::get_object_types_in_insertion_order(&objects_to_add);
+        let mut object_type_slots: HashMap<ObjectType, InstanceSlots> = HashMap::new();
+        let object_slots = objects_to_add.iter().map(|(object_id, object)| (*object_id, object_type_slots.entry(ObjectType::of(object.as_ref())).or_default().insert(*object_id))).collect::<HashMap<_, _>>();
+        let objects = objects_to_add.into_iter().map(|(object_id, object)| (object_id, (ObjectType::of(object.as_ref()), object))).collect::<HashMap<_, _>>();
+        let object_type_meshes = object_type_draw_order.iter().map(|object_type| (*object_type, mesh_registry.acquire_without_gpu(objects[&object_type_references[object_type].0].1.as_ref()))).collect();
+        let descriptor_sets = object_type_draw_order.iter().map(|object_type| (*object_type, (0..frames_in_flight).map(|frame| DescriptorSet::from_raw(object_type.get_hash().0.wrapping_add(frame as u64))).collect())).collect();
+
+        Self {
+            objects,
+            object_type_num_instances,
+            object_type_meshes,
+            object_id_storage_buffer_bytes_indices: HashMap::new(),
+            object_slots,
+            instance_capacities: object_type_slots.iter().map(|(object_type, instance_slots)| (*object_type, instance_slots.get_num_slots())).collect(),
+            object_type_slots,
+            hidden_objects: HashSet::new(),
+            object_type_num_hidden_instances: HashMap::new(),
+            textures: HashMap::new(),
+            fallback_textures: HashMap::new(),
+            render_target_bindings: HashMap::new(),
+            object_type_references,
+            object_type_draw_order,
+            uniform_buffers: HashMap::new(),
+            uniform_buffer_data: HashMap::new(),
+            storage_buffers: HashMap::new(),
+            dynamic_uniform_buffer_strides: HashMap::new(),
+            descriptor_type_data: Vec::new(),
+            descriptor_sets,
+            allocations_and_descriptor_sets_to_remove: (LastFrameIndex(0), Vec::new()),
+            poisoned_resources: HashSet::new(),
+            dirty_objects: HashMap::new(),
+            num_full_upload_frames: frames_in_flight as u32,
+            frames_in_flight,
+        }
+    }
+
     fn process_descriptor_type_data(objects_to_add: &[(ObjectID, Box<dyn Renderable>)], descriptor_type_data: &mut Vec<(ResourceID, DescriptorType, DescriptorSetLayoutBinding)>) {
         for (resource_id, resource) in objects_to_add.first().unwrap().1.get_type_resources().iter() {
-            let layout_binding = resource.read().unwrap().get_descriptor_set_layout_binding();
-            match resource.read().unwrap().get_resource() {
-                ObjectTypeGraphicsResourceType::Texture(_) => {
+            let layout_binding = resource.read().unwrap_or_else(PoisonError::into_inner).get_descriptor_set_layout_binding();
+            match resource.read().unwrap_or_else(PoisonError::into_inner).get_resource() {
+                ObjectTypeGraphicsResourceType::Texture(..) | ObjectTypeGraphicsResourceType::CubeMap(..) | ObjectTypeGraphicsResourceType::RenderTarget(_) => {
                     descriptor_type_data.push((*resource_id, DescriptorType::COMBINED_IMAGE_SAMPLER, layout_binding));
                 },
                 ObjectTypeGraphicsResourceType::UniformBuffer(_) => {
@@ -318,14 +1767,14 @@ impl DataUsedInShader {
         }
     }
 
-    fn process_object_types(objects_to_add: &[(ObjectID, Box<dyn Renderable>)], object_type_num_instances: &HashMap<ObjectType, (NumInstances, NumIndices)>, textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, storage_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, object_id_storage_buffer_bytes_indices: &mut HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>, object_type_vertices_bytes_indices: &mut HashMap<ObjectType, (Inclusive, Exclusive)>, object_type_indices_bytes_indices: &mut HashMap<ObjectType, (Inclusive, Exclusive)>, descriptor_type_data: &mut Vec<(ResourceID, DescriptorType, DescriptorSetLayoutBinding)>, object_types: &mut HashSet<ObjectType>, vertices_data: &mut Vec<u8>, indices_data: &mut Vec<u8>, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
+    fn process_object_types(objects_to_add: &[(ObjectID, Box<dyn Renderable>)], object_type_num_instances: &HashMap<ObjectType, (NumInstances, NumIndices)>, textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, storage_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, dynamic_uniform_buffer_strides: &mut HashMap<ObjectType, usize>, descriptor_type_data: &mut Vec<(ResourceID, DescriptorType, DescriptorSetLayoutBinding)>, texture_cache: &mut TextureCache, frames_in_flight: usize, allocator: &VkAllocator) -> Result<(), EngineError> {
         for (object_type, num_instances) in object_type_num_instances.iter() {
-            let (_, object) = objects_to_add.iter().find(|obj| obj.1.get_vertices_and_indices_hash() == object_type.0).unwrap();
+            let (_, object) = objects_to_add.iter().find(|obj| ObjectType::of(obj.1.as_ref()) == *object_type).unwrap();
             for (resource_id, resource) in object.get_object_instance_resources() {
-                let resource_lock = resource.read().unwrap();
+                let resource_lock = resource.read().unwrap_or_else(PoisonError::into_inner);
                 match resource_lock.get_resource() {
                     ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) => {
-                        match Self::create_storage_buffer(*object_type, resource_id, num_instances.0, buffer.clone(), textures, uniform_buffers, storage_uniform_buffers, allocator) {
+                        match Self::create_storage_buffer(*object_type, resource_id, num_instances.0, buffer.clone(), textures, uniform_buffers, storage_uniform_buffers, texture_cache, frames_in_flight, allocator) {
                             Ok(_) => (),
                             Err(e) => return Err(e),
                         }
@@ -334,29 +1783,47 @@ impl DataUsedInShader {
                             descriptor_type_data.push((resource_id, DescriptorType::STORAGE_BUFFER, resource_lock.get_descriptor_set_layout_binding()));
                         }
                     },
+                    ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(_) => {
+                        let stride = Self::get_dynamic_uniform_buffer_stride(object.as_ref());
+                        Self::create_dynamic_uniform_buffer(*object_type, resource_id, num_instances.0, stride, textures, uniform_buffers, storage_uniform_buffers, texture_cache, frames_in_flight, allocator)?;
+                        dynamic_uniform_buffer_strides.insert(*object_type, stride);
+
+                        if !descriptor_type_data.iter().any(|x| x.0 == resource_id) {
+                            descriptor_type_data.push((resource_id, DescriptorType::UNIFORM_BUFFER_DYNAMIC, resource_lock.get_descriptor_set_layout_binding()));
+                        }
+                    },
                 }
             } 
-            Self::add_object_vertices_and_indices_if_new_object_type(*object_type, object, object_type_vertices_bytes_indices, object_type_indices_bytes_indices, vertices_data, indices_data).unwrap();
         }
         Ok(())
     }
 
-    fn insert_new_objects (objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, storage_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, object_types: &mut HashSet<ObjectType>, objects: &mut HashMap<ObjectID, (ObjectType, Box<dyn Renderable>)>, object_type_vertices_bytes_indices: &mut HashMap<ObjectType, (Inclusive, Exclusive)>, object_type_indices_bytes_indices: &mut HashMap<ObjectType, (Inclusive, Exclusive)>, vertices_data: &mut Vec<u8>, indices_data: &mut Vec<u8>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, current_frame: usize, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
+    fn insert_new_objects (objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, fallback_textures: &mut HashMap<(ObjectType, ResourceID), FallbackTexture>, uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, storage_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, render_target_bindings: &mut HashMap<(ObjectType, ResourceID), (RenderTargetId, u32)>, object_types: &mut HashSet<ObjectType>, objects: &mut HashMap<ObjectID, (ObjectType, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, texture_cache: &mut TextureCache, current_frame: usize, frames_in_flight: usize, allocator: &VkAllocator) -> Result<(), EngineError> {
         for object in objects_to_add {
-            let object_type = ObjectType(object.1.get_vertices_and_indices_hash());
+            let object_type = ObjectType::of(object.1.as_ref());
             let newly_added_object_type = object_types.insert(object_type);
             
             if newly_added_object_type {
                 for (resource_id, resource) in object.1.get_type_resources() {
-                    match resource.read().unwrap().get_resource() {
-                        ObjectTypeGraphicsResourceType::Texture(image) => {
-                            match Self::create_and_add_static_texture(object_type, resource_id, image, device, instance, physical_device, command_pool, graphics_queue, textures, uniform_buffers, storage_uniform_buffers, sampler_manager, allocator) {
+                    let resource_lock = resource.read().unwrap_or_else(PoisonError::into_inner);
+                    match resource_lock.get_resource() {
+                        ObjectTypeGraphicsResourceType::Texture(image, asset_key) => {
+                            match Self::create_and_add_static_texture(object_type, resource_id, vec![image], asset_key, resource_lock.get_load_state(), device, instance, physical_device, command_pool, graphics_queue, textures, fallback_textures, uniform_buffers, storage_uniform_buffers, sampler_manager, texture_cache, allocator) {
+                                Ok(_) => (),
+                                Err(e) => return Err(e),
+                            }
+                        },
+                        ObjectTypeGraphicsResourceType::CubeMap(faces, asset_key) => {
+                            match Self::create_and_add_static_texture(object_type, resource_id, faces.to_vec(), asset_key, resource_lock.get_load_state(), device, instance, physical_device, command_pool, graphics_queue, textures, fallback_textures, uniform_buffers, storage_uniform_buffers, sampler_manager, texture_cache, allocator) {
                                 Ok(_) => (),
                                 Err(e) => return Err(e),
                             }
                         },
+                        ObjectTypeGraphicsResourceType::RenderTarget(render_target_id) => {
+                            render_target_bindings.insert((object_type, resource_id), (render_target_id, resource_lock.get_descriptor_set_layout_binding().binding));
+                        },
                     ObjectTypeGraphicsResourceType::UniformBuffer(buffer) => {
-                        match Self::create_and_add_static_uniform_buffer(object_type, resource_id, &buffer, current_frame, textures, uniform_buffers, storage_uniform_buffers, allocator) {
+                        match Self::create_and_add_static_uniform_buffer(object_type, resource_id, &buffer, current_frame, textures, uniform_buffers, storage_uniform_buffers, texture_cache, frames_in_flight, allocator) {
                             Ok(_) => (),
                             Err(e) => return Err(e),
                         }
@@ -370,65 +1837,87 @@ impl DataUsedInShader {
         Ok(())
     }
 
-    fn add_objects(&mut self, pipeline_config: &PipelineConfig, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, current_frame: usize, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
+    fn add_objects(&mut self, pipeline_config: &PipelineConfig, objects_to_add: Vec<(ObjectID, Box<dyn Renderable>)>, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, texture_cache: &mut TextureCache, mesh_registry: &mut MeshRegistry, current_frame: usize, allocator: &VkAllocator) -> Result<(), EngineError> {
+        self.num_full_upload_frames = self.frames_in_flight as u32;
         let mut textures = HashMap::new();
+        let mut fallback_textures = HashMap::new();
         let mut uniform_buffers = HashMap::new();
         let mut storage_uniform_buffers: HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)> = HashMap::new();
         let mut object_id_storage_buffer_bytes_indices = HashMap::new();
-        let mut object_type_vertices_bytes_indices = self.object_type_vertices_bytes_indices.clone();
-        let mut object_type_indices_bytes_indices = self.object_type_indices_bytes_indices.clone();
         let descriptor_type_data = self.descriptor_type_data.clone();
         let mut object_types = HashSet::new();
         let mut new_object_types = HashSet::new();
         let mut new_objects: HashMap<ObjectID, (ObjectType, Box<dyn Renderable>)> = HashMap::new();
-        let mut vertices_data = self.vertices.1.clone();
-        let mut indices_data = self.indices.1.clone();
 
-        let (_, mut object_type_num_instances) = Self::get_object_type_data_and_num_instances(&objects_to_add);
+        let (added_object_type_references, added_object_type_num_instances) = Self::get_object_type_data_and_num_instances(&objects_to_add);
+        let object_types_in_insertion_order = Self::get_object_types_in_insertion_order(&objects_to_add);
 
-        object_type_num_instances.iter_mut().for_each(|(object_type, data)| {
-            *data = self.object_type_num_instances.get(object_type).unwrap().clone();
-        });
+        // The slots are only kept when the objects were added, so they are given out on copies first
+        let mut object_slots = self.object_slots.clone();
+        let mut object_type_slots: HashMap<ObjectType, InstanceSlots> = HashMap::new();
+        for (object_id, object) in objects_to_add.iter() {
+            let object_type = ObjectType::of(object.as_ref());
+            let instance_slots = object_type_slots.entry(object_type).or_insert_with(|| self.object_type_slots.get(&object_type).cloned().unwrap_or_default());
+            object_slots.insert(*object_id, instance_slots.insert(*object_id));
+        }
 
-        for (object_type, (num_instances, _)) in object_type_num_instances.iter() {
-            for (resource_id, resource) in objects_to_add.iter().find(|obj| obj.1.get_vertices_and_indices_hash() == object_type.0).unwrap().1.get_object_instance_resources() {
-                let resource_lock = resource.read().unwrap();
+        // The buffers are kept while the slots fit in them, so the descriptor sets that point at them stay valid. When they don't fit the capacity is at least doubled,
+        // and the object type gets new descriptor sets for the new buffers
+        let mut instance_capacities = HashMap::new();
+        let mut resized_object_types = HashSet::new();
+        for (object_type, instance_slots) in object_type_slots.iter() {
+            let Some(capacity) = Self::get_new_instance_capacity(self.instance_capacities.get(object_type).copied(), instance_slots.get_num_slots()) else {
+                continue;
+            };
+            if self.instance_capacities.contains_key(object_type) {
+                resized_object_types.insert(*object_type);
+            }
+            instance_capacities.insert(*object_type, capacity);
+            let num_instances = NumInstances(capacity);
+            for (resource_id, resource) in objects_to_add.iter().find(|obj| ObjectType::of(obj.1.as_ref()) == *object_type).unwrap().1.get_object_instance_resources() {
+                let resource_lock = resource.read().unwrap_or_else(PoisonError::into_inner);
                 match resource_lock.get_resource() {
                     ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) => {
-                        match Self::create_storage_buffer(*object_type, resource_id, *num_instances, buffer.clone(), &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, allocator) {
+                        match Self::create_storage_buffer(*object_type, resource_id, num_instances, buffer.clone(), &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, texture_cache, self.frames_in_flight, allocator) {
                             Ok(_) => (),
                             Err(e) => return Err(e),
                         }
                     },
+                    ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(_) => {
+                        let stride = Self::get_dynamic_uniform_buffer_stride(objects_to_add.iter().find(|obj| ObjectType::of(obj.1.as_ref()) == *object_type).unwrap().1.as_ref());
+                        Self::create_dynamic_uniform_buffer(*object_type, resource_id, num_instances, stride, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, texture_cache, self.frames_in_flight, allocator)?;
+                        self.dynamic_uniform_buffer_strides.insert(*object_type, stride);
+                    },
                 }
             }
-
-            let reference_object = match self.object_type_references.get(&object_type) {
-                Some(reference_id) => &self.objects.get(&reference_id.0).unwrap().1,
-                None => {
-                    &objects_to_add.iter().find(|obj| obj.1.get_vertices_and_indices_hash() == object_type.0).unwrap().1
-                },
-            };
-
-            Self::add_object_vertices_and_indices_if_new_object_type(*object_type, reference_object, &mut object_type_vertices_bytes_indices, &mut object_type_indices_bytes_indices, &mut vertices_data, &mut indices_data).unwrap();
         }
         
         for object in objects_to_add {
-            let object_type = ObjectType(object.1.get_vertices_and_indices_hash());
+            let object_type = ObjectType::of(object.1.as_ref());
             let newly_added_object_type = object_types.insert(object_type) && !self.descriptor_sets.contains_key(&object_type); // This could also be self.object_type_num_instances.contains_key(&object_type)
             
             // TODO: add the ability to override static object type data
             if newly_added_object_type {
                 for (resource_id, resource) in object.1.get_type_resources() {
-                    match resource.read().unwrap().get_resource() {
-                        ObjectTypeGraphicsResourceType::Texture(image) => {
-                            match Self::create_and_add_static_texture(object_type, resource_id, image, device, instance, physical_device, command_pool, graphics_queue, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, sampler_manager, allocator) {
+                    let resource_lock = resource.read().unwrap_or_else(PoisonError::into_inner);
+                    match resource_lock.get_resource() {
+                        ObjectTypeGraphicsResourceType::Texture(image, asset_key) => {
+                            match Self::create_and_add_static_texture(object_type, resource_id, vec![image], asset_key, resource_lock.get_load_state(), device, instance, physical_device, command_pool, graphics_queue, &mut textures, &mut fallback_textures, &mut uniform_buffers, &mut storage_uniform_buffers, sampler_manager, texture_cache, allocator) {
+                                Ok(_) => (),
+                                Err(e) => return Err(e),
+                            }
+                        },
+                        ObjectTypeGraphicsResourceType::CubeMap(faces, asset_key) => {
+                            match Self::create_and_add_static_texture(object_type, resource_id, faces.to_vec(), asset_key, resource_lock.get_load_state(), device, instance, physical_device, command_pool, graphics_queue, &mut textures, &mut fallback_textures, &mut uniform_buffers, &mut storage_uniform_buffers, sampler_manager, texture_cache, allocator) {
                                 Ok(_) => (),
                                 Err(e) => return Err(e),
                             }
                         },
+                        ObjectTypeGraphicsResourceType::RenderTarget(render_target_id) => {
+                            self.render_target_bindings.insert((object_type, resource_id), (render_target_id, resource_lock.get_descriptor_set_layout_binding().binding));
+                        },
                     ObjectTypeGraphicsResourceType::UniformBuffer(buffer) => {
-                        match Self::create_and_add_static_uniform_buffer(object_type, resource_id, &buffer, current_frame, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, allocator) {
+                        match Self::create_and_add_static_uniform_buffer(object_type, resource_id, &buffer, current_frame, &mut textures, &mut uniform_buffers, &mut storage_uniform_buffers, texture_cache, self.frames_in_flight, allocator) {
                             Ok(_) => (),
                             Err(e) => return Err(e),
                         }
@@ -441,45 +1930,33 @@ impl DataUsedInShader {
             new_objects.insert(object.0, (object_type, object.1));
         }
         
-        let mut all_objects = self.objects.iter().map(|(k, v)| (k, v)).collect::<Vec<_>>();
-        all_objects.extend(new_objects.iter().map(|(k, v)| (k, (v))));
-
-        Self::create_storage_buffer_byte_indices(&all_objects, &mut object_id_storage_buffer_bytes_indices);
-        
-        Self::copy_storage_buffer_data_to_gpu(&self.objects, &mut storage_uniform_buffers, &object_id_storage_buffer_bytes_indices, current_frame as usize);
-        Self::copy_storage_buffer_data_to_gpu(&mut new_objects, &mut storage_uniform_buffers, &object_id_storage_buffer_bytes_indices, current_frame as usize);
-
-        let mut vertex_allocation = match allocator.create_device_local_buffer(command_pool, graphics_queue, &vertices_data, vk::BufferUsageFlags::VERTEX_BUFFER, false) {
-            Ok(alloc) => alloc,
-            Err(e) => return Err(Cow::from(e)),
-        };
-        let mut index_allocation = match allocator.create_device_local_buffer(command_pool, graphics_queue, &indices_data, vk::BufferUsageFlags::INDEX_BUFFER, false) {
-            Ok(alloc) => alloc,
-            Err(e) => {
-                let mut error_str = e.to_string();
-                free_allocations_add_error_string!(allocator, vec![vertex_allocation], error_str);
-                return Err(Cow::from(error_str));
-            },
-        };
-        std::mem::swap(&mut self.vertices.0, &mut vertex_allocation);
-        self.vertices.1 = vertices_data;
-        std::mem::swap(&mut self.indices.0, &mut index_allocation);
-        self.indices.1 = indices_data;
+        Self::create_storage_buffer_byte_indices(self.objects.iter().chain(new_objects.iter()), &object_slots, &mut object_id_storage_buffer_bytes_indices);
 
-        self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(vertex_allocation)));
-        self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(index_allocation)));
+        // Only the object types that are new to the pipeline take a reference to their mesh
+        let new_object_type_meshes = Self::acquire_meshes(new_object_types.iter().map(|object_type| (*object_type, new_objects.values().find(|(other_object_type, _)| other_object_type == object_type).unwrap().1.as_ref())), mesh_registry, command_pool, graphics_queue, allocator)?;
+        self.object_type_meshes.extend(new_object_type_meshes);
 
         if !new_object_types.is_empty() {
-            let mut descriptor_sets = Self::create_descriptor_sets(device, descriptor_pool, pipeline_config.borrow_descriptor_set_layout().unwrap(), &new_object_types, &descriptor_type_data, &uniform_buffers, &textures, &storage_uniform_buffers, VkController::MAX_FRAMES_IN_FLIGHT as u32);
+            let mut descriptor_sets = Self::create_descriptor_sets(device, descriptor_pool, pipeline_config.borrow_descriptor_set_layout().unwrap(), &new_object_types, &descriptor_type_data, &uniform_buffers, &textures, &storage_uniform_buffers, &self.dynamic_uniform_buffer_strides, self.frames_in_flight as u32)?;
             self.descriptor_sets.extend(descriptor_sets.drain());
+            self.object_type_draw_order.extend(object_types_in_insertion_order.into_iter().filter(|object_type| new_object_types.contains(object_type)));
         }
+        // The sets of the frames in flight still point at the old buffers, so they are replaced like the buffers instead of written again
+        let resized_descriptor_sets = if resized_object_types.is_empty() {
+            HashMap::new()
+        } else {
+            Self::create_descriptor_sets(device, descriptor_pool, pipeline_config.borrow_descriptor_set_layout().unwrap(), &resized_object_types, &descriptor_type_data, &self.uniform_buffers, &self.textures, &storage_uniform_buffers, &self.dynamic_uniform_buffer_strides, self.frames_in_flight as u32)?
+        };
 
         let texture_keys = textures.keys().cloned().collect::<Vec<_>>();
         self.textures.iter_mut().filter(|(k, _)| texture_keys.contains(k)).for_each(|(k, v)| {
             std::mem::swap(v, textures.get_mut(k).unwrap());
-            self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(textures.remove(k).unwrap().0)));
+            if let Some(allocation) = texture_cache.release(textures.remove(k).unwrap().0) {
+                self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
+            }
         });
         self.textures.extend(textures);
+        self.fallback_textures.extend(fallback_textures);
 
         let uniform_keys = uniform_buffers.keys().cloned().collect::<Vec<_>>();
         self.uniform_buffers.iter_mut().filter(|(k, _)| uniform_keys.contains(k)).for_each(|(k, v)| {
@@ -494,24 +1971,63 @@ impl DataUsedInShader {
             self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(storage_uniform_buffers.remove(k).unwrap().0)));
         });
         self.storage_buffers.extend(storage_uniform_buffers);
+        self.instance_capacities.extend(instance_capacities);
+
+        for (object_type, descriptor_sets) in resized_descriptor_sets {
+            let old_descriptor_sets = self.descriptor_sets.insert(object_type, descriptor_sets).expect("Descriptor sets not found for a resized object type. This should never happen!");
+            self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::DescriptorSets(old_descriptor_sets)));
+        }
+
+        for (object_type, (num_instances, num_indices)) in added_object_type_num_instances {
+            self.object_type_num_instances.entry(object_type).or_insert((NumInstances(0), num_indices)).0.0 += num_instances.0;
+        }
+        self.object_type_references.extend(added_object_type_references.into_iter().filter(|(object_type, _)| new_object_types.contains(object_type)));
+        self.object_slots = object_slots;
+        let object_type_slots_keys = object_type_slots.keys().copied().collect::<HashSet<_>>();
+        self.object_type_slots.extend(object_type_slots);
+        self.object_id_storage_buffer_bytes_indices = object_id_storage_buffer_bytes_indices;
+        self.objects.extend(new_objects);
+
+        // Only the snapshot is written, the frames in flight can still read the buffers. Every frame copies all of it after objects were added
+        let objects = &self.objects;
+        Self::snapshot_storage_buffer_data(objects.iter().filter(|(_, (object_type, _))| object_type_slots_keys.contains(object_type)), &mut self.storage_buffers, &self.object_id_storage_buffer_bytes_indices, &mut self.poisoned_resources);
 
         Ok(())
     }
 
-    fn remove_objects(&mut self, object_ids_to_remove: Vec<ObjectID>, command_pool: &vk::CommandPool, graphics_queue: &Queue, current_frame: usize, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
+    // None when the slots fit in the buffers the object type already has
+    fn get_new_instance_capacity(capacity: Option<usize>, num_slots: usize) -> Option<usize> {
+        match capacity {
+            Some(capacity) if num_slots <= capacity => None,
+            Some(capacity) => Some(num_slots.max(capacity * 2)),
+            None => Some(num_slots),
+        }
+    }
+
+    fn remove_objects(&mut self, object_ids_to_remove: Vec<ObjectID>, texture_cache: &mut TextureCache, mesh_registry: &mut MeshRegistry) -> Result<(), EngineError> {
+        self.num_full_upload_frames = self.frames_in_flight as u32;
         let mut objects_to_remove: Vec<(ObjectID, (ObjectType, Box<dyn Renderable>))> = Vec::new();
         object_ids_to_remove.iter().for_each(|id| {
             if !self.objects.contains_key(id) {
-                eprintln!("Object with id {:?} not found in object manager. So we are skipping it.", id);
+                log::warn!(target: logging::OBJECTS, "Object with id {:?} not found in object manager. So we are skipping it.", id);
                 return;
             }
             objects_to_remove.push((*id, self.objects.remove(id).unwrap()));
         });
         if objects_to_remove.is_empty() {
-            eprintln!("No objects to remove. So nothing to do.");
+            log::debug!(target: logging::OBJECTS, "No objects to remove. So nothing to do.");
             return Ok(());
         }
 
+        objects_to_remove.iter().for_each(|(object_id, (object_type, _))| {
+            if self.hidden_objects.remove(object_id) {
+                *self.object_type_num_hidden_instances.get_mut(object_type).unwrap() -= 1;
+            }
+            // The other objects keep their slots, the freed one is given to the next added object of the type
+            let slot = self.object_slots.remove(object_id).unwrap();
+            self.object_type_slots.get_mut(object_type).unwrap().remove(slot);
+        });
+
         let mut num_object_types_to_remove: HashMap<ObjectType, NumInstances> = HashMap::new();
         objects_to_remove.iter().for_each(|(_, (object_type, object))| {
             let e = num_object_types_to_remove.entry(*object_type).or_insert(NumInstances(0));
@@ -528,8 +2044,12 @@ impl DataUsedInShader {
             }
         });
         self.object_type_num_instances.retain(|k, _: _| !object_types_to_remove.contains(k));
+        self.object_type_num_hidden_instances.retain(|k, _| !object_types_to_remove.contains(k));
+        self.object_type_slots.retain(|k, _| !object_types_to_remove.contains(k));
+        self.instance_capacities.retain(|k, _| !object_types_to_remove.contains(k));
 
         self.object_type_references.retain(|k, _| !object_types_to_remove.contains(k));
+        self.object_type_draw_order.retain(|k| !object_types_to_remove.contains(k));
         self.object_type_references.iter_mut().for_each(|(obj_type, reference)| {
             if object_ids_to_remove.contains(&reference.0) {
                 let new_reference = self.objects.iter().find(|(id, (object_type, obj))| *id != &reference.0 && object_type == obj_type).map(|(id, _)| ReferenceObjectID(*id)).expect(format!("Failed to find a new reference object for object type {:?}. This should never happen!", obj_type).as_str());
@@ -538,35 +2058,25 @@ impl DataUsedInShader {
         });
 
         object_types_to_remove.iter().for_each(|object_type| {
-            let vertex_byte_indices = self.object_type_vertices_bytes_indices.remove(object_type).unwrap();
-            let index_byte_indices = self.object_type_indices_bytes_indices.remove(object_type).unwrap();
-            self.vertices.1.drain(vertex_byte_indices.0.0 as usize..vertex_byte_indices.1.0 as usize);
-            self.indices.1.drain(index_byte_indices.0.0 as usize..index_byte_indices.1.0 as usize);
-            // Update the byte indices for the other object types
-            let num_vertex_bytes = vertex_byte_indices.1.0 - vertex_byte_indices.0.0 + 1;
-            self.object_type_vertices_bytes_indices.par_iter_mut().for_each(|(_, (start, end))| {
-                if *start > vertex_byte_indices.0 {
-                    start.0 -= num_vertex_bytes;
-                    end.0 -= num_vertex_bytes;
-                }
-            });
-            let num_index_bytes = index_byte_indices.1.0 - index_byte_indices.0.0 + 1;
-            self.object_type_indices_bytes_indices.par_iter_mut().for_each(|(_, (start, end))| {
-                if *start > index_byte_indices.0 {
-                    start.0 -= num_index_bytes;
-                    end.0 -= num_index_bytes;
-                }
-            });
+            // Other object types might still use the same mesh
+            let mesh = self.object_type_meshes.remove(object_type).unwrap();
+            for allocation in mesh_registry.release(mesh) {
+                self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
+            }
 
             let texture_keys = self.textures.keys().cloned().filter(|k| k.0 == *object_type).collect::<Vec<_>>();
             texture_keys.iter().filter(|k| k.0 == *object_type).for_each(|k| {
-                let allocation = self.textures.remove(&k).unwrap().0;
-                self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
+                // Other object types might still use the same texture
+                if let Some(allocation) = texture_cache.release(self.textures.remove(&k).unwrap().0) {
+                    self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
+                }
+                self.fallback_textures.remove(k);
             });
 
             let uniform_keys = self.uniform_buffers.keys().cloned().filter(|k| k.0 == *object_type).collect::<Vec<_>>();
             uniform_keys.iter().filter(|k| k.0 == *object_type).for_each(|k| {
                 let allocation = self.uniform_buffers.remove(&k).unwrap();
+                self.uniform_buffer_data.remove(k);
                 self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
             });
 
@@ -576,84 +2086,391 @@ impl DataUsedInShader {
                 self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
             });
 
+            self.dynamic_uniform_buffer_strides.remove(object_type);
+            self.render_target_bindings.retain(|(o, _), _| o != object_type);
+
             let descriptor_sets = self.descriptor_sets.remove(object_type).unwrap();
             self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::DescriptorSets(descriptor_sets)));
         });
 
-        let mut new_storage_buffers = HashMap::new();
-        for (object_type, (num_instances, _)) in self.object_type_num_instances.iter() {
-            for (resource_id, resource) in self.objects.iter().find(|(_, (obj_type, obj))| obj_type == object_type).unwrap().1.1.get_object_instance_resources() {
-                let resource_lock = resource.read().unwrap();
-                match resource_lock.get_resource() {
-                    ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) => {
-                        match Self::create_storage_buffer(*object_type, resource_id, *num_instances, buffer.clone(), &mut HashMap::new(), &mut HashMap::new(), &mut new_storage_buffers, allocator) {
-                            Ok(_) => (),
-                            Err(e) => return Err(e),
-                        }
-                    },
+        // The remaining objects keep their slots in the same buffers, so their descriptor sets stay valid. The freed slots are skipped by the draws until they are given out again
+        self.object_id_storage_buffer_bytes_indices.retain(|(object_id, _), _| self.object_slots.contains_key(object_id));
+
+        Ok(())
+    }
+
+    // Gives every object type with textures new textures and descriptor sets. The old ones are only replaced when all the new ones have been made
+    fn reupload_textures(&mut self, pipeline_config: &PipelineConfig, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, descriptor_pool: &DescriptorPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, texture_cache: &mut TextureCache, allocator: &VkAllocator) -> Result<(), EngineError> {
+        if self.textures.is_empty() {
+            return Ok(());
+        }
+
+        let mut new_textures = HashMap::new();
+        let mut new_fallback_textures = HashMap::new();
+        for &(object_type, resource_id) in self.textures.keys() {
+            let reference_id = self.object_type_references.get(&object_type).expect("Reference object not found in object manager. This should never happen!");
+            let (_, reference_object) = self.objects.get(&reference_id.0).expect("Reference object not found in object manager. This should never happen!");
+            let Some((_, resource)) = reference_object.get_type_resources().into_iter().find(|(id, _)| *id == resource_id) else {
+                continue;
+            };
+            let resource_lock = resource.read().unwrap_or_else(PoisonError::into_inner);
+            let (images, asset_key) = match resource_lock.get_resource() {
+                ObjectTypeGraphicsResourceType::Texture(image, asset_key) => (vec![image], asset_key),
+                ObjectTypeGraphicsResourceType::CubeMap(faces, asset_key) => (faces.to_vec(), asset_key),
+                ObjectTypeGraphicsResourceType::UniformBuffer(_) | ObjectTypeGraphicsResourceType::RenderTarget(_) => continue,
+            };
+            Self::create_and_add_static_texture(object_type, resource_id, images, asset_key, resource_lock.get_load_state(), device, instance, physical_device, command_pool, graphics_queue, &mut new_textures, &mut new_fallback_textures, &mut HashMap::new(), &mut HashMap::new(), sampler_manager, texture_cache, allocator)?;
+        }
+
+        let object_types = new_textures.keys().map(|(object_type, _)| *object_type).collect::<HashSet<_>>();
+        let new_descriptor_sets = Self::create_descriptor_sets(device, descriptor_pool, pipeline_config.borrow_descriptor_set_layout().unwrap(), &object_types, &self.descriptor_type_data, &self.uniform_buffers, &new_textures, &self.storage_buffers, &self.dynamic_uniform_buffer_strides, self.frames_in_flight as u32)?;
+
+        for (key, new_texture) in new_textures {
+            match new_fallback_textures.remove(&key) {
+                Some(reason) => self.fallback_textures.insert(key, reason),
+                None => self.fallback_textures.remove(&key),
+            };
+            if let Some((old_allocation, _)) = self.textures.insert(key, new_texture) {
+                if let Some(allocation) = texture_cache.release(old_allocation) {
+                    self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(allocation)));
                 }
             }
         }
+        for (object_type, descriptor_sets) in new_descriptor_sets {
+            if let Some(old_descriptor_sets) = self.descriptor_sets.insert(object_type, descriptor_sets) {
+                self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::DescriptorSets(old_descriptor_sets)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the bytes of every resource into the CPU side of the buffers. Each lock is only held while the bytes of its resource are cloned,
+    /// so the GPU copies are made from the snapshot and other threads can write the next frame's data while they happen.
+    fn snapshot_resource_data(&mut self, is_partial_instance_upload_enabled: bool) {
+        if is_partial_instance_upload_enabled && self.num_full_upload_frames == 0 {
+            let objects = &self.objects;
+            Self::snapshot_storage_buffer_data(self.dirty_objects.keys().filter_map(|object_id| objects.get_key_value(object_id)), &mut self.storage_buffers, &self.object_id_storage_buffer_bytes_indices, &mut self.poisoned_resources);
+        } else {
+            Self::snapshot_storage_buffer_data(self.objects.iter(), &mut self.storage_buffers, &self.object_id_storage_buffer_bytes_indices, &mut self.poisoned_resources);
+        }
+        self.object_type_references.iter().for_each(|(object_type, reference)| {
+            let (_, object) = self.objects.get(&reference.0).expect("Reference object not found in object manager. This should never happen!");
+            for (resource_id, resource) in object.get_type_resources() {
+                match Self::read_resource(&resource, |resource| resource.get_resource(), &mut self.poisoned_resources) {
+                    Some(ObjectTypeGraphicsResourceType::UniformBuffer(data)) => {
+                        self.uniform_buffer_data.insert((*object_type, resource_id), data);
+                    },
+                    Some(ObjectTypeGraphicsResourceType::Texture(..) | ObjectTypeGraphicsResourceType::CubeMap(..) | ObjectTypeGraphicsResourceType::RenderTarget(_)) | None => (), //TODO: Implement texture update
+                };
+            }
+        });
+    }
 
-        let new_storage_keys = new_storage_buffers.keys().cloned().collect::<Vec<_>>();
-        self.storage_buffers.iter_mut().filter(|(k, _)| new_storage_keys.contains(k)).for_each(|(k, v)| {
-            std::mem::swap(v, new_storage_buffers.get_mut(k).unwrap());
-            self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(new_storage_buffers.remove(k).unwrap().0)));
+    fn update_all_uniform_data(&mut self, is_partial_instance_upload_enabled: bool, current_frame: usize) {
+        if is_partial_instance_upload_enabled && self.num_full_upload_frames == 0 {
+            self.upload_dirty_storage_buffer_ranges(current_frame);
+        } else {
+            Self::upload_storage_buffer_data(&self.storage_buffers, current_frame);
+            self.num_full_upload_frames = self.num_full_upload_frames.saturating_sub(1);
+        }
+        // Every frame in flight has its own copy of the buffers, so a changed instance is copied once to each of them
+        self.dirty_objects.retain(|_, num_frames_left| {
+            *num_frames_left -= 1;
+            *num_frames_left > 0
+        });
+        self.uniform_buffer_data.iter().for_each(|(key, data)| {
+            let allocation = self.uniform_buffers.get(key).expect("Uniform buffer not found for object type. This should never happen. Was the uniform buffer added to the object type?");
+            unsafe {
+                std::ptr::copy_nonoverlapping(data.as_ptr() as *const std::ffi::c_void, allocation.get_uniform_pointers()[current_frame], data.len().min(allocation.get_uniform_buffer_size() as usize));
+            }
         });
+    }
 
-        let all_objects = self.objects.iter().map(|(k, v)| (k, v)).collect::<Vec<_>>();
-        
-        Self::create_storage_buffer_byte_indices(&all_objects, &mut self.object_id_storage_buffer_bytes_indices);
-        
-        Self::copy_storage_buffer_data_to_gpu(&self.objects, &mut self.storage_buffers, &self.object_id_storage_buffer_bytes_indices, current_frame as usize);
+    /// Returns the draws of every object type in the order they were added. Object types with empty vertex data are skipped, since there is nothing to draw.
+    /// An object type has one draw per run of visible slots, so hidden objects and free slots between visible ones add a draw each. At worst every other slot is skipped, which is a draw per object.
+    pub fn draw_batches<'a>(&'a self, current_frame: usize, mesh_registry: &'a MeshRegistry) -> impl Iterator<Item = DrawBatch> + 'a {
+        self.object_type_draw_order.iter().flat_map(move |object_type| self.get_draw_batches(*object_type, current_frame, mesh_registry))
+    }
 
-        let mut vertex_allocation = match allocator.create_device_local_buffer(command_pool, graphics_queue, &self.vertices.1, vk::BufferUsageFlags::VERTEX_BUFFER, false) {
-            Ok(alloc) => alloc,
-            Err(e) => return Err(Cow::from(e)),
+    // The hidden objects and the free slots are skipped, so each run of visible slots is its own draw
+    fn get_draw_batches(&self, object_type: ObjectType, current_frame: usize, mesh_registry: &MeshRegistry) -> Vec<DrawBatch> {
+        let Some(draw_batch) = self.get_draw_batch(object_type, current_frame, mesh_registry) else {
+            return Vec::new();
         };
-        let mut index_allocation = match allocator.create_device_local_buffer(command_pool, graphics_queue, &self.indices.1, vk::BufferUsageFlags::INDEX_BUFFER, false) {
-            Ok(alloc) => alloc,
-            Err(e) => {
-                let mut error_str = e.to_string();
-                free_allocations_add_error_string!(allocator, vec![vertex_allocation], error_str);
-                return Err(Cow::from(error_str));
-            },
+        self.get_instance_runs(object_type, |_| true).into_iter().map(|(first_instance, num_instances)| DrawBatch { first_instance, num_instances, ..draw_batch }).collect()
+    }
+
+    fn get_draw_batch(&self, object_type: ObjectType, current_frame: usize, mesh_registry: &MeshRegistry) -> Option<DrawBatch> {
+        let (num_instances, num_indices) = self.object_type_num_instances.get(&object_type)?;
+        let num_visible_instances = num_instances.0 - self.object_type_num_hidden_instances.get(&object_type).copied().unwrap_or(0);
+        let mesh = mesh_registry.get(*self.object_type_meshes.get(&object_type)?)?;
+        if mesh.vertices.1.is_empty() || num_visible_instances == 0 {
+            return None;
+        }
+        let reference_object = &self.objects.get(&self.object_type_references.get(&object_type)?.0)?.1;
+        let vertex_stride = reference_object.get_vertex_binding_info().stride as usize;
+        // Every mesh has its own buffers, so the draws start at their first vertex and index, or at the first index of the submesh
+        Some(DrawBatch {
+            object_type,
+            vertex_buffer: mesh.vertices.0.as_ref()?.get_buffer()?,
+            first_vertex: 0,
+            num_vertices: (mesh.vertices.1.len() / vertex_stride) as u32,
+            index_buffer: if num_indices.0 == 0 { None } else { mesh.indices.0.as_ref().and_then(|allocation| allocation.get_buffer()) },
+            index_type: mesh.index_type,
+            first_index: reference_object.get_submesh_indices().map(|(first_index, _)| first_index).unwrap_or(0),
+            num_indices: num_indices.0 as u32,
+            first_instance: 0,
+            num_instances: num_visible_instances as u32,
+            descriptor_set: self.descriptor_sets.get(&object_type)?[current_frame],
+            num_dynamic_uniform_buffers: self.descriptor_type_data.iter().filter(|(_, descriptor_type, _)| *descriptor_type == DescriptorType::UNIFORM_BUFFER_DYNAMIC).count() as u32,
+            dynamic_uniform_buffer_stride: self.dynamic_uniform_buffer_strides.get(&object_type).copied().unwrap_or(0) as u32,
+        })
+    }
+
+    pub fn get_object_type_report(&self, object_type: ObjectType, mesh_registry: &MeshRegistry) -> Option<ObjectTypeReport> {
+        let (num_instances, num_indices) = self.object_type_num_instances.get(&object_type)?;
+        let mesh = mesh_registry.get(*self.object_type_meshes.get(&object_type)?)?;
+        let estimated_memory_bytes = self.textures.iter().filter(|((o, _), _)| *o == object_type).map(|(_, (allocation, _))| allocation.get_size()).sum::<u64>()
+            + self.uniform_buffers.iter().filter(|((o, _), _)| *o == object_type).map(|(_, allocation)| allocation.get_size()).sum::<u64>()
+            + self.storage_buffers.iter().filter(|((o, _), _)| *o == object_type).map(|(_, (allocation, _))| allocation.get_size()).sum::<u64>();
+        Some(ObjectTypeReport {
+            object_type: object_type.0,
+            num_instances: num_instances.0,
+            num_indices: num_indices.0,
+            vertex_byte_range: (0, mesh.vertices.1.len()),
+            index_byte_range: (0, mesh.indices.1.len()),
+            num_descriptor_sets: self.descriptor_sets.get(&object_type).map(|descriptor_sets| descriptor_sets.len()).unwrap_or(0),
+            estimated_memory_bytes,
+        })
+    }
+
+    pub fn get_num_instances(&self, object_type: ObjectType) -> Option<usize> {
+        self.object_type_num_instances.get(&object_type).map(|(num_instances, _)| num_instances.0)
+    }
+
+    /// The instance slot of an object is its position in the storage buffers of its object type, which is the `gl_InstanceIndex` the shaders index them with.
+    /// Object types without storage buffers draw every instance the same, so the reference object is returned for them.
+    pub fn get_object_id_at_instance(&self, object_type: ObjectType, instance_index: usize) -> Option<ObjectID> {
+        if !self.has_instance_buffers(object_type) {
+            return self.object_type_references.get(&object_type).map(|reference| reference.0);
+        }
+        self.object_type_slots.get(&object_type)?.objects.get(&(instance_index as u32)).copied()
+    }
+
+    fn has_instance_buffers(&self, object_type: ObjectType) -> bool {
+        self.storage_buffers.keys().any(|(o, _)| *o == object_type)
+    }
+
+    /// Hiding is cheap, since the object keeps its slot and only the draws leave it out. Hidden objects between visible ones split the draw of their object type.
+    fn set_object_visible(&mut self, object_id: ObjectID, is_visible: bool) {
+        let Some((object_type, _)) = self.objects.get(&object_id) else {
+            return;
+        };
+        let num_hidden_instances = self.object_type_num_hidden_instances.entry(*object_type).or_insert(0);
+        if is_visible && self.hidden_objects.remove(&object_id) {
+            *num_hidden_instances -= 1;
+        } else if !is_visible && self.hidden_objects.insert(object_id) {
+            *num_hidden_instances += 1;
+        }
+    }
+
+    fn is_object_visible(&self, object_id: ObjectID) -> Option<bool> {
+        self.objects.contains_key(&object_id).then(|| !self.hidden_objects.contains(&object_id))
+    }
+
+    // The first instance and number of instances of every run of the included visible objects' slots. Object types without storage buffers draw every instance the same, so all of them are drawn
+    fn get_instance_runs(&self, object_type: ObjectType, is_included: impl Fn(&ObjectID) -> bool) -> Vec<(u32, u32)> {
+        let Some(instance_slots) = self.object_type_slots.get(&object_type) else {
+            return Vec::new();
+        };
+        // The slots are in order, so the runs come out sorted
+        let mut visible_slots = instance_slots.objects.iter().filter(|(_, object_id)| is_included(object_id) && !self.hidden_objects.contains(object_id)).map(|(slot, _)| *slot).peekable();
+        if visible_slots.peek().is_none() {
+            return Vec::new();
+        }
+        if !self.has_instance_buffers(object_type) {
+            let (num_instances, _) = self.object_type_num_instances.get(&object_type).unwrap();
+            return vec![(0, (num_instances.0 - self.object_type_num_hidden_instances.get(&object_type).copied().unwrap_or(0)) as u32)];
+        }
+
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for slot in visible_slots {
+            match runs.last_mut() {
+                Some((first_instance, num_instances)) if *first_instance + *num_instances == slot => *num_instances += 1,
+                _ => runs.push((slot, 1)),
+            }
+        }
+        runs
+    }
+
+    fn get_sprite_instance_data(&self, object_id: ObjectID) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        self.objects.get(&object_id)?.1.get_sprite_instance_data()
+    }
+
+    /// The inverse of [`Self::get_object_id_at_instance`]. None when the object's type has no storage buffers, since every instance then reads the same data.
+    pub fn get_instance_slot(&self, object_id: ObjectID) -> Option<u32> {
+        let (object_type, _) = self.objects.get(&object_id)?;
+        if !self.has_instance_buffers(*object_type) {
+            return None;
+        }
+        self.object_slots.get(&object_id).copied()
+    }
+
+    // The bytes of the object's instance resource in the last snapshot of its storage buffer
+    fn get_instance_data(&self, object_id: ObjectID, resource_id: ResourceID) -> Option<&[u8]> {
+        let (object_type, _) = self.objects.get(&object_id)?;
+        let (start, end) = self.object_id_storage_buffer_bytes_indices.get(&(object_id, resource_id))?;
+        let (_, data) = self.storage_buffers.get(&(*object_type, resource_id))?;
+        data.get(start.0..end.0)
+    }
+
+    // The descriptor sets of the object's type, one for each frame in flight
+    #[cfg(test)]
+    fn get_descriptor_sets(&self, object_id: ObjectID) -> Option<&[DescriptorSet]> {
+        let (object_type, _) = self.objects.get(&object_id)?;
+        self.descriptor_sets.get(object_type).map(Vec::as_slice)
+    }
+
+    /// Tests the ray against the triangles of every object, using the CPU copy of the geometry and each object's model matrix.
+    pub fn raycast(&self, origin: &glm::Vec3, direction: &glm::Vec3, mesh_registry: &MeshRegistry) -> Option<(ObjectID, f32)> {
+        self.object_type_draw_order.iter().filter_map(|object_type| {
+            let triangles = self.get_object_type_triangles(*object_type, mesh_registry)?;
+            self.objects.iter().filter(|(object_id, (o, _))| o == object_type && !self.hidden_objects.contains(object_id)).filter_map(|(object_id, (_, object))| {
+                // The ray is moved into the object's space instead of moving every triangle into world space. This keeps the distance along the ray the same
+                let inverse_model_matrix = self.get_model_matrix(*object_id, object.as_ref()).try_inverse()?;
+                let local_origin = (inverse_model_matrix * glm::vec4(origin.x, origin.y, origin.z, 1.0)).xyz();
+                let local_direction = (inverse_model_matrix * glm::vec4(direction.x, direction.y, direction.z, 0.0)).xyz();
+                triangles.iter().filter_map(|triangle| Self::intersect_ray_triangle(&local_origin, &local_direction, triangle)).min_by(f32::total_cmp).map(|distance| (*object_id, distance))
+            }).min_by(|a, b| a.1.total_cmp(&b.1))
+        }).min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// The triangles in the object type's own space, read from the position at location 0 of the vertices. Object types without indices are read as a triangle list.
+    fn get_object_type_triangles(&self, object_type: ObjectType, mesh_registry: &MeshRegistry) -> Option<Vec<[glm::Vec3; 3]>> {
+        let reference_object = &self.objects.get(&self.object_type_references.get(&object_type)?.0)?.1;
+        let vertex_stride = reference_object.get_vertex_binding_info().stride as usize;
+        let position_attribute = reference_object.get_vertex_attribute_descriptions().into_iter().find(|attribute| attribute.location == 0)?;
+        let num_components = match position_attribute.format {
+            vk::Format::R32G32_SFLOAT => 2,
+            vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32A32_SFLOAT => 3,
+            _ => return None,
+        };
+
+        let mesh = mesh_registry.get(*self.object_type_meshes.get(&object_type)?)?;
+        let positions = mesh.vertices.1.chunks_exact(vertex_stride).map(|vertex| {
+            let component = |i: usize| {
+                let offset = position_attribute.offset as usize + i * std::mem::size_of::<f32>();
+                f32::from_ne_bytes([vertex[offset], vertex[offset + 1], vertex[offset + 2], vertex[offset + 3]])
+            };
+            glm::vec3(component(0), component(1), if num_components == 3 { component(2) } else { 0.0 })
+        }).collect::<Vec<_>>();
+
+        let index_bytes = &mesh.indices.1;
+        let indices = match mesh.index_type {
+            _ if index_bytes.is_empty() => (0..positions.len()).collect::<Vec<_>>(),
+            vk::IndexType::UINT16 => index_bytes.chunks_exact(std::mem::size_of::<u16>()).map(|index| u16::from_ne_bytes([index[0], index[1]]) as usize).collect(),
+            _ => index_bytes.chunks_exact(std::mem::size_of::<u32>()).map(|index| u32::from_ne_bytes([index[0], index[1], index[2], index[3]]) as usize).collect(),
         };
-        std::mem::swap(&mut self.vertices.0, &mut vertex_allocation);
-        std::mem::swap(&mut self.indices.0, &mut index_allocation);
-        self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(vertex_allocation)));
-        self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Allocation(index_allocation)));
+        Some(indices.chunks_exact(3).filter_map(|triangle| Some([*positions.get(triangle[0])?, *positions.get(triangle[1])?, *positions.get(triangle[2])?])).collect())
+    }
 
-        Ok(())
+    /// The translation of the object's model matrix.
+    fn get_object_position(&self, object_id: ObjectID) -> Option<glm::Vec3> {
+        let model_matrix = self.get_model_matrix(object_id, self.objects.get(&object_id)?.1.as_ref());
+        Some(glm::vec3(model_matrix[(0, 3)], model_matrix[(1, 3)], model_matrix[(2, 3)]))
     }
 
-    fn update_all_uniform_data(&mut self, current_frame: usize) {
-        Self::copy_storage_buffer_data_to_gpu(&self.objects, &mut self.storage_buffers, &self.object_id_storage_buffer_bytes_indices, current_frame);
-        self.object_type_references.iter().for_each(|(object_type, reference)| {
-            let (_, object) = self.objects.get(&reference.0).expect("Reference object not found in object manager. This should never happen!");
-            for (resource_id, resource) in object.get_type_resources() {
-                match resource.read().unwrap().get_resource() {
-                    ObjectTypeGraphicsResourceType::UniformBuffer(data) => {
-                        let allocation = self.uniform_buffers.get(&(*object_type, resource_id)).expect("Uniform buffer not found for object type. This should never happen. Was the uniform buffer added to the object type?");
-                        unsafe {
-                            std::ptr::copy_nonoverlapping(data.as_ptr() as *const std::ffi::c_void, allocation.get_uniform_pointers()[current_frame], (allocation.get_memory_end()-allocation.get_memory_start()) as usize);
-                        }
-                    },
-                    ObjectTypeGraphicsResourceType::Texture(_) => (), //TODO: Implement texture update
-                };
+    fn get_model_matrix(&self, object_id: ObjectID, object: &dyn Renderable) -> glm::Mat4 {
+        Self::read_model_matrix(object, |resource_id| self.get_instance_data(object_id, resource_id).map(<[u8]>::to_vec))
+    }
+
+    // The model matrix is the first mat4 of the instance resource at binding 0, which is where the shaders read it from. Objects without one are not transformed.
+    // A poisoned resource is read from the last snapshot instead, like when it is drawn. The snapshot is what logs it
+    fn read_model_matrix(object: &dyn Renderable, get_snapshot_data: impl Fn(ResourceID) -> Option<Vec<u8>>) -> glm::Mat4 {
+        object.get_object_instance_resources().iter().find_map(|(resource_id, resource)| {
+            let resource_lock = resource.read();
+            let is_poisoned = resource_lock.is_err();
+            let resource_lock = resource_lock.unwrap_or_else(PoisonError::into_inner);
+            if resource_lock.get_descriptor_set_layout_binding().binding != 0 {
+                return None;
             }
-        });
+            let buffer = if is_poisoned {
+                get_snapshot_data(*resource_id)?
+            } else {
+                match resource_lock.get_resource() {
+                    ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) | ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(buffer) => buffer,
+                }
+            };
+            (buffer.len() >= std::mem::size_of::<glm::Mat4>()).then(|| glm::Mat4::from_iterator(buffer.chunks_exact(std::mem::size_of::<f32>()).take(16).map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))))
+        }).unwrap_or_else(glm::identity)
+    }
+
+    // Möller–Trumbore, both sides of the triangle are hit
+    fn intersect_ray_triangle(origin: &glm::Vec3, direction: &glm::Vec3, triangle: &[glm::Vec3; 3]) -> Option<f32> {
+        let edge_one = triangle[1] - triangle[0];
+        let edge_two = triangle[2] - triangle[0];
+        let p = direction.cross(&edge_two);
+        let determinant = edge_one.dot(&p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let t = origin - triangle[0];
+        let u = t.dot(&p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = t.cross(&edge_one);
+        let v = direction.dot(&q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge_two.dot(&q) * inverse_determinant;
+        (distance > f32::EPSILON).then_some(distance)
     }
 
     fn get_object_types(&self) -> HashSet<ObjectType> {
         self.descriptor_sets.iter().map(|(o, _)| o.clone()).collect()
     }
 
-    fn destroy(self, device: &Device, descriptor_pool: &DescriptorPool, allocator: &mut VkAllocator) {
+    // The vertex and index buffers are shared by all the object types with the same mesh
+    fn get_object_debug_handles(&self, object_id: ObjectID, mesh_registry: &MeshRegistry) -> Option<Vec<(vk::ObjectType, u64, String)>> {
+        let object_type = self.objects.get(&object_id)?.0;
+        let mut handles = mesh_registry.get_debug_handles(*self.object_type_meshes.get(&object_type)?);
+        handles.extend(self.get_object_type_debug_handles(object_type));
+        Some(handles)
+    }
+
+    fn get_object_type_debug_handles(&self, object_type: ObjectType) -> Vec<(vk::ObjectType, u64, String)> {
+        let mut handles = Vec::new();
+        if let Some(descriptor_sets) = self.descriptor_sets.get(&object_type) {
+            for (frame, descriptor_set) in descriptor_sets.iter().enumerate() {
+                handles.push((vk::ObjectType::DESCRIPTOR_SET, descriptor_set.as_raw(), format!("descriptor set (frame {})", frame)));
+            }
+        }
+        for ((_, resource_id), allocation) in self.uniform_buffers.iter().filter(|((o, _), _)| *o == object_type) {
+            handles.push((vk::ObjectType::BUFFER, allocation.get_buffer().unwrap().as_raw(), format!("uniform buffer (resource {})", resource_id.0)));
+        }
+        for ((_, resource_id), (allocation, _)) in self.storage_buffers.iter().filter(|((o, _), _)| *o == object_type) {
+            handles.push((vk::ObjectType::BUFFER, allocation.get_buffer().unwrap().as_raw(), format!("storage buffer (resource {})", resource_id.0)));
+        }
+        for ((_, resource_id), (allocation, _)) in self.textures.iter().filter(|((o, _), _)| *o == object_type) {
+            handles.push((vk::ObjectType::IMAGE, allocation.get_image().unwrap().as_raw(), format!("texture (resource {})", resource_id.0)));
+        }
+        handles
+    }
+
+    fn destroy(self, device: &Device, descriptor_pool: &DescriptorPool, texture_cache: &mut TextureCache, mesh_registry: &mut MeshRegistry, allocator: &VkAllocator) {
         let mut error_str = String::new();
-        free_allocations_add_error_string!(allocator, vec![self.vertices.0, self.indices.0], error_str);
+        for (_, mesh) in self.object_type_meshes {
+            free_allocations_add_error_string!(allocator, mesh_registry.release(mesh), error_str);
+        }
         for (_, (allocation, _)) in self.textures {
-            free_allocations_add_error_string!(allocator, vec![allocation], error_str);
+            if let Some(allocation) = texture_cache.release(allocation) {
+                free_allocations_add_error_string!(allocator, vec![allocation], error_str);
+            }
         }
         for (_, allocation) in self.uniform_buffers {
             free_allocations_add_error_string!(allocator, vec![allocation], error_str);
@@ -674,15 +2491,16 @@ impl DataUsedInShader {
                         device.free_descriptor_sets(*descriptor_pool, &descriptor_sets).unwrap();
                     }
                 },
+                DataToRemove::Removal(_) => {},
             }
         }
         if !error_str.is_empty() {
-            eprintln!("Error when freeing allocations: {}", error_str);
+            log::error!(target: logging::OBJECTS, "Error when freeing allocations: {}", error_str);
         }
         
     }
 
-    fn create_descriptor_sets(device: &Device, descriptor_pool: &DescriptorPool, descriptor_set_layout: &DescriptorSetLayout, object_types: &HashSet<ObjectType>, descriptor_type_data: &[(ResourceID, DescriptorType, DescriptorSetLayoutBinding)], uniform_buffers: &HashMap<(ObjectType, ResourceID), AllocationInfo>, textures: &HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, storage_buffers: &HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, frames_in_flight: u32) -> HashMap<ObjectType, Vec<DescriptorSet>> {
+    fn create_descriptor_sets(device: &Device, descriptor_pool: &DescriptorPool, descriptor_set_layout: &DescriptorSetLayout, object_types: &HashSet<ObjectType>, descriptor_type_data: &[(ResourceID, DescriptorType, DescriptorSetLayoutBinding)], uniform_buffers: &HashMap<(ObjectType, ResourceID), AllocationInfo>, textures: &HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, storage_buffers: &HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, dynamic_uniform_buffer_strides: &HashMap<ObjectType, usize>, frames_in_flight: u32) -> Result<HashMap<ObjectType, Vec<DescriptorSet>>, EngineError> {
         let mut descriptor_sets = HashMap::new();
 
         for object_type in object_types {
@@ -696,8 +2514,8 @@ impl DataUsedInShader {
             };
     
             let descriptor_sets_local = unsafe {
-                device.allocate_descriptor_sets(&alloc_info).unwrap()
-            };
+                device.allocate_descriptor_sets(&alloc_info)
+            }.map_err(|result| Self::get_descriptor_set_allocation_error(result, frames_in_flight))?;
     
             for i in 0..frames_in_flight {
                 let num_resources = descriptor_type_data.len();
@@ -712,8 +2530,7 @@ impl DataUsedInShader {
                         DescriptorType::UNIFORM_BUFFER => {
                             let allocation_info = uniform_buffers.get(&(*object_type, *resource_id)).expect("Uniform buffer not found for object type. This should never happen. Was the uniform buffer added to the object type?");
                             let offset = unsafe {allocation_info.get_uniform_pointers()[i as usize].offset_from(allocation_info.get_uniform_pointers()[0])} as u64;
-                            let size = (allocation_info.get_memory_end()-allocation_info.get_memory_start())/allocation_info.get_uniform_pointers().len().max(1) as u64;
-                            // println!("Offset: {}, size: {}", offset , size);
+                            let size = allocation_info.get_uniform_buffer_size();
                             let buffer = allocation_info.get_buffer().unwrap();
                             let buffer_info = DescriptorBufferInfo {
                                 buffer,
@@ -739,7 +2556,7 @@ impl DataUsedInShader {
                         DescriptorType::STORAGE_BUFFER => {
                             let (allocation_info, _) = storage_buffers.get(&(*object_type, *resource_id)).expect("Dynamic uniform buffer not found for object type. This should never happen. Was the storage buffer added to the object type?");
                             let offset = unsafe {allocation_info.get_uniform_pointers()[i as usize].offset_from(allocation_info.get_uniform_pointers()[0])} as u64;
-                            let size = (allocation_info.get_memory_end()-allocation_info.get_memory_start())/allocation_info.get_uniform_pointers().len().max(1) as u64;
+                            let size = allocation_info.get_uniform_buffer_size();
                             let buffer = allocation_info.get_buffer().unwrap();
                             let buffer_info = DescriptorBufferInfo {
                                 buffer,
@@ -762,8 +2579,34 @@ impl DataUsedInShader {
                                 ..Default::default()
                             }
                         },
+                        DescriptorType::UNIFORM_BUFFER_DYNAMIC => {
+                            let (allocation_info, _) = storage_buffers.get(&(*object_type, *resource_id)).expect("Dynamic uniform buffer not found for object type. This should never happen. Was the dynamic uniform buffer added to the object type?");
+                            let offset = unsafe {allocation_info.get_uniform_pointers()[i as usize].offset_from(allocation_info.get_uniform_pointers()[0])} as u64;
+                            // The range is one instance, the dynamic offset moves it to the instance that is drawn
+                            let buffer_info = DescriptorBufferInfo {
+                                buffer: allocation_info.get_buffer().unwrap(),
+                                offset,
+                                range: *dynamic_uniform_buffer_strides.get(object_type).expect("Dynamic uniform buffer stride not found for object type. This should never happen!") as u64,
+                            };
+
+                            buffer_infos.push(buffer_info);
+                            let buffer_info = buffer_infos.last().unwrap();
+                            vk::WriteDescriptorSet {
+                                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                                dst_set: descriptor_sets_local[i as usize],
+                                dst_binding: layout_binding.binding,
+                                dst_array_element: 0,
+                                descriptor_type: DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                                descriptor_count: 1,
+                                p_buffer_info: buffer_info,
+                                ..Default::default()
+                            }
+                        },
                         DescriptorType::COMBINED_IMAGE_SAMPLER => {
-                            let (allocation_info, sampler) = textures.get(&(*object_type, *resource_id)).expect("Texture not found for object type. This should never happen. Was the texture added to the object type?");
+                            // Render targets are written before each frame by update_render_target_descriptors
+                            let Some((allocation_info, sampler)) = textures.get(&(*object_type, *resource_id)) else {
+                                continue;
+                            };
                             let image_info = DescriptorImageInfo {
                                 sampler: sampler.clone(),
                                 image_view: allocation_info.get_image_view().unwrap(),
@@ -800,15 +2643,24 @@ impl DataUsedInShader {
             descriptor_sets.insert(*object_type, descriptor_sets_local);
         }
 
-        descriptor_sets
+        Ok(descriptor_sets)
+    }
+
+    // The pool only has room for the sets of VkController::MAX_OBJECT_TYPES object types, so running out of it is logged with what was asked for
+    fn get_descriptor_set_allocation_error(result: vk::Result, num_sets: u32) -> EngineError {
+        if matches!(result, vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) {
+            log::error!(target: logging::OBJECTS, "The descriptor pool is exhausted, so the {} descriptor sets of an object type could not be allocated: {}", num_sets, result);
+        }
+        EngineError::vulkan("Failed to allocate the descriptor sets of an object type", result)
     }
 
     fn get_object_type_data_and_num_instances(objects_to_add: &[(ObjectID, Box<dyn Renderable>)]) -> (HashMap<ObjectType, ReferenceObjectID>, HashMap<ObjectType, (NumInstances, NumIndices)>) {
         let mut object_type_data = HashMap::new();
         let mut object_type_num_instances = HashMap::new();
         objects_to_add.iter().for_each(|(object_id, object)| {
-            let object_type = ObjectType(object.get_vertices_and_indices_hash());
-            let e = object_type_num_instances.entry(object_type).or_insert((NumInstances(0), NumIndices(object.get_indices().len())));
+            let object_type = ObjectType::of(object.as_ref());
+            let num_indices = object.get_submesh_indices().map(|(_, num_indices)| num_indices as usize).unwrap_or_else(|| object.get_indices().len());
+            let e = object_type_num_instances.entry(object_type).or_insert((NumInstances(0), NumIndices(num_indices)));
             e.0.0 += 1;
             if object_type_data.contains_key(&object_type) {
                 return;
@@ -818,15 +2670,26 @@ impl DataUsedInShader {
         (object_type_data, object_type_num_instances)
     }
 
-    fn create_storage_buffer(object_type: ObjectType, resource_id: ResourceID, num_instances: NumInstances, buffer: Vec<u8>, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
-        let allocation = match allocator.create_storage_buffers(num_instances.0 as usize * buffer.len(), VkController::MAX_FRAMES_IN_FLIGHT) {
+    fn get_object_types_in_insertion_order(objects_to_add: &[(ObjectID, Box<dyn Renderable>)]) -> Vec<ObjectType> {
+        let mut object_types = Vec::new();
+        objects_to_add.iter().for_each(|(_, object)| {
+            let object_type = ObjectType::of(object.as_ref());
+            if !object_types.contains(&object_type) {
+                object_types.push(object_type);
+            }
+        });
+        object_types
+    }
+
+    fn create_storage_buffer(object_type: ObjectType, resource_id: ResourceID, num_instances: NumInstances, buffer: Vec<u8>, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, texture_cache: &mut TextureCache, frames_in_flight: usize, allocator: &VkAllocator) -> Result<(), EngineError> {
+        let allocation = match allocator.create_storage_buffers(num_instances.0 as usize * buffer.len(), frames_in_flight) {
             Ok(alloc) => alloc,
             Err(e) => {
-                let mut error_str = e.to_string();
+                let mut error_str = String::new();
                 let mut allocations = Vec::new();
-                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, &mut allocations);
+                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, texture_cache, &mut allocations);
                 free_allocations_add_error_string!(allocator, allocations, error_str);
-                return Err(Cow::from(error_str));
+                return Err(e.with_cleanup_errors(error_str));
             },
         };
 
@@ -834,75 +2697,146 @@ impl DataUsedInShader {
         Ok(())
     }
 
-    fn add_object_vertices_and_indices_if_new_object_type(object_type: ObjectType, reference_object: &Box<dyn Renderable>, object_type_vertices_bytes_indices: &mut HashMap<ObjectType, (Inclusive, Exclusive)>, object_type_indices_bytes_indices: &mut HashMap<ObjectType, (Inclusive, Exclusive)>, vertices_data: &mut Vec<u8>, indices_data: &mut Vec<u8>) -> Result<(), Cow<'static, str>> {
-        if !object_type_vertices_bytes_indices.contains_key(&object_type) {
-            let object_vertices_data = reference_object.get_vertex_byte_data();
-            let object_indices = reference_object.get_indices();
-            let object_indices_data = object_indices.iter().map(|x| x.to_ne_bytes()).flatten().collect::<Vec<u8>>();
-            object_type_vertices_bytes_indices.insert(object_type, (Inclusive(vertices_data.len()), Exclusive((vertices_data.len() + object_vertices_data.len()) - 1)));
-            vertices_data.extend_from_slice(&object_vertices_data);
-            object_type_indices_bytes_indices.insert(object_type, (Inclusive(indices_data.len()), Exclusive((indices_data.len() + object_indices.len()) - 1)));    
-            indices_data.extend_from_slice(&object_indices_data);
-        }
-        Ok(())
-    }
-
-    fn create_and_add_static_texture(object_type: ObjectType, resource_id: ResourceID, image: DynamicImage, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, graphics_queue: &Queue, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, sampler_manager: &mut SamplerManager, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
-        let mut allocation = match allocator.create_device_local_image(image, command_pool, graphics_queue, u32::MAX, vk::SampleCountFlags::TYPE_1, false) {
+    fn create_dynamic_uniform_buffer(object_type: ObjectType, resource_id: ResourceID, num_instances: NumInstances, stride: usize, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, texture_cache: &mut TextureCache, frames_in_flight: usize, allocator: &VkAllocator) -> Result<(), EngineError> {
+        let allocation = match allocator.create_uniform_buffers(num_instances.0 * stride, frames_in_flight) {
             Ok(alloc) => alloc,
             Err(e) => {
-                let mut error_str = e.to_string();
+                let mut error_str = String::new();
                 let mut allocations = Vec::new();
-                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, &mut allocations);
+                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, texture_cache, &mut allocations);
                 free_allocations_add_error_string!(allocator, allocations, error_str);
-                return Err(Cow::from(error_str));
+                return Err(e.with_cleanup_errors(error_str));
             },
         };
-        let mip_levels = allocation.get_mip_levels().unwrap();
-        // The format needs to be the same as the format read in [`VkAllocator::create_device_local_image`]
-        match allocator.create_image_view(&mut allocation, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, mip_levels) {
-            Ok(_) => (),
+
+        new_storage_buffers.insert((object_type, resource_id), (allocation, vec![0; num_instances.0 * stride]));
+        Ok(())
+    }
+
+    // Each instance starts at a multiple of 256, which is the largest minUniformBufferOffsetAlignment Vulkan allows, so the dynamic offsets are valid on every device.
+    // The largest dynamic uniform buffer of the object type decides the stride of all of them, so one offset per instance works for all of them
+    fn get_dynamic_uniform_buffer_stride(object: &dyn Renderable) -> usize {
+        object.get_object_instance_resources().iter().filter_map(|(_, resource)| match resource.read().unwrap_or_else(PoisonError::into_inner).get_resource() {
+            ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(buffer) => Some(buffer.len().next_multiple_of(Self::DYNAMIC_UNIFORM_BUFFER_ALIGNMENT)),
+            ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(_) => None,
+        }).max().unwrap_or(0)
+    }
+
+    // Takes a reference to the mesh of every object type. When a mesh can't be uploaded, the references taken before it are dropped again
+    fn acquire_meshes<'a>(object_types: impl Iterator<Item = (ObjectType, &'a dyn Renderable)>, mesh_registry: &mut MeshRegistry, command_pool: &vk::CommandPool, graphics_queue: &Queue, allocator: &VkAllocator) -> Result<HashMap<ObjectType, VerticesIndicesHash>, EngineError> {
+        let object_types = object_types.map(|(object_type, object)| (object_type, object.get_vertices_and_indices_hash(), object)).collect::<Vec<_>>();
+        // Only the serialization is parallel, the uploads are made in the order of the object types
+        let mut serialized_meshes = mesh_registry.serialize_new_meshes(object_types.iter().map(|(_, _, object)| *object));
+        let mut object_type_meshes = HashMap::new();
+        for (object_type, mesh, _) in object_types {
+            match mesh_registry.acquire(mesh, &mut serialized_meshes, command_pool, graphics_queue, allocator) {
+                Ok(mesh) => {
+                    object_type_meshes.insert(object_type, mesh);
+                },
+                Err(e) => {
+                    // Nothing has drawn with the meshes yet, so they can be freed right away
+                    let mut error_str = String::new();
+                    for (_, mesh) in object_type_meshes {
+                        free_allocations_add_error_string!(allocator, mesh_registry.release(mesh), error_str);
+                    }
+                    return Err(e.with_cleanup_errors(error_str));
+                },
+            }
+        }
+        Ok(object_type_meshes)
+    }
+
+    // One image is a 2D texture and six images are the faces of a cube map
+    /// Uses the placeholder while the images load and the missing texture instead of images that can't be uploaded, and adds the texture to `new_fallback_textures` with the reason.
+    fn create_and_add_static_texture(object_type: ObjectType, resource_id: ResourceID, images: Vec<DynamicImage>, asset_key: Option<String>, load_state: ResourceLoadState, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, graphics_queue: &Queue, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_fallback_textures: &mut HashMap<(ObjectType, ResourceID), FallbackTexture>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, sampler_manager: &mut SamplerManager, texture_cache: &mut TextureCache, allocator: &VkAllocator) -> Result<(), EngineError> {
+        let is_loading = load_state == ResourceLoadState::Loading;
+        let invalid_reason = match load_state {
+            ResourceLoadState::Ready => Self::get_invalid_texture_reason(&images),
+            ResourceLoadState::Loading => None,
+            ResourceLoadState::Failed(reason) => Some(Cow::Owned(reason)),
+        };
+        let (images, asset_key) = match invalid_reason {
+            Some(reason) => {
+                log::warn!(target: logging::OBJECTS, "Texture {:?} of object type {:?} is drawn as the missing texture: {}", resource_id, object_type, reason);
+                new_fallback_textures.insert((object_type, resource_id), FallbackTexture::Missing(reason));
+                TextureCache::get_missing_texture(images.len() == 6)
+            },
+            None if is_loading => {
+                new_fallback_textures.insert((object_type, resource_id), FallbackTexture::Loading);
+                TextureCache::get_placeholder_texture(images.len() == 6)
+            },
+            None => (images, asset_key),
+        };
+        let cache_key = texture_cache.get_key(&images, asset_key);
+        if let Some(cached_texture) = texture_cache.acquire(&cache_key) {
+            new_textures.insert((object_type, resource_id), cached_texture);
+            return Ok(());
+        }
+
+        let full_quality_bytes = images.iter().map(TextureCache::get_full_quality_bytes).sum();
+        let allocation = match TextureCache::upload_texture(images, texture_cache.quality, texture_cache.max_mip_levels, command_pool, graphics_queue, allocator) {
+            Ok(alloc) => alloc,
             Err(e) => {
-                let mut error_str = e.to_string();
+                let mut error_str = String::new();
                 let mut allocations = Vec::new();
-                allocations.push(allocation);
-                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, &mut allocations);
+                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, texture_cache, &mut allocations);
                 free_allocations_add_error_string!(allocator, allocations, error_str);
-                return Err(Cow::from(error_str));
+                return Err(e.with_cleanup_errors(error_str));
             },
-        }
-        
-        let sampler_config = SamplerConfig {
-            s_type: StructureType::SAMPLER_CREATE_INFO,
-            mag_filter: vk::Filter::LINEAR,
-            min_filter: vk::Filter::LINEAR,
-            address_mode_u: vk::SamplerAddressMode::REPEAT,
-            address_mode_v: vk::SamplerAddressMode::REPEAT,
-            address_mode_w: vk::SamplerAddressMode::REPEAT,
-            anisotropy_enable: vk::TRUE,
-            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
-            unnormalized_coordinates: vk::FALSE,
-            compare_enable: vk::FALSE,
-            compare_op: vk::CompareOp::ALWAYS,
-            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
-            mip_lod_bias: 0.0,
-            min_lod: 0.0,
-            max_lod: allocation.get_mip_levels().unwrap() as f32,
         };
+        let sampler_config = TextureCache::get_sampler_config(&allocation);
         let sampler = sampler_manager.get_or_create_sampler(device, instance, physical_device, sampler_config, allocator)?;
+        texture_cache.insert(cache_key, unsafe { allocation.duplicate_handle() }, sampler, full_quality_bytes);
         new_textures.insert((object_type, resource_id), (allocation, sampler));
         Ok(())
     }
 
-    fn create_and_add_static_uniform_buffer(object_type: ObjectType, resource_id: ResourceID, buffer: &[u8], current_frame: usize, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, allocator: &mut VkAllocator) -> Result<(), Cow<'static, str>> {
-        let allocation = match allocator.create_uniform_buffers(buffer.len(), VkController::MAX_FRAMES_IN_FLIGHT) {
+    pub fn get_invalid_texture_reason(images: &[DynamicImage]) -> Option<Cow<'static, str>> {
+        if images.iter().any(|image| image.width() == 0 || image.height() == 0) {
+            return Some(Cow::Borrowed("The image is empty"));
+        }
+        if images.len() == 6 && images.iter().any(|face| face.width() != face.height() || face.width() != images[0].width()) {
+            return Some(Cow::Borrowed("The faces of the cube map have to be squares of the same size"));
+        }
+        None
+    }
+
+    // The fallback textures whose resource has an image that can be uploaded now, or has finished loading
+    fn has_fixed_fallback_texture(&self) -> bool {
+        self.fallback_textures.iter().any(|((object_type, resource_id), fallback_texture)| {
+            let Some(reference_id) = self.object_type_references.get(object_type) else {
+                return false;
+            };
+            let Some((_, reference_object)) = self.objects.get(&reference_id.0) else {
+                return false;
+            };
+            let Some((_, resource)) = reference_object.get_type_resources().into_iter().find(|(id, _)| id == resource_id) else {
+                return false;
+            };
+            let resource = resource.read().unwrap_or_else(PoisonError::into_inner);
+            match (fallback_texture, resource.get_load_state()) {
+                // A failed load is replaced by the missing texture
+                (FallbackTexture::Loading, load_state) => return load_state != ResourceLoadState::Loading,
+                (FallbackTexture::Missing(_), ResourceLoadState::Ready) => (),
+                (FallbackTexture::Missing(_), _) => return false,
+            }
+            match resource.get_resource() {
+                ObjectTypeGraphicsResourceType::Texture(image, _) => Self::get_invalid_texture_reason(&[image]).is_none(),
+                ObjectTypeGraphicsResourceType::CubeMap(faces, _) => Self::get_invalid_texture_reason(&faces).is_none(),
+                ObjectTypeGraphicsResourceType::UniformBuffer(_) | ObjectTypeGraphicsResourceType::RenderTarget(_) => false,
+            }
+        })
+    }
+
+    fn create_and_add_static_uniform_buffer(object_type: ObjectType, resource_id: ResourceID, buffer: &[u8], current_frame: usize, new_textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, new_uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, new_storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, texture_cache: &mut TextureCache, frames_in_flight: usize, allocator: &VkAllocator) -> Result<(), EngineError> {
+        let allocation = match allocator.create_uniform_buffers(buffer.len(), frames_in_flight) {
             Ok(alloc) => alloc,
             Err(e) => {
-                let mut error_str = e.to_string();
+                let mut error_str = String::new();
                 let mut allocations = Vec::new();
-                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, &mut allocations);
+                Self::add_hashmap_allocations_to_free(new_textures, new_uniform_buffers, new_storage_buffers, texture_cache, &mut allocations);
                 free_allocations_add_error_string!(allocator, allocations, error_str);
-                return Err(Cow::from(error_str));
+                return Err(e.with_cleanup_errors(error_str));
             },
         };
 
@@ -914,40 +2848,103 @@ impl DataUsedInShader {
         Ok(())
     }
 
-    fn create_storage_buffer_byte_indices(objects_to_add: &[(&ObjectID, &(ObjectType, Box<dyn Renderable>))], object_id_storage_buffer_bytes_indices: &mut HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>) {
-        let mut number_of_allocated_storage_buffers_per_object_and_resource_id = HashMap::new();
-        objects_to_add.iter().for_each(|(object_id, (object_type, object))| {
+    // Every instance resource of an object is at the object's slot in the buffers of its object type
+    fn create_storage_buffer_byte_indices<'a>(objects: impl Iterator<Item = (&'a ObjectID, &'a (ObjectType, Box<dyn Renderable>))>, object_slots: &HashMap<ObjectID, u32>, object_id_storage_buffer_bytes_indices: &mut HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>) {
+        objects.for_each(|(object_id, (_, object))| {
+            let slot = *object_slots.get(object_id).expect("Slot not found for object id. This should never happen. Was the object given a slot when it was added?") as usize;
             object.get_object_instance_resources().iter().for_each(|(resource_id, resource)| {
-                let resource_lock = resource.read().unwrap();
-                match resource_lock.get_resource() {
-                    ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) => {
-                        let current_resource_allocation_number = number_of_allocated_storage_buffers_per_object_and_resource_id.entry((object_type, *resource_id)).or_insert(0);
-                        object_id_storage_buffer_bytes_indices.insert((**object_id, *resource_id), (Inclusive(*current_resource_allocation_number as usize *buffer.len()), Exclusive(((*current_resource_allocation_number + 1) as usize * buffer.len()) - 1)));
-                        *current_resource_allocation_number += 1;
-                    }
-                }
+                let resource_lock = resource.read().unwrap_or_else(PoisonError::into_inner);
+                let instance_size = match resource_lock.get_resource() {
+                    ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) => buffer.len(),
+                    // The range includes the padding up to the next instance
+                    ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(_) => Self::get_dynamic_uniform_buffer_stride(object.as_ref()),
+                };
+                object_id_storage_buffer_bytes_indices.insert((*object_id, *resource_id), (Inclusive(slot * instance_size), Exclusive((slot + 1) * instance_size)));
             });
         });
     }
 
-    fn copy_storage_buffer_data_to_gpu(objects: &HashMap<ObjectID, (ObjectType, Box<dyn Renderable>)>, storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, object_id_storage_buffer_bytes_indices: &HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>, current_frame: usize) {
-        objects.iter().for_each(|(object_id, (object_type, object))| {
+    fn copy_storage_buffer_data_to_gpu<'a>(objects: impl Iterator<Item = (&'a ObjectID, &'a (ObjectType, Box<dyn Renderable>))>, storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, object_id_storage_buffer_bytes_indices: &HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>, poisoned_resources: &mut HashSet<usize>, current_frame: usize) {
+        Self::snapshot_storage_buffer_data(objects, storage_buffers, object_id_storage_buffer_bytes_indices, poisoned_resources);
+        Self::upload_storage_buffer_data(storage_buffers, current_frame);
+    }
+
+    // A thread that panicked while writing poisons the lock and can have left the data half written, so None is returned and the last snapshot of it is used instead
+    fn read_resource<T: ?Sized, R>(resource: &Arc<RwLock<T>>, read: impl FnOnce(&T) -> R, poisoned_resources: &mut HashSet<usize>) -> Option<R> {
+        match resource.read() {
+            Ok(resource_lock) => Some(read(&resource_lock)),
+            Err(_) => {
+                if poisoned_resources.insert(Arc::as_ptr(resource) as *const () as usize) {
+                    log::error!(target: logging::OBJECTS, "The lock of a resource is poisoned, since a thread panicked while writing to it. The data it had before that is drawn instead");
+                }
+                None
+            },
+        }
+    }
+
+    fn snapshot_storage_buffer_data<'a>(objects: impl Iterator<Item = (&'a ObjectID, &'a (ObjectType, Box<dyn Renderable>))>, storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, object_id_storage_buffer_bytes_indices: &HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>, poisoned_resources: &mut HashSet<usize>) {
+        objects.for_each(|(object_id, (object_type, object))| {
             for (resource_id, resource) in object.get_object_instance_resources() {
-                let resource_lock = resource.read().unwrap();
-                match resource_lock.get_resource() {
+                let Some(resource_data) = Self::read_resource(&resource, |resource| resource.get_resource(), poisoned_resources) else {
+                    continue;
+                };
+                match resource_data {
                     ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(buffer) => {
                         let (_, alloc_buffer) = storage_buffers.get_mut(&(*object_type, resource_id)).expect("Dynamic uniform buffer not found for object type. This should never happen. Was the storage buffer added to the object type?");
                         let (start, end) = object_id_storage_buffer_bytes_indices.get(&(*object_id, resource_id)).expect("Dynamic uniform buffer bytes indices not found for object id. This should never happen. Was the storage buffer added to the object id?");
-                        if buffer.len() != (end.0 - start.0 + 1) as usize {
-                            eprintln!("The storage buffer size does not match the size of the buffer that was allocated for it. This should never happen.");
+                        if buffer.len() != end.0 - start.0 {
+                            log::error!(target: logging::OBJECTS, "The storage buffer size does not match the size of the buffer that was allocated for it. This should never happen.");
                         }
-                        // dbg!(alloc_buffer.len(), start.0, end.0, buffer.len());
-                        alloc_buffer[(start.0 as usize)..(end.0 as usize + 1)].copy_from_slice(&buffer[0..((end.0 - start.0 + 1))]);
+                        alloc_buffer[start.0..end.0].copy_from_slice(&buffer[0..(end.0 - start.0)]);
+                    },
+                    ObjectInstanceGraphicsResourceType::DynamicUniformBuffer(buffer) => {
+                        let (_, alloc_buffer) = storage_buffers.get_mut(&(*object_type, resource_id)).expect("Dynamic uniform buffer not found for object type. This should never happen. Was the dynamic uniform buffer added to the object type?");
+                        let (start, end) = object_id_storage_buffer_bytes_indices.get(&(*object_id, resource_id)).expect("Dynamic uniform buffer bytes indices not found for object id. This should never happen. Was the dynamic uniform buffer added to the object id?");
+                        let size = buffer.len().min(end.0 - start.0);
+                        alloc_buffer[start.0..start.0 + size].copy_from_slice(&buffer[..size]);
                     },
                 }
             }
         });
+    }
+
+    fn mark_instance_dirty(&mut self, object_id: ObjectID) {
+        self.dirty_objects.insert(object_id, self.frames_in_flight as u32);
+    }
+
+    // The byte ranges of the dirty instances are merged where they touch, so neighbouring instances are copied together
+    fn upload_dirty_storage_buffer_ranges(&self, current_frame: usize) {
+        let mut dirty_ranges: HashMap<(ObjectType, ResourceID), Vec<(usize, usize)>> = HashMap::new();
+        for object_id in self.dirty_objects.keys() {
+            let Some((object_type, _)) = self.objects.get(object_id) else {
+                continue;
+            };
+            for (key, _) in self.storage_buffers.iter().filter(|((buffer_object_type, _), _)| buffer_object_type == object_type) {
+                if let Some((start, end)) = self.object_id_storage_buffer_bytes_indices.get(&(*object_id, key.1)) {
+                    dirty_ranges.entry(*key).or_default().push((start.0, end.0));
+                }
+            }
+        }
+
+        for (key, mut ranges) in dirty_ranges {
+            let (allocation_info, buffer) = self.storage_buffers.get(&key).unwrap();
+            ranges.sort_unstable();
+            let mut merged_ranges: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+            for (start, end) in ranges {
+                match merged_ranges.last_mut() {
+                    Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+                    _ => merged_ranges.push((start, end)),
+                }
+            }
+            for (start, end) in merged_ranges {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(buffer[start..end].as_ptr(), (allocation_info.get_uniform_pointers()[current_frame] as *mut u8).add(start), end - start);
+                }
+            }
+        }
+    }
 
+    fn upload_storage_buffer_data(storage_buffers: &HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, current_frame: usize) {
         storage_buffers.iter().for_each(|(_, (allocation_info, buffer))| {
             unsafe {
                 std::ptr::copy_nonoverlapping(buffer.as_ptr() as *const std::ffi::c_void, allocation_info.get_uniform_pointers()[current_frame], buffer.len());
@@ -955,9 +2952,9 @@ impl DataUsedInShader {
         });
     }
 
-    fn add_hashmap_allocations_to_free(textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, allocations: &mut Vec<AllocationInfo>) {
+    fn add_hashmap_allocations_to_free(textures: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Sampler)>, uniform_buffers: &mut HashMap<(ObjectType, ResourceID), AllocationInfo>, storage_buffers: &mut HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, texture_cache: &mut TextureCache, allocations: &mut Vec<AllocationInfo>) {
         for (_, (allocation, _)) in textures.drain() {
-            allocations.push(allocation);
+            allocations.extend(texture_cache.release(allocation));
         }
         for (_, allocation) in uniform_buffers.drain() {
             allocations.push(allocation);
@@ -967,41 +2964,522 @@ impl DataUsedInShader {
         }
     }
 
-    fn update(&mut self, device: &Device, descriptor_pool: &DescriptorPool, current_frame: usize, allocator: &mut VkAllocator) {
+    fn update(&mut self, device: &Device, render_target_textures: &HashMap<RenderTargetId, (vk::ImageView, Sampler)>, is_partial_instance_upload_enabled: bool, current_frame: usize) {
         // Update the uniform data
-        self.update_all_uniform_data(current_frame);
-        // Update the allocations to remove counter and free allocations that are not used
-        self.update_allocation_to_remove_counter_and_free_allocations_that_are_not_used(device, descriptor_pool, current_frame, allocator);
+        self.update_all_uniform_data(is_partial_instance_upload_enabled, current_frame);
+        self.update_render_target_descriptors(device, render_target_textures, current_frame);
     }
 
-    fn update_allocation_to_remove_counter_and_free_allocations_that_are_not_used(&mut self, device: &Device, descriptor_pool: &DescriptorPool, current_frame: usize, allocator: &mut VkAllocator) {
-        let last_frame_index = LastFrameIndex(current_frame);
-        if last_frame_index.0 == self.allocations_and_descriptor_sets_to_remove.0.0 {
+    // The descriptor sets of the current frame are not used by any frame in flight, so they can be written even when a render target got new images
+    fn update_render_target_descriptors(&self, device: &Device, render_target_textures: &HashMap<RenderTargetId, (vk::ImageView, Sampler)>, current_frame: usize) {
+        let image_infos = self.render_target_bindings.iter().filter_map(|((object_type, _), (render_target_id, binding))| {
+            let Some((image_view, sampler)) = render_target_textures.get(render_target_id) else {
+                log::error!(target: logging::OBJECTS, "The render target {:?} used by the object type {:?} does not exist", render_target_id, object_type);
+                return None;
+            };
+            let descriptor_set = self.descriptor_sets.get(object_type)?[current_frame];
+            Some((descriptor_set, *binding, DescriptorImageInfo {
+                sampler: *sampler,
+                image_view: *image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }))
+        }).collect::<Vec<_>>();
+        if image_infos.is_empty() {
             return;
         }
-        
-        self.allocations_and_descriptor_sets_to_remove.0 = last_frame_index;
+
+        let descriptor_writes = image_infos.iter().map(|(descriptor_set, binding, image_info)| WriteDescriptorSet {
+            s_type: StructureType::WRITE_DESCRIPTOR_SET,
+            dst_set: *descriptor_set,
+            dst_binding: *binding,
+            dst_array_element: 0,
+            descriptor_type: DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            p_image_info: image_info,
+            ..Default::default()
+        }).collect::<Vec<_>>();
+        unsafe {
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    fn queue_removal(&mut self, removal: RemovalHandle) {
+        self.allocations_and_descriptor_sets_to_remove.1.push((Counter(0), DataToRemove::Removal(removal)));
+    }
+
+    fn is_removal_queued(&self, removal: RemovalHandle) -> bool {
+        self.allocations_and_descriptor_sets_to_remove.1.iter().any(|(_, data_to_remove)| matches!(data_to_remove, DataToRemove::Removal(queued_removal) if *queued_removal == removal))
+    }
+
+    // The counters only advance once per frame, and the data is expired when every frame in flight that could use it has finished
+    fn take_expired_data_to_remove(data_to_remove: &mut (LastFrameIndex, Vec<(Counter, DataToRemove)>), frame_index: u64, frames_in_flight: usize) -> Vec<DataToRemove> {
+        let last_frame_index = LastFrameIndex(frame_index);
+        if last_frame_index == data_to_remove.0 {
+            return Vec::new();
+        }
+
+        data_to_remove.0 = last_frame_index;
+        data_to_remove.1.iter_mut().for_each(|(counter, _)| counter.increment());
+        // The expired entries are taken out of the queue before they are freed, so nothing can be freed twice
+        let (expired, pending): (Vec<_>, Vec<_>) = data_to_remove.1.drain(..).partition(|(counter, _)| counter.0 >= frames_in_flight);
+        data_to_remove.1 = pending;
+        expired.into_iter().map(|(_, data)| data).collect()
+    }
+
+    fn is_render_target_used(&self, render_target_id: RenderTargetId) -> bool {
+        self.render_target_bindings.values().any(|(id, _)| *id == render_target_id)
+    }
+
+    fn update_allocation_to_remove_counter_and_free_allocations_that_are_not_used(&mut self, device: &Device, descriptor_pool: &DescriptorPool, frame_index: u64, allocator: &VkAllocator) {
+        let expired = Self::take_expired_data_to_remove(&mut self.allocations_and_descriptor_sets_to_remove, frame_index, self.frames_in_flight);
+
         let mut descriptor_sets_to_remove = Vec::new();
-        self.allocations_and_descriptor_sets_to_remove.1.iter_mut().for_each(|(counter, data_to_remove)| {
-            counter.increment();
-            if counter.0 >= VkController::MAX_FRAMES_IN_FLIGHT {
-                match data_to_remove {
-                    DataToRemove::Allocation(alloc) => {
-                        allocator.free_memory_allocation(alloc.clone()).expect("Failed to free memory allocation. Which should never happen!");
-                    },
-                    DataToRemove::DescriptorSets(descriptor_sets) => {
-                        descriptor_sets_to_remove.extend(descriptor_sets.to_owned());
-                    },
-                }
+        for data_to_remove in expired {
+            match data_to_remove {
+                DataToRemove::Allocation(allocation) => {
+                    allocator.free_memory_allocation(allocation).expect("Failed to free memory allocation. Which should never happen!");
+                },
+                DataToRemove::DescriptorSets(descriptor_sets) => {
+                    descriptor_sets_to_remove.extend(descriptor_sets);
+                },
+                DataToRemove::Removal(_) => {},
             }
-        });
+        }
 
         if !descriptor_sets_to_remove.is_empty() {
             unsafe {
                 device.free_descriptor_sets(*descriptor_pool, &descriptor_sets_to_remove).expect("Failed to free descriptor sets. Which should never happen!");
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, time::{Duration, Instant}};
+
+    use crate::{gpu_layout::GpuLayout, graphics_objects::{GraphicsObject, LitRenderableObject}, lighting::LitVertex, logging::test_logger::{capture_logs, has_log}, pipeline_manager::ShaderSource, sprite::UvRect, vk_controller::Time};
+
+    use super::*;
+
+    fn get_sprite_animation_state(animation: SpriteAnimation) -> SpriteAnimationState {
+        let instance_data = UniformBufferResource {
+            buffer: SpriteInstanceData {
+                position_px: glm::vec2(10.0, 20.0),
+                size_px: glm::vec2(32.0, 32.0),
+                uv_rect: UvRect::FULL,
+                depth: 0.5,
+                rotation: 0.0,
+                scale: glm::vec2(1.0, 1.0),
+            },
+            binding: 0,
+        };
+        let mut sprite_animation = SpriteAnimationState {
+            instance_data: Arc::new(RwLock::new(instance_data)),
+            animation,
+            elapsed: 0.0,
+            is_paused: false,
+            is_finished: false,
+        };
+        sprite_animation.advance(0.0);
+        sprite_animation
+    }
+
+    // The frame whose UV rect is in the bytes the sprite's storage buffer is filled with
+    fn get_uploaded_frame_index(sprite_animation: &SpriteAnimationState) -> Option<usize> {
+        let instance_data = sprite_animation.instance_data.read().unwrap();
+        let ObjectInstanceGraphicsResourceType::DynamicStorageBuffer(bytes) = ObjectInstanceGraphicsResource::get_resource(&*instance_data) else {
+            panic!("Sprites are drawn from a storage buffer");
+        };
+        sprite_animation.animation.frames.iter().position(|uv_rect| SpriteInstanceData { uv_rect: *uv_rect, ..instance_data.buffer }.std430_bytes() == bytes)
+    }
+
+    // Advances the animation with the clock at each of the milliseconds after the start, and returns the uploaded frame after each of them
+    fn play(sprite_animation: &mut SpriteAnimationState, time: &mut Time, start: Instant, frame_times_ms: &[u64]) -> Vec<usize> {
+        frame_times_ms.iter().map(|frame_time_ms| {
+            time.update(start + Duration::from_millis(*frame_time_ms));
+            sprite_animation.advance(time.delta_seconds());
+            get_uploaded_frame_index(sprite_animation).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn looping_sprite_animation_uploads_the_frame_of_the_clock() {
+        // 4 frames per second, so a frame is shown for 250 ms. The times are exact in binary, so no frame is off by a rounding error
+        let mut sprite_animation = get_sprite_animation_state(SpriteAnimation::from_grid(2, 2, 4, 4.0, true));
+        assert_eq!(get_uploaded_frame_index(&sprite_animation), Some(0));
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        time.set_max_delta_seconds(0.25);
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[125, 250, 500, 750, 1000, 1125]), vec![0, 1, 2, 3, 0, 0]);
+        // A stall is clamped to the max delta, so the animation only moves one frame
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[5000]), vec![1]);
+        time.set_paused(true);
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[5250, 5500]), vec![1, 1]);
+        time.set_paused(false);
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[5625, 5875]), vec![2, 3]);
+        assert!(!sprite_animation.is_finished);
+    }
+
+    #[test]
+    fn finished_sprite_animation_keeps_uploading_its_last_frame() {
+        let mut sprite_animation = get_sprite_animation_state(SpriteAnimation::from_grid(3, 1, 3, 8.0, false));
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        time.set_max_delta_seconds(0.25);
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[125, 250, 375]), vec![1, 2, 2]);
+        assert!(sprite_animation.is_finished);
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[500, 1000]), vec![2, 2]);
+    }
+
+    #[test]
+    fn paused_sprite_animation_keeps_uploading_the_same_frame() {
+        let mut sprite_animation = get_sprite_animation_state(SpriteAnimation::from_grid(4, 1, 4, 8.0, true));
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        time.set_max_delta_seconds(0.25);
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[125]), vec![1]);
+        sprite_animation.is_paused = true;
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[250, 375]), vec![1, 1]);
+        sprite_animation.is_paused = false;
+        assert_eq!(play(&mut sprite_animation, &mut time, start, &[500]), vec![2]);
+    }
+
+    fn get_descriptor_set_ids(data_to_remove: &[DataToRemove]) -> Vec<u64> {
+        data_to_remove.iter().map(|data| match data {
+            DataToRemove::DescriptorSets(descriptor_sets) => descriptor_sets[0].as_raw(),
+            DataToRemove::Allocation(_) | DataToRemove::Removal(_) => panic!("Only descriptor sets are queued in the tests"),
+        }).collect()
+    }
+
+    #[test]
+    fn data_to_remove_is_freed_after_every_frame_in_flight_has_finished() {
+        let frames_in_flight = 2;
+        let mut data_to_remove = (LastFrameIndex(0), Vec::new());
+        // Queued between frame 0 and 1
+        data_to_remove.1.push((Counter(0), DataToRemove::DescriptorSets(vec![DescriptorSet::from_raw(1)])));
+
+        assert!(DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, 1, frames_in_flight).is_empty());
+        // Updating again in the same frame doesn't advance the counters
+        assert!(DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, 1, frames_in_flight).is_empty());
+        // Queued between frame 1 and 2
+        data_to_remove.1.push((Counter(0), DataToRemove::DescriptorSets(vec![DescriptorSet::from_raw(2)])));
+
+        // Frame 2 waits for frame 0, which is the last frame that could use the first data
+        assert_eq!(get_descriptor_set_ids(&DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, 2, frames_in_flight)), vec![1]);
+        assert_eq!(data_to_remove.1.len(), 1);
+        assert!(DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, 2, frames_in_flight).is_empty());
+        assert_eq!(get_descriptor_set_ids(&DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, 3, frames_in_flight)), vec![2]);
+        assert!(data_to_remove.1.is_empty());
+        assert!(DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, 4, frames_in_flight).is_empty());
+    }
+
+    #[test]
+    fn data_to_remove_waits_for_as_many_frames_as_there_are_in_flight() {
+        for frames_in_flight in 1..=3 {
+            let mut data_to_remove = (LastFrameIndex(10), vec![(Counter(0), DataToRemove::DescriptorSets(vec![DescriptorSet::from_raw(1)]))]);
+            let freed_in_frame = (11..20).find(|&frame_index| !DataUsedInShader::take_expired_data_to_remove(&mut data_to_remove, frame_index, frames_in_flight).is_empty());
+            assert_eq!(freed_in_frame, Some(10 + frames_in_flight as u64));
+        }
+    }
+
+    #[test]
+    fn removing_the_middle_instance_keeps_the_other_slots() {
+        let mut instance_slots = InstanceSlots::default();
+        let [first, middle, last] = [1, 2, 3].map(|id| instance_slots.insert(ObjectID(id)));
+        assert_eq!([first, middle, last], [0, 1, 2]);
+
+        instance_slots.remove(middle);
+        assert_eq!(instance_slots.objects.get(&first), Some(&ObjectID(1)));
+        assert_eq!(instance_slots.objects.get(&last), Some(&ObjectID(3)));
+        // The buffers keep room for the free slot, since a slot after it is still used
+        assert_eq!(instance_slots.get_num_slots(), 3);
+
+        // The free slot is given out before a new one is added at the end
+        assert_eq!(instance_slots.insert(ObjectID(4)), middle);
+        assert_eq!(instance_slots.insert(ObjectID(5)), 3);
+    }
+
+    #[test]
+    fn removing_the_last_instances_drops_the_free_slots_at_the_end() {
+        let mut instance_slots = InstanceSlots::default();
+        let slots = [1, 2, 3, 4].map(|id| instance_slots.insert(ObjectID(id)));
+
+        instance_slots.remove(slots[1]);
+        instance_slots.remove(slots[3]);
+        assert_eq!(instance_slots.get_num_slots(), 3);
+        instance_slots.remove(slots[2]);
+        // Slot 1 was only kept because slot 2 was used after it
+        assert_eq!(instance_slots.get_num_slots(), 1);
+        assert_eq!(instance_slots.insert(ObjectID(5)), 1);
+    }
+
+    #[test]
+    fn instance_buffers_are_only_made_again_when_the_slots_do_not_fit() {
+        assert_eq!(DataUsedInShader::get_new_instance_capacity(None, 3), Some(3));
+        assert_eq!(DataUsedInShader::get_new_instance_capacity(Some(3), 2), None);
+        assert_eq!(DataUsedInShader::get_new_instance_capacity(Some(3), 3), None);
+        // Adding one object at a time only makes the buffers again each time the capacity has doubled
+        assert_eq!(DataUsedInShader::get_new_instance_capacity(Some(3), 4), Some(6));
+        assert_eq!(DataUsedInShader::get_new_instance_capacity(Some(3), 10), Some(10));
+    }
+
+    type SnapshotState = (Arc<RwLock<UniformBufferResource<glm::Mat4>>>, HashMap<ObjectID, (ObjectType, Box<dyn Renderable>)>, HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>, HashMap<(ObjectID, ResourceID), (Inclusive, Exclusive)>);
+
+    // One lit object in slot 0, with the storage buffer of its model matrix
+    fn get_snapshot_state() -> SnapshotState {
+        let vertices = [glm::vec3(0.0, 0.5, 0.0), glm::vec3(-0.5, -0.5, 0.0), glm::vec3(0.5, -0.5, 0.0)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+        let lit_object = LitRenderableObject::new(vertices.to_vec(), vec![0, 1, 2], DynamicImage::new_rgba8(2, 2), glm::Mat4::identity());
+        let model_matrix = lit_object.model_matrix.clone();
+        let object: Arc<RwLock<dyn GraphicsObject<LitVertex>>> = Arc::new(RwLock::new(lit_object));
+        let object_type = ObjectType::of(&object);
+        let model_matrix_id = ResourceID(0);
+        let matrix_size = std::mem::size_of::<glm::Mat4>();
+        let objects = HashMap::from([(ObjectID(1), (object_type, Box::new(object.clone()) as Box<dyn Renderable>))]);
+        let storage_buffers = HashMap::from([((object_type, model_matrix_id), (AllocationInfo::without_memory(), vec![0; matrix_size]))]);
+        let object_id_storage_buffer_bytes_indices = HashMap::from([((ObjectID(1), model_matrix_id), (Inclusive(0), Exclusive(matrix_size)))]);
+        (model_matrix, objects, storage_buffers, object_id_storage_buffer_bytes_indices)
+    }
+
+    fn set_model_matrix(model_matrix: &RwLock<UniformBufferResource<glm::Mat4>>, value: f32) {
+        model_matrix.write().unwrap().buffer = glm::Mat4::from_element(value);
+    }
+
+    fn get_snapshot_matrix(storage_buffers: &HashMap<(ObjectType, ResourceID), (AllocationInfo, Vec<u8>)>) -> Vec<f32> {
+        let (_, data) = storage_buffers.values().next().unwrap();
+        data.chunks_exact(4).map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn resource_data_snapshot_has_the_last_write_before_it() {
+        let (model_matrix, objects, mut storage_buffers, object_id_storage_buffer_bytes_indices) = get_snapshot_state();
+        set_model_matrix(&model_matrix, 1.0);
+        set_model_matrix(&model_matrix, 2.0);
+        DataUsedInShader::snapshot_storage_buffer_data(objects.iter(), &mut storage_buffers, &object_id_storage_buffer_bytes_indices, &mut HashSet::new());
+        assert_eq!(get_snapshot_matrix(&storage_buffers), vec![2.0; 16]);
+
+        // The writes after the snapshot wait for the next one
+        set_model_matrix(&model_matrix, 3.0);
+        assert_eq!(get_snapshot_matrix(&storage_buffers), vec![2.0; 16]);
+        DataUsedInShader::snapshot_storage_buffer_data(objects.iter(), &mut storage_buffers, &object_id_storage_buffer_bytes_indices, &mut HashSet::new());
+        assert_eq!(get_snapshot_matrix(&storage_buffers), vec![3.0; 16]);
+    }
+
+    #[test]
+    fn poisoned_model_matrix_is_read_from_the_last_snapshot() {
+        let (model_matrix, objects, mut storage_buffers, object_id_storage_buffer_bytes_indices) = get_snapshot_state();
+        set_model_matrix(&model_matrix, 2.0);
+        DataUsedInShader::snapshot_storage_buffer_data(objects.iter(), &mut storage_buffers, &object_id_storage_buffer_bytes_indices, &mut HashSet::new());
+        let (_, object) = &objects[&ObjectID(1)];
+        let get_snapshot_data = |_| Some(storage_buffers.values().next().unwrap().1.clone());
+        assert_eq!(DataUsedInShader::read_model_matrix(object.as_ref(), get_snapshot_data), glm::Mat4::from_element(2.0));
+
+        // The writer panics halfway, so the lock is poisoned and the data it left is not used
+        let writer = {
+            let model_matrix = model_matrix.clone();
+            std::thread::spawn(move || {
+                let mut model_matrix = model_matrix.write().unwrap();
+                model_matrix.buffer[0] = 3.0;
+                panic!("The writer panicked while holding the lock");
+            })
+        };
+        assert!(writer.join().is_err());
+        assert!(model_matrix.is_poisoned());
+        assert_eq!(DataUsedInShader::read_model_matrix(object.as_ref(), get_snapshot_data), glm::Mat4::from_element(2.0));
+    }
+
+    #[test]
+    fn resource_data_snapshot_never_has_half_a_write() {
+        let (model_matrix, objects, mut storage_buffers, object_id_storage_buffer_bytes_indices) = get_snapshot_state();
+        const NUM_WRITES: u32 = 20_000;
+        set_model_matrix(&model_matrix, 0.0);
+        let writer = {
+            let model_matrix = model_matrix.clone();
+            std::thread::spawn(move || (1..=NUM_WRITES).for_each(|value| set_model_matrix(&model_matrix, value as f32)))
+        };
+
+        let mut last_value = 0.0;
+        while !writer.is_finished() {
+            DataUsedInShader::snapshot_storage_buffer_data(objects.iter(), &mut storage_buffers, &object_id_storage_buffer_bytes_indices, &mut HashSet::new());
+            let matrix = get_snapshot_matrix(&storage_buffers);
+            assert!(matrix.iter().all(|value| *value == matrix[0]), "The snapshot has parts of different writes: {:?}", matrix);
+            assert!(matrix[0] >= last_value);
+            last_value = matrix[0];
+        }
+        writer.join().unwrap();
+        DataUsedInShader::snapshot_storage_buffer_data(objects.iter(), &mut storage_buffers, &object_id_storage_buffer_bytes_indices, &mut HashSet::new());
+        assert_eq!(get_snapshot_matrix(&storage_buffers), vec![NUM_WRITES as f32; 16]);
+    }
+
+    // A triangle moved along x, so every offset is its own mesh and object type
+    fn get_lit_object(x: f32) -> (ObjectType, Box<dyn Renderable>) {
+        let vertices = [glm::vec3(x, 0.5, 0.0), glm::vec3(x - 0.5, -0.5, 0.0), glm::vec3(x + 0.5, -0.5, 0.0)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+        let object: Arc<RwLock<dyn GraphicsObject<LitVertex>>> = Arc::new(RwLock::new(LitRenderableObject::new(vertices.to_vec(), vec![0, 1, 2], DynamicImage::new_rgba8(2, 2), glm::Mat4::identity())));
+        (ObjectType::of(&object), Box::new(object))
+    }
+
+    fn get_pipeline_config(vertex_shader: &str) -> PipelineConfig {
+        PipelineConfig::without_device(vec![ShaderInfo { source: ShaderSource::File(vertex_shader.into()), shader_stage_flag: vk::ShaderStageFlags::VERTEX, entry_point: CString::new("main").unwrap() }])
+    }
+
+    // Pipeline a has the object types a0 and a1, where a0 has two instances, and the pipelines b and c have one object type each
+    fn get_draw_order_state() -> (ObjectManager, [ObjectType; 4]) {
+        let mut object_manager = ObjectManager::new(2);
+        let [(a0, a0_object), (a1, a1_object), (b0, b0_object), (c0, c0_object)] = [0.0, 1.0, 2.0, 3.0].map(get_lit_object);
+        let (_, a0_second_object) = get_lit_object(0.0);
+        object_manager.add_objects_without_gpu(get_pipeline_config("a.vert"), vec![(ObjectID(1), a0_object), (ObjectID(2), a1_object), (ObjectID(3), a0_second_object)]);
+        object_manager.add_objects_without_gpu(get_pipeline_config("b.vert"), vec![(ObjectID(4), b0_object)]);
+        object_manager.add_objects_without_gpu(get_pipeline_config("c.vert"), vec![(ObjectID(5), c0_object)]);
+        (object_manager, [a0, a1, b0, c0])
+    }
+
+    fn get_drawn_order(object_manager: &ObjectManager) -> Vec<(String, ObjectType)> {
+        object_manager.get_draws_in_order(0).into_iter().map(|(pipeline_config, draw_batch)| (pipeline_config.get_shader_paths().join(", "), draw_batch.object_type)).collect()
+    }
+
+    #[test]
+    fn draws_are_in_the_order_the_pipelines_and_object_types_were_added() {
+        let (object_manager, [a0, a1, b0, c0]) = get_draw_order_state();
+        let draws = object_manager.get_draws_in_order(1);
+        assert_eq!(get_drawn_order(&object_manager), vec![("a.vert".to_string(), a0), ("a.vert".to_string(), a1), ("b.vert".to_string(), b0), ("c.vert".to_string(), c0)]);
+        // Both instances of a0 are one draw, and every draw uses the descriptor set of the frame
+        assert_eq!((draws[0].1.first_instance, draws[0].1.num_instances), (0, 2));
+        assert_eq!(draws[0].1.descriptor_set, DescriptorSet::from_raw(a0.get_hash().0.wrapping_add(1)));
+        // The draws are the same every time they are asked for
+        assert_eq!(get_drawn_order(&object_manager), get_drawn_order(&object_manager));
+    }
+
+    #[test]
+    fn sort_key_draws_are_sorted_by_their_keys_and_ties_keep_the_insertion_order() {
+        let (mut object_manager, [a0, a1, b0, c0]) = get_draw_order_state();
+        object_manager.set_draw_order(DrawOrder::SortKey);
+        // Without keys everything ties at 0, so the order is the insertion order
+        assert_eq!(get_drawn_order(&object_manager).into_iter().map(|(_, object_type)| object_type).collect::<Vec<_>>(), vec![a0, a1, b0, c0]);
+
+        // a1 and c0 keep the default key of 0, so they tie and a1 stays first
+        object_manager.set_draw_order_key(a0, 2);
+        object_manager.set_draw_order_key(b0, 1);
+        assert_eq!(get_drawn_order(&object_manager), vec![("a.vert".to_string(), a1), ("c.vert".to_string(), c0), ("b.vert".to_string(), b0), ("a.vert".to_string(), a0)]);
+
+        // A changed key moves the object type, the others keep their places
+        object_manager.set_draw_order_key(a0, -1);
+        object_manager.set_draw_order_key(c0, 1);
+        assert_eq!(get_drawn_order(&object_manager), vec![("a.vert".to_string(), a0), ("a.vert".to_string(), a1), ("b.vert".to_string(), b0), ("c.vert".to_string(), c0)]);
+        object_manager.set_draw_order_key(b0, 5);
+        assert_eq!(get_drawn_order(&object_manager).into_iter().map(|(_, object_type)| object_type).collect::<Vec<_>>(), vec![a0, a1, c0, b0]);
+
+        // The keys are kept, but only used in the sort key order
+        object_manager.set_draw_order(DrawOrder::InsertionOrder);
+        assert_eq!(get_drawn_order(&object_manager).into_iter().map(|(_, object_type)| object_type).collect::<Vec<_>>(), vec![a0, a1, b0, c0]);
+    }
+
+    #[test]
+    fn pipelines_that_share_a_mesh_share_its_registry_entry_and_vertex_buffer() {
+        let mut object_manager = ObjectManager::new(2);
+        // Unique object types give the two triangles at 0 their own types, which are drawn by different pipelines with the same mesh
+        let [shared_mesh_object, other_shared_mesh_object, other_object] = [0.0, 0.0, 1.0].map(|x| Box::new(UniqueTypeRenderable::new(get_lit_object(x).1)) as Box<dyn Renderable>);
+        let shared_mesh = shared_mesh_object.get_vertices_and_indices_hash();
+        assert_eq!(other_shared_mesh_object.get_vertices_and_indices_hash(), shared_mesh);
+        assert_ne!(ObjectType::of(shared_mesh_object.as_ref()), ObjectType::of(other_shared_mesh_object.as_ref()));
+
+        object_manager.add_objects_without_gpu(get_pipeline_config("a.vert"), vec![(ObjectID(1), shared_mesh_object)]);
+        object_manager.add_objects_without_gpu(get_pipeline_config("b.vert"), vec![(ObjectID(2), other_shared_mesh_object)]);
+        assert_eq!(object_manager.get_num_meshes(), 1);
+        assert_eq!(object_manager.mesh_registry.get(shared_mesh).unwrap().references, 2);
+        object_manager.add_objects_without_gpu(get_pipeline_config("c.vert"), vec![(ObjectID(3), other_object)]);
+        assert_eq!(object_manager.get_num_meshes(), 2);
+
+        let vertex_buffers = object_manager.get_draws_in_order(0).into_iter().map(|(_, draw_batch)| draw_batch.vertex_buffer).collect::<Vec<_>>();
+        assert_eq!(vertex_buffers.len(), 3);
+        assert_eq!(vertex_buffers[0], vertex_buffers[1]);
+        assert_ne!(vertex_buffers[0], vertex_buffers[2]);
+    }
+
+    // Takes the expired data out of the queues of the pipelines like update_objects does, without freeing anything on the GPU
+    fn take_expired_data_to_remove(object_manager: &mut ObjectManager, frame_index: u64) {
+        for data_used_in_shader in object_manager.data_used_in_shader.values_mut() {
+            DataUsedInShader::take_expired_data_to_remove(&mut data_used_in_shader.allocations_and_descriptor_sets_to_remove, frame_index, data_used_in_shader.frames_in_flight);
+        }
+    }
+
+    // Pipeline a has the object types of the objects 1 and 2, and pipeline b has the object type of object 3
+    fn get_removal_state(frames_in_flight: usize) -> ObjectManager {
+        let mut object_manager = ObjectManager::new(frames_in_flight);
+        let [(_, first_object), (_, second_object), (_, third_object)] = [0.0, 1.0, 2.0].map(get_lit_object);
+        object_manager.add_objects_without_gpu(get_pipeline_config("a.vert"), vec![(ObjectID(1), first_object), (ObjectID(2), second_object)]);
+        object_manager.add_objects_without_gpu(get_pipeline_config("b.vert"), vec![(ObjectID(3), third_object)]);
+        object_manager
+    }
+
+    #[test]
+    fn removal_is_freed_after_every_frame_in_flight_has_finished() {
+        for frames_in_flight in 1..=3 {
+            let mut object_manager = get_removal_state(frames_in_flight);
+            take_expired_data_to_remove(&mut object_manager, 10);
+            let handle = object_manager.remove_objects_tracked(vec![ObjectID(1)]).unwrap();
+            assert_eq!(object_manager.poll_removal(handle), Some(RemovalStatus::Pending));
+
+            // The same frame only counts once
+            take_expired_data_to_remove(&mut object_manager, 10);
+            for frame_index in 11..10 + frames_in_flight as u64 {
+                take_expired_data_to_remove(&mut object_manager, frame_index);
+                take_expired_data_to_remove(&mut object_manager, frame_index);
+                assert_eq!(object_manager.poll_removal(handle), Some(RemovalStatus::Pending), "{} frames in flight, frame {}", frames_in_flight, frame_index);
+            }
+            take_expired_data_to_remove(&mut object_manager, 10 + frames_in_flight as u64);
+            assert_eq!(object_manager.poll_removal(handle), Some(RemovalStatus::Freed), "{} frames in flight", frames_in_flight);
+            // A freed removal is only returned once, and nothing is kept for it after that
+            assert_eq!(object_manager.poll_removal(handle), None);
+            assert!(object_manager.removals.is_empty());
+        }
+    }
+
+    #[test]
+    fn removals_are_freed_in_the_order_they_were_made() {
+        let mut object_manager = get_removal_state(2);
+        take_expired_data_to_remove(&mut object_manager, 1);
+        let first = object_manager.remove_objects_tracked(vec![ObjectID(1)]).unwrap();
+        take_expired_data_to_remove(&mut object_manager, 2);
+        // The removal is queued in both pipelines, so it is pending until both have freed their data
+        let second = object_manager.remove_objects_tracked(vec![ObjectID(2), ObjectID(3)]).unwrap();
+
+        take_expired_data_to_remove(&mut object_manager, 3);
+        assert_eq!(object_manager.poll_removal(first), Some(RemovalStatus::Freed));
+        assert_eq!(object_manager.poll_removal(second), Some(RemovalStatus::Pending));
+        take_expired_data_to_remove(&mut object_manager, 4);
+        assert_eq!(object_manager.poll_removal(second), Some(RemovalStatus::Freed));
+        assert_eq!(object_manager.poll_removal(RemovalHandle(second.0 + 1)), None);
+        assert!(object_manager.removals.is_empty());
+    }
+
+    #[test]
+    fn failed_removal_gives_out_no_handle() {
+        let mut object_manager = get_removal_state(2);
+        assert!(matches!(object_manager.remove_objects_tracked(vec![ObjectID(7)]), Err(EngineError::ObjectNotFound(ObjectID(7)))));
+        assert!(object_manager.removals.is_empty());
+        let handle = object_manager.remove_objects_tracked(vec![ObjectID(1)]).unwrap();
+        assert_eq!(handle, RemovalHandle(0));
+    }
+
+    #[test]
+    fn removing_an_unknown_object_fails_with_object_not_found() {
+        let object_manager = ObjectManager::new(2);
+        let (result, captured_logs) = capture_logs(|| object_manager.get_objects_by_pipeline_config(&[ObjectID(7)]));
+        assert!(matches!(result, Err(EngineError::ObjectNotFound(ObjectID(7)))));
+        assert!(has_log(&captured_logs, log::Level::Warn, logging::OBJECTS, "Object with id ObjectID(7) not found"));
+    }
+
+    #[test]
+    fn descriptor_pool_exhaustion_is_logged_as_an_error() {
+        for result in [vk::Result::ERROR_OUT_OF_POOL_MEMORY, vk::Result::ERROR_FRAGMENTED_POOL] {
+            let (error, captured_logs) = capture_logs(|| DataUsedInShader::get_descriptor_set_allocation_error(result, 3));
+            assert!(matches!(error, EngineError::VulkanApi { result: error_result, .. } if error_result == result));
+            assert!(has_log(&captured_logs, log::Level::Error, logging::OBJECTS, "The descriptor pool is exhausted, so the 3 descriptor sets"));
+        }
 
-        self.allocations_and_descriptor_sets_to_remove.1.retain(|(counter, _)| counter.0 < VkController::MAX_FRAMES_IN_FLIGHT);
+        // The other failures are only returned, the caller decides if they are worth logging
+        let (error, captured_logs) = capture_logs(|| DataUsedInShader::get_descriptor_set_allocation_error(vk::Result::ERROR_OUT_OF_HOST_MEMORY, 3));
+        assert!(matches!(error, EngineError::VulkanApi { result: vk::Result::ERROR_OUT_OF_HOST_MEMORY, .. }));
+        assert!(captured_logs.is_empty());
     }
 }
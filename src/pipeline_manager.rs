@@ -1,18 +1,25 @@
-use std::{borrow::Cow, ffi::CString, fs::read_to_string, hash::Hash};
+use std::{borrow::Cow, collections::HashMap, ffi::CString, fmt::{Display, Formatter}, hash::Hash, time::SystemTime};
 
-use ash::{vk::{self, DescriptorSetLayoutBinding, RenderPass, SampleCountFlags, StructureType, VertexInputAttributeDescription, VertexInputBindingDescription}, Device};
+use ash::{extensions::ext::DebugUtils, vk::{self, DescriptorSetLayoutBinding, Handle, RenderPass, SampleCountFlags, StructureType, VertexInputAttributeDescription, VertexInputBindingDescription}, Device};
 use image::DynamicImage;
 use shaderc::{Compiler, ShaderKind};
 
-use crate::vk_allocator::{Serializable, VkAllocator};
+use crate::{logging, asset_resolver::AssetResolver, builtin_shaders::BuiltinShader, error::EngineError, post_process::PostEffect, render_target::RenderTargetId, shader_reflection::{self, BufferBindingSize, ShaderBinding}, vk_allocator::{Serializable, VkAllocator}, vk_controller::VkController};
 
 pub enum ObjectInstanceGraphicsResourceType {
     DynamicStorageBuffer(Vec<u8>),
+    /// Bound with a dynamic offset, so each instance is drawn on its own
+    DynamicUniformBuffer(Vec<u8>),
 }
 
 pub enum ObjectTypeGraphicsResourceType {
     UniformBuffer(Vec<u8>),
-    Texture(DynamicImage),
+    /// Samples the color image of the render target, which is drawn before the main pass every frame
+    RenderTarget(RenderTargetId),
+    /// The optional string is an asset key that identifies the image in the texture cache instead of hashing its bytes
+    Texture(DynamicImage, Option<String>),
+    /// The faces in the order +X, -X, +Y, -Y, +Z and -Z, with an asset key like [`ObjectTypeGraphicsResourceType::Texture`]
+    CubeMap([DynamicImage; 6], Option<String>),
 }
 
 pub trait Vertex: Serializable + Hash + Clone + Send + 'static {
@@ -20,9 +27,23 @@ pub trait Vertex: Serializable + Hash + Clone + Send + 'static {
     fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription>;
 }
 
+/// Whether the data of a resource can be used yet, see [`crate::graphics_objects::TextureResource::from_path_async`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceLoadState {
+    Ready,
+    /// A placeholder is used until it is ready.
+    Loading,
+    /// A fallback is used, and the reason is reported as a resource error.
+    Failed(String),
+}
+
 pub trait ObjectTypeGraphicsResource {
     fn get_descriptor_set_layout_binding(&self) -> vk::DescriptorSetLayoutBinding;
     fn get_resource(&self) -> ObjectTypeGraphicsResourceType;
+    /// Read every frame while it isn't ready, so it should be cheap.
+    fn get_load_state(&self) -> ResourceLoadState {
+        ResourceLoadState::Ready
+    }
 }
 
 pub trait ObjectInstanceGraphicsResource {
@@ -32,9 +53,26 @@ pub trait ObjectInstanceGraphicsResource {
 
 
 
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ShaderSource {
+    /// GLSL relative to the asset roots, see [`AssetResolver`]. It is compiled when the pipeline is created
+    File(std::path::PathBuf),
+    /// Already compiled into the binary, so nothing is read from the asset roots
+    Builtin(BuiltinShader),
+}
+
+impl Display for ShaderSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderSource::File(path) => write!(f, "{}", path.display()),
+            ShaderSource::Builtin(shader) => write!(f, "builtin:{}", shader.get_file_name()),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone)]
 pub struct ShaderInfo {
-    pub path: std::path::PathBuf,
+    pub source: ShaderSource,
     pub shader_stage_flag: vk::ShaderStageFlags,
     pub entry_point: CString,
 }
@@ -50,24 +88,19 @@ pub struct PipelineConfig {
     descriptor_set_layout_bindings: Vec<vk::DescriptorSetLayoutBinding>,
     descriptor_set_layout: Option<vk::DescriptorSetLayout>,
     pipeline_layout: Option<vk::PipelineLayout>,
+    topology: vk::PrimitiveTopology,
+    is_depth_write_enabled: bool,
+    is_depth_test_enabled: bool,
+    cull_mode: vk::CullModeFlags,
+    is_alpha_premultiplied: bool,
+    // Read from the compiled shaders, so they stay empty until the pipeline has been created with them
+    buffer_binding_sizes: HashMap<u32, BufferBindingSize>,
+    shader_bindings: HashMap<u32, (ShaderBinding, vk::ShaderStageFlags)>,
 }
 
 impl PipelineConfig {
-    pub fn new(device: &Device, shaders: Vec<ShaderInfo>, vertex_binding_info: VertexInputBindingDescription, vertex_attribute_info: Vec<VertexInputAttributeDescription>, descriptor_set_layout_bindings: &[DescriptorSetLayoutBinding], msaa_samples: vk::SampleCountFlags, swapchain_format: vk::Format, depth_format: vk::Format, allocator: &mut VkAllocator) -> Result<Self, Cow<'static, str>> {
-        if vertex_attribute_info.is_empty() {
-            return Err(Cow::Borrowed("Vertex attribute descriptions are empty"));
-        }
-        if vertex_attribute_info.iter().any(|attribute| attribute.binding != vertex_binding_info.binding) {
-            return Err(Cow::Borrowed("Vertex attribute descriptions have different binding than the vertex input binding description"));
-        }
-        // Check if any of the vertex attribute descriptions have the same location
-        for i in 0..vertex_attribute_info.len() {
-            for j in i + 1..vertex_attribute_info.len() {
-                if vertex_attribute_info[i].location == vertex_attribute_info[j].location {
-                    return Err(Cow::Borrowed("Vertex attribute descriptions have the same location"));
-                }
-            }
-        }
+    pub fn new(device: &Device, shaders: Vec<ShaderInfo>, vertex_binding_info: VertexInputBindingDescription, vertex_attribute_info: Vec<VertexInputAttributeDescription>, descriptor_set_layout_bindings: &[DescriptorSetLayoutBinding], msaa_samples: vk::SampleCountFlags, swapchain_format: vk::Format, depth_format: vk::Format, allocator: &VkAllocator) -> Result<Self, EngineError> {
+        Self::validate_vertex_input(&vertex_binding_info, &vertex_attribute_info)?;
 
         Ok(PipelineConfig {
             shaders,
@@ -79,29 +112,168 @@ impl PipelineConfig {
             descriptor_set_layout_bindings: descriptor_set_layout_bindings.to_vec(),
             descriptor_set_layout: None,
             pipeline_layout: None,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            is_depth_write_enabled: true,
+            is_depth_test_enabled: true,
+            cull_mode: vk::CullModeFlags::BACK,
+            is_alpha_premultiplied: false,
+            buffer_binding_sizes: HashMap::new(),
+            shader_bindings: HashMap::new(),
         })
     }
 
+    /// A config that only differs from the others by its shaders, for the tests that never create the pipeline.
+    #[cfg(test)]
+    pub fn without_device(shaders: Vec<ShaderInfo>) -> Self {
+        PipelineConfig {
+            shaders,
+            vertex_binding_info: VertexInputBindingDescription::default(),
+            vertex_attribute_info: Vec::new(),
+            msaa_samples: vk::SampleCountFlags::TYPE_1,
+            swapchain_format: vk::Format::UNDEFINED,
+            depth_format: vk::Format::UNDEFINED,
+            descriptor_set_layout_bindings: Vec::new(),
+            descriptor_set_layout: None,
+            pipeline_layout: None,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            is_depth_write_enabled: true,
+            is_depth_test_enabled: true,
+            cull_mode: vk::CullModeFlags::BACK,
+            is_alpha_premultiplied: false,
+            buffer_binding_sizes: HashMap::new(),
+            shader_bindings: HashMap::new(),
+        }
+    }
+
+    // The attributes have to be read from the binding of the vertices, each from its own location
+    fn validate_vertex_input(vertex_binding_info: &VertexInputBindingDescription, vertex_attribute_info: &[VertexInputAttributeDescription]) -> Result<(), EngineError> {
+        if vertex_attribute_info.is_empty() {
+            return Err(EngineError::from("Vertex attribute descriptions are empty"));
+        }
+        if vertex_attribute_info.iter().any(|attribute| attribute.binding != vertex_binding_info.binding) {
+            return Err(EngineError::from("Vertex attribute descriptions have different binding than the vertex input binding description"));
+        }
+        // Check if any of the vertex attribute descriptions have the same location
+        for i in 0..vertex_attribute_info.len() {
+            for j in i + 1..vertex_attribute_info.len() {
+                if vertex_attribute_info[i].location == vertex_attribute_info[j].location {
+                    return Err(EngineError::from("Vertex attribute descriptions have the same location"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The topology is a triangle list by default.
+    pub fn with_topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// The depth is still tested when the writes are disabled, so the drawn primitives are hidden behind the objects without hiding anything themselves.
+    pub fn with_depth_write(mut self, is_depth_write_enabled: bool) -> Self {
+        self.is_depth_write_enabled = is_depth_write_enabled;
+        self
+    }
+
+    /// Without the test the primitives are drawn over everything drawn before them, like a UI.
+    pub fn with_depth_test(mut self, is_depth_test_enabled: bool) -> Self {
+        self.is_depth_test_enabled = is_depth_test_enabled;
+        self
+    }
+
+    /// The back faces are culled by default.
+    pub fn with_cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// For fragment colors that are already multiplied by their alpha, which are blended with a source factor of one.
+    pub fn with_premultiplied_alpha(mut self, is_alpha_premultiplied: bool) -> Self {
+        self.is_alpha_premultiplied = is_alpha_premultiplied;
+        self
+    }
+
+    // The same pipeline with the built-in error shaders, which only need the position at location 0 and draw everything in magenta
+    fn with_error_shaders(&self) -> PipelineConfig {
+        let has_model_matrices = self.descriptor_set_layout_bindings.iter().any(|binding| {
+            binding.binding == 0 && binding.descriptor_type == vk::DescriptorType::STORAGE_BUFFER && binding.stage_flags.contains(vk::ShaderStageFlags::VERTEX)
+        });
+        let vertex_shader = if has_model_matrices { BuiltinShader::ErrorInstancedVertex } else { BuiltinShader::ErrorVertex };
+        PipelineConfig {
+            shaders: vec![vertex_shader.get_shader_info(), BuiltinShader::ErrorFragment.get_shader_info()],
+            ..self.clone()
+        }
+    }
+
+    // When the shaders were last modified, so a failed pipeline can be tried again after they have been fixed
+    fn get_shader_modified_times(&self, asset_resolver: &AssetResolver) -> Vec<Option<SystemTime>> {
+        self.shaders.iter().map(|shader| match &shader.source {
+            ShaderSource::File(path) => asset_resolver.resolve(path).ok().and_then(|path| std::fs::metadata(path).ok()).and_then(|metadata| metadata.modified().ok()),
+            ShaderSource::Builtin(_) => None,
+        }).collect()
+    }
+
+    /// The sizes of the buffer blocks in the object set of the shaders by binding. Empty when the pipeline hasn't been created with its own shaders, so there is nothing to check against.
+    pub fn get_buffer_binding_sizes(&self) -> &HashMap<u32, BufferBindingSize> {
+        &self.buffer_binding_sizes
+    }
+
+    /// Checks that the bindings the object declares have the types, counts and stages the shaders use them with. Bindings the shaders don't use are allowed.
+    /// Nothing is checked until the pipeline has been created with its own shaders.
+    pub fn validate_descriptor_set_layout_bindings(&self) -> Result<(), Cow<'static, str>> {
+        let mut shader_bindings = self.shader_bindings.iter().collect::<Vec<_>>();
+        shader_bindings.sort_by_key(|(binding, _)| **binding);
+        for (binding, (shader_binding, stages)) in shader_bindings {
+            let Some(layout_binding) = self.descriptor_set_layout_bindings.iter().find(|layout_binding| layout_binding.binding == *binding) else {
+                return Err(Cow::Owned(format!("The shaders use binding {} in set 1 as a {:?}, but the object has no resource with that binding", binding, shader_binding.kind)));
+            };
+            if !shader_binding.kind.is_compatible(layout_binding.descriptor_type) {
+                return Err(Cow::Owned(format!("Binding {} is a {:?} in the shaders, but the object's resource has the descriptor type {}", binding, shader_binding.kind, layout_binding.descriptor_type.as_raw())));
+            }
+            if shader_binding.count.is_some_and(|count| layout_binding.descriptor_count < count) {
+                return Err(Cow::Owned(format!("Binding {} is an array of {} in the shaders, but the object's resource only has {} descriptors", binding, shader_binding.count.unwrap(), layout_binding.descriptor_count)));
+            }
+            if !layout_binding.stage_flags.contains(*stages) {
+                return Err(Cow::Owned(format!("Binding {} is used in the shader stages {:#x}, but the object's resource is only visible to the stages {:#x}", binding, stages.as_raw(), layout_binding.stage_flags.as_raw())));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_shader_paths(&self) -> Vec<String> {
-        self.shaders.iter().map(|shader| shader.path.to_string_lossy().to_string()).collect()
+        self.shaders.iter().map(|shader| shader.source.to_string()).collect()
     }
 
-    fn create_graphics_pipeline(&mut self, device: &Device, swapchain_extent: &vk::Extent2D, render_pass: RenderPass, allocator: &mut VkAllocator) -> Result<vk::Pipeline, Cow<'static, str>> {
+    fn create_graphics_pipeline(&mut self, device: &Device, swapchain_extent: &vk::Extent2D, render_pass: RenderPass, global_descriptor_set_layout: vk::DescriptorSetLayout, bindless_texture_descriptor_set_layout: Option<vk::DescriptorSetLayout>, is_picking_enabled: bool, num_extra_color_attachments: usize, is_sample_rate_shading_supported: bool, asset_resolver: &AssetResolver, allocator: &VkAllocator) -> Result<vk::Pipeline, Cow<'static, str>> {
         for shader in self.shaders.iter() {
             if !(shader.shader_stage_flag == vk::ShaderStageFlags::VERTEX ||
                 shader.shader_stage_flag == vk::ShaderStageFlags::FRAGMENT)  
              {
-                 return Err(format!("The shader stage flag for shader with path {:?} cannot be more or less than one constant!", shader.path).into());
+                 return Err(format!("The shader stage flag for shader {} cannot be more or less than one constant!", shader.source).into());
              };   
         }
 
-        let shader_modules: Vec<(ShaderInfo, vk::ShaderModule)> = self.shaders.iter().map(|shader_info| {
+        // Everything is compiled before the modules are created, so nothing has to be destroyed when a shader can't be found
+        let shader_codes = self.shaders.iter().map(|shader_info| {
             let shader_kind = match shader_info.shader_stage_flag {
                 vk::ShaderStageFlags::VERTEX => ShaderKind::Vertex,
                 vk::ShaderStageFlags::FRAGMENT => ShaderKind::Fragment,
-                _ => panic!("Invalid shader stage flag for shader with path {:?}. This should never happen! The stage flag had number: {}!", shader_info.path, shader_info.shader_stage_flag.as_raw()),
+                _ => panic!("Invalid shader stage flag for shader {}. This should never happen! The stage flag had number: {}!", shader_info.source, shader_info.shader_stage_flag.as_raw()),
             };
-            let code = Self::compile_shader(&shader_info.path, shader_info.entry_point.to_str().unwrap(), shader_kind, &shader_info.path.to_string_lossy());
+            Ok((shader_info, Self::get_shader_code(shader_info, shader_kind, asset_resolver)?))
+        }).collect::<Result<Vec<_>, Cow<'static, str>>>()?;
+        self.buffer_binding_sizes = HashMap::new();
+        self.shader_bindings = HashMap::new();
+        for (shader_info, code) in shader_codes.iter() {
+            self.buffer_binding_sizes.extend(shader_reflection::get_buffer_binding_sizes(code, 1).unwrap_or_default());
+            for (binding, shader_binding) in shader_reflection::get_descriptor_bindings(code, 1).unwrap_or_default() {
+                self.shader_bindings.entry(binding).or_insert((shader_binding, vk::ShaderStageFlags::empty())).1 |= shader_info.shader_stage_flag;
+            }
+        }
+        // A layout without the shaders' bindings is invalid, so the pipeline isn't created with it
+        self.validate_descriptor_set_layout_bindings()?;
+        let shader_modules: Vec<(ShaderInfo, vk::ShaderModule)> = shader_codes.into_iter().map(|(shader_info, code)| {
             let module = Self::create_shader_module(device, code, allocator);
             (shader_info.clone(), module)
         }).collect::<Vec<_>>();
@@ -130,7 +302,7 @@ impl PipelineConfig {
 
         let input_assembly = vk::PipelineInputAssemblyStateCreateInfo {
             s_type: StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            topology: self.topology,
             primitive_restart_enable: vk::FALSE,
             ..Default::default()
         };
@@ -161,8 +333,8 @@ impl PipelineConfig {
             depth_clamp_enable: vk::FALSE,
             rasterizer_discard_enable: vk::FALSE,
             polygon_mode: vk::PolygonMode::FILL,//LINE,//
-            line_width: 1.0,
-            cull_mode: vk::CullModeFlags::BACK,
+            line_width: 1.0, // Wider lines need the wide_lines feature, which Metal doesn't have
+            cull_mode: self.cull_mode,
             front_face: vk::FrontFace::COUNTER_CLOCKWISE,
             depth_bias_enable: vk::FALSE,
             depth_bias_constant_factor: 0.0,
@@ -173,7 +345,8 @@ impl PipelineConfig {
 
         let multisampling = vk::PipelineMultisampleStateCreateInfo {
             s_type: StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
-            sample_shading_enable: vk::TRUE, // This may cause performance loss, but it's not required
+            // This may cause performance loss, but it's not required, so it's left out when the device doesn't support it
+            sample_shading_enable: if is_sample_rate_shading_supported { vk::TRUE } else { vk::FALSE },
             rasterization_samples: self.msaa_samples,
             min_sample_shading: 0.2,
             p_sample_mask: std::ptr::null(),
@@ -185,28 +358,42 @@ impl PipelineConfig {
         let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
             color_write_mask: vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A,
             blend_enable: vk::TRUE,
-            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            src_color_blend_factor: if self.is_alpha_premultiplied { vk::BlendFactor::ONE } else { vk::BlendFactor::SRC_ALPHA },
             dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
             color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            src_alpha_blend_factor: if self.is_alpha_premultiplied { vk::BlendFactor::ONE } else { vk::BlendFactor::SRC_ALPHA },
             dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
             alpha_blend_op: vk::BlendOp::ADD,
         };
 
+        // Integer attachments can not be blended, so the object id is written as it is
+        let object_id_blend_attachment = vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::R | vk::ColorComponentFlags::G,
+            blend_enable: vk::FALSE,
+            ..Default::default()
+        };
+        let mut color_blend_attachments = if is_picking_enabled { vec![color_blend_attachment, object_id_blend_attachment] } else { vec![color_blend_attachment] };
+        // The extra attachments hold data like normals, which would be wrong if it was blended
+        color_blend_attachments.extend(std::iter::repeat_n(vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A,
+            blend_enable: vk::FALSE,
+            ..Default::default()
+        }, num_extra_color_attachments));
+
         let color_blending = vk::PipelineColorBlendStateCreateInfo {
             s_type: StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
             logic_op_enable: vk::FALSE,
             logic_op: vk::LogicOp::COPY,
-            attachment_count: 1,
-            p_attachments: &color_blend_attachment,
+            attachment_count: color_blend_attachments.len() as u32,
+            p_attachments: color_blend_attachments.as_ptr(),
             blend_constants: [0.0, 0.0, 0.0, 0.0],
             ..Default::default()
         };
 
         let depth_stencil = vk::PipelineDepthStencilStateCreateInfo {
             s_type: StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
-            depth_test_enable: vk::TRUE,
-            depth_write_enable: vk::TRUE,
+            depth_test_enable: if self.is_depth_test_enabled { vk::TRUE } else { vk::FALSE },
+            depth_write_enable: if self.is_depth_write_enabled { vk::TRUE } else { vk::FALSE },
             depth_compare_op: vk::CompareOp::LESS,
             depth_bounds_test_enable: vk::FALSE,
             min_depth_bounds: 0.0,
@@ -218,7 +405,7 @@ impl PipelineConfig {
         };
 
         let descriptor_set_layout = self.descriptor_set_layout;
-        let pipeline_layout = self.get_or_create_pipeline_layout(device, allocator);
+        let pipeline_layout = self.get_or_create_pipeline_layout(device, global_descriptor_set_layout, bindless_texture_descriptor_set_layout, allocator);
 
         // let render_pass = self.create_render_pass(device, allocator);
 
@@ -243,25 +430,36 @@ impl PipelineConfig {
         };
 
         let graphics_pipeline = unsafe {
-            device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], Some(&allocator.get_allocation_callbacks()))
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], allocator.get_allocation_callbacks())
         }.unwrap()[0];
 
         for (_, shader_module) in shader_modules {
             unsafe {
-                device.destroy_shader_module(shader_module, Some(&allocator.get_allocation_callbacks()));
+                device.destroy_shader_module(shader_module, allocator.get_allocation_callbacks());
             }
         }
 
         Ok(graphics_pipeline)
     }
 
-    fn compile_shader(path: &std::path::PathBuf, entry_point_name: &str, shader_kind: ShaderKind, identifier: &str) -> Vec<u32> {
+    fn get_shader_code(shader_info: &ShaderInfo, shader_kind: ShaderKind, asset_resolver: &AssetResolver) -> Result<Vec<u32>, EngineError> {
+        match &shader_info.source {
+            ShaderSource::File(path) => Self::compile_shader(path, shader_info.entry_point.to_str().unwrap(), shader_kind, &path.to_string_lossy(), asset_resolver),
+            ShaderSource::Builtin(shader) => Ok(shader.get_spirv()),
+        }
+    }
+
+    fn compile_shader(path: &std::path::Path, entry_point_name: &str, shader_kind: ShaderKind, identifier: &str, asset_resolver: &AssetResolver) -> Result<Vec<u32>, EngineError> {
+        let source = asset_resolver.read_to_string(path)?;
         let compiler = Compiler::new().unwrap();
-        let artifact = compiler.compile_into_spirv(&read_to_string(path).unwrap(), shader_kind, identifier, entry_point_name, None).unwrap();
-        artifact.as_binary().to_owned()
+        let artifact = compiler.compile_into_spirv(&source, shader_kind, identifier, entry_point_name, None).map_err(|e| EngineError::PipelineCreation {
+            shader: ShaderSource::File(path.to_path_buf()),
+            log: e.to_string(),
+        })?;
+        Ok(artifact.as_binary().to_owned())
     }
 
-    fn create_shader_module(device: &Device, code: Vec<u32>, allocator: &mut VkAllocator) -> vk::ShaderModule {
+    fn create_shader_module(device: &Device, code: Vec<u32>, allocator: &VkAllocator) -> vk::ShaderModule {
         let create_info = vk::ShaderModuleCreateInfo {
             s_type: StructureType::SHADER_MODULE_CREATE_INFO,
             code_size: code.len() * std::mem::size_of::<u32>(),
@@ -270,7 +468,7 @@ impl PipelineConfig {
         };
 
         unsafe {
-            device.create_shader_module(&create_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_shader_module(&create_info, allocator.get_allocation_callbacks())
         }.unwrap()
     }
 
@@ -295,27 +493,35 @@ impl PipelineConfig {
         }
     }
 
-    fn get_or_create_pipeline_layout(&mut self, device: &Device, allocator: &mut VkAllocator) -> vk::PipelineLayout {
+    fn get_or_create_pipeline_layout(&mut self, device: &Device, global_descriptor_set_layout: vk::DescriptorSetLayout, bindless_texture_descriptor_set_layout: Option<vk::DescriptorSetLayout>, allocator: &VkAllocator) -> vk::PipelineLayout {
         if self.pipeline_layout.is_some() {
             return self.pipeline_layout.unwrap();
         }
 
-        let descriptor_set_layouts = [self.get_or_create_descriptor_set_layout(device, allocator)];
+        // Set 0 is the engine owned per-frame data, set 1 is the object type's own resources and set 2 is the bindless texture array if it is enabled
+        let mut descriptor_set_layouts = vec![global_descriptor_set_layout, self.get_or_create_descriptor_set_layout(device, allocator)];
+        descriptor_set_layouts.extend(bindless_texture_descriptor_set_layout);
+        // The draw index is always pushed, so that shaders can write the object id whether picking is enabled or not
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: PipelineManager::PICKING_PUSH_CONSTANT_SIZE,
+        };
         let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
             s_type: StructureType::PIPELINE_LAYOUT_CREATE_INFO,
-            set_layout_count: 1,
+            set_layout_count: descriptor_set_layouts.len() as u32,
             p_set_layouts: descriptor_set_layouts.as_ptr(),
-            push_constant_range_count: 0,
-            p_push_constant_ranges: std::ptr::null(),
+            push_constant_range_count: 1,
+            p_push_constant_ranges: &push_constant_range,
             ..Default::default()
         };
         self.pipeline_layout = Some(unsafe {
-            device.create_pipeline_layout(&pipeline_layout_create_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_pipeline_layout(&pipeline_layout_create_info, allocator.get_allocation_callbacks())
         }.unwrap());
         self.pipeline_layout.unwrap()
     }
 
-    fn get_or_create_descriptor_set_layout(&mut self, device: &Device, allocator: &mut VkAllocator) -> vk::DescriptorSetLayout {
+    fn get_or_create_descriptor_set_layout(&mut self, device: &Device, allocator: &VkAllocator) -> vk::DescriptorSetLayout {
         if self.descriptor_set_layout.is_some() {
             return self.descriptor_set_layout.unwrap();
         }
@@ -330,7 +536,7 @@ impl PipelineConfig {
         };
 
         self.descriptor_set_layout = Some(unsafe {
-            device.create_descriptor_set_layout(&layout_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_descriptor_set_layout(&layout_info, allocator.get_allocation_callbacks())
         }.unwrap());
 
         self.descriptor_set_layout.unwrap()
@@ -364,13 +570,18 @@ impl PartialEq for PipelineConfig {
             binding.stage_flags == binding2.stage_flags &&
             binding.p_immutable_samplers == binding2.p_immutable_samplers
         })) &&
-        self.descriptor_set_layout_bindings.len() == other.descriptor_set_layout_bindings.len() //&&
+        self.descriptor_set_layout_bindings.len() == other.descriptor_set_layout_bindings.len() &&
+        self.topology == other.topology &&
+        self.is_depth_write_enabled == other.is_depth_write_enabled &&
+        self.is_depth_test_enabled == other.is_depth_test_enabled &&
+        self.cull_mode == other.cull_mode &&
+        self.is_alpha_premultiplied == other.is_alpha_premultiplied
     }
 }
 
 impl Hash for PipelineConfig {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.shaders.iter().for_each(|shader| shader.path.hash(state));
+        self.shaders.iter().for_each(|shader| shader.source.hash(state));
         self.vertex_binding_info.binding.hash(state);
         self.vertex_binding_info.stride.hash(state);
         self.vertex_binding_info.input_rate.hash(state);
@@ -391,80 +602,686 @@ impl Hash for PipelineConfig {
             binding.p_immutable_samplers.hash(state);
         });
         self.descriptor_set_layout_bindings.len().hash(state);
+        self.topology.hash(state);
+        self.is_depth_write_enabled.hash(state);
+        self.is_depth_test_enabled.hash(state);
+        self.cull_mode.hash(state);
+        self.is_alpha_premultiplied.hash(state);
     }
 }
 
+struct FailedPipeline {
+    pipeline_config: PipelineConfig,
+    error: Cow<'static, str>,
+    shader_modified_times: Vec<Option<SystemTime>>,
+}
+
 pub struct PipelineManager {
     graphics_pipelines: Vec<(PipelineConfig, vk::Pipeline)>,
+    // The pipelines whose shaders failed, which are drawn with the error pipeline in graphics_pipelines until their shaders are changed and they can be created
+    failed_pipelines: Vec<FailedPipeline>,
+    // The error pipelines that have been replaced. The frames in flight might still use them, so they are destroyed with the manager
+    replaced_error_pipelines: Vec<vk::Pipeline>,
+    // The same pipelines for render targets with a color format, which have a single sample and no object id attachment. They share the layouts with the pipelines above
+    render_target_pipelines: Vec<(PipelineConfig, vk::Format, vk::Pipeline)>,
+    // The post effects' pipelines for each color format they write, which have no vertex buffer
+    fullscreen_pipelines: Vec<(ShaderInfo, vk::Format, vk::Pipeline)>,
     render_pass: Option<vk::RenderPass>,
+    global_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
+    // Every post effect samples its input image at set 0 and gets its uniforms as push constants
+    post_effect_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
+    post_effect_pipeline_layout: Option<vk::PipelineLayout>,
+    // Only set when bindless textures have been enabled
+    bindless_texture_descriptor_set_layout: Option<vk::DescriptorSetLayout>,
+    // Only set when the debug messenger is enabled, used to name the pipelines after their shaders
+    debug_utils_loader: Option<DebugUtils>,
+    // Finds the shader sources, changing the roots doesn't recompile the pipelines that have already been created
+    asset_resolver: AssetResolver,
+    is_picking_enabled: bool,
+    // The formats of the color attachments after the scene color and object id attachments, in the order of their fragment output locations
+    extra_color_attachment_formats: Vec<vk::Format>,
+    is_sample_rate_shading_supported: bool,
 }
 
 impl PipelineManager {
-    pub fn new(device: &Device, swapchain_format: vk::Format, msaa_samples: SampleCountFlags, depth_format: vk::Format, allocator: &mut VkAllocator) -> Self {
+    /// Each object id texel is the draw index plus one and the instance index, 0 means that no object was drawn there
+    pub const OBJECT_ID_FORMAT: vk::Format = vk::Format::R32G32_UINT;
+    /// The fragment push constant holds the draw index plus one as a u32
+    pub const PICKING_PUSH_CONSTANT_SIZE: u32 = std::mem::size_of::<u32>() as u32;
+
+    /// `color_format` is the format of the image the scene is drawn in, not the swapchain's.
+    pub fn new(device: &Device, color_format: vk::Format, msaa_samples: SampleCountFlags, depth_format: vk::Format, debug_utils_loader: Option<DebugUtils>, is_sample_rate_shading_supported: bool, allocator: &VkAllocator) -> Self {
+        let (post_effect_descriptor_set_layout, post_effect_pipeline_layout) = Self::create_post_effect_layouts(device, allocator);
         PipelineManager {
             graphics_pipelines: Vec::new(),
-            render_pass: Some(Self::create_render_pass(device, swapchain_format, msaa_samples, depth_format, allocator)),
+            failed_pipelines: Vec::new(),
+            replaced_error_pipelines: Vec::new(),
+            render_target_pipelines: Vec::new(),
+            fullscreen_pipelines: Vec::new(),
+            render_pass: Some(Self::create_render_pass(device, color_format, msaa_samples, depth_format, vk::AttachmentLoadOp::CLEAR, false, false, &[], allocator)),
+            global_descriptor_set_layout: Some(Self::create_global_descriptor_set_layout(device, allocator)),
+            post_effect_descriptor_set_layout: Some(post_effect_descriptor_set_layout),
+            post_effect_pipeline_layout: Some(post_effect_pipeline_layout),
+            bindless_texture_descriptor_set_layout: None,
+            debug_utils_loader,
+            asset_resolver: AssetResolver::new(),
+            is_picking_enabled: false,
+            extra_color_attachment_formats: Vec::new(),
+            is_sample_rate_shading_supported,
         }
     }
 
-    pub fn get_or_create_pipeline(&mut self, pipeline_config: &mut PipelineConfig, device: &Device, swapchain_extent: &vk::Extent2D, allocator: &mut VkAllocator) -> Result<vk::Pipeline, Cow<'static, str>> {
+    /// When the shaders fail to compile, the pipeline is created with the error shaders instead, see [`PipelineManager::get_pipeline_error`].
+    pub fn get_or_create_pipeline(&mut self, pipeline_config: &mut PipelineConfig, device: &Device, swapchain_extent: &vk::Extent2D, allocator: &VkAllocator) -> Result<vk::Pipeline, EngineError> {
         if let Some((p_config, pipeline)) = self.graphics_pipelines.iter().find(|(config, _)| config == pipeline_config) {
             if pipeline_config.pipeline_layout.is_none() {
                 // This is needed because some new objects with the same pipeline layout might be added, so we need to update their pipeline layout and descriptor_set_layout
                 pipeline_config.pipeline_layout = Some(p_config.pipeline_layout.unwrap());
                 pipeline_config.descriptor_set_layout = Some(p_config.descriptor_set_layout.unwrap());
             }
+            pipeline_config.buffer_binding_sizes = p_config.buffer_binding_sizes.clone();
+            pipeline_config.shader_bindings = p_config.shader_bindings.clone();
             Ok(*pipeline)
         } else {
-            println!("Did not find the pipeline in the list, creating a new one");
-            let pipeline = pipeline_config.create_graphics_pipeline(device, swapchain_extent, self.render_pass.unwrap(), allocator)?;
+            log::debug!(target: logging::PIPELINES, "Did not find the pipeline in the list, creating a new one");
+            let pipeline = match pipeline_config.create_graphics_pipeline(device, swapchain_extent, self.render_pass.unwrap(), self.global_descriptor_set_layout.unwrap(), self.bindless_texture_descriptor_set_layout, self.is_picking_enabled, self.extra_color_attachment_formats.len(), self.is_sample_rate_shading_supported, &self.asset_resolver, allocator) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    log::error!(target: logging::PIPELINES, "Failed to create the pipeline for {}, it is drawn with the error pipeline instead: {}", pipeline_config.get_shader_paths().join(", "), error);
+                    let mut error_config = pipeline_config.with_error_shaders();
+                    let pipeline = error_config.create_graphics_pipeline(device, swapchain_extent, self.render_pass.unwrap(), self.global_descriptor_set_layout.unwrap(), self.bindless_texture_descriptor_set_layout, self.is_picking_enabled, self.extra_color_attachment_formats.len(), self.is_sample_rate_shading_supported, &self.asset_resolver, allocator)?;
+                    // The objects' descriptor sets are made with the layouts of their own pipeline, so the real pipeline can use them when it works
+                    pipeline_config.descriptor_set_layout = error_config.descriptor_set_layout;
+                    pipeline_config.pipeline_layout = error_config.pipeline_layout;
+                    self.failed_pipelines.push(FailedPipeline {
+                        pipeline_config: pipeline_config.clone(),
+                        error,
+                        shader_modified_times: pipeline_config.get_shader_modified_times(&self.asset_resolver),
+                    });
+                    pipeline
+                },
+            };
+            if let Some(debug_utils_loader) = self.debug_utils_loader.as_ref() {
+                VkController::set_debug_utils_object_name(debug_utils_loader, device, vk::ObjectType::PIPELINE, pipeline.as_raw(), &pipeline_config.get_shader_paths().join(", "));
+            }
             self.graphics_pipelines.push((pipeline_config.clone(), pipeline));
             Ok(pipeline)
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut VkAllocator) {
+    /// Why the pipeline is drawn with the error shaders, or None when its own shaders work.
+    pub fn get_pipeline_error(&self, pipeline_config: &PipelineConfig) -> Option<&str> {
+        self.failed_pipelines.iter().find(|failed_pipeline| failed_pipeline.pipeline_config == *pipeline_config).map(|failed_pipeline| failed_pipeline.error.as_ref())
+    }
+
+    /// Tries to create the failed pipelines again if their shaders have been modified since the last try, and returns how many now have their own pipeline.
+    /// Render target pipelines keep the error shaders until they are recreated.
+    pub fn retry_failed_pipelines(&mut self, device: &Device, swapchain_extent: &vk::Extent2D, allocator: &VkAllocator) -> usize {
+        let mut num_fixed_pipelines = 0;
+        let mut failed_pipelines = std::mem::take(&mut self.failed_pipelines);
+        failed_pipelines.retain_mut(|failed_pipeline| {
+            let shader_modified_times = failed_pipeline.pipeline_config.get_shader_modified_times(&self.asset_resolver);
+            if shader_modified_times == failed_pipeline.shader_modified_times {
+                return true;
+            }
+            failed_pipeline.shader_modified_times = shader_modified_times;
+            let pipeline = match failed_pipeline.pipeline_config.create_graphics_pipeline(device, swapchain_extent, self.render_pass.unwrap(), self.global_descriptor_set_layout.unwrap(), self.bindless_texture_descriptor_set_layout, self.is_picking_enabled, self.extra_color_attachment_formats.len(), self.is_sample_rate_shading_supported, &self.asset_resolver, allocator) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    log::warn!(target: logging::PIPELINES, "The pipeline for {} still fails: {}", failed_pipeline.pipeline_config.get_shader_paths().join(", "), error);
+                    failed_pipeline.error = error;
+                    return true;
+                },
+            };
+            if let Some((_, error_pipeline)) = self.graphics_pipelines.iter_mut().find(|(config, _)| *config == failed_pipeline.pipeline_config) {
+                self.replaced_error_pipelines.push(std::mem::replace(error_pipeline, pipeline));
+            }
+            num_fixed_pipelines += 1;
+            false
+        });
+        self.failed_pipelines = failed_pipelines;
+        num_fixed_pipelines
+    }
+
+    /// The pipeline has to have been created with [`PipelineManager::get_or_create_pipeline`] first, since the render target pipeline uses its layout.
+    /// Render passes from [`PipelineManager::create_render_target_render_pass`] with the same color format are compatible, so one pipeline works for all of them.
+    pub fn get_or_create_render_target_pipeline(&mut self, pipeline_config: &PipelineConfig, color_format: vk::Format, render_pass: RenderPass, device: &Device, extent: &vk::Extent2D, allocator: &VkAllocator) -> Result<vk::Pipeline, Cow<'static, str>> {
+        if let Some((_, _, pipeline)) = self.render_target_pipelines.iter().find(|(config, format, _)| config == pipeline_config && *format == color_format) {
+            return Ok(*pipeline);
+        }
+        if pipeline_config.pipeline_layout.is_none() {
+            return Err(Cow::Borrowed("The pipeline has to be created before it can be used for a render target"));
+        }
+
+        let mut render_target_config = if self.get_pipeline_error(pipeline_config).is_some() { pipeline_config.with_error_shaders() } else { pipeline_config.clone() };
+        render_target_config.msaa_samples = SampleCountFlags::TYPE_1;
+        render_target_config.swapchain_format = color_format;
+        let pipeline = render_target_config.create_graphics_pipeline(device, extent, render_pass, self.global_descriptor_set_layout.unwrap(), self.bindless_texture_descriptor_set_layout, false, 0, self.is_sample_rate_shading_supported, &self.asset_resolver, allocator)?;
+        if let Some(debug_utils_loader) = self.debug_utils_loader.as_ref() {
+            VkController::set_debug_utils_object_name(debug_utils_loader, device, vk::ObjectType::PIPELINE, pipeline.as_raw(), &format!("{} (render target)", pipeline_config.get_shader_paths().join(", ")));
+        }
+        self.render_target_pipelines.push((pipeline_config.clone(), color_format, pipeline));
+        Ok(pipeline)
+    }
+
+    /// Draws one triangle that covers the whole render area with the fragment shader, which samples the input image at `layout(set = 0, binding = 0)`.
+    /// Render passes from [`PipelineManager::create_post_effect_render_pass`] with the same color format are compatible, so one pipeline works for all of them.
+    pub fn get_or_create_fullscreen_pipeline(&mut self, fragment_shader: &ShaderInfo, color_format: vk::Format, render_pass: RenderPass, device: &Device, allocator: &VkAllocator) -> Result<vk::Pipeline, Cow<'static, str>> {
+        if let Some((_, _, pipeline)) = self.fullscreen_pipelines.iter().find(|(shader, format, _)| shader == fragment_shader && *format == color_format) {
+            return Ok(*pipeline);
+        }
+        if fragment_shader.shader_stage_flag != vk::ShaderStageFlags::FRAGMENT {
+            return Err(Cow::Owned(format!("The post effect shader {} has to be a fragment shader", fragment_shader.source)));
+        }
+
+        let vertex_shader = BuiltinShader::FullscreenVertex.get_shader_info();
+        let vertex_code = PipelineConfig::get_shader_code(&vertex_shader, ShaderKind::Vertex, &self.asset_resolver)?;
+        let fragment_code = PipelineConfig::get_shader_code(fragment_shader, ShaderKind::Fragment, &self.asset_resolver)?;
+        let shader_modules = [(&vertex_shader, vertex_code), (fragment_shader, fragment_code)].map(|(shader_info, code)| {
+            (shader_info, PipelineConfig::create_shader_module(device, code, allocator))
+        });
+        let shader_stage_create_infos = shader_modules.iter().map(|(shader_info, shader_module)| vk::PipelineShaderStageCreateInfo {
+            s_type: StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+            stage: shader_info.shader_stage_flag,
+            module: *shader_module,
+            p_name: shader_info.entry_point.as_ptr(),
+            ..Default::default()
+        }).collect::<Vec<_>>();
+
+        // The triangle's corners are made from the vertex index
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
+            s_type: StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
+            ..Default::default()
+        };
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type: StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: vk::FALSE,
+            ..Default::default()
+        };
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            s_type: StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: dynamic_states.as_ptr(),
+            ..Default::default()
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            s_type: StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            s_type: StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
+            polygon_mode: vk::PolygonMode::FILL,
+            line_width: 1.0,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            ..Default::default()
+        };
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfo {
+            s_type: StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
+            rasterization_samples: SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+
+        // Every pixel is written, so there is nothing to blend with
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A,
+            blend_enable: vk::FALSE,
+            ..Default::default()
+        };
+        let color_blending = vk::PipelineColorBlendStateCreateInfo {
+            s_type: StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
+            logic_op_enable: vk::FALSE,
+            attachment_count: 1,
+            p_attachments: &color_blend_attachment,
+            ..Default::default()
+        };
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            s_type: StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
+            stage_count: shader_stage_create_infos.len() as u32,
+            p_stages: shader_stage_create_infos.as_ptr(),
+            p_vertex_input_state: &vertex_input_info,
+            p_input_assembly_state: &input_assembly,
+            p_viewport_state: &viewport_state,
+            p_rasterization_state: &rasterizer,
+            p_multisample_state: &multisampling,
+            p_color_blend_state: &color_blending,
+            p_dynamic_state: &dynamic_state,
+            layout: self.post_effect_pipeline_layout.unwrap(),
+            render_pass,
+            subpass: 0,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipeline = unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], allocator.get_allocation_callbacks())
+        };
+        for (_, shader_module) in shader_modules {
+            unsafe {
+                device.destroy_shader_module(shader_module, allocator.get_allocation_callbacks());
+            }
+        }
+        let pipeline = pipeline.map_err(|(_, err)| Cow::Owned(format!("Failed to create the post effect pipeline for {}: {}", fragment_shader.source, err)))?[0];
+
+        if let Some(debug_utils_loader) = self.debug_utils_loader.as_ref() {
+            VkController::set_debug_utils_object_name(debug_utils_loader, device, vk::ObjectType::PIPELINE, pipeline.as_raw(), &fragment_shader.source.to_string());
+        }
+        self.fullscreen_pipelines.push((fragment_shader.clone(), color_format, pipeline));
+        Ok(pipeline)
+    }
+
+    pub fn get_post_effect_descriptor_set_layout(&self) -> Option<vk::DescriptorSetLayout> {
+        self.post_effect_descriptor_set_layout
+    }
+
+    pub fn get_post_effect_pipeline_layout(&self) -> Option<vk::PipelineLayout> {
+        self.post_effect_pipeline_layout
+    }
+
+    pub fn get_asset_resolver(&self) -> &AssetResolver {
+        &self.asset_resolver
+    }
+
+    pub fn get_asset_resolver_mut(&mut self) -> &mut AssetResolver {
+        &mut self.asset_resolver
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &VkAllocator) {
+        for (_, _, pipeline) in self.fullscreen_pipelines.drain(..) {
+            unsafe {
+                device.destroy_pipeline(pipeline, allocator.get_allocation_callbacks());
+            }
+        }
+        for pipeline in self.replaced_error_pipelines.drain(..).chain(self.render_target_pipelines.drain(..).map(|(_, _, pipeline)| pipeline)) {
+            unsafe {
+                device.destroy_pipeline(pipeline, allocator.get_allocation_callbacks());
+            }
+        }
         for (config, pipeline) in self.graphics_pipelines.iter() {
             unsafe {
-                device.destroy_pipeline(*pipeline, Some(&allocator.get_allocation_callbacks()));
-                device.destroy_pipeline_layout(config.pipeline_layout.unwrap(), Some(&allocator.get_allocation_callbacks()));
-                device.destroy_descriptor_set_layout(config.descriptor_set_layout.unwrap(), Some(&allocator.get_allocation_callbacks()));
-                // device.destroy_descriptor_set_layout(config.descriptor_set_layout.unwrap(), Some(&allocator.get_allocation_callbacks()));
+                device.destroy_pipeline(*pipeline, allocator.get_allocation_callbacks());
+                device.destroy_pipeline_layout(config.pipeline_layout.unwrap(), allocator.get_allocation_callbacks());
+                device.destroy_descriptor_set_layout(config.descriptor_set_layout.unwrap(), allocator.get_allocation_callbacks());
+                // device.destroy_descriptor_set_layout(config.descriptor_set_layout.unwrap(), allocator.get_allocation_callbacks());
             }
         }
         unsafe {
-            device.destroy_render_pass(self.render_pass.unwrap(), Some(&allocator.get_allocation_callbacks()));
+            device.destroy_render_pass(self.render_pass.unwrap(), allocator.get_allocation_callbacks());
+            device.destroy_descriptor_set_layout(self.global_descriptor_set_layout.unwrap(), allocator.get_allocation_callbacks());
+            device.destroy_pipeline_layout(self.post_effect_pipeline_layout.unwrap(), allocator.get_allocation_callbacks());
+            device.destroy_descriptor_set_layout(self.post_effect_descriptor_set_layout.unwrap(), allocator.get_allocation_callbacks());
+            if let Some(bindless_texture_descriptor_set_layout) = self.bindless_texture_descriptor_set_layout {
+                device.destroy_descriptor_set_layout(bindless_texture_descriptor_set_layout, allocator.get_allocation_callbacks());
+            }
         }
         self.graphics_pipelines.clear();
+        self.failed_pipelines.clear();
     }
 
     pub fn get_render_pass(&self) -> Option<vk::RenderPass> {
         self.render_pass
     }
 
-    fn create_render_pass(device: &Device, swapchain_format: vk::Format, msaa_samples: SampleCountFlags, depth_format: vk::Format, allocator: &mut VkAllocator) -> vk::RenderPass {
-        let color_attachment = vk::AttachmentDescription {
-            format: swapchain_format,
-            samples: msaa_samples,
-            load_op: vk::AttachmentLoadOp::CLEAR,
+    /// Replaces the render pass with one that loads the color attachment with `color_load_op` and stores the depth when `is_depth_stored` is set.
+    /// The framebuffers have to be recreated afterwards, but the pipelines stay valid since render passes that only differ in load and store operations and layouts are compatible.
+    pub fn recreate_render_pass(&mut self, device: &Device, color_format: vk::Format, msaa_samples: SampleCountFlags, depth_format: vk::Format, color_load_op: vk::AttachmentLoadOp, is_depth_stored: bool, allocator: &VkAllocator) {
+        unsafe {
+            device.destroy_render_pass(self.render_pass.unwrap(), allocator.get_allocation_callbacks());
+        }
+        self.render_pass = Some(Self::create_render_pass(device, color_format, msaa_samples, depth_format, color_load_op, is_depth_stored, self.is_picking_enabled, &self.extra_color_attachment_formats, allocator));
+    }
+
+    /// Adds the object id attachment to the render pass the next time it is recreated and to every pipeline created after this.
+    /// It has to be enabled before any pipelines are created, since the pipelines have to agree with the render pass on the number of color attachments.
+    pub fn enable_picking(&mut self) -> Result<(), Cow<'static, str>> {
+        if self.is_picking_enabled {
+            return Err(Cow::Borrowed("Picking has already been enabled"));
+        }
+        if !self.graphics_pipelines.is_empty() {
+            return Err(Cow::Borrowed("Picking has to be enabled before any objects are added"));
+        }
+        self.is_picking_enabled = true;
+        Ok(())
+    }
+
+    pub fn is_picking_enabled(&self) -> bool {
+        self.is_picking_enabled
+    }
+
+    /// Adds color attachments with the formats to the render pass the next time it is recreated and to every pipeline created after this.
+    /// The fragment shaders write them at the locations after the scene color, and after the object id when picking is enabled.
+    /// Like picking, they have to be set before any pipelines are created.
+    pub fn set_extra_color_attachments(&mut self, formats: &[vk::Format]) -> Result<(), Cow<'static, str>> {
+        if !self.graphics_pipelines.is_empty() {
+            return Err(Cow::Borrowed("The extra color attachments have to be set before any objects are added"));
+        }
+        self.extra_color_attachment_formats = formats.to_vec();
+        Ok(())
+    }
+
+    pub fn get_extra_color_attachment_formats(&self) -> &[vk::Format] {
+        &self.extra_color_attachment_formats
+    }
+
+    pub fn get_global_descriptor_set_layout(&self) -> Option<vk::DescriptorSetLayout> {
+        self.global_descriptor_set_layout
+    }
+
+    /// Creates the layout of the bindless texture array, which is added as set 2 to all pipelines created after this.
+    /// It has to be enabled before any pipelines are created, so that all pipeline layouts agree on the sets.
+    pub fn enable_bindless_textures(&mut self, device: &Device, max_textures: u32, allocator: &VkAllocator) -> Result<vk::DescriptorSetLayout, Cow<'static, str>> {
+        if self.bindless_texture_descriptor_set_layout.is_some() {
+            return Err(Cow::Borrowed("Bindless textures have already been enabled"));
+        }
+        if !self.graphics_pipelines.is_empty() {
+            return Err(Cow::Borrowed("Bindless textures have to be enabled before any objects are added"));
+        }
+
+        let layout_bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: max_textures,
+                stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+        // Not every element of the array has a texture, and textures are added while the set might be in use
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND];
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_BINDING_FLAGS_CREATE_INFO,
+            binding_count: binding_flags.len() as u32,
+            p_binding_flags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: &binding_flags_info as *const _ as *const std::ffi::c_void,
+            flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+            binding_count: layout_bindings.len() as u32,
+            p_bindings: layout_bindings.as_ptr(),
+        };
+
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(&layout_info, allocator.get_allocation_callbacks())
+        }.map_err(|err| Cow::Owned(format!("Failed to create the bindless texture descriptor set layout: {}", err)))?;
+        self.bindless_texture_descriptor_set_layout = Some(descriptor_set_layout);
+        Ok(descriptor_set_layout)
+    }
+
+    fn create_global_descriptor_set_layout(device: &Device, allocator: &VkAllocator) -> vk::DescriptorSetLayout {
+        let layout_bindings = [
+            // Dynamic so that every view drawn in a frame can select its own per-frame data with an offset
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
+                p_immutable_samplers: std::ptr::null(),
+            },
+            // The lights are the same for every view, so they don't need an offset
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            binding_count: layout_bindings.len() as u32,
+            p_bindings: layout_bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device.create_descriptor_set_layout(&layout_info, allocator.get_allocation_callbacks())
+        }.unwrap()
+    }
+
+    /// A single color attachment that every pixel is written to, so its contents are not loaded. `final_layout` is `SHADER_READ_ONLY_OPTIMAL` when the next post effect samples it and `PRESENT_SRC_KHR` for the swapchain.
+    pub fn create_post_effect_render_pass(device: &Device, color_format: vk::Format, final_layout: vk::ImageLayout, allocator: &VkAllocator) -> vk::RenderPass {
+        let color_attachment = vk::AttachmentDescription2 {
+            s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+            format: color_format,
+            samples: SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
             store_op: vk::AttachmentStoreOp::STORE,
             stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
             stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
             initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+            ..Default::default()
+        };
+
+        let color_attachment_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            ..Default::default()
+        };
+
+        let subpass = vk::SubpassDescription2 {
+            s_type: StructureType::SUBPASS_DESCRIPTION_2,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            ..Default::default()
+        };
+
+        // The input was written by the pass before, and the image written now might still be sampled by the effect before that.
+        // The stage also waits for the swapchain image to be acquired, like the main pass
+        let dependency = vk::SubpassDependency2 {
+            s_type: StructureType::SUBPASS_DEPENDENCY_2,
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            dst_subpass: 0,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::SHADER_READ,
+            ..Default::default()
+        };
+
+        let render_pass_info = vk::RenderPassCreateInfo2 {
+            s_type: StructureType::RENDER_PASS_CREATE_INFO_2,
+            attachment_count: 1,
+            p_attachments: &color_attachment,
+            subpass_count: 1,
+            p_subpasses: &subpass,
+            dependency_count: 1,
+            p_dependencies: &dependency,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.create_render_pass2(&render_pass_info, allocator.get_allocation_callbacks())
+        }.unwrap()
+    }
+
+    fn create_post_effect_layouts(device: &Device, allocator: &VkAllocator) -> (vk::DescriptorSetLayout, vk::PipelineLayout) {
+        let layout_bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            binding_count: layout_bindings.len() as u32,
+            p_bindings: layout_bindings.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(&layout_info, allocator.get_allocation_callbacks())
+        }.unwrap();
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: PostEffect::MAX_UNIFORMS_SIZE as u32,
+        };
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
+            s_type: StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            set_layout_count: 1,
+            p_set_layouts: &descriptor_set_layout,
+            push_constant_range_count: 1,
+            p_push_constant_ranges: &push_constant_range,
+            ..Default::default()
+        };
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(&pipeline_layout_create_info, allocator.get_allocation_callbacks())
+        }.unwrap();
+        (descriptor_set_layout, pipeline_layout)
+    }
+
+    /// A color and a depth attachment with a single sample. The color attachment ends in `SHADER_READ_ONLY_OPTIMAL`, and the dependencies make the main pass wait for it before sampling it.
+    pub fn create_render_target_render_pass(device: &Device, color_format: vk::Format, depth_format: vk::Format, allocator: &VkAllocator) -> vk::RenderPass {
+        let attachments = [
+            vk::AttachmentDescription2 {
+                s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+                format: color_format,
+                samples: SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription2 {
+                s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+                format: depth_format,
+                samples: SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+        ];
+
+        let color_attachment_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            ..Default::default()
+        };
+
+        let depth_attachment_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            ..Default::default()
+        };
+
+        let subpass = vk::SubpassDescription2 {
+            s_type: StructureType::SUBPASS_DESCRIPTION_2,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            p_depth_stencil_attachment: &depth_attachment_ref,
+            ..Default::default()
+        };
+
+        let dependencies = [
+            // The last frame's main pass might still be sampling the image
+            vk::SubpassDependency2 {
+                s_type: StructureType::SUBPASS_DEPENDENCY_2,
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency2 {
+                s_type: StructureType::SUBPASS_DEPENDENCY_2,
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo2 {
+            s_type: StructureType::RENDER_PASS_CREATE_INFO_2,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: 1,
+            p_subpasses: &subpass,
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device.create_render_pass2(&render_pass_info, allocator.get_allocation_callbacks())
+        }.unwrap()
+    }
+
+    /// When multisampling is used the depth and the object id are resolved into attachments with a single sample, since a multisampled image can not be copied to a buffer.
+    /// The attachments are the color, depth, color resolve, depth resolve and then the object id and its resolve, where the attachments that are not used are left out.
+    /// The color is resolved into the scene image, which the post effects sample afterwards.
+    fn create_render_pass(device: &Device, color_format: vk::Format, msaa_samples: SampleCountFlags, depth_format: vk::Format, color_load_op: vk::AttachmentLoadOp, is_depth_stored: bool, has_object_id_attachment: bool, extra_color_formats: &[vk::Format], allocator: &VkAllocator) -> vk::RenderPass {
+        let is_depth_resolved = msaa_samples != SampleCountFlags::TYPE_1;
+        let depth_store_op = if is_depth_stored { vk::AttachmentStoreOp::STORE } else { vk::AttachmentStoreOp::DONT_CARE };
+
+        // The previous contents are only kept when the image is not transitioned from UNDEFINED
+        let color_initial_layout = if color_load_op == vk::AttachmentLoadOp::LOAD { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL } else { vk::ImageLayout::UNDEFINED };
+        let color_attachment = vk::AttachmentDescription2 {
+            s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+            format: color_format,
+            samples: msaa_samples,
+            load_op: color_load_op,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: color_initial_layout,
             final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             ..Default::default()
         };
 
-        let color_attachment_ref = vk::AttachmentReference {
+        let color_attachment_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
             attachment: 0,
             layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            ..Default::default()
         };
 
-        let depth_attachment = vk::AttachmentDescription {
+        let depth_attachment = vk::AttachmentDescription2 {
+            s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
             format: depth_format,
             samples: msaa_samples,
             load_op: vk::AttachmentLoadOp::CLEAR,
-            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            store_op: if is_depth_resolved { vk::AttachmentStoreOp::DONT_CARE } else { depth_store_op },
             stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
             stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
             initial_layout: vk::ImageLayout::UNDEFINED,
@@ -472,61 +1289,261 @@ impl PipelineManager {
             ..Default::default()
         };
 
-        let depth_attachment_ref = vk::AttachmentReference {
+        let depth_attachment_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
             attachment: 1,
             layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            ..Default::default()
         };
 
-        let color_attachment_resolve = vk::AttachmentDescription {
-            format: swapchain_format,
+        let color_attachment_resolve = vk::AttachmentDescription2 {
+            s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+            format: color_format,
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::DONT_CARE,
             store_op: vk::AttachmentStoreOp::STORE,
             stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
             stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
             initial_layout: vk::ImageLayout::UNDEFINED,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             ..Default::default()
         };
 
-        let color_attachment_resolve_ref = vk::AttachmentReference {
+        let color_attachment_resolve_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
             attachment: 2,
             layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            ..Default::default()
         };
 
-        let subpass = vk::SubpassDescription {
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-            color_attachment_count: 1,
-            p_color_attachments: &color_attachment_ref,
-            p_depth_stencil_attachment: &depth_attachment_ref,
-            p_resolve_attachments: &color_attachment_resolve_ref,
+        let depth_attachment_resolve = vk::AttachmentDescription2 {
+            s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+            format: depth_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: depth_store_op,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
             ..Default::default()
         };
 
-        let dependency = vk::SubpassDependency {
-            src_subpass: vk::SUBPASS_EXTERNAL,
-            dst_subpass: 0,
-            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        let depth_attachment_resolve_ref = vk::AttachmentReference2 {
+            s_type: StructureType::ATTACHMENT_REFERENCE_2,
+            attachment: 3,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            ..Default::default()
+        };
+
+        // Resolving sample zero is supported by every device that supports depth resolves
+        let depth_stencil_resolve = vk::SubpassDescriptionDepthStencilResolve {
+            s_type: StructureType::SUBPASS_DESCRIPTION_DEPTH_STENCIL_RESOLVE,
+            depth_resolve_mode: vk::ResolveModeFlags::SAMPLE_ZERO,
+            stencil_resolve_mode: vk::ResolveModeFlags::SAMPLE_ZERO,
+            p_depth_stencil_resolve_attachment: &depth_attachment_resolve_ref,
             ..Default::default()
         };
 
-        let attachments = [color_attachment, depth_attachment, color_attachment_resolve];
-        let render_pass_info = vk::RenderPassCreateInfo {
-            s_type: StructureType::RENDER_PASS_CREATE_INFO,
+        let mut attachments = vec![color_attachment, depth_attachment, color_attachment_resolve];
+        if is_depth_resolved {
+            attachments.push(depth_attachment_resolve);
+        }
+
+        let mut color_attachment_refs = vec![color_attachment_ref];
+        let mut resolve_attachment_refs = vec![color_attachment_resolve_ref];
+        if has_object_id_attachment {
+            // The object id is cleared to 0 every frame, so that pixels without any object can be told apart
+            let object_id_attachment = vk::AttachmentDescription2 {
+                s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+                format: Self::OBJECT_ID_FORMAT,
+                samples: msaa_samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: if is_depth_resolved { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE },
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            };
+            color_attachment_refs.push(vk::AttachmentReference2 {
+                s_type: StructureType::ATTACHMENT_REFERENCE_2,
+                attachment: attachments.len() as u32,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                ..Default::default()
+            });
+            attachments.push(object_id_attachment);
+
+            // Integer attachments are resolved by picking one of the samples, so the ids are never mixed
+            if is_depth_resolved {
+                resolve_attachment_refs.push(vk::AttachmentReference2 {
+                    s_type: StructureType::ATTACHMENT_REFERENCE_2,
+                    attachment: attachments.len() as u32,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    ..Default::default()
+                });
+                attachments.push(vk::AttachmentDescription2 {
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::DONT_CARE,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    ..object_id_attachment
+                });
+            } else {
+                resolve_attachment_refs.push(vk::AttachmentReference2 {
+                    s_type: StructureType::ATTACHMENT_REFERENCE_2,
+                    attachment: vk::ATTACHMENT_UNUSED,
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Each extra attachment is followed by its resolve attachment when multisampling is used, and the single sampled image is left ready to be sampled
+        for format in extra_color_formats {
+            let extra_color_attachment = vk::AttachmentDescription2 {
+                s_type: StructureType::ATTACHMENT_DESCRIPTION_2,
+                format: *format,
+                samples: msaa_samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: if is_depth_resolved { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE },
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: if is_depth_resolved { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL } else { vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL },
+                ..Default::default()
+            };
+            color_attachment_refs.push(vk::AttachmentReference2 {
+                s_type: StructureType::ATTACHMENT_REFERENCE_2,
+                attachment: attachments.len() as u32,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                ..Default::default()
+            });
+            attachments.push(extra_color_attachment);
+
+            if is_depth_resolved {
+                resolve_attachment_refs.push(vk::AttachmentReference2 {
+                    s_type: StructureType::ATTACHMENT_REFERENCE_2,
+                    attachment: attachments.len() as u32,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    ..Default::default()
+                });
+                attachments.push(vk::AttachmentDescription2 {
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::DONT_CARE,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ..extra_color_attachment
+                });
+            } else {
+                resolve_attachment_refs.push(vk::AttachmentReference2 {
+                    s_type: StructureType::ATTACHMENT_REFERENCE_2,
+                    attachment: vk::ATTACHMENT_UNUSED,
+                    ..Default::default()
+                });
+            }
+        }
+
+        let subpass = vk::SubpassDescription2 {
+            s_type: StructureType::SUBPASS_DESCRIPTION_2,
+            p_next: if is_depth_resolved { &depth_stencil_resolve as *const _ as *const std::ffi::c_void } else { std::ptr::null() },
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: color_attachment_refs.len() as u32,
+            p_color_attachments: color_attachment_refs.as_ptr(),
+            p_depth_stencil_attachment: &depth_attachment_ref,
+            p_resolve_attachments: resolve_attachment_refs.as_ptr(),
+            ..Default::default()
+        };
+
+        // The scene image might still be read by the post effects or the blit of the previous frame
+        let dependencies = [
+            vk::SubpassDependency2 {
+                s_type: StructureType::SUBPASS_DEPENDENCY_2,
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency2 {
+                s_type: StructureType::SUBPASS_DEPENDENCY_2,
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+                dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo2 {
+            s_type: StructureType::RENDER_PASS_CREATE_INFO_2,
             attachment_count: attachments.len() as u32,
             p_attachments: attachments.as_ptr(),
             subpass_count: 1,
             p_subpasses: &subpass,
-            dependency_count: 1,
-            p_dependencies: &dependency,
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
             ..Default::default()
         };
 
         unsafe {
-            device.create_render_pass(&render_pass_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_render_pass2(&render_pass_info, allocator.get_allocation_callbacks())
         }.unwrap()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn shader_that_does_not_compile_fails_with_pipeline_creation() {
+        let root = std::env::temp_dir().join(format!("artewald-engine-2-broken-shader-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("shaders")).unwrap();
+        std::fs::write(root.join("shaders/broken.frag"), "#version 450\nthis is not glsl\n").unwrap();
+        let mut asset_resolver = AssetResolver::new();
+        asset_resolver.set_root(root.clone());
+
+        let path = Path::new("shaders/broken.frag");
+        let result = PipelineConfig::compile_shader(path, "main", ShaderKind::Fragment, "shaders/broken.frag", &asset_resolver);
+        let _ = std::fs::remove_dir_all(&root);
+        match result {
+            Err(EngineError::PipelineCreation { shader, log }) => {
+                assert_eq!(shader, ShaderSource::File(path.to_path_buf()));
+                assert!(log.contains("shaders/broken.frag"), "The compiler log doesn't name the shader: {}", log);
+            },
+            Err(error) => panic!("Expected a pipeline creation error, got: {}", error),
+            Ok(_) => panic!("The broken shader compiled"),
+        }
+    }
+
+    #[test]
+    fn missing_shader_fails_with_an_asset_error() {
+        let mut asset_resolver = AssetResolver::new();
+        asset_resolver.set_root(std::env::temp_dir().join("artewald-engine-2-no-shaders"));
+        let result = PipelineConfig::compile_shader(Path::new("shaders/missing.frag"), "main", ShaderKind::Fragment, "shaders/missing.frag", &asset_resolver);
+        assert!(matches!(result, Err(EngineError::Asset(_))));
+    }
+
+    #[test]
+    fn invalid_vertex_input_fails_with_an_error_instead_of_a_panic() {
+        let vertex_binding_info = vk::VertexInputBindingDescription { binding: 0, stride: 24, input_rate: vk::VertexInputRate::VERTEX };
+        let attribute = |binding, location, offset| vk::VertexInputAttributeDescription { binding, location, format: vk::Format::R32G32B32_SFLOAT, offset };
+
+        assert!(PipelineConfig::validate_vertex_input(&vertex_binding_info, &[attribute(0, 0, 0), attribute(0, 1, 12)]).is_ok());
+        for vertex_attribute_info in [vec![], vec![attribute(0, 0, 0), attribute(1, 1, 12)], vec![attribute(0, 0, 0), attribute(0, 0, 12)]] {
+            let result = PipelineConfig::validate_vertex_input(&vertex_binding_info, &vertex_attribute_info);
+            assert!(matches!(result, Err(EngineError::Other(_))), "Expected the message of the invalid vertex input, got: {:?}", result);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::{borrow::Cow, ffi::CString};
+
+use ash::{vk::{self, DescriptorPool, DescriptorSet, ImageView, PhysicalDevice, StructureType}, Device, Instance};
+
+use crate::{logging, builtin_shaders::BuiltinShader, free_allocations_add_error_string, pipeline_manager::{PipelineManager, ShaderInfo, ShaderSource}, sampler_manager::{SamplerConfig, SamplerManager}, vk_allocator::{AllocationInfo, VkAllocator}};
+
+/// A fullscreen pass that reads the image of the pass before it, starting with the scene, at `layout(set = 0, binding = 0) uniform sampler2D`.
+/// The uniforms are given to the fragment shader as push constants, so they have to be at most [`PostEffect::MAX_UNIFORMS_SIZE`] bytes and a multiple of 4.
+#[derive(Clone)]
+pub struct PostEffect {
+    pub fragment_shader: ShaderInfo,
+    pub uniforms: Vec<u8>,
+}
+
+impl PostEffect {
+    /// The smallest push constant size every device supports.
+    pub const MAX_UNIFORMS_SIZE: usize = 128;
+    const REINHARD_OPERATOR: u32 = 0;
+    const ACES_OPERATOR: u32 = 1;
+
+    /// The fragment shader is read from the asset roots.
+    pub fn new(fragment_shader_path: &str, uniforms: Vec<u8>) -> Self {
+        Self {
+            fragment_shader: ShaderInfo {
+                source: ShaderSource::File(std::path::PathBuf::from(fragment_shader_path)),
+                shader_stage_flag: vk::ShaderStageFlags::FRAGMENT,
+                entry_point: CString::new("main").unwrap(),
+            },
+            uniforms,
+        }
+    }
+
+    fn builtin(fragment_shader: BuiltinShader, uniforms: Vec<u8>) -> Self {
+        Self {
+            fragment_shader: fragment_shader.get_shader_info(),
+            uniforms,
+        }
+    }
+
+    /// Maps the HDR colors to the displayable range with `color / (1 + color)`, after multiplying them with the exposure.
+    pub fn reinhard_tonemap(exposure: f32) -> Self {
+        Self::tonemap(exposure, Self::REINHARD_OPERATOR)
+    }
+
+    /// Narkowicz's fit of the ACES filmic curve, which keeps more contrast than Reinhard.
+    pub fn aces_tonemap(exposure: f32) -> Self {
+        Self::tonemap(exposure, Self::ACES_OPERATOR)
+    }
+
+    /// Smooths the edges the main pass left jagged. It expects colors in the displayable range, so it goes after the tonemap.
+    pub fn fxaa() -> Self {
+        Self::builtin(BuiltinShader::FxaaFragment, Vec::new())
+    }
+
+    fn tonemap(exposure: f32, operator: u32) -> Self {
+        Self::builtin(BuiltinShader::TonemapFragment, exposure.to_ne_bytes().into_iter().chain(operator.to_ne_bytes()).collect())
+    }
+
+    // Used instead of the blit when the device can't blit the scene to the swapchain
+    fn copy() -> Self {
+        Self::builtin(BuiltinShader::CopyFragment, Vec::new())
+    }
+
+    // The last pass in the linear workflow when the swapchain format doesn't encode to sRGB on write
+    fn gamma_encode() -> Self {
+        Self::builtin(BuiltinShader::GammaEncodeFragment, Vec::new())
+    }
+
+    fn validate(&self) -> Result<(), Cow<'static, str>> {
+        if self.uniforms.len() > Self::MAX_UNIFORMS_SIZE || !self.uniforms.len().is_multiple_of(4) {
+            return Err(Cow::Owned(format!("The uniforms of the post effect {} are {} bytes, but they have to be a multiple of 4 and at most {} bytes", self.fragment_shader.source, self.uniforms.len(), Self::MAX_UNIFORMS_SIZE)));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the post effects on the scene image after the main pass. The passes ping-pong between two intermediate images and the last one writes to the swapchain image.
+/// Without any effects the scene is blitted straight to the swapchain image.
+pub struct PostProcessor {
+    // The effects that were set, the passes also include the copy or the gamma encode the engine adds after them
+    effects: Vec<PostEffect>,
+    passes: Vec<PostEffect>,
+    is_gamma_encoded: bool,
+    pipelines: Vec<vk::Pipeline>,
+    pipeline_layout: vk::PipelineLayout,
+    // The intermediate images use the scene format, so they keep the values above 1 between the effects
+    scene_format: vk::Format,
+    swapchain_format: vk::Format,
+    is_blit_supported: bool,
+    intermediate_render_pass: vk::RenderPass,
+    final_render_pass: vk::RenderPass,
+    sampler: vk::Sampler,
+    descriptor_pool: DescriptorPool,
+    // Reads the scene and the two intermediate images
+    descriptor_sets: [DescriptorSet; 3],
+    // The rest depends on the swapchain, so it is recreated with it
+    extent: vk::Extent2D,
+    intermediate_targets: Vec<(AllocationInfo, vk::Framebuffer)>,
+    swapchain_framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl PostProcessor {
+    pub fn new(device: &Device, instance: &Instance, physical_device: &PhysicalDevice, scene_format: vk::Format, swapchain_format: vk::Format, is_blit_supported: bool, pipeline_manager: &mut PipelineManager, sampler_manager: &mut SamplerManager, allocator: &VkAllocator) -> Result<Self, Cow<'static, str>> {
+        // Every effect reads one texel per pixel at the same resolution, so there are no mip levels to filter between
+        let sampler_config = SamplerConfig {
+            s_type: StructureType::SAMPLER_CREATE_INFO,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            anisotropy_enable: vk::FALSE,
+            max_anisotropy: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: 0.0,
+        };
+        let sampler = sampler_manager.get_or_create_sampler(device, instance, physical_device, sampler_config, allocator)?;
+        let (descriptor_pool, descriptor_sets) = Self::create_descriptor_sets(device, pipeline_manager.get_post_effect_descriptor_set_layout().unwrap(), allocator)?;
+
+        let mut post_processor = Self {
+            effects: Vec::new(),
+            passes: Vec::new(),
+            is_gamma_encoded: false,
+            pipelines: Vec::new(),
+            pipeline_layout: pipeline_manager.get_post_effect_pipeline_layout().unwrap(),
+            scene_format,
+            swapchain_format,
+            is_blit_supported,
+            intermediate_render_pass: PipelineManager::create_post_effect_render_pass(device, scene_format, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, allocator),
+            final_render_pass: PipelineManager::create_post_effect_render_pass(device, swapchain_format, vk::ImageLayout::PRESENT_SRC_KHR, allocator),
+            sampler,
+            descriptor_pool,
+            descriptor_sets,
+            extent: vk::Extent2D::default(),
+            intermediate_targets: Vec::new(),
+            swapchain_framebuffers: Vec::new(),
+        };
+        if let Err(e) = post_processor.set_effects(Vec::new(), device, pipeline_manager, allocator) {
+            post_processor.destroy(device, allocator);
+            return Err(e);
+        }
+        Ok(post_processor)
+    }
+
+    /// Creates the pipelines of the effects. The targets have to be destroyed before and created again after, since the number of intermediate images depends on the number of effects.
+    pub fn set_effects(&mut self, effects: Vec<PostEffect>, device: &Device, pipeline_manager: &mut PipelineManager, allocator: &VkAllocator) -> Result<(), Cow<'static, str>> {
+        effects.iter().try_for_each(|effect| effect.validate())?;
+        let mut passes = effects.clone();
+        if self.is_gamma_encoded {
+            passes.push(PostEffect::gamma_encode());
+        } else if passes.is_empty() && !self.is_blit_supported {
+            passes.push(PostEffect::copy());
+        }
+
+        let mut pipelines = Vec::with_capacity(passes.len());
+        for (pass_index, effect) in passes.iter().enumerate() {
+            let (color_format, render_pass) = if pass_index + 1 == passes.len() { (self.swapchain_format, self.final_render_pass) } else { (self.scene_format, self.intermediate_render_pass) };
+            pipelines.push(pipeline_manager.get_or_create_fullscreen_pipeline(&effect.fragment_shader, color_format, render_pass, device, allocator)?);
+        }
+        self.effects = effects;
+        self.passes = passes;
+        self.pipelines = pipelines;
+        Ok(())
+    }
+
+    /// Adds a pass after the effects that encodes the linear colors to sRGB, for swapchain formats that don't do it on write. The targets have to be recreated like for [`PostProcessor::set_effects`].
+    pub fn set_gamma_encoded(&mut self, is_gamma_encoded: bool, device: &Device, pipeline_manager: &mut PipelineManager, allocator: &VkAllocator) -> Result<(), Cow<'static, str>> {
+        let was_gamma_encoded = self.is_gamma_encoded;
+        self.is_gamma_encoded = is_gamma_encoded;
+        let result = self.set_effects(self.effects.clone(), device, pipeline_manager, allocator);
+        if result.is_err() {
+            self.is_gamma_encoded = was_gamma_encoded;
+        }
+        result
+    }
+
+    /// The scene image view is what the first effect reads, and there is a framebuffer for each swapchain image view.
+    pub fn create_targets(&mut self, device: &Device, scene_image_view: ImageView, swapchain_image_views: &[ImageView], extent: vk::Extent2D, allocator: &VkAllocator) -> Result<(), Cow<'static, str>> {
+        self.extent = extent;
+        // Each pass except the last writes an intermediate image, and the pass after the next can write to the same image again
+        let num_intermediate_targets = self.passes.len().saturating_sub(1).min(2);
+        for _ in 0..num_intermediate_targets {
+            let target = match Self::create_intermediate_target(device, self.intermediate_render_pass, self.scene_format, extent, allocator) {
+                Ok(target) => target,
+                Err(e) => {
+                    self.destroy_targets(device, allocator);
+                    return Err(e);
+                },
+            };
+            self.intermediate_targets.push(target);
+        }
+        if !self.passes.is_empty() {
+            for swapchain_image_view in swapchain_image_views {
+                let framebuffer = match Self::create_framebuffer(device, self.final_render_pass, *swapchain_image_view, extent, allocator) {
+                    Ok(framebuffer) => framebuffer,
+                    Err(e) => {
+                        self.destroy_targets(device, allocator);
+                        return Err(e);
+                    },
+                };
+                self.swapchain_framebuffers.push(framebuffer);
+            }
+        }
+
+        let image_infos = std::iter::once(scene_image_view).chain(self.intermediate_targets.iter().map(|(image, _)| image.get_image_view().unwrap())).map(|image_view| vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }).collect::<Vec<_>>();
+        let descriptor_writes = image_infos.iter().zip(self.descriptor_sets.iter()).map(|(image_info, descriptor_set)| vk::WriteDescriptorSet {
+            s_type: StructureType::WRITE_DESCRIPTOR_SET,
+            dst_set: *descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            p_image_info: image_info,
+            ..Default::default()
+        }).collect::<Vec<_>>();
+        unsafe {
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+        Ok(())
+    }
+
+    /// The frames in flight have to be done with the targets first.
+    pub fn destroy_targets(&mut self, device: &Device, allocator: &VkAllocator) {
+        let mut error_str = String::new();
+        for (image, framebuffer) in self.intermediate_targets.drain(..) {
+            unsafe {
+                device.destroy_framebuffer(framebuffer, allocator.get_allocation_callbacks());
+            }
+            free_allocations_add_error_string!(allocator, [image], error_str);
+        }
+        for framebuffer in self.swapchain_framebuffers.drain(..) {
+            unsafe {
+                device.destroy_framebuffer(framebuffer, allocator.get_allocation_callbacks());
+            }
+        }
+        if !error_str.is_empty() {
+            log::error!(target: logging::RENDERER, "Failed to free the post processing images: {}", error_str);
+        }
+    }
+
+    /// Records the effects after the main pass, which has left the scene image in `SHADER_READ_ONLY_OPTIMAL`. The last pass leaves the swapchain image ready to be presented.
+    /// Returns the number of recorded commands.
+    pub fn record(&self, device: &Device, command_buffer: &vk::CommandBuffer, image_index: usize, swapchain_image: vk::Image, scene_image: vk::Image) -> usize {
+        unsafe {
+            if self.passes.is_empty() {
+                return self.record_blit(device, command_buffer, swapchain_image, scene_image);
+            }
+
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            };
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: self.extent.width as f32,
+                height: self.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            let mut num_recorded_commands = 0;
+            for (pass_index, (effect, pipeline)) in self.passes.iter().zip(self.pipelines.iter()).enumerate() {
+                let (render_pass, framebuffer) = if pass_index + 1 == self.passes.len() {
+                    (self.final_render_pass, self.swapchain_framebuffers[image_index])
+                } else {
+                    (self.intermediate_render_pass, self.intermediate_targets[pass_index % 2].1)
+                };
+                // The first pass reads the scene and the others read what the pass before wrote
+                let descriptor_set = if pass_index == 0 { self.descriptor_sets[0] } else { self.descriptor_sets[1 + (pass_index - 1) % 2] };
+
+                let render_pass_info = vk::RenderPassBeginInfo {
+                    s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+                    render_pass,
+                    framebuffer,
+                    render_area,
+                    ..Default::default()
+                };
+                device.cmd_begin_render_pass(*command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+                device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, *pipeline);
+                device.cmd_set_viewport(*command_buffer, 0, &[viewport]);
+                device.cmd_set_scissor(*command_buffer, 0, &[render_area]);
+                device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, &[descriptor_set], &[]);
+                num_recorded_commands += 5;
+                if !effect.uniforms.is_empty() {
+                    device.cmd_push_constants(*command_buffer, self.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, &effect.uniforms);
+                    num_recorded_commands += 1;
+                }
+                device.cmd_draw(*command_buffer, 3, 1, 0, 0);
+                device.cmd_end_render_pass(*command_buffer);
+                num_recorded_commands += 2;
+            }
+            num_recorded_commands
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &VkAllocator) {
+        self.destroy_targets(device, allocator);
+        unsafe {
+            device.destroy_render_pass(self.intermediate_render_pass, allocator.get_allocation_callbacks());
+            device.destroy_render_pass(self.final_render_pass, allocator.get_allocation_callbacks());
+            device.destroy_descriptor_pool(self.descriptor_pool, allocator.get_allocation_callbacks());
+        }
+    }
+
+    // The blit converts the scene format to the swapchain format, including the sRGB encoding
+    unsafe fn record_blit(&self, device: &Device, command_buffer: &vk::CommandBuffer, swapchain_image: vk::Image, scene_image: vk::Image) -> usize {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer_barriers = [
+            vk::ImageMemoryBarrier {
+                s_type: StructureType::IMAGE_MEMORY_BARRIER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: scene_image,
+                subresource_range,
+                ..Default::default()
+            },
+            // The acquire semaphore is waited on at the color attachment output stage, so the blit has to wait for that stage too
+            vk::ImageMemoryBarrier {
+                s_type: StructureType::IMAGE_MEMORY_BARRIER,
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: swapchain_image,
+                subresource_range,
+                ..Default::default()
+            },
+        ];
+        device.cmd_pipeline_barrier(*command_buffer, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &to_transfer_barriers);
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let corners = [vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: self.extent.width as i32, y: self.extent.height as i32, z: 1 }];
+        let blit = vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: corners,
+            dst_subresource: subresource,
+            dst_offsets: corners,
+        };
+        // The images are the same size, so no filtering is needed
+        device.cmd_blit_image(*command_buffer, scene_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, swapchain_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[blit], vk::Filter::NEAREST);
+
+        let to_present_barrier = vk::ImageMemoryBarrier {
+            s_type: StructureType::IMAGE_MEMORY_BARRIER,
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::empty(),
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: swapchain_image,
+            subresource_range,
+            ..Default::default()
+        };
+        device.cmd_pipeline_barrier(*command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::DependencyFlags::empty(), &[], &[], &[to_present_barrier]);
+        3
+    }
+
+    fn create_intermediate_target(device: &Device, render_pass: vk::RenderPass, scene_format: vk::Format, extent: vk::Extent2D, allocator: &VkAllocator) -> Result<(AllocationInfo, vk::Framebuffer), Cow<'static, str>> {
+        let mut image = allocator.create_image(extent.width, extent.height, 1, 1, vk::ImageCreateFlags::empty(), vk::SampleCountFlags::TYPE_1, scene_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        if let Err(e) = allocator.create_image_view(&mut image, scene_format, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D) {
+            let mut error_str = e.to_string();
+            free_allocations_add_error_string!(allocator, [image], error_str);
+            return Err(Cow::from(error_str));
+        }
+        match Self::create_framebuffer(device, render_pass, image.get_image_view().unwrap(), extent, allocator) {
+            Ok(framebuffer) => Ok((image, framebuffer)),
+            Err(e) => {
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [image], error_str);
+                Err(Cow::from(error_str))
+            },
+        }
+    }
+
+    fn create_framebuffer(device: &Device, render_pass: vk::RenderPass, image_view: ImageView, extent: vk::Extent2D, allocator: &VkAllocator) -> Result<vk::Framebuffer, Cow<'static, str>> {
+        let framebuffer_create_info = vk::FramebufferCreateInfo {
+            s_type: StructureType::FRAMEBUFFER_CREATE_INFO,
+            render_pass,
+            attachment_count: 1,
+            p_attachments: &image_view,
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        unsafe {
+            device.create_framebuffer(&framebuffer_create_info, allocator.get_allocation_callbacks())
+        }.map_err(|err| Cow::Owned(format!("Failed to create the post processing framebuffer: {}", err)))
+    }
+
+    fn create_descriptor_sets(device: &Device, descriptor_set_layout: vk::DescriptorSetLayout, allocator: &VkAllocator) -> Result<(DescriptorPool, [DescriptorSet; 3]), Cow<'static, str>> {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 3,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            max_sets: 3,
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&pool_info, allocator.get_allocation_callbacks())
+        }.map_err(|err| Cow::Owned(format!("Failed to create the post processing descriptor pool: {}", err)))?;
+
+        let set_layouts = [descriptor_set_layout; 3];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            descriptor_pool,
+            descriptor_set_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            ..Default::default()
+        };
+        match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+            Ok(descriptor_sets) => Ok((descriptor_pool, [descriptor_sets[0], descriptor_sets[1], descriptor_sets[2]])),
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_pool(descriptor_pool, allocator.get_allocation_callbacks());
+                }
+                Err(Cow::Owned(format!("Failed to allocate the post processing descriptor sets: {}", err)))
+            },
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use ash::{vk::{self, StructureType}, Device};
+use nalgebra_glm as glm;
+
+use crate::{logging, free_allocations_add_error_string, object_manager::Counter, pipeline_manager::PipelineManager, vk_allocator::{AllocationInfo, VkAllocator}, vk_controller::{ObjectID, VkController}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RenderTargetId(pub usize);
+
+// Replaced when the render target is resized
+struct RenderTargetImages {
+    color: AllocationInfo,
+    depth: AllocationInfo,
+    framebuffer: vk::Framebuffer,
+}
+
+enum RenderTargetResource {
+    Images(Box<RenderTargetImages>),
+    RenderPass(vk::RenderPass),
+}
+
+/// An offscreen color image that some of the objects are drawn into before the main pass, so other objects can sample it.
+pub struct RenderTarget {
+    id: RenderTargetId,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    render_pass: vk::RenderPass,
+    images: RenderTargetImages,
+    sampler: vk::Sampler,
+    view_projection: glm::Mat4,
+    object_ids: Vec<ObjectID>,
+}
+
+impl RenderTarget {
+    pub fn get_id(&self) -> RenderTargetId {
+        self.id
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn get_format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn get_render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn get_framebuffer(&self) -> vk::Framebuffer {
+        self.images.framebuffer
+    }
+
+    pub fn get_view_projection(&self) -> glm::Mat4 {
+        self.view_projection
+    }
+
+    pub fn get_object_ids(&self) -> &[ObjectID] {
+        &self.object_ids
+    }
+}
+
+pub struct RenderTargetManager {
+    render_targets: Vec<RenderTarget>,
+    next_id: usize,
+    depth_format: vk::Format,
+    // The frames in flight might still draw into or sample the resources, so they are destroyed when those frames are done
+    resources_to_destroy: Vec<(Counter, RenderTargetResource)>,
+}
+
+impl RenderTargetManager {
+    pub fn new(depth_format: vk::Format) -> Self {
+        Self {
+            render_targets: Vec::new(),
+            next_id: 0,
+            depth_format,
+            resources_to_destroy: Vec::new(),
+        }
+    }
+
+    pub fn create_render_target(&mut self, device: &Device, extent: vk::Extent2D, format: vk::Format, sampler: vk::Sampler, allocator: &VkAllocator) -> Result<RenderTargetId, Cow<'static, str>> {
+        if self.render_targets.len() >= VkController::MAX_RENDER_TARGETS {
+            return Err(Cow::Owned(format!("There can be at most {} render targets", VkController::MAX_RENDER_TARGETS)));
+        }
+        if extent.width == 0 || extent.height == 0 {
+            return Err(Cow::Borrowed("A render target can not be empty"));
+        }
+
+        let render_pass = PipelineManager::create_render_target_render_pass(device, format, self.depth_format, allocator);
+        let images = match Self::create_images(device, render_pass, extent, format, self.depth_format, allocator) {
+            Ok(images) => images,
+            Err(e) => {
+                unsafe {
+                    device.destroy_render_pass(render_pass, allocator.get_allocation_callbacks());
+                }
+                return Err(e);
+            },
+        };
+
+        let id = RenderTargetId(self.next_id);
+        self.next_id += 1;
+        self.render_targets.push(RenderTarget {
+            id,
+            extent,
+            format,
+            render_pass,
+            images,
+            sampler,
+            view_projection: glm::identity(),
+            object_ids: Vec::new(),
+        });
+        Ok(id)
+    }
+
+    /// The old images are destroyed when the frames in flight are done with them.
+    pub fn resize_render_target(&mut self, device: &Device, id: RenderTargetId, extent: vk::Extent2D, allocator: &VkAllocator) -> Result<(), Cow<'static, str>> {
+        if extent.width == 0 || extent.height == 0 {
+            return Err(Cow::Borrowed("A render target can not be empty"));
+        }
+        let depth_format = self.depth_format;
+        let render_target = self.render_targets.iter_mut().find(|render_target| render_target.id == id).ok_or(Cow::Owned(format!("The render target {:?} does not exist", id)))?;
+        if render_target.extent == extent {
+            return Ok(());
+        }
+
+        let images = Self::create_images(device, render_target.render_pass, extent, render_target.format, depth_format, allocator)?;
+        let old_images = std::mem::replace(&mut render_target.images, images);
+        render_target.extent = extent;
+        self.resources_to_destroy.push((Counter(0), RenderTargetResource::Images(Box::new(old_images))));
+        Ok(())
+    }
+
+    /// The images are destroyed when the frames in flight are done with them.
+    pub fn destroy_render_target(&mut self, id: RenderTargetId) -> Result<(), Cow<'static, str>> {
+        let index = self.render_targets.iter().position(|render_target| render_target.id == id).ok_or(Cow::Owned(format!("The render target {:?} does not exist", id)))?;
+        let render_target = self.render_targets.remove(index);
+        self.resources_to_destroy.push((Counter(0), RenderTargetResource::Images(Box::new(render_target.images))));
+        self.resources_to_destroy.push((Counter(0), RenderTargetResource::RenderPass(render_target.render_pass)));
+        Ok(())
+    }
+
+    pub fn set_view_projection(&mut self, id: RenderTargetId, view_projection: glm::Mat4) -> Result<(), Cow<'static, str>> {
+        self.get_render_target_mut(id)?.view_projection = view_projection;
+        Ok(())
+    }
+
+    pub fn set_object_ids(&mut self, id: RenderTargetId, object_ids: Vec<ObjectID>) -> Result<(), Cow<'static, str>> {
+        self.get_render_target_mut(id)?.object_ids = object_ids;
+        Ok(())
+    }
+
+    /// In the order they are drawn, which is also the order of their per-frame data after the views.
+    pub fn get_render_targets(&self) -> &[RenderTarget] {
+        &self.render_targets
+    }
+
+    /// The image view and sampler objects bind in place of a texture.
+    pub fn get_textures(&self) -> HashMap<RenderTargetId, (vk::ImageView, vk::Sampler)> {
+        self.render_targets.iter().map(|render_target| (render_target.id, (render_target.images.color.get_image_view().unwrap(), render_target.sampler))).collect()
+    }
+
+    /// Has to be called once per frame, after the fence of the frame has been waited on.
+    pub fn destroy_unused_resources(&mut self, device: &Device, frames_in_flight: usize, allocator: &VkAllocator) {
+        self.resources_to_destroy.iter_mut().for_each(|(counter, _)| counter.increment());
+        let (expired, pending): (Vec<_>, Vec<_>) = self.resources_to_destroy.drain(..).partition(|(counter, _)| counter.0 >= frames_in_flight);
+        self.resources_to_destroy = pending;
+        for (_, resource) in expired {
+            Self::destroy_resource(device, resource, allocator);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &VkAllocator) {
+        let render_targets = std::mem::take(&mut self.render_targets);
+        for render_target in render_targets {
+            Self::destroy_resource(device, RenderTargetResource::Images(Box::new(render_target.images)), allocator);
+            Self::destroy_resource(device, RenderTargetResource::RenderPass(render_target.render_pass), allocator);
+        }
+        for (_, resource) in std::mem::take(&mut self.resources_to_destroy) {
+            Self::destroy_resource(device, resource, allocator);
+        }
+    }
+
+    fn get_render_target_mut(&mut self, id: RenderTargetId) -> Result<&mut RenderTarget, Cow<'static, str>> {
+        self.render_targets.iter_mut().find(|render_target| render_target.id == id).ok_or(Cow::Owned(format!("The render target {:?} does not exist", id)))
+    }
+
+    fn create_images(device: &Device, render_pass: vk::RenderPass, extent: vk::Extent2D, format: vk::Format, depth_format: vk::Format, allocator: &VkAllocator) -> Result<RenderTargetImages, Cow<'static, str>> {
+        let mut color = allocator.create_image(extent.width, extent.height, 1, 1, vk::ImageCreateFlags::empty(), vk::SampleCountFlags::TYPE_1, format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        if let Err(e) = allocator.create_image_view(&mut color, format, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D) {
+            let mut error_str = e.to_string();
+            free_allocations_add_error_string!(allocator, [color], error_str);
+            return Err(Cow::from(error_str));
+        }
+
+        let mut depth = match allocator.create_image(extent.width, extent.height, 1, 1, vk::ImageCreateFlags::empty(), vk::SampleCountFlags::TYPE_1, depth_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, vk::MemoryPropertyFlags::DEVICE_LOCAL) {
+            Ok(depth) => depth,
+            Err(e) => {
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [color], error_str);
+                return Err(Cow::from(error_str));
+            },
+        };
+        if let Err(e) = allocator.create_image_view(&mut depth, depth_format, vk::ImageAspectFlags::DEPTH, 1, vk::ImageViewType::TYPE_2D) {
+            let mut error_str = e.to_string();
+            free_allocations_add_error_string!(allocator, [color, depth], error_str);
+            return Err(Cow::from(error_str));
+        }
+
+        let attachments = [color.get_image_view().unwrap(), depth.get_image_view().unwrap()];
+        let framebuffer_create_info = vk::FramebufferCreateInfo {
+            s_type: StructureType::FRAMEBUFFER_CREATE_INFO,
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = match unsafe { device.create_framebuffer(&framebuffer_create_info, allocator.get_allocation_callbacks()) } {
+            Ok(framebuffer) => framebuffer,
+            Err(err) => {
+                let mut error_str = format!("Failed to create the render target framebuffer: {}", err);
+                free_allocations_add_error_string!(allocator, [color, depth], error_str);
+                return Err(Cow::from(error_str));
+            },
+        };
+
+        Ok(RenderTargetImages { color, depth, framebuffer })
+    }
+
+    fn destroy_resource(device: &Device, resource: RenderTargetResource, allocator: &VkAllocator) {
+        match resource {
+            RenderTargetResource::Images(images) => {
+                unsafe {
+                    device.destroy_framebuffer(images.framebuffer, allocator.get_allocation_callbacks());
+                }
+                let mut error_str = String::new();
+                free_allocations_add_error_string!(allocator, [images.color, images.depth], error_str);
+                if !error_str.is_empty() {
+                    log::error!(target: logging::RENDERER, "Failed to free the render target images: {}", error_str);
+                }
+            },
+            RenderTargetResource::RenderPass(render_pass) => unsafe {
+                device.destroy_render_pass(render_pass, allocator.get_allocation_callbacks());
+            },
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Frame captures triggered from code through the RenderDoc in-application API, for catching a bad frame at the moment it happens.
+//! The API is only found when the application was started from RenderDoc and the `renderdoc` feature is enabled, otherwise everything here does nothing.
+
+#[cfg(feature = "renderdoc")]
+use std::ffi::c_void;
+
+use crate::logging;
+
+pub struct RenderDocCapture {
+    #[cfg(feature = "renderdoc")]
+    api: Option<api::RenderDocApi>,
+    num_pending_frames: u32,
+    is_capturing: bool,
+}
+
+impl RenderDocCapture {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "renderdoc")]
+            api: api::RenderDocApi::load(),
+            num_pending_frames: 0,
+            is_capturing: false,
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    pub fn is_available(&self) -> bool {
+        self.api.is_some()
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    pub fn is_available(&self) -> bool {
+        false
+    }
+
+    /// Captures each of the next `num_frames` frames on its own. Returns false when RenderDoc isn't available.
+    pub fn trigger(&mut self, num_frames: u32) -> bool {
+        if !self.is_available() {
+            return false;
+        }
+        self.num_pending_frames = self.num_pending_frames.saturating_add(num_frames);
+        true
+    }
+
+    pub fn start_frame(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_ref().filter(|_| self.num_pending_frames > 0) {
+            // Null for the device and window captures whichever one RenderDoc sees first, which is fine with a single device
+            unsafe {
+                (api.functions().start_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut());
+            }
+            self.is_capturing = true;
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        if !self.is_capturing {
+            return;
+        }
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_ref() {
+            let is_captured = unsafe {
+                (api.functions().end_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut())
+            } == 1;
+            if !is_captured {
+                log::warn!(target: logging::RENDERER, "RenderDoc failed to capture the frame");
+            }
+        }
+        self.is_capturing = false;
+        self.num_pending_frames -= 1;
+        if self.num_pending_frames == 0 {
+            log::info!(target: logging::RENDERER, "Finished the RenderDoc captures");
+        }
+    }
+}
+
+impl Default for RenderDocCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "renderdoc")]
+mod api {
+    use super::*;
+
+    // eRENDERDOC_API_Version_1_1_2, the first version with everything used here
+    const API_VERSION_1_1_2: i32 = 10102;
+
+    type GetApiFn = unsafe extern "C" fn(version: i32, out_api_pointers: *mut *mut c_void) -> i32;
+
+    // RENDERDOC_API_1_1_2, only the functions that are used have their types, but the others have to be there so the offsets match
+    #[repr(C)]
+    pub struct RenderDocFunctions {
+        _unused_functions: [*const c_void; 19],
+        pub start_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void),
+        _is_frame_capturing: *const c_void,
+        pub end_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32,
+    }
+
+    pub struct RenderDocApi {
+        // The functions point into the library, so it is kept loaded for as long as they are used
+        _library: libloading::Library,
+        functions: *const RenderDocFunctions,
+    }
+
+    impl RenderDocApi {
+        pub fn load() -> Option<Self> {
+            let library = unsafe { open_injected_library() }?;
+            let mut functions = std::ptr::null_mut();
+            let result = unsafe {
+                let get_api = library.get::<GetApiFn>(b"RENDERDOC_GetAPI\0").ok()?;
+                get_api(API_VERSION_1_1_2, &mut functions)
+            };
+            if result != 1 || functions.is_null() {
+                log::warn!(target: logging::RENDERER, "RenderDoc is loaded, but its API version 1.1.2 is not available");
+                return None;
+            }
+            log::info!(target: logging::RENDERER, "RenderDoc is available for captures");
+            Some(Self {
+                _library: library,
+                functions: functions as *const RenderDocFunctions,
+            })
+        }
+
+        pub fn functions(&self) -> &RenderDocFunctions {
+            unsafe { &*self.functions }
+        }
+    }
+
+    // Only finds the library when RenderDoc has injected it, the engine never loads it by itself
+    #[cfg(target_os = "linux")]
+    unsafe fn open_injected_library() -> Option<libloading::Library> {
+        const RTLD_NOLOAD: std::os::raw::c_int = 0x4;
+        libloading::os::unix::Library::open(Some("librenderdoc.so"), libloading::os::unix::RTLD_NOW | RTLD_NOLOAD).ok().map(Into::into)
+    }
+
+    #[cfg(windows)]
+    unsafe fn open_injected_library() -> Option<libloading::Library> {
+        libloading::os::windows::Library::open_already_loaded("renderdoc.dll").ok().map(Into::into)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    unsafe fn open_injected_library() -> Option<libloading::Library> {
+        None
+    }
+}
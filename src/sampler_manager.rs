@@ -2,7 +2,7 @@ use std::borrow::Cow;
 
 use ash::{vk::{self, Sampler}, Device, Instance};
 
-use crate::vk_allocator::VkAllocator;
+use crate::{logging, vk_allocator::VkAllocator, vk_controller::SamplerStats};
 
 pub struct SamplerConfig {
     pub s_type: vk::StructureType,
@@ -12,7 +12,8 @@ pub struct SamplerConfig {
     pub address_mode_v: vk::SamplerAddressMode,
     pub address_mode_w: vk::SamplerAddressMode,
     pub anisotropy_enable: vk::Bool32,
-    // pub max_anisotropy: f32,
+    // None uses the engine wide default. The level is clamped to what the physical device supports
+    pub max_anisotropy: Option<f32>,
     pub border_color: vk::BorderColor,
     pub unnormalized_coordinates: vk::Bool32,
     pub compare_enable: vk::Bool32,
@@ -23,27 +24,62 @@ pub struct SamplerConfig {
     pub max_lod: f32,
 }
 
+struct ManagedSampler {
+    config: SamplerConfig,
+    sampler: Sampler,
+    references: usize,
+    // How many frames the sampler has had no references, it's destroyed once the frames in flight can't use it anymore
+    num_idle_frames: usize,
+}
+
+/// Shares one sampler between everything that asks for the same config. Every `get_or_create_sampler` takes a reference, and samplers without references are destroyed by [`SamplerManager::destroy_idle_samplers`].
 pub struct SamplerManager {
-    samplers: Vec<(SamplerConfig, Sampler)>
+    samplers: Vec<ManagedSampler>,
+    default_max_anisotropy: f32,
+    is_mip_lod_bias_supported: bool,
+    num_created: usize,
+    num_reuse_hits: usize,
 }
 
 impl SamplerManager {
-    pub fn new() -> Self {
+    pub fn new(is_mip_lod_bias_supported: bool) -> Self {
         Self {
-            samplers: Vec::new()
+            samplers: Vec::new(),
+            // Clamped to the highest level the physical device supports
+            default_max_anisotropy: f32::MAX,
+            is_mip_lod_bias_supported,
+            num_created: 0,
+            num_reuse_hits: 0,
         }
     }
 
-    pub fn get_or_create_sampler(&mut self, device: &Device, instance: &Instance, physical_device: &vk::PhysicalDevice, sampler_config: SamplerConfig, allocator: &mut VkAllocator) -> Result<Sampler, Cow<'static, str>> {
-        for (config, sampler) in &self.samplers {
-            if config == &sampler_config {
-                return Ok(*sampler);
-            }
-        }
+    pub fn set_default_max_anisotropy(&mut self, max_anisotropy: f32) {
+        self.default_max_anisotropy = max_anisotropy;
+    }
 
-        let max_anisotropy = unsafe {
-            instance.get_physical_device_properties(*physical_device).limits.max_sampler_anisotropy
+    pub fn get_or_create_sampler(&mut self, device: &Device, instance: &Instance, physical_device: &vk::PhysicalDevice, mut sampler_config: SamplerConfig, allocator: &VkAllocator) -> Result<Sampler, Cow<'static, str>> {
+        let limits = unsafe {
+            instance.get_physical_device_properties(*physical_device).limits
         };
+        // Anisotropic filtering is only enabled on the device when it's supported
+        if unsafe { instance.get_physical_device_features(*physical_device) }.sampler_anisotropy != vk::TRUE {
+            sampler_config.anisotropy_enable = vk::FALSE;
+        }
+        // Portability subset devices might not support a LOD bias, the sampler then uses no bias
+        if !self.is_mip_lod_bias_supported {
+            sampler_config.mip_lod_bias = 0.0;
+        }
+        // The level is resolved before looking for an existing sampler, so changing the default does not reuse samplers with the old level
+        let max_anisotropy = Self::clamp_max_anisotropy(sampler_config.max_anisotropy.unwrap_or(self.default_max_anisotropy), &limits);
+        sampler_config.max_anisotropy = Some(max_anisotropy);
+
+        // An idle sampler is taken back instead of being destroyed
+        if let Some(managed_sampler) = self.samplers.iter_mut().find(|managed_sampler| managed_sampler.config == sampler_config) {
+            managed_sampler.references += 1;
+            managed_sampler.num_idle_frames = 0;
+            self.num_reuse_hits += 1;
+            return Ok(managed_sampler.sampler);
+        }
 
         let sampler_create_info = vk::SamplerCreateInfo {
             s_type: sampler_config.s_type,
@@ -66,17 +102,57 @@ impl SamplerManager {
         };
 
         let sampler = unsafe {
-            device.create_sampler(&sampler_create_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_sampler(&sampler_create_info, allocator.get_allocation_callbacks())
         }.map_err(|err| Cow::Owned(format!("Failed to create sampler: {}", err)))?;
 
-        self.samplers.push((sampler_config, sampler));
+        self.samplers.push(ManagedSampler { config: sampler_config, sampler, references: 1, num_idle_frames: 0 });
+        self.num_created += 1;
         Ok(sampler)
     }
 
-    pub fn destroy_samplers(&mut self, device: &Device, allocator: &mut VkAllocator) {
-        for (_, sampler) in self.samplers.drain(..) {
+    /// Drops a reference taken by [`SamplerManager::get_or_create_sampler`]. The sampler is kept until it has been idle for the frames in flight.
+    pub fn release_sampler(&mut self, sampler: Sampler) {
+        match self.samplers.iter_mut().find(|managed_sampler| managed_sampler.sampler == sampler) {
+            Some(managed_sampler) if managed_sampler.references > 0 => managed_sampler.references -= 1,
+            _ => log::warn!(target: logging::RENDERER, "Sampler {:?} was released more times than it was used, or it's not from the sampler manager", sampler),
+        }
+    }
+
+    /// Has to be called once per frame. Destroys the samplers that have had no references for `frames_in_flight` frames, so no frame in flight can still use them.
+    pub fn destroy_idle_samplers(&mut self, device: &Device, frames_in_flight: usize, allocator: &VkAllocator) {
+        for sampler in self.take_idle_samplers(frames_in_flight) {
+            unsafe {
+                device.destroy_sampler(sampler, allocator.get_allocation_callbacks());
+            }
+        }
+    }
+
+    // Counts one more idle frame for the samplers without references, and forgets the ones that have been idle long enough
+    fn take_idle_samplers(&mut self, frames_in_flight: usize) -> Vec<Sampler> {
+        self.samplers.iter_mut().filter(|managed_sampler| managed_sampler.references == 0).for_each(|managed_sampler| managed_sampler.num_idle_frames += 1);
+        self.samplers.extract_if(.., |managed_sampler| managed_sampler.references == 0 && managed_sampler.num_idle_frames >= frames_in_flight).map(|managed_sampler| managed_sampler.sampler).collect()
+    }
+
+    pub fn get_stats(&self) -> SamplerStats {
+        SamplerStats {
+            live_samplers: self.samplers.len(),
+            total_created: self.num_created,
+            reuse_hits: self.num_reuse_hits,
+        }
+    }
+
+    // f32::clamp keeps NaN, so the values that aren't finite are turned into no anisotropy first
+    fn clamp_max_anisotropy(max_anisotropy: f32, limits: &vk::PhysicalDeviceLimits) -> f32 {
+        if !max_anisotropy.is_finite() {
+            return 1.0;
+        }
+        max_anisotropy.clamp(1.0, limits.max_sampler_anisotropy.max(1.0))
+    }
+
+    pub fn destroy_samplers(&mut self, device: &Device, allocator: &VkAllocator) {
+        for managed_sampler in self.samplers.drain(..) {
             unsafe {
-                device.destroy_sampler(sampler, Some(&allocator.get_allocation_callbacks()));
+                device.destroy_sampler(managed_sampler.sampler, allocator.get_allocation_callbacks());
             }
         }
     }
@@ -93,7 +169,7 @@ impl PartialEq for SamplerConfig {
         self.address_mode_v == other.address_mode_v &&
         self.address_mode_w == other.address_mode_w &&
         self.anisotropy_enable == other.anisotropy_enable &&
-        // self.max_anisotropy == other.max_anisotropy &&
+        self.max_anisotropy == other.max_anisotropy &&
         self.border_color == other.border_color &&
         self.unnormalized_coordinates == other.unnormalized_coordinates &&
         self.compare_enable == other.compare_enable &&
@@ -103,4 +179,104 @@ impl PartialEq for SamplerConfig {
         self.min_lod == other.min_lod &&
         self.max_lod == other.max_lod
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    fn get_sampler_config(max_lod: f32) -> SamplerConfig {
+        SamplerConfig {
+            s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            anisotropy_enable: vk::FALSE,
+            max_anisotropy: Some(1.0),
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod,
+        }
+    }
+
+    // Like get_or_create_sampler for a config that has no sampler yet, without a device
+    fn insert_sampler(sampler_manager: &mut SamplerManager, raw_sampler: u64) -> Sampler {
+        let sampler = Sampler::from_raw(raw_sampler);
+        sampler_manager.samplers.push(ManagedSampler { config: get_sampler_config(raw_sampler as f32), sampler, references: 1, num_idle_frames: 0 });
+        sampler_manager.num_created += 1;
+        sampler
+    }
+
+    #[test]
+    fn released_samplers_are_destroyed_after_the_frames_in_flight() {
+        let frames_in_flight = 2;
+        let mut sampler_manager = SamplerManager::new(true);
+        let kept = insert_sampler(&mut sampler_manager, 1);
+        let baseline = sampler_manager.get_stats().live_samplers;
+
+        for round in 0..10 {
+            let sampler = insert_sampler(&mut sampler_manager, 100 + round);
+            assert!(sampler_manager.take_idle_samplers(frames_in_flight).is_empty());
+            sampler_manager.release_sampler(sampler);
+            // The frames in flight can still use it
+            assert!(sampler_manager.take_idle_samplers(frames_in_flight).is_empty());
+            assert_eq!(sampler_manager.take_idle_samplers(frames_in_flight), vec![sampler]);
+            assert_eq!(sampler_manager.get_stats().live_samplers, baseline);
+        }
+        assert_eq!(sampler_manager.get_stats().total_created, 11);
+        assert!(sampler_manager.samplers.iter().any(|managed_sampler| managed_sampler.sampler == kept));
+    }
+
+    #[test]
+    fn sampler_is_kept_while_it_has_references() {
+        let mut sampler_manager = SamplerManager::new(true);
+        let sampler = insert_sampler(&mut sampler_manager, 1);
+        sampler_manager.samplers[0].references += 1;
+
+        sampler_manager.release_sampler(sampler);
+        for _ in 0..4 {
+            assert!(sampler_manager.take_idle_samplers(1).is_empty());
+        }
+        sampler_manager.release_sampler(sampler);
+        assert_eq!(sampler_manager.take_idle_samplers(1), vec![sampler]);
+    }
+
+    fn get_limits(max_sampler_anisotropy: f32) -> vk::PhysicalDeviceLimits {
+        vk::PhysicalDeviceLimits {
+            max_sampler_anisotropy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn max_anisotropy_is_clamped_to_the_device_limit() {
+        let limits = get_limits(16.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(8.0, &limits), 8.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(32.0, &limits), 16.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(0.5, &limits), 1.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(-4.0, &limits), 1.0);
+    }
+
+    #[test]
+    fn max_anisotropy_that_is_not_finite_is_one() {
+        let limits = get_limits(16.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(f32::NAN, &limits), 1.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(f32::INFINITY, &limits), 1.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(f32::NEG_INFINITY, &limits), 1.0);
+    }
+
+    #[test]
+    fn max_anisotropy_is_one_without_a_device_limit() {
+        assert_eq!(SamplerManager::clamp_max_anisotropy(8.0, &get_limits(0.0)), 1.0);
+        assert_eq!(SamplerManager::clamp_max_anisotropy(8.0, &get_limits(f32::NAN)), 1.0);
+    }
+}
@@ -0,0 +1,279 @@
+//! Reads the descriptor bindings and the sizes of the buffer blocks from compiled SPIR-V, so the objects' bindings and buffers can be checked against what the shaders expect.
+//! Only the instructions needed for the resource types and the explicit layouts of `uniform` and `buffer` blocks are read, everything else is skipped.
+
+use std::collections::{HashMap, HashSet};
+
+use ash::vk;
+
+const MAGIC_NUMBER: u32 = 0x07230203;
+const HEADER_WORD_COUNT: usize = 5;
+
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DIM_SUBPASS_DATA: u32 = 6;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+/// The size of a buffer block in a shader. A block that ends with an array without a length, like `mat4 model[];`, has the size of the members before it and the stride of one element of the array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferBindingSize {
+    pub size: u64,
+    pub runtime_array_stride: Option<u64>,
+}
+
+/// What kind of resource a binding is in the shader. Whether a buffer is bound with a dynamic offset isn't in the shader, so both descriptor types match it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorKind {
+    UniformBuffer,
+    StorageBuffer,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    Sampler,
+}
+
+impl DescriptorKind {
+    pub fn is_compatible(&self, descriptor_type: vk::DescriptorType) -> bool {
+        match self {
+            DescriptorKind::UniformBuffer => descriptor_type == vk::DescriptorType::UNIFORM_BUFFER || descriptor_type == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            DescriptorKind::StorageBuffer => descriptor_type == vk::DescriptorType::STORAGE_BUFFER || descriptor_type == vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+            DescriptorKind::CombinedImageSampler => descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            DescriptorKind::SampledImage => descriptor_type == vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorKind::StorageImage => descriptor_type == vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorKind::Sampler => descriptor_type == vk::DescriptorType::SAMPLER,
+        }
+    }
+}
+
+/// A binding the shader declares. The count is None for an array without a length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderBinding {
+    pub kind: DescriptorKind,
+    pub count: Option<u32>,
+}
+
+#[derive(Clone)]
+enum SpirvType {
+    Scalar(u64),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    // Whether it is sampled or a storage image
+    Image(u32),
+    Sampler,
+    SampledImage,
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32),
+}
+
+#[derive(Default)]
+struct Module {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    // Variable id to its pointer type and storage class
+    variables: Vec<(u32, u32, u32)>,
+    bindings: HashMap<u32, u32>,
+    descriptor_sets: HashMap<u32, u32>,
+    buffer_block_types: HashSet<u32>,
+    array_strides: HashMap<u32, u64>,
+    member_offsets: HashMap<(u32, u32), u64>,
+    member_matrix_strides: HashMap<(u32, u32), u64>,
+}
+
+/// The sizes of the uniform and storage buffer blocks in the given descriptor set by binding. Returns None when the code isn't valid SPIR-V.
+pub fn get_buffer_binding_sizes(code: &[u32], descriptor_set: u32) -> Option<HashMap<u32, BufferBindingSize>> {
+    let module = Module::parse(code)?;
+    let mut sizes = HashMap::new();
+    for (binding, block_type, storage_class) in module.get_resource_variables(descriptor_set) {
+        if storage_class != STORAGE_CLASS_UNIFORM && storage_class != STORAGE_CLASS_STORAGE_BUFFER {
+            continue;
+        }
+        // An array of blocks is several descriptors, which the engine doesn't make for buffers
+        if let Some(size) = module.get_block_size(block_type) {
+            sizes.insert(binding, size);
+        }
+    }
+    Some(sizes)
+}
+
+/// The resources the shader declares in the given descriptor set by binding. Returns None when the code isn't valid SPIR-V.
+pub fn get_descriptor_bindings(code: &[u32], descriptor_set: u32) -> Option<HashMap<u32, ShaderBinding>> {
+    let module = Module::parse(code)?;
+    let mut bindings = HashMap::new();
+    for (binding, resource_type, storage_class) in module.get_resource_variables(descriptor_set) {
+        let (element_type, count) = match module.types.get(&resource_type) {
+            Some(SpirvType::Array(element_type, length)) => (*element_type, Some(*module.constants.get(length)?)),
+            Some(SpirvType::RuntimeArray(element_type)) => (*element_type, None),
+            _ => (resource_type, Some(1)),
+        };
+        let kind = match module.types.get(&element_type) {
+            // Older SPIR-V has storage buffers in the uniform storage class with the block decorated as a buffer block
+            Some(SpirvType::Struct(_)) if storage_class == STORAGE_CLASS_STORAGE_BUFFER || module.buffer_block_types.contains(&element_type) => DescriptorKind::StorageBuffer,
+            Some(SpirvType::Struct(_)) => DescriptorKind::UniformBuffer,
+            Some(SpirvType::SampledImage) => DescriptorKind::CombinedImageSampler,
+            Some(SpirvType::Image(2)) => DescriptorKind::StorageImage,
+            Some(SpirvType::Image(_)) => DescriptorKind::SampledImage,
+            Some(SpirvType::Sampler) => DescriptorKind::Sampler,
+            _ => continue,
+        };
+        bindings.insert(binding, ShaderBinding { kind, count });
+    }
+    Some(bindings)
+}
+
+impl Module {
+    // The binding, the type the variable points to and the storage class of the resources in the descriptor set
+    fn get_resource_variables(&self, descriptor_set: u32) -> Vec<(u32, u32, u32)> {
+        self.variables.iter().filter_map(|(variable, pointer_type, storage_class)| {
+            if ![STORAGE_CLASS_UNIFORM_CONSTANT, STORAGE_CLASS_UNIFORM, STORAGE_CLASS_STORAGE_BUFFER].contains(storage_class) {
+                return None;
+            }
+            if self.descriptor_sets.get(variable).copied().unwrap_or(0) != descriptor_set {
+                return None;
+            }
+            match (self.bindings.get(variable), self.types.get(pointer_type)) {
+                (Some(binding), Some(SpirvType::Pointer(resource_type))) => Some((*binding, *resource_type, *storage_class)),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    fn parse(code: &[u32]) -> Option<Self> {
+        if code.len() < HEADER_WORD_COUNT || code[0] != MAGIC_NUMBER {
+            return None;
+        }
+
+        let mut module = Module::default();
+        let mut i = HEADER_WORD_COUNT;
+        while i < code.len() {
+            let word_count = (code[i] >> 16) as usize;
+            let opcode = code[i] & 0xffff;
+            if word_count == 0 || i + word_count > code.len() {
+                return None;
+            }
+            let operands = &code[i + 1..i + word_count];
+            i += word_count;
+
+            match opcode {
+                OP_TYPE_INT | OP_TYPE_FLOAT if operands.len() >= 2 => {
+                    module.types.insert(operands[0], SpirvType::Scalar(operands[1] as u64 / 8));
+                },
+                OP_TYPE_VECTOR if operands.len() >= 3 => {
+                    module.types.insert(operands[0], SpirvType::Vector(operands[1], operands[2]));
+                },
+                OP_TYPE_MATRIX if operands.len() >= 3 => {
+                    module.types.insert(operands[0], SpirvType::Matrix(operands[1], operands[2]));
+                },
+                // The operand 6 is 1 for a sampled image and 2 for a storage image. Input attachments are skipped, the engine doesn't bind them in the object set
+                OP_TYPE_IMAGE if operands.len() >= 7 && operands[2] != DIM_SUBPASS_DATA => {
+                    module.types.insert(operands[0], SpirvType::Image(operands[6]));
+                },
+                OP_TYPE_SAMPLER if !operands.is_empty() => {
+                    module.types.insert(operands[0], SpirvType::Sampler);
+                },
+                OP_TYPE_SAMPLED_IMAGE if !operands.is_empty() => {
+                    module.types.insert(operands[0], SpirvType::SampledImage);
+                },
+                OP_TYPE_ARRAY if operands.len() >= 3 => {
+                    module.types.insert(operands[0], SpirvType::Array(operands[1], operands[2]));
+                },
+                OP_TYPE_RUNTIME_ARRAY if operands.len() >= 2 => {
+                    module.types.insert(operands[0], SpirvType::RuntimeArray(operands[1]));
+                },
+                OP_TYPE_STRUCT if !operands.is_empty() => {
+                    module.types.insert(operands[0], SpirvType::Struct(operands[1..].to_vec()));
+                },
+                OP_TYPE_POINTER if operands.len() >= 3 => {
+                    module.types.insert(operands[0], SpirvType::Pointer(operands[2]));
+                },
+                // Only 32 bit constants are used for array lengths
+                OP_CONSTANT if operands.len() >= 3 => {
+                    module.constants.insert(operands[1], operands[2]);
+                },
+                OP_VARIABLE if operands.len() >= 3 => {
+                    module.variables.push((operands[1], operands[0], operands[2]));
+                },
+                OP_DECORATE if operands.len() == 2 && operands[1] == DECORATION_BUFFER_BLOCK => {
+                    module.buffer_block_types.insert(operands[0]);
+                },
+                OP_DECORATE if operands.len() >= 3 => match operands[1] {
+                    DECORATION_BINDING => { module.bindings.insert(operands[0], operands[2]); },
+                    DECORATION_DESCRIPTOR_SET => { module.descriptor_sets.insert(operands[0], operands[2]); },
+                    DECORATION_ARRAY_STRIDE => { module.array_strides.insert(operands[0], operands[2] as u64); },
+                    _ => (),
+                },
+                OP_MEMBER_DECORATE if operands.len() >= 4 => match operands[2] {
+                    DECORATION_OFFSET => { module.member_offsets.insert((operands[0], operands[1]), operands[3] as u64); },
+                    DECORATION_MATRIX_STRIDE => { module.member_matrix_strides.insert((operands[0], operands[1]), operands[3] as u64); },
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+        Some(module)
+    }
+
+    fn get_block_size(&self, block_type: u32) -> Option<BufferBindingSize> {
+        let Some(SpirvType::Struct(members)) = self.types.get(&block_type) else {
+            return None;
+        };
+        let mut size = 0;
+        let mut runtime_array_stride = None;
+        for (member_index, member_type) in members.iter().enumerate() {
+            let offset = *self.member_offsets.get(&(block_type, member_index as u32))?;
+            if let Some(SpirvType::RuntimeArray(_)) = self.types.get(member_type) {
+                // The runtime array has to be the last member, so the block ends where it starts
+                size = size.max(offset);
+                runtime_array_stride = Some(*self.array_strides.get(member_type)?);
+                continue;
+            }
+            let matrix_stride = self.member_matrix_strides.get(&(block_type, member_index as u32)).copied();
+            size = size.max(offset + self.get_type_size(*member_type, matrix_stride)?);
+        }
+        Some(BufferBindingSize { size, runtime_array_stride })
+    }
+
+    // The matrix stride is the one of the member that has the type, or of the member whose array has the type
+    fn get_type_size(&self, type_id: u32, matrix_stride: Option<u64>) -> Option<u64> {
+        match self.types.get(&type_id)? {
+            SpirvType::Scalar(size) => Some(*size),
+            SpirvType::Vector(component_type, count) => Some(self.get_type_size(*component_type, None)? * *count as u64),
+            SpirvType::Matrix(column_type, count) => Some(matrix_stride.unwrap_or(16).max(self.get_type_size(*column_type, None)?) * *count as u64),
+            SpirvType::Array(_, length) => Some(*self.array_strides.get(&type_id)? * *self.constants.get(length)? as u64),
+            SpirvType::Struct(members) => {
+                let mut size = 0;
+                for (member_index, member_type) in members.iter().enumerate() {
+                    let offset = *self.member_offsets.get(&(type_id, member_index as u32))?;
+                    let matrix_stride = self.member_matrix_strides.get(&(type_id, member_index as u32)).copied();
+                    size = size.max(offset + self.get_type_size(*member_type, matrix_stride)?);
+                }
+                Some(size)
+            },
+            SpirvType::Image(_) | SpirvType::Sampler | SpirvType::SampledImage | SpirvType::RuntimeArray(_) | SpirvType::Pointer(_) => None,
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{hash::{DefaultHasher, Hash, Hasher}, sync::{Arc, RwLock}};
+
+use ash::vk;
+use image::DynamicImage;
+use memoffset::offset_of;
+use nalgebra_glm as glm;
+
+use crate::{builtin_shaders::BuiltinShader, graphics_objects::{CubeMapResource, GraphicsObject, ResourceID}, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectTypeGraphicsResource, ShaderInfo, Vertex}, vk_allocator::Serializable, vk_controller::VerticesIndicesHash};
+
+/// A corner of the skybox cube, which is also the direction the cube map is sampled in.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SkyboxVertex {
+    pub position: glm::Vec3,
+}
+
+impl Vertex for SkyboxVertex {
+    fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 0,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: offset_of!(Self, position) as u32,
+        }]
+    }
+}
+
+impl Hash for SkyboxVertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.position.iter().for_each(|&i| i.to_bits().hash(state));
+    }
+}
+
+impl Serializable for SkyboxVertex {
+    fn to_u8(&self) -> Vec<u8> {
+        self.position.iter().flat_map(|x| x.to_ne_bytes()).collect()
+    }
+}
+
+/// A cube map drawn behind everything else. The shader reads `view` and `proj` from the per-frame data and removes the translation of the view, so the sky follows the camera.
+/// It is drawn at the far end of the depth range, so it can be added in any order with the other objects.
+pub struct Skybox {
+    pub cube_map: Arc<RwLock<CubeMapResource>>,
+}
+
+impl Skybox {
+    // Corner i has the positive x, y and z coordinate when bit 0, 1 and 2 of i is set
+    const CORNERS: [SkyboxVertex; 8] = [
+        SkyboxVertex { position: glm::Vec3::new(-1.0, -1.0, -1.0) },
+        SkyboxVertex { position: glm::Vec3::new(1.0, -1.0, -1.0) },
+        SkyboxVertex { position: glm::Vec3::new(-1.0, 1.0, -1.0) },
+        SkyboxVertex { position: glm::Vec3::new(1.0, 1.0, -1.0) },
+        SkyboxVertex { position: glm::Vec3::new(-1.0, -1.0, 1.0) },
+        SkyboxVertex { position: glm::Vec3::new(1.0, -1.0, 1.0) },
+        SkyboxVertex { position: glm::Vec3::new(-1.0, 1.0, 1.0) },
+        SkyboxVertex { position: glm::Vec3::new(1.0, 1.0, 1.0) },
+    ];
+    // The faces are wound to face the inside of the cube, since that is where the camera is
+    const INDICES: [u32; 36] = [
+        1, 5, 7, 7, 3, 1,
+        4, 0, 2, 2, 6, 4,
+        7, 6, 2, 2, 3, 7,
+        1, 0, 4, 4, 5, 1,
+        5, 4, 6, 6, 7, 5,
+        0, 1, 3, 3, 2, 0,
+    ];
+
+    /// The faces are in the order +X, -X, +Y, -Y, +Z and -Z. They have to be square and the same size.
+    pub fn new(faces: [DynamicImage; 6], asset_key: Option<String>) -> Self {
+        Self {
+            cube_map: Arc::new(RwLock::new(CubeMapResource {
+                faces,
+                binding: 0,
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                asset_key,
+            })),
+        }
+    }
+}
+
+impl GraphicsObject<SkyboxVertex> for Skybox {
+    fn get_vertices(&self) -> Vec<SkyboxVertex> {
+        Self::CORNERS.to_vec()
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        Self::INDICES.to_vec()
+    }
+
+    fn get_index_type(&self) -> vk::IndexType {
+        vk::IndexType::UINT16
+    }
+
+    fn get_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)> {
+        Vec::new()
+    }
+
+    fn get_shader_infos(&self) -> Vec<ShaderInfo> {
+        vec![
+            BuiltinShader::SkyboxVertex.get_shader_info(),
+            BuiltinShader::SkyboxFragment.get_shader_info(),
+        ]
+    }
+
+    // Every skybox has the same cube, so the cube map is part of the hash to give each one its own object type
+    fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        let mut hasher = DefaultHasher::new();
+        Self::CORNERS.iter().for_each(|vertex| vertex.hash(&mut hasher));
+        Self::INDICES.iter().for_each(|index| index.hash(&mut hasher));
+        (Arc::as_ptr(&self.cube_map) as *const () as usize).hash(&mut hasher);
+        VerticesIndicesHash(hasher.finish())
+    }
+
+    fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)> {
+        vec![(ResourceID(0), self.cube_map.clone())]
+    }
+}
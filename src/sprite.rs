@@ -0,0 +1,272 @@
+use std::{borrow::Cow, hash::{DefaultHasher, Hash, Hasher}, sync::{Arc, RwLock}};
+
+use ash::vk;
+use image::DynamicImage;
+use memoffset::offset_of;
+use nalgebra_glm as glm;
+
+use crate::{builtin_shaders::BuiltinShader, gpu_layout::{self, BlockLayout, GpuLayout, TypeLayout}, graphics_objects::{GraphicsObject, ResourceID, TextureResource, UniformBufferResource}, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectTypeGraphicsResource, ShaderInfo, Vertex}, vk_allocator::Serializable, vk_controller::VerticesIndicesHash};
+
+/// Maps pixel coordinates, with the origin at the top left of the render area, to Vulkan's normalized device coordinates.
+/// The sprite shader does the same with the viewport size in the per-frame data, so sprites follow resizes without any updates.
+pub struct Ortho2D;
+
+impl Ortho2D {
+    /// For custom 2D shaders. Depth 0 is the nearest and 1 the farthest, like the sprites.
+    pub fn projection(extent: vk::Extent2D) -> glm::Mat4 {
+        // Vulkan's NDC already has y pointing down, so the top of the render area is the bottom of the projection
+        glm::ortho_lh_zo(0.0, extent.width as f32, 0.0, extent.height as f32, 0.0, 1.0)
+    }
+}
+
+/// A corner of the sprite quad, from (0, 0) at the top left to (1, 1) at the bottom right.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SpriteVertex {
+    pub corner: glm::Vec2,
+}
+
+impl Vertex for SpriteVertex {
+    fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: offset_of!(Self, corner) as u32,
+        }]
+    }
+}
+
+impl Hash for SpriteVertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.corner.iter().for_each(|&i| i.to_bits().hash(state));
+    }
+}
+
+impl Serializable for SpriteVertex {
+    fn to_u8(&self) -> Vec<u8> {
+        self.corner.iter().flat_map(|x| x.to_ne_bytes()).collect()
+    }
+}
+
+/// A rectangle of a texture in UV coordinates, with (0, 0) at the top left of the texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub u: f32,
+    pub v: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl UvRect {
+    /// The whole texture.
+    pub const FULL: Self = Self { u: 0.0, v: 0.0, width: 1.0, height: 1.0 };
+
+    pub fn new(u: f32, v: f32, width: f32, height: f32) -> Self {
+        Self { u, v, width, height }
+    }
+
+    /// The cell at `index` of a sprite sheet with equally sized cells, counted row by row from the top left.
+    pub fn from_grid(columns: u32, rows: u32, index: u32) -> Self {
+        let width = 1.0 / columns as f32;
+        let height = 1.0 / rows as f32;
+        Self {
+            u: (index % columns) as f32 * width,
+            v: (index / columns) as f32 * height,
+            width,
+            height,
+        }
+    }
+}
+
+// A vec4 in the shaders
+impl GpuLayout for UvRect {
+    const STD140: TypeLayout = glm::Vec4::STD140;
+    const STD430: TypeLayout = glm::Vec4::STD430;
+
+    fn write_bytes(&self, layout: BlockLayout, bytes: &mut [u8]) {
+        glm::vec4(self.u, self.v, self.width, self.height).write_bytes(layout, bytes);
+    }
+}
+
+/// A flip-book animation that steps through sprite sheet cells. Started on a sprite with [`crate::vk_controller::VkController::set_sprite_animation`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteAnimation {
+    pub frames: Vec<UvRect>,
+    pub fps: f32,
+    // Non looping animations stay on the last frame when they are finished
+    pub looping: bool,
+}
+
+impl SpriteAnimation {
+    pub fn new(frames: Vec<UvRect>, fps: f32, looping: bool) -> Self {
+        Self { frames, fps, looping }
+    }
+
+    /// Plays the first `frame_count` cells of the sprite sheet in order, see [`UvRect::from_grid`].
+    pub fn from_grid(columns: u32, rows: u32, frame_count: u32, fps: f32, looping: bool) -> Self {
+        Self::new((0..frame_count).map(|index| UvRect::from_grid(columns, rows, index)).collect(), fps, looping)
+    }
+
+    pub fn validate(&self) -> Result<(), Cow<'static, str>> {
+        if self.frames.is_empty() {
+            return Err(Cow::from("A sprite animation needs at least one frame"));
+        }
+        if self.fps.is_nan() || self.fps <= 0.0 {
+            return Err(Cow::Owned(format!("A sprite animation needs a positive frame rate, but it was {}", self.fps)));
+        }
+        Ok(())
+    }
+
+    /// The frame shown after `elapsed` seconds, and whether a non looping animation has finished.
+    pub fn get_frame_index(&self, elapsed: f32) -> (usize, bool) {
+        let frame = (elapsed * self.fps) as usize;
+        if self.looping {
+            (frame % self.frames.len(), false)
+        } else {
+            (frame.min(self.frames.len() - 1), frame >= self.frames.len())
+        }
+    }
+}
+
+/// The per-instance data the sprite shader reads from `layout(set = 1, binding = 0)`, laid out with std430.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteInstanceData {
+    pub position_px: glm::Vec2,
+    pub size_px: glm::Vec2,
+    // The part of the texture that is drawn
+    pub uv_rect: UvRect,
+    // 0 is in front and 1 is at the back. Sprites at the same depth are drawn in no particular order
+    pub depth: f32,
+    // In radians around the center of the sprite, clockwise on the screen
+    pub rotation: f32,
+    // Multiplies the size, so a sprite can be scaled around its center without changing its size
+    pub scale: glm::Vec2,
+}
+
+crate::impl_gpu_layout!(SpriteInstanceData {
+    position_px: glm::Vec2,
+    size_px: glm::Vec2,
+    uv_rect: UvRect,
+    depth: f32,
+    rotation: f32,
+    scale: glm::Vec2,
+});
+
+// The stride of the sprites array in the shader, a multiple of the 16 byte alignment the vec4 gives the struct
+const _: () = gpu_layout::assert_std430_size::<SpriteInstanceData>(48);
+
+/// A textured quad positioned in pixels, which can be rotated and scaled around its center. Sprites that share the same texture [`Arc`] are one object type, so they are drawn with a single instanced draw.
+pub struct Sprite {
+    pub texture: Arc<RwLock<TextureResource>>,
+    pub instance_data: Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>,
+}
+
+impl Sprite {
+    const CORNERS: [SpriteVertex; 4] = [
+        SpriteVertex { corner: glm::Vec2::new(0.0, 0.0) },
+        SpriteVertex { corner: glm::Vec2::new(1.0, 0.0) },
+        SpriteVertex { corner: glm::Vec2::new(1.0, 1.0) },
+        SpriteVertex { corner: glm::Vec2::new(0.0, 1.0) },
+    ];
+    const INDICES: [u32; 6] = [0, 1, 2, 2, 3, 0];
+
+    /// Draws the whole texture at depth 0.5, without any rotation or scale.
+    pub fn new(texture: Arc<RwLock<TextureResource>>, position_px: glm::Vec2, size_px: glm::Vec2) -> Self {
+        Self {
+            texture,
+            instance_data: Arc::new(RwLock::new(UniformBufferResource {
+                buffer: SpriteInstanceData {
+                    position_px,
+                    size_px,
+                    uv_rect: UvRect::FULL,
+                    depth: 0.5,
+                    rotation: 0.0,
+                    scale: glm::Vec2::new(1.0, 1.0),
+                },
+                binding: 0,
+            })),
+        }
+    }
+
+    /// Creates the texture with the binding the sprite shader reads it from. Create it once and give the same [`Arc`] to every sprite that uses it.
+    pub fn create_texture(image: DynamicImage, asset_key: Option<String>) -> Arc<RwLock<TextureResource>> {
+        Arc::new(RwLock::new(TextureResource::new(image, 1, vk::ShaderStageFlags::FRAGMENT, asset_key)))
+    }
+
+    pub fn set_position(&self, position_px: glm::Vec2) {
+        self.instance_data.write().unwrap().buffer.position_px = position_px;
+    }
+
+    pub fn set_size(&self, size_px: glm::Vec2) {
+        self.instance_data.write().unwrap().buffer.size_px = size_px;
+    }
+
+    /// In radians around the center of the sprite, clockwise on the screen.
+    pub fn set_rotation(&self, rotation: f32) {
+        self.instance_data.write().unwrap().buffer.rotation = rotation;
+    }
+
+    pub fn set_scale(&self, scale: glm::Vec2) {
+        self.instance_data.write().unwrap().buffer.scale = scale;
+    }
+
+    /// Overwritten every frame while the sprite has an animation.
+    pub fn set_uv_rect(&self, uv_rect: UvRect) {
+        self.instance_data.write().unwrap().buffer.uv_rect = uv_rect;
+    }
+
+    pub fn set_depth(&self, depth: f32) {
+        self.instance_data.write().unwrap().buffer.depth = depth;
+    }
+}
+
+impl GraphicsObject<SpriteVertex> for Sprite {
+    fn get_vertices(&self) -> Vec<SpriteVertex> {
+        Self::CORNERS.to_vec()
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        Self::INDICES.to_vec()
+    }
+
+    fn get_index_type(&self) -> vk::IndexType {
+        vk::IndexType::UINT16
+    }
+
+    fn get_instance_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectInstanceGraphicsResource>>)> {
+        vec![(ResourceID(0), self.instance_data.clone())]
+    }
+
+    fn get_shader_infos(&self) -> Vec<ShaderInfo> {
+        vec![
+            BuiltinShader::SpriteVertex.get_shader_info(),
+            BuiltinShader::SpriteFragment.get_shader_info(),
+        ]
+    }
+
+    // Every sprite has the same quad, so the texture is part of the hash to give each texture its own object type
+    fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        let mut hasher = DefaultHasher::new();
+        Self::CORNERS.iter().for_each(|vertex| vertex.hash(&mut hasher));
+        Self::INDICES.iter().for_each(|index| index.hash(&mut hasher));
+        (Arc::as_ptr(&self.texture) as *const () as usize).hash(&mut hasher);
+        VerticesIndicesHash(hasher.finish())
+    }
+
+    fn get_type_resources(&self) -> Vec<(ResourceID, Arc<RwLock<dyn ObjectTypeGraphicsResource>>)> {
+        vec![(ResourceID(1), self.texture.clone())]
+    }
+
+    fn get_sprite_instance_data(&self) -> Option<Arc<RwLock<UniformBufferResource<SpriteInstanceData>>>> {
+        Some(self.instance_data.clone())
+    }
+}
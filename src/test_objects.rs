@@ -1,9 +1,7 @@
-use std::{collections::{hash_map, HashMap}, hash::{self, Hash, Hasher}, sync::{Arc, RwLock}};
-use ash::{vk::{self, DescriptorBufferInfo, DescriptorImageInfo, DescriptorPool, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateFlags, DescriptorSetLayoutCreateInfo, StructureType}, Device};
-use image::DynamicImage;
+use std::{hash::{self, Hash, Hasher}, sync::{Arc, RwLock}};
 use nalgebra_glm as glm;
 
-use crate::{graphics_objects::{GraphicsObject, ResourceID, TextureResource, UniformBufferResource}, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectInstanceGraphicsResourceType, ObjectTypeGraphicsResource, ObjectTypeGraphicsResourceType, ShaderInfo}, vertex::{OnlyTwoDPositionVertex, SimpleVertex}, vk_allocator::{Serializable, VkAllocator}, vk_controller::VerticesIndicesHash};
+use artewald_engine_2::{graphics_objects::{GraphicsObject, ResourceID, TextureResource, UniformBufferResource}, pipeline_manager::{ObjectInstanceGraphicsResource, ObjectTypeGraphicsResource, ShaderInfo}, vertex::{OnlyTwoDPositionVertex, SimpleVertex}, vk_controller::VerticesIndicesHash};
 
 // =========================================== Resources ===========================================
 
@@ -51,18 +49,6 @@ use crate::{graphics_objects::{GraphicsObject, ResourceID, TextureResource, Unif
 //     }
 // }
 
-impl Serializable for glm::Mat4 {
-    fn to_u8(&self) -> Vec<u8> {
-        let mat = self.as_slice();
-        let mut result = Vec::with_capacity(std::mem::size_of::<glm::Mat4>());
-        for i in 0..16 {
-            result.extend_from_slice(&mat[i].to_ne_bytes());
-        }
-
-        result
-    }
-}
-
 // =========================================== Objects ===========================================
 
 pub struct SimpleRenderableObject {
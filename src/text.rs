@@ -0,0 +1,389 @@
+use std::{borrow::Cow, collections::HashMap, hash::{Hash, Hasher}};
+
+use ash::{vk::{self, CommandPool, DescriptorPool, DescriptorSet, PhysicalDevice, Queue, StructureType}, Device, Instance};
+use image::DynamicImage;
+use memoffset::offset_of;
+use nalgebra_glm as glm;
+
+use crate::{logging, builtin_shaders::BuiltinShader, free_allocations_add_error_string, pipeline_manager::{PipelineConfig, PipelineManager, Vertex}, sampler_manager::{SamplerConfig, SamplerManager}, sprite::UvRect, vk_allocator::{AllocationInfo, Serializable, VkAllocator}, vk_controller::VkController};
+
+/// A corner of a glyph quad, positioned in pixels from the top left of the render area.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct TextVertex {
+    pub position_px: glm::Vec2,
+    pub tex_coord: glm::Vec2,
+    pub color: glm::Vec4,
+}
+
+impl Vertex for TextVertex {
+    fn get_input_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, position_px) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, tex_coord) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(Self, color) as u32,
+            },
+        ]
+    }
+}
+
+impl Hash for TextVertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.position_px.iter()
+            .chain(self.tex_coord.iter())
+            .chain(self.color.iter())
+            .for_each(|&i| i.to_bits().hash(state));
+    }
+}
+
+impl Serializable for TextVertex {
+    fn to_u8(&self) -> Vec<u8> {
+        self.position_px.iter()
+            .chain(self.tex_coord.iter())
+            .chain(self.color.iter())
+            .flat_map(|x| x.to_ne_bytes())
+            .collect()
+    }
+}
+
+/// Where a character is in the font atlas and how it is placed on the line, in pixels of the atlas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    pub uv_rect: UvRect,
+    pub size_px: glm::Vec2,
+    // From the pen position at the top of the line to the top left of the glyph
+    pub offset_px: glm::Vec2,
+    // How far the pen moves to the right after the glyph
+    pub advance_px: f32,
+}
+
+/// A font atlas image with the metrics of its glyphs, which [`VkController::draw_text`] draws with.
+pub struct BitmapFont {
+    atlas: DynamicImage,
+    glyphs: HashMap<char, Glyph>,
+    line_height_px: f32,
+}
+
+impl BitmapFont {
+    /// Reads the metrics from the text format of BMFont. Only `lineHeight` of the `common` line and the `char` lines are used, so the font has to fit in a single page.
+    /// Every `char` line needs `id`, `x`, `y`, `width`, `height`, `xoffset`, `yoffset` and `xadvance`, where `id` is the unicode code point.
+    pub fn new(atlas: DynamicImage, metrics: &str) -> Result<Self, Cow<'static, str>> {
+        if atlas.width() == 0 || atlas.height() == 0 {
+            return Err(Cow::Borrowed("The font atlas can not be empty"));
+        }
+        let atlas_size = glm::Vec2::new(atlas.width() as f32, atlas.height() as f32);
+
+        let mut glyphs = HashMap::new();
+        let mut line_height_px = None;
+        for (line_index, line) in metrics.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let tag = tokens.next();
+            if tag != Some("common") && tag != Some("char") {
+                continue;
+            }
+            let values = tokens.filter_map(|token| token.split_once('=')).collect::<HashMap<_, _>>();
+            let get_value = |key: &str| -> Result<f32, Cow<'static, str>> {
+                values.get(key).and_then(|value| value.parse::<f32>().ok()).ok_or(Cow::Owned(format!("Line {} of the font metrics has no number for {}", line_index + 1, key)))
+            };
+
+            if tag == Some("common") {
+                line_height_px = Some(get_value("lineHeight")?);
+                continue;
+            }
+            let id = get_value("id")? as u32;
+            let character = char::from_u32(id).ok_or(Cow::Owned(format!("Line {} of the font metrics has the id {}, which is not a character", line_index + 1, id)))?;
+            let position_px = glm::Vec2::new(get_value("x")?, get_value("y")?);
+            let size_px = glm::Vec2::new(get_value("width")?, get_value("height")?);
+            if position_px.x + size_px.x > atlas_size.x || position_px.y + size_px.y > atlas_size.y {
+                return Err(Cow::Owned(format!("The glyph of {:?} is outside of the font atlas", character)));
+            }
+            glyphs.insert(character, Glyph {
+                uv_rect: UvRect::new(position_px.x / atlas_size.x, position_px.y / atlas_size.y, size_px.x / atlas_size.x, size_px.y / atlas_size.y),
+                size_px,
+                offset_px: glm::Vec2::new(get_value("xoffset")?, get_value("yoffset")?),
+                advance_px: get_value("xadvance")?,
+            });
+        }
+
+        if glyphs.is_empty() {
+            return Err(Cow::Borrowed("The font metrics have no glyphs"));
+        }
+        Ok(Self {
+            atlas,
+            glyphs,
+            line_height_px: line_height_px.ok_or(Cow::Borrowed("The font metrics have no common line with the line height"))?,
+        })
+    }
+
+    pub fn get_glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.get(&character)
+    }
+
+    pub fn get_line_height(&self) -> f32 {
+        self.line_height_px
+    }
+}
+
+/// Draws the text queued during a frame with a single non-indexed draw, from a vertex buffer that is written again every frame.
+pub struct TextRenderer {
+    glyphs: HashMap<char, Glyph>,
+    line_height_px: f32,
+    atlas: AllocationInfo,
+    descriptor_pool: DescriptorPool,
+    descriptor_set: DescriptorSet,
+    pipeline_config: PipelineConfig,
+    // One part for each frame in flight, since the vertices of the earlier frames might still be read
+    vertex_buffers: AllocationInfo,
+    num_vertices: [u32; VkController::MAX_FRAMES_IN_FLIGHT],
+    queued_vertices: Vec<TextVertex>,
+}
+
+impl TextRenderer {
+    /// The most glyphs that are drawn in one frame, the rest are skipped.
+    pub const MAX_GLYPHS_PER_FRAME: usize = 4096;
+    const VERTICES_PER_GLYPH: usize = 6;
+
+    pub fn new(font: BitmapFont, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &CommandPool, graphics_queue: &Queue, pipeline_manager: &mut PipelineManager, sampler_manager: &mut SamplerManager, msaa_samples: vk::SampleCountFlags, swapchain_format: vk::Format, depth_format: vk::Format, swapchain_extent: &vk::Extent2D, allocator: &VkAllocator) -> Result<Self, Cow<'static, str>> {
+        let BitmapFont { atlas, glyphs, line_height_px } = font;
+
+        let shaders = vec![
+            BuiltinShader::TextVertex.get_shader_info(),
+            BuiltinShader::TextFragment.get_shader_info(),
+        ];
+        let layout_bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: std::ptr::null(),
+        }];
+        let mut pipeline_config = PipelineConfig::new(device, shaders, TextVertex::get_input_binding_description(), TextVertex::get_attribute_descriptions(), &layout_bindings, msaa_samples, swapchain_format, depth_format, allocator)?;
+        // Creating the pipeline also creates the descriptor set layout the font atlas is written to
+        pipeline_manager.get_or_create_pipeline(&mut pipeline_config, device, swapchain_extent, allocator)?;
+
+        // The glyphs are drawn at the size they have in the atlas or larger, so they don't need any mip levels
+        let mut atlas = allocator.create_device_local_image(atlas, command_pool, graphics_queue, 1, vk::SampleCountFlags::TYPE_1, false)?;
+        // The format needs to be the same as the format read in [`VkAllocator::create_device_local_image`]
+        if let Err(e) = allocator.create_image_view(&mut atlas, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D) {
+            let mut error_str = e.to_string();
+            free_allocations_add_error_string!(allocator, [atlas], error_str);
+            return Err(Cow::from(error_str));
+        }
+
+        let sampler_config = SamplerConfig {
+            s_type: StructureType::SAMPLER_CREATE_INFO,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            anisotropy_enable: vk::FALSE,
+            max_anisotropy: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: 0.0,
+        };
+        let sampler = match sampler_manager.get_or_create_sampler(device, instance, physical_device, sampler_config, allocator) {
+            Ok(sampler) => sampler,
+            Err(e) => {
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [atlas], error_str);
+                return Err(Cow::from(error_str));
+            },
+        };
+
+        let (descriptor_pool, descriptor_set) = match Self::create_descriptor_set(device, *pipeline_config.borrow_descriptor_set_layout().unwrap(), allocator) {
+            Ok(descriptor) => descriptor,
+            Err(e) => {
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [atlas], error_str);
+                return Err(Cow::from(error_str));
+            },
+        };
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: atlas.get_image_view().unwrap(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            s_type: StructureType::WRITE_DESCRIPTOR_SET,
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+        unsafe {
+            device.update_descriptor_sets(&[descriptor_write], &[]);
+        }
+
+        let vertex_buffers = match allocator.create_vertex_buffers(Self::MAX_GLYPHS_PER_FRAME * Self::VERTICES_PER_GLYPH * std::mem::size_of::<TextVertex>(), VkController::MAX_FRAMES_IN_FLIGHT) {
+            Ok(vertex_buffers) => vertex_buffers,
+            Err(e) => {
+                unsafe {
+                    device.destroy_descriptor_pool(descriptor_pool, allocator.get_allocation_callbacks());
+                }
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [atlas], error_str);
+                return Err(Cow::from(error_str));
+            },
+        };
+
+        Ok(Self {
+            glyphs,
+            line_height_px,
+            atlas,
+            descriptor_pool,
+            descriptor_set,
+            pipeline_config,
+            vertex_buffers,
+            num_vertices: [0; VkController::MAX_FRAMES_IN_FLIGHT],
+            queued_vertices: Vec::new(),
+        })
+    }
+
+    /// Adds a quad for every character of the text, starting with the top left of the first line at `position_px`. Characters without a glyph in the font are skipped.
+    pub fn queue_text(&mut self, text: &str, position_px: glm::Vec2, scale: f32, color: glm::Vec4) {
+        let mut pen_px = position_px;
+        for character in text.chars() {
+            if character == '\n' {
+                pen_px = glm::Vec2::new(position_px.x, pen_px.y + self.line_height_px * scale);
+                continue;
+            }
+            let Some(glyph) = self.glyphs.get(&character) else {
+                continue;
+            };
+
+            let top_left = pen_px + glyph.offset_px * scale;
+            let bottom_right = top_left + glyph.size_px * scale;
+            let uv_top_left = glm::Vec2::new(glyph.uv_rect.u, glyph.uv_rect.v);
+            let uv_bottom_right = uv_top_left + glm::Vec2::new(glyph.uv_rect.width, glyph.uv_rect.height);
+            let corners = [
+                (top_left, uv_top_left),
+                (glm::Vec2::new(bottom_right.x, top_left.y), glm::Vec2::new(uv_bottom_right.x, uv_top_left.y)),
+                (bottom_right, uv_bottom_right),
+                (glm::Vec2::new(top_left.x, bottom_right.y), glm::Vec2::new(uv_top_left.x, uv_bottom_right.y)),
+            ];
+            // The same winding as the sprite quad
+            self.queued_vertices.extend([0, 1, 2, 2, 3, 0].map(|corner| TextVertex {
+                position_px: corners[corner].0,
+                tex_coord: corners[corner].1,
+                color,
+            }));
+            pen_px.x += glyph.advance_px * scale;
+        }
+    }
+
+    /// Writes the queued text to the vertex buffer of the frame. The queue is kept until [`TextRenderer::clear_queued_text`] is called.
+    pub fn upload_queued_text(&mut self, current_frame: usize) {
+        let max_vertices = Self::MAX_GLYPHS_PER_FRAME * Self::VERTICES_PER_GLYPH;
+        if self.queued_vertices.len() > max_vertices {
+            log::trace!(target: logging::RENDERER, "Only the first {} of the {} glyphs are drawn", Self::MAX_GLYPHS_PER_FRAME, self.queued_vertices.len() / Self::VERTICES_PER_GLYPH);
+        }
+        let vertices = &self.queued_vertices[..self.queued_vertices.len().min(max_vertices)];
+        let data = vertices.iter().flat_map(|vertex| vertex.to_u8()).collect::<Vec<u8>>();
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.vertex_buffers.get_uniform_pointers()[current_frame].cast::<u8>(), data.len());
+        }
+        self.num_vertices[current_frame] = vertices.len() as u32;
+    }
+
+    pub fn clear_queued_text(&mut self) {
+        self.queued_vertices.clear();
+    }
+
+    pub fn get_pipeline_config(&self) -> &PipelineConfig {
+        &self.pipeline_config
+    }
+
+    pub fn get_descriptor_set(&self) -> DescriptorSet {
+        self.descriptor_set
+    }
+
+    /// The vertex buffer and the offset of the frame's vertices in it.
+    pub fn get_vertex_buffer(&self, current_frame: usize) -> (vk::Buffer, vk::DeviceSize) {
+        let offset = unsafe { self.vertex_buffers.get_uniform_pointers()[current_frame].offset_from(self.vertex_buffers.get_uniform_pointers()[0]) } as vk::DeviceSize;
+        (self.vertex_buffers.get_buffer().unwrap(), offset)
+    }
+
+    pub fn get_num_vertices(&self, current_frame: usize) -> u32 {
+        self.num_vertices[current_frame]
+    }
+
+    /// The pipeline is owned by the pipeline manager, so it is destroyed with the other pipelines.
+    pub fn destroy(self, device: &Device, allocator: &VkAllocator) {
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, allocator.get_allocation_callbacks());
+        }
+        let mut error_str = String::new();
+        free_allocations_add_error_string!(allocator, [self.atlas, self.vertex_buffers], error_str);
+        if !error_str.is_empty() {
+            log::error!(target: logging::RENDERER, "Failed to free the text renderer: {}", error_str);
+        }
+    }
+
+    fn create_descriptor_set(device: &Device, descriptor_set_layout: vk::DescriptorSetLayout, allocator: &VkAllocator) -> Result<(DescriptorPool, DescriptorSet), Cow<'static, str>> {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            max_sets: 1,
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&pool_info, allocator.get_allocation_callbacks())
+        }.map_err(|err| Cow::Owned(format!("Failed to create the text descriptor pool: {}", err)))?;
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &descriptor_set_layout,
+            ..Default::default()
+        };
+        match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+            Ok(descriptor_sets) => Ok((descriptor_pool, descriptor_sets[0])),
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_pool(descriptor_pool, allocator.get_allocation_callbacks());
+                }
+                Err(Cow::Owned(format!("Failed to allocate the text descriptor set: {}", err)))
+            },
+        }
+    }
+}
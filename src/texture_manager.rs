@@ -0,0 +1,145 @@
+use std::borrow::Cow;
+
+use ash::{vk::{self, DescriptorPool, DescriptorSet, DescriptorSetLayout, PhysicalDevice, Queue, Sampler, StructureType}, Device, Instance};
+use image::DynamicImage;
+
+use crate::{logging, free_allocations_add_error_string, graphics_objects::TextureHandle, sampler_manager::{SamplerConfig, SamplerManager}, vk_allocator::{AllocationInfo, VkAllocator}};
+
+/// Owns the bindless texture array, which is a single descriptor set with one `COMBINED_IMAGE_SAMPLER` array binding that every pipeline can index into.
+pub struct TextureManager {
+    descriptor_pool: DescriptorPool,
+    descriptor_set: DescriptorSet,
+    textures: Vec<(AllocationInfo, Sampler)>,
+    max_textures: u32,
+}
+
+impl TextureManager {
+    pub fn new(device: &Device, descriptor_set_layout: &DescriptorSetLayout, max_textures: u32, allocator: &VkAllocator) -> Result<Self, Cow<'static, str>> {
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: max_textures,
+            },
+        ];
+
+        // The textures are written to the descriptor set while it might be bound in a command buffer that is in flight
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            max_sets: 1,
+            flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+            ..Default::default()
+        };
+
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&pool_info, allocator.get_allocation_callbacks())
+        }.map_err(|err| Cow::Owned(format!("Failed to create the bindless texture descriptor pool: {}", err)))?;
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: descriptor_set_layout,
+            ..Default::default()
+        };
+
+        let descriptor_set = match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+            Ok(descriptor_sets) => descriptor_sets[0],
+            Err(err) => {
+                unsafe {
+                    device.destroy_descriptor_pool(descriptor_pool, allocator.get_allocation_callbacks());
+                }
+                return Err(Cow::Owned(format!("Failed to allocate the bindless texture descriptor set: {}", err)));
+            },
+        };
+
+        Ok(Self {
+            descriptor_pool,
+            descriptor_set,
+            textures: Vec::new(),
+            max_textures,
+        })
+    }
+
+    /// Uploads the image and writes it to the next free element of the texture array. The returned handle is the index shaders use to sample it.
+    pub fn add_texture(&mut self, image: DynamicImage, device: &Device, instance: &Instance, physical_device: &PhysicalDevice, command_pool: &vk::CommandPool, graphics_queue: &Queue, sampler_manager: &mut SamplerManager, allocator: &VkAllocator) -> Result<TextureHandle, Cow<'static, str>> {
+        if self.textures.len() as u32 >= self.max_textures {
+            return Err(Cow::Owned(format!("The bindless texture array is full, it can hold at most {} textures", self.max_textures)));
+        }
+
+        let mut allocation = allocator.create_device_local_image(image, command_pool, graphics_queue, u32::MAX, vk::SampleCountFlags::TYPE_1, false)?;
+        let mip_levels = allocation.get_mip_levels().unwrap();
+        // The format needs to be the same as the format read in [`VkAllocator::create_device_local_image`]
+        if let Err(e) = allocator.create_image_view(&mut allocation, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, mip_levels, vk::ImageViewType::TYPE_2D) {
+            let mut error_str = e.to_string();
+            free_allocations_add_error_string!(allocator, [allocation], error_str);
+            return Err(Cow::from(error_str));
+        }
+
+        let sampler_config = SamplerConfig {
+            s_type: StructureType::SAMPLER_CREATE_INFO,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            anisotropy_enable: vk::TRUE,
+            max_anisotropy: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: mip_levels as f32,
+        };
+        let sampler = match sampler_manager.get_or_create_sampler(device, instance, physical_device, sampler_config, allocator) {
+            Ok(sampler) => sampler,
+            Err(e) => {
+                let mut error_str = e.to_string();
+                free_allocations_add_error_string!(allocator, [allocation], error_str);
+                return Err(Cow::from(error_str));
+            },
+        };
+
+        let texture_handle = TextureHandle(self.textures.len() as u32);
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: allocation.get_image_view().unwrap(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            s_type: StructureType::WRITE_DESCRIPTOR_SET,
+            dst_set: self.descriptor_set,
+            dst_binding: 0,
+            dst_array_element: texture_handle.0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+        unsafe {
+            device.update_descriptor_sets(&[descriptor_write], &[]);
+        }
+
+        self.textures.push((allocation, sampler));
+        Ok(texture_handle)
+    }
+
+    pub fn get_descriptor_set(&self) -> DescriptorSet {
+        self.descriptor_set
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &VkAllocator) {
+        let mut error_str = String::new();
+        free_allocations_add_error_string!(allocator, self.textures.drain(..).map(|(allocation, _)| allocation), error_str);
+        if !error_str.is_empty() {
+            log::error!(target: logging::RENDERER, "Failed to free the bindless textures: {}", error_str);
+        }
+        unsafe {
+            device.destroy_descriptor_pool(self.descriptor_pool, allocator.get_allocation_callbacks());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Building objects on worker threads, see [`crate::vk_controller::VkController::create_upload_context`].
+//! A worker reads the mesh and the static textures of an object and uploads them through the shared allocator, which returns a [`PreparedObject`].
+//! The render thread then commits it with [`crate::vk_controller::VkController::commit_prepared`], which only makes the descriptor sets, the pipelines and the bookkeeping.
+
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use ash::vk;
+
+use crate::{async_loader::{PreparedMesh, PreparedRenderable}, error::EngineError, graphics_objects::{GraphicsObject, Renderable}, object_manager::{DataUsedInShader, MeshRegistry, TextureCache, TextureCacheKey}, pipeline_manager::{ObjectTypeGraphicsResourceType, Vertex}, vk_allocator::{AllocationInfo, UploadMarker, VkAllocator}, vk_controller::{TextureQuality, VerticesIndicesHash}};
+
+// Allocations that can be freed once the GPU has finished the uploads before the marker
+pub type AllocationsToFree = Arc<Mutex<Vec<(UploadMarker, AllocationInfo)>>>;
+
+/// The vertex and index buffers of a mesh with the bytes that were uploaded to them. The buffers are None when there is no data.
+pub struct PreparedMeshBuffers {
+    pub vertices: (Option<AllocationInfo>, Vec<u8>),
+    pub indices: (Option<AllocationInfo>, Vec<u8>),
+    pub index_type: vk::IndexType,
+}
+
+/// An uploaded texture with its view, and the key it gets in the texture cache.
+pub struct PreparedTexture {
+    pub key: (TextureCacheKey, TextureQuality, u32),
+    pub allocation: AllocationInfo,
+    pub full_quality_bytes: u64,
+}
+
+/// An object whose data is already on the GPU. It has to be given to [`crate::vk_controller::VkController::commit_prepared`] or [`UploadContext::discard`], otherwise its memory is only freed with the controller.
+pub struct PreparedObject {
+    // Renderables aren't Send, so the object is only made into one on the render thread
+    into_renderable: Box<dyn FnOnce() -> Box<dyn Renderable> + Send>,
+    mesh: Arc<PreparedMesh>,
+    mesh_buffers: PreparedMeshBuffers,
+    textures: Vec<PreparedTexture>,
+    upload_marker: UploadMarker,
+}
+
+impl PreparedObject {
+    pub fn get_vertices_and_indices_hash(&self) -> VerticesIndicesHash {
+        self.mesh.get_vertices_and_indices_hash()
+    }
+
+    /// The renderable to add, the mesh buffers and textures for the mesh registry and the texture cache, and the marker of their uploads.
+    pub fn into_parts(self) -> (Box<dyn Renderable>, PreparedMeshBuffers, Vec<PreparedTexture>, UploadMarker) {
+        let object = Box::new(PreparedRenderable::new((self.into_renderable)(), self.mesh)) as Box<dyn Renderable>;
+        (object, self.mesh_buffers, self.textures, self.upload_marker)
+    }
+
+    fn into_allocations(self) -> Vec<AllocationInfo> {
+        self.mesh_buffers.vertices.0.into_iter().chain(self.mesh_buffers.indices.0).chain(self.textures.into_iter().map(|texture| texture.allocation)).collect()
+    }
+}
+
+/// Uploads objects from any thread. Clones share the allocator and the command pool, which the allocator only uses while it is locked, so the uploads of different threads are serialized there.
+/// The texture quality and the mesh validation are the ones the controller had when the context was created. Textures uploaded with an older quality are freed on commit and uploaded again.
+/// It must not be used after the controller has been cleaned up.
+#[derive(Clone)]
+pub struct UploadContext {
+    allocator: Arc<VkAllocator>,
+    command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    texture_quality: TextureQuality,
+    max_mip_levels: u32,
+    is_mesh_validation_enabled: bool,
+    allocations_to_free: AllocationsToFree,
+}
+
+impl UploadContext {
+    pub fn new(allocator: Arc<VkAllocator>, command_pool: vk::CommandPool, graphics_queue: vk::Queue, (texture_quality, max_mip_levels): (TextureQuality, u32), is_mesh_validation_enabled: bool, allocations_to_free: AllocationsToFree) -> Self {
+        Self {
+            allocator,
+            command_pool,
+            graphics_queue,
+            texture_quality,
+            max_mip_levels,
+            is_mesh_validation_enabled,
+            allocations_to_free,
+        }
+    }
+
+    /// Reads the mesh and the static textures of the object and submits their uploads. Textures that can't be uploaded are skipped, the render thread then uses the missing texture for them as usual.
+    pub fn prepare<T: Vertex + 'static>(&self, object: Arc<RwLock<dyn GraphicsObject<T> + Send + Sync>>) -> Result<PreparedObject, EngineError> {
+        let renderable = object.clone() as Arc<RwLock<dyn GraphicsObject<T>>>;
+        let vertices_and_indices_hash = renderable.get_vertices_and_indices_hash();
+        let mesh = PreparedMesh::read(renderable.clone(), vertices_and_indices_hash, self.is_mesh_validation_enabled)?;
+        let mesh_buffers = self.upload_mesh(&mesh, renderable.get_index_type())?;
+        let textures = match self.upload_textures(&renderable) {
+            Ok(textures) => textures,
+            Err(e) => {
+                self.free_after_upload(mesh_buffers.vertices.0.into_iter().chain(mesh_buffers.indices.0).collect());
+                return Err(e);
+            },
+        };
+
+        Ok(PreparedObject {
+            into_renderable: Box::new(move || Box::new(object as Arc<RwLock<dyn GraphicsObject<T>>>) as Box<dyn Renderable>),
+            mesh,
+            mesh_buffers,
+            textures,
+            upload_marker: self.allocator.get_upload_marker(),
+        })
+    }
+
+    /// Frees what was uploaded for an object that won't be committed. The memory is freed by the controller once the GPU has finished the uploads.
+    pub fn discard(&self, prepared_object: PreparedObject) {
+        self.free_after_upload(prepared_object.into_allocations());
+    }
+
+    // The copies into the allocations can still be running, so they are freed by the controller once the uploads submitted until now have finished
+    fn free_after_upload(&self, allocations: Vec<AllocationInfo>) {
+        let upload_marker = self.allocator.get_upload_marker();
+        let mut allocations_to_free = self.allocations_to_free.lock().unwrap_or_else(PoisonError::into_inner);
+        allocations_to_free.extend(allocations.into_iter().map(|allocation| (upload_marker, allocation)));
+    }
+
+    fn upload_mesh(&self, mesh: &PreparedMesh, index_type: vk::IndexType) -> Result<PreparedMeshBuffers, EngineError> {
+        let (index_type, indices_data) = MeshRegistry::get_index_data(mesh.get_vertices_and_indices_hash(), mesh.get_indices(), index_type);
+        let vertex_allocation = MeshRegistry::create_geometry_buffer(mesh.get_vertex_byte_data(), vk::BufferUsageFlags::VERTEX_BUFFER, &self.command_pool, &self.graphics_queue, &self.allocator)?;
+        let index_allocation = match MeshRegistry::create_geometry_buffer(&indices_data, vk::BufferUsageFlags::INDEX_BUFFER, &self.command_pool, &self.graphics_queue, &self.allocator) {
+            Ok(alloc) => alloc,
+            Err(e) => {
+                self.free_after_upload(vertex_allocation.into_iter().collect());
+                return Err(e);
+            },
+        };
+        Ok(PreparedMeshBuffers {
+            vertices: (vertex_allocation, mesh.get_vertex_byte_data().to_vec()),
+            indices: (index_allocation, indices_data),
+            index_type,
+        })
+    }
+
+    fn upload_textures(&self, object: &dyn Renderable) -> Result<Vec<PreparedTexture>, EngineError> {
+        let mut textures = Vec::new();
+        for (_, resource) in object.get_type_resources() {
+            let (images, asset_key) = match resource.read().unwrap().get_resource() {
+                ObjectTypeGraphicsResourceType::Texture(image, asset_key) => (vec![image], asset_key),
+                ObjectTypeGraphicsResourceType::CubeMap(faces, asset_key) => (faces.to_vec(), asset_key),
+                _ => continue,
+            };
+            if DataUsedInShader::get_invalid_texture_reason(&images).is_some() {
+                continue;
+            }
+
+            let key = TextureCache::get_key_for_quality(&images, asset_key, self.texture_quality, self.max_mip_levels);
+            let full_quality_bytes = images.iter().map(TextureCache::get_full_quality_bytes).sum();
+            match TextureCache::upload_texture(images, self.texture_quality, self.max_mip_levels, &self.command_pool, &self.graphics_queue, &self.allocator) {
+                Ok(allocation) => textures.push(PreparedTexture { key, allocation, full_quality_bytes }),
+                Err(e) => {
+                    self.free_after_upload(textures.into_iter().map(|texture| texture.allocation).collect());
+                    return Err(e);
+                },
+            }
+        }
+        Ok(textures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_contexts_can_be_shared_with_worker_threads() {
+        fn assert_clone_send_sync<T: Clone + Send + Sync>() {}
+        assert_clone_send_sync::<UploadContext>();
+        fn assert_send<T: Send>() {}
+        assert_send::<PreparedObject>();
+    }
+}
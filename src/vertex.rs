@@ -1,9 +1,9 @@
 use ash::vk;
 use memoffset::offset_of;
 use nalgebra_glm as glm;
-use std::{collections::VecDeque, f32::consts::PI, hash::{Hash, Hasher}, num};
+use std::{borrow::Cow, collections::VecDeque, f32::consts::PI, hash::{Hash, Hasher}, num};
 
-use crate::{pipeline_manager::Vertex, vk_allocator::Serializable};
+use crate::{lighting::LitVertex, pipeline_manager::Vertex, vk_allocator::Serializable};
 
 pub const TEST_RECTANGLE: [SimpleVertex; 4] = [
     SimpleVertex::new(glm::Vec3::new(-0.5, -0.5, 0.0), glm::Vec3::new(0.0, 0.0, 1.0), glm::Vec2::new(0.0, 0.0)),
@@ -154,8 +154,86 @@ impl Serializable for OnlyTwoDPositionVertex {
     }
 }
 
+// ========================================================================================================================================
+
+/// Gives each vertex the average normal of the triangles that use it, weighted by their area, for meshes that don't have normals.
+/// The triangles are expected to be counter-clockwise when seen from the front. Vertices that no triangle uses get a zero normal.
+pub fn compute_smooth_normals(vertices: &mut [LitVertex], indices: &[u32]) {
+    vertices.iter_mut().for_each(|vertex| vertex.normal = glm::Vec3::zeros());
+    for triangle in indices.chunks_exact(3) {
+        // The cross product's length is twice the triangle's area, so adding it unnormalized weights it by the area
+        let face_normal = triangle_cross(vertices, triangle);
+        triangle.iter().for_each(|&index| vertices[index as usize].normal += face_normal);
+    }
+    vertices.iter_mut().for_each(|vertex| {
+        if vertex.normal != glm::Vec3::zeros() {
+            vertex.normal = vertex.normal.normalize();
+        }
+    });
+}
+
+/// Gives each triangle its own vertices with the triangle's normal, so the faces are shaded flat instead of being smoothed into each other.
+/// This needs three vertices per triangle, so the mesh is returned with new vertices and indices.
+pub fn compute_flat_normals(vertices: &[LitVertex], indices: &[u32]) -> (Vec<LitVertex>, Vec<u32>) {
+    let mut flat_vertices = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let face_normal = triangle_cross(vertices, triangle);
+        let normal = if face_normal != glm::Vec3::zeros() { face_normal.normalize() } else { face_normal };
+        flat_vertices.extend(triangle.iter().map(|&index| LitVertex { normal, ..vertices[index as usize] }));
+    }
+    let flat_indices = (0..flat_vertices.len() as u32).collect();
+    (flat_vertices, flat_indices)
+}
+
+/// The tangent of each vertex for normal mapping, pointing in the direction the U coordinate increases and made perpendicular to the normal.
+/// `w` is 1.0 or -1.0 for the handedness, so the bitangent is `cross(normal, tangent.xyz) * tangent.w`. That is -1.0 where the UVs are mirrored.
+/// The normals have to be computed before this. Vertices that no triangle uses, or only with degenerate UVs, get a tangent perpendicular to the normal.
+pub fn compute_tangents(vertices: &[LitVertex], indices: &[u32]) -> Vec<glm::Vec4> {
+    let mut tangents = vec![glm::Vec3::zeros(); vertices.len()];
+    let mut bitangents = vec![glm::Vec3::zeros(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+        let (edge_1, edge_2) = (b.position - a.position, c.position - a.position);
+        let (delta_uv_1, delta_uv_2) = (b.tex_coord - a.tex_coord, c.tex_coord - a.tex_coord);
+        let determinant = delta_uv_1.x * delta_uv_2.y - delta_uv_2.x * delta_uv_1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+        // Not normalized, so the larger triangles count more, like for the smooth normals
+        let tangent = (edge_1 * delta_uv_2.y - edge_2 * delta_uv_1.y) / determinant;
+        let bitangent = (edge_2 * delta_uv_1.x - edge_1 * delta_uv_2.x) / determinant;
+        triangle.iter().for_each(|&index| {
+            tangents[index as usize] += tangent;
+            bitangents[index as usize] += bitangent;
+        });
+    }
+    vertices.iter().zip(tangents.iter().zip(bitangents.iter())).map(|(vertex, (&tangent, bitangent))| {
+        let normal = vertex.normal;
+        // Gram-Schmidt, so the tangent is perpendicular to the normal
+        let mut orthogonal = tangent - normal * normal.dot(&tangent);
+        if orthogonal.norm() <= f32::EPSILON {
+            let axis = if normal.x.abs() < 0.9 { glm::Vec3::x() } else { glm::Vec3::y() };
+            orthogonal = axis - normal * normal.dot(&axis);
+        }
+        let orthogonal = if orthogonal != glm::Vec3::zeros() { orthogonal.normalize() } else { orthogonal };
+        let handedness = if normal.cross(&orthogonal).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+        glm::Vec4::new(orthogonal.x, orthogonal.y, orthogonal.z, handedness)
+    }).collect()
+}
+
+fn triangle_cross(vertices: &[LitVertex], triangle: &[u32]) -> glm::Vec3 {
+    let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize].position);
+    (b - a).cross(&(c - a))
+}
+
+// ========================================================================================================================================
+// The circles all have their points on the circle counter-clockwise and are drawn with a triangle list. They differ in how the inside is split into triangles,
+// which matters because the GPU shades long thin triangles slower than triangles with the same area that are close to equilateral
 
-pub fn generate_circle_type_one(radius: f32, num_points: usize) -> (Vec<OnlyTwoDPositionVertex>, Vec<u32>) {
+/// A triangle fan from a vertex in the center, with `num_points + 1` vertices and `3 * num_points` indices.
+/// The simplest and cheapest to generate, but every triangle is long and thin and they all meet in the center, which is the slowest to draw for many points.
+pub fn generate_circle_type_one(radius: f32, num_points: usize) -> Result<(Vec<OnlyTwoDPositionVertex>, Vec<u32>), Cow<'static, str>> {
+    validate_circle(radius, num_points, 3)?;
     let points = calculate_circle_points(radius, num_points);
     let mut vertices = vec![OnlyTwoDPositionVertex { position: glm::Vec2::new(0.0, 0.0), _padding: 0.0}];
     let mut indices = Vec::new();
@@ -175,14 +253,17 @@ pub fn generate_circle_type_one(radius: f32, num_points: usize) -> (Vec<OnlyTwoD
     indices.push(1);
 
     indices.reverse();
-    (vertices, indices)
+    Ok((vertices, indices))
 }
 
-pub fn generate_circle_type_two(radius: f32, num_points: usize) -> (Vec<OnlyTwoDPositionVertex>, Vec<u32>) {
-    // if num_points % 2 != 0 {
-    //     panic!("Number of points must be even for this type of circle");
-    // }
-    
+/// Zigzags across the circle from the first point to the opposite one, with `num_points` vertices and `3 * (num_points - 2)` indices.
+/// There is no center vertex, but the triangles still get thin for many points. `num_points` has to be even, since the zigzag needs a point on each side.
+pub fn generate_circle_type_two(radius: f32, num_points: usize) -> Result<(Vec<OnlyTwoDPositionVertex>, Vec<u32>), Cow<'static, str>> {
+    validate_circle(radius, num_points, 4)?;
+    if !num_points.is_multiple_of(2) {
+        return Err(Cow::Owned(format!("The number of points has to be even for this type of circle, but it was {}", num_points)));
+    }
+
     let points = calculate_circle_points(radius, num_points);
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
@@ -210,10 +291,19 @@ pub fn generate_circle_type_two(radius: f32, num_points: usize) -> (Vec<OnlyTwoD
     indices.push(num_points as u32 / 2 + 1);
 
     // indices.reverse();
-    (vertices, indices)
+    Ok((vertices, indices))
 }
 
-pub fn generate_circle_type_three(radius: f32, num_points: usize) -> (Vec<OnlyTwoDPositionVertex>, Vec<u32>) {
+/// Starts with a triangle, square or hexagon and splits the edges on the outside in half until there are `num_points` vertices, with `3 * (num_points - 2)` indices.
+/// The triangles are as large as they can be, which makes it the fastest to draw, but the slowest to generate. The starting shape depends on `num_points % 3`,
+/// so the number of points has to be at least 3, 4 or 6 for the remainders 0, 1 and 2.
+pub fn generate_circle_type_three(radius: f32, num_points: usize) -> Result<(Vec<OnlyTwoDPositionVertex>, Vec<u32>), Cow<'static, str>> {
+    let min_points = match num_points % 3 {
+        0 => 3,
+        1 => 4,
+        _ => 6,
+    };
+    validate_circle(radius, num_points, min_points)?;
     let mut vertices = Vec::with_capacity(num_points);
     let mut indices = Vec::with_capacity(num_points * 3);
     let mut edge_queue = VecDeque::with_capacity(num_points);
@@ -235,7 +325,10 @@ pub fn generate_circle_type_three(radius: f32, num_points: usize) -> (Vec<OnlyTw
     for (i, &position) in positions.iter().enumerate() {
         vertices.push(OnlyTwoDPositionVertex { position, _padding: 0.0 });
         if i > 0 {
-            indices.extend_from_slice(&[0, i as u32, (i + 1) as u32 % positions.len() as u32]);
+            // The last point has no triangle, since the fan from point 0 already covers the shape
+            if i + 1 < positions.len() {
+                indices.extend_from_slice(&[0, i as u32, (i + 1) as u32]);
+            }
             edge_queue.push_back((i - 1, i));
         }
     }
@@ -252,7 +345,17 @@ pub fn generate_circle_type_three(radius: f32, num_points: usize) -> (Vec<OnlyTw
         edge_queue.push_back((mid_index, p2));
     }
 
-    (vertices, indices)
+    Ok((vertices, indices))
+}
+
+fn validate_circle(radius: f32, num_points: usize, min_points: usize) -> Result<(), Cow<'static, str>> {
+    if !(radius.is_finite() && radius > 0.0) {
+        return Err(Cow::Owned(format!("The radius of the circle has to be positive, but it was {}", radius)));
+    }
+    if num_points < min_points {
+        return Err(Cow::Owned(format!("This type of circle needs at least {} points, but it got {}", min_points, num_points)));
+    }
+    Ok(())
 }
 
 fn mid_point(p1: glm::Vec2, p2: glm::Vec2) -> glm::Vec2 {
@@ -270,3 +373,93 @@ fn calculate_circle_points(radius: f32, num_points: usize) -> Vec<glm::Vec2> {
         glm::Vec2::new(radius * angle.cos(), radius * angle.sin())
     }).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit cube around the origin, with shared corners and the triangles counter-clockwise seen from outside
+    fn get_cube() -> (Vec<LitVertex>, Vec<u32>) {
+        let vertices = (0..8).map(|corner| LitVertex {
+            position: glm::vec3((corner & 1) as f32 - 0.5, ((corner >> 1) & 1) as f32 - 0.5, ((corner >> 2) & 1) as f32 - 0.5),
+            normal: glm::Vec3::zeros(),
+            tex_coord: glm::Vec2::zeros(),
+        }).collect();
+        let indices = vec![
+            4, 5, 7, 7, 6, 4, // +z
+            0, 2, 3, 3, 1, 0, // -z
+            1, 3, 7, 7, 5, 1, // +x
+            0, 4, 6, 6, 2, 0, // -x
+            2, 6, 7, 7, 3, 2, // +y
+            0, 1, 5, 5, 4, 0, // -y
+        ];
+        (vertices, indices)
+    }
+
+    fn is_close(a: &glm::Vec3, b: &glm::Vec3) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    #[test]
+    fn flat_normals_of_a_cube_point_out_of_its_six_faces() {
+        let (vertices, indices) = get_cube();
+        let (flat_vertices, flat_indices) = compute_flat_normals(&vertices, &indices);
+        assert_eq!(flat_vertices.len(), 36);
+        assert_eq!(flat_indices, (0..36).collect::<Vec<_>>());
+
+        let directions = [glm::Vec3::z(), -glm::Vec3::z(), glm::Vec3::x(), -glm::Vec3::x(), glm::Vec3::y(), -glm::Vec3::y()];
+        for (face, direction) in directions.iter().enumerate() {
+            // Two triangles per face, and every vertex of them has the face's normal
+            for vertex in &flat_vertices[face * 6..face * 6 + 6] {
+                assert!(is_close(&vertex.normal, direction), "Face {} has the normal {:?} instead of {:?}", face, vertex.normal, direction);
+                // The vertices are on the face they point out of
+                assert!((vertex.position.dot(direction) - 0.5).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_normals_keep_the_positions_and_tex_coords() {
+        let (mut vertices, indices) = get_cube();
+        vertices.iter_mut().enumerate().for_each(|(i, vertex)| vertex.tex_coord = glm::vec2(i as f32, 0.0));
+        let (flat_vertices, _) = compute_flat_normals(&vertices, &indices);
+        for (flat_vertex, &index) in flat_vertices.iter().zip(indices.iter()) {
+            assert_eq!(flat_vertex.position, vertices[index as usize].position);
+            assert_eq!(flat_vertex.tex_coord, vertices[index as usize].tex_coord);
+        }
+    }
+
+    // A quad in the XY plane facing +z, with the given tex coords at its corners (-x -y), (+x -y), (+x +y) and (-x +y)
+    fn get_quad(tex_coords: [glm::Vec2; 4]) -> (Vec<LitVertex>, Vec<u32>) {
+        let positions = [glm::vec3(-1.0, -1.0, 0.0), glm::vec3(1.0, -1.0, 0.0), glm::vec3(1.0, 1.0, 0.0), glm::vec3(-1.0, 1.0, 0.0)];
+        let vertices = positions.iter().zip(tex_coords).map(|(&position, tex_coord)| LitVertex { position, normal: glm::Vec3::z(), tex_coord }).collect();
+        (vertices, vec![0, 1, 2, 2, 3, 0])
+    }
+
+    fn assert_tangents(tangents: &[glm::Vec4], direction: glm::Vec3, handedness: f32) {
+        assert_eq!(tangents.len(), 4);
+        for tangent in tangents {
+            assert!(is_close(&tangent.xyz(), &direction), "The tangent {:?} isn't {:?}", tangent.xyz(), direction);
+            assert_eq!(tangent.w, handedness);
+        }
+    }
+
+    #[test]
+    fn tangents_of_a_planar_quad_follow_u() {
+        let (vertices, indices) = get_quad([glm::vec2(0.0, 0.0), glm::vec2(1.0, 0.0), glm::vec2(1.0, 1.0), glm::vec2(0.0, 1.0)]);
+        assert_tangents(&compute_tangents(&vertices, &indices), glm::Vec3::x(), 1.0);
+    }
+
+    #[test]
+    fn tangents_follow_u_when_the_tex_coords_are_rotated() {
+        // U increases along +y and V along -x
+        let (vertices, indices) = get_quad([glm::vec2(0.0, 1.0), glm::vec2(0.0, 0.0), glm::vec2(1.0, 0.0), glm::vec2(1.0, 1.0)]);
+        assert_tangents(&compute_tangents(&vertices, &indices), glm::Vec3::y(), 1.0);
+    }
+
+    #[test]
+    fn tangents_of_mirrored_tex_coords_are_left_handed() {
+        let (vertices, indices) = get_quad([glm::vec2(1.0, 0.0), glm::vec2(0.0, 0.0), glm::vec2(0.0, 1.0), glm::vec2(1.0, 1.0)]);
+        assert_tangents(&compute_tangents(&vertices, &indices), -glm::Vec3::x(), -1.0);
+    }
+}
@@ -1,28 +1,44 @@
-use std::{borrow::Cow, collections::HashMap, ffi::c_void, rc::Rc, sync::{Arc, Mutex}};
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, ffi::c_void, sync::{Arc, Mutex, MutexGuard, PoisonError}};
 
 use ash::{vk::{self, DependencyFlags, StructureType, SystemAllocationScope}, Instance, Device};
 use image::DynamicImage;
 
+use crate::{logging, error::EngineError, vk_controller::{AllocationKind, AllocationKindStats, DefragmentationReport, HeapBudget, StagingStats}};
+
 type MemoryTypeIndex = u32;
 type MemoryOffset = vk::DeviceSize;
 type MemorySizeRange = (vk::DeviceSize, vk::DeviceSize);
+type AllocationID = u64;
 type Alignment = usize;
 
+/// The staging copies that had been submitted when it was taken, see [`VkAllocator::is_upload_finished`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct UploadMarker(pub usize);
+
 pub trait Serializable {
     fn to_u8(&self) -> Vec<u8>;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct AllocationInfo {
     buffer: Option<vk::Buffer>,
     image: Option<vk::Image>,
     mip_levels: Option<u32>,
+    // 6 for cube maps, otherwise 1
+    array_layers: u32,
     image_view: Option<vk::ImageView>,
     memory_index: MemoryTypeIndex,
     memory_start: MemoryOffset,
     memory_end: vk::DeviceSize,
     memory: vk::DeviceMemory,
     uniform_pointers: Vec<*mut c_void>,
+    // The size of the part each uniform pointer points to
+    uniform_buffer_size: vk::DeviceSize,
+    // The size and raw vk::BufferUsageFlags the buffer was created with, which are needed to recreate it when it is moved by defragment
+    buffer_size_and_usage: Option<(vk::DeviceSize, vk::Flags)>,
+    // Unique for every allocation the allocator makes, so freeing a clone of an allocation that is already freed can be detected
+    allocation_id: AllocationID,
+    kind: AllocationKind,
 }
 
 #[derive(Debug)]
@@ -30,84 +46,418 @@ struct HostAllocationPool {
     start_ptr: *mut u8,
     size: usize,
     alignment: Alignment,
+    // Sorted byte ranges, the start is included and the end excluded. Every start is a multiple of the alignment
     free_allocations: Vec<(usize, usize)>,
 }
 
+#[derive(Debug, Clone, Copy)]
+struct HostAllocation {
+    alignment: Alignment,
+    // The size that was asked for
+    size: usize,
+    // The size taken from the pool, which is padded to keep the next allocation aligned
+    reserved_size: usize,
+}
+
+/// A persistently mapped upload buffer that is used as a ring. A region is reused once the fence of the copy that reads it has signaled.
+struct StagingRing {
+    allocation: AllocationInfo,
+    mapped_ptr: *mut u8,
+    head: vk::DeviceSize,
+    // Oldest first, so the front region is always the next one to retire
+    in_flight: VecDeque<StagingRegion>,
+    free_fences: Vec<vk::Fence>,
+}
+
+struct StagingRegion {
+    // The number of ring uploads that had been submitted when this one was
+    sequence: usize,
+    start: vk::DeviceSize,
+    end: vk::DeviceSize,
+    fence: vk::Fence,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+}
+
+/// Hands out the device memory, buffers and images of the renderer. It is `Send + Sync`, so it can be shared with worker threads that upload at the same time.
+/// Every call locks the device memory side for as long as it runs, so concurrent calls are serialized. What the callers do before and after, like building the data, runs in parallel.
+/// The calls that record commands take the queue lock around their submits. Anything else that uses the same queue, or calls `vkDeviceWaitIdle`, has to hold [`VkAllocator::lock_queue`] while doing it.
+/// The command pool given to a call is used from the calling thread, so every thread has to give its own pool, or one that is only ever used through the allocator.
 pub struct VkAllocator {
-    device: Rc<Device>,
+    // Declared before the host allocator, so it is dropped while the callbacks can still be called
+    device_memory: Mutex<DeviceMemoryAllocator>,
+    queue_lock: Arc<Mutex<()>>,
+    host_allocator: Arc<Mutex<VkHostAllocator>>,
+    // Only set when the host allocations go through host_allocator. The user data holds one reference to it, which is released on drop
+    allocation_callbacks: Option<vk::AllocationCallbacks>,
+}
+
+// The callbacks only point at the host allocator, which is behind its mutex, and the rest is behind the device memory mutex
+unsafe impl Send for VkAllocator {}
+unsafe impl Sync for VkAllocator {}
+
+struct DeviceMemoryAllocator {
+    device: Arc<Device>,
     physical_device: vk::PhysicalDevice,
-    instance: Rc<Instance>,
+    instance: Arc<Instance>,
     device_allocations: HashMap<MemoryTypeIndex, Vec<(vk::DeviceMemory, Vec<MemorySizeRange>)>>,
-    host_allocator: Arc<Mutex<VkHostAllocator>>,
+    // A copy of the VkAllocator's callbacks, which owns the reference in them
+    allocation_callbacks: Option<vk::AllocationCallbacks>,
+    // Shared with the VkAllocator, see VkAllocator::lock_queue
+    queue_lock: Arc<Mutex<()>>,
+    // Where every allocation that hasn't been freed yet is, and what it is for
+    live_allocations: HashMap<AllocationID, (MemoryTypeIndex, vk::DeviceMemory, MemorySizeRange, AllocationKind)>,
+    next_allocation_id: AllocationID,
+    // Memory blocks that hold a single allocation, they are given back to the driver when that allocation is freed
+    dedicated_memories: HashSet<vk::DeviceMemory>,
+    dedicated_allocation_threshold: vk::DeviceSize,
+    // vkGetImageMemoryRequirements2 and dedicated allocations are core in Vulkan 1.1
+    is_dedicated_image_memory_supported: bool,
+    min_uniform_buffer_offset_alignment: vk::DeviceSize,
+    min_storage_buffer_offset_alignment: vk::DeviceSize,
+    // Created by the first upload
+    staging_ring: Option<StagingRing>,
+    staging_stats: StagingStats,
+    // Set when VK_EXT_memory_budget is enabled on the device, otherwise the budget of a heap is its size
+    is_memory_budget_supported: bool,
+    // The bytes of the memory blocks taken from each heap
+    heap_usage: [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+    // The heap and size of every memory block, so the heap usage goes down again when the block is freed
+    memory_blocks: HashMap<vk::DeviceMemory, (u32, vk::DeviceSize)>,
+    // New memory blocks that would take a heap past this fraction of its budget fail, instead of making the driver page memory out
+    memory_budget_fraction: f32,
 }
 
+// The raw pointers are mapped device memory and the host allocation callbacks, which can be used from any thread
+unsafe impl Send for DeviceMemoryAllocator {}
+
+/// Serves the Vulkan host allocation callbacks. The driver may call them from any thread, so the allocator is only ever used through the [`Mutex`] it is created in.
 pub struct VkHostAllocator {
     host_allocations: HashMap<Alignment, Vec<HostAllocationPool>>,
-    allocated_host_pointers: HashMap<*mut c_void, (Alignment, usize)>,
+    // Ordered, so the allocation that a pointer falls into can be found
+    allocated_host_pointers: BTreeMap<*mut c_void, HostAllocation>,
 }
 
-// Device memory allocation
 impl VkAllocator {
-    const DEFAULT_DEVICE_MEMORY_ALLOCATION_BYTE_SIZE: vk::DeviceSize = 256_000_000; // 256 MB 
-
-    pub fn new(instance: Rc<Instance>, physical_device: vk::PhysicalDevice, device: Rc<Device>) -> Self {
-        Self {
+    pub const DEFAULT_DEDICATED_ALLOCATION_THRESHOLD: vk::DeviceSize = 64_000_000; // 64 MB
+    pub const DEFAULT_MEMORY_BUDGET_FRACTION: f32 = 0.9;
+    pub const DEFAULT_STAGING_RING_BYTE_SIZE: vk::DeviceSize = 64_000_000; // 64 MB
+
+    /// With `use_host_allocation_callbacks` the host memory Vulkan allocates for the objects made through the allocator is taken from [`VkHostAllocator`], which keeps track of it.
+    /// Otherwise the driver allocates it itself. `is_memory_budget_supported` is whether `VK_EXT_memory_budget` is enabled on the device.
+    pub fn new(instance: Arc<Instance>, physical_device: vk::PhysicalDevice, device: Arc<Device>, use_host_allocation_callbacks: bool, is_memory_budget_supported: bool) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let api_version = properties.api_version;
+        let is_dedicated_image_memory_supported = vk::api_version_major(api_version) > 1 || vk::api_version_minor(api_version) >= 1;
+        let host_allocator = Arc::new(Mutex::new(VkHostAllocator {
+            host_allocations: HashMap::new(),
+            allocated_host_pointers: BTreeMap::new(),
+        }));
+        let allocation_callbacks = use_host_allocation_callbacks.then(|| vk::AllocationCallbacks {
+            p_user_data: Arc::into_raw(host_allocator.clone()) as *mut c_void,
+            pfn_allocation: Some(pfn_allocation),
+            pfn_reallocation: Some(pfn_reallocation),
+            pfn_free: Some(pfn_free),
+            pfn_internal_allocation: None,
+            pfn_internal_free: None,
+        });
+        let queue_lock = Arc::new(Mutex::new(()));
+        let device_memory = DeviceMemoryAllocator {
             device,
             physical_device,
             instance,
             device_allocations: HashMap::new(),
-            host_allocator: Arc::new(Mutex::new(VkHostAllocator {
-                host_allocations: HashMap::new(),
-                allocated_host_pointers: HashMap::new(),
-            })),
+            allocation_callbacks,
+            queue_lock: queue_lock.clone(),
+            live_allocations: HashMap::new(),
+            next_allocation_id: 0,
+            dedicated_memories: HashSet::new(),
+            dedicated_allocation_threshold: Self::DEFAULT_DEDICATED_ALLOCATION_THRESHOLD,
+            is_dedicated_image_memory_supported,
+            min_uniform_buffer_offset_alignment: properties.limits.min_uniform_buffer_offset_alignment,
+            min_storage_buffer_offset_alignment: properties.limits.min_storage_buffer_offset_alignment,
+            staging_ring: None,
+            staging_stats: StagingStats {
+                ring_size: Self::DEFAULT_STAGING_RING_BYTE_SIZE,
+                ..Default::default()
+            },
+            is_memory_budget_supported,
+            heap_usage: [0; vk::MAX_MEMORY_HEAPS],
+            memory_blocks: HashMap::new(),
+            memory_budget_fraction: Self::DEFAULT_MEMORY_BUDGET_FRACTION,
+        };
+        Self {
+            device_memory: Mutex::new(device_memory),
+            queue_lock,
+            host_allocator,
+            allocation_callbacks,
         }
     }
 
-    pub fn create_uniform_buffers(&mut self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, Cow<'static, str>> {
-        let total_buffer_size = (buffer_size * num_buffers) as u64;
+    // A panic while the lock was held can only have left the bookkeeping of a single call unfinished, so the allocator stays usable
+    fn lock_device_memory(&self) -> MutexGuard<'_, DeviceMemoryAllocator> {
+        self.device_memory.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        // let mut uniform_buffers = Vec::with_capacity(num_buffers);
-        
-        let mut allocation_info = self.create_buffer(total_buffer_size, vk::BufferUsageFlags::UNIFORM_BUFFER, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, true)?; //Self::create_buffer(instance, physical_device, device, buffer_size as u64, vk::BufferUsageFlags::UNIFORM_BUFFER, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, allocator);
-        // println!("Device: {:?}, memory start (inclusive): {}, memory end (exclusive): {}, type: {}", allocation_info.memory, allocation_info.memory_start, allocation_info.memory_end, allocation_info.memory_index);
-        let data_ptr = unsafe {
-            self.device.map_memory(allocation_info.get_memory(), allocation_info.get_memory_start(), total_buffer_size, vk::MemoryMapFlags::empty()).unwrap()
+    /// Held by the allocator around every submit to the queues it is given. Submits, presents and waits on those queues from outside the allocator have to hold it too, since Vulkan needs them to be externally synchronized.
+    pub fn lock_queue(&self) -> MutexGuard<'_, ()> {
+        self.queue_lock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Allocations bigger than this get their own memory block, like with `force_own_memory_block`, so they don't fragment the shared blocks and their memory is released when they are freed.
+    pub fn set_dedicated_allocation_threshold(&self, threshold: vk::DeviceSize) {
+        self.lock_device_memory().set_dedicated_allocation_threshold(threshold);
+    }
+
+    pub fn get_dedicated_allocation_threshold(&self) -> vk::DeviceSize {
+        self.lock_device_memory().get_dedicated_allocation_threshold()
+    }
+
+    /// Clamped to 0..=1.
+    pub fn set_memory_budget_fraction(&self, fraction: f32) {
+        self.lock_device_memory().set_memory_budget_fraction(fraction);
+    }
+
+    pub fn get_memory_budget_fraction(&self) -> f32 {
+        self.lock_device_memory().get_memory_budget_fraction()
+    }
+
+    /// The usage and budget of every heap. With `VK_EXT_memory_budget` they come from the driver and include what the rest of the process uses,
+    /// otherwise the budget is the size of the heap and the usage is only the allocator's own memory blocks.
+    pub fn get_memory_budget(&self) -> Vec<HeapBudget> {
+        self.lock_device_memory().get_memory_budget()
+    }
+
+    pub fn create_uniform_buffers(&self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_uniform_buffers(buffer_size, num_buffers)
+    }
+
+    pub fn create_storage_buffers(&self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_storage_buffers(buffer_size, num_buffers)
+    }
+
+    pub fn create_vertex_buffers(&self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_vertex_buffers(buffer_size, num_buffers)
+    }
+
+    pub fn create_index_buffers(&self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_index_buffers(buffer_size, num_buffers)
+    }
+
+    pub fn create_buffer(&self, size: vk::DeviceSize, usage: vk::BufferUsageFlags, properties: vk::MemoryPropertyFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_buffer(size, usage, properties, force_own_memory_block)
+    }
+
+    /// The data goes through the staging ring, unless it is bigger than the ring. Then it gets its own staging buffer.
+    pub fn create_device_local_buffer(&self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, data: &[u8], buffer_usage: vk::BufferUsageFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_device_local_buffer(command_pool, graphics_queue, data, buffer_usage, force_own_memory_block)
+    }
+
+    /// Cube maps need 6 array layers and [`vk::ImageCreateFlags::CUBE_COMPATIBLE`].
+    pub fn create_image(&self, width: u32, height: u32, mip_levels: u32, array_layers: u32, flags: vk::ImageCreateFlags, num_samples: vk::SampleCountFlags, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags, properties: vk::MemoryPropertyFlags) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_image(width, height, mip_levels, array_layers, flags, num_samples, format, tiling, usage, properties)
+    }
+
+    /// The texels go through the staging ring like [`VkAllocator::create_device_local_buffer`].
+    pub fn create_device_local_image(&self, image: DynamicImage, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, max_mip_levels: u32, num_samples: vk::SampleCountFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_device_local_image(image, command_pool, graphics_queue, max_mip_levels, num_samples, force_own_memory_block)
+    }
+
+    /// The faces are in the order of the array layers, which is +X, -X, +Y, -Y, +Z and -Z. They all have to be square and the same size.
+    pub fn create_device_local_cube_image(&self, faces: [DynamicImage; 6], command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, max_mip_levels: u32, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        self.lock_device_memory().create_device_local_cube_image(faces, command_pool, graphics_queue, max_mip_levels, force_own_memory_block)
+    }
+
+    /// The view covers all the array layers of the image, so a cube map needs [`vk::ImageViewType::CUBE`].
+    pub fn create_image_view(&self, allocation_info: &mut AllocationInfo, format: vk::Format, aspect_flags: vk::ImageAspectFlags, mip_levels: u32, view_type: vk::ImageViewType) -> Result<(), EngineError> {
+        self.lock_device_memory().create_image_view(allocation_info, format, aspect_flags, mip_levels, view_type)
+    }
+
+    /// Moves a newly created color attachment to the layout it has after a render pass, so that the first render pass that loads it gets the layout it expects.
+    pub fn initialize_color_attachment_layout(&self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, allocation_info: &AllocationInfo) -> Result<(), EngineError> {
+        self.lock_device_memory().initialize_color_attachment_layout(command_pool, graphics_queue, allocation_info)
+    }
+
+    /// Copies the rectangle of the first mip level of the image with the aspect into host memory and returns the texels tightly packed. The image is moved back to `layout` afterwards.
+    pub fn copy_image_to_host(&self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, allocation_info: &AllocationInfo, layout: vk::ImageLayout, aspect_mask: vk::ImageAspectFlags, rect: vk::Rect2D, bytes_per_texel: u32) -> Result<Vec<u8>, EngineError> {
+        self.lock_device_memory().copy_image_to_host(command_pool, graphics_queue, allocation_info, layout, aspect_mask, rect, bytes_per_texel)
+    }
+
+    /// Fails without destroying anything when the allocation has already been freed, for example through a clone, or doesn't match the memory it was allocated from.
+    pub fn free_memory_allocation(&self, allocation_info: AllocationInfo) -> Result<(), EngineError> {
+        self.lock_device_memory().free_memory_allocation(allocation_info)
+    }
+
+    /// Only called when the device is idle, which also means no other thread may be using the allocator.
+    pub fn free_all_allocations(&self) -> Result<(), EngineError> {
+        self.lock_device_memory().free_all_allocations();
+        let mut host_allocator = match self.host_allocator.lock() {
+            Ok(allocator) => allocator,
+            Err(err) => return Err(EngineError::from(format!("Failed to lock host allocator when freeing all allocations because: {}", err))),
         };
-        for i in 0..num_buffers {
-            let offset = match (i*buffer_size).try_into() {
-                Ok(offset) => offset,
-                Err(err) => return Err(Cow::from(format!("Failed to create uniform buffers because: {}", err))),
+        unsafe {
+            host_allocator.free_all_host_memory()?;
+        }
+        Ok(())
+    }
+
+    /// The number and bytes of the live allocations of each kind.
+    pub fn get_allocation_stats(&self) -> BTreeMap<AllocationKind, AllocationKindStats> {
+        self.lock_device_memory().get_allocation_stats()
+    }
+
+    /// The kind, size and memory type of every allocation that hasn't been freed, ordered by when they were made. Meant for finding leaks.
+    pub fn dump_live_allocations(&self) -> Vec<(AllocationKind, vk::DeviceSize, MemoryTypeIndex)> {
+        self.lock_device_memory().dump_live_allocations()
+    }
+
+    /// Moves the given buffers out of the shared memory blocks they are in and releases the blocks that become empty, so the free space is in fewer and larger ranges.
+    /// Only device local buffers that aren't mapped and can be copied, which are the vertex and index buffers, are moved. Images and the buffers descriptor sets point at stay where they are,
+    /// and are counted in the report's unmoved allocations. and only out of blocks where every allocation is moved, since the rest have to stay where they are.
+    /// The moved allocations are updated in place and get new buffer handles, so nothing may use the old handles, which means the device has to be idle.
+    pub fn defragment(&self, allocations: &mut [&mut AllocationInfo], command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<DefragmentationReport, EngineError> {
+        self.lock_device_memory().defragment(allocations, command_pool, graphics_queue)
+    }
+
+    /// Only takes effect before the first upload, which creates the ring.
+    pub fn set_staging_ring_size(&self, size: vk::DeviceSize) {
+        self.lock_device_memory().set_staging_ring_size(size);
+    }
+
+    pub fn get_staging_stats(&self) -> StagingStats {
+        self.lock_device_memory().get_staging_stats()
+    }
+
+    pub fn get_upload_marker(&self) -> UploadMarker {
+        self.lock_device_memory().get_upload_marker()
+    }
+
+    /// True when the GPU has finished every staging copy that was submitted before the marker was taken. The uploads that don't go through the ring are waited for when they are made.
+    pub fn is_upload_finished(&self, marker: UploadMarker) -> Result<bool, EngineError> {
+        self.lock_device_memory().is_upload_finished(marker)
+    }
+
+    /// What to give Vulkan when creating and destroying objects. None unless the host allocation callbacks were turned on with [`VkAllocator::new`].
+    pub fn get_allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.allocation_callbacks.as_ref()
+    }
+}
+
+impl Drop for VkAllocator {
+    fn drop(&mut self) {
+        // Gives back the reference the callbacks' user data was made from
+        if let Some(allocation_callbacks) = self.allocation_callbacks.take() {
+            unsafe {
+                drop(Arc::from_raw(allocation_callbacks.p_user_data as *const Mutex<VkHostAllocator>));
+            }
+        }
+    }
+}
+
+// Device memory allocation
+impl DeviceMemoryAllocator {
+    const DEFAULT_DEVICE_MEMORY_ALLOCATION_BYTE_SIZE: vk::DeviceSize = 256_000_000; // 256 MB 
+
+    fn set_dedicated_allocation_threshold(&mut self, threshold: vk::DeviceSize) {
+        self.dedicated_allocation_threshold = threshold;
+    }
+
+    fn set_memory_budget_fraction(&mut self, fraction: f32) {
+        self.memory_budget_fraction = fraction.clamp(0.0, 1.0);
+    }
+
+    fn get_memory_budget_fraction(&self) -> f32 {
+        self.memory_budget_fraction
+    }
+
+    fn get_memory_budget(&self) -> Vec<HeapBudget> {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT {
+            s_type: StructureType::PHYSICAL_DEVICE_MEMORY_BUDGET_PROPERTIES_EXT,
+            ..Default::default()
+        };
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties2 {
+            s_type: StructureType::PHYSICAL_DEVICE_MEMORY_PROPERTIES_2,
+            p_next: if self.is_memory_budget_supported { &mut budget_properties as *mut vk::PhysicalDeviceMemoryBudgetPropertiesEXT as *mut c_void } else { std::ptr::null_mut() },
+            ..Default::default()
+        };
+        unsafe {
+            self.instance.get_physical_device_memory_properties2(self.physical_device, &mut memory_properties);
+        }
+
+        let memory_properties = memory_properties.memory_properties;
+        memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter().enumerate().map(|(i, heap)| {
+            // The driver's usage can lag behind the blocks that were just allocated
+            let (usage, budget) = if self.is_memory_budget_supported {
+                (budget_properties.heap_usage[i].max(self.heap_usage[i]), budget_properties.heap_budget[i])
+            } else {
+                (self.heap_usage[i], heap.size)
             };
-            // println!("Total size: {}, single size: {}, offset: {}, num_buffer: {}", total_buffer_size, buffer_size, offset, num_buffers);
-            allocation_info.uniform_pointers.push(unsafe {data_ptr.offset(offset)});
+            HeapBudget {
+                heap_index: i as u32,
+                is_device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                engine_usage: self.heap_usage[i],
+                usage,
+                budget,
+            }
+        }).collect()
+    }
+
+    fn get_heap_index(&self, memory_type_index: MemoryTypeIndex) -> u32 {
+        let memory_properties = unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) };
+        memory_properties.memory_types[memory_type_index as usize].heap_index
+    }
+
+    // Lowers the usage of the block's heap. The memory itself is freed by the caller
+    fn untrack_memory_block(&mut self, memory: vk::DeviceMemory) {
+        if let Some((heap_index, size)) = self.memory_blocks.remove(&memory) {
+            self.heap_usage[heap_index as usize] -= size;
         }
+    }
 
-        Ok(allocation_info)
+    fn get_dedicated_allocation_threshold(&self) -> vk::DeviceSize {
+        self.dedicated_allocation_threshold
     }
 
-    pub fn create_storage_buffers(&mut self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, Cow<'static, str>> {
-        let total_buffer_size = (buffer_size * num_buffers) as u64;
+    fn create_uniform_buffers(&mut self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.create_mapped_buffers(buffer_size, num_buffers, vk::BufferUsageFlags::UNIFORM_BUFFER, self.min_uniform_buffer_offset_alignment)
+    }
 
-        // let mut uniform_buffers = Vec::with_capacity(num_buffers);
-        
-        let mut allocation_info = self.create_buffer(total_buffer_size, vk::BufferUsageFlags::STORAGE_BUFFER, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, true)?; //Self::create_buffer(instance, physical_device, device, buffer_size as u64, vk::BufferUsageFlags::UNIFORM_BUFFER, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, allocator);
-        // println!("Device: {:?}, memory start (inclusive): {}, memory end (exclusive): {}, type: {}", allocation_info.memory, allocation_info.memory_start, allocation_info.memory_end, allocation_info.memory_index);
+    fn create_storage_buffers(&mut self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.create_mapped_buffers(buffer_size, num_buffers, vk::BufferUsageFlags::STORAGE_BUFFER, self.min_storage_buffer_offset_alignment)
+    }
+
+    // Vertex attributes only need their components aligned, so the parts only have to start at a multiple of 4
+    fn create_vertex_buffers(&mut self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.create_mapped_buffers(buffer_size, num_buffers, vk::BufferUsageFlags::VERTEX_BUFFER, 4)
+    }
+
+    // The parts are bound with an offset that has to be a multiple of the size of a u32 index
+    fn create_index_buffers(&mut self, buffer_size: usize, num_buffers: usize) -> Result<AllocationInfo, EngineError> {
+        self.create_mapped_buffers(buffer_size, num_buffers, vk::BufferUsageFlags::INDEX_BUFFER, 4)
+    }
+
+    // One buffer split into `num_buffers` parts that each start at a multiple of `offset_alignment`, so they can be bound with descriptor offsets
+    fn create_mapped_buffers(&mut self, buffer_size: usize, num_buffers: usize, usage: vk::BufferUsageFlags, offset_alignment: vk::DeviceSize) -> Result<AllocationInfo, EngineError> {
+        let stride = (buffer_size as vk::DeviceSize).next_multiple_of(offset_alignment.max(1));
+        let total_buffer_size = stride * (num_buffers.max(1) - 1) as vk::DeviceSize + buffer_size as vk::DeviceSize;
+
+        let mut allocation_info = self.create_buffer(total_buffer_size, usage, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, true)?;
         let data_ptr = unsafe {
             self.device.map_memory(allocation_info.get_memory(), allocation_info.get_memory_start(), total_buffer_size, vk::MemoryMapFlags::empty()).unwrap()
         };
         for i in 0..num_buffers {
-            let offset = match (i*buffer_size).try_into() {
+            let offset = match (i as vk::DeviceSize * stride).try_into() {
                 Ok(offset) => offset,
-                Err(err) => return Err(Cow::from(format!("Failed to create uniform buffers because: {}", err))),
+                Err(err) => return Err(EngineError::from(format!("Failed to create mapped buffers because: {}", err))),
             };
-            // println!("Total size: {}, single size: {}, offset: {}, num_buffer: {}", total_buffer_size, buffer_size, offset, num_buffers);
-            allocation_info.uniform_pointers.push(unsafe {data_ptr.offset(offset)});
+            allocation_info.uniform_pointers.push(unsafe { data_ptr.cast::<u8>().add(offset).cast() });
         }
+        allocation_info.uniform_buffer_size = buffer_size as vk::DeviceSize;
 
         Ok(allocation_info)
     }
 
-    pub fn create_buffer(&mut self, size: vk::DeviceSize, usage: vk::BufferUsageFlags, properties: vk::MemoryPropertyFlags, force_own_memory_block: bool) -> Result<AllocationInfo, Cow<'static, str>> {
+    fn create_buffer(&mut self, size: vk::DeviceSize, usage: vk::BufferUsageFlags, properties: vk::MemoryPropertyFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
         let buffer_info = vk::BufferCreateInfo {
             s_type: StructureType::BUFFER_CREATE_INFO,
             size,
@@ -117,9 +467,9 @@ impl VkAllocator {
         };
 
         let buffer = unsafe {
-            match self.device.create_buffer(&buffer_info, Some(&self.get_allocation_callbacks())) {
+            match self.device.create_buffer(&buffer_info, self.get_allocation_callbacks()) {
                 Ok(buffer) => buffer,
-                Err(err) => return Err(Cow::from(format!("Failed to create buffer when creating buffer because: {}", err))),
+                Err(err) => return Err(EngineError::vulkan("Failed to create buffer when creating buffer", err)),
             }
         };
 
@@ -135,13 +485,15 @@ impl VkAllocator {
         };
 
         let mut allocation_info = self.get_allocation(alloc_info.memory_type_index, alloc_info.allocation_size, memory_requirements.alignment, force_own_memory_block)?;
+        allocation_info.buffer_size_and_usage = Some((size, usage.as_raw()));
+        self.set_allocation_kind(&mut allocation_info, AllocationKind::of_buffer(usage));
 
         unsafe {
             match self.device.bind_buffer_memory(buffer, allocation_info.memory, allocation_info.memory_start) {
                 Ok(_) => {},
                 Err(err) => {
                     self.free_memory_allocation(allocation_info)?;
-                    return Err(Cow::from(format!("Failed to bind buffer memory when creating buffer because: {}", err)));
+                    return Err(EngineError::vulkan("Failed to bind buffer memory when creating buffer", err));
                 },
             };
         }
@@ -151,42 +503,76 @@ impl VkAllocator {
         Ok(allocation_info)
     }
 
-    pub fn create_device_local_buffer(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, data: &[u8], buffer_usage: vk::BufferUsageFlags, force_own_memory_block: bool) -> Result<AllocationInfo, Cow<'static, str>> {
-        // let data_vec = Self::serializable_vec_to_u8_vec(to_serialize);
-        // let data = data_vec.as_slice();
-
+    fn create_device_local_buffer(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, data: &[u8], buffer_usage: vk::BufferUsageFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
         let size = std::mem::size_of_val(data);
 
-        let staging_allocation = self.create_buffer(size as u64, vk::BufferUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, force_own_memory_block)?;
-        
+        // TRANSFER_SRC lets defragment copy the buffer
+        let device_local_allocation = self.create_buffer(size as u64, buffer_usage | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST, vk::MemoryPropertyFlags::DEVICE_LOCAL, force_own_memory_block)?;
+
+        if let Err(err) = self.upload_to_buffer(command_pool, graphics_queue, data, &device_local_allocation, force_own_memory_block) {
+            self.free_memory_allocation(device_local_allocation)?;
+            return Err(err);
+        }
+
+        Ok(device_local_allocation)
+    }
+
+    fn upload_to_buffer(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, data: &[u8], dst_allocation: &AllocationInfo, force_own_memory_block: bool) -> Result<(), EngineError> {
+        let Some((staging_buffer, staging_offset)) = self.write_to_staging_ring(data)? else {
+            let staging_allocation = self.create_staging_buffer(data, force_own_memory_block)?;
+            let result = self.copy_buffer(&staging_allocation, dst_allocation, command_pool, graphics_queue);
+            self.free_memory_allocation(staging_allocation)?;
+            return result;
+        };
+
+        let Some(dst_buffer) = dst_allocation.buffer else {
+            return Err(EngineError::from("Failed to upload to buffer because the dst buffer was None!"));
+        };
+
+        let command_buffer = self.begin_single_time_command(command_pool)?;
+
+        let copy_region = vk::BufferCopy {
+            src_offset: staging_offset,
+            dst_offset: 0,
+            size: data.len() as vk::DeviceSize,
+        };
+        // The copy isn't waited for, so the barrier makes the data visible to everything that is submitted after it
+        let barrier = vk::MemoryBarrier {
+            s_type: StructureType::MEMORY_BARRIER,
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ | vk::AccessFlags::UNIFORM_READ | vk::AccessFlags::SHADER_READ,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.device.cmd_copy_buffer(command_buffer, staging_buffer, dst_buffer, &[copy_region]);
+            self.device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER, DependencyFlags::empty(), &[barrier], &[], &[]);
+        }
+
+        self.end_staging_command(command_pool, graphics_queue, command_buffer, staging_offset, staging_offset + data.len() as vk::DeviceSize)
+    }
+
+    // For uploads that don't fit in the staging ring
+    fn create_staging_buffer(&mut self, data: &[u8], force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        let size = data.len() as vk::DeviceSize;
+        let staging_allocation = self.create_buffer(size, vk::BufferUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, force_own_memory_block)?;
+
         unsafe {
-            let mapped_memory_ptr = match self.device.map_memory(staging_allocation.memory, staging_allocation.memory_start, size as u64, vk::MemoryMapFlags::empty()) {
+            let mapped_memory_ptr = match self.device.map_memory(staging_allocation.memory, staging_allocation.memory_start, size, vk::MemoryMapFlags::empty()) {
                 Ok(ptr) => ptr as *mut u8,
                 Err(err) => {
                     self.free_memory_allocation(staging_allocation)?;
-                    return Err(Cow::from(format!("Failed to map memory when creating device local buffer because: {}", err)));
+                    return Err(EngineError::vulkan("Failed to map memory when creating staging buffer", err));
                 },
             };
-            let data_ptr = data.as_ptr();
-            std::ptr::copy_nonoverlapping(data_ptr, mapped_memory_ptr, size);
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped_memory_ptr, data.len());
             self.device.unmap_memory(staging_allocation.memory);
         }
-        
-        let device_local_allocation = self.create_buffer(size as u64, buffer_usage | vk::BufferUsageFlags::TRANSFER_DST, vk::MemoryPropertyFlags::DEVICE_LOCAL, force_own_memory_block)?;
-        
-        self.copy_buffer(&staging_allocation, &device_local_allocation, command_pool, graphics_queue)?;
-
-        if self.free_memory_allocation(staging_allocation).is_err() {
-            if self.free_memory_allocation(device_local_allocation).is_err() {
-                return Err(Cow::from("Failed to free device local buffer allocation after freeing staging buffer allocation failed!"));
-            }
-            return Err(Cow::from("Failed to free staging buffer allocation!"));
-        }
 
-        Ok(device_local_allocation)
+        Ok(staging_allocation)
     }
 
-    pub fn create_image(&mut self, width: u32, height: u32, mip_levels: u32, num_samples: vk::SampleCountFlags, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags, properties: vk::MemoryPropertyFlags) -> Result<AllocationInfo, Cow<'static, str>> {
+    fn create_image(&mut self, width: u32, height: u32, mip_levels: u32, array_layers: u32, flags: vk::ImageCreateFlags, num_samples: vk::SampleCountFlags, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags, properties: vk::MemoryPropertyFlags) -> Result<AllocationInfo, EngineError> {
         let image_info = vk::ImageCreateInfo {
             s_type: StructureType::IMAGE_CREATE_INFO,
             image_type: vk::ImageType::TYPE_2D,
@@ -196,38 +582,44 @@ impl VkAllocator {
                 depth: 1,
             },
             mip_levels,
-            array_layers: 1,
+            array_layers,
             format,
             tiling,
             initial_layout: vk::ImageLayout::UNDEFINED,
             usage,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             samples: num_samples,
-            flags: vk::ImageCreateFlags::empty(),
+            flags,
             ..Default::default()
         };
 
         let image = unsafe {
-            match self.device.create_image(&image_info, Some(&self.get_allocation_callbacks())) {
+            match self.device.create_image(&image_info, self.get_allocation_callbacks()) {
                 Ok(image) => image,
-                Err(err) => return Err(Cow::from(format!("Failed to create image when creating image because: {}", err))),
+                Err(err) => return Err(EngineError::vulkan("Failed to create image when creating image", err)),
             }
         };
 
-        let mem_requirements = unsafe {
-            self.device.get_image_memory_requirements(image)
-        };
+        let (mem_requirements, is_dedicated_preferred) = self.get_image_memory_requirements(image);
+        let memory_type_index = self.find_memory_type(mem_requirements.memory_type_bits, properties)?;
 
-        let mut image_allocation = self.get_allocation(self.find_memory_type(mem_requirements.memory_type_bits, properties)?, mem_requirements.size, mem_requirements.alignment, false)?;
+        // Some drivers, mostly on tiled GPUs, are faster when the image has memory of its own
+        let mut image_allocation = if is_dedicated_preferred {
+            self.create_own_device_memory_block(memory_type_index, mem_requirements.size, Some(image))?
+        } else {
+            self.get_allocation(memory_type_index, mem_requirements.size, mem_requirements.alignment, false)?
+        };
 
         image_allocation.image = Some(image);
+        image_allocation.array_layers = array_layers;
+        self.set_allocation_kind(&mut image_allocation, AllocationKind::of_image(usage));
 
         unsafe {
             match self.device.bind_image_memory(image, image_allocation.memory, image_allocation.memory_start) {
                 Ok(_) => {},
                 Err(err) => {
                     self.free_memory_allocation(image_allocation)?;
-                    return Err(Cow::from(format!("Failed to bind image memory when creating image because: {}", err)));
+                    return Err(EngineError::vulkan("Failed to bind image memory when creating image", err));
                 },
             };
         }
@@ -235,80 +627,73 @@ impl VkAllocator {
         Ok(image_allocation)
     }    
 
-    pub fn create_device_local_image(&mut self, image: DynamicImage, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, max_mip_levels: u32, num_samples: vk::SampleCountFlags, force_own_memory_block: bool) -> Result<AllocationInfo, Cow<'static, str>> {
-        // let binding = image::open("./assets/images/viking_room.png").unwrap();
+    fn create_device_local_image(&mut self, image: DynamicImage, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, max_mip_levels: u32, num_samples: vk::SampleCountFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
         let image = image.to_rgba8();
-        let image_size: vk::DeviceSize = image.dimensions().0 as vk::DeviceSize * image.dimensions().1 as vk::DeviceSize * 4 as vk::DeviceSize;
-        
-        let mip_levels = (((image.dimensions().0 as f32).max(image.dimensions().1 as f32).log2().floor() + 1.0) as u32).min(max_mip_levels);
+        self.create_device_local_image_with_layers(image.as_raw(), image.width(), image.height(), 1, vk::ImageCreateFlags::empty(), command_pool, graphics_queue, max_mip_levels, num_samples, force_own_memory_block)
+    }
 
-        let staging_allocation = self.create_buffer(image_size, vk::BufferUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, force_own_memory_block)?;
-        // println!("Memory start (including): {}, memory end (excluding): {}, index: {}, device memory: {:?}, force_own_memory_block: {}, img: {:?}", staging_allocation.memory_start, staging_allocation.memory_end, staging_allocation.memory_index, staging_allocation.memory, force_own_memory_block, image.get_pixel(0, 0));
-        unsafe {
-            let data_ptr = match self.device.map_memory(staging_allocation.memory, staging_allocation.memory_start, image_size, vk::MemoryMapFlags::empty()) {
-                Ok(ptr) => ptr as *mut u8,
-                Err(err) => {
-                    self.free_memory_allocation(staging_allocation)?;
-                    return Err(Cow::from(format!("Failed to map memory when creating device local image because: {}", err)));
-                },
-            };
-            std::ptr::copy_nonoverlapping(image.as_ptr(), data_ptr, image_size as usize);
-            self.device.unmap_memory(staging_allocation.memory);
-        };
+    fn create_device_local_cube_image(&mut self, faces: [DynamicImage; 6], command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, max_mip_levels: u32, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        let size = faces[0].width();
+        if let Some(face) = faces.iter().find(|face| face.width() != size || face.height() != size) {
+            return Err(EngineError::from(format!("The cube map faces have to be square and the same size, but a face was {}x{} and the first face is {}x{}", face.width(), face.height(), faces[0].width(), faces[0].height())));
+        }
+        let data = faces.iter().flat_map(|face| face.to_rgba8().into_raw()).collect::<Vec<u8>>();
+        self.create_device_local_image_with_layers(&data, size, size, 6, vk::ImageCreateFlags::CUBE_COMPATIBLE, command_pool, graphics_queue, max_mip_levels, vk::SampleCountFlags::TYPE_1, force_own_memory_block)
+    }
 
-        let mut image_allocation = self.create_image( image.dimensions().0, image.dimensions().1, mip_levels, num_samples, vk::Format::R8G8B8A8_SRGB, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+    // The data is RGBA8 and holds the layers one after another
+    fn create_device_local_image_with_layers(&mut self, data: &[u8], width: u32, height: u32, array_layers: u32, flags: vk::ImageCreateFlags, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, max_mip_levels: u32, num_samples: vk::SampleCountFlags, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        let mip_levels = (((width as f32).max(height as f32).log2().floor() + 1.0) as u32).min(max_mip_levels);
 
-        match self.transition_image_layout(command_pool, graphics_queue, &image_allocation.image.unwrap(), vk::Format::R8G8B8A8_SRGB, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, mip_levels) {
+        let mut image_allocation = self.create_image(width, height, mip_levels, array_layers, flags, num_samples, vk::Format::R8G8B8A8_SRGB, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+        match self.transition_image_layout(command_pool, graphics_queue, &image_allocation.image.unwrap(), vk::Format::R8G8B8A8_SRGB, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, mip_levels, array_layers) {
             Ok(_) => {},
             Err(err) => {
-                self.free_memory_allocation(staging_allocation)?;
                 self.free_memory_allocation(image_allocation)?;
-                return Err(Cow::from(format!("Failed to transition image layout when creating device local image because: {}", err)));
+                return Err(err);
             },
         };
-        match self.copy_buffer_to_image(&staging_allocation.buffer.unwrap(), &image_allocation.image.unwrap(), image.dimensions().0, image.dimensions().1, command_pool, graphics_queue) {
+        match self.upload_to_image(command_pool, graphics_queue, data, &image_allocation.image.unwrap(), width, height, array_layers, force_own_memory_block) {
             Ok(_) => {},
             Err(err) => {
-                self.free_memory_allocation(staging_allocation)?;
                 self.free_memory_allocation(image_allocation)?;
-                return Err(Cow::from(format!("Failed to copy buffer to image when creating device local image because: {}", err)));
+                return Err(err);
             },
         };
         
-        self.free_memory_allocation(staging_allocation)?;
-        
-        self.generate_mipmaps(command_pool, graphics_queue, &image_allocation.image.unwrap(), vk::Format::R8G8B8A8_SRGB, image.dimensions().0, image.dimensions().1, mip_levels)?;
+        self.generate_mipmaps(command_pool, graphics_queue, &image_allocation.image.unwrap(), vk::Format::R8G8B8A8_SRGB, width, height, mip_levels, array_layers)?;
         
         image_allocation.mip_levels = Some(mip_levels);
 
         Ok(image_allocation)
     }
 
-    pub fn create_image_view(&mut self, allocation_info: &mut AllocationInfo, format: vk::Format, aspect_flags: vk::ImageAspectFlags, mip_levels: u32) -> Result<(), Cow<'static, str>> {
+    fn create_image_view(&mut self, allocation_info: &mut AllocationInfo, format: vk::Format, aspect_flags: vk::ImageAspectFlags, mip_levels: u32, view_type: vk::ImageViewType) -> Result<(), EngineError> {
         let image = match allocation_info.image {
             Some(image) => image,
-            None => return Err(Cow::from("Failed to create image view because the image was None!")),
+            None => return Err(EngineError::from("Failed to create image view because the image was None!")),
         };
         
         let view_info = vk::ImageViewCreateInfo {
             s_type: StructureType::IMAGE_VIEW_CREATE_INFO,
             image,
-            view_type: vk::ImageViewType::TYPE_2D,
+            view_type,
             format,
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: aspect_flags,
                 base_mip_level: 0,
                 level_count: mip_levels,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: allocation_info.array_layers,
             },
             ..Default::default()
         };
 
         let image_view = unsafe {
-            match self.device.create_image_view(&view_info, Some(&self.get_allocation_callbacks())) {
+            match self.device.create_image_view(&view_info, self.get_allocation_callbacks()) {
                 Ok(image_view) => image_view,
-                Err(err) => return Err(Cow::from(format!("Failed to create image view when creating image view because: {}", err))),
+                Err(err) => return Err(EngineError::vulkan("Failed to create image view when creating image view", err)),
             }
         };
 
@@ -317,30 +702,27 @@ impl VkAllocator {
         Ok(())
     }
 
-    pub fn free_all_allocations(&mut self) -> Result<(), Cow<'static, str>> {
+    fn free_all_allocations(&mut self) {
+        self.destroy_staging_ring();
         for (_, allocations) in self.device_allocations.iter() {
             for (memory, _) in allocations.iter() {
                 unsafe {
-                    self.device.free_memory(*memory, Some(&self.get_allocation_callbacks()));
+                    self.device.free_memory(*memory, self.get_allocation_callbacks());
                 }
             }
         }
         self.device_allocations.clear();
-        unsafe { 
-            let mut allocator = match self.host_allocator.lock() {
-                Ok(allocator) => allocator,
-                Err(err) => return Err(Cow::from(format!("Failed to lock host allocator when freeing all allocations because: {}", err))),
-            };
-            allocator.free_all_host_memory()?; 
-        }
-        Ok(())
+        self.dedicated_memories.clear();
+        self.live_allocations.clear();
+        self.memory_blocks.clear();
+        self.heap_usage = [0; vk::MAX_MEMORY_HEAPS];
     }
 
     fn slice_of_serializable_to_u8<T: Serializable>(vec: &[T]) -> Vec<u8> {
         vec.iter().map(|item| item.to_u8()).flatten().collect()
     }
 
-    fn generate_mipmaps(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, image: &vk::Image, image_format: vk::Format, width: u32, height: u32, mip_levels: u32) -> Result<(), Cow<'static, str>> {
+    fn generate_mipmaps(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, image: &vk::Image, image_format: vk::Format, width: u32, height: u32, mip_levels: u32, array_layers: u32) -> Result<(), EngineError> {
         let format_properties = unsafe {
             self.instance.get_physical_device_format_properties(self.physical_device, image_format)
         };
@@ -359,7 +741,7 @@ impl VkAllocator {
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: array_layers,
                 level_count: 1,
                 ..Default::default()
             },
@@ -397,7 +779,7 @@ impl VkAllocator {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: i - 1,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count: array_layers,
                 },
                 dst_offsets: [
                     vk::Offset3D {
@@ -415,7 +797,7 @@ impl VkAllocator {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: i,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count: array_layers,
                 },
             };
 
@@ -452,13 +834,13 @@ impl VkAllocator {
 
         match self.end_single_time_command(command_pool, graphics_queue, command_buffer) {
             Ok(_) => {},
-            Err(err) => return Err(Cow::from(format!("Failed to end single time command when generating mipmaps because: {}", err))),
+            Err(err) => return Err(err),
         
         };
         Ok(())
     }
 
-    fn transition_image_layout(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, image: &vk::Image, format: vk::Format, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, mip_levels: u32) -> Result<(), Cow<'static, str>> {
+    fn transition_image_layout(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, image: &vk::Image, format: vk::Format, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, mip_levels: u32, array_layers: u32) -> Result<(), EngineError> {
         let command_buffer = self.begin_single_time_command(command_pool)?;
 
         let mut barrier = vk::ImageMemoryBarrier {
@@ -473,7 +855,7 @@ impl VkAllocator {
                 base_mip_level: 0,
                 level_count: mip_levels,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: array_layers,
             },
             ..Default::default()
         };
@@ -489,6 +871,11 @@ impl VkAllocator {
                 barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
                 (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER)
             },
+            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => {
+                barrier.src_access_mask = vk::AccessFlags::empty();
+                barrier.dst_access_mask = vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+                (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            },
             _ => panic!("Unsupported layout transition! {} {}", old_layout.as_raw(), new_layout.as_raw()),
         };
 
@@ -498,115 +885,247 @@ impl VkAllocator {
 
         match self.end_single_time_command(command_pool, graphics_queue, command_buffer) {
             Ok(_) => {},
-            Err(err) => return Err(Cow::from(format!("Failed to end single time command when transitioning image layout because: {}", err))),
+            Err(err) => return Err(err),
         
         };
         Ok(())
     }
 
-    pub fn free_memory_allocation(&mut self, allocation_info: AllocationInfo) -> Result<(), Cow<'static, str>> {
-        if let Some(memories) = self.device_allocations.get_mut(&allocation_info.memory_index) {
-            for (memory, free_ranges) in memories.iter_mut() {
-                if *memory != allocation_info.memory {
-                    continue;
-                }
-                free_ranges.push((allocation_info.memory_start, allocation_info.memory_end));
-                
-                free_ranges.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-
-                let mut i = 0;
-                while i < free_ranges.len() - 1 {
-                    if free_ranges[i + 1].0 > 0 && free_ranges[i].1 == free_ranges[i + 1].0 - 1 {
-                        free_ranges[i].1 = free_ranges[i + 1].1;
-                        free_ranges.remove(i + 1);
-                    }
-                    i += 1;
-                }
-            }
+    fn initialize_color_attachment_layout(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, allocation_info: &AllocationInfo) -> Result<(), EngineError> {
+        let image = allocation_info.get_image().ok_or(EngineError::from("Can not initialize the layout of an allocation without an image"))?;
+        self.transition_image_layout(command_pool, graphics_queue, &image, vk::Format::UNDEFINED, vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, 1, allocation_info.array_layers)
+    }
 
+    fn copy_image_to_host(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, allocation_info: &AllocationInfo, layout: vk::ImageLayout, aspect_mask: vk::ImageAspectFlags, rect: vk::Rect2D, bytes_per_texel: u32) -> Result<Vec<u8>, EngineError> {
+        let image = allocation_info.get_image().ok_or(EngineError::from("Can not copy an allocation without an image to the host"))?;
+        let size = rect.extent.width as vk::DeviceSize * rect.extent.height as vk::DeviceSize * bytes_per_texel as vk::DeviceSize;
+        let readback_allocation = self.create_buffer(size, vk::BufferUsageFlags::TRANSFER_DST, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, true)?;
 
-            if let Some(buffer) = allocation_info.buffer {
-                unsafe {
-                    self.device.destroy_buffer(buffer, Some(&self.get_allocation_callbacks()));
-                }
-            }
-            if let Some(image_view) = allocation_info.image_view {
-                unsafe {
-                    self.device.destroy_image_view(image_view, Some(&self.get_allocation_callbacks()));
-                }
-            }
-            if let Some(image) = allocation_info.image {
-                unsafe {
-                    self.device.destroy_image(image, Some(&self.get_allocation_callbacks()));
-                }
-            }
-        } else {
-            return Err(Cow::from("Failed to free memory!"));
-        }
-        Ok(())
-    }
+        let command_buffer = match self.begin_single_time_command(command_pool) {
+            Ok(command_buffer) => command_buffer,
+            Err(err) => {
+                self.free_memory_allocation(readback_allocation)?;
+                return Err(err);
+            },
+        };
 
-    fn copy_buffer_to_image(&self, src_buffer: &vk::Buffer, dst_image: &vk::Image, width: u32, height: u32, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<(), Cow<'static, str>> {
-        let command_buffer = self.begin_single_time_command(command_pool)?;
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer_barrier = vk::ImageMemoryBarrier {
+            s_type: StructureType::IMAGE_MEMORY_BARRIER,
+            old_layout: layout,
+            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range,
+            src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            ..Default::default()
+        };
+        let from_transfer_barrier = vk::ImageMemoryBarrier {
+            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout: layout,
+            src_access_mask: vk::AccessFlags::TRANSFER_READ,
+            dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            ..to_transfer_barrier
+        };
 
         let region = vk::BufferImageCopy {
             buffer_offset: 0,
             buffer_row_length: 0,
             buffer_image_height: 0,
             image_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask,
                 mip_level: 0,
                 base_array_layer: 0,
                 layer_count: 1,
             },
             image_offset: vk::Offset3D {
-                x: 0,
-                y: 0,
+                x: rect.offset.x,
+                y: rect.offset.y,
                 z: 0,
             },
             image_extent: vk::Extent3D {
-                width,
-                height,
+                width: rect.extent.width,
+                height: rect.extent.height,
                 depth: 1,
             },
         };
 
         unsafe {
-            self.device.cmd_copy_buffer_to_image(command_buffer, *src_buffer, *dst_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+            self.device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::TRANSFER, DependencyFlags::empty(), &[], &[], &[to_transfer_barrier]);
+            self.device.cmd_copy_image_to_buffer(command_buffer, image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, readback_allocation.get_buffer().unwrap(), &[region]);
+            self.device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS, DependencyFlags::empty(), &[], &[], &[from_transfer_barrier]);
         }
 
-        self.end_single_time_command(command_pool, graphics_queue, command_buffer)?;
-        Ok(())
-    }
-
-    fn copy_buffer(&self, src_allocation: &AllocationInfo, dst_allocation: &AllocationInfo, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<(), Cow<'static, str>> {
-        let command_buffer = self.begin_single_time_command(command_pool)?;
-
-        let size = src_allocation.memory_end - src_allocation.memory_start;
-
-        let copy_region = vk::BufferCopy {
-            size,
-            ..Default::default()
-        };
-
-        let Some(src_buffer) = src_allocation.buffer else {
-            return Err(Cow::from("Failed to copy buffer because the src buffer was None!"));
-        };
-
-        let Some(dst_buffer) = dst_allocation.buffer else {
-            return Err(Cow::from("Failed to copy buffer because the dst buffer was None!"));
-        };
+        if let Err(err) = self.end_single_time_command(command_pool, graphics_queue, command_buffer) {
+            self.free_memory_allocation(readback_allocation)?;
+            return Err(err);
+        }
 
+        let mut data = vec![0u8; size as usize];
         unsafe {
-            self.device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &[copy_region]);
+            let mapped_memory_ptr = match self.device.map_memory(readback_allocation.memory, readback_allocation.memory_start, size, vk::MemoryMapFlags::empty()) {
+                Ok(ptr) => ptr as *const u8,
+                Err(err) => {
+                    self.free_memory_allocation(readback_allocation)?;
+                    return Err(EngineError::vulkan("Failed to map memory when copying image to host", err));
+                },
+            };
+            std::ptr::copy_nonoverlapping(mapped_memory_ptr, data.as_mut_ptr(), size as usize);
+            self.device.unmap_memory(readback_allocation.memory);
         }
 
-        self.end_single_time_command(command_pool, graphics_queue, command_buffer)?;
+        self.free_memory_allocation(readback_allocation)?;
+        Ok(data)
+    }
+
+    fn free_memory_allocation(&mut self, allocation_info: AllocationInfo) -> Result<(), EngineError> {
+        Self::release_memory_range(&mut self.live_allocations, &mut self.device_allocations, &allocation_info)?;
+
+        unsafe {
+            if let Some(buffer) = allocation_info.buffer {
+                self.device.destroy_buffer(buffer, self.get_allocation_callbacks());
+            }
+            if let Some(image_view) = allocation_info.image_view {
+                self.device.destroy_image_view(image_view, self.get_allocation_callbacks());
+            }
+            if let Some(image) = allocation_info.image {
+                self.device.destroy_image(image, self.get_allocation_callbacks());
+            }
+        }
+
+        // Both are checked to match the live allocation
+        let (memory_index, memory) = (allocation_info.memory_index, allocation_info.memory);
+        if self.dedicated_memories.remove(&memory) {
+            unsafe {
+                self.device.free_memory(memory, self.get_allocation_callbacks());
+            }
+            self.untrack_memory_block(memory);
+            if let Some(memories) = self.device_allocations.get_mut(&memory_index) {
+                memories.retain(|(block, _)| *block != memory);
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that the allocation is live and hasn't been changed, and gives its range back to the free ranges of its memory block
+    fn release_memory_range(live_allocations: &mut HashMap<AllocationID, (MemoryTypeIndex, vk::DeviceMemory, MemorySizeRange, AllocationKind)>, device_allocations: &mut HashMap<MemoryTypeIndex, Vec<(vk::DeviceMemory, Vec<MemorySizeRange>)>>, allocation_info: &AllocationInfo) -> Result<(), EngineError> {
+        let allocation_id = allocation_info.allocation_id;
+        let Some(&(memory_index, memory, (memory_start, memory_end), _)) = live_allocations.get(&allocation_id) else {
+            return Err(EngineError::from(format!("Failed to free memory because allocation {} has already been freed or wasn't made by this allocator!", allocation_id)));
+        };
+        if memory_index != allocation_info.memory_index || memory != allocation_info.memory || memory_start != allocation_info.memory_start || memory_end != allocation_info.memory_end {
+            return Err(EngineError::from(format!("Failed to free memory because allocation {} was made from memory type {} at {}..{}, but the freed allocation says memory type {} at {}..{}!", allocation_id, memory_index, memory_start, memory_end, allocation_info.memory_index, allocation_info.memory_start, allocation_info.memory_end)));
+        }
+        let Some((_, free_ranges)) = device_allocations.get_mut(&memory_index).and_then(|memories| memories.iter_mut().find(|(block, _)| *block == memory)) else {
+            return Err(EngineError::from(format!("Failed to free memory because the memory block of allocation {} doesn't exist anymore!", allocation_id)));
+        };
+        if free_ranges.iter().any(|&(start, end)| memory_start < end && start < memory_end) {
+            return Err(EngineError::from(format!("Failed to free memory because {}..{} of allocation {} is already free!", memory_start, memory_end, allocation_id)));
+        }
+
+        live_allocations.remove(&allocation_id);
+        free_ranges.push((memory_start, memory_end));
+        free_ranges.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        // The ranges exclude their end, so ranges that touch are merged
+        let mut i = 0;
+        while i + 1 < free_ranges.len() {
+            if free_ranges[i].1 >= free_ranges[i + 1].0 {
+                free_ranges[i].1 = free_ranges[i].1.max(free_ranges[i + 1].1);
+                free_ranges.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // The image has to be in TRANSFER_DST_OPTIMAL and the layers have to follow each other in the data
+    fn upload_to_image(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, data: &[u8], dst_image: &vk::Image, width: u32, height: u32, array_layers: u32, force_own_memory_block: bool) -> Result<(), EngineError> {
+        let Some((staging_buffer, staging_offset)) = self.write_to_staging_ring(data)? else {
+            let staging_allocation = self.create_staging_buffer(data, force_own_memory_block)?;
+            let result = self.copy_buffer_to_image(&staging_allocation.buffer.unwrap(), 0, dst_image, width, height, array_layers, data.len() as vk::DeviceSize, command_pool, graphics_queue);
+            self.free_memory_allocation(staging_allocation)?;
+            return result;
+        };
+
+        let command_buffer = self.begin_single_time_command(command_pool)?;
+        self.record_copy_buffer_to_image(command_buffer, &staging_buffer, staging_offset, dst_image, width, height, array_layers, data.len() as vk::DeviceSize);
+        self.end_staging_command(command_pool, graphics_queue, command_buffer, staging_offset, staging_offset + data.len() as vk::DeviceSize)
+    }
+
+    fn copy_buffer_to_image(&self, src_buffer: &vk::Buffer, buffer_offset: vk::DeviceSize, dst_image: &vk::Image, width: u32, height: u32, array_layers: u32, data_size: vk::DeviceSize, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<(), EngineError> {
+        let command_buffer = self.begin_single_time_command(command_pool)?;
+        self.record_copy_buffer_to_image(command_buffer, src_buffer, buffer_offset, dst_image, width, height, array_layers, data_size);
+        self.end_single_time_command(command_pool, graphics_queue, command_buffer)?;
+        Ok(())
+    }
+
+    // One region per layer, each layer takes an equal part of the data
+    fn record_copy_buffer_to_image(&self, command_buffer: vk::CommandBuffer, src_buffer: &vk::Buffer, buffer_offset: vk::DeviceSize, dst_image: &vk::Image, width: u32, height: u32, array_layers: u32, data_size: vk::DeviceSize) {
+        let layer_size = data_size / array_layers as vk::DeviceSize;
+        let regions = (0..array_layers).map(|layer| vk::BufferImageCopy {
+            buffer_offset: buffer_offset + layer as vk::DeviceSize * layer_size,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: layer,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: 0,
+                y: 0,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+        }).collect::<Vec<_>>();
+
+        unsafe {
+            self.device.cmd_copy_buffer_to_image(command_buffer, *src_buffer, *dst_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions);
+        }
+    }
+
+    fn copy_buffer(&self, src_allocation: &AllocationInfo, dst_allocation: &AllocationInfo, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<(), EngineError> {
+        let command_buffer = self.begin_single_time_command(command_pool)?;
+
+        let size = src_allocation.get_size();
+
+        let copy_region = vk::BufferCopy {
+            size,
+            ..Default::default()
+        };
+
+        let Some(src_buffer) = src_allocation.buffer else {
+            return Err(EngineError::from("Failed to copy buffer because the src buffer was None!"));
+        };
+
+        let Some(dst_buffer) = dst_allocation.buffer else {
+            return Err(EngineError::from("Failed to copy buffer because the dst buffer was None!"));
+        };
+
+        unsafe {
+            self.device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &[copy_region]);
+        }
+
+        self.end_single_time_command(command_pool, graphics_queue, command_buffer)?;
 
         Ok(())
     }
 
-    fn begin_single_time_command(&self, command_pool: &vk::CommandPool) -> Result<vk::CommandBuffer, Cow<'static, str>> {
+    fn begin_single_time_command(&self, command_pool: &vk::CommandPool) -> Result<vk::CommandBuffer, EngineError> {
         let alloc_info = vk::CommandBufferAllocateInfo {
             s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
             level: vk::CommandBufferLevel::PRIMARY,
@@ -618,7 +1137,7 @@ impl VkAllocator {
         let command_buffer = unsafe {
             let allocated_command_buffers = match self.device.allocate_command_buffers(&alloc_info) {
                 Ok(command_buffers) => command_buffers,
-                Err(err) => return Err(Cow::from(format!("Failed to allocate command buffer when beginning single time command because: {}", err))),
+                Err(err) => return Err(EngineError::vulkan("Failed to allocate command buffer when beginning single time command", err)),
             };
             allocated_command_buffers[0]
         };
@@ -634,7 +1153,7 @@ impl VkAllocator {
                 Ok(_) => {},
                 Err(err) => {
                     self.device.free_command_buffers(*command_pool, &[command_buffer]);
-                    return Err(Cow::from(format!("Failed to begin command buffer when beginning single time command because: {}", err)))
+                    return Err(EngineError::vulkan("Failed to begin command buffer when beginning single time command", err))
                 },
             };
         }
@@ -642,13 +1161,13 @@ impl VkAllocator {
         Ok(command_buffer)
     }
 
-    fn end_single_time_command(&self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, command_buffer: vk::CommandBuffer) -> Result<(), Cow<'static, str>> {
+    fn end_single_time_command(&self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, command_buffer: vk::CommandBuffer) -> Result<(), EngineError> {
         unsafe {
             match self.device.end_command_buffer(command_buffer) {
                 Ok(_) => {},
                 Err(err) => {
                     self.device.free_command_buffers(*command_pool, &[command_buffer]);
-                    return Err(Cow::from(format!("Failed to end command buffer when ending single time command because: {}", err)))
+                    return Err(EngineError::vulkan("Failed to end command buffer when ending single time command", err))
                 },
             }
         }
@@ -660,19 +1179,20 @@ impl VkAllocator {
             ..Default::default()
         };
 
+        let _queue_lock = self.queue_lock.lock().unwrap_or_else(PoisonError::into_inner);
         unsafe {
             match self.device.queue_submit(*graphics_queue, &[submit_info], vk::Fence::null()) {
                 Ok(_) => {},
                 Err(err) => {
                     self.device.free_command_buffers(*command_pool, &[command_buffer]);
-                    return Err(Cow::from(format!("Failed to submit queue when ending single time command because: {}", err)))
+                    return Err(EngineError::vulkan("Failed to submit queue when ending single time command", err))
                 },
             };
             match self.device.queue_wait_idle(*graphics_queue) {
                 Ok(_) => {},
                 Err(err) => {
                     self.device.free_command_buffers(*command_pool, &[command_buffer]);
-                    return Err(Cow::from(format!("Failed to wait for queue to be idle when ending single time command because: {}", err)))
+                    return Err(EngineError::vulkan("Failed to wait for queue to be idle when ending single time command", err))
                 },
             };
             self.device.free_command_buffers(*command_pool, &[command_buffer]);
@@ -681,76 +1201,131 @@ impl VkAllocator {
         Ok(())
     }
 
-    fn allocate_new_device_memory(&mut self, memory_type_index: MemoryTypeIndex, size: vk::DeviceSize, force_own_memory_block: bool) -> Result<(), Cow<'static, str>> {
-        let allocated_size = size.max(Self::DEFAULT_DEVICE_MEMORY_ALLOCATION_BYTE_SIZE) * !force_own_memory_block as vk::DeviceSize + force_own_memory_block as vk::DeviceSize * size;
+    // Whether the driver prefers the image to have a dedicated allocation
+    fn get_image_memory_requirements(&self, image: vk::Image) -> (vk::MemoryRequirements, bool) {
+        if !self.is_dedicated_image_memory_supported {
+            return (unsafe { self.device.get_image_memory_requirements(image) }, false);
+        }
+
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements {
+            s_type: StructureType::MEMORY_DEDICATED_REQUIREMENTS,
+            ..Default::default()
+        };
+        let mut mem_requirements = vk::MemoryRequirements2 {
+            s_type: StructureType::MEMORY_REQUIREMENTS_2,
+            p_next: &mut dedicated_requirements as *mut vk::MemoryDedicatedRequirements as *mut c_void,
+            ..Default::default()
+        };
+        let requirements_info = vk::ImageMemoryRequirementsInfo2 {
+            s_type: StructureType::IMAGE_MEMORY_REQUIREMENTS_INFO_2,
+            image,
+            ..Default::default()
+        };
+        unsafe {
+            self.device.get_image_memory_requirements2(&requirements_info, &mut mem_requirements);
+        }
+
+        let is_dedicated_preferred = dedicated_requirements.prefers_dedicated_allocation == vk::TRUE || dedicated_requirements.requires_dedicated_allocation == vk::TRUE;
+        (mem_requirements.memory_requirements, is_dedicated_preferred)
+    }
+
+    // `dedicated_image` makes the memory a dedicated allocation for that image, which requires `force_own_memory_block`
+    fn allocate_new_device_memory(&mut self, memory_type_index: MemoryTypeIndex, size: vk::DeviceSize, force_own_memory_block: bool, dedicated_image: Option<vk::Image>) -> Result<(), EngineError> {
+        let mut allocated_size = size.max(Self::DEFAULT_DEVICE_MEMORY_ALLOCATION_BYTE_SIZE) * !force_own_memory_block as vk::DeviceSize + force_own_memory_block as vk::DeviceSize * size;
+
+        let heap_index = self.get_heap_index(memory_type_index);
+        let heap_budget = self.get_memory_budget()[heap_index as usize];
+        let usage_limit = (heap_budget.budget as f64 * self.memory_budget_fraction as f64) as vk::DeviceSize;
+        // A shared block that would go past the limit is only made as big as the allocation, which can still fit
+        if heap_budget.usage + allocated_size > usage_limit {
+            allocated_size = size;
+        }
+        if heap_budget.usage + allocated_size > usage_limit {
+            log::warn!(target: logging::ALLOCATOR, "Allocating {} bytes would take heap {} past {}% of its budget, {} of {} bytes are already used", allocated_size, heap_index, (self.memory_budget_fraction * 100.0).round(), heap_budget.usage, heap_budget.budget);
+            return Err(EngineError::OutOfDeviceMemory { heap_index, bytes_requested: allocated_size });
+        }
         
+        let dedicated_info = dedicated_image.map(|image| vk::MemoryDedicatedAllocateInfo {
+            s_type: StructureType::MEMORY_DEDICATED_ALLOCATE_INFO,
+            image,
+            ..Default::default()
+        });
         let alloc_info = vk::MemoryAllocateInfo {
             s_type: StructureType::MEMORY_ALLOCATE_INFO,
+            p_next: dedicated_info.as_ref().map_or(std::ptr::null(), |info| info as *const vk::MemoryDedicatedAllocateInfo as *const c_void),
             allocation_size: allocated_size,
             memory_type_index,
-            ..Default::default()
         };
 
         let memory = unsafe {
-            match self.device.allocate_memory(&alloc_info, Some(&self.get_allocation_callbacks())) {
+            match self.device.allocate_memory(&alloc_info, self.get_allocation_callbacks()) {
                 Ok(memory) => memory,
-                Err(err) => return Err(Cow::from(format!("Failed to allocate memory when allocating new device memory because: {}", err))),
+                Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => return Err(EngineError::OutOfDeviceMemory { heap_index, bytes_requested: allocated_size }),
+                Err(err) => return Err(EngineError::vulkan("Failed to allocate memory when allocating new device memory", err)),
             }
         };
 
         self.device_allocations.entry(memory_type_index).or_default().push((memory, vec![(0, allocated_size)]));
+        self.memory_blocks.insert(memory, (heap_index, allocated_size));
+        self.heap_usage[heap_index as usize] += allocated_size;
         Ok(())
     }
 
-    fn get_allocation(&mut self, memory_type_index: MemoryTypeIndex, size: vk::DeviceSize, alignment: vk::DeviceSize, force_own_memory_block: bool) -> Result<AllocationInfo, Cow<'static, str>> {
-        if force_own_memory_block {
-            return self.create_own_device_memory_block(memory_type_index, size);
+    fn get_allocation(&mut self, memory_type_index: MemoryTypeIndex, size: vk::DeviceSize, alignment: vk::DeviceSize, force_own_memory_block: bool) -> Result<AllocationInfo, EngineError> {
+        if force_own_memory_block || size > self.dedicated_allocation_threshold {
+            return self.create_own_device_memory_block(memory_type_index, size, None);
         }
         
         let mut allocation = self.find_allocation(memory_type_index, size, alignment);
 
         if allocation.is_err() {
-            self.allocate_new_device_memory(memory_type_index, size, false)?;
+            self.allocate_new_device_memory(memory_type_index, size, false, None)?;
             allocation = self.find_allocation(memory_type_index, size, alignment);
         }
 
         allocation
     }
 
-    fn create_own_device_memory_block(&mut self, memory_type_index: u32, size: u64) -> Result<AllocationInfo, Cow<'static, str>> {
-        self.allocate_new_device_memory(memory_type_index, size, true)?;
+    fn create_own_device_memory_block(&mut self, memory_type_index: u32, size: u64, dedicated_image: Option<vk::Image>) -> Result<AllocationInfo, EngineError> {
+        self.allocate_new_device_memory(memory_type_index, size, true, dedicated_image)?;
 
         if let Some(memories) = self.device_allocations.get_mut(&memory_type_index) {
             for (memory, free_ranges) in memories.iter_mut() {
                 if free_ranges.len() > 1 || free_ranges.first().unwrap().0 != 0 || free_ranges.first().unwrap().1 != size {
                     continue;
                 }
-                let allocation = Ok(AllocationInfo {
+                let allocation = AllocationInfo {
                     buffer: None,
                     image: None,
                     mip_levels: None,
+                    array_layers: 1,
                     image_view: None,
                     memory_index: memory_type_index,
                     memory_start: free_ranges.first().unwrap().0,
                     memory_end: free_ranges.first().unwrap().1,
                     memory: *memory,
                     uniform_pointers: Vec::new(),
-                });
+                    uniform_buffer_size: 0,
+                    buffer_size_and_usage: None,
+                    allocation_id: 0,
+                    kind: AllocationKind::Other,
+                };
                 free_ranges.get_mut(0).unwrap().0 = size;
-                return allocation;
+                self.dedicated_memories.insert(*memory);
+                return Ok(self.track_allocation(allocation));
             }
         }
         Err("Could not find free own memory block".into())
     }
 
-    fn find_allocation(&mut self, memory_type_index: u32, size: u64, alignment: vk::DeviceSize) -> Result<AllocationInfo, Cow<'static, str>> {
+    fn find_allocation(&mut self, memory_type_index: u32, size: u64, alignment: vk::DeviceSize) -> Result<AllocationInfo, EngineError> {
         if let Some(memories) = self.device_allocations.get_mut(&memory_type_index) {
             for (memory, free_ranges) in memories.iter_mut() {
                 for (start, end) in free_ranges.iter_mut() {
                     let alignment_offset = if *start % alignment == 0 { 0 } else { alignment - (*start % alignment) };
                     let aligned_start = (*start + alignment_offset).min(*end);
                     if *end - aligned_start >= size {
-                        let allocation = Ok(AllocationInfo {
+                        let allocation = AllocationInfo {
                             memory_index: memory_type_index,
                             memory_start: aligned_start, // Including
                             memory_end: aligned_start + size, // Excluding
@@ -759,18 +1334,54 @@ impl VkAllocator {
                             memory: *memory,
                             image_view: None,
                             uniform_pointers: Vec::new(),
+                            uniform_buffer_size: 0,
                             mip_levels: None,
-                        });
+                            array_layers: 1,
+                            buffer_size_and_usage: None,
+                            allocation_id: 0,
+                            kind: AllocationKind::Other,
+                        };
                         *start += size + alignment_offset;
-                        return allocation;
+                        return Ok(self.track_allocation(allocation));
                     }
                 }
             }
         }
-        Err(Cow::from("Failed to find allocation!"))
+        Err(EngineError::from("Failed to find allocation!"))
     }
 
-    fn find_memory_type(&self, type_filter: u32, properties: vk::MemoryPropertyFlags) -> Result<u32, Cow<'static, str>> {
+    fn track_allocation(&mut self, mut allocation: AllocationInfo) -> AllocationInfo {
+        allocation.allocation_id = self.next_allocation_id;
+        self.next_allocation_id += 1;
+        self.live_allocations.insert(allocation.allocation_id, (allocation.memory_index, allocation.memory, (allocation.memory_start, allocation.memory_end), allocation.kind));
+        allocation
+    }
+
+    // The allocation is tracked before its buffer or image is made, which is what decides the kind
+    fn set_allocation_kind(&mut self, allocation: &mut AllocationInfo, kind: AllocationKind) {
+        allocation.kind = kind;
+        if let Some(live_allocation) = self.live_allocations.get_mut(&allocation.allocation_id) {
+            live_allocation.3 = kind;
+        }
+    }
+
+    fn get_allocation_stats(&self) -> BTreeMap<AllocationKind, AllocationKindStats> {
+        let mut stats: BTreeMap<AllocationKind, AllocationKindStats> = BTreeMap::new();
+        for (_, _, (memory_start, memory_end), kind) in self.live_allocations.values() {
+            let kind_stats = stats.entry(*kind).or_default();
+            kind_stats.num_allocations += 1;
+            kind_stats.total_bytes += memory_end - memory_start;
+        }
+        stats
+    }
+
+    fn dump_live_allocations(&self) -> Vec<(AllocationKind, vk::DeviceSize, MemoryTypeIndex)> {
+        let mut live_allocations = self.live_allocations.iter().collect::<Vec<_>>();
+        live_allocations.sort_unstable_by_key(|(allocation_id, _)| **allocation_id);
+        live_allocations.into_iter().map(|(_, (memory_index, _, (memory_start, memory_end), kind))| (*kind, memory_end - memory_start, *memory_index)).collect()
+    }
+
+    fn find_memory_type(&self, type_filter: u32, properties: vk::MemoryPropertyFlags) -> Result<u32, EngineError> {
         let mem_properties = unsafe {
             self.instance.get_physical_device_memory_properties(self.physical_device)
         };
@@ -780,22 +1391,429 @@ impl VkAllocator {
                 return Ok(i as u32);
             }
         }
-        Err(Cow::from("Failed to find suitable memory type!"))
+        Err(EngineError::from("Failed to find suitable memory type!"))
     }
 
-    pub unsafe fn get_allocation_callbacks(&self) -> vk::AllocationCallbacks {
-        vk::AllocationCallbacks {
-            p_user_data: Arc::into_raw(self.host_allocator.clone()) as *mut c_void,
-            pfn_allocation: Some(pfn_allocation),
-            pfn_reallocation: Some(pfn_reallocation),
-            pfn_free: Some(pfn_free),
-            pfn_internal_allocation: None,
-            pfn_internal_free: None,
+    fn get_allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.allocation_callbacks.as_ref()
+    }
+}
+
+// Defragmentation
+impl DeviceMemoryAllocator {
+    fn defragment(&mut self, allocations: &mut [&mut AllocationInfo], command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<DefragmentationReport, EngineError> {
+        let mut report = DefragmentationReport::default();
+
+        let is_movable = |allocation: &AllocationInfo| {
+            allocation.buffer.is_some() && allocation.image.is_none() && allocation.uniform_pointers.is_empty()
+                && allocation.buffer_size_and_usage.is_some_and(|(_, usage)| vk::BufferUsageFlags::from_raw(usage).contains(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST))
+                && !self.dedicated_memories.contains(&allocation.memory)
+                && self.live_allocations.contains_key(&allocation.allocation_id)
+        };
+        let movable_ids = allocations.iter().filter(|allocation| is_movable(allocation)).map(|allocation| allocation.allocation_id).collect::<HashSet<_>>();
+
+        // A block can only be released when everything in it is moved
+        let mut blocks_to_empty: HashSet<vk::DeviceMemory> = allocations.iter().filter(|allocation| movable_ids.contains(&allocation.allocation_id)).map(|allocation| allocation.memory).collect();
+        for (allocation_id, (_, memory, _, _)) in self.live_allocations.iter() {
+            if !movable_ids.contains(allocation_id) {
+                blocks_to_empty.remove(memory);
+            }
+        }
+        if blocks_to_empty.is_empty() {
+            Self::add_unmoved_allocations(&self.live_allocations, &HashSet::new(), &mut report);
+            return Ok(report);
+        }
+
+        // The emptied blocks are taken out while the new allocations are made, so nothing is moved into them
+        let mut taken_blocks = Vec::new();
+        for (memory_index, memories) in self.device_allocations.iter_mut() {
+            let (taken, kept): (Vec<_>, Vec<_>) = memories.drain(..).partition(|(memory, _)| blocks_to_empty.contains(memory));
+            *memories = kept;
+            taken_blocks.extend(taken.into_iter().map(|block| (*memory_index, block)));
+        }
+
+        // Biggest first, so they are placed before the small ones fill the holes
+        let mut to_move = allocations.iter_mut().filter(|allocation| blocks_to_empty.contains(&allocation.memory)).collect::<Vec<_>>();
+        to_move.sort_by_key(|allocation| std::cmp::Reverse(allocation.get_size()));
+
+        let mut new_allocations = Vec::with_capacity(to_move.len());
+        let mut result = Ok(());
+        for allocation in to_move.iter() {
+            let (size, usage) = allocation.buffer_size_and_usage.unwrap();
+            match self.create_buffer_in_memory_type(allocation.memory_index, size, vk::BufferUsageFlags::from_raw(usage)) {
+                Ok(new_allocation) => new_allocations.push(new_allocation),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                },
+            }
+        }
+        if result.is_ok() {
+            result = self.copy_buffers(to_move.iter().map(|allocation| &***allocation).zip(new_allocations.iter()), command_pool, graphics_queue);
+        }
+
+        for (memory_index, block) in taken_blocks {
+            self.device_allocations.entry(memory_index).or_default().push(block);
+        }
+
+        if let Err(err) = result {
+            let mut cleanup_errors = String::new();
+            for new_allocation in new_allocations {
+                if let Err(err) = self.free_memory_allocation(new_allocation) {
+                    cleanup_errors.push_str(&format!("\n{}", err));
+                }
+            }
+            return Err(err.with_cleanup_errors(cleanup_errors));
+        }
+
+        let mut moved_ids = HashSet::new();
+        for (allocation, new_allocation) in to_move.into_iter().zip(new_allocations) {
+            moved_ids.insert(new_allocation.allocation_id);
+            report.moved_allocations += 1;
+            report.moved_bytes += new_allocation.buffer_size_and_usage.unwrap().0;
+            let old_allocation = std::mem::replace(&mut **allocation, new_allocation);
+            self.free_memory_allocation(old_allocation)?;
+        }
+
+        let allocation_callbacks = self.allocation_callbacks;
+        for memories in self.device_allocations.values_mut() {
+            memories.retain(|(memory, _)| {
+                if !blocks_to_empty.contains(memory) {
+                    return true;
+                }
+                unsafe {
+                    self.device.free_memory(*memory, allocation_callbacks.as_ref());
+                }
+                report.released_blocks += 1;
+                false
+            });
         }
+        for memory in blocks_to_empty {
+            self.untrack_memory_block(memory);
+        }
+
+        Self::add_unmoved_allocations(&self.live_allocations, &moved_ids, &mut report);
+        Ok(report)
+    }
+
+    fn add_unmoved_allocations(live_allocations: &HashMap<AllocationID, (MemoryTypeIndex, vk::DeviceMemory, MemorySizeRange, AllocationKind)>, moved_ids: &HashSet<AllocationID>, report: &mut DefragmentationReport) {
+        for (_, (_, _, (memory_start, memory_end), _)) in live_allocations.iter().filter(|(allocation_id, _)| !moved_ids.contains(allocation_id)) {
+            report.unmoved_allocations += 1;
+            report.unmoved_bytes += memory_end - memory_start;
+        }
+        log::debug!(target: logging::ALLOCATOR, "Defragmentation moved {} allocations and left {} allocations with {} bytes in place, only vertex and index buffers are moved", report.moved_allocations, report.unmoved_allocations, report.unmoved_bytes);
+    }
+
+    fn create_buffer_in_memory_type(&mut self, memory_type_index: MemoryTypeIndex, size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> Result<AllocationInfo, EngineError> {
+        let buffer_info = vk::BufferCreateInfo {
+            s_type: StructureType::BUFFER_CREATE_INFO,
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let buffer = unsafe { self.device.create_buffer(&buffer_info, self.get_allocation_callbacks()) }
+            .map_err(|err| EngineError::vulkan("Failed to create buffer when moving a buffer", err))?;
+        let memory_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+
+        let mut allocation_info = match self.get_allocation(memory_type_index, memory_requirements.size, memory_requirements.alignment, false) {
+            Ok(allocation_info) => allocation_info,
+            Err(err) => {
+                unsafe { self.device.destroy_buffer(buffer, self.get_allocation_callbacks()) };
+                return Err(err);
+            },
+        };
+        allocation_info.buffer = Some(buffer);
+        allocation_info.buffer_size_and_usage = Some((size, usage.as_raw()));
+        self.set_allocation_kind(&mut allocation_info, AllocationKind::of_buffer(usage));
+
+        if let Err(err) = unsafe { self.device.bind_buffer_memory(buffer, allocation_info.memory, allocation_info.memory_start) } {
+            self.free_memory_allocation(allocation_info)?;
+            return Err(EngineError::vulkan("Failed to bind buffer memory when moving a buffer", err));
+        }
+
+        Ok(allocation_info)
+    }
+
+    // Copies every pair with one submit
+    fn copy_buffers<'a>(&self, copies: impl Iterator<Item = (&'a AllocationInfo, &'a AllocationInfo)>, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue) -> Result<(), EngineError> {
+        let command_buffer = self.begin_single_time_command(command_pool)?;
+
+        for (src_allocation, dst_allocation) in copies {
+            let copy_region = vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: src_allocation.buffer_size_and_usage.unwrap().0,
+            };
+            unsafe {
+                self.device.cmd_copy_buffer(command_buffer, src_allocation.buffer.unwrap(), dst_allocation.buffer.unwrap(), &[copy_region]);
+            }
+        }
+
+        self.end_single_time_command(command_pool, graphics_queue, command_buffer)
     }
 }
 
+// Staging ring
+impl DeviceMemoryAllocator {
+    // vkCmdCopyBufferToImage needs offsets that are a multiple of the texel size, and 16 covers every format
+    const STAGING_RING_ALIGNMENT: vk::DeviceSize = 16;
+
+    fn set_staging_ring_size(&mut self, size: vk::DeviceSize) {
+        if self.staging_ring.is_some() {
+            log::warn!(target: logging::ALLOCATOR, "The staging ring has already been created, so its size can't be changed");
+            return;
+        }
+        self.staging_stats.ring_size = size;
+    }
+
+    fn get_staging_stats(&self) -> StagingStats {
+        self.staging_stats
+    }
+
+    fn get_upload_marker(&self) -> UploadMarker {
+        UploadMarker(self.staging_stats.ring_uploads)
+    }
+
+    fn is_upload_finished(&mut self, marker: UploadMarker) -> Result<bool, EngineError> {
+        let Some(staging_ring) = self.staging_ring.as_mut() else {
+            return Ok(true);
+        };
+        staging_ring.retire_regions(&self.device, false)?;
+        Ok(staging_ring.in_flight.front().is_none_or(|region| region.sequence > marker.0))
+    }
+
+    fn create_staging_ring(&mut self) -> Result<(), EngineError> {
+        let size = self.staging_stats.ring_size;
+        let allocation = self.create_buffer(size, vk::BufferUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, true)?;
+        let mapped_ptr = match unsafe { self.device.map_memory(allocation.memory, allocation.memory_start, size, vk::MemoryMapFlags::empty()) } {
+            Ok(ptr) => ptr as *mut u8,
+            Err(err) => {
+                self.free_memory_allocation(allocation)?;
+                return Err(EngineError::vulkan("Failed to map memory when creating the staging ring", err));
+            },
+        };
+        self.staging_ring = Some(StagingRing {
+            allocation,
+            mapped_ptr,
+            head: 0,
+            in_flight: VecDeque::new(),
+            free_fences: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Copies the data into a free region of the ring and returns the ring's buffer and the offset of the data, waiting for the oldest copies when the ring is full.
+    /// None when the data is bigger than the ring. The region has to be submitted with [`DeviceMemoryAllocator::end_staging_command`] before the next upload.
+    fn write_to_staging_ring(&mut self, data: &[u8]) -> Result<Option<(vk::Buffer, vk::DeviceSize)>, EngineError> {
+        let size = data.len() as vk::DeviceSize;
+        if size > self.staging_stats.ring_size {
+            self.staging_stats.fallback_uploads += 1;
+            return Ok(None);
+        }
+        if self.staging_ring.is_none() {
+            self.create_staging_ring()?;
+        }
+
+        let ring_size = self.staging_stats.ring_size;
+        let staging_ring = self.staging_ring.as_mut().unwrap();
+        let offset = loop {
+            staging_ring.retire_regions(&self.device, false)?;
+            if let Some(offset) = staging_ring.find_free_offset(size, ring_size) {
+                break offset;
+            }
+            staging_ring.retire_regions(&self.device, true)?;
+        };
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), staging_ring.mapped_ptr.add(offset as usize), data.len());
+        }
+        staging_ring.head = offset + size;
+        Ok(Some((staging_ring.allocation.buffer.unwrap(), offset)))
+    }
+
+    // Like end_single_time_command, but it returns right after the submit. The ring region is retired when the fence has signaled
+    fn end_staging_command(&mut self, command_pool: &vk::CommandPool, graphics_queue: &vk::Queue, command_buffer: vk::CommandBuffer, start: vk::DeviceSize, end: vk::DeviceSize) -> Result<(), EngineError> {
+        unsafe {
+            if let Err(err) = self.device.end_command_buffer(command_buffer) {
+                self.device.free_command_buffers(*command_pool, &[command_buffer]);
+                return Err(EngineError::vulkan("Failed to end command buffer when ending staging command", err));
+            }
+        }
+
+        let allocation_callbacks = self.allocation_callbacks;
+        let staging_ring = self.staging_ring.as_mut().unwrap();
+        let fence = match staging_ring.free_fences.pop() {
+            Some(fence) => fence,
+            None => {
+                let fence_info = vk::FenceCreateInfo {
+                    s_type: StructureType::FENCE_CREATE_INFO,
+                    ..Default::default()
+                };
+                match unsafe { self.device.create_fence(&fence_info, allocation_callbacks.as_ref()) } {
+                    Ok(fence) => fence,
+                    Err(err) => {
+                        unsafe { self.device.free_command_buffers(*command_pool, &[command_buffer]) };
+                        return Err(EngineError::vulkan("Failed to create fence when ending staging command", err));
+                    },
+                }
+            },
+        };
+
+        let submit_info = vk::SubmitInfo {
+            s_type: StructureType::SUBMIT_INFO,
+            command_buffer_count: 1,
+            p_command_buffers: &command_buffer,
+            ..Default::default()
+        };
+
+        let queue_lock = self.queue_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let submit_result = unsafe { self.device.queue_submit(*graphics_queue, &[submit_info], fence) };
+        drop(queue_lock);
+        if let Err(err) = submit_result {
+            staging_ring.free_fences.push(fence);
+            unsafe { self.device.free_command_buffers(*command_pool, &[command_buffer]) };
+            return Err(EngineError::vulkan("Failed to submit queue when ending staging command", err));
+        }
+
+        staging_ring.in_flight.push_back(StagingRegion {
+            sequence: self.staging_stats.ring_uploads + 1,
+            start,
+            end,
+            fence,
+            command_pool: *command_pool,
+            command_buffer,
+        });
+
+        let bytes_in_flight = staging_ring.in_flight.iter().map(|region| region.end - region.start).sum::<vk::DeviceSize>();
+        self.staging_stats.high_water_mark = self.staging_stats.high_water_mark.max(bytes_in_flight);
+        self.staging_stats.ring_uploads += 1;
+        Ok(())
+    }
+
+    // Only called when the device is idle. The command buffers are freed with their command pools, which are destroyed before this
+    fn destroy_staging_ring(&mut self) {
+        let Some(staging_ring) = self.staging_ring.take() else {
+            return;
+        };
+        unsafe {
+            let allocation_callbacks = self.get_allocation_callbacks();
+            for fence in staging_ring.in_flight.iter().map(|region| region.fence).chain(staging_ring.free_fences) {
+                self.device.destroy_fence(fence, allocation_callbacks);
+            }
+            self.device.destroy_buffer(staging_ring.allocation.buffer.unwrap(), allocation_callbacks);
+        }
+    }
+}
+
+impl StagingRing {
+    // When `wait_for_oldest` is set, the oldest region is waited for if it hasn't finished yet
+    fn retire_regions(&mut self, device: &Device, wait_for_oldest: bool) -> Result<(), EngineError> {
+        if let (true, Some(oldest)) = (wait_for_oldest, self.in_flight.front()) {
+            unsafe { device.wait_for_fences(&[oldest.fence], true, u64::MAX) }
+                .map_err(|err| EngineError::vulkan("Failed to wait for a staging copy", err))?;
+        }
+
+        while let Some(oldest) = self.in_flight.front() {
+            let is_finished = unsafe { device.get_fence_status(oldest.fence) }
+                .map_err(|err| EngineError::vulkan("Failed to get the status of a staging copy", err))?;
+            if !is_finished {
+                break;
+            }
+            let region = self.in_flight.pop_front().unwrap();
+            unsafe {
+                device.reset_fences(&[region.fence]).map_err(|err| EngineError::vulkan("Failed to reset the fence of a staging copy", err))?;
+                device.free_command_buffers(region.command_pool, &[region.command_buffer]);
+            }
+            self.free_fences.push(region.fence);
+        }
+
+        if self.in_flight.is_empty() {
+            self.head = 0;
+        }
+        Ok(())
+    }
+
+    // The regions are used in order, so the free space is after the head and, when the head has passed the oldest region, before the oldest region.
+    // A region never ends exactly at the oldest region's start, so the head only equals that start when nothing is in flight
+    fn find_free_offset(&self, size: vk::DeviceSize, ring_size: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let Some(oldest) = self.in_flight.front() else {
+            return (size <= ring_size).then_some(0);
+        };
+        let aligned_head = self.head.next_multiple_of(DeviceMemoryAllocator::STAGING_RING_ALIGNMENT);
+        if self.head >= oldest.start {
+            if aligned_head + size <= ring_size {
+                Some(aligned_head)
+            } else if size < oldest.start {
+                Some(0)
+            } else {
+                None
+            }
+        } else if aligned_head + size < oldest.start {
+            Some(aligned_head)
+        } else {
+            None
+        }
+    }
+}
+
+// The uniform pointers point into mapped device memory, which stays valid on every thread, so the buffers made by worker threads can be handed back
+unsafe impl Send for AllocationInfo {}
+
 impl AllocationInfo {
+    /// An allocation without memory behind it, for the tests of code that only keeps it next to the CPU copy of the data.
+    #[cfg(test)]
+    pub fn without_memory() -> AllocationInfo {
+        AllocationInfo {
+            buffer: None,
+            image: None,
+            mip_levels: None,
+            array_layers: 1,
+            image_view: None,
+            memory_index: 0,
+            memory_start: 0,
+            memory_end: 0,
+            memory: vk::DeviceMemory::null(),
+            uniform_pointers: Vec::new(),
+            uniform_buffer_size: 0,
+            buffer_size_and_usage: None,
+            allocation_id: AllocationID::MAX,
+            kind: AllocationKind::Other,
+        }
+    }
+
+    /// Like [`AllocationInfo::without_memory`], with a buffer handle for the code that only records or compares it.
+    #[cfg(test)]
+    pub fn with_buffer(buffer: vk::Buffer) -> AllocationInfo {
+        AllocationInfo {
+            buffer: Some(buffer),
+            ..Self::without_memory()
+        }
+    }
+
+    /// A second AllocationInfo with the same handles, for sharing one allocation between several owners.
+    /// # Safety
+    /// Only one of the copies may be freed, and none of them may be used after that.
+    pub unsafe fn duplicate_handle(&self) -> AllocationInfo {
+        AllocationInfo {
+            buffer: self.buffer,
+            image: self.image,
+            mip_levels: self.mip_levels,
+            array_layers: self.array_layers,
+            image_view: self.image_view,
+            memory_index: self.memory_index,
+            memory_start: self.memory_start,
+            memory_end: self.memory_end,
+            memory: self.memory,
+            uniform_pointers: self.uniform_pointers.clone(),
+            uniform_buffer_size: self.uniform_buffer_size,
+            buffer_size_and_usage: self.buffer_size_and_usage,
+            allocation_id: self.allocation_id,
+            kind: self.kind,
+        }
+    }
+
     pub fn get_memory(&self) -> vk::DeviceMemory {
         self.memory
     }
@@ -816,6 +1834,11 @@ impl AllocationInfo {
         self.memory_start
     }
 
+    /// The size of each part of the buffer that [`AllocationInfo::get_uniform_pointers`] points to. The parts are padded to the device's offset alignment, so this can be smaller than the distance between them.
+    pub fn get_uniform_buffer_size(&self) -> vk::DeviceSize {
+        self.uniform_buffer_size
+    }
+
     pub fn get_uniform_pointers(&self) -> &[*mut c_void] {
         &self.uniform_pointers
     }
@@ -824,107 +1847,133 @@ impl AllocationInfo {
         self.mip_levels
     }
 
+    pub fn get_array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
     pub fn get_memory_end(&self) -> vk::DeviceSize {
         self.memory_end
     }
+
+    /// The bytes of memory the allocation takes, which can be more than was asked for because of the memory requirements.
+    pub fn get_size(&self) -> vk::DeviceSize {
+        self.memory_end - self.memory_start
+    }
+
+    pub fn get_kind(&self) -> AllocationKind {
+        self.kind
+    }
 }
 
+// The raw pointers only point into the pools the allocator owns, and every access goes through its Mutex
+unsafe impl Send for VkHostAllocator {}
+
 // Host memory allocation
 impl VkHostAllocator {
     const DEFAULT_HOST_MEMORY_ALLOCATION_BYTE_SIZE: usize = 512_000; // 512 KB
 
-    pub fn allocate_host_memory(&mut self, size: usize, alignment: usize) -> Result<*mut c_void, Cow<'static, str>> {
-        let mut allocation = self.find_host_allocation(size, alignment);
-
-        if allocation.is_err() {
-            unsafe {
-                self.allocate_new_host_memory(size, alignment)?;
-            }
-            allocation = self.find_host_allocation(size, alignment);
+    pub fn allocate_host_memory(&mut self, size: usize, alignment: usize) -> Result<*mut c_void, EngineError> {
+        // Empty allocations still take up one aligned slot, so every pointer is unique
+        let reserved_size = size.max(1).next_multiple_of(alignment);
+        if let Some(ptr) = self.find_host_allocation(size, reserved_size, alignment)? {
+            return Ok(ptr);
         }
 
-        allocation
+        unsafe {
+            self.allocate_new_host_memory(reserved_size, alignment)?;
+        }
+        self.find_host_allocation(size, reserved_size, alignment)?.ok_or(EngineError::from("Failed to find host allocation in a new pool!"))
     }
 
-    fn find_host_allocation(&mut self, size: usize, alignment: usize) -> Result<*mut c_void, Cow<'static, str>> {
+    // None when no pool has enough free space. An error means that the free ranges and the live allocations disagree, which should never happen
+    fn find_host_allocation(&mut self, size: usize, reserved_size: usize, alignment: usize) -> Result<Option<*mut c_void>, EngineError> {
         if let Some(allocations) = self.host_allocations.get_mut(&alignment) {
             for allocation in allocations.iter_mut() {
-                for free_range in allocation.free_allocations.iter_mut() {
-                    if (free_range.1 + 1) - free_range.0 >= size {
-                        let allocation_ptr = unsafe { allocation.start_ptr.add(free_range.0) as *mut c_void};
-                        let previous = self.allocated_host_pointers.get(&allocation_ptr);
-                        if previous.is_some() {
-                            return Err(Cow::from("Failed to find host allocation! Because the allocation was already allocated!"));
-                        }
-                        self.allocated_host_pointers.insert(allocation_ptr, (alignment, size));
-                        // Add size and padding to allocation, so that the alignment is correct for the next allocation as well
-                        free_range.0 += size + ((alignment - (size % alignment)) % alignment);
-                        return Ok(allocation_ptr);
+                let Some(range_index) = allocation.free_allocations.iter().position(|(start, end)| end - start >= reserved_size) else {
+                    continue;
+                };
+                let offset = allocation.free_allocations[range_index].0;
+                let allocation_ptr = unsafe { allocation.start_ptr.add(offset) as *mut c_void };
+                // The live allocation that starts last before the end of the new one is the only one that can overlap it
+                let end_ptr = unsafe { (allocation_ptr as *mut u8).add(reserved_size) as *mut c_void };
+                if let Some((&live_ptr, live)) = self.allocated_host_pointers.range(..end_ptr).next_back() {
+                    if unsafe { (live_ptr as *mut u8).add(live.reserved_size) } > allocation_ptr as *mut u8 {
+                        return Err(EngineError::from("Failed to find host allocation! Because the free range overlaps memory that is already allocated!"));
                     }
                 }
+                self.allocated_host_pointers.insert(allocation_ptr, HostAllocation { alignment, size, reserved_size });
+
+                let free_range = &mut allocation.free_allocations[range_index];
+                free_range.0 += reserved_size;
+                if free_range.0 == free_range.1 {
+                    allocation.free_allocations.remove(range_index);
+                }
+                return Ok(Some(allocation_ptr));
             }
         }
-        Err(Cow::from("Failed to find host allocation!"))
+        Ok(None)
     }
 
-    unsafe fn allocate_new_host_memory(&mut self, size: usize, alignment: usize) -> Result<(), Cow<'static, str>> {
+    unsafe fn allocate_new_host_memory(&mut self, size: usize, alignment: usize) -> Result<(), EngineError> {
         let allocated_size = size.max(Self::DEFAULT_HOST_MEMORY_ALLOCATION_BYTE_SIZE).div_ceil(alignment) * alignment;
 
         let layout = match std::alloc::Layout::from_size_align(allocated_size, alignment) {
             Ok(layout) => layout,
-            Err(err) => return Err(Cow::from(format!("Failed to create layout when allocating new host memory because: {}", err))),
+            Err(err) => return Err(EngineError::from(format!("Failed to create layout when allocating new host memory because: {}", err))),
         
         };
 
         let ptr = std::alloc::alloc(layout);
         if ptr.is_null() {
-            return Err(Cow::from("Failed to allocate new host memory!"));
+            return Err(EngineError::from("Failed to allocate new host memory!"));
         }
 
         let allocation = HostAllocationPool {
             start_ptr: ptr,
             size: allocated_size,
             alignment,
-            free_allocations: vec![(0, allocated_size - 1)],
+            free_allocations: vec![(0, allocated_size)],
         };
         self.host_allocations.entry(alignment).or_default().push(allocation);
         Ok(())
     }
 
-    pub unsafe fn free_host_memory(&mut self, ptr: *mut c_void) -> Result<(), Cow<'static, str>> {
-        if let Some((alignment, size)) = self.allocated_host_pointers.remove(&ptr) {
-            if let Some(allocations) = self.host_allocations.get_mut(&alignment) {
-                for allocation in allocations.iter_mut() {
-                    if allocation.start_ptr <= ptr as *mut u8 && allocation.start_ptr.add(allocation.size) > ptr as *mut u8 {
-                        let pointer_offset: usize = match ptr.offset_from(allocation.start_ptr as *mut c_void).try_into() {
-                            Ok(offset) => offset,
-                            Err(err) => return Err(Cow::from(format!("Failed to free host memory because: {}", err))),
-                        };
-                        allocation.free_allocations.push((pointer_offset, pointer_offset + size + ((alignment - (size % alignment)) % alignment) - 1));
-                        allocation.free_allocations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-                        let mut i = 0;
-                        while i < allocation.free_allocations.len() - 1 {
-                            if allocation.free_allocations[i].1 == allocation.free_allocations[i + 1].0 - 1 {
-                                allocation.free_allocations[i].1 = allocation.free_allocations[i + 1].1;
-                                allocation.free_allocations.remove(i + 1);
-                            }
-                            i += 1;
-                        }
-                        return Ok(());
-                    }
-                }
-            }
-        }
+    pub unsafe fn free_host_memory(&mut self, ptr: *mut c_void) -> Result<(), EngineError> {
+        let Some(host_allocation) = self.allocated_host_pointers.get(&ptr).copied() else {
+            return Err(EngineError::from("Failed to free host memory because the pointer isn't allocated!"));
+        };
+        let Some(allocation) = self.host_allocations.get_mut(&host_allocation.alignment).and_then(|allocations| allocations.iter_mut().find(|allocation| allocation.start_ptr <= ptr as *mut u8 && allocation.start_ptr.add(allocation.size) > ptr as *mut u8)) else {
+            return Err(EngineError::from("Failed to free host memory because the pointer isn't in any pool!"));
+        };
 
-        Err(Cow::from("Failed to free host memory!"))
+        let start = (ptr as *mut u8).offset_from(allocation.start_ptr) as usize;
+        let end = start + host_allocation.reserved_size;
+        if allocation.free_allocations.iter().any(|&(free_start, free_end)| start < free_end && free_start < end) {
+            return Err(EngineError::from("Failed to free host memory because part of it is already free!"));
+        }
+        self.allocated_host_pointers.remove(&ptr);
+
+        let free_allocations = &mut allocation.free_allocations;
+        let index = free_allocations.partition_point(|&(free_start, _)| free_start < start);
+        free_allocations.insert(index, (start, end));
+        // Merge with the neighbours when they touch
+        if index + 1 < free_allocations.len() && free_allocations[index].1 == free_allocations[index + 1].0 {
+            free_allocations[index].1 = free_allocations[index + 1].1;
+            free_allocations.remove(index + 1);
+        }
+        if index > 0 && free_allocations[index - 1].1 == free_allocations[index].0 {
+            free_allocations[index - 1].1 = free_allocations[index].1;
+            free_allocations.remove(index);
+        }
+        Ok(())
     }
 
-    pub unsafe fn free_all_host_memory(&mut self) -> Result<(), Cow<'static, str>> {
+    pub unsafe fn free_all_host_memory(&mut self) -> Result<(), EngineError> {
         for (_, allocations) in self.host_allocations.iter_mut() {
             for allocation in allocations.iter_mut() {
                 let layout = match std::alloc::Layout::from_size_align(allocation.size, allocation.alignment) {
                     Ok(layout) => layout,
-                    Err(err) => return Err(Cow::from(format!("Failed to create layout when freeing all host memory because: {}", err))),
+                    Err(err) => return Err(EngineError::from(format!("Failed to create layout when freeing all host memory because: {}", err))),
                 };
                 std::alloc::dealloc(allocation.start_ptr, layout);
             }
@@ -934,14 +1983,24 @@ impl VkHostAllocator {
         Ok(())
     }
 
-    pub unsafe fn reallocate(&mut self, ptr: *mut c_void, new_size: usize) -> Result<*mut c_void, Cow<'static, str>> {
-        if let Some((alignment, _)) = self.allocated_host_pointers.get(&ptr) {
-            let new_ptr = self.allocate_host_memory(new_size, *alignment)?;
-            std::ptr::copy_nonoverlapping(ptr, new_ptr, new_size);
+    /// Like realloc, a null pointer allocates and a size of 0 frees, in which case null is returned.
+    pub unsafe fn reallocate(&mut self, ptr: *mut c_void, new_size: usize, alignment: usize) -> Result<*mut c_void, EngineError> {
+        if ptr.is_null() {
+            return self.allocate_host_memory(new_size, alignment);
+        }
+        let Some(host_allocation) = self.allocated_host_pointers.get(&ptr).copied() else {
+            return Err(EngineError::from("Failed to reallocate host memory because the pointer isn't allocated!"));
+        };
+        if new_size == 0 {
             self.free_host_memory(ptr)?;
-            return Ok(new_ptr);
+            return Ok(std::ptr::null_mut());
         }
-        Err(Cow::from("Failed to reallocate host memory!"))
+
+        // Vulkan requires the same alignment as the original allocation
+        let new_ptr = self.allocate_host_memory(new_size, host_allocation.alignment)?;
+        std::ptr::copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8, host_allocation.size.min(new_size));
+        self.free_host_memory(ptr)?;
+        Ok(new_ptr)
     }
 }
 
@@ -949,14 +2008,15 @@ unsafe extern "system" fn pfn_allocation(p_user_data: *mut c_void, size: usize,
     let allocator_arc = Arc::from_raw(p_user_data as *mut Mutex<VkHostAllocator>);
     
     let alloced_ptr = {
-        let allocator = &mut allocator_arc.lock().unwrap();
+        // A panic can't unwind out of the callback, and the bookkeeping is only changed after every check has passed
+        let allocator = &mut allocator_arc.lock().unwrap_or_else(PoisonError::into_inner);
 
         match allocation_scope {
             SystemAllocationScope::COMMAND => {
                 match allocator.allocate_host_memory(size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to allocate host memory when allocating command because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to allocate host memory when allocating command because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
@@ -965,7 +2025,7 @@ unsafe extern "system" fn pfn_allocation(p_user_data: *mut c_void, size: usize,
                 match allocator.allocate_host_memory(size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to allocate host memory when allocating object because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to allocate host memory when allocating object because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
@@ -974,7 +2034,7 @@ unsafe extern "system" fn pfn_allocation(p_user_data: *mut c_void, size: usize,
                 match allocator.allocate_host_memory(size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to allocate host memory when allocating cache because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to allocate host memory when allocating cache because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
@@ -983,7 +2043,7 @@ unsafe extern "system" fn pfn_allocation(p_user_data: *mut c_void, size: usize,
                 match allocator.allocate_host_memory(size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to allocate host memory when allocating device because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to allocate host memory when allocating device because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
@@ -992,13 +2052,13 @@ unsafe extern "system" fn pfn_allocation(p_user_data: *mut c_void, size: usize,
                 match allocator.allocate_host_memory(size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to allocate host memory when allocating instance because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to allocate host memory when allocating instance because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
             },
             _ => {
-                eprintln!("Failed to allocate host memory because the allocation scope was not supported!");
+                log::error!(target: logging::ALLOCATOR, "Failed to allocate host memory because the allocation scope was not supported!");
                 std::ptr::null_mut()
             },
         }
@@ -1008,59 +2068,60 @@ unsafe extern "system" fn pfn_allocation(p_user_data: *mut c_void, size: usize,
     alloced_ptr
 }
 
-unsafe extern "system" fn pfn_reallocation(p_user_data: *mut c_void, original: *mut c_void, size: usize, _alignment: usize, allocation_scope: SystemAllocationScope) -> *mut c_void {
+unsafe extern "system" fn pfn_reallocation(p_user_data: *mut c_void, original: *mut c_void, size: usize, alignment: usize, allocation_scope: SystemAllocationScope) -> *mut c_void {
     let allocator_arc = Arc::from_raw(p_user_data as *mut Mutex<VkHostAllocator>);
     
     let realloc_ptr = {
-        let allocator = &mut allocator_arc.lock().unwrap();
+        // A panic can't unwind out of the callback, and the bookkeeping is only changed after every check has passed
+        let allocator = &mut allocator_arc.lock().unwrap_or_else(PoisonError::into_inner);
         match allocation_scope {
             SystemAllocationScope::COMMAND => {
-                match allocator.reallocate(original, size) {
+                match allocator.reallocate(original, size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to reallocate host memory when allocating command because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to reallocate host memory when allocating command because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
             },
             SystemAllocationScope::OBJECT => {
-                match allocator.reallocate(original, size) {
+                match allocator.reallocate(original, size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to reallocate host memory when allocating object because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to reallocate host memory when allocating object because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
             },
             SystemAllocationScope::CACHE => {
-                match allocator.reallocate(original, size) {
+                match allocator.reallocate(original, size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to reallocate host memory when allocating cache because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to reallocate host memory when allocating cache because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
             },
             SystemAllocationScope::DEVICE => {
-                match allocator.reallocate(original, size) {
+                match allocator.reallocate(original, size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to reallocate host memory when allocating device because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to reallocate host memory when allocating device because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
             },
             SystemAllocationScope::INSTANCE => {
-                match allocator.reallocate(original, size) {
+                match allocator.reallocate(original, size, alignment) {
                     Ok(ptr) => ptr,
                     Err(err) => {
-                        eprintln!("Failed to reallocate host memory when allocating instance because: {}", err);
+                        log::error!(target: logging::ALLOCATOR, "Failed to reallocate host memory when allocating instance because: {}", err);
                         std::ptr::null_mut()
                     },
                 }
             },
             _ => {
-                eprintln!("Failed to reallocate host memory because the allocation scope was not supported!");
+                log::error!(target: logging::ALLOCATOR, "Failed to reallocate host memory because the allocation scope was not supported!");
                 std::ptr::null_mut()
             },
         }
@@ -1077,14 +2138,295 @@ unsafe extern "system" fn pfn_free(p_user_data: *mut c_void, ptr: *mut c_void) {
 
     let allocator_arc = Arc::from_raw(p_user_data as *mut Mutex<VkHostAllocator>);
     {
-        let allocator = &mut allocator_arc.lock().unwrap();
+        // A panic can't unwind out of the callback, and the bookkeeping is only changed after every check has passed
+        let allocator = &mut allocator_arc.lock().unwrap_or_else(PoisonError::into_inner);
         match allocator.free_host_memory(ptr) {
             Ok(_) => {},
             Err(err) => {
-                eprintln!("Failed to free host memory when freeing because: {}", err);
+                log::error!(target: logging::ALLOCATOR, "Failed to free host memory when freeing because: {}", err);
             },
         };
     }
 
     std::mem::forget(allocator_arc);
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    type LiveAllocations = HashMap<AllocationID, (MemoryTypeIndex, vk::DeviceMemory, MemorySizeRange, AllocationKind)>;
+    type DeviceAllocations = HashMap<MemoryTypeIndex, Vec<(vk::DeviceMemory, Vec<MemorySizeRange>)>>;
+
+    const MEMORY_INDEX: MemoryTypeIndex = 2;
+
+    fn get_memory() -> vk::DeviceMemory {
+        vk::DeviceMemory::from_raw(1)
+    }
+
+    fn get_allocation_info(allocation_id: AllocationID, memory_index: MemoryTypeIndex, range: MemorySizeRange) -> AllocationInfo {
+        AllocationInfo {
+            buffer: None,
+            image: None,
+            mip_levels: None,
+            array_layers: 1,
+            image_view: None,
+            memory_index,
+            memory_start: range.0,
+            memory_end: range.1,
+            memory: get_memory(),
+            uniform_pointers: Vec::new(),
+            uniform_buffer_size: 0,
+            buffer_size_and_usage: None,
+            allocation_id,
+            kind: AllocationKind::Other,
+        }
+    }
+
+    // One 1024 byte block with live allocations 0 at 0..256, 1 at 256..512 and 2 at 512..768
+    fn get_allocator_state() -> (LiveAllocations, DeviceAllocations) {
+        let live_allocations = (0..3).map(|id| (id, (MEMORY_INDEX, get_memory(), (id * 256, (id + 1) * 256), AllocationKind::Other))).collect();
+        let device_allocations = HashMap::from([(MEMORY_INDEX, vec![(get_memory(), vec![(768, 1024)])])]);
+        (live_allocations, device_allocations)
+    }
+
+    fn get_free_ranges(device_allocations: &DeviceAllocations) -> Vec<MemorySizeRange> {
+        device_allocations[&MEMORY_INDEX][0].1.clone()
+    }
+
+    #[test]
+    fn freed_ranges_are_merged_with_their_neighbours() {
+        let (mut live_allocations, mut device_allocations) = get_allocator_state();
+        DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &get_allocation_info(0, MEMORY_INDEX, (0, 256))).unwrap();
+        assert_eq!(get_free_ranges(&device_allocations), vec![(0, 256), (768, 1024)]);
+        DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &get_allocation_info(2, MEMORY_INDEX, (512, 768))).unwrap();
+        assert_eq!(get_free_ranges(&device_allocations), vec![(0, 256), (512, 1024)]);
+        DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &get_allocation_info(1, MEMORY_INDEX, (256, 512))).unwrap();
+        assert_eq!(get_free_ranges(&device_allocations), vec![(0, 1024)]);
+        assert!(live_allocations.is_empty());
+    }
+
+    #[test]
+    fn defragmentation_reports_the_allocations_it_left_in_place() {
+        let (live_allocations, _) = get_allocator_state();
+        let mut report = DefragmentationReport::default();
+        DeviceMemoryAllocator::add_unmoved_allocations(&live_allocations, &HashSet::from([1]), &mut report);
+        assert_eq!((report.unmoved_allocations, report.unmoved_bytes), (2, 512));
+    }
+
+    #[test]
+    fn double_free_fails_and_changes_nothing() {
+        let (mut live_allocations, mut device_allocations) = get_allocator_state();
+        let allocation = get_allocation_info(1, MEMORY_INDEX, (256, 512));
+        // Like a duplicated handle that is freed a second time
+        let duplicate = unsafe { allocation.duplicate_handle() };
+        DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &allocation).unwrap();
+
+        let err = DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &duplicate).unwrap_err();
+        assert!(err.to_string().contains("allocation 1 has already been freed"), "{}", err);
+        assert_eq!(get_free_ranges(&device_allocations), vec![(256, 512), (768, 1024)]);
+        assert_eq!(live_allocations.len(), 2);
+    }
+
+    #[test]
+    fn unknown_allocation_fails_and_changes_nothing() {
+        let (mut live_allocations, mut device_allocations) = get_allocator_state();
+        let err = DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &get_allocation_info(42, MEMORY_INDEX, (768, 1024))).unwrap_err();
+        assert!(err.to_string().contains("allocation 42 has already been freed or wasn't made by this allocator"), "{}", err);
+        assert_eq!(get_free_ranges(&device_allocations), vec![(768, 1024)]);
+        assert_eq!(live_allocations.len(), 3);
+    }
+
+    #[test]
+    fn allocation_with_the_wrong_memory_index_fails_and_stays_live() {
+        let (mut live_allocations, mut device_allocations) = get_allocator_state();
+        let err = DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &get_allocation_info(1, MEMORY_INDEX + 1, (256, 512))).unwrap_err();
+        assert!(err.to_string().contains("made from memory type 2 at 256..512, but the freed allocation says memory type 3 at 256..512"), "{}", err);
+        assert_eq!(get_free_ranges(&device_allocations), vec![(768, 1024)]);
+        assert!(live_allocations.contains_key(&1));
+
+        // The right allocation can still be freed afterwards
+        DeviceMemoryAllocator::release_memory_range(&mut live_allocations, &mut device_allocations, &get_allocation_info(1, MEMORY_INDEX, (256, 512))).unwrap();
+        assert_eq!(get_free_ranges(&device_allocations), vec![(256, 512), (768, 1024)]);
+    }
+
+    fn get_host_allocator() -> VkHostAllocator {
+        VkHostAllocator {
+            host_allocations: HashMap::new(),
+            allocated_host_pointers: BTreeMap::new(),
+        }
+    }
+
+    // Checks that the live allocations and the free ranges of every pool cover it exactly once, and that free ranges that touch have been merged
+    fn check_host_pools(host_allocator: &VkHostAllocator) {
+        for (&alignment, pools) in host_allocator.host_allocations.iter() {
+            for pool in pools {
+                let pool_range = pool.start_ptr as usize..pool.start_ptr as usize + pool.size;
+                let live_ranges = host_allocator.allocated_host_pointers.iter()
+                    .filter(|(&ptr, _)| pool_range.contains(&(ptr as usize)))
+                    .map(|(&ptr, live)| {
+                        assert_eq!(live.alignment, alignment, "A pointer is in a pool of another alignment");
+                        assert_eq!(ptr as usize % alignment, 0, "A pointer isn't aligned");
+                        let start = ptr as usize - pool_range.start;
+                        (start, start + live.reserved_size, false)
+                    });
+                let mut ranges = pool.free_allocations.iter().map(|&(start, end)| (start, end, true)).chain(live_ranges).collect::<Vec<_>>();
+                ranges.sort_unstable();
+
+                let mut end_of_previous = 0;
+                let mut is_previous_free = false;
+                for &(start, end, is_free) in ranges.iter() {
+                    assert!(start < end, "Empty range {}..{}", start, end);
+                    assert_eq!(start, end_of_previous, "The ranges overlap or leave a gap at {}", start);
+                    assert!(!(is_free && is_previous_free), "The free ranges that touch at {} weren't merged", start);
+                    end_of_previous = end;
+                    is_previous_free = is_free;
+                }
+                assert_eq!(end_of_previous, pool.size);
+            }
+        }
+    }
+
+    fn fill(ptr: *mut c_void, size: usize, value: u8) {
+        unsafe { std::ptr::write_bytes(ptr as *mut u8, value, size) };
+    }
+
+    fn is_filled_with(ptr: *mut c_void, size: usize, value: u8) -> bool {
+        unsafe { std::slice::from_raw_parts(ptr as *const u8, size) }.iter().all(|&byte| byte == value)
+    }
+
+    #[test]
+    fn random_host_allocations_never_overlap() {
+        let mut rng = StdRng::seed_from_u64(342);
+        let mut host_allocator = get_host_allocator();
+        // The size, alignment and the byte every live allocation is filled with
+        let mut live: Vec<(*mut c_void, usize, usize, u8)> = Vec::new();
+
+        for step in 0..5000 {
+            let value = (step % 251) as u8;
+            match rng.gen_range(0..3) {
+                0 => {
+                    let size = rng.gen_range(0..20_000);
+                    let alignment = 1 << rng.gen_range(0..9);
+                    let ptr = host_allocator.allocate_host_memory(size, alignment).unwrap();
+                    fill(ptr, size, value);
+                    live.push((ptr, size, alignment, value));
+                },
+                1 if !live.is_empty() => {
+                    let (ptr, size, _, expected) = live.swap_remove(rng.gen_range(0..live.len()));
+                    assert!(is_filled_with(ptr, size, expected), "An allocation was written through another one");
+                    unsafe { host_allocator.free_host_memory(ptr) }.unwrap();
+                },
+                2 if !live.is_empty() => {
+                    let index = rng.gen_range(0..live.len());
+                    let (ptr, size, alignment, expected) = live[index];
+                    let new_size = rng.gen_range(1..20_000);
+                    let new_ptr = unsafe { host_allocator.reallocate(ptr, new_size, alignment) }.unwrap();
+                    assert_eq!(new_ptr as usize % alignment, 0);
+                    assert!(is_filled_with(new_ptr, size.min(new_size), expected), "Reallocating lost the data");
+                    fill(new_ptr, new_size, value);
+                    live[index] = (new_ptr, new_size, alignment, value);
+                },
+                _ => {},
+            }
+            check_host_pools(&host_allocator);
+        }
+
+        for (ptr, size, _, expected) in live.drain(..) {
+            assert!(is_filled_with(ptr, size, expected));
+            unsafe { host_allocator.free_host_memory(ptr) }.unwrap();
+        }
+        check_host_pools(&host_allocator);
+        // Everything merged back into one free range per pool
+        assert!(host_allocator.host_allocations.values().flatten().all(|pool| pool.free_allocations == vec![(0, pool.size)]));
+        unsafe { host_allocator.free_all_host_memory() }.unwrap();
+    }
+
+    #[test]
+    fn freeing_a_host_pointer_twice_fails() {
+        let mut host_allocator = get_host_allocator();
+        let ptr = host_allocator.allocate_host_memory(100, 16).unwrap();
+        unsafe { host_allocator.free_host_memory(ptr) }.unwrap();
+        assert!(unsafe { host_allocator.free_host_memory(ptr) }.is_err());
+        assert!(unsafe { host_allocator.reallocate(ptr, 200, 16) }.is_err());
+        check_host_pools(&host_allocator);
+        unsafe { host_allocator.free_all_host_memory() }.unwrap();
+    }
+
+    #[test]
+    fn allocator_types_can_be_shared_with_worker_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        fn assert_send<T: Send>() {}
+        assert_send_sync::<VkAllocator>();
+        assert_send_sync::<Mutex<DeviceMemoryAllocator>>();
+        assert_send_sync::<Mutex<VkHostAllocator>>();
+        assert_send::<AllocationInfo>();
+    }
+
+    #[test]
+    fn host_callbacks_from_many_threads_never_hand_out_a_live_pointer_twice() {
+        const NUM_THREADS: usize = 8;
+        const NUM_STEPS: usize = 2000;
+        let host_allocator = Arc::new(Mutex::new(get_host_allocator()));
+        // Like the allocation callbacks the driver gets, which hold one reference
+        let user_data = Arc::into_raw(host_allocator.clone()) as usize;
+        // Pointers are added after they are handed out and removed before they are freed, so a pointer that is handed out while it's live is found
+        let live_pointers = Arc::new(Mutex::new(HashSet::new()));
+
+        let threads = (0..NUM_THREADS).map(|thread_index| {
+            let live_pointers = live_pointers.clone();
+            std::thread::spawn(move || {
+                let user_data = user_data as *mut c_void;
+                let mut rng = StdRng::seed_from_u64(thread_index as u64);
+                let value = thread_index as u8 + 1;
+                let mut own: Vec<(*mut c_void, usize)> = Vec::new();
+                for _ in 0..NUM_STEPS {
+                    if own.is_empty() || rng.gen_bool(0.5) {
+                        let size = rng.gen_range(1..4096);
+                        let ptr = unsafe { pfn_allocation(user_data, size, 1 << rng.gen_range(0..7), SystemAllocationScope::OBJECT) };
+                        assert!(!ptr.is_null());
+                        assert!(live_pointers.lock().unwrap().insert(ptr as usize), "A live pointer was handed out twice");
+                        fill(ptr, size, value);
+                        own.push((ptr, size));
+                    } else if rng.gen_bool(0.5) {
+                        let (ptr, size) = own.swap_remove(rng.gen_range(0..own.len()));
+                        assert!(is_filled_with(ptr, size, value), "Another thread wrote to the allocation");
+                        assert!(live_pointers.lock().unwrap().remove(&(ptr as usize)));
+                        unsafe { pfn_free(user_data, ptr) };
+                    } else {
+                        let index = rng.gen_range(0..own.len());
+                        let (ptr, size) = own[index];
+                        let new_size = rng.gen_range(1..4096);
+                        assert!(live_pointers.lock().unwrap().remove(&(ptr as usize)));
+                        let new_ptr = unsafe { pfn_reallocation(user_data, ptr, new_size, 1, SystemAllocationScope::OBJECT) };
+                        assert!(!new_ptr.is_null());
+                        assert!(live_pointers.lock().unwrap().insert(new_ptr as usize), "A live pointer was handed out twice");
+                        assert!(is_filled_with(new_ptr, size.min(new_size), value), "Reallocating lost the data");
+                        fill(new_ptr, new_size, value);
+                        own[index] = (new_ptr, new_size);
+                    }
+                }
+                for (ptr, size) in own {
+                    assert!(is_filled_with(ptr, size, value));
+                    assert!(live_pointers.lock().unwrap().remove(&(ptr as usize)));
+                    unsafe { pfn_free(user_data, ptr) };
+                }
+            })
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(live_pointers.lock().unwrap().is_empty());
+        let mut host_allocator_guard = host_allocator.lock().unwrap();
+        assert!(host_allocator_guard.allocated_host_pointers.is_empty());
+        check_host_pools(&host_allocator_guard);
+        unsafe { host_allocator_guard.free_all_host_memory() }.unwrap();
+        drop(host_allocator_guard);
+        // Releases the reference of the callbacks
+        unsafe { drop(Arc::from_raw(user_data as *const Mutex<VkHostAllocator>)) };
+    }
+}
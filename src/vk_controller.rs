@@ -1,10 +1,12 @@
-use std::{borrow::Cow, collections::{HashMap, HashSet}, rc::Rc, sync::{Arc, RwLock}};
+use std::{borrow::Cow, ffi::{CStr, CString}, collections::{BTreeMap, HashMap, HashSet}, fmt, path::PathBuf, sync::{Arc, Mutex, PoisonError, RwLock}, time::Instant};
 
-use ash::{extensions::{ext::DebugUtils, khr::{Surface, Swapchain}}, vk::{self, DebugUtilsMessengerCreateInfoEXT, DescriptorSetLayoutBinding, DeviceCreateInfo, DeviceQueueCreateInfo, ExtDescriptorIndexingFn, Image, ImageView, InstanceCreateInfo, PhysicalDevice, Queue, StructureType, SurfaceKHR, SwapchainCreateInfoKHR, SwapchainKHR}, Device, Entry, Instance};
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use winit::window::Window;
+use ash::{extensions::{ext::{DebugUtils, HeadlessSurface}, khr::{Surface, Swapchain}}, vk::{self, DebugUtilsMessengerCreateInfoEXT, DescriptorSetLayoutBinding, DeviceCreateInfo, DeviceQueueCreateInfo, ExtDescriptorIndexingFn, Handle, Image, ImageView, InstanceCreateInfo, PhysicalDevice, Queue, StructureType, SurfaceKHR, SwapchainCreateInfoKHR, SwapchainKHR}, Device, Entry, Instance};
+use image::DynamicImage;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle};
+use winit::{event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent}, monitor::{MonitorHandle, VideoMode}, window::{Fullscreen, Window}};
+use nalgebra_glm as glm;
 
-use crate::{graphics_objects::{GraphicsObject, Renderable, ResourceID}, pipeline_manager::{ObjectTypeGraphicsResourceType, PipelineConfig, PipelineManager, Vertex}, sampler_manager::SamplerManager, object_manager::ObjectManager, vertex::SimpleVertex, vk_allocator::{AllocationInfo, Serializable, VkAllocator}};
+use crate::{logging, asset_resolver::AssetResolver, draw_recorder::{CommandBufferRecorder, DrawCommand, DrawRecorder}, async_loader::{AsyncLoader, LoadHandle, LoadStatus}, error::EngineError, graphics_objects::{GraphicsObject, LodGroup, Renderable, ResourceID, TextureHandle}, texture_manager::TextureManager, pipeline_manager::{PipelineConfig, PipelineManager, Vertex}, post_process::{PostEffect, PostProcessor}, sampler_manager::{SamplerConfig, SamplerManager}, sprite::{Ortho2D, SpriteAnimation}, text::{BitmapFont, TextRenderer}, upload_context::{AllocationsToFree, PreparedObject, UploadContext}, debug_draw::DebugDrawer, egui_renderer::EguiRenderer, lighting::{Light, LightId, LightManager}, object_manager::{ObjectManager, ObjectType}, renderdoc::RenderDocCapture, render_target::{RenderTargetId, RenderTargetManager}, vertex::SimpleVertex, vk_allocator::{AllocationInfo, VkAllocator}};
 
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub struct ObjectID(pub usize);
@@ -12,50 +14,824 @@ pub struct ObjectID(pub usize);
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub struct ReferenceObjectID(pub ObjectID);
 
+/// Returned by [`VkController::queue_remove_objects`], see [`VkController::poll_removal`].
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct RemovalHandle(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalStatus {
+    /// The objects are no longer drawn, but frames in flight can still use their buffers and descriptor sets.
+    Pending,
+    /// Everything the objects used on the GPU that no other object uses has been freed.
+    Freed,
+}
+
+/// Returned by [`VkController::add_camera`].
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct CameraId(pub u64);
+
+/// A part of the window that is drawn with its own camera, see [`VkController::set_viewports`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewportRegion {
+    /// The x, y, width and height as fractions of the window, with (0, 0) in the top left corner.
+    pub rect: (f32, f32, f32, f32),
+    pub camera: CameraId,
+    /// Only these objects are drawn in the region. None draws every object.
+    pub object_filter: Option<HashSet<ObjectID>>,
+}
+
+/// One of the views the frames are drawn with. The regions set with [`VkController::set_viewports`] are kept, and follow the window and their cameras.
+/// The views given to [`VkController::draw_views`] are only drawn in that frame, so they keep their pixels and view projection.
+enum View {
+    Region(ViewportRegion),
+    Pixels { render_rect: vk::Rect2D, view_projection: glm::Mat4 },
+}
+
+/// One of the views a frame is drawn with, in pixels of the current swapchain.
+struct FrameView {
+    render_rect: vk::Rect2D,
+    view: glm::Mat4,
+    projection: glm::Mat4,
+    view_projection: glm::Mat4,
+    object_filter: Option<Vec<ObjectID>>,
+}
+
+/// Counts the snapshots of the objects' resource data, one for each drawn frame.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct RenderableDataVersion(pub u64);
+
 type FrameCounter = usize;
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub struct VerticesIndicesHash(pub u64);
 pub type VertexAllocation = AllocationInfo;
 pub type IndexAllocation = AllocationInfo;
 
+/// How the object types are ordered when the command buffers are recorded.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum DrawOrder {
+    /// Pipelines and object types are drawn in the order they were first added.
+    InsertionOrder,
+    /// Object types are drawn by ascending key set with [`VkController::set_draw_order_key`]. Object types without a key use 0, and ties keep the insertion order.
+    SortKey,
+}
+
+/// The pipeline statistics of one frame's render pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+/// Where the times of a [`PresentTiming`] come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentTimingSource {
+    /// `VK_GOOGLE_display_timing`, so the times are when the images were actually shown on the display.
+    DisplayTiming,
+    /// The CPU time around `queue_present`, which is when the images were given to the presentation engine.
+    CpuQueuePresent,
+}
+
+/// The timing of the last present, for measuring the frame pacing and the throughput with the `IMMEDIATE` present mode, see [`VkController::last_present_timing`].
+#[derive(Clone, Copy, PartialEq)]
+pub struct PresentTiming {
+    pub source: PresentTimingSource,
+    /// The present mode the swapchain uses, which is `FIFO` when the one given to [`VkControllerBuilder::present_mode`] isn't supported.
+    pub present_mode: vk::PresentModeKHR,
+    /// Only `IMMEDIATE` can show a new image in the middle of a refresh, which tears.
+    pub is_tearing: bool,
+    /// The time between the last two presents. None until there has been two.
+    pub present_interval_ms: Option<f32>,
+    /// How long `queue_present` blocked the CPU, which is where `FIFO` waits for the display.
+    pub queue_present_ms: f32,
+    /// How much earlier the image could have been shown. Only with display timing.
+    pub present_margin_ms: Option<f32>,
+    /// The refresh duration of the display. Only with display timing.
+    pub refresh_cycle_ms: Option<f32>,
+}
+
+// The present mode is shown as the raw value, since ash only implements Debug for it with the debug feature
+impl fmt::Debug for PresentTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PresentTiming")
+            .field("source", &self.source)
+            .field("present_mode", &self.present_mode.as_raw())
+            .field("is_tearing", &self.is_tearing)
+            .field("present_interval_ms", &self.present_interval_ms)
+            .field("queue_present_ms", &self.queue_present_ms)
+            .field("present_margin_ms", &self.present_margin_ms)
+            .field("refresh_cycle_ms", &self.refresh_cycle_ms)
+            .finish()
+    }
+}
+
+/// Everything needed to record the draw of one object type. The counts use the same naming as the other parts of the engine, so `num_indices` is 0 for object types without indices.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DrawBatch {
+    pub object_type: ObjectType,
+    pub vertex_buffer: vk::Buffer,
+    pub first_vertex: u32,
+    pub num_vertices: u32,
+    // None for object types without indices, they are drawn with a non-indexed draw
+    pub index_buffer: Option<vk::Buffer>,
+    pub index_type: vk::IndexType,
+    pub first_index: u32,
+    pub num_indices: u32,
+    // Only render targets draw from a later instance, when they draw some of the object type's objects
+    pub first_instance: u32,
+    pub num_instances: u32,
+    pub descriptor_set: vk::DescriptorSet,
+    // 0 for object types without dynamic uniform buffers. They all have the same stride, so every one of them gets the instance index times the stride as its dynamic offset
+    pub num_dynamic_uniform_buffers: u32,
+    pub dynamic_uniform_buffer_stride: u32,
+}
+
+// Written by hand, since the Vulkan enums only implement Debug with ash's debug feature
+impl fmt::Debug for DrawBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DrawBatch")
+            .field("object_type", &self.object_type)
+            .field("vertex_buffer", &self.vertex_buffer)
+            .field("first_vertex", &self.first_vertex)
+            .field("num_vertices", &self.num_vertices)
+            .field("index_buffer", &self.index_buffer)
+            .field("index_type", &self.index_type.as_raw())
+            .field("first_index", &self.first_index)
+            .field("num_indices", &self.num_indices)
+            .field("first_instance", &self.first_instance)
+            .field("num_instances", &self.num_instances)
+            .field("descriptor_set", &self.descriptor_set)
+            .field("num_dynamic_uniform_buffers", &self.num_dynamic_uniform_buffers)
+            .field("dynamic_uniform_buffer_stride", &self.dynamic_uniform_buffer_stride)
+            .finish()
+    }
+}
+
+/// The draw of an object type with the pipeline it is drawn with.
+#[derive(Clone)]
+struct ObjectDraw {
+    // The index in the draws of the frame, which is written to the object id attachment for picking
+    draw_index: usize,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // The name of the debug label around the draws of the pipeline, None when the draws are not labeled
+    pipeline_label: Option<String>,
+    draw_batch: DrawBatch,
+}
+
+/// The state a frame's command buffer is recorded with.
+struct FrameRecording<'a> {
+    command_buffer: vk::CommandBuffer,
+    current_frame: usize,
+    image_index: usize,
+    swapchain_image: vk::Image,
+    // The image the render pass draws to, before it is post processed into the swapchain image
+    scene_image: vk::Image,
+    scene_framebuffer: vk::Framebuffer,
+    swapchain_extent: &'a vk::Extent2D,
+    global_descriptor_set: vk::DescriptorSet,
+    bindless_texture_descriptor_set: Option<vk::DescriptorSet>,
+    clear_mode: ClearMode,
+    views: &'a [FrameView],
+    timestamp_query_pool: Option<vk::QueryPool>,
+    pipeline_statistics_query_pool: Option<vk::QueryPool>,
+    // Filled with what was recorded, when the frame report is enabled
+    frame_report: Option<&'a mut FrameReport>,
+    // The debug labels are only recorded when there is a loader
+    debug_utils_loader: Option<&'a DebugUtils>,
+    // The overlays, which are drawn over the objects
+    text_renderer: Option<&'a TextRenderer>,
+    debug_drawer: Option<&'a DebugDrawer>,
+    egui_renderer: Option<&'a EguiRenderer>,
+    extra_recording: Option<&'a mut Box<dyn FnMut(vk::CommandBuffer)>>,
+}
+
+/// The state that was bound by the last draws, so it is only bound again when it changes.
+#[derive(Default)]
+struct BoundDrawState {
+    vertex_buffer: Option<vk::Buffer>,
+    index_buffer: Option<(vk::Buffer, vk::IndexType)>,
+    descriptor_set: Option<vk::DescriptorSet>,
+}
+
+/// The engine's clock, which is advanced once at the start of every drawn frame. The same times are in the per-frame data of the shaders.
+#[derive(Debug, Clone, Copy)]
+pub struct Time {
+    elapsed_seconds: f32,
+    delta_seconds: f32,
+    frame_index: u64,
+    max_delta_seconds: f32,
+    is_paused: bool,
+    last_update: Instant,
+}
+
+impl Time {
+    pub const DEFAULT_MAX_DELTA_SECONDS: f32 = 0.1;
+
+    pub fn new(now: Instant) -> Self {
+        Self {
+            elapsed_seconds: 0.0,
+            delta_seconds: 0.0,
+            frame_index: 0,
+            max_delta_seconds: Self::DEFAULT_MAX_DELTA_SECONDS,
+            is_paused: false,
+            last_update: now,
+        }
+    }
+
+    /// Starts a new frame. The delta is clamped to the max delta, so a stall like dragging the window doesn't make everything jump ahead.
+    pub fn update(&mut self, now: Instant) {
+        let delta_seconds = now.saturating_duration_since(self.last_update).as_secs_f32().min(self.max_delta_seconds);
+        self.last_update = now;
+        self.delta_seconds = if self.is_paused { 0.0 } else { delta_seconds };
+        self.elapsed_seconds += self.delta_seconds;
+        self.frame_index += 1;
+    }
+
+    /// Seconds since the renderer was created, without the time it was paused and the part of the deltas that was clamped away.
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed_seconds
+    }
+
+    /// Seconds since the last frame, 0 while paused.
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta_seconds
+    }
+
+    /// The number of frames drawn, including the frames drawn while paused.
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    pub fn max_delta_seconds(&self) -> f32 {
+        self.max_delta_seconds
+    }
+
+    pub fn set_max_delta_seconds(&mut self, max_delta_seconds: f32) {
+        self.max_delta_seconds = max_delta_seconds.max(0.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Freezes the time while the frames are still drawn, which stops shader animations and sprite animations.
+    pub fn set_paused(&mut self, is_paused: bool) {
+        self.is_paused = is_paused;
+    }
+}
+
+/// The limits and optional features of the physical device the renderer picked, see [`VkController::device_capabilities`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCapabilities {
+    pub device_name: String,
+    pub max_push_constants_size: u32,
+    pub max_per_stage_descriptor_samplers: u32,
+    pub max_descriptor_set_samplers: u32,
+    /// The highest sample count usable for both color and depth attachments, like 8 for 8x MSAA.
+    pub max_msaa_samples: u32,
+    pub is_sampler_anisotropy_supported: bool,
+    pub max_sampler_anisotropy: f32,
+    pub is_geometry_shader_supported: bool,
+    pub is_pipeline_statistics_query_supported: bool,
+    pub is_descriptor_indexing_supported: bool,
+    /// 0 when bindless textures aren't supported.
+    pub max_bindless_textures: u32,
+    pub max_image_dimension_2d: u32,
+    pub max_image_dimension_3d: u32,
+    pub max_image_dimension_cube: u32,
+    pub max_image_array_layers: u32,
+    /// The size in bytes of every memory heap, and whether it is device local.
+    pub memory_heaps: Vec<(u64, bool)>,
+}
+
+/// What was recorded for each pipeline and object type in the last frame, see [`VkController::frame_debug_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameReport {
+    pub pipelines: Vec<PipelineReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineReport {
+    pub shader_paths: Vec<String>,
+    pub object_types: Vec<ObjectTypeReport>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectTypeReport {
+    pub object_type: VerticesIndicesHash,
+    pub num_instances: usize,
+    pub num_indices: usize,
+    pub vertex_byte_range: (usize, usize),
+    pub index_byte_range: (usize, usize),
+    pub num_descriptor_sets: usize,
+    // The textures, uniform buffers and storage buffers of the object type. Textures shared with other object types are counted for each of them
+    pub estimated_memory_bytes: u64,
+}
+
+impl fmt::Display for FrameReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let num_object_types = self.pipelines.iter().map(|pipeline| pipeline.object_types.len()).sum::<usize>();
+        writeln!(f, "Frame report: {} pipelines, {} object types", self.pipelines.len(), num_object_types)?;
+        for pipeline in self.pipelines.iter() {
+            writeln!(f, "Pipeline {}", pipeline.shader_paths.join(", "))?;
+            for object_type in pipeline.object_types.iter() {
+                writeln!(f, "    ObjectType({}): {} instances, {} indices, vertex bytes {}..{}, index bytes {}..{}, {} descriptor sets, ~{} bytes",
+                    object_type.object_type.0,
+                    object_type.num_instances,
+                    object_type.num_indices,
+                    object_type.vertex_byte_range.0, object_type.vertex_byte_range.1,
+                    object_type.index_byte_range.0, object_type.index_byte_range.1,
+                    object_type.num_descriptor_sets,
+                    object_type.estimated_memory_bytes,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the color attachment is initialized at the start of every frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClearMode {
+    /// Clears to the RGBA color. The scene is drawn in a linear format, so the color is linear like the shader outputs, not sRGB.
+    Clear([f32; 4]),
+    /// Leaves the contents undefined, for when every pixel is drawn anyway.
+    DontCare,
+    /// Keeps what was drawn in the previous frame.
+    Load,
+}
+
+impl ClearMode {
+    pub fn get_attachment_load_op(&self) -> vk::AttachmentLoadOp {
+        match self {
+            ClearMode::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            ClearMode::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+            ClearMode::Load => vk::AttachmentLoadOp::LOAD,
+        }
+    }
+}
+
+/// How the window covers the screen, see [`VkController::set_fullscreen`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Windowed,
+    /// A window without decorations that covers the monitor it is on.
+    Borderless,
+    /// Takes over the monitor and changes its video mode, not every platform supports it.
+    Exclusive,
+}
+
+/// How many textures the object types share. `hits` counts the textures that were reused instead of uploaded again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureCacheStats {
+    pub unique_textures: usize,
+    pub total_bytes: u64,
+    pub hits: usize,
+    /// How much less memory the textures take than they would with [`TextureQuality::Full`] and full mip chains.
+    pub estimated_saved_bytes: u64,
+}
+
+/// The samplers shared through the sampler manager. `reuse_hits` counts the times an existing sampler was returned instead of creating one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerStats {
+    pub live_samplers: usize,
+    pub total_created: usize,
+    pub reuse_hits: usize,
+}
+
+/// A resource of an object type that couldn't be used, so the object type is drawn with a fallback until it's fixed, see [`VkController::get_resource_errors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceError {
+    /// The texture is the magenta checker texture until the resource has an image that can be uploaded.
+    MissingTexture { object_type: VerticesIndicesHash, resource_id: ResourceID, reason: String },
+    /// The object type is drawn in magenta with the error pipeline until its shaders are modified and work.
+    FailedPipeline { object_type: VerticesIndicesHash, shaders: Vec<String>, reason: String },
+}
+
+/// Trades texture memory for quality, see [`VkController::set_texture_quality`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TextureQuality {
+    #[default]
+    Full,
+    /// Half the width and height, which is a quarter of the memory.
+    Half,
+    Quarter,
+}
+
+impl TextureQuality {
+    /// How many times the width and height are halved before the texture is uploaded.
+    pub fn get_skipped_mip_levels(&self) -> u32 {
+        match self {
+            TextureQuality::Full => 0,
+            TextureQuality::Half => 1,
+            TextureQuality::Quarter => 2,
+        }
+    }
+}
+
+/// How the uploads used the staging ring, see [`VkController::staging_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StagingStats {
+    pub ring_size: u64,
+    /// The most bytes of the ring that were waiting for their copies at the same time.
+    pub high_water_mark: u64,
+    pub ring_uploads: usize,
+    /// Uploads bigger than the ring, which got their own staging buffer.
+    pub fallback_uploads: usize,
+}
+
+/// What an allocation is for, decided from the usage its buffer or image was created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AllocationKind {
+    VertexBuffer,
+    IndexBuffer,
+    UniformBuffer,
+    StorageBuffer,
+    TextureImage,
+    DepthImage,
+    /// Images that are rendered to, like the scene image, the multisampled attachments and render targets.
+    ColorTarget,
+    /// Buffers that are only copied from or to, like the staging buffers and the readback buffers.
+    Staging,
+    /// Buffers with none of the usages above.
+    Other,
+}
+
+impl AllocationKind {
+    pub fn of_buffer(usage: vk::BufferUsageFlags) -> Self {
+        if usage.contains(vk::BufferUsageFlags::VERTEX_BUFFER) {
+            AllocationKind::VertexBuffer
+        } else if usage.contains(vk::BufferUsageFlags::INDEX_BUFFER) {
+            AllocationKind::IndexBuffer
+        } else if usage.contains(vk::BufferUsageFlags::UNIFORM_BUFFER) {
+            AllocationKind::UniformBuffer
+        } else if usage.contains(vk::BufferUsageFlags::STORAGE_BUFFER) {
+            AllocationKind::StorageBuffer
+        } else if usage.intersects(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST) {
+            AllocationKind::Staging
+        } else {
+            AllocationKind::Other
+        }
+    }
+
+    pub fn of_image(usage: vk::ImageUsageFlags) -> Self {
+        if usage.contains(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT) {
+            AllocationKind::DepthImage
+        } else if usage.contains(vk::ImageUsageFlags::COLOR_ATTACHMENT) {
+            AllocationKind::ColorTarget
+        } else {
+            AllocationKind::TextureImage
+        }
+    }
+}
+
+/// The live allocations of one [`AllocationKind`], see [`VkController::allocation_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocationKindStats {
+    pub num_allocations: usize,
+    pub total_bytes: u64,
+}
+
+/// The memory of a heap, see [`VkController::memory_budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapBudget {
+    pub heap_index: u32,
+    pub is_device_local: bool,
+    /// The bytes of the memory blocks the engine has allocated from the heap.
+    pub engine_usage: u64,
+    /// What the whole process uses when `VK_EXT_memory_budget` is available, otherwise the same as `engine_usage`.
+    pub usage: u64,
+    /// How much the process can use before the driver starts paging when `VK_EXT_memory_budget` is available, otherwise the size of the heap.
+    pub budget: u64,
+}
+
+/// How close the fullest device-local heap is to the part of its budget the allocator may use, see [`VkController::set_memory_pressure_callback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPressureLevel {
+    Normal,
+    High,
+    /// The next allocations can fail with [`EngineError::OutOfDeviceMemory`].
+    Critical,
+}
+
+impl MemoryPressureLevel {
+    pub const HIGH_THRESHOLD: f64 = 0.75;
+    pub const CRITICAL_THRESHOLD: f64 = 0.9;
+
+    /// The thresholds are fractions of the usable part of the heap's budget, which is `budget_fraction` of it.
+    pub fn of(heap_budget: &HeapBudget, budget_fraction: f32) -> Self {
+        let usable_bytes = heap_budget.budget as f64 * budget_fraction as f64;
+        let used_fraction = if usable_bytes > 0.0 { heap_budget.usage as f64 / usable_bytes } else { 1.0 };
+        if used_fraction >= Self::CRITICAL_THRESHOLD {
+            MemoryPressureLevel::Critical
+        } else if used_fraction >= Self::HIGH_THRESHOLD {
+            MemoryPressureLevel::High
+        } else {
+            MemoryPressureLevel::Normal
+        }
+    }
+}
+
+/// What [`VkController::defragment_memory`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DefragmentationReport {
+    pub moved_allocations: usize,
+    pub moved_bytes: u64,
+    /// The memory blocks that were given back to the driver.
+    pub released_blocks: usize,
+    /// The live allocations that were left where they are. Only vertex and index buffers are moved, so this includes every image and every uniform and storage buffer.
+    pub unmoved_allocations: usize,
+    pub unmoved_bytes: u64,
+}
+
 #[cfg(debug_assertions)]
 const IS_DEBUG_MODE: bool = true;
 #[cfg(not(debug_assertions))]
 const IS_DEBUG_MODE: bool = false;
 
+/// Configures what [`VkController::new`] otherwise picks on its own. Every option that is not set keeps the default [`VkController::new`] uses.
+#[derive(Clone)]
+pub struct VkControllerBuilder {
+    present_mode: vk::PresentModeKHR,
+    msaa_samples: Option<vk::SampleCountFlags>,
+    frames_in_flight: usize,
+    surface_format_preference: Vec<vk::Format>,
+    swapchain_image_count: Option<u32>,
+    is_validation_enabled: bool,
+    clear_color: [f32; 4],
+    is_bindless_required: bool,
+    staging_ring_size: u64,
+    use_host_allocation_callbacks: bool,
+    scene_format: vk::Format,
+}
+
+impl Default for VkControllerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VkControllerBuilder {
+    pub fn new() -> Self {
+        Self {
+            present_mode: vk::PresentModeKHR::MAILBOX,
+            msaa_samples: None,
+            frames_in_flight: 2,
+            surface_format_preference: Vec::new(),
+            swapchain_image_count: None,
+            is_validation_enabled: IS_DEBUG_MODE,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            is_bindless_required: false,
+            staging_ring_size: VkAllocator::DEFAULT_STAGING_RING_BYTE_SIZE,
+            use_host_allocation_callbacks: false,
+            scene_format: VkController::DEFAULT_SCENE_FORMAT,
+        }
+    }
+
+    /// The format the main pass draws the scene in before the post effects write it to the swapchain. Defaults to [`VkController::DEFAULT_SCENE_FORMAT`].
+    /// A floating point format keeps the values above 1 that the shaders output until a tonemap effect maps them, an 8-bit format clamps them.
+    /// The default is used instead when the device can't render to, blend and sample the format.
+    pub fn scene_format(mut self, scene_format: vk::Format) -> Self {
+        self.scene_format = scene_format;
+        self
+    }
+
+    /// Gives Vulkan allocation callbacks that take its host memory from the engine's own tracking allocator, instead of letting the driver allocate it. Off by default.
+    pub fn use_host_allocation_callbacks(mut self, use_host_allocation_callbacks: bool) -> Self {
+        self.use_host_allocation_callbacks = use_host_allocation_callbacks;
+        self
+    }
+
+    /// The size in bytes of the persistently mapped buffer that vertex, index and texture uploads are copied through. Defaults to 64 MB.
+    /// Bigger uploads still work, they get their own staging buffer.
+    pub fn staging_ring_size(mut self, staging_ring_size: u64) -> Self {
+        self.staging_ring_size = staging_ring_size;
+        self
+    }
+
+    /// Only picks physical devices that support bindless textures, see [`VkController::enable_bindless_textures`].
+    /// Defaults to false, which also allows devices without `VK_EXT_descriptor_indexing`. Bindless textures are then only available when the picked device happens to support them.
+    pub fn require_bindless_textures(mut self, is_bindless_required: bool) -> Self {
+        self.is_bindless_required = is_bindless_required;
+        self
+    }
+
+    /// Defaults to `MAILBOX`. FIFO is used when the surface doesn't support the mode, since every surface supports FIFO.
+    pub fn present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    /// Defaults to the highest sample count the physical device supports for both color and depth. Higher counts are lowered to that.
+    pub fn msaa_samples(mut self, msaa_samples: vk::SampleCountFlags) -> Self {
+        self.msaa_samples = Some(msaa_samples);
+        self
+    }
+
+    /// Defaults to 2. Building fails when it is outside 1..=[`VkController::MAX_FRAMES_IN_FLIGHT`].
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    /// The swapchain formats to try first, in order. The color space has to be `SRGB_NONLINEAR`.
+    /// Defaults to none, which goes straight to `B8G8R8A8_SRGB`, then `R8G8B8A8_SRGB`, then any other sRGB format, and at last whatever the surface lists first.
+    pub fn surface_format_preference(mut self, formats: Vec<vk::Format>) -> Self {
+        self.surface_format_preference = formats;
+        self
+    }
+
+    /// The number of swapchain images to ask for, clamped to what the surface supports. Defaults to one more than the surface's minimum.
+    /// More images let more frames be queued for presentation, fewer lower the latency.
+    pub fn swapchain_image_count(mut self, swapchain_image_count: u32) -> Self {
+        self.swapchain_image_count = Some(swapchain_image_count);
+        self
+    }
+
+    /// Enables the validation layers and the debug messenger. Defaults to true in debug builds and false in release builds.
+    pub fn validation(mut self, is_validation_enabled: bool) -> Self {
+        self.is_validation_enabled = is_validation_enabled;
+        self
+    }
+
+    /// Defaults to opaque black. The color is linear, see [`ClearMode::Clear`].
+    pub fn clear_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
+        self.clear_color = [r, g, b, a];
+        self
+    }
+
+    /// Panics when the renderer can't be created, see [`VkControllerBuilder::try_build`] for handling that.
+    pub fn build(self, window: Window, application_name: &str) -> VkController {
+        self.try_build(window, application_name).unwrap()
+    }
+
+    /// Returns an error instead of panicking when, for example, there is no GPU with the features the engine needs.
+    pub fn try_build(self, window: Window, application_name: &str) -> Result<VkController, EngineError> {
+        let display_handle = window.raw_display_handle();
+        let window_handle = window.raw_window_handle();
+        let size = window.inner_size();
+        let window_extent = vk::Extent2D { width: size.width, height: size.height };
+        unsafe { VkController::new_with_handles(Some(window), Some((display_handle, window_handle)), window_extent, application_name, self) }
+    }
+
+    /// See [`VkController::new_from_raw_handles`].
+    ///
+    /// # Safety
+    /// The handles have to be valid and the window they refer to has to outlive the returned [`VkController`].
+    pub unsafe fn build_from_raw_handles(self, display_handle: RawDisplayHandle, window_handle: RawWindowHandle, initial_extent: vk::Extent2D, application_name: &str) -> VkController {
+        self.try_build_from_raw_handles(display_handle, window_handle, initial_extent, application_name).unwrap()
+    }
+
+    /// # Safety
+    /// The same as [`VkControllerBuilder::build_from_raw_handles`].
+    pub unsafe fn try_build_from_raw_handles(self, display_handle: RawDisplayHandle, window_handle: RawWindowHandle, initial_extent: vk::Extent2D, application_name: &str) -> Result<VkController, EngineError> {
+        VkController::new_with_handles(None, Some((display_handle, window_handle)), initial_extent, application_name, self)
+    }
+
+    /// Presents to a surface without a window with VK_EXT_headless_surface, for tests and tools that don't show the frames.
+    /// Like for raw handles, the size is set with [`VkController::set_window_extent`].
+    pub fn try_build_headless(self, initial_extent: vk::Extent2D, application_name: &str) -> Result<VkController, EngineError> {
+        unsafe { VkController::new_with_handles(None, None, initial_extent, application_name, self) }
+    }
+
+    // Checked before anything is created, so every way of building fails the same
+    fn validate(&self) -> Result<(), EngineError> {
+        if !(1..=VkController::MAX_FRAMES_IN_FLIGHT).contains(&self.frames_in_flight) {
+            return Err(EngineError::from(format!("The number of frames in flight has to be from 1 to {}, but it is {}", VkController::MAX_FRAMES_IN_FLIGHT, self.frames_in_flight)));
+        }
+        Ok(())
+    }
+}
+
 pub struct VkController {
-    window: Window,
+    // None when the renderer was created from raw handles, the host application owns the window then
+    window: Option<Window>,
+    // Only used when there is no window, the host application has to keep it up to date with set_window_extent
+    window_extent: vk::Extent2D,
     entry: Entry,
-    instance: Rc<Instance>,
+    instance: Arc<Instance>,
     debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
+    // Only set when the debug messenger is enabled
+    debug_utils_loader: Option<DebugUtils>,
     physical_device: PhysicalDevice,
-    device: Rc<Device>,
+    device: Arc<Device>,
     graphics_queue: Queue,
     present_queue: Queue,
     surface: SurfaceKHR,
     swapchain_loader: Swapchain,
     swapchain: SwapchainKHR,
+    // The swapchains replaced by a recreation, with the number of frames until they are destroyed. Presents to them can still be pending after the fences of the frames in flight
+    retired_swapchains: Vec<(SwapchainKHR, usize)>,
     swapchain_images: Vec<Image>,
     swapchain_image_format: vk::Format,
     swapchain_extent: vk::Extent2D,
     swapchain_image_views: Vec<ImageView>,
-    swapchain_framebuffers: Vec<vk::Framebuffer>,
+    // The main pass draws into the scene image, which the post processor then writes to the swapchain image
+    scene_framebuffer: vk::Framebuffer,
+    scene_image_allocation: Option<AllocationInfo>,
+    scene_format: vk::Format,
+    post_processor: PostProcessor,
+    // The engine encodes to sRGB itself when the swapchain format doesn't, see set_linear_workflow
+    is_linear_workflow: bool,
     command_pool: vk::CommandPool,
+    // Only used through the allocator by the upload contexts, so their threads never share it with the frame's command buffers
+    upload_command_pool: vk::CommandPool,
     command_buffers: Vec<Vec<vk::CommandBuffer>>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
     current_frame: usize,
+    // The command buffers, sync objects and object buffers are made for this many frames. The engine's own buffers are made for MAX_FRAMES_IN_FLIGHT frames, but only this many are cycled through
+    frames_in_flight: usize,
+    present_mode: vk::PresentModeKHR,
+    // The mode the swapchain was created with, since the requested one might not be supported
+    active_present_mode: vk::PresentModeKHR,
+    surface_format_preference: Vec<vk::Format>,
+    swapchain_image_count: Option<u32>,
     pub frame_buffer_resized: bool,
     is_minimized: bool,
     descriptor_pool: vk::DescriptorPool,
     color_image_allocation: Option<AllocationInfo>,
     depth_image_allocation: Option<AllocationInfo>,
+    // The single sampled image the multisampled depth is resolved into, None when multisampling is not used
+    depth_resolve_image_allocation: Option<AllocationInfo>,
+    // Only set when picking is enabled, the resolve image is None when multisampling is not used
+    object_id_image_allocation: Option<AllocationInfo>,
+    object_id_resolve_image_allocation: Option<AllocationInfo>,
+    // The object types of the draws in the last submitted frame, indexed by the draw index in the object id attachment. None until a frame with picking has been drawn
+    picking_draw_object_types: Option<Vec<ObjectType>>,
+    // The extra color attachments of the main pass and their resolve images, which are None when multisampling is not used
+    extra_color_attachment_allocations: Vec<(AllocationInfo, Option<AllocationInfo>)>,
     msaa_samples: vk::SampleCountFlags,
-    allocator: VkAllocator,
+    allocator: Arc<VkAllocator>,
     graphics_pipeline_manager: PipelineManager,
     sampler_manager: SamplerManager,
     object_manager: ObjectManager,
+    num_recorded_commands: usize,
+    is_frame_report_enabled: bool,
+    // None renders to the whole window
+    render_rect: Option<vk::Rect2D>,
+    // Empty draws one view with the render rect, view and projection set on the controller. draw_views replaces them for its frame
+    views: Vec<View>,
+    // The view and projection of each camera
+    cameras: HashMap<CameraId, (glm::Mat4, glm::Mat4)>,
+    next_camera_id: u64,
+    clear_mode: ClearMode,
+    fullscreen_mode: FullscreenMode,
+    // Alt+Enter toggles borderless fullscreen when the window events are given to handle_window_event
+    is_fullscreen_toggle_enabled: bool,
+    modifiers: ModifiersState,
+    is_depth_kept: bool,
+    // Set when the depth of the last submitted frame is stored and can be read back
+    is_depth_available: bool,
+    frame_report: FrameReport,
+    global_frame_data_allocation: AllocationInfo,
+    light_manager: LightManager,
+    global_descriptor_sets: Vec<vk::DescriptorSet>,
+    view: glm::Mat4,
+    projection: glm::Mat4,
+    time: Time,
+    // 0 when the physical device does not support the descriptor indexing features needed for bindless textures
+    max_bindless_textures: u32,
+    // Only set when bindless textures have been enabled
+    texture_manager: Option<TextureManager>,
+    render_target_manager: RenderTargetManager,
+    // Only set when a font has been set
+    text_renderer: Option<TextRenderer>,
+    // Created the first time a debug shape is drawn
+    debug_drawer: Option<DebugDrawer>,
+    // Created the first time egui is drawn, and never without the egui feature
+    egui_renderer: Option<EguiRenderer>,
+    // Recorded by the user at the end of the main pass, like a UI over the scene
+    extra_recording: Option<Box<dyn FnMut(vk::CommandBuffer)>>,
+    // None when the graphics queue does not support timestamps. Each frame in flight uses two queries, one for the start and one for the end of the frame
+    timestamp_query_pool: Option<vk::QueryPool>,
+    // The number of nanoseconds per timestamp tick
+    timestamp_period: f32,
+    timestamp_valid_bits: u32,
+    are_timestamps_written: Vec<bool>,
+    last_frame_gpu_time_ms: Option<f32>,
+    // None when the physical device does not support pipeline statistics queries. Each frame in flight uses one query
+    pipeline_statistics_query_pool: Option<vk::QueryPool>,
+    are_pipeline_statistics_written: Vec<bool>,
+    last_frame_pipeline_stats: Option<PipelineStats>,
+    // None when the device does not support VK_GOOGLE_display_timing, then the CPU time around queue_present is used
+    display_timing_fn: Option<vk::GoogleDisplayTimingFn>,
+    next_present_id: u32,
+    last_present_instant: Option<Instant>,
+    // The actual present time of the newest image the display timing has reported for the current swapchain
+    last_actual_present_time: Option<u64>,
+    last_present_timing: Option<PresentTiming>,
+    render_doc_capture: RenderDocCapture,
+    async_loader: AsyncLoader,
+    upload_allocations_to_free: AllocationsToFree,
+    memory_pressure_callback: Option<Box<dyn FnMut(MemoryPressureLevel, HeapBudget)>>,
+    memory_pressure_level: MemoryPressureLevel,
+    // Set by cleanup, so dropping the controller after it doesn't destroy everything a second time
+    is_cleaned_up: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -78,78 +854,185 @@ struct SwapchainSupportDetails {
 
 // Instance and device management
 impl VkController {
-    const DEVICE_EXTENSIONS: [*const i8; 2] = [Swapchain::name().as_ptr(), ExtDescriptorIndexingFn::name().as_ptr()];
-    pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+    const DEVICE_EXTENSIONS: [*const i8; 1] = [Swapchain::name().as_ptr()];
+    /// The most frames in flight [`VkControllerBuilder::frames_in_flight`] allows.
+    pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
     const VALIDATION_LAYERS: [&'static str; 1] = ["VK_LAYER_KHRONOS_validation"];
     pub const MAX_OBJECT_TYPES:  usize = 1000;
-
+    // view, projection and view_proj (3 * mat4), camera position (vec4), time, delta time and viewport size (2 * float + vec2) laid out with std140
+    const GLOBAL_FRAME_DATA_SIZE: usize = 3 * 64 + 16 + 16;
+    // Each view's data has to start at a multiple of minUniformBufferOffsetAlignment, which is never larger than 256
+    const GLOBAL_FRAME_DATA_STRIDE: usize = 256;
+    /// The maximum number of views that can be drawn in one frame, with [`VkController::set_viewports`] or [`VkController::draw_views`].
+    pub const MAX_VIEWS: usize = 4;
+    /// The maximum number of render targets that can exist at the same time, see [`VkController::create_render_target`].
+    pub const MAX_RENDER_TARGETS: usize = 4;
+    // The views come first, then one slot for each render target
+    const GLOBAL_FRAME_DATA_SLOTS: usize = Self::MAX_VIEWS + Self::MAX_RENDER_TARGETS;
+    /// The format the main pass draws the scene in unless [`VkControllerBuilder::scene_format`] picks another. It can hold values above 1, so the post effects can tonemap them.
+    pub const DEFAULT_SCENE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+    /// Creates the renderer with the defaults of [`VkControllerBuilder`]. Panics when that fails, use [`VkController::try_new`] to handle it instead.
     pub fn new(window: Window, application_name: &str) -> Self {
+        Self::try_new(window, application_name).unwrap()
+    }
+
+    /// Returns an error when there is no suitable GPU, the instance or device extensions the engine needs are missing, or the surface or swapchain can't be created.
+    pub fn try_new(window: Window, application_name: &str) -> Result<Self, EngineError> {
+        VkControllerBuilder::new().try_build(window, application_name)
+    }
+
+    /// Creates the renderer for a window that is owned by another windowing library, for example when embedding it in an existing application.
+    /// The host application has to report size changes with [`VkController::set_window_extent`].
+    ///
+    /// # Safety
+    /// The handles have to be valid and the window they refer to has to outlive the returned [`VkController`].
+    pub unsafe fn new_from_raw_handles(display_handle: RawDisplayHandle, window_handle: RawWindowHandle, initial_extent: vk::Extent2D, application_name: &str) -> Self {
+        VkControllerBuilder::new().build_from_raw_handles(display_handle, window_handle, initial_extent, application_name)
+    }
+
+    // Without handles the surface is a headless one
+    unsafe fn new_with_handles(window: Option<Window>, handles: Option<(RawDisplayHandle, RawWindowHandle)>, window_extent: vk::Extent2D, application_name: &str, builder: VkControllerBuilder) -> Result<Self, EngineError> {
+        builder.validate()?;
         let entry = Entry::linked();
         
-        let debug_messenger_create_info = if IS_DEBUG_MODE {
+        let debug_messenger_create_info = if builder.is_validation_enabled {
             Some(Self::get_debug_messenger_create_info())
         } else {
             None
         };
-        let instance = Rc::new(Self::create_instance(&entry, application_name, &window, debug_messenger_create_info.as_ref()));
+        let instance = Arc::new(Self::create_instance(&entry, application_name, handles.map(|(display_handle, _)| display_handle), debug_messenger_create_info.as_ref())?);
 
         let mut debug_messenger = None;
-        if IS_DEBUG_MODE {
-            debug_messenger = Some(Self::setup_debug_messenger(&entry, &instance, debug_messenger_create_info.unwrap()));
+        if builder.is_validation_enabled {
+            match Self::setup_debug_messenger(&entry, &instance, debug_messenger_create_info.unwrap()) {
+                Ok(messenger) => debug_messenger = Some(messenger),
+                Err(e) => {
+                    Self::destroy_early_handles(&entry, &instance, None, None, None);
+                    return Err(e.into());
+                },
+            }
         }
 
-        let surface = Self::create_surface(&entry, &instance, &window);
+        let surface = match Self::create_surface(&entry, &instance, handles) {
+            Ok(surface) => surface,
+            Err(e) => {
+                Self::destroy_early_handles(&entry, &instance, debug_messenger, None, None);
+                return Err(e.into());
+            },
+        };
 
-        let (physical_device, msaa_samples) = Self::pick_physical_device(&entry, &instance, &surface);
+        let (physical_device, max_msaa_samples) = match Self::pick_physical_device(&entry, &instance, &surface, builder.is_bindless_required) {
+            Ok(physical_device) => physical_device,
+            Err(e) => {
+                Self::destroy_early_handles(&entry, &instance, debug_messenger, Some(surface), None);
+                return Err(e.into());
+            },
+        };
+        // The sample counts are single bits, so a lower raw value is a lower count
+        let msaa_samples = builder.msaa_samples.map_or(max_msaa_samples, |msaa_samples| if msaa_samples.as_raw() > max_msaa_samples.as_raw() { max_msaa_samples } else { msaa_samples });
 
         let queue_families = Self::find_queue_families(&entry, &instance, &physical_device, &surface);
         
-        let device = Rc::new(Self::create_logical_device(&entry, &instance, &physical_device, &surface));
+        let device = match Self::create_logical_device(&entry, &instance, &physical_device, &surface) {
+            Ok(device) => Arc::new(device),
+            Err(e) => {
+                Self::destroy_early_handles(&entry, &instance, debug_messenger, Some(surface), None);
+                return Err(e.into());
+            },
+        };
+
+        let max_bindless_textures = Self::get_max_bindless_textures_supported(&instance, &physical_device);
+        let display_timing_fn = Self::load_display_timing_fn(&instance, &physical_device, &device);
 
-        let mut allocator = VkAllocator::new(instance.clone(), physical_device, device.clone());
+        let allocator = VkAllocator::new(instance.clone(), physical_device, device.clone(), builder.use_host_allocation_callbacks, Self::is_memory_budget_available(&instance, &physical_device));
+        allocator.set_staging_ring_size(builder.staging_ring_size);
 
         let (graphics_queue, present_queue) = Self::create_graphics_and_present_queue(&device, &queue_families);
 
         let swapchain_loader = Swapchain::new(&instance, &device);
 
-        let swapchain = Self::create_swapchain(&entry, &instance, &physical_device,  &surface, &window, &swapchain_loader, &mut allocator);
+        let swapchain = match Self::create_swapchain(&entry, &instance, &physical_device,  &surface, window_extent, &swapchain_loader, vk::SwapchainKHR::null(), builder.present_mode, &builder.surface_format_preference, builder.swapchain_image_count, &allocator) {
+            Ok(swapchain) => swapchain,
+            Err(e) => {
+                Self::destroy_early_handles(&entry, &instance, debug_messenger, Some(surface), Some(&device));
+                return Err(e.into());
+            },
+        };
 
-        let swapchain_images = Self::get_swapchain_images(&swapchain, &swapchain_loader);
+        let swapchain_images = match Self::get_swapchain_images(&swapchain, &swapchain_loader) {
+            Ok(swapchain_images) => swapchain_images,
+            Err(e) => {
+                swapchain_loader.destroy_swapchain(swapchain, allocator.get_allocation_callbacks());
+                Self::destroy_early_handles(&entry, &instance, debug_messenger, Some(surface), Some(&device));
+                return Err(e.into());
+            },
+        };
 
-        let swapchain_image_format = Self::choose_swap_surface_format(&Self::query_swapchain_support(&entry, &instance, &physical_device, &surface).formats).format;
+        let swapchain_image_format = Self::choose_swap_surface_format(&Self::query_swapchain_support(&entry, &instance, &physical_device, &surface).formats, &builder.surface_format_preference).format;
+        let active_present_mode = Self::choose_swap_present_mode(&Self::query_swapchain_support(&entry, &instance, &physical_device, &surface).present_modes, builder.present_mode);
 
-        let swapchain_extent = Self::choose_swap_extent(&Self::query_swapchain_support(&entry, &instance, &physical_device, &surface).capabilities, &window);
+        let swapchain_extent = Self::choose_swap_extent(&Self::query_swapchain_support(&entry, &instance, &physical_device, &surface).capabilities, window_extent);
         
-        let swapchain_image_views = Self::create_image_views(&device, &swapchain_images, swapchain_image_format, &mut allocator );
+        let swapchain_image_views = Self::create_image_views(&device, &swapchain_images, swapchain_image_format, &allocator );
+
+        let scene_format = if Self::is_scene_format_supported(&instance, &physical_device, builder.scene_format) {
+            builder.scene_format
+        } else {
+            log::warn!(target: logging::RENDERER, "The device can't render to, blend and sample the scene format {}. The default format {} is used instead", builder.scene_format.as_raw(), Self::DEFAULT_SCENE_FORMAT.as_raw());
+            Self::DEFAULT_SCENE_FORMAT
+        };
         
-        let color_image_allocation = Self::create_color_resources(swapchain_image_format, &swapchain_extent, msaa_samples, &mut allocator );
+        let color_image_allocation = Self::create_color_resources(scene_format, &swapchain_extent, msaa_samples, &allocator );
+        let scene_image_allocation = Self::create_scene_resources(scene_format, &swapchain_extent, &allocator);
         
-        let depth_image_allocation = Self::create_depth_resources(&instance, &physical_device, &swapchain_extent, msaa_samples, &mut allocator );
+        let depth_image_allocation = Self::create_depth_resources(&instance, &physical_device, &swapchain_extent, msaa_samples, &allocator );
+        let depth_resolve_image_allocation = Self::create_depth_resolve_resources(&instance, &physical_device, &swapchain_extent, msaa_samples, &allocator);
         
         
-        let command_pool = Self::create_command_pool(&device, &queue_families, &mut allocator );
+        let command_pool = Self::create_command_pool(&device, &queue_families, &allocator );
+        let upload_command_pool = Self::create_command_pool(&device, &queue_families, &allocator);
+        allocator.initialize_color_attachment_layout(&command_pool, &graphics_queue, &color_image_allocation).unwrap();
+
+        let descriptor_pool = Self::create_descriptor_pool(&device, &allocator );
+        // A non zero mip LOD bias needs the portability subset's sampler_mip_lod_bias on devices like MoltenVK
+        let is_mip_lod_bias_supported = Self::get_portability_subset_features(&instance, &physical_device).is_none_or(|features| features.sampler_mip_lod_bias == vk::TRUE);
+        let mut sampler_manager = SamplerManager::new(is_mip_lod_bias_supported);
 
-        let descriptor_pool = Self::create_descriptor_pool(&device, &mut allocator );
-        let sampler_manager = SamplerManager::new();
+        let debug_utils_loader = debug_messenger.map(|_| DebugUtils::new(&entry, &instance));
+        let is_sample_rate_shading_supported = unsafe { instance.get_physical_device_features(physical_device) }.sample_rate_shading == vk::TRUE;
+        let mut pipeline_manager = PipelineManager::new(&device, scene_format, msaa_samples, Self::find_depth_format(&instance, &physical_device), debug_utils_loader.clone(), is_sample_rate_shading_supported, &allocator);
+        let render_target_manager = RenderTargetManager::new(Self::find_depth_format(&instance, &physical_device));
 
-        let pipeline_manager = PipelineManager::new(&device, swapchain_image_format, msaa_samples, Self::find_depth_format(&instance, &physical_device), &mut allocator);
+        let global_frame_data_allocation = allocator.create_uniform_buffers(Self::GLOBAL_FRAME_DATA_STRIDE * Self::GLOBAL_FRAME_DATA_SLOTS, Self::MAX_FRAMES_IN_FLIGHT).unwrap();
+        let light_manager = LightManager::new(&allocator).unwrap();
+        let global_descriptor_sets = Self::create_global_descriptor_sets(&device, &descriptor_pool, &pipeline_manager.get_global_descriptor_set_layout().unwrap(), &global_frame_data_allocation, &light_manager);
 
-        let swapchain_framebuffers = Self::create_framebuffers(&device, &pipeline_manager.get_render_pass().unwrap(), &swapchain_image_views, &swapchain_extent, &depth_image_allocation, &color_image_allocation, &mut allocator );
+        let scene_framebuffer = Self::create_framebuffer(&device, &pipeline_manager.get_render_pass().unwrap(), scene_image_allocation.get_image_view().unwrap(), &swapchain_extent, &depth_image_allocation, &depth_resolve_image_allocation.iter().map(|allocation| allocation.get_image_view().unwrap()).collect::<Vec<_>>(), &color_image_allocation, &allocator );
 
-        // let uniform_allocation = Self::create_uniform_buffers(&mut allocator );
+        let is_blit_supported = Self::is_scene_blit_supported(&entry, &instance, &physical_device, &surface, scene_format, swapchain_image_format);
+        let mut post_processor = PostProcessor::new(&device, &instance, &physical_device, scene_format, swapchain_image_format, is_blit_supported, &mut pipeline_manager, &mut sampler_manager, &allocator).unwrap();
+        post_processor.create_targets(&device, scene_image_allocation.get_image_view().unwrap(), &swapchain_image_views, swapchain_extent, &allocator).unwrap();
 
-        let mut command_buffers = Vec::with_capacity(Self::MAX_FRAMES_IN_FLIGHT);
-        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
+        // let uniform_allocation = Self::create_uniform_buffers(&allocator );
+
+        let mut command_buffers = Vec::with_capacity(builder.frames_in_flight);
+        for _ in 0..builder.frames_in_flight {
             command_buffers.push(Self::create_command_buffers(&device, &command_pool, 1));
         }
         
-        let (image_available_semaphores, render_finished_semaphores, in_flight_fences) = Self::create_sync_objects(&device, &mut allocator );
+        let (image_available_semaphores, render_finished_semaphores, in_flight_fences) = Self::create_sync_objects(&device, builder.frames_in_flight, &allocator);
 
-        Self {
+        let (timestamp_query_pool, timestamp_period, timestamp_valid_bits) = Self::create_timestamp_query_pool(&instance, &physical_device, &device, &queue_families, &allocator);
+        let pipeline_statistics_query_pool = Self::create_pipeline_statistics_query_pool(&instance, &physical_device, &device, &allocator);
+
+        let controller = Self {
             window,
+            window_extent,
             entry,
             instance,
             debug_messenger,
+            debug_utils_loader,
             physical_device,
             device,
             graphics_queue,
@@ -157,33 +1040,111 @@ impl VkController {
             surface,
             swapchain_loader,
             swapchain,
+            retired_swapchains: Vec::new(),
             swapchain_images,
             swapchain_image_format,
             swapchain_extent,
             swapchain_image_views,
-            swapchain_framebuffers,
+            scene_framebuffer,
+            scene_image_allocation: Some(scene_image_allocation),
+            scene_format,
+            post_processor,
+            is_linear_workflow: false,
             command_pool,
+            upload_command_pool,
             command_buffers,
             image_available_semaphores,
             render_finished_semaphores,
             in_flight_fences,
             current_frame: 0,
+            frames_in_flight: builder.frames_in_flight,
+            present_mode: builder.present_mode,
+            active_present_mode,
+            surface_format_preference: builder.surface_format_preference,
+            swapchain_image_count: builder.swapchain_image_count,
             frame_buffer_resized: false,
             is_minimized: false,
             descriptor_pool,
             color_image_allocation: Some(color_image_allocation),
             depth_image_allocation: Some(depth_image_allocation),
+            depth_resolve_image_allocation,
+            object_id_image_allocation: None,
+            object_id_resolve_image_allocation: None,
+            picking_draw_object_types: None,
+            extra_color_attachment_allocations: Vec::new(),
             msaa_samples,
-            allocator,
+            allocator: Arc::new(allocator),
             graphics_pipeline_manager: pipeline_manager,
             sampler_manager,
-            object_manager: ObjectManager::new(),
+            object_manager: ObjectManager::new(builder.frames_in_flight),
+            num_recorded_commands: 0,
+            is_frame_report_enabled: true,
+            render_rect: None,
+            views: Vec::new(),
+            cameras: HashMap::new(),
+            next_camera_id: 0,
+            clear_mode: ClearMode::Clear(builder.clear_color),
+            fullscreen_mode: FullscreenMode::Windowed,
+            is_fullscreen_toggle_enabled: true,
+            modifiers: ModifiersState::empty(),
+            is_depth_kept: false,
+            is_depth_available: false,
+            frame_report: FrameReport::default(),
+            global_frame_data_allocation,
+            light_manager,
+            global_descriptor_sets,
+            view: glm::identity(),
+            projection: glm::identity(),
+            time: Time::new(Instant::now()),
+            max_bindless_textures,
+            texture_manager: None,
+            render_target_manager,
+            text_renderer: None,
+            debug_drawer: None,
+            egui_renderer: None,
+            extra_recording: None,
+            timestamp_query_pool,
+            timestamp_period,
+            timestamp_valid_bits,
+            are_timestamps_written: vec![false; Self::MAX_FRAMES_IN_FLIGHT],
+            last_frame_gpu_time_ms: None,
+            pipeline_statistics_query_pool,
+            are_pipeline_statistics_written: vec![false; Self::MAX_FRAMES_IN_FLIGHT],
+            last_frame_pipeline_stats: None,
+            display_timing_fn,
+            next_present_id: 0,
+            last_present_instant: None,
+            last_actual_present_time: None,
+            last_present_timing: None,
+            render_doc_capture: RenderDocCapture::new(),
+            async_loader: AsyncLoader::new(),
+            upload_allocations_to_free: Arc::new(Mutex::new(Vec::new())),
+            memory_pressure_callback: None,
+            memory_pressure_level: MemoryPressureLevel::Normal,
+            is_cleaned_up: false,
+        };
+        controller.set_frame_debug_names();
+        Ok(controller)
+    }
+
+    // Destroys what is created before the swapchain, when creating the renderer fails. Nothing else has been created from the device at that point
+    unsafe fn destroy_early_handles(entry: &Entry, instance: &Instance, debug_messenger: Option<vk::DebugUtilsMessengerEXT>, surface: Option<SurfaceKHR>, device: Option<&Device>) {
+        if let Some(device) = device {
+            device.destroy_device(None);
+        }
+        if let Some(surface) = surface {
+            Surface::new(entry, instance).destroy_surface(surface, None);
+        }
+        if let Some(debug_messenger) = debug_messenger {
+            DebugUtils::new(entry, instance).destroy_debug_utils_messenger(debug_messenger, None);
         }
+        instance.destroy_instance(None);
     }
 
-    fn create_instance(entry: &Entry, application_name: &str, window: &Window, debug_create_info: Option<&DebugUtilsMessengerCreateInfoEXT>) -> Instance {
-        if IS_DEBUG_MODE && !Self::check_validation_layer_support(entry) {
-            panic!("Validation layers requested because of debug mode, but is not available!");
+    // The validation layers are enabled when there is a debug messenger to report their messages to
+    fn create_instance(entry: &Entry, application_name: &str, display_handle: Option<RawDisplayHandle>, debug_create_info: Option<&DebugUtilsMessengerCreateInfoEXT>) -> Result<Instance, Cow<'static, str>> {
+        if debug_create_info.is_some() && !Self::check_validation_layer_support(entry) {
+            return Err(Cow::from("Validation layers requested, but they are not available! Install the Vulkan SDK or disable validation with VkControllerBuilder::validation(false)"));
         }
 
         let app_info = ash::vk::ApplicationInfo {
@@ -194,10 +1155,17 @@ impl VkController {
             ..Default::default()
         };
     
-        let mut required_instance_extensions = ash_window::enumerate_required_extensions(window.raw_display_handle()).unwrap().to_vec();
-        // println!("Adding KhrPortabilityEnumerationFn here might not work!");
-        // required_instance_extensions.push(KhrPortabilityEnumerationFn::name().as_ptr());
-        if IS_DEBUG_MODE {
+        let mut required_instance_extensions = match display_handle {
+            Some(display_handle) => ash_window::enumerate_required_extensions(display_handle)
+                .map_err(|e| Cow::Owned(format!("The window system isn't supported by Vulkan: {}", e)))?
+                .to_vec(),
+            None => vec![Surface::name().as_ptr(), HeadlessSurface::name().as_ptr()],
+        };
+        let available_instance_extensions = entry.enumerate_instance_extension_properties(None)
+            .map_err(|e| Cow::Owned(format!("Failed to enumerate the instance extensions: {}", e)))?;
+        let (portability_extensions, portability_flags) = Self::get_portability_instance_extensions(&available_instance_extensions);
+        required_instance_extensions.extend(portability_extensions);
+        if debug_create_info.is_some() {
             required_instance_extensions.push(DebugUtils::name().as_ptr());
         }
 
@@ -207,16 +1175,15 @@ impl VkController {
             enabled_extension_count: required_instance_extensions.len() as u32,
             pp_enabled_extension_names: required_instance_extensions.as_ptr(),
             enabled_layer_count: 0,
+            flags: portability_flags,
             ..Default::default()
         };
 
-        // create_info.flags |= InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
-
-        if IS_DEBUG_MODE {
+        if let Some(debug_create_info) = debug_create_info {
             create_info.enabled_layer_count = Self::VALIDATION_LAYERS.len() as u32;
             create_info.pp_enabled_layer_names = Self::VALIDATION_LAYERS.as_ptr().cast();
             
-            create_info.p_next = debug_create_info.unwrap() as *const _ as *const std::ffi::c_void;
+            create_info.p_next = debug_create_info as *const _ as *const std::ffi::c_void;
         } else {
             create_info.enabled_layer_count = 0;
             create_info.p_next = std::ptr::null();
@@ -224,7 +1191,92 @@ impl VkController {
 
         unsafe {
             entry.create_instance(&create_info, None)
-        }.unwrap()
+        }.map_err(|e| match e {
+            vk::Result::ERROR_EXTENSION_NOT_PRESENT => Cow::from("Failed to create the Vulkan instance, the driver is missing the surface extensions for this window system"),
+            vk::Result::ERROR_INCOMPATIBLE_DRIVER => Cow::from("Failed to create the Vulkan instance, no driver supports Vulkan 1.3"),
+            e => Cow::Owned(format!("Failed to create the Vulkan instance: {}", e)),
+        })
+    }
+
+    // Portability subset devices, like MoltenVK on macOS, are only listed when the instance enables portability enumeration.
+    // CI can't run Metal, so check these by hand on a Mac after changing anything here:
+    // 1. The engine starts with the MoltenVK ICD and picks the Apple GPU instead of panicking with "No suitable physical device found!"
+    // 2. The validation layers report no portability subset errors while drawing, resizing and toggling fullscreen
+    // 3. Textured objects are sampled correctly, even with the optional features turned off
+    fn get_portability_instance_extensions(available_extensions: &[vk::ExtensionProperties]) -> (Vec<*const i8>, vk::InstanceCreateFlags) {
+        if Self::is_extension_available(available_extensions, vk::KhrPortabilityEnumerationFn::name()) {
+            (vec![vk::KhrPortabilityEnumerationFn::name().as_ptr()], vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR)
+        } else {
+            (Vec::new(), vk::InstanceCreateFlags::empty())
+        }
+    }
+
+    /// The required device extensions, plus `VK_KHR_portability_subset` which has to be enabled whenever the device advertises it, and `VK_EXT_descriptor_indexing` for bindless textures when it is available.
+    fn get_device_extensions(available_extensions: &[vk::ExtensionProperties]) -> Vec<*const i8> {
+        let mut device_extensions = Self::DEVICE_EXTENSIONS.to_vec();
+        if Self::is_extension_available(available_extensions, ExtDescriptorIndexingFn::name()) {
+            device_extensions.push(ExtDescriptorIndexingFn::name().as_ptr());
+        }
+        if Self::is_extension_available(available_extensions, vk::KhrPortabilitySubsetFn::name()) {
+            device_extensions.push(vk::KhrPortabilitySubsetFn::name().as_ptr());
+        }
+        if Self::is_extension_available(available_extensions, vk::GoogleDisplayTimingFn::name()) {
+            device_extensions.push(vk::GoogleDisplayTimingFn::name().as_ptr());
+        }
+        if Self::is_extension_available(available_extensions, vk::ExtMemoryBudgetFn::name()) {
+            device_extensions.push(vk::ExtMemoryBudgetFn::name().as_ptr());
+        }
+        device_extensions
+    }
+
+    // The extension is enabled by create_logical_device when it is available
+    fn is_memory_budget_available(instance: &Instance, physical_device: &PhysicalDevice) -> bool {
+        let available_extensions = unsafe {
+            instance.enumerate_device_extension_properties(*physical_device)
+        }.unwrap();
+        Self::is_extension_available(&available_extensions, vk::ExtMemoryBudgetFn::name())
+    }
+
+    fn is_extension_available(available_extensions: &[vk::ExtensionProperties], name: &CStr) -> bool {
+        available_extensions.iter().any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == name)
+    }
+
+    // The extension is enabled by create_logical_device when it is available
+    fn load_display_timing_fn(instance: &Instance, physical_device: &PhysicalDevice, device: &Device) -> Option<vk::GoogleDisplayTimingFn> {
+        let available_extensions = unsafe {
+            instance.enumerate_device_extension_properties(*physical_device)
+        }.unwrap();
+        if !Self::is_extension_available(&available_extensions, vk::GoogleDisplayTimingFn::name()) {
+            return None;
+        }
+        Some(vk::GoogleDisplayTimingFn::load(|name| unsafe {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        }))
+    }
+
+    /// None when the device isn't a portability subset device, which means that everything in the subset is supported.
+    fn get_portability_subset_features(instance: &Instance, physical_device: &PhysicalDevice) -> Option<vk::PhysicalDevicePortabilitySubsetFeaturesKHR> {
+        let available_extensions = unsafe {
+            instance.enumerate_device_extension_properties(*physical_device)
+        }.unwrap();
+        if !Self::is_extension_available(&available_extensions, vk::KhrPortabilitySubsetFn::name()) {
+            return None;
+        }
+
+        let mut portability_subset_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR {
+            s_type: StructureType::PHYSICAL_DEVICE_PORTABILITY_SUBSET_FEATURES_KHR,
+            ..Default::default()
+        };
+        let mut features = vk::PhysicalDeviceFeatures2 {
+            s_type: StructureType::PHYSICAL_DEVICE_FEATURES_2,
+            p_next: &mut portability_subset_features as *mut _ as *mut std::ffi::c_void,
+            ..Default::default()
+        };
+        unsafe {
+            instance.get_physical_device_features2(*physical_device, &mut features);
+        }
+        portability_subset_features.p_next = std::ptr::null_mut();
+        Some(portability_subset_features)
     }
 
     fn check_validation_layer_support(entry: &Entry) -> bool {
@@ -258,13 +1310,13 @@ impl VkController {
         true
     }
 
-    fn pick_physical_device(entry: &Entry, instance: &Instance, surface: &SurfaceKHR) -> (PhysicalDevice, vk::SampleCountFlags) {
+    fn pick_physical_device(entry: &Entry, instance: &Instance, surface: &SurfaceKHR, is_bindless_required: bool) -> Result<(PhysicalDevice, vk::SampleCountFlags), Cow<'static, str>> {
         let mut device_vec = unsafe {
             instance.enumerate_physical_devices()
-        }.expect("Expected to be able to look for physical devices (GPU)!");
+        }.map_err(|e| Cow::Owned(format!("Failed to look for physical devices (GPU): {}", e)))?;
 
         if device_vec.is_empty() {
-            panic!("No physical devices found that support Vulkan!");
+            return Err(Cow::from("No physical devices found that support Vulkan!"));
         }
 
         device_vec.sort_by_key(|device| Self::rate_physical_device_suitability(instance, device));
@@ -274,7 +1326,7 @@ impl VkController {
         let mut msaa_samples = vk::SampleCountFlags::TYPE_1;
 
         for device in device_vec.iter() {
-            if Self::is_device_suitable(entry, instance, device, surface) {
+            if Self::is_device_suitable(entry, instance, device, surface, is_bindless_required) {
                 msaa_samples = Self::get_max_usable_sample_count(instance, device);
                 chosen_device = Some(*device);
                 break;
@@ -282,20 +1334,23 @@ impl VkController {
         }
 
         if let Some(device) = chosen_device {
-            (device, msaa_samples)
+            Ok((device, msaa_samples))
         } else {
-            panic!("No suitable physical device found!");
+            let device_names = device_vec.iter().map(|device| {
+                let properties = unsafe { instance.get_physical_device_properties(*device) };
+                unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned()
+            }).collect::<Vec<_>>();
+            let extension_names = Self::DEVICE_EXTENSIONS.iter().map(|name| unsafe { CStr::from_ptr(*name) }.to_string_lossy()).collect::<Vec<_>>();
+            let bindless_requirement = if is_bindless_required { ", bindless texture support" } else { "" };
+            Err(Cow::Owned(format!("No suitable physical device found! A device needs graphics and present queues, the {} extensions{} and a surface format and present mode for the window. Found: {}", extension_names.join(", "), bindless_requirement, device_names.join(", "))))
         }
     }
 
-    fn is_device_suitable(entry: &Entry, instance: &Instance, device: &PhysicalDevice, surface: &SurfaceKHR) -> bool {
+    fn is_device_suitable(entry: &Entry, instance: &Instance, device: &PhysicalDevice, surface: &SurfaceKHR, is_bindless_required: bool) -> bool {
         let indices = Self::find_queue_families(entry, instance, device, surface);
         let swapchain_support = Self::query_swapchain_support(entry, instance, device, surface);
-        let supported_features = unsafe {
-            instance.get_physical_device_features(*device)
-        };
 
-        indices.is_complete() && Self::check_device_extension_support(instance, device) && Self::is_swapchain_adequate(&swapchain_support) && supported_features.sampler_anisotropy == vk::TRUE
+        indices.is_complete() && Self::check_device_extension_support(instance, device) && Self::is_swapchain_adequate(&swapchain_support) && (!is_bindless_required || Self::is_bindless_textures_supported(instance, device))
     }
 
     fn check_device_extension_support(instance: &Instance, device: &PhysicalDevice) -> bool {
@@ -330,17 +1385,38 @@ impl VkController {
             instance.get_physical_device_features(*device)
         };
 
-        let mut score = 0;
-        
-        if device_properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU {
-            score += 1000;
-        }
+        let memory_properties = unsafe {
+            instance.get_physical_device_memory_properties(*device)
+        };
 
-        if device_features.geometry_shader != vk::TRUE {
-            return 0;
+        Self::get_physical_device_score(&device_properties, &device_features, &memory_properties)
+    }
+
+    fn get_physical_device_score(device_properties: &vk::PhysicalDeviceProperties, device_features: &vk::PhysicalDeviceFeatures, memory_properties: &vk::PhysicalDeviceMemoryProperties) -> i32 {
+        let mut score: i64 = match device_properties.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 100_000,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 50_000,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 25_000,
+            vk::PhysicalDeviceType::CPU => 1_000,
+            _ => 0,
+        };
+
+        // One point per MiB of the largest device local heap, capped so that memory never outweighs the device type
+        let largest_device_local_heap = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .max()
+            .unwrap_or(0);
+        score += ((largest_device_local_heap / (1024 * 1024)) as i64).min(24_000);
+
+        // The optional features the engine uses, everything works without them, but it looks better with them
+        for is_supported in [device_features.sampler_anisotropy, device_features.sample_rate_shading, device_features.fill_mode_non_solid] {
+            if is_supported == vk::TRUE {
+                score += 100;
+            }
         }
 
-        score
+        score.min(i32::MAX as i64) as i32
     }
 
     fn find_queue_families(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR) -> QueueFamilyIndices {
@@ -371,22 +1447,81 @@ impl VkController {
         indices
     }
 
-    fn create_graphics_and_present_queue(device: &Device, indices: &QueueFamilyIndices) -> (Queue, Queue) {
-        (unsafe {
-            device.get_device_queue(indices.graphics_family.expect("Expected the graphics family to be something, but it's not!"), 0)
-        },
-        unsafe {
-            device.get_device_queue(indices.present_family.expect("Expected the present family to be something, but it's not!"), 0)
+    // Descriptor indexing is core from Vulkan 1.2, older devices need the extension
+    fn is_descriptor_indexing_available(instance: &Instance, physical_device: &PhysicalDevice) -> bool {
+        let api_version = unsafe { instance.get_physical_device_properties(*physical_device) }.api_version;
+        if vk::api_version_major(api_version) > 1 || vk::api_version_minor(api_version) >= 2 {
+            return true;
         }
-        )
+        unsafe { instance.enumerate_device_extension_properties(*physical_device) }
+            .is_ok_and(|available_extensions| Self::is_extension_available(&available_extensions, ExtDescriptorIndexingFn::name()))
     }
 
-    fn create_logical_device(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR) -> Device {
-        let indices = Self::find_queue_families(entry, instance, physical_device, surface);
-        
-        let unique_queue_families = HashSet::from([indices.graphics_family.expect("No graphics family index was set!"), indices.present_family.expect("No present family index was set!")]);
-        
-        let mut queue_create_infos = Vec::new();
+    fn is_bindless_textures_supported(instance: &Instance, physical_device: &PhysicalDevice) -> bool {
+        if !Self::is_descriptor_indexing_available(instance, physical_device) {
+            return false;
+        }
+
+        let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures {
+            s_type: StructureType::PHYSICAL_DEVICE_DESCRIPTOR_INDEXING_FEATURES,
+            ..Default::default()
+        };
+        let mut features = vk::PhysicalDeviceFeatures2 {
+            s_type: StructureType::PHYSICAL_DEVICE_FEATURES_2,
+            p_next: &mut descriptor_indexing_features as *mut _ as *mut std::ffi::c_void,
+            ..Default::default()
+        };
+        unsafe {
+            instance.get_physical_device_features2(*physical_device, &mut features);
+        }
+
+        descriptor_indexing_features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE &&
+        descriptor_indexing_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE &&
+        descriptor_indexing_features.descriptor_binding_partially_bound == vk::TRUE &&
+        descriptor_indexing_features.runtime_descriptor_array == vk::TRUE
+    }
+
+    fn get_max_bindless_textures_supported(instance: &Instance, physical_device: &PhysicalDevice) -> u32 {
+        if !Self::is_bindless_textures_supported(instance, physical_device) {
+            return 0;
+        }
+
+        let mut descriptor_indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties {
+            s_type: StructureType::PHYSICAL_DEVICE_DESCRIPTOR_INDEXING_PROPERTIES,
+            ..Default::default()
+        };
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            s_type: StructureType::PHYSICAL_DEVICE_PROPERTIES_2,
+            p_next: &mut descriptor_indexing_properties as *mut _ as *mut std::ffi::c_void,
+            ..Default::default()
+        };
+        unsafe {
+            instance.get_physical_device_properties2(*physical_device, &mut properties);
+        }
+
+        let limits = properties.properties.limits;
+        limits.max_descriptor_set_sampled_images
+            .min(limits.max_per_stage_descriptor_sampled_images)
+            .min(descriptor_indexing_properties.max_descriptor_set_update_after_bind_sampled_images)
+            .min(descriptor_indexing_properties.max_per_stage_descriptor_update_after_bind_sampled_images)
+    }
+
+    fn create_graphics_and_present_queue(device: &Device, indices: &QueueFamilyIndices) -> (Queue, Queue) {
+        (unsafe {
+            device.get_device_queue(indices.graphics_family.expect("Expected the graphics family to be something, but it's not!"), 0)
+        },
+        unsafe {
+            device.get_device_queue(indices.present_family.expect("Expected the present family to be something, but it's not!"), 0)
+        }
+        )
+    }
+
+    fn create_logical_device(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR) -> Result<Device, Cow<'static, str>> {
+        let indices = Self::find_queue_families(entry, instance, physical_device, surface);
+        
+        let unique_queue_families = HashSet::from([indices.graphics_family.expect("No graphics family index was set!"), indices.present_family.expect("No present family index was set!")]);
+        
+        let mut queue_create_infos = Vec::new();
         for queue_family in unique_queue_families.iter() {
             let queue_create_info = DeviceQueueCreateInfo {
                 s_type: StructureType::DEVICE_QUEUE_CREATE_INFO,
@@ -399,20 +1534,54 @@ impl VkController {
             queue_create_infos.push(queue_create_info);
         }
 
+        let supported_features = unsafe {
+            instance.get_physical_device_features(*physical_device)
+        };
+        // None of these are required, they are only enabled when the device supports them. Wide lines are never used, since Metal doesn't have them
         let device_features = vk::PhysicalDeviceFeatures {
-            sampler_anisotropy: vk::TRUE,
-            sample_rate_shading: vk::TRUE, // This may cause performance loss, but it's not required
-            fill_mode_non_solid: vk::TRUE, // This is only required for wireframe rendering
+            sampler_anisotropy: supported_features.sampler_anisotropy, // Samplers fall back to no anisotropic filtering
+            pipeline_statistics_query: supported_features.pipeline_statistics_query, // This is only used for the optional pipeline statistics
+            sample_rate_shading: supported_features.sample_rate_shading, // This may cause performance loss, but it's not required
+            fill_mode_non_solid: supported_features.fill_mode_non_solid, // This is only required for wireframe rendering
+            ..Default::default()
+        };
+
+        // Bindless textures are opt-in, but the features they need are enabled whenever they are supported
+        let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures {
+            s_type: StructureType::PHYSICAL_DEVICE_DESCRIPTOR_INDEXING_FEATURES,
             ..Default::default()
         };
+        if Self::is_bindless_textures_supported(instance, physical_device) {
+            descriptor_indexing_features.shader_sampled_image_array_non_uniform_indexing = vk::TRUE;
+            descriptor_indexing_features.descriptor_binding_sampled_image_update_after_bind = vk::TRUE;
+            descriptor_indexing_features.descriptor_binding_partially_bound = vk::TRUE;
+            descriptor_indexing_features.runtime_descriptor_array = vk::TRUE;
+        }
+
+        // The portability subset features are enabled as they are reported, the engine works around the missing ones
+        let mut portability_subset_features = Self::get_portability_subset_features(instance, physical_device);
+        let portability_subset_features_ptr = portability_subset_features.as_mut().map_or(std::ptr::null_mut(), |features| features as *mut _ as *mut std::ffi::c_void);
+        // The descriptor indexing features can only be chained when the device knows about them
+        let p_next = if Self::is_descriptor_indexing_available(instance, physical_device) {
+            descriptor_indexing_features.p_next = portability_subset_features_ptr;
+            &descriptor_indexing_features as *const _ as *const std::ffi::c_void
+        } else {
+            portability_subset_features_ptr as *const std::ffi::c_void
+        };
+
+        let available_extensions = unsafe {
+            instance.enumerate_device_extension_properties(*physical_device)
+        }.map_err(|e| Cow::Owned(format!("Failed to enumerate the device extensions: {}", e)))?;
+        let device_extensions = Self::get_device_extensions(&available_extensions);
 
         let device_create_info = DeviceCreateInfo {
             s_type: StructureType::DEVICE_CREATE_INFO,
+            p_next,
             queue_create_info_count: queue_create_infos.len() as u32,
             p_queue_create_infos: queue_create_infos.as_ptr(),
             p_enabled_features: &device_features,
-            pp_enabled_extension_names: Self::DEVICE_EXTENSIONS.as_ptr(),
-            enabled_extension_count: Self::DEVICE_EXTENSIONS.len() as u32,
+            pp_enabled_extension_names: device_extensions.as_ptr(),
+            enabled_extension_count: device_extensions.len() as u32,
             ..Default::default()
         };
 
@@ -428,42 +1597,85 @@ impl VkController {
         
         unsafe {
             instance.create_device(*physical_device, &device_create_info, None)
-        }.unwrap()
+        }.map_err(|e| match e {
+            vk::Result::ERROR_EXTENSION_NOT_PRESENT => Cow::from("Failed to create the logical device, the physical device is missing a required extension"),
+            vk::Result::ERROR_FEATURE_NOT_PRESENT => Cow::from("Failed to create the logical device, the physical device is missing a required feature"),
+            e => Cow::Owned(format!("Failed to create the logical device: {}", e)),
+        })
     }
 
     fn wait_for_device(&self) {
+        let _queue_lock = self.allocator.lock_queue();
         unsafe {
             self.device.device_wait_idle().unwrap();
         }
     }
 
+    /// Destroys every Vulkan object the controller owns. It is also done when the controller is dropped, and calling it more than once does nothing.
     pub fn cleanup(&mut self) {
+        if self.is_cleaned_up {
+            return;
+        }
+        self.is_cleaned_up = true;
+
         unsafe {
             self.wait_for_device();
 
             self.cleanup_swapchain();
 
-            self.sampler_manager.destroy_samplers(&self.device, &mut self.allocator);
+            self.sampler_manager.destroy_samplers(&self.device, &self.allocator);
 
-            self.object_manager.destroy_all_objects(&self.device, &self.descriptor_pool, &mut self.allocator);
+            // The objects free their descriptor sets back to the pool, so the pool has to outlive them
+            self.object_manager.destroy_all_objects(&self.device, &self.descriptor_pool, &self.allocator);
 
-            self.device.destroy_descriptor_pool(self.descriptor_pool, Some(&self.allocator.get_allocation_callbacks()));
+            if let Some(mut texture_manager) = self.texture_manager.take() {
+                texture_manager.destroy(&self.device, &self.allocator);
+            }
 
-            
-            self.graphics_pipeline_manager.destroy(&self.device, &mut self.allocator);
+            self.render_target_manager.destroy(&self.device, &self.allocator);
+
+            if let Some(text_renderer) = self.text_renderer.take() {
+                text_renderer.destroy(&self.device, &self.allocator);
+            }
+            if let Some(debug_drawer) = self.debug_drawer.take() {
+                debug_drawer.destroy(&self.allocator);
+            }
+            if let Some(egui_renderer) = self.egui_renderer.take() {
+                egui_renderer.destroy(&self.device, &self.allocator);
+            }
+
+            self.post_processor.destroy(&self.device, &self.allocator);
+
+            for (_, allocation) in self.upload_allocations_to_free.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+                if let Err(err) = self.allocator.free_memory_allocation(allocation) {
+                    log::error!(target: logging::ALLOCATOR, "Failed to free an allocation of an upload context: {}", err);
+                }
+            }
 
-            for i in 0..Self::MAX_FRAMES_IN_FLIGHT {
-                self.device.destroy_semaphore(self.render_finished_semaphores[i], Some(&self.allocator.get_allocation_callbacks()));
-                self.device.destroy_semaphore(self.image_available_semaphores[i], Some(&self.allocator.get_allocation_callbacks()));
-                self.device.destroy_fence(self.in_flight_fences[i], Some(&self.allocator.get_allocation_callbacks()));
+            self.device.destroy_descriptor_pool(self.descriptor_pool, self.allocator.get_allocation_callbacks());
+
+            self.graphics_pipeline_manager.destroy(&self.device, &self.allocator);
+
+            for i in 0..self.frames_in_flight {
+                self.device.destroy_semaphore(self.render_finished_semaphores[i], self.allocator.get_allocation_callbacks());
+                self.device.destroy_semaphore(self.image_available_semaphores[i], self.allocator.get_allocation_callbacks());
+                self.device.destroy_fence(self.in_flight_fences[i], self.allocator.get_allocation_callbacks());
+            }
+
+            if let Some(timestamp_query_pool) = self.timestamp_query_pool {
+                self.device.destroy_query_pool(timestamp_query_pool, self.allocator.get_allocation_callbacks());
+            }
+            if let Some(pipeline_statistics_query_pool) = self.pipeline_statistics_query_pool {
+                self.device.destroy_query_pool(pipeline_statistics_query_pool, self.allocator.get_allocation_callbacks());
             }
 
-            self.device.destroy_command_pool(self.command_pool, Some(&self.allocator.get_allocation_callbacks()));
+            self.device.destroy_command_pool(self.command_pool, self.allocator.get_allocation_callbacks());
+            self.device.destroy_command_pool(self.upload_command_pool, self.allocator.get_allocation_callbacks());
             self.allocator.free_all_allocations().unwrap();
             self.device.destroy_device(None);
 
-            if IS_DEBUG_MODE {
-                DebugUtils::new(&self.entry, &self.instance).destroy_debug_utils_messenger(self.debug_messenger.unwrap(), None);
+            if let Some(debug_messenger) = self.debug_messenger {
+                DebugUtils::new(&self.entry, &self.instance).destroy_debug_utils_messenger(debug_messenger, None);
             }
 
             Surface::new(&self.entry, &self.instance).destroy_surface(self.surface, None);
@@ -472,18 +1684,33 @@ impl VkController {
     }
 }
 
+impl Drop for VkController {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
 // Swapchain management
 impl VkController {
-    fn create_surface(entry: &Entry, instance: &Instance, window: &Window) -> SurfaceKHR {
+    fn create_surface(entry: &Entry, instance: &Instance, handles: Option<(RawDisplayHandle, RawWindowHandle)>) -> Result<SurfaceKHR, Cow<'static, str>> {
+        let Some((display_handle, window_handle)) = handles else {
+            let create_info = vk::HeadlessSurfaceCreateInfoEXT {
+                s_type: StructureType::HEADLESS_SURFACE_CREATE_INFO_EXT,
+                ..Default::default()
+            };
+            return unsafe {
+                HeadlessSurface::new(entry, instance).create_headless_surface(&create_info, None)
+            }.map_err(|e| Cow::Owned(format!("Failed to create the headless surface: {}", e)));
+        };
         unsafe {
             ash_window::create_surface(
                 entry,
                 instance,
-                window.raw_display_handle(),
-                window.raw_window_handle(),
+                display_handle,
+                window_handle,
                 None
-            ).unwrap()
-        }
+            )
+        }.map_err(|e| Cow::Owned(format!("Failed to create the window surface: {}", e)))
     }
 
     fn query_swapchain_support(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR) -> SwapchainSupportDetails {
@@ -504,20 +1731,72 @@ impl VkController {
         !swapchain_support.formats.is_empty() && !swapchain_support.present_modes.is_empty()
     }
 
-    fn choose_swap_surface_format(available_formats: &Vec<vk::SurfaceFormatKHR>) -> vk::SurfaceFormatKHR {
-        println!("The format we are checking for is B8G8R8A8_SRGB!, which might not be what you want!");
-        for available_format in available_formats {
-            if available_format.format == vk::Format::B8G8R8A8_SRGB && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
-                return *available_format;
-            }
+    // The shaders output linear colors, so an sRGB format is preferred since it applies the gamma when the colors are written. The formats the user prefers are tried before that
+    fn choose_swap_surface_format(available_formats: &[vk::SurfaceFormatKHR], surface_format_preference: &[vk::Format]) -> vk::SurfaceFormatKHR {
+        let user_preferred_format = surface_format_preference.iter()
+            .find_map(|preferred_format| available_formats.iter().find(|available_format| available_format.format == *preferred_format && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR));
+        if let Some(available_format) = user_preferred_format {
+            return *available_format;
+        }
+
+        let srgb_formats = available_formats.iter().filter(|available_format| Self::is_srgb_format(available_format.format) && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        let preferred_format = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB].into_iter()
+            .find_map(|preferred_format| srgb_formats.clone().find(|available_format| available_format.format == preferred_format))
+            .or(srgb_formats.clone().next());
+
+        match preferred_format {
+            Some(available_format) => *available_format,
+            None => {
+                log::warn!(target: logging::RENDERER, "No sRGB swapchain format is available, the colors have to be gamma corrected manually, see VkController::needs_manual_gamma");
+                available_formats[0]
+            },
+        }
+    }
+
+    fn is_srgb_format(format: vk::Format) -> bool {
+        matches!(format, vk::Format::R8_SRGB | vk::Format::R8G8_SRGB | vk::Format::R8G8B8_SRGB | vk::Format::B8G8R8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32)
+    }
+
+    /// True when the swapchain format does not convert to sRGB on write and the linear workflow is off, so the shaders have to apply the gamma themselves, e.g. with pow(color, vec3(1.0 / 2.2)).
+    pub fn needs_manual_gamma(&self) -> bool {
+        !self.is_linear_workflow && !Self::is_srgb_format(self.swapchain_image_format)
+    }
+
+    /// The main pass always draws into the linear scene format, see [`VkControllerBuilder::scene_format`], so the clears, blending and post effects work on linear colors.
+    /// The colors only get their gamma when they are written to the swapchain, which sRGB swapchain formats do by themselves.
+    ///
+    /// Some surfaces only offer UNORM formats, and [`VkControllerBuilder::surface_format_preference`] can pick one. Those write the linear colors as they are, which looks too dark.
+    /// With the linear workflow on, the engine then encodes the colors to sRGB in an extra fullscreen pass after the post effects, so the shaders can always output linear colors.
+    /// With it off, which is the default, the shaders have to encode the colors themselves, see [`VkController::needs_manual_gamma`]. Blending then mixes the encoded colors, which is not physically correct.
+    /// It does nothing with an sRGB swapchain format.
+    pub fn set_linear_workflow(&mut self, is_linear_workflow: bool) -> Result<(), EngineError> {
+        if is_linear_workflow == self.is_linear_workflow {
+            return Ok(());
         }
+        self.is_linear_workflow = is_linear_workflow;
+        if Self::is_srgb_format(self.swapchain_image_format) {
+            return Ok(());
+        }
+        // The frames in flight can still use the old targets
+        unsafe {
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX).unwrap();
+        }
+        self.post_processor.destroy_targets(&self.device, &self.allocator);
+        let result = self.post_processor.set_gamma_encoded(is_linear_workflow, &self.device, &mut self.graphics_pipeline_manager, &self.allocator);
+        if result.is_err() {
+            self.is_linear_workflow = !is_linear_workflow;
+        }
+        self.post_processor.create_targets(&self.device, self.scene_image_allocation.as_ref().unwrap().get_image_view().unwrap(), &self.swapchain_image_views, self.swapchain_extent, &self.allocator)?;
+        result.map_err(EngineError::from)
+    }
 
-        available_formats[0]
+    pub fn is_linear_workflow(&self) -> bool {
+        self.is_linear_workflow
     }
 
-    fn choose_swap_present_mode(available_present_modes: &Vec<vk::PresentModeKHR>) -> vk::PresentModeKHR {
+    fn choose_swap_present_mode(available_present_modes: &Vec<vk::PresentModeKHR>, preferred_present_mode: vk::PresentModeKHR) -> vk::PresentModeKHR {
         for available_present_mode in available_present_modes {
-            if *available_present_mode == vk::PresentModeKHR::MAILBOX {
+            if *available_present_mode == preferred_present_mode {
                 return *available_present_mode;
             }
         }
@@ -525,30 +1804,31 @@ impl VkController {
         vk::PresentModeKHR::FIFO
     }
 
-    fn choose_swap_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window: &Window) -> vk::Extent2D {
+    fn choose_swap_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window_size: vk::Extent2D) -> vk::Extent2D {
         if capabilities.current_extent.width != u32::MAX {
             return capabilities.current_extent;
         }
 
-        let window_size = window.inner_size();
         vk::Extent2D {
             width: window_size.width.max(capabilities.min_image_extent.width).min(capabilities.max_image_extent.width),
             height: window_size.height.max(capabilities.min_image_extent.height).min(capabilities.max_image_extent.height),
         }
     }
 
-    fn create_swapchain(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR, window: &Window, swapchain_loader: &Swapchain, allocator: &mut VkAllocator) -> SwapchainKHR {
+    fn create_swapchain(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR, window_extent: vk::Extent2D, swapchain_loader: &Swapchain, old_swapchain: SwapchainKHR, preferred_present_mode: vk::PresentModeKHR, surface_format_preference: &[vk::Format], preferred_image_count: Option<u32>, allocator: &VkAllocator) -> Result<SwapchainKHR, Cow<'static, str>> {
         let swapchain_support = Self::query_swapchain_support(entry, instance, physical_device, surface);
 
-        let surface_format = Self::choose_swap_surface_format(&swapchain_support.formats);
-        let present_mode = Self::choose_swap_present_mode(&swapchain_support.present_modes);
-        let extent = Self::choose_swap_extent(&swapchain_support.capabilities, window);
+        let surface_format = Self::choose_swap_surface_format(&swapchain_support.formats, surface_format_preference);
+        let present_mode = Self::choose_swap_present_mode(&swapchain_support.present_modes, preferred_present_mode);
+        let extent = Self::choose_swap_extent(&swapchain_support.capabilities, window_extent);
 
-        let mut image_count = swapchain_support.capabilities.min_image_count + 1;
+        let mut image_count = preferred_image_count.unwrap_or(swapchain_support.capabilities.min_image_count + 1).max(swapchain_support.capabilities.min_image_count);
         if swapchain_support.capabilities.max_image_count > 0 && image_count > swapchain_support.capabilities.max_image_count {
             image_count = swapchain_support.capabilities.max_image_count;
         }
 
+        // The scene is blitted to the swapchain image when there are no post effects
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | (swapchain_support.capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_DST);
         let mut swapchain_create_info = SwapchainCreateInfoKHR {
             s_type: StructureType::SWAPCHAIN_CREATE_INFO_KHR,
             surface: *surface,
@@ -557,12 +1837,13 @@ impl VkController {
             image_color_space: surface_format.color_space,
             image_extent: extent,
             image_array_layers: 1,
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            image_usage,
             pre_transform: swapchain_support.capabilities.current_transform,
             composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
             present_mode,
             clipped: vk::TRUE,
-            old_swapchain: vk::SwapchainKHR::null(),
+            // Passing the old swapchain lets the driver recycle its images while resizing
+            old_swapchain,
             ..Default::default()
         };
 
@@ -579,60 +1860,136 @@ impl VkController {
         }
 
         unsafe {
-            swapchain_loader.create_swapchain(&swapchain_create_info, Some(&allocator.get_allocation_callbacks()))
-        }.unwrap()
+            swapchain_loader.create_swapchain(&swapchain_create_info, allocator.get_allocation_callbacks())
+        }.map_err(|e| Cow::Owned(format!("Failed to create the swapchain: {}", e)))
+    }
+
+    // Otherwise the scene is drawn to the swapchain image with a fullscreen pass that copies it
+    // The scene is drawn with blending and sampled by the post effects
+    fn is_scene_format_supported(instance: &Instance, physical_device: &PhysicalDevice, scene_format: vk::Format) -> bool {
+        let format_properties = unsafe {
+            instance.get_physical_device_format_properties(*physical_device, scene_format)
+        };
+        format_properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND | vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    }
+
+    fn is_scene_blit_supported(entry: &Entry, instance: &Instance, physical_device: &PhysicalDevice, surface: &SurfaceKHR, scene_format: vk::Format, swapchain_format: vk::Format) -> bool {
+        let is_transfer_dst_supported = Self::query_swapchain_support(entry, instance, physical_device, surface).capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_DST);
+        let (scene_format_properties, swapchain_format_properties) = unsafe {
+            (instance.get_physical_device_format_properties(*physical_device, scene_format), instance.get_physical_device_format_properties(*physical_device, swapchain_format))
+        };
+        is_transfer_dst_supported && scene_format_properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::BLIT_SRC) && swapchain_format_properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::BLIT_DST)
     }
 
     #[inline(always)]
-    fn get_swapchain_images(swapchain: &SwapchainKHR, swapchain_loader: &Swapchain) -> Vec<Image> {
+    fn get_swapchain_images(swapchain: &SwapchainKHR, swapchain_loader: &Swapchain) -> Result<Vec<Image>, Cow<'static, str>> {
         unsafe {
             swapchain_loader.get_swapchain_images(*swapchain)
-        }.unwrap()
+        }.map_err(|e| Cow::Owned(format!("Failed to get the swapchain images: {}", e)))
     }
 
-    pub fn recreate_swapchain(&mut self) {
-        if self.window.inner_size().width == 0 || self.window.inner_size().height == 0 {
+    /// When the new swapchain can't be created, the frames are not drawn and the recreation is tried again on the next frame.
+    pub fn recreate_swapchain(&mut self) -> Result<(), EngineError> {
+        let window_extent = self.get_window_extent();
+        if window_extent.width == 0 || window_extent.height == 0 {
             self.is_minimized = true;
-            return;
+            return Ok(());
         }
         self.is_minimized = false;
 
-        println!("Recreating swapchain!");
+        log::debug!(target: logging::RENDERER, "Recreating swapchain!");
 
+        // Only the frames in flight can use the swapchain resources, so there is no need to wait for the whole device to be idle
         unsafe {
-            self.device.device_wait_idle().unwrap();
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX)
+        }.map_err(|result| EngineError::VulkanApi { context: Cow::from("Failed to wait for the frames in flight before recreating the swapchain"), result })?;
+
+        // The old swapchain is retired even when the new one can't be created, so it is never used again
+        let old_swapchain = self.swapchain;
+        let new_swapchain = Self::create_swapchain(&self.entry, &self.instance, &self.physical_device, &self.surface, window_extent, &self.swapchain_loader, old_swapchain, self.present_mode, &self.surface_format_preference, self.swapchain_image_count, &self.allocator);
+        if old_swapchain != SwapchainKHR::null() {
+            self.retired_swapchains.push((old_swapchain, self.frames_in_flight));
         }
+        self.swapchain = match new_swapchain {
+            Ok(swapchain) => swapchain,
+            Err(e) => {
+                self.swapchain = SwapchainKHR::null();
+                return Err(EngineError::Other(Cow::from(format!("Failed to recreate the swapchain: {}", e))));
+            },
+        };
 
-        self.cleanup_swapchain();
+        self.cleanup_swapchain_resources();
+        self.is_depth_available = false;
+        self.picking_draw_object_types = None;
 
-        self.swapchain = Self::create_swapchain(&self.entry, &self.instance, &self.physical_device, &self.surface, &self.window, &self.swapchain_loader, &mut self.allocator);
-        self.swapchain_images = Self::get_swapchain_images(&self.swapchain, &self.swapchain_loader);
-        self.swapchain_image_views = Self::create_image_views(&self.device, &self.swapchain_images, self.swapchain_image_format, &mut self.allocator);
+        self.swapchain_images = Self::get_swapchain_images(&self.swapchain, &self.swapchain_loader).unwrap();
+        self.swapchain_image_views = Self::create_image_views(&self.device, &self.swapchain_images, self.swapchain_image_format, &self.allocator);
         let swapchain_capabilities = Self::query_swapchain_support(&self.entry, &self.instance, &self.physical_device, &self.surface);
-        self.swapchain_extent = Self::choose_swap_extent(&swapchain_capabilities.capabilities, &self.window);
-        self.color_image_allocation = Some(Self::create_color_resources(self.swapchain_image_format, &self.swapchain_extent, self.msaa_samples, &mut self.allocator));
-        self.depth_image_allocation = Some(Self::create_depth_resources(&self.instance, &self.physical_device, &self.swapchain_extent, self.msaa_samples, &mut self.allocator));
-        self.swapchain_framebuffers = Self::create_framebuffers(&self.device, &self.graphics_pipeline_manager.get_render_pass().unwrap(), &self.swapchain_image_views, &self.swapchain_extent, self.depth_image_allocation.as_ref().unwrap(), self.color_image_allocation.as_ref().unwrap(), &mut self.allocator);
+        self.swapchain_extent = Self::choose_swap_extent(&swapchain_capabilities.capabilities, window_extent);
+        self.active_present_mode = Self::choose_swap_present_mode(&swapchain_capabilities.present_modes, self.present_mode);
+        // The display timing is per swapchain
+        self.last_actual_present_time = None;
+        self.color_image_allocation = Some(Self::create_color_resources(self.scene_format, &self.swapchain_extent, self.msaa_samples, &self.allocator));
+        self.scene_image_allocation = Some(Self::create_scene_resources(self.scene_format, &self.swapchain_extent, &self.allocator));
+        self.allocator.initialize_color_attachment_layout(&self.command_pool, &self.graphics_queue, self.color_image_allocation.as_ref().unwrap()).unwrap();
+        self.depth_image_allocation = Some(Self::create_depth_resources(&self.instance, &self.physical_device, &self.swapchain_extent, self.msaa_samples, &self.allocator));
+        self.depth_resolve_image_allocation = Self::create_depth_resolve_resources(&self.instance, &self.physical_device, &self.swapchain_extent, self.msaa_samples, &self.allocator);
+        self.create_picking_resources();
+        self.create_extra_color_resources();
+        let extra_image_views = self.get_extra_framebuffer_image_views();
+        self.scene_framebuffer = Self::create_framebuffer(&self.device, &self.graphics_pipeline_manager.get_render_pass().unwrap(), self.scene_image_allocation.as_ref().unwrap().get_image_view().unwrap(), &self.swapchain_extent, self.depth_image_allocation.as_ref().unwrap(), &extra_image_views, self.color_image_allocation.as_ref().unwrap(), &self.allocator);
+        self.post_processor.create_targets(&self.device, self.scene_image_allocation.as_ref().unwrap().get_image_view().unwrap(), &self.swapchain_image_views, self.swapchain_extent, &self.allocator).unwrap();
+        self.set_frame_debug_names();
+        Ok(())
+    }
+
+    /// Counts down the frames of the retired swapchains, and destroys the ones that no present can be pending for anymore.
+    fn destroy_retired_swapchains(&mut self) {
+        let swapchain_loader = &self.swapchain_loader;
+        let allocation_callbacks = self.allocator.get_allocation_callbacks();
+        self.retired_swapchains.retain_mut(|(swapchain, num_frames_left)| {
+            *num_frames_left = num_frames_left.saturating_sub(1);
+            if *num_frames_left > 0 {
+                return true;
+            }
+            unsafe {
+                swapchain_loader.destroy_swapchain(*swapchain, allocation_callbacks);
+            }
+            false
+        });
     }
 
     fn cleanup_swapchain(&mut self) {
+        self.cleanup_swapchain_resources();
+        unsafe {
+            for (swapchain, _) in self.retired_swapchains.drain(..) {
+                self.swapchain_loader.destroy_swapchain(swapchain, self.allocator.get_allocation_callbacks());
+            }
+            self.swapchain_loader.destroy_swapchain(self.swapchain, self.allocator.get_allocation_callbacks());
+        }
+    }
+
+    // Destroys everything that depends on the swapchain, but not the swapchain itself, so that it can be passed as the old swapchain when recreating it
+    fn cleanup_swapchain_resources(&mut self) {
         unsafe {
             self.allocator.free_memory_allocation(self.color_image_allocation.take().unwrap()).unwrap();
             self.color_image_allocation = None;
             self.allocator.free_memory_allocation(self.depth_image_allocation.take().unwrap()).unwrap();
             self.depth_image_allocation = None;
+            for allocation in [self.scene_image_allocation.take(), self.depth_resolve_image_allocation.take(), self.object_id_image_allocation.take(), self.object_id_resolve_image_allocation.take()].into_iter().flatten() {
+                self.allocator.free_memory_allocation(allocation).unwrap();
+            }
+            self.free_extra_color_resources();
             
-            self.swapchain_framebuffers.iter().for_each(|framebuffer| {
-                self.device.destroy_framebuffer(*framebuffer, Some(&self.allocator.get_allocation_callbacks()));
-            });
+            self.device.destroy_framebuffer(self.scene_framebuffer, self.allocator.get_allocation_callbacks());
+            self.post_processor.destroy_targets(&self.device, &self.allocator);
             self.swapchain_image_views.iter().for_each(|image_view| {
-                self.device.destroy_image_view(*image_view, Some(&self.allocator.get_allocation_callbacks()));
+                self.device.destroy_image_view(*image_view, self.allocator.get_allocation_callbacks());
             });
-            self.swapchain_loader.destroy_swapchain(self.swapchain, Some(&self.allocator.get_allocation_callbacks()));
         }
     }
 
-    fn create_image_views(device: &Device, swapchain_images: &[Image], swapchain_image_format: vk::Format, allocator: &mut VkAllocator) -> Vec<ImageView> {
+    fn create_image_views(device: &Device, swapchain_images: &[Image], swapchain_image_format: vk::Format, allocator: &VkAllocator) -> Vec<ImageView> {
         let mut swapchain_image_views = Vec::with_capacity(swapchain_images.len());
 
         for swapchain_image in swapchain_images {
@@ -652,7 +2009,7 @@ impl VkController {
             };
     
             let image_view = unsafe {
-                device.create_image_view(&view_info, Some(&allocator.get_allocation_callbacks()))
+                device.create_image_view(&view_info, allocator.get_allocation_callbacks())
             }.unwrap();
             swapchain_image_views.push(image_view);
         }
@@ -663,12 +2020,12 @@ impl VkController {
 
 // Rendering and graphics pipeline
 impl VkController {
-    fn get_viewport(swapchain_extent: &vk::Extent2D) -> vk::Viewport {
+    pub fn get_viewport(render_rect: &vk::Rect2D) -> vk::Viewport {
         vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: swapchain_extent.width as f32,
-            height: swapchain_extent.height as f32,
+            x: render_rect.offset.x as f32,
+            y: render_rect.offset.y as f32,
+            width: render_rect.extent.width as f32,
+            height: render_rect.extent.height as f32,
             min_depth: 0.0,
             max_depth: 1.0,
         }
@@ -684,32 +2041,44 @@ impl VkController {
         }
     }
 
-    fn create_framebuffers(device: &Device, render_pass: &vk::RenderPass, swapchain_image_allocations: &[ImageView], swapchain_extent: &vk::Extent2D, depth_image_view: &AllocationInfo, color_image_view: &AllocationInfo, allocator: &mut VkAllocator) -> Vec<vk::Framebuffer> {
-        let mut swapchain_framebuffers = Vec::with_capacity(swapchain_image_allocations.len());
-
-        for swapchain_image_view in swapchain_image_allocations.iter() {
-            let attachments = [color_image_view.get_image_view().unwrap(), depth_image_view.get_image_view().unwrap(), *swapchain_image_view];
+    /// Returns the render rect clamped to the swapchain, or the whole swapchain when no render rect is set.
+    fn get_render_rect(render_rect: Option<vk::Rect2D>, swapchain_extent: &vk::Extent2D) -> vk::Rect2D {
+        let Some(render_rect) = render_rect else {
+            return Self::get_scissor(swapchain_extent);
+        };
+        // The window can shrink after the render rect was set, and the scissor has to be inside the framebuffer
+        let x = render_rect.offset.x.clamp(0, swapchain_extent.width as i32);
+        let y = render_rect.offset.y.clamp(0, swapchain_extent.height as i32);
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D {
+                width: render_rect.extent.width.min(swapchain_extent.width - x as u32),
+                height: render_rect.extent.height.min(swapchain_extent.height - y as u32),
+            },
+        }
+    }
 
-            let framebuffer_create_info = vk::FramebufferCreateInfo {
-                s_type: StructureType::FRAMEBUFFER_CREATE_INFO,
-                render_pass: *render_pass,
-                attachment_count: attachments.len() as u32,
-                p_attachments: attachments.as_ptr(),
-                width: swapchain_extent.width,
-                height: swapchain_extent.height,
-                layers: 1,
-                ..Default::default()
-            };
+    /// The extra image views are the attachments after the scene image, in the order the render pass has them.
+    fn create_framebuffer(device: &Device, render_pass: &vk::RenderPass, scene_image_view: ImageView, swapchain_extent: &vk::Extent2D, depth_image_view: &AllocationInfo, extra_image_views: &[ImageView], color_image_view: &AllocationInfo, allocator: &VkAllocator) -> vk::Framebuffer {
+        let attachments = [color_image_view.get_image_view().unwrap(), depth_image_view.get_image_view().unwrap(), scene_image_view].into_iter().chain(extra_image_views.iter().copied()).collect::<Vec<_>>();
 
-            swapchain_framebuffers.push(unsafe {
-                device.create_framebuffer(&framebuffer_create_info, Some(&allocator.get_allocation_callbacks()))
-            }.unwrap());
-        }
+        let framebuffer_create_info = vk::FramebufferCreateInfo {
+            s_type: StructureType::FRAMEBUFFER_CREATE_INFO,
+            render_pass: *render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: swapchain_extent.width,
+            height: swapchain_extent.height,
+            layers: 1,
+            ..Default::default()
+        };
 
-        swapchain_framebuffers
+        unsafe {
+            device.create_framebuffer(&framebuffer_create_info, allocator.get_allocation_callbacks())
+        }.unwrap()
     }
 
-    fn create_command_pool(device: &Device, indices: &QueueFamilyIndices, allocator: &mut VkAllocator) -> vk::CommandPool {
+    fn create_command_pool(device: &Device, indices: &QueueFamilyIndices, allocator: &VkAllocator) -> vk::CommandPool {
 
         let pool_info = vk::CommandPoolCreateInfo {
             s_type: StructureType::COMMAND_POOL_CREATE_INFO,
@@ -719,7 +2088,7 @@ impl VkController {
         };
 
         unsafe {
-            device.create_command_pool(&pool_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_command_pool(&pool_info, allocator.get_allocation_callbacks())
         }.unwrap()
     }
 
@@ -737,7 +2106,10 @@ impl VkController {
         }.unwrap()
     }
 
-    fn record_command_buffer(device: &Device, command_buffer: &vk::CommandBuffer, swapchain_framebuffers: &[vk::Framebuffer], render_pass: &vk::RenderPass, image_index: usize, swapchain_extent: &vk::Extent2D, object_manager: &ObjectManager, pipeline_manager: &mut PipelineManager, current_frame: usize, allocator: &mut VkAllocator) {
+    /// Returns the number of commands recorded inside the render pass. When a frame report is given it is filled with what was recorded.
+    fn record_command_buffer(device: &Device, frame: FrameRecording, post_processor: &PostProcessor, object_manager: &ObjectManager, render_target_manager: &RenderTargetManager, pipeline_manager: &mut PipelineManager, allocator: &VkAllocator) -> usize {
+        let FrameRecording { command_buffer, current_frame, image_index, swapchain_image, scene_image, scene_framebuffer, swapchain_extent, global_descriptor_set, bindless_texture_descriptor_set, clear_mode, views, timestamp_query_pool, pipeline_statistics_query_pool, mut frame_report, debug_utils_loader, text_renderer, debug_drawer, egui_renderer, extra_recording } = frame;
+        let render_pass = pipeline_manager.get_render_pass().unwrap();
         let begin_info = vk::CommandBufferBeginInfo {
             s_type: StructureType::COMMAND_BUFFER_BEGIN_INFO,
             p_inheritance_info: std::ptr::null(),
@@ -745,27 +2117,47 @@ impl VkController {
         };
 
         unsafe {
-            device.begin_command_buffer(*command_buffer, &begin_info)
+            device.begin_command_buffer(command_buffer, &begin_info)
         }.unwrap();
 
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
+        let first_timestamp_query = (current_frame * 2) as u32;
+        if let Some(timestamp_query_pool) = timestamp_query_pool {
+            unsafe {
+                device.cmd_reset_query_pool(command_buffer, timestamp_query_pool, first_timestamp_query, 2);
+                device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, timestamp_query_pool, first_timestamp_query);
+            }
+        }
+        if let Some(pipeline_statistics_query_pool) = pipeline_statistics_query_pool {
+            unsafe {
+                device.cmd_reset_query_pool(command_buffer, pipeline_statistics_query_pool, current_frame as u32, 1);
+                device.cmd_begin_query(command_buffer, pipeline_statistics_query_pool, current_frame as u32, vk::QueryControlFlags::empty());
+            }
+        }
+
+        // The object id and extra color attachments come after the first three and the depth resolve attachment, each followed by at most one resolve attachment,
+        // and are cleared to 0. The resolve attachments are not cleared, so their values are ignored
+        let num_clear_values = 4 + 2 * (1 + pipeline_manager.get_extra_color_attachment_formats().len());
+        let mut clear_values = vec![vk::ClearValue { color: vk::ClearColorValue { uint32: [0; 4] } }; num_clear_values];
+        clear_values[0] = vk::ClearValue {
+            color: vk::ClearColorValue {
+                // The clear value is ignored when the color attachment is not cleared
+                float32: match clear_mode {
+                    ClearMode::Clear(color) => color,
+                    ClearMode::DontCare | ClearMode::Load => [0.0, 0.0, 0.0, 1.0],
                 },
             },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                },
-            }
-        ];
+        };
+        clear_values[1] = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
 
         let render_pass_info = vk::RenderPassBeginInfo {
             s_type: StructureType::RENDER_PASS_BEGIN_INFO,
-            render_pass: *render_pass,
-            framebuffer: swapchain_framebuffers[image_index],
+            render_pass,
+            framebuffer: scene_framebuffer,
             render_area: vk::Rect2D {
                 offset: vk::Offset2D {
                     x: 0,
@@ -778,118 +2170,698 @@ impl VkController {
             ..Default::default()
         };
 
-        let viewport = Self::get_viewport(swapchain_extent);
-        let scissor = Self::get_scissor(swapchain_extent);
-
-        let offsets = [0_u64];
+        // The draws are sorted so that the same pipeline and buffers mostly come after each other, so state is only bound when it changes
+        let mut bound_state = BoundDrawState::default();
+        let mut recorder = CommandBufferRecorder { device, command_buffer, debug_utils_loader };
+        // The render targets are drawn first, so the main pass can sample them
+        let mut num_recorded_commands = Self::record_render_target_passes(device, &command_buffer, global_descriptor_set, bindless_texture_descriptor_set, object_manager, render_target_manager, pipeline_manager, current_frame, allocator);
+        let mut last_reported_pipeline: Option<&PipelineConfig> = None;
+        if let Some(frame_report) = frame_report.as_mut() {
+            frame_report.pipelines.clear();
+        }
 
         unsafe {
-            device.cmd_begin_render_pass(*command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
-            object_manager.borrow_objects_to_render().iter().for_each(|(p_c_k, data_using_p_c)| {
-                let mut p_c = p_c_k.clone();
-                let pipeline = pipeline_manager.get_or_create_pipeline(&mut p_c, device, swapchain_extent, allocator).unwrap();
-                data_using_p_c.object_type_num_instances.iter().for_each(|(object_type, (num_instances, num_indices))| {
-                    device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
-                    device.cmd_set_viewport(*command_buffer, 0, &[viewport]);
-                    device.cmd_set_scissor(*command_buffer, 0, &[scissor]);
-                    device.cmd_bind_vertex_buffers(*command_buffer, 0, &[data_using_p_c.vertices.0.get_buffer().unwrap()], &offsets);
-                    device.cmd_bind_index_buffer(*command_buffer, data_using_p_c.indices.0.get_buffer().unwrap(), data_using_p_c.object_type_indices_bytes_indices.get(object_type).unwrap().0.0 as u64, vk::IndexType::UINT32);
-                    device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, p_c.get_pipeline_layout().unwrap(), 0, &[data_using_p_c.descriptor_sets.get(object_type).unwrap()[current_frame]], &[]);
-                    device.cmd_draw_indexed(*command_buffer, num_indices.0 as u32, num_instances.0 as u32, 0, 0, 0);
-                });
-            });
-            device.cmd_end_render_pass(*command_buffer);
-            device.end_command_buffer(*command_buffer)
+            device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+            let all_draws = object_manager.get_draws_in_order(current_frame);
+            for (view_index, frame_view) in views.iter().enumerate() {
+                // Picking finds the object type from the index of the draw, so the draws of a view with an object filter keep the index of the draw they are part of
+                let draws = match frame_view.object_filter.as_ref() {
+                    Some(object_ids) => object_manager.get_indexed_draws_of_objects(object_ids, current_frame),
+                    None => all_draws.iter().enumerate().map(|(draw_index, &(p_c_k, draw_batch))| (draw_index, p_c_k, draw_batch)).collect(),
+                };
+                // The render pass still clears the whole framebuffer, so the area outside the render rects keeps the clear color
+                let scissor = frame_view.render_rect;
+                // Each view has its own per-frame data, which is selected with the dynamic offset of the global descriptor set
+                let global_frame_data_offset = (view_index * Self::GLOBAL_FRAME_DATA_STRIDE) as u32;
+                let mut object_draws = Vec::with_capacity(draws.len());
+                for &(draw_index, p_c_k, draw_batch) in draws.iter() {
+                    // The report only reads data the object manager already has on the CPU, so it does not add any Vulkan calls. Only the draws of the first view are reported
+                    if let Some(frame_report) = frame_report.as_mut().filter(|_| view_index == 0) {
+                        if last_reported_pipeline != Some(p_c_k) {
+                            frame_report.pipelines.push(PipelineReport { shader_paths: p_c_k.get_shader_paths(), object_types: Vec::new() });
+                            last_reported_pipeline = Some(p_c_k);
+                        }
+                        if let Some(object_type_report) = object_manager.get_object_type_report(draw_batch.object_type) {
+                            frame_report.pipelines.last_mut().unwrap().object_types.push(object_type_report);
+                        }
+                    }
+                    let mut p_c = p_c_k.clone();
+                    let pipeline = pipeline_manager.get_or_create_pipeline(&mut p_c, device, swapchain_extent, allocator).unwrap();
+                    object_draws.push(ObjectDraw {
+                        draw_index,
+                        pipeline,
+                        pipeline_layout: p_c.get_pipeline_layout().unwrap(),
+                        pipeline_label: debug_utils_loader.map(|_| format!("pipeline {}", p_c_k.get_shader_paths().join(", "))),
+                        draw_batch,
+                    });
+                }
+                num_recorded_commands += Self::record_object_draws(&mut recorder, &object_draws, scissor, global_descriptor_set, global_frame_data_offset, bindless_texture_descriptor_set, &mut bound_state);
+                // The debug lines are in world space, so they are drawn in every view
+                if let Some(debug_drawer) = debug_drawer.filter(|debug_drawer| debug_drawer.get_num_vertices(current_frame) > 0) {
+                    num_recorded_commands += Self::record_debug_line_draw(device, &command_buffer, global_descriptor_set, global_frame_data_offset, &scissor, swapchain_extent, debug_drawer, pipeline_manager, current_frame, allocator);
+                    bound_state.vertex_buffer = None;
+                }
+            }
+            // The text is drawn last, so it is in front of the objects and blends with them
+            if let Some(text_renderer) = text_renderer.filter(|text_renderer| text_renderer.get_num_vertices(current_frame) > 0) {
+                num_recorded_commands += Self::record_text_draw(device, &command_buffer, global_descriptor_set, &views[0].render_rect, swapchain_extent, text_renderer, pipeline_manager, current_frame, allocator);
+            }
+            // The UI is over everything else, including the text
+            if let Some(egui_renderer) = egui_renderer {
+                num_recorded_commands += egui_renderer.record_draw(device, &command_buffer, global_descriptor_set, &views[0].render_rect, swapchain_extent, pipeline_manager, current_frame, allocator);
+            }
+            if let Some(extra_recording) = extra_recording {
+                extra_recording(command_buffer);
+            }
+            device.cmd_end_render_pass(command_buffer);
+            num_recorded_commands += post_processor.record(device, &command_buffer, image_index, swapchain_image, scene_image);
+            if let Some(pipeline_statistics_query_pool) = pipeline_statistics_query_pool {
+                device.cmd_end_query(command_buffer, pipeline_statistics_query_pool, current_frame as u32);
+            }
+            if let Some(timestamp_query_pool) = timestamp_query_pool {
+                device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, timestamp_query_pool, first_timestamp_query + 1);
+            }
+            device.end_command_buffer(command_buffer)
         }.unwrap();
+
+        num_recorded_commands
     }
 
-    pub fn try_to_draw_frame(&mut self) -> bool {
-        self.draw_frame(0)
+    /// Records the draws in the render rect. The viewport, scissor and global descriptor sets are bound together with the pipeline, so they are only bound again when the pipeline changes.
+    /// Draws with a pipeline label are grouped in a label for each pipeline, around a label for each object type. Returns the number of recorded commands.
+    fn record_object_draws(recorder: &mut impl DrawRecorder, draws: &[ObjectDraw], render_rect: vk::Rect2D, global_descriptor_set: vk::DescriptorSet, global_frame_data_offset: u32, bindless_texture_descriptor_set: Option<vk::DescriptorSet>, bound_state: &mut BoundDrawState) -> usize {
+        let mut num_recorded_commands = 0;
+        let mut bound_pipeline = None;
+        let mut labeled_pipeline = None;
+        for object_draw in draws {
+            let draw_batch = &object_draw.draw_batch;
+            // Labels each object type's draw, so captures in tools like RenderDoc show which object types the commands belong to
+            if let Some(pipeline_label) = object_draw.pipeline_label.as_ref() {
+                if labeled_pipeline != Some(object_draw.pipeline) {
+                    if labeled_pipeline.is_some() {
+                        recorder.record(DrawCommand::EndLabel);
+                        num_recorded_commands += 1;
+                    }
+                    recorder.record(DrawCommand::BeginLabel(pipeline_label.clone()));
+                    labeled_pipeline = Some(object_draw.pipeline);
+                    num_recorded_commands += 1;
+                }
+                recorder.record(DrawCommand::BeginLabel(format!("draw ObjectType({}) x{}", draw_batch.object_type.get_hash().0, draw_batch.num_instances)));
+                num_recorded_commands += 1;
+            }
+            if bound_pipeline != Some(object_draw.pipeline) {
+                recorder.record(DrawCommand::BindPipeline(object_draw.pipeline));
+                recorder.record(DrawCommand::SetViewport(render_rect));
+                recorder.record(DrawCommand::SetScissor(render_rect));
+                recorder.record(DrawCommand::BindDescriptorSet { pipeline_layout: object_draw.pipeline_layout, set_index: 0, descriptor_set: global_descriptor_set, dynamic_offsets: vec![global_frame_data_offset] });
+                num_recorded_commands += 4;
+                if let Some(bindless_texture_descriptor_set) = bindless_texture_descriptor_set {
+                    recorder.record(DrawCommand::BindDescriptorSet { pipeline_layout: object_draw.pipeline_layout, set_index: 2, descriptor_set: bindless_texture_descriptor_set, dynamic_offsets: Vec::new() });
+                    num_recorded_commands += 1;
+                }
+                bound_pipeline = Some(object_draw.pipeline);
+                // A new pipeline can have a different layout for set 1, so the object type's descriptor set has to be bound again
+                bound_state.descriptor_set = None;
+            }
+            num_recorded_commands += Self::record_draw_batch(recorder, object_draw.pipeline_layout, draw_batch, object_draw.draw_index, bound_state);
+            if object_draw.pipeline_label.is_some() {
+                recorder.record(DrawCommand::EndLabel);
+                num_recorded_commands += 1;
+            }
+        }
+        if labeled_pipeline.is_some() {
+            recorder.record(DrawCommand::EndLabel);
+            num_recorded_commands += 1;
+        }
+        num_recorded_commands
     }
 
-    fn draw_frame(&mut self, timeout: u64) -> bool {
-        if self.is_minimized && !self.frame_buffer_resized {
-            return false;
+    /// Binds the buffers and descriptor set of the draw batch that are not already bound and records its draw. Returns the number of recorded commands.
+    fn record_draw_batch(recorder: &mut impl DrawRecorder, pipeline_layout: vk::PipelineLayout, draw_batch: &DrawBatch, draw_index: usize, bound_state: &mut BoundDrawState) -> usize {
+        let mut num_recorded_commands = 0;
+        if bound_state.vertex_buffer != Some(draw_batch.vertex_buffer) {
+            recorder.record(DrawCommand::BindVertexBuffer(draw_batch.vertex_buffer));
+            num_recorded_commands += 1;
+            bound_state.vertex_buffer = Some(draw_batch.vertex_buffer);
+        }
+        // The object types in a pipeline share the index buffer, but they can use different index types
+        if let Some(index_buffer) = draw_batch.index_buffer.filter(|index_buffer| bound_state.index_buffer != Some((*index_buffer, draw_batch.index_type))) {
+            recorder.record(DrawCommand::BindIndexBuffer(index_buffer, draw_batch.index_type));
+            num_recorded_commands += 1;
+            bound_state.index_buffer = Some((index_buffer, draw_batch.index_type));
+        }
+        if draw_batch.num_dynamic_uniform_buffers == 0 && bound_state.descriptor_set != Some(draw_batch.descriptor_set) {
+            recorder.record(DrawCommand::BindDescriptorSet { pipeline_layout, set_index: 1, descriptor_set: draw_batch.descriptor_set, dynamic_offsets: Vec::new() });
+            num_recorded_commands += 1;
+            bound_state.descriptor_set = Some(draw_batch.descriptor_set);
+        }
+        // The draw index is offset by one, since 0 is the object id of the pixels without any object
+        recorder.record(DrawCommand::PushDrawId { pipeline_layout, draw_id: draw_index as u32 + 1 });
+        num_recorded_commands += 1;
+        if draw_batch.num_dynamic_uniform_buffers == 0 {
+            recorder.record(match draw_batch.index_buffer {
+                Some(_) => DrawCommand::DrawIndexed { num_indices: draw_batch.num_indices, num_instances: draw_batch.num_instances, first_index: draw_batch.first_index, vertex_offset: draw_batch.first_vertex as i32, first_instance: draw_batch.first_instance },
+                // Object types without indices are drawn straight from the vertex buffer
+                None => DrawCommand::Draw { num_vertices: draw_batch.num_vertices, num_instances: draw_batch.num_instances, first_vertex: draw_batch.first_vertex, first_instance: draw_batch.first_instance },
+            });
+            num_recorded_commands += 1;
+        } else {
+            // The dynamic offsets select the instance's part of the dynamic uniform buffers. The first instance keeps gl_InstanceIndex the same as in an instanced draw
+            for instance_index in draw_batch.first_instance..draw_batch.first_instance + draw_batch.num_instances {
+                let dynamic_offsets = vec![instance_index * draw_batch.dynamic_uniform_buffer_stride; draw_batch.num_dynamic_uniform_buffers as usize];
+                recorder.record(DrawCommand::BindDescriptorSet { pipeline_layout, set_index: 1, descriptor_set: draw_batch.descriptor_set, dynamic_offsets });
+                recorder.record(match draw_batch.index_buffer {
+                    Some(_) => DrawCommand::DrawIndexed { num_indices: draw_batch.num_indices, num_instances: 1, first_index: draw_batch.first_index, vertex_offset: draw_batch.first_vertex as i32, first_instance: instance_index },
+                    None => DrawCommand::Draw { num_vertices: draw_batch.num_vertices, num_instances: 1, first_vertex: draw_batch.first_vertex, first_instance: instance_index },
+                });
+            }
+            num_recorded_commands += 2 * draw_batch.num_instances as usize;
+            bound_state.descriptor_set = None;
         }
+        num_recorded_commands
+    }
 
-        unsafe {
-            match self.device.wait_for_fences(&[self.in_flight_fences[self.current_frame]], true, timeout) {
-                Ok(_) => (),
-                Err(_) => return false,
+    /// Draws the debug lines of the frame in the view with the per-frame data at the offset. Returns the number of recorded commands.
+    unsafe fn record_debug_line_draw(device: &Device, command_buffer: &vk::CommandBuffer, global_descriptor_set: vk::DescriptorSet, global_frame_data_offset: u32, render_rect: &vk::Rect2D, swapchain_extent: &vk::Extent2D, debug_drawer: &DebugDrawer, pipeline_manager: &mut PipelineManager, current_frame: usize, allocator: &VkAllocator) -> usize {
+        let mut p_c = debug_drawer.get_pipeline_config().clone();
+        let pipeline = pipeline_manager.get_or_create_pipeline(&mut p_c, device, swapchain_extent, allocator).unwrap();
+        let pipeline_layout = p_c.get_pipeline_layout().unwrap();
+        let (vertex_buffer, vertex_buffer_offset) = debug_drawer.get_vertex_buffer(current_frame);
+        device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_viewport(*command_buffer, 0, &[Self::get_viewport(render_rect)]);
+        device.cmd_set_scissor(*command_buffer, 0, &[*render_rect]);
+        device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 0, &[global_descriptor_set], &[global_frame_data_offset]);
+        device.cmd_bind_vertex_buffers(*command_buffer, 0, &[vertex_buffer], &[vertex_buffer_offset]);
+        device.cmd_draw(*command_buffer, debug_drawer.get_num_vertices(current_frame), 1, 0, 0);
+        // The pipeline, viewport, scissor, descriptor set, vertex buffer and draw
+        6
+    }
+
+    /// Draws all the text of the frame in the render rect of the first view. Returns the number of recorded commands.
+    unsafe fn record_text_draw(device: &Device, command_buffer: &vk::CommandBuffer, global_descriptor_set: vk::DescriptorSet, render_rect: &vk::Rect2D, swapchain_extent: &vk::Extent2D, text_renderer: &TextRenderer, pipeline_manager: &mut PipelineManager, current_frame: usize, allocator: &VkAllocator) -> usize {
+        let mut p_c = text_renderer.get_pipeline_config().clone();
+        let pipeline = pipeline_manager.get_or_create_pipeline(&mut p_c, device, swapchain_extent, allocator).unwrap();
+        let pipeline_layout = p_c.get_pipeline_layout().unwrap();
+        let (vertex_buffer, vertex_buffer_offset) = text_renderer.get_vertex_buffer(current_frame);
+        device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_viewport(*command_buffer, 0, &[Self::get_viewport(render_rect)]);
+        device.cmd_set_scissor(*command_buffer, 0, &[*render_rect]);
+        device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 0, &[global_descriptor_set], &[0]);
+        device.cmd_bind_descriptor_sets(*command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 1, &[text_renderer.get_descriptor_set()], &[]);
+        device.cmd_bind_vertex_buffers(*command_buffer, 0, &[vertex_buffer], &[vertex_buffer_offset]);
+        device.cmd_draw(*command_buffer, text_renderer.get_num_vertices(current_frame), 1, 0, 0);
+        // The pipeline, viewport, scissor, two descriptor sets, vertex buffer and draw
+        7
+    }
+
+    /// Draws the objects of every render target into its color image with its own render pass. Returns the number of recorded commands.
+    fn record_render_target_passes(device: &Device, command_buffer: &vk::CommandBuffer, global_descriptor_set: vk::DescriptorSet, bindless_texture_descriptor_set: Option<vk::DescriptorSet>, object_manager: &ObjectManager, render_target_manager: &RenderTargetManager, pipeline_manager: &mut PipelineManager, current_frame: usize, allocator: &VkAllocator) -> usize {
+        let clear_values = [
+            vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] } },
+            vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+        ];
+        let mut num_recorded_commands = 0;
+        for (render_target_index, render_target) in render_target_manager.get_render_targets().iter().enumerate() {
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: render_target.get_extent(),
             };
+            let render_pass_info = vk::RenderPassBeginInfo {
+                s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+                render_pass: render_target.get_render_pass(),
+                framebuffer: render_target.get_framebuffer(),
+                render_area,
+                clear_value_count: clear_values.len() as u32,
+                p_clear_values: clear_values.as_ptr(),
+                ..Default::default()
+            };
+            let global_frame_data_offset = ((Self::MAX_VIEWS + render_target_index) * Self::GLOBAL_FRAME_DATA_STRIDE) as u32;
+
+            unsafe {
+                device.cmd_begin_render_pass(*command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+                num_recorded_commands += 1;
+                let mut object_draws = Vec::new();
+                for (draw_index, (p_c_k, draw_batch)) in object_manager.get_draws_of_objects(render_target.get_object_ids(), current_frame).into_iter().enumerate() {
+                    let mut p_c = p_c_k.clone();
+                    let pipeline = match pipeline_manager.get_or_create_pipeline(&mut p_c, device, &render_target.get_extent(), allocator).map_err(Cow::from).and_then(|_| pipeline_manager.get_or_create_render_target_pipeline(&p_c, render_target.get_format(), render_target.get_render_pass(), device, &render_target.get_extent(), allocator)) {
+                        Ok(pipeline) => pipeline,
+                        Err(e) => {
+                            log::error!(target: logging::RENDERER, "Failed to get the pipeline for the render target {:?}: {}", render_target.get_id(), e);
+                            continue;
+                        },
+                    };
+                    object_draws.push(ObjectDraw { draw_index, pipeline, pipeline_layout: p_c.get_pipeline_layout().unwrap(), pipeline_label: None, draw_batch });
+                }
+                let mut recorder = CommandBufferRecorder { device, command_buffer: *command_buffer, debug_utils_loader: None };
+                num_recorded_commands += Self::record_object_draws(&mut recorder, &object_draws, render_area, global_descriptor_set, global_frame_data_offset, bindless_texture_descriptor_set, &mut BoundDrawState::default());
+                device.cmd_end_render_pass(*command_buffer);
+                num_recorded_commands += 1;
+            }
         }
+        num_recorded_commands
+    }
 
-        let image_index = match unsafe {
-            self.swapchain_loader.acquire_next_image(self.swapchain, u64::MAX, self.image_available_semaphores[self.current_frame], vk::Fence::null())
-        } {
-            Ok((image_index, _)) => image_index,
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                self.frame_buffer_resized = false;
-                self.recreate_swapchain();
-                return false;
-            },
-            Err(error) => panic!("Failed to acquire next image: {:?}", error),
-        };
-        
+    /// Returns the seconds since the last frame.
+    fn update_global_frame_data(&mut self) -> f32 {
+        self.time.update(Instant::now());
+        let time = self.time.elapsed_seconds();
+        let delta_time = self.time.delta_seconds();
+
+        for (view_index, frame_view) in self.get_views().into_iter().enumerate() {
+            let viewport_size = [frame_view.render_rect.extent.width as f32, frame_view.render_rect.extent.height as f32];
+            let camera_position = glm::inverse(&frame_view.view).column(3).into_owned();
+
+            let data = frame_view.view.as_slice().iter()
+                .chain(frame_view.projection.as_slice())
+                .chain(frame_view.view_projection.as_slice())
+                .chain(camera_position.as_slice())
+                .chain(&[time, delta_time])
+                .chain(&viewport_size)
+                .flat_map(|x| x.to_ne_bytes())
+                .collect::<Vec<u8>>();
+            self.write_global_frame_data(view_index, &data);
+        }
+
+        // The render targets use the slots after the views. Only view_proj and the viewport size are their own
+        let camera_position = glm::inverse(&self.view).column(3).into_owned();
+        for (render_target_index, render_target) in self.render_target_manager.get_render_targets().iter().enumerate() {
+            let viewport_size = [render_target.get_extent().width as f32, render_target.get_extent().height as f32];
+
+            let data = self.view.as_slice().iter()
+                .chain(self.projection.as_slice())
+                .chain(render_target.get_view_projection().as_slice())
+                .chain(camera_position.as_slice())
+                .chain(&[time, delta_time])
+                .chain(&viewport_size)
+                .flat_map(|x| x.to_ne_bytes())
+                .collect::<Vec<u8>>();
+            self.write_global_frame_data(Self::MAX_VIEWS + render_target_index, &data);
+        }
+        delta_time
+    }
+
+    fn write_global_frame_data(&self, slot: usize, data: &[u8]) {
+        debug_assert_eq!(data.len(), Self::GLOBAL_FRAME_DATA_SIZE);
         unsafe {
-            self.device.reset_fences(&[self.in_flight_fences[self.current_frame]]).unwrap();
+            let slot_data_pointer = self.global_frame_data_allocation.get_uniform_pointers()[self.current_frame].cast::<u8>().add(slot * Self::GLOBAL_FRAME_DATA_STRIDE);
+            std::ptr::copy_nonoverlapping(data.as_ptr(), slot_data_pointer, data.len());
         }
+    }
 
-        let cmd_buffer = self.command_buffers[self.current_frame][0];
+    /// Returns every view that is drawn this frame, in pixels of the current swapchain.
+    /// Without any views there is one, which uses the render rect, view and projection set on the controller.
+    fn get_views(&self) -> Vec<FrameView> {
+        if !self.views.is_empty() {
+            return self.views.iter().map(|view| match view {
+                View::Region(region) => {
+                    let (view, projection) = self.cameras[&region.camera];
+                    FrameView {
+                        render_rect: Self::get_region_rect(region.rect, &self.swapchain_extent),
+                        view,
+                        projection,
+                        view_projection: projection * view,
+                        object_filter: region.object_filter.as_ref().map(|object_ids| object_ids.iter().copied().collect()),
+                    }
+                },
+                View::Pixels { render_rect, view_projection } => FrameView {
+                    render_rect: Self::get_render_rect(Some(*render_rect), &self.swapchain_extent),
+                    view: self.view,
+                    projection: self.projection,
+                    view_projection: *view_projection,
+                    object_filter: None,
+                },
+            }).collect();
+        }
+        vec![FrameView {
+            render_rect: Self::get_render_rect(self.render_rect, &self.swapchain_extent),
+            view: self.view,
+            projection: self.projection,
+            view_projection: self.projection * self.view,
+            object_filter: None,
+        }]
+    }
 
-        self.object_manager.update_objects(&self.device, &self.descriptor_pool, self.current_frame, &mut self.allocator);
-        Self::record_command_buffer(&self.device, &cmd_buffer, &self.swapchain_framebuffers, &self.graphics_pipeline_manager.get_render_pass().unwrap(), image_index as usize, &self.swapchain_extent, &self.object_manager, &mut self.graphics_pipeline_manager, self.current_frame, &mut self.allocator);
+    /// The pixels of a region in the swapchain. Both edges are rounded, so regions that touch share an edge without a gap or overlap.
+    fn get_region_rect(rect: (f32, f32, f32, f32), swapchain_extent: &vk::Extent2D) -> vk::Rect2D {
+        let (width, height) = (swapchain_extent.width as f32, swapchain_extent.height as f32);
+        let (x0, y0) = ((rect.0 * width).round() as u32, (rect.1 * height).round() as u32);
+        let (x1, y1) = (((rect.0 + rect.2) * width).round() as u32, ((rect.1 + rect.3) * height).round() as u32);
+        Self::get_render_rect(Some(vk::Rect2D {
+            offset: vk::Offset2D { x: x0 as i32, y: y0 as i32 },
+            extent: vk::Extent2D { width: x1.saturating_sub(x0), height: y1.saturating_sub(y0) },
+        }), swapchain_extent)
+    }
 
-        let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+    /// Adds a camera for [`ViewportRegion`]s with its view and projection matrices.
+    pub fn add_camera(&mut self, view: glm::Mat4, projection: glm::Mat4) -> CameraId {
+        let id = CameraId(self.next_camera_id);
+        self.next_camera_id += 1;
+        self.cameras.insert(id, (view, projection));
+        id
+    }
 
-        let submit_info = vk::SubmitInfo {
-            s_type: StructureType::SUBMIT_INFO,
-            wait_semaphore_count: wait_semaphores.len() as u32,
-            p_wait_semaphores: wait_semaphores.as_ptr(),
-            p_wait_dst_stage_mask: wait_stages.as_ptr(),
-            command_buffer_count: self.command_buffers[self.current_frame].len() as u32,
-            p_command_buffers: self.command_buffers[self.current_frame].as_ptr(),
-            signal_semaphore_count: 1,
-            p_signal_semaphores: signal_semaphores.as_ptr(),
-            ..Default::default()
+    pub fn set_camera(&mut self, id: CameraId, view: glm::Mat4, projection: glm::Mat4) -> Result<(), EngineError> {
+        let Some(camera) = self.cameras.get_mut(&id) else {
+            return Err(EngineError::from(format!("There is no camera with the id {:?}", id)));
         };
+        *camera = (view, projection);
+        Ok(())
+    }
 
-        unsafe {
-            self.device.queue_submit(self.graphics_queue, &[submit_info], self.in_flight_fences[self.current_frame]).unwrap();
+    /// Fails if a viewport region uses the camera.
+    pub fn remove_camera(&mut self, id: CameraId) -> Result<(), EngineError> {
+        if self.views.iter().any(|view| matches!(view, View::Region(region) if region.camera == id)) {
+            return Err(EngineError::from(format!("The camera {:?} is used by a viewport region", id)));
         }
+        self.cameras.remove(&id).map(|_| ()).ok_or_else(|| EngineError::from(format!("There is no camera with the id {:?}", id)))
+    }
 
+    /// Draws every frame once per region, each into its part of the window with the view and projection of its camera, like split-screen.
+    /// The regions are fractions of the window, so they follow it when it is resized. Shaders have to read the view and projection from the per-frame data at `layout(set = 0, binding = 0)`.
+    /// At most [`VkController::MAX_VIEWS`] regions can be set, and an empty Vec draws the whole window with the view and projection set on the controller again.
+    pub fn set_viewports(&mut self, regions: Vec<ViewportRegion>) -> Result<(), EngineError> {
+        if regions.len() > Self::MAX_VIEWS {
+            return Err(EngineError::from(format!("There can be at most {} viewport regions, but {} were given", Self::MAX_VIEWS, regions.len())));
+        }
+        for region in regions.iter() {
+            if !self.cameras.contains_key(&region.camera) {
+                return Err(EngineError::from(format!("There is no camera with the id {:?}", region.camera)));
+            }
+            let (x, y, width, height) = region.rect;
+            if x < 0.0 || y < 0.0 || width < 0.0 || height < 0.0 || x + width > 1.0 || y + height > 1.0 {
+                return Err(EngineError::from(format!("The viewport region {:?} is not inside the window", region.rect)));
+            }
+        }
+        self.views = regions.into_iter().map(View::Region).collect();
+        Ok(())
+    }
 
-        let swapchains = [self.swapchain];
+    /// Draws the objects once per view, each into its own rectangle of the window with its own view projection matrix.
+    /// Only `view_proj` in the per-frame data at `layout(set = 0, binding = 0)` differs between the views, so shaders have to read it from there instead of from an object type uniform buffer.
+    /// The views replace the regions set with [`VkController::set_viewports`] for this frame only, and an empty slice draws one view like without regions.
+    /// At most [`VkController::MAX_VIEWS`] views are drawn. Returns false if the frame was not drawn, like [`VkController::try_to_draw_frame`].
+    pub fn draw_views(&mut self, views: &[(vk::Rect2D, glm::Mat4)]) -> bool {
+        if views.len() > Self::MAX_VIEWS {
+            log::trace!(target: logging::RENDERER, "Only the first {} of the {} views are drawn", Self::MAX_VIEWS, views.len());
+        }
+        let frame_views = views.iter().take(Self::MAX_VIEWS).map(|(render_rect, view_projection)| View::Pixels { render_rect: *render_rect, view_projection: *view_projection }).collect();
+        let regions = std::mem::replace(&mut self.views, frame_views);
+        let is_frame_drawn = self.draw_frame(0);
+        self.views = regions;
+        self.clear_queued_draws();
+        is_frame_drawn
+    }
 
-        let present_info = vk::PresentInfoKHR {
-            s_type: StructureType::PRESENT_INFO_KHR,
-            wait_semaphore_count: 1,
-            p_wait_semaphores: &self.render_finished_semaphores[self.current_frame],
-            swapchain_count: swapchains.len() as u32,
-            p_swapchains: swapchains.as_ptr().cast(),
-            p_image_indices: &image_index,
-            p_results: std::ptr::null_mut(),
-            ..Default::default()
-        };
+    pub fn try_to_draw_frame(&mut self) -> bool {
+        let is_frame_drawn = self.draw_frame(0);
+        self.clear_queued_draws();
+        is_frame_drawn
+    }
 
-        match unsafe {
-            self.swapchain_loader.queue_present(self.present_queue, &present_info)
+    /// Replaces the asset roots on disk, which default to the `assets` directory next to the executable and then the one in the working directory.
+    /// The pipelines that have already been created keep the shaders they were compiled with.
+    pub fn set_asset_root(&mut self, root: PathBuf) {
+        self.graphics_pipeline_manager.get_asset_resolver_mut().set_root(root);
+    }
+
+    /// Searches the root after the other asset roots.
+    pub fn add_asset_root(&mut self, root: PathBuf) {
+        self.graphics_pipeline_manager.get_asset_resolver_mut().add_root(root);
+    }
+
+    /// Finds the assets the same way as the shaders are found, for loading the textures and models of the objects.
+    pub fn get_asset_resolver(&self) -> &AssetResolver {
+        self.graphics_pipeline_manager.get_asset_resolver()
+    }
+
+    /// Replaces the post effects, which run in order on the scene after the main pass. An empty list shows the scene as it is.
+    pub fn set_post_effects(&mut self, effects: Vec<PostEffect>) -> Result<(), EngineError> {
+        // The frames in flight can still use the old targets
+        unsafe {
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX).unwrap();
+        }
+        self.post_processor.destroy_targets(&self.device, &self.allocator);
+        let result = self.post_processor.set_effects(effects, &self.device, &mut self.graphics_pipeline_manager, &self.allocator);
+        // The old effects are kept when the new ones fail, so the targets are created either way
+        self.post_processor.create_targets(&self.device, self.scene_image_allocation.as_ref().unwrap().get_image_view().unwrap(), &self.swapchain_image_views, self.swapchain_extent, &self.allocator)?;
+        result.map_err(EngineError::from)
+    }
+
+    /// Sets the font [`VkController::draw_text`] draws with. Replacing a font waits for the device to be idle, since the frames in flight might still draw with the old one.
+    /// Like the objects, it has to be set after picking and bindless textures have been enabled.
+    pub fn set_font(&mut self, font: BitmapFont) -> Result<(), EngineError> {
+        let text_renderer = TextRenderer::new(font, &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.graphics_queue, &mut self.graphics_pipeline_manager, &mut self.sampler_manager, self.msaa_samples, self.scene_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, &self.allocator)?;
+        if let Some(old_text_renderer) = self.text_renderer.replace(text_renderer) {
+            self.wait_for_device();
+            old_text_renderer.destroy(&self.device, &self.allocator);
+        }
+        Ok(())
+    }
+
+    /// Draws the text in front of the objects in the next frame, in the render rect of the first view. `position_px` is the top left of the first line in pixels, like for sprites.
+    /// `scale` multiplies the size the glyphs have in the font atlas and `color` multiplies their color. The text is only drawn in one frame, so it has to be drawn again every frame.
+    pub fn draw_text(&mut self, text: &str, position_px: glm::Vec2, scale: f32, color: glm::Vec4) -> Result<(), EngineError> {
+        let text_renderer = self.text_renderer.as_mut().ok_or(Cow::Borrowed("A font has to be set before text can be drawn"))?;
+        text_renderer.queue_text(text, position_px, scale, color);
+        Ok(())
+    }
+
+    /// Draws the output of an egui frame over everything else in the next frame, in the render rect of the first view like the text.
+    /// The textures in the output are uploaded right away, and the shapes are tessellated with the context, so it has to be the context that gave the output.
+    #[cfg(feature = "egui")]
+    pub fn draw_egui(&mut self, context: &egui::Context, output: &egui::FullOutput) -> Result<(), EngineError> {
+        if self.egui_renderer.is_none() {
+            self.egui_renderer = Some(EguiRenderer::new(&self.device, &mut self.graphics_pipeline_manager, self.msaa_samples, self.scene_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, &self.allocator)?);
+        }
+        let egui_renderer = self.egui_renderer.as_mut().unwrap();
+        egui_renderer.update_textures(&output.textures_delta, &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.graphics_queue, &mut self.sampler_manager, &self.allocator)?;
+        egui_renderer.queue_primitives(context.tessellate(output.shapes.clone()), context.pixels_per_point());
+        Ok(())
+    }
+
+    /// Draws a line for the next frame only, so it has to be drawn again every frame it should be seen.
+    pub fn debug_line(&mut self, a: glm::Vec3, b: glm::Vec3, color: glm::Vec4) {
+        if let Some(debug_drawer) = self.get_or_create_debug_drawer() {
+            debug_drawer.queue_line(a, b, color);
+        }
+    }
+
+    /// Draws the edges of the axis aligned box for the next frame only.
+    pub fn debug_box(&mut self, min: glm::Vec3, max: glm::Vec3, color: glm::Vec4) {
+        if let Some(debug_drawer) = self.get_or_create_debug_drawer() {
+            debug_drawer.queue_box(min, max, color);
+        }
+    }
+
+    /// Draws a circle around each axis of the sphere for the next frame only.
+    pub fn debug_sphere(&mut self, center: glm::Vec3, radius: f32, color: glm::Vec4) {
+        if let Some(debug_drawer) = self.get_or_create_debug_drawer() {
+            debug_drawer.queue_sphere(center, radius, color);
+        }
+    }
+
+    // The shapes are only for debugging, so failing to create the drawer is reported instead of returned
+    fn get_or_create_debug_drawer(&mut self) -> Option<&mut DebugDrawer> {
+        if self.debug_drawer.is_none() {
+            match DebugDrawer::new(&self.device, &mut self.graphics_pipeline_manager, self.msaa_samples, self.scene_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, &self.allocator) {
+                Ok(debug_drawer) => self.debug_drawer = Some(debug_drawer),
+                Err(e) => log::error!(target: logging::RENDERER, "Failed to create the debug drawer: {}", e),
+            }
+        }
+        self.debug_drawer.as_mut()
+    }
+
+    // The text, debug shapes and egui meshes are cleared whether the frame was drawn or not, so a frame that is skipped doesn't draw them twice in the next one
+    fn clear_queued_draws(&mut self) {
+        if let Some(text_renderer) = self.text_renderer.as_mut() {
+            text_renderer.clear_queued_text();
+        }
+        if let Some(debug_drawer) = self.debug_drawer.as_mut() {
+            debug_drawer.clear_queued_lines();
+        }
+        if let Some(egui_renderer) = self.egui_renderer.as_mut() {
+            egui_renderer.clear_queued_primitives();
+        }
+    }
+
+    fn draw_frame(&mut self, timeout: u64) -> bool {
+        if self.is_minimized && !self.frame_buffer_resized {
+            return false;
+        }
+
+        unsafe {
+            match self.device.wait_for_fences(&[self.in_flight_fences[self.current_frame]], true, timeout) {
+                Ok(_) => (),
+                Err(_) => return false,
+            };
+        }
+
+        // The fence guarantees that the last frame recorded in this frame slot has finished, so its timestamps can be read without waiting
+        self.read_last_frame_gpu_time();
+        self.read_last_frame_pipeline_stats();
+        self.render_target_manager.destroy_unused_resources(&self.device, self.frames_in_flight, &self.allocator);
+        for sampler in self.object_manager.take_released_samplers() {
+            self.sampler_manager.release_sampler(sampler);
+        }
+        self.sampler_manager.destroy_idle_samplers(&self.device, self.frames_in_flight, &self.allocator);
+        self.update_memory_pressure();
+        self.destroy_retired_swapchains();
+
+        // A failed recreation leaves no swapchain, so it is tried again before anything is drawn
+        if self.swapchain == SwapchainKHR::null() {
+            if let Err(e) = self.recreate_swapchain() {
+                log::error!(target: logging::RENDERER, "{}", e);
+            }
+            return false;
+        }
+
+        let image_index = match unsafe {
+            self.swapchain_loader.acquire_next_image(self.swapchain, u64::MAX, self.image_available_semaphores[self.current_frame], vk::Fence::null())
         } {
-            Ok(_) => (),
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) | Err(vk::Result::SUBOPTIMAL_KHR) => {
+            Ok((image_index, _)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                 self.frame_buffer_resized = false;
-                self.recreate_swapchain();
+                if let Err(e) = self.recreate_swapchain() {
+                    log::error!(target: logging::RENDERER, "{}", e);
+                }
+                return false;
             },
+            Err(error) => panic!("Failed to acquire next image: {:?}", error),
+        };
+        
+        unsafe {
+            self.device.reset_fences(&[self.in_flight_fences[self.current_frame]]).unwrap();
+        }
+        self.render_doc_capture.start_frame();
+
+        let cmd_buffer = self.command_buffers[self.current_frame][0];
+
+        let delta_time = self.update_global_frame_data();
+        let views = self.get_views();
+        let camera_position = glm::inverse(&self.view).column(3).xyz();
+        self.object_manager.update_fallback_resources(&mut self.graphics_pipeline_manager, &self.device, &self.swapchain_extent, &self.allocator);
+        if let Err(err) = self.object_manager.reupload_outdated_textures(&self.graphics_pipeline_manager, &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.descriptor_pool, &self.graphics_queue, &mut self.sampler_manager, &self.allocator) {
+            log::error!(target: logging::RENDERER, "Failed to upload the textures again: {}", err);
+        }
+        self.update_async_loads(self.time.frame_index());
+        self.free_finished_upload_allocations();
+        if let Some(debug_utils_loader) = self.debug_utils_loader.as_ref() {
+            if let Some(debug_names) = self.object_manager.take_outdated_debug_names() {
+                for (object_type, object_handle, name) in debug_names {
+                    Self::set_debug_utils_object_name(debug_utils_loader, &self.device, object_type, object_handle, &name);
+                }
+            }
+        }
+        if let Some(text_renderer) = self.text_renderer.as_mut() {
+            text_renderer.upload_queued_text(self.current_frame);
+        }
+        if let Some(debug_drawer) = self.debug_drawer.as_mut() {
+            debug_drawer.upload_queued_lines(self.current_frame);
+        }
+        if let Some(egui_renderer) = self.egui_renderer.as_mut() {
+            egui_renderer.upload_queued_primitives(self.current_frame, &self.device, &self.allocator);
+        }
+        self.light_manager.upload_if_outdated(self.current_frame);
+        self.object_manager.update_objects(&self.device, &self.descriptor_pool, &self.render_target_manager.get_textures(), &camera_position, delta_time, self.current_frame, self.time.frame_index(), &self.allocator);
+        let frame = FrameRecording {
+            command_buffer: cmd_buffer,
+            current_frame: self.current_frame,
+            image_index: image_index as usize,
+            swapchain_image: self.swapchain_images[image_index as usize],
+            scene_image: self.scene_image_allocation.as_ref().unwrap().get_image().unwrap(),
+            scene_framebuffer: self.scene_framebuffer,
+            swapchain_extent: &self.swapchain_extent,
+            global_descriptor_set: self.global_descriptor_sets[self.current_frame],
+            bindless_texture_descriptor_set: self.texture_manager.as_ref().map(|texture_manager| texture_manager.get_descriptor_set()),
+            clear_mode: self.clear_mode,
+            views: &views,
+            timestamp_query_pool: self.timestamp_query_pool,
+            pipeline_statistics_query_pool: self.pipeline_statistics_query_pool,
+            frame_report: self.is_frame_report_enabled.then_some(&mut self.frame_report),
+            debug_utils_loader: self.debug_utils_loader.as_ref(),
+            text_renderer: self.text_renderer.as_ref(),
+            debug_drawer: self.debug_drawer.as_ref(),
+            egui_renderer: self.egui_renderer.as_ref(),
+            extra_recording: self.extra_recording.as_mut(),
+        };
+        self.num_recorded_commands = Self::record_command_buffer(&self.device, frame, &self.post_processor, &self.object_manager, &self.render_target_manager, &mut self.graphics_pipeline_manager, &self.allocator);
+        self.are_timestamps_written[self.current_frame] = self.timestamp_query_pool.is_some();
+        self.are_pipeline_statistics_written[self.current_frame] = self.pipeline_statistics_query_pool.is_some();
+
+        let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+
+        let submit_info = vk::SubmitInfo {
+            s_type: StructureType::SUBMIT_INFO,
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: self.command_buffers[self.current_frame].len() as u32,
+            p_command_buffers: self.command_buffers[self.current_frame].as_ptr(),
+            signal_semaphore_count: 1,
+            p_signal_semaphores: signal_semaphores.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            let _queue_lock = self.allocator.lock_queue();
+            self.device.queue_submit(self.graphics_queue, &[submit_info], self.in_flight_fences[self.current_frame]).unwrap();
+        }
+        self.is_depth_available = self.is_depth_kept;
+        if self.graphics_pipeline_manager.is_picking_enabled() {
+            self.picking_draw_object_types = Some(self.object_manager.get_draws_in_order(self.current_frame).into_iter().map(|(_, draw_batch)| draw_batch.object_type).collect());
+        }
+
+
+        let swapchains = [self.swapchain];
+
+        // The id is only used to match the display timings to the presents, and 0 as the desired time shows the image as soon as possible
+        let present_times = [vk::PresentTimeGOOGLE {
+            present_id: self.next_present_id,
+            desired_present_time: 0,
+        }];
+        let present_times_info = vk::PresentTimesInfoGOOGLE {
+            s_type: StructureType::PRESENT_TIMES_INFO_GOOGLE,
+            swapchain_count: present_times.len() as u32,
+            p_times: present_times.as_ptr(),
+            ..Default::default()
+        };
+
+        let present_info = vk::PresentInfoKHR {
+            s_type: StructureType::PRESENT_INFO_KHR,
+            p_next: if self.display_timing_fn.is_some() { &present_times_info as *const vk::PresentTimesInfoGOOGLE as *const std::ffi::c_void } else { std::ptr::null() },
+            wait_semaphore_count: 1,
+            p_wait_semaphores: &self.render_finished_semaphores[self.current_frame],
+            swapchain_count: swapchains.len() as u32,
+            p_swapchains: swapchains.as_ptr().cast(),
+            p_image_indices: &image_index,
+            p_results: std::ptr::null_mut(),
+        };
+
+        let queue_lock = self.allocator.lock_queue();
+        let present_start = Instant::now();
+        let present_result = unsafe {
+            self.swapchain_loader.queue_present(self.present_queue, &present_info)
+        };
+        drop(queue_lock);
+        let is_swapchain_out_of_date = match present_result {
+            Ok(is_suboptimal) => is_suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) | Err(vk::Result::SUBOPTIMAL_KHR) => true,
             Err(error) => panic!("Failed to present queue: {:?}", error),
         };
-        if self.frame_buffer_resized {
+        self.render_doc_capture.end_frame();
+        self.next_present_id = self.next_present_id.wrapping_add(1);
+        self.update_present_timing(present_start);
+        // Any number of resize events and out of date results during one frame only leads to a single recreation
+        if is_swapchain_out_of_date || self.frame_buffer_resized {
             self.frame_buffer_resized = false;
-            self.recreate_swapchain();
+            if let Err(e) = self.recreate_swapchain() {
+                log::error!(target: logging::RENDERER, "{}", e);
+            }
         }
 
-        self.current_frame = (self.current_frame + 1) % Self::MAX_FRAMES_IN_FLIGHT;
+        self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
 
         true
     }
@@ -897,10 +2869,245 @@ impl VkController {
 
 // Synchronization and utilities
 impl VkController {
-    fn create_sync_objects(device: &Device, allocator: &mut VkAllocator) -> (Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>) {
-        let mut image_available_semaphores = Vec::with_capacity(Self::MAX_FRAMES_IN_FLIGHT);
-        let mut render_finished_semaphores = Vec::with_capacity(Self::MAX_FRAMES_IN_FLIGHT);
-        let mut in_flight_fences = Vec::with_capacity(Self::MAX_FRAMES_IN_FLIGHT);
+    fn create_timestamp_query_pool(instance: &Instance, physical_device: &PhysicalDevice, device: &Device, queue_families: &QueueFamilyIndices, allocator: &VkAllocator) -> (Option<vk::QueryPool>, f32, u32) {
+        let timestamp_period = unsafe {
+            instance.get_physical_device_properties(*physical_device)
+        }.limits.timestamp_period;
+        let timestamp_valid_bits = unsafe {
+            instance.get_physical_device_queue_family_properties(*physical_device)
+        }[queue_families.graphics_family.unwrap() as usize].timestamp_valid_bits;
+
+        if timestamp_valid_bits == 0 {
+            return (None, timestamp_period, timestamp_valid_bits);
+        }
+
+        let query_pool_create_info = vk::QueryPoolCreateInfo {
+            s_type: StructureType::QUERY_POOL_CREATE_INFO,
+            query_type: vk::QueryType::TIMESTAMP,
+            query_count: (Self::MAX_FRAMES_IN_FLIGHT * 2) as u32,
+            ..Default::default()
+        };
+
+        let query_pool = match unsafe {
+            device.create_query_pool(&query_pool_create_info, allocator.get_allocation_callbacks())
+        } {
+            Ok(query_pool) => Some(query_pool),
+            Err(e) => {
+                log::warn!(target: logging::RENDERER, "Failed to create the timestamp query pool, so the GPU frame time will not be available: {:?}", e);
+                None
+            },
+        };
+
+        (query_pool, timestamp_period, timestamp_valid_bits)
+    }
+
+    fn read_last_frame_gpu_time(&mut self) {
+        let timestamp_query_pool = match self.timestamp_query_pool {
+            Some(timestamp_query_pool) if self.are_timestamps_written[self.current_frame] => timestamp_query_pool,
+            _ => return,
+        };
+
+        let mut timestamps = [0_u64; 2];
+        let result = unsafe {
+            self.device.get_query_pool_results(timestamp_query_pool, (self.current_frame * 2) as u32, 2, &mut timestamps, vk::QueryResultFlags::TYPE_64)
+        };
+        if result.is_err() {
+            return;
+        }
+        self.are_timestamps_written[self.current_frame] = false;
+
+        // Only the lowest timestamp_valid_bits bits of the timestamps are valid, and the counter can wrap around between the two
+        let valid_mask = if self.timestamp_valid_bits >= 64 { u64::MAX } else { (1_u64 << self.timestamp_valid_bits) - 1 };
+        let ticks = timestamps[1].wrapping_sub(timestamps[0]) & valid_mask;
+        self.last_frame_gpu_time_ms = Some(ticks as f32 * self.timestamp_period / 1_000_000.0);
+    }
+
+    /// The GPU time of the last frame that has finished rendering, in milliseconds. Because of the frames in flight this is a frame that was drawn
+    /// [`VkControllerBuilder::frames_in_flight`] frames ago. None until a frame has finished, or when the graphics queue does not support timestamps.
+    pub fn last_frame_gpu_time_ms(&self) -> Option<f32> {
+        self.last_frame_gpu_time_ms
+    }
+
+    /// The timing of the last present, which is meant for profiling with the `IMMEDIATE` present mode, since `FIFO` limits the frame rate to the display
+    /// and `MAILBOX` throws away frames. None until a frame has been presented.
+    pub fn last_present_timing(&self) -> Option<PresentTiming> {
+        self.last_present_timing
+    }
+
+    fn update_present_timing(&mut self, present_start: Instant) {
+        let queue_present_ms = present_start.elapsed().as_secs_f32() * 1000.0;
+        let cpu_present_interval_ms = self.last_present_instant.map(|last_present| present_start.saturating_duration_since(last_present).as_secs_f32() * 1000.0);
+        self.last_present_instant = Some(present_start);
+
+        let mut present_timing = PresentTiming {
+            source: PresentTimingSource::CpuQueuePresent,
+            present_mode: self.active_present_mode,
+            is_tearing: self.active_present_mode == vk::PresentModeKHR::IMMEDIATE,
+            present_interval_ms: cpu_present_interval_ms,
+            queue_present_ms,
+            present_margin_ms: None,
+            refresh_cycle_ms: None,
+        };
+        if let Some(display_timing_fn) = &self.display_timing_fn {
+            // The images are reported a few frames after they are presented, so there are often none or several new ones
+            let past_timings = Self::get_past_presentation_timings(display_timing_fn, &self.device, self.swapchain);
+            match (past_timings.last(), self.last_present_timing) {
+                (Some(newest), _) => {
+                    let previous_present_time = past_timings.len().checked_sub(2).map(|i| past_timings[i].actual_present_time).or(self.last_actual_present_time);
+                    let mut refresh_cycle = vk::RefreshCycleDurationGOOGLE::default();
+                    let refresh_cycle_result = unsafe { (display_timing_fn.get_refresh_cycle_duration_google)(self.device.handle(), self.swapchain, &mut refresh_cycle) };
+                    present_timing.source = PresentTimingSource::DisplayTiming;
+                    present_timing.present_interval_ms = previous_present_time.map(|previous| newest.actual_present_time.saturating_sub(previous) as f32 / 1_000_000.0);
+                    present_timing.present_margin_ms = Some(newest.present_margin as f32 / 1_000_000.0);
+                    present_timing.refresh_cycle_ms = (refresh_cycle_result == vk::Result::SUCCESS).then(|| refresh_cycle.refresh_duration as f32 / 1_000_000.0);
+                    self.last_actual_present_time = Some(newest.actual_present_time);
+                },
+                // Nothing new has been shown, so the last shown image's timing is still the newest
+                (None, Some(last_present_timing)) if last_present_timing.source == PresentTimingSource::DisplayTiming => {
+                    present_timing = PresentTiming { queue_present_ms, ..last_present_timing };
+                },
+                (None, _) => (),
+            }
+        }
+        self.last_present_timing = Some(present_timing);
+    }
+
+    fn get_past_presentation_timings(display_timing_fn: &vk::GoogleDisplayTimingFn, device: &Device, swapchain: SwapchainKHR) -> Vec<vk::PastPresentationTimingGOOGLE> {
+        let mut num_timings = 0;
+        if unsafe { (display_timing_fn.get_past_presentation_timing_google)(device.handle(), swapchain, &mut num_timings, std::ptr::null_mut()) } != vk::Result::SUCCESS {
+            return Vec::new();
+        }
+        let mut timings = vec![vk::PastPresentationTimingGOOGLE::default(); num_timings as usize];
+        match unsafe { (display_timing_fn.get_past_presentation_timing_google)(device.handle(), swapchain, &mut num_timings, timings.as_mut_ptr()) } {
+            vk::Result::SUCCESS | vk::Result::INCOMPLETE => {
+                timings.truncate(num_timings as usize);
+                timings
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    // The order of the results from a query follows the order of the bits, so this has to match the fields read in `read_last_frame_pipeline_stats`
+    const PIPELINE_STATISTICS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+        vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw() |
+        vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw() |
+        vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw() |
+        vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw() |
+        vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+    );
+
+    fn create_pipeline_statistics_query_pool(instance: &Instance, physical_device: &PhysicalDevice, device: &Device, allocator: &VkAllocator) -> Option<vk::QueryPool> {
+        let supported_features = unsafe {
+            instance.get_physical_device_features(*physical_device)
+        };
+        if supported_features.pipeline_statistics_query != vk::TRUE {
+            return None;
+        }
+
+        let query_pool_create_info = vk::QueryPoolCreateInfo {
+            s_type: StructureType::QUERY_POOL_CREATE_INFO,
+            query_type: vk::QueryType::PIPELINE_STATISTICS,
+            query_count: Self::MAX_FRAMES_IN_FLIGHT as u32,
+            pipeline_statistics: Self::PIPELINE_STATISTICS,
+            ..Default::default()
+        };
+
+        match unsafe {
+            device.create_query_pool(&query_pool_create_info, allocator.get_allocation_callbacks())
+        } {
+            Ok(query_pool) => Some(query_pool),
+            Err(e) => {
+                log::warn!(target: logging::RENDERER, "Failed to create the pipeline statistics query pool, so the pipeline statistics will not be available: {:?}", e);
+                None
+            },
+        }
+    }
+
+    fn read_last_frame_pipeline_stats(&mut self) {
+        let pipeline_statistics_query_pool = match self.pipeline_statistics_query_pool {
+            Some(pipeline_statistics_query_pool) if self.are_pipeline_statistics_written[self.current_frame] => pipeline_statistics_query_pool,
+            _ => return,
+        };
+
+        let mut statistics = [0_u64; 5];
+        let result = unsafe {
+            self.device.get_query_pool_results(pipeline_statistics_query_pool, self.current_frame as u32, 1, &mut statistics, vk::QueryResultFlags::TYPE_64)
+        };
+        if result.is_err() {
+            return;
+        }
+        self.are_pipeline_statistics_written[self.current_frame] = false;
+
+        self.last_frame_pipeline_stats = Some(PipelineStats {
+            input_assembly_vertices: statistics[0],
+            input_assembly_primitives: statistics[1],
+            vertex_shader_invocations: statistics[2],
+            clipping_primitives: statistics[3],
+            fragment_shader_invocations: statistics[4],
+        });
+    }
+
+    /// The pipeline statistics of the last frame that has finished rendering, with the same latency as [`VkController::last_frame_gpu_time_ms`].
+    /// None until a frame has finished, or when the physical device does not support pipeline statistics queries.
+    pub fn last_frame_pipeline_stats(&self) -> Option<PipelineStats> {
+        self.last_frame_pipeline_stats
+    }
+
+    pub fn get_texture_cache_stats(&self) -> TextureCacheStats {
+        self.object_manager.get_texture_cache_stats()
+    }
+
+    /// The samplers of textures that are no longer used are destroyed a few frames after, so this goes back down when textured objects are removed.
+    pub fn get_sampler_stats(&self) -> SamplerStats {
+        self.sampler_manager.get_stats()
+    }
+
+    /// Scales the object types' textures down and caps their mip chains, to use less memory on devices with little of it. `max_mip_levels` is at least 1.
+    /// Textures that are already uploaded are uploaded again before the next frame. The bindless textures are not affected.
+    pub fn set_texture_quality(&mut self, quality: TextureQuality, max_mip_levels: u32) {
+        self.object_manager.set_texture_quality(quality, max_mip_levels);
+    }
+
+    pub fn texture_quality(&self) -> (TextureQuality, u32) {
+        self.object_manager.get_texture_quality()
+    }
+
+    /// What was recorded in the last frame per pipeline and object type. Empty when the report is disabled.
+    pub fn frame_debug_report(&self) -> &FrameReport {
+        &self.frame_report
+    }
+
+    pub fn set_frame_report_enabled(&mut self, is_enabled: bool) {
+        self.is_frame_report_enabled = is_enabled;
+        if !is_enabled {
+            self.frame_report = FrameReport::default();
+        }
+    }
+
+    /// The draws that the next recorded frame will contain, in the order they are recorded.
+    pub fn get_draw_batches(&self) -> Vec<DrawBatch> {
+        self.object_manager.get_draws_in_order(self.current_frame).into_iter().map(|(_, draw_batch)| draw_batch).collect()
+    }
+
+    pub fn get_num_instances(&self, object_type: ObjectType) -> Option<usize> {
+        self.object_manager.get_num_instances(object_type)
+    }
+
+    /// Returns the closest object the ray hits and the distance to it in lengths of `direction`, without any GPU work.
+    /// Each object is transformed by the mat4 at the start of its instance resource at binding 0, which is the model matrix the shaders use.
+    pub fn raycast(&self, origin: glm::Vec3, direction: glm::Vec3) -> Option<(ObjectID, f32)> {
+        self.object_manager.raycast(&origin, &direction)
+    }
+
+    /// The total size in bytes of the vertex and index data of all the meshes. Object types with the same mesh share its data.
+    pub fn get_geometry_buffer_sizes(&self) -> (usize, usize) {
+        self.object_manager.get_geometry_buffer_sizes()
+    }
+
+    fn create_sync_objects(device: &Device, frames_in_flight: usize, allocator: &VkAllocator) -> (Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>) {
+        let mut image_available_semaphores = Vec::with_capacity(frames_in_flight);
+        let mut render_finished_semaphores = Vec::with_capacity(frames_in_flight);
+        let mut in_flight_fences = Vec::with_capacity(frames_in_flight);
 
         let semaphore_create_info = vk::SemaphoreCreateInfo {
             s_type: StructureType::SEMAPHORE_CREATE_INFO,
@@ -913,18 +3120,18 @@ impl VkController {
             ..Default::default()
         };
 
-        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
+        for _ in 0..frames_in_flight {
 
             image_available_semaphores.push(unsafe {
-                device.create_semaphore(&semaphore_create_info, Some(&allocator.get_allocation_callbacks()))
+                device.create_semaphore(&semaphore_create_info, allocator.get_allocation_callbacks())
             }.unwrap());
 
             render_finished_semaphores.push(unsafe {
-                device.create_semaphore(&semaphore_create_info, Some(&allocator.get_allocation_callbacks()))
+                device.create_semaphore(&semaphore_create_info, allocator.get_allocation_callbacks())
             }.unwrap());
 
             in_flight_fences.push(unsafe {
-                device.create_fence(&fence_create_info, Some(&allocator.get_allocation_callbacks()))
+                device.create_fence(&fence_create_info, allocator.get_allocation_callbacks())
             }.unwrap());
         }
 
@@ -934,11 +3141,17 @@ impl VkController {
 
 // Resource management
 impl VkController {
-    fn create_descriptor_pool(device: &Device, allocator: &mut VkAllocator) -> vk::DescriptorPool {
+    fn create_descriptor_pool(device: &Device, allocator: &VkAllocator) -> vk::DescriptorPool {
         let pool_sizes = [
+            // For the lights in the global descriptor sets and the uniform buffers of the object types
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: Self::MAX_FRAMES_IN_FLIGHT as u32,
+                descriptor_count: 2 * Self::MAX_FRAMES_IN_FLIGHT as u32,
+            },
+            // For the global descriptor sets and the dynamic uniform buffers of the object types
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: 2 * Self::MAX_FRAMES_IN_FLIGHT as u32,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_BUFFER,
@@ -954,26 +3167,86 @@ impl VkController {
             s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
             pool_size_count: pool_sizes.len() as u32,
             p_pool_sizes: pool_sizes.as_ptr(),
-            max_sets: Self::MAX_FRAMES_IN_FLIGHT as u32 * Self::MAX_OBJECT_TYPES as u32,
+            max_sets: Self::MAX_FRAMES_IN_FLIGHT as u32 * (Self::MAX_OBJECT_TYPES as u32 + 1),
             flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
             ..Default::default()
         };
 
         unsafe {
-            device.create_descriptor_pool(&pool_info, Some(&allocator.get_allocation_callbacks()))
+            device.create_descriptor_pool(&pool_info, allocator.get_allocation_callbacks())
         }.unwrap()
     }
 
-    fn create_depth_resources(instance: &Instance, physical_device: &PhysicalDevice, swapchain_extent: &vk::Extent2D, msaa_samples: vk::SampleCountFlags, allocator: &mut VkAllocator) -> AllocationInfo {
+    fn create_depth_resources(instance: &Instance, physical_device: &PhysicalDevice, swapchain_extent: &vk::Extent2D, msaa_samples: vk::SampleCountFlags, allocator: &VkAllocator) -> AllocationInfo {
         let depth_format = Self::find_depth_format(instance, physical_device);
 
-        let mut allocation_info = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, msaa_samples, depth_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
+        let mut allocation_info = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, 1, vk::ImageCreateFlags::empty(), msaa_samples, depth_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
 
-        allocator.create_image_view(&mut allocation_info, depth_format, vk::ImageAspectFlags::DEPTH, 1).unwrap();
+        allocator.create_image_view(&mut allocation_info, depth_format, vk::ImageAspectFlags::DEPTH, 1, vk::ImageViewType::TYPE_2D).unwrap();
 
         allocation_info
     }
 
+    fn create_depth_resolve_resources(instance: &Instance, physical_device: &PhysicalDevice, swapchain_extent: &vk::Extent2D, msaa_samples: vk::SampleCountFlags, allocator: &VkAllocator) -> Option<AllocationInfo> {
+        if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            return None;
+        }
+        Some(Self::create_depth_resources(instance, physical_device, swapchain_extent, vk::SampleCountFlags::TYPE_1, allocator))
+    }
+
+    // In the order of the render pass attachments after the scene image
+    fn get_extra_framebuffer_image_views(&self) -> Vec<ImageView> {
+        let extra_color_allocations = self.extra_color_attachment_allocations.iter().flat_map(|(allocation, resolve_allocation)| std::iter::once(allocation).chain(resolve_allocation.as_ref()));
+        [&self.depth_resolve_image_allocation, &self.object_id_image_allocation, &self.object_id_resolve_image_allocation].into_iter().flatten().chain(extra_color_allocations).map(|allocation| allocation.get_image_view().unwrap()).collect()
+    }
+
+    fn create_extra_color_resources(&mut self) {
+        for format in self.graphics_pipeline_manager.get_extra_color_attachment_formats().to_vec() {
+            // The single sampled image is the one that is read after the pass, so only it has to be sampled
+            let allocation = if self.msaa_samples == vk::SampleCountFlags::TYPE_1 {
+                Self::create_extra_color_resource(format, &self.swapchain_extent, self.msaa_samples, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED, &self.allocator)
+            } else {
+                Self::create_extra_color_resource(format, &self.swapchain_extent, self.msaa_samples, vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT, &self.allocator)
+            };
+            let resolve_allocation = (self.msaa_samples != vk::SampleCountFlags::TYPE_1).then(|| Self::create_extra_color_resource(format, &self.swapchain_extent, vk::SampleCountFlags::TYPE_1, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED, &self.allocator));
+            self.extra_color_attachment_allocations.push((allocation, resolve_allocation));
+        }
+    }
+
+    fn create_extra_color_resource(format: vk::Format, swapchain_extent: &vk::Extent2D, num_samples: vk::SampleCountFlags, usage: vk::ImageUsageFlags, allocator: &VkAllocator) -> AllocationInfo {
+        let mut allocation = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, 1, vk::ImageCreateFlags::empty(), num_samples, format, vk::ImageTiling::OPTIMAL, usage, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
+
+        allocator.create_image_view(&mut allocation, format, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D).unwrap();
+
+        allocation
+    }
+
+    fn free_extra_color_resources(&mut self) {
+        for (allocation, resolve_allocation) in std::mem::take(&mut self.extra_color_attachment_allocations) {
+            for allocation in std::iter::once(allocation).chain(resolve_allocation) {
+                self.allocator.free_memory_allocation(allocation).unwrap();
+            }
+        }
+    }
+
+    fn create_picking_resources(&mut self) {
+        if !self.graphics_pipeline_manager.is_picking_enabled() {
+            return;
+        }
+        self.object_id_image_allocation = Some(Self::create_object_id_resources(&self.swapchain_extent, self.msaa_samples, &self.allocator));
+        if self.msaa_samples != vk::SampleCountFlags::TYPE_1 {
+            self.object_id_resolve_image_allocation = Some(Self::create_object_id_resources(&self.swapchain_extent, vk::SampleCountFlags::TYPE_1, &self.allocator));
+        }
+    }
+
+    fn create_object_id_resources(swapchain_extent: &vk::Extent2D, num_samples: vk::SampleCountFlags, allocator: &VkAllocator) -> AllocationInfo {
+        let mut object_id_allocation = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, 1, vk::ImageCreateFlags::empty(), num_samples, PipelineManager::OBJECT_ID_FORMAT, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
+
+        allocator.create_image_view(&mut object_id_allocation, PipelineManager::OBJECT_ID_FORMAT, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D).unwrap();
+
+        object_id_allocation
+    }
+
     fn find_supported_formats(instance: &Instance, physical_device: &PhysicalDevice, candidates: &[vk::Format], tiling: vk::ImageTiling, features: vk::FormatFeatureFlags) -> Option<vk::Format> {
         for format in candidates {
             let props = unsafe {
@@ -1015,10 +3288,19 @@ impl VkController {
         }
     }
 
-    fn create_color_resources(swapchain_format: vk::Format, swapchain_extent: &vk::Extent2D, num_samples: vk::SampleCountFlags, allocator: &mut VkAllocator) -> AllocationInfo {
-        let mut color_allocation = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, num_samples, swapchain_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
+    // Sampled by the first post effect, or blitted to the swapchain image when there are none
+    fn create_scene_resources(scene_format: vk::Format, swapchain_extent: &vk::Extent2D, allocator: &VkAllocator) -> AllocationInfo {
+        let mut scene_allocation = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, 1, vk::ImageCreateFlags::empty(), vk::SampleCountFlags::TYPE_1, scene_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
+
+        allocator.create_image_view(&mut scene_allocation, scene_format, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D).unwrap();
+
+        scene_allocation
+    }
+
+    fn create_color_resources(swapchain_format: vk::Format, swapchain_extent: &vk::Extent2D, num_samples: vk::SampleCountFlags, allocator: &VkAllocator) -> AllocationInfo {
+        let mut color_allocation = allocator.create_image(swapchain_extent.width, swapchain_extent.height, 1, 1, vk::ImageCreateFlags::empty(), num_samples, swapchain_format, vk::ImageTiling::OPTIMAL, vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
 
-        allocator.create_image_view(&mut color_allocation, swapchain_format, vk::ImageAspectFlags::COLOR, 1).unwrap();
+        allocator.create_image_view(&mut color_allocation, swapchain_format, vk::ImageAspectFlags::COLOR, 1, vk::ImageViewType::TYPE_2D).unwrap();
 
         color_allocation
     }
@@ -1027,35 +3309,904 @@ impl VkController {
         self.swapchain_extent
     }
 
-    // The object will not be remove until the all frames in flight have passed
-    pub fn remove_objects_to_render(&mut self, object_ids: Vec<ObjectID>) -> Result<(), Cow<'static, str>> {
-        self.object_manager.remove_objects(object_ids, &self.command_pool, &self.graphics_queue, self.current_frame, &mut self.allocator)
+    /// Clears every frame to the linear color, which switches the clear mode to [`ClearMode::Clear`]. Takes effect from the next frame.
+    pub fn set_clear_color(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.set_clear_mode(ClearMode::Clear([r, g, b, a]));
     }
-}
 
-// Debugging and validation
-impl VkController {
-    fn setup_debug_messenger(entry: &Entry, instance: &Instance, debug_utils_create_info: DebugUtilsMessengerCreateInfoEXT) -> vk::DebugUtilsMessengerEXT {
-        let debug_utils_loader = DebugUtils::new(entry, instance);
-        match unsafe {
-            debug_utils_loader.create_debug_utils_messenger(&debug_utils_create_info, None)
-        } {
-            Ok(messenger) => messenger,
-            Err(e) => panic!("Failed to set up debug messenger: {:?}", e),
+    /// The render pass and framebuffers are recreated when the attachment load operation changes, changing only the clear color is free.
+    pub fn set_clear_mode(&mut self, clear_mode: ClearMode) {
+        let is_load_op_changed = clear_mode.get_attachment_load_op() != self.clear_mode.get_attachment_load_op();
+        self.clear_mode = clear_mode;
+        if is_load_op_changed {
+            self.recreate_render_pass();
         }
     }
 
-    fn get_debug_messenger_create_info() -> DebugUtilsMessengerCreateInfoEXT {
-        DebugUtilsMessengerCreateInfoEXT {
-            s_type: StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
-            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::INFO | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
-            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-            pfn_user_callback: Some(Self::debug_callback),
-            ..Default::default()
+    pub fn get_clear_mode(&self) -> ClearMode {
+        self.clear_mode
+    }
+
+    /// Stores the depth of every frame so that it can be read with [`VkController::read_depth`]. Recreates the render pass when the setting changes.
+    pub fn set_keep_depth(&mut self, is_depth_kept: bool) {
+        if self.is_depth_kept == is_depth_kept {
+            return;
         }
+        self.is_depth_kept = is_depth_kept;
+        self.recreate_render_pass();
     }
 
-    unsafe extern "system" fn debug_callback(
+    /// Returns the width, height and the raw depth values of the last drawn frame, row by row. Depth has to be kept with [`VkController::set_keep_depth`] before the frame is drawn.
+    /// Waits for the frames in flight to finish.
+    pub fn read_depth(&mut self) -> Result<(u32, u32, Vec<f32>), EngineError> {
+        if !self.is_depth_kept {
+            return Err(EngineError::from("Can not read the depth when it is not kept, call set_keep_depth first"));
+        }
+        if !self.is_depth_available {
+            return Err(EngineError::from("Can not read the depth before a frame has been drawn"));
+        }
+
+        unsafe {
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX).unwrap();
+        }
+
+        let depth_format = Self::find_depth_format(&self.instance, &self.physical_device);
+        let bytes_per_texel = match depth_format {
+            vk::Format::D32_SFLOAT | vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT => 4,
+            _ => return Err(EngineError::from(format!("Reading back the depth format {} is not supported", depth_format.as_raw()))),
+        };
+        let depth_allocation = self.depth_resolve_image_allocation.as_ref().or(self.depth_image_allocation.as_ref()).unwrap();
+        let extent = self.swapchain_extent;
+        let data = self.allocator.copy_image_to_host(&self.command_pool, &self.graphics_queue, depth_allocation, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, vk::ImageAspectFlags::DEPTH, Self::get_scissor(&extent), bytes_per_texel)?;
+
+        // The depth aspect of D24_UNORM_S8_UINT is copied as 24 bit unsigned normalized values in the low bits of 32 bit texels
+        let depth = data.chunks_exact(bytes_per_texel as usize).map(|texel| {
+            let texel = [texel[0], texel[1], texel[2], texel[3]];
+            match depth_format {
+                vk::Format::D24_UNORM_S8_UINT => (u32::from_ne_bytes(texel) & 0x00FF_FFFF) as f32 / 0x00FF_FFFF as f32,
+                _ => f32::from_ne_bytes(texel),
+            }
+        }).collect();
+
+        Ok((extent.width, extent.height, depth))
+    }
+
+    /// Adds an object id attachment that the objects write their draw and instance index to, so that [`VkController::pick`] can find the object at a pixel.
+    /// It has to be enabled before any objects are added, and the fragment shaders have to write the object id like `assets/shaders/triangle.frag` does.
+    pub fn enable_picking(&mut self) -> Result<(), EngineError> {
+        self.graphics_pipeline_manager.enable_picking()?;
+        self.create_picking_resources();
+        self.recreate_render_pass();
+        Ok(())
+    }
+
+    /// Adds color attachments with the formats to the main pass, for example to write the albedo and normals for deferred shading.
+    /// The fragment shaders write them at the output locations after the scene color, and after the object id when picking is enabled.
+    /// They are cleared to 0 every frame and resolved when multisampling is used. Like picking, they have to be set before any objects are added.
+    pub fn set_extra_color_attachments(&mut self, formats: &[vk::Format]) -> Result<(), EngineError> {
+        self.graphics_pipeline_manager.set_extra_color_attachments(formats)?;
+        unsafe {
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX).unwrap();
+        }
+        self.free_extra_color_resources();
+        self.create_extra_color_resources();
+        self.recreate_render_pass();
+        self.set_frame_debug_names();
+        Ok(())
+    }
+
+    /// The view of the extra color attachment at the index in [`VkController::set_extra_color_attachments`], resolved when multisampling is used.
+    /// It is in `SHADER_READ_ONLY_OPTIMAL` after the main pass. The view changes when the swapchain is recreated.
+    pub fn get_extra_color_attachment_image_view(&self, index: usize) -> Option<ImageView> {
+        let (allocation, resolve_allocation) = self.extra_color_attachment_allocations.get(index)?;
+        resolve_allocation.as_ref().unwrap_or(allocation).get_image_view()
+    }
+
+    /// Returns the object that was drawn at the pixel in the last frame, where (0, 0) is the top left corner of the swapchain image.
+    /// Waits for the frames in flight to finish.
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<ObjectID> {
+        let draw_object_types = self.picking_draw_object_types.as_ref()?;
+        if x >= self.swapchain_extent.width || y >= self.swapchain_extent.height {
+            return None;
+        }
+
+        unsafe {
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX).unwrap();
+        }
+
+        let object_id_allocation = self.object_id_resolve_image_allocation.as_ref().or(self.object_id_image_allocation.as_ref())?;
+        let pixel = vk::Rect2D {
+            offset: vk::Offset2D { x: x as i32, y: y as i32 },
+            extent: vk::Extent2D { width: 1, height: 1 },
+        };
+        let texel = match self.allocator.copy_image_to_host(&self.command_pool, &self.graphics_queue, object_id_allocation, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageAspectFlags::COLOR, pixel, 2 * std::mem::size_of::<u32>() as u32) {
+            Ok(texel) => texel,
+            Err(e) => {
+                log::error!(target: logging::RENDERER, "Failed to read the object id at ({}, {}): {}", x, y, e);
+                return None;
+            },
+        };
+        let draw_id = u32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]) as usize;
+        let instance_index = u32::from_ne_bytes([texel[4], texel[5], texel[6], texel[7]]) as usize;
+
+        // A draw id of 0 means that no object was drawn at the pixel
+        let object_type = *draw_object_types.get(draw_id.checked_sub(1)?)?;
+        self.object_manager.get_object_id_at_instance(object_type, instance_index)
+    }
+
+    fn recreate_render_pass(&mut self) {
+        // The command buffers in flight can still use the render pass and framebuffers
+        unsafe {
+            self.device.wait_for_fences(&self.in_flight_fences, true, u64::MAX).unwrap();
+            self.device.destroy_framebuffer(self.scene_framebuffer, self.allocator.get_allocation_callbacks());
+        }
+        self.is_depth_available = false;
+        self.graphics_pipeline_manager.recreate_render_pass(&self.device, self.scene_format, self.msaa_samples, Self::find_depth_format(&self.instance, &self.physical_device), self.clear_mode.get_attachment_load_op(), self.is_depth_kept, &self.allocator);
+        let extra_image_views = self.get_extra_framebuffer_image_views();
+        self.scene_framebuffer = Self::create_framebuffer(&self.device, &self.graphics_pipeline_manager.get_render_pass().unwrap(), self.scene_image_allocation.as_ref().unwrap().get_image_view().unwrap(), &self.swapchain_extent, self.depth_image_allocation.as_ref().unwrap(), &extra_image_views, self.color_image_allocation.as_ref().unwrap(), &self.allocator);
+    }
+
+    /// Renders into the rectangle instead of the whole window, the area outside it keeps the clear color. None renders to the whole window again.
+    pub fn set_render_rect(&mut self, render_rect: Option<vk::Rect2D>) {
+        self.render_rect = render_rect;
+    }
+
+    /// Does nothing when the renderer was created with [`VkController::new_from_raw_handles`], the host application owns the title then.
+    pub fn set_window_title(&self, title: &str) {
+        if let Some(window) = self.window.as_ref() {
+            window.set_title(title);
+        }
+    }
+
+    /// Switches the window to the fullscreen mode on the monitor it is on, and recreates the swapchain so that the next frame has the new size.
+    /// Exclusive fullscreen uses the monitor's current resolution with the highest refresh rate it has.
+    pub fn set_fullscreen(&mut self, fullscreen_mode: FullscreenMode) -> Result<(), EngineError> {
+        let window = self.window.as_ref().ok_or(Cow::from("Can not change the fullscreen mode of a window the renderer does not own"))?;
+        let fullscreen = match fullscreen_mode {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(window.current_monitor())),
+            FullscreenMode::Exclusive => {
+                let monitor = window.current_monitor().ok_or(Cow::from("Failed to find the monitor the window is on"))?;
+                let video_mode = Self::choose_video_mode(&monitor).ok_or(Cow::from("The monitor does not have any video modes for exclusive fullscreen"))?;
+                Some(Fullscreen::Exclusive(video_mode))
+            },
+        };
+        window.set_fullscreen(fullscreen);
+        self.fullscreen_mode = fullscreen_mode;
+        // Some platforms resize the window later, the resize event then recreates the swapchain again
+        self.recreate_swapchain()
+    }
+
+    pub fn get_fullscreen_mode(&self) -> FullscreenMode {
+        self.fullscreen_mode
+    }
+
+    fn choose_video_mode(monitor: &MonitorHandle) -> Option<VideoMode> {
+        let monitor_size = monitor.size();
+        monitor.video_modes().max_by_key(|video_mode| (video_mode.size() == monitor_size, video_mode.refresh_rate_millihertz(), video_mode.bit_depth(), video_mode.size().width * video_mode.size().height))
+    }
+
+    /// Turns the built-in Alt+Enter binding in [`VkController::handle_window_event`] on or off, it is on by default.
+    pub fn set_fullscreen_toggle_enabled(&mut self, is_fullscreen_toggle_enabled: bool) {
+        self.is_fullscreen_toggle_enabled = is_fullscreen_toggle_enabled;
+    }
+
+    /// Handles the built-in key bindings, which is Alt+Enter to toggle between windowed and borderless fullscreen. Returns true when the event was used.
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            },
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::Return),
+                    ..
+                },
+                ..
+            } if self.is_fullscreen_toggle_enabled && self.modifiers.alt() => {
+                let fullscreen_mode = if self.fullscreen_mode == FullscreenMode::Windowed { FullscreenMode::Borderless } else { FullscreenMode::Windowed };
+                if let Err(e) = self.set_fullscreen(fullscreen_mode) {
+                    log::error!(target: logging::RENDERER, "Failed to toggle fullscreen: {}", e);
+                }
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// The size of the window's client area in physical pixels.
+    pub fn window_inner_size(&self) -> (u32, u32) {
+        let extent = self.get_window_extent();
+        (extent.width, extent.height)
+    }
+
+    /// Tells the renderer the new size of a window it does not own, the swapchain is recreated on the next frame.
+    pub fn set_window_extent(&mut self, window_extent: vk::Extent2D) {
+        self.window_extent = window_extent;
+        self.frame_buffer_resized = true;
+    }
+
+    fn get_window_extent(&self) -> vk::Extent2D {
+        match self.window.as_ref() {
+            Some(window) => vk::Extent2D { width: window.inner_size().width, height: window.inner_size().height },
+            None => self.window_extent,
+        }
+    }
+
+    fn create_global_descriptor_sets(device: &Device, descriptor_pool: &vk::DescriptorPool, global_descriptor_set_layout: &vk::DescriptorSetLayout, global_frame_data_allocation: &AllocationInfo, light_manager: &LightManager) -> Vec<vk::DescriptorSet> {
+        let layouts = [*global_descriptor_set_layout; Self::MAX_FRAMES_IN_FLIGHT];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            descriptor_pool: *descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(&alloc_info)
+        }.unwrap();
+
+        for (i, descriptor_set) in descriptor_sets.iter().enumerate() {
+            let buffer_info = vk::DescriptorBufferInfo {
+                buffer: global_frame_data_allocation.get_buffer().unwrap(),
+                offset: (i * Self::GLOBAL_FRAME_DATA_STRIDE * Self::GLOBAL_FRAME_DATA_SLOTS) as u64,
+                range: Self::GLOBAL_FRAME_DATA_SIZE as u64,
+            };
+            let light_buffer_info = light_manager.get_buffer_info(i);
+            let descriptor_writes = [
+                vk::WriteDescriptorSet {
+                    s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                    dst_set: *descriptor_set,
+                    dst_binding: 0,
+                    dst_array_element: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                    descriptor_count: 1,
+                    p_buffer_info: &buffer_info,
+                    ..Default::default()
+                },
+                vk::WriteDescriptorSet {
+                    s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                    dst_set: *descriptor_set,
+                    dst_binding: 1,
+                    dst_array_element: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    p_buffer_info: &light_buffer_info,
+                    ..Default::default()
+                },
+            ];
+            unsafe {
+                device.update_descriptor_sets(&descriptor_writes, &[]);
+            }
+        }
+
+        descriptor_sets
+    }
+
+    /// Adds a light that shaders can read from `layout(set = 0, binding = 1)`, like the shaders of [`LitRenderableObject`](crate::graphics_objects::LitRenderableObject). There can be at most [`LightManager::MAX_LIGHTS`] lights.
+    pub fn add_light(&mut self, light: Light) -> Result<LightId, EngineError> {
+        self.light_manager.add_light(light).map_err(EngineError::from)
+    }
+
+    /// The light buffer is only written again when the light is different from before.
+    pub fn update_light(&mut self, id: LightId, light: Light) -> Result<(), EngineError> {
+        self.light_manager.update_light(id, light).map_err(EngineError::from)
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Result<(), EngineError> {
+        self.light_manager.remove_light(id).map_err(EngineError::from)
+    }
+
+    pub fn get_light(&self, id: LightId) -> Option<Light> {
+        self.light_manager.get_light(id)
+    }
+
+    /// Sets the view matrix in the engine owned per-frame data, which shaders can read with `layout(set = 0, binding = 0)`. The camera position is taken from its inverse.
+    pub fn set_view(&mut self, view: glm::Mat4) {
+        self.view = view;
+    }
+
+    /// Sets the projection matrix in the engine owned per-frame data, which shaders can read with `layout(set = 0, binding = 0)`.
+    pub fn set_projection(&mut self, projection: glm::Mat4) {
+        self.projection = projection;
+    }
+
+    /// Enables the bindless texture array, which every pipeline gets as `layout(set = 2, binding = 0) uniform sampler2D textures[]`.
+    /// Has to be called before any objects are added, and `max_textures` can be at most [`VkController::get_max_bindless_textures`].
+    /// Object types that do not use the array keep working as before.
+    pub fn enable_bindless_textures(&mut self, max_textures: u32) -> Result<(), EngineError> {
+        if self.max_bindless_textures == 0 {
+            return Err(EngineError::from("Bindless textures are not supported by the physical device"));
+        }
+        if max_textures == 0 || max_textures > self.max_bindless_textures {
+            return Err(EngineError::from(format!("The bindless texture array has to hold between 1 and {} textures, but {} was requested", self.max_bindless_textures, max_textures)));
+        }
+
+        let descriptor_set_layout = self.graphics_pipeline_manager.enable_bindless_textures(&self.device, max_textures, &self.allocator)?;
+        self.texture_manager = Some(TextureManager::new(&self.device, &descriptor_set_layout, max_textures, &self.allocator)?);
+        Ok(())
+    }
+
+    /// Uploads the image to the bindless texture array. The returned handle is the index shaders use to sample it, and it can be given per instance through a storage buffer.
+    pub fn add_bindless_texture(&mut self, image: DynamicImage) -> Result<TextureHandle, EngineError> {
+        let texture_manager = match self.texture_manager.as_mut() {
+            Some(texture_manager) => texture_manager,
+            None => return Err(EngineError::from("Bindless textures have not been enabled")),
+        };
+        texture_manager.add_texture(image, &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.graphics_queue, &mut self.sampler_manager, &self.allocator).map_err(EngineError::from)
+    }
+
+    /// Sets the anisotropic filtering level used by textures added after this, clamped between 1 and the highest level the physical device supports.
+    /// The default is the highest supported level. The samplers with the old level are destroyed once no texture uses them anymore.
+    pub fn set_default_anisotropy(&mut self, max_anisotropy: f32) {
+        self.sampler_manager.set_default_max_anisotropy(max_anisotropy);
+    }
+
+    /// The largest bindless texture array the physical device supports, or 0 if it does not support bindless textures.
+    pub fn get_max_bindless_textures(&self) -> u32 {
+        self.max_bindless_textures
+    }
+
+    /// Creates an offscreen color image that objects can sample through a [`crate::graphics_objects::RenderTargetResource`].
+    /// Every frame it is cleared and the objects set with [`VkController::set_render_target_objects`] are drawn into it before the window, with the view projection set with [`VkController::set_render_target_camera`].
+    pub fn create_render_target(&mut self, extent: vk::Extent2D, format: vk::Format) -> Result<RenderTargetId, EngineError> {
+        if Self::find_supported_formats(&self.instance, &self.physical_device, &[format], vk::ImageTiling::OPTIMAL, vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE).is_none() {
+            return Err(EngineError::from("The format can not be both drawn into and sampled on the physical device"));
+        }
+
+        let sampler_config = SamplerConfig {
+            s_type: StructureType::SAMPLER_CREATE_INFO,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            anisotropy_enable: vk::FALSE,
+            max_anisotropy: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: 0.0,
+        };
+        let sampler = self.sampler_manager.get_or_create_sampler(&self.device, &self.instance, &self.physical_device, sampler_config, &self.allocator)?;
+        self.render_target_manager.create_render_target(&self.device, extent, format, sampler, &self.allocator).map_err(EngineError::from)
+    }
+
+    /// The objects that sample the render target get the new image in the next frame.
+    pub fn resize_render_target(&mut self, render_target_id: RenderTargetId, extent: vk::Extent2D) -> Result<(), EngineError> {
+        self.render_target_manager.resize_render_target(&self.device, render_target_id, extent, &self.allocator).map_err(EngineError::from)
+    }
+
+    /// Fails while objects that sample the render target exist, since their descriptor sets would point to the destroyed image.
+    pub fn destroy_render_target(&mut self, render_target_id: RenderTargetId) -> Result<(), EngineError> {
+        if self.object_manager.is_render_target_used(render_target_id) {
+            return Err(EngineError::from(format!("The render target {:?} is still used by some objects", render_target_id)));
+        }
+        self.render_target_manager.destroy_render_target(render_target_id).map_err(EngineError::from)
+    }
+
+    /// Sets `view_proj` in the per-frame data the objects are drawn into the render target with. `view`, `proj` and the camera position are the same as the window's.
+    pub fn set_render_target_camera(&mut self, render_target_id: RenderTargetId, view_projection: glm::Mat4) -> Result<(), EngineError> {
+        self.render_target_manager.set_view_projection(render_target_id, view_projection).map_err(EngineError::from)
+    }
+
+    /// Sets the objects that are drawn into the render target. Hidden objects and objects that have been removed are skipped.
+    pub fn set_render_target_objects(&mut self, render_target_id: RenderTargetId, object_ids: Vec<ObjectID>) -> Result<(), EngineError> {
+        self.render_target_manager.set_object_ids(render_target_id, object_ids).map_err(EngineError::from)
+    }
+
+    pub fn staging_stats(&self) -> StagingStats {
+        self.allocator.get_staging_stats()
+    }
+
+    /// The number and bytes of the live allocations of each kind.
+    pub fn allocation_stats(&self) -> BTreeMap<AllocationKind, AllocationKindStats> {
+        self.allocator.get_allocation_stats()
+    }
+
+    /// The kind, size and memory type index of every allocation that hasn't been freed, oldest first. For finding leaks, like checking that only the swapchain's allocations are left after the objects are removed.
+    pub fn dump_live_allocations(&self) -> Vec<(AllocationKind, u64, u32)> {
+        self.allocator.dump_live_allocations()
+    }
+
+    /// The usage and budget of every memory heap, from `VK_EXT_memory_budget` when the device has it and from the heap sizes otherwise.
+    pub fn memory_budget(&self) -> Vec<HeapBudget> {
+        self.allocator.get_memory_budget()
+    }
+
+    /// New memory blocks that would take their heap past this fraction of its budget fail with [`EngineError::OutOfDeviceMemory`], so the driver doesn't start paging memory out.
+    /// It's 0.9 by default and clamped to 0..=1.
+    pub fn set_memory_budget_fraction(&mut self, fraction: f32) {
+        self.allocator.set_memory_budget_fraction(fraction);
+    }
+
+    /// Calls `callback` at the start of a frame when the [`MemoryPressureLevel`] of the fullest device-local heap has changed, with that heap's budget,
+    /// so the application can drop LOD levels or unload textures before the allocations start failing. Replaces the previous callback.
+    pub fn set_memory_pressure_callback(&mut self, callback: impl FnMut(MemoryPressureLevel, HeapBudget) + 'static) {
+        self.memory_pressure_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_memory_pressure_callback(&mut self) {
+        self.memory_pressure_callback = None;
+    }
+
+    // The budget is queried from the driver every frame, so it's only done when there is a callback
+    fn update_memory_pressure(&mut self) {
+        let Some(callback) = self.memory_pressure_callback.as_mut() else {
+            return;
+        };
+        let budget_fraction = self.allocator.get_memory_budget_fraction();
+        let Some((level, heap_budget)) = self.allocator.get_memory_budget().into_iter().filter(|heap_budget| heap_budget.is_device_local).map(|heap_budget| (MemoryPressureLevel::of(&heap_budget, budget_fraction), heap_budget)).max_by_key(|(level, _)| *level) else {
+            return;
+        };
+        if level != self.memory_pressure_level {
+            self.memory_pressure_level = level;
+            log::debug!(target: logging::ALLOCATOR, "The memory pressure is now {:?}, heap {} uses {} of {} bytes", level, heap_budget.heap_index, heap_budget.usage, heap_budget.budget);
+            callback(level, heap_budget);
+        }
+    }
+
+    /// Geometry-only compaction: moves the vertex and index buffers of the objects into fewer memory blocks, for when large allocations fail after many objects have been added and removed.
+    /// Images and uniform and storage buffers are never moved, since descriptor sets point at them, so a block that holds one of them is never released. The report has what was left in place.
+    /// It waits for the device to be idle, so call it at a point where a stall doesn't matter, like between levels.
+    pub fn defragment_memory(&mut self) -> Result<DefragmentationReport, EngineError> {
+        self.wait_for_device();
+        let mut allocations = self.object_manager.get_geometry_allocations_mut();
+        self.allocator.defragment(&mut allocations, &self.command_pool, &self.graphics_queue)
+    }
+
+    /// Queries the physical device every time, so keep the result instead of calling this every frame.
+    pub fn device_capabilities(&self) -> DeviceCapabilities {
+        let properties = unsafe { self.instance.get_physical_device_properties(self.physical_device) };
+        let features = unsafe { self.instance.get_physical_device_features(self.physical_device) };
+        let memory_properties = unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) };
+        let limits = properties.limits;
+        DeviceCapabilities {
+            device_name: unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned(),
+            max_push_constants_size: limits.max_push_constants_size,
+            max_per_stage_descriptor_samplers: limits.max_per_stage_descriptor_samplers,
+            max_descriptor_set_samplers: limits.max_descriptor_set_samplers,
+            // The sample count flags are single bits whose value is the count
+            max_msaa_samples: Self::get_max_usable_sample_count(&self.instance, &self.physical_device).as_raw(),
+            is_sampler_anisotropy_supported: features.sampler_anisotropy == vk::TRUE,
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            is_geometry_shader_supported: features.geometry_shader == vk::TRUE,
+            is_pipeline_statistics_query_supported: features.pipeline_statistics_query == vk::TRUE,
+            is_descriptor_indexing_supported: Self::is_descriptor_indexing_available(&self.instance, &self.physical_device),
+            max_bindless_textures: self.max_bindless_textures,
+            max_image_dimension_2d: limits.max_image_dimension2_d,
+            max_image_dimension_3d: limits.max_image_dimension3_d,
+            max_image_dimension_cube: limits.max_image_dimension_cube,
+            max_image_array_layers: limits.max_image_array_layers,
+            memory_heaps: memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter()
+                .map(|heap| (heap.size, heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)))
+                .collect(),
+        }
+    }
+
+    /// Whether [`VkController::enable_bindless_textures`] can be used, which needs descriptor indexing on the picked physical device.
+    pub fn is_bindless_supported(&self) -> bool {
+        self.max_bindless_textures > 0
+    }
+
+    // The object will not be remove until the all frames in flight have passed
+    pub fn remove_objects_to_render(&mut self, object_ids: Vec<ObjectID>) -> Result<(), EngineError> {
+        self.object_manager.remove_objects(object_ids)
+    }
+
+    /// Removes the objects like [`VkController::remove_objects_to_render`], and returns a handle that tells when their GPU resources have been freed, so the CPU side data they were made from can be released.
+    pub fn queue_remove_objects(&mut self, object_ids: Vec<ObjectID>) -> Result<RemovalHandle, EngineError> {
+        self.object_manager.remove_objects_tracked(object_ids)
+    }
+
+    /// Whether the resources of a removal have been freed yet. Removals move on every drawn frame, and are freed once every frame in flight that could use them has finished.
+    /// None when the handle is unknown, or when the removal was freed and that was already returned.
+    pub fn poll_removal(&mut self, handle: RemovalHandle) -> Option<RemovalStatus> {
+        self.object_manager.poll_removal(handle)
+    }
+
+    pub fn set_draw_order(&mut self, draw_order: DrawOrder) {
+        self.object_manager.set_draw_order(draw_order);
+    }
+
+    // The object type is the one returned from `get_object_type` on the objects of that type
+    pub fn set_draw_order_key(&mut self, object_type: ObjectType, key: i32) {
+        self.object_manager.set_draw_order_key(object_type, key);
+    }
+
+    /// The hash of the object's mesh, combined with its material key when it isn't the default one, and with its type resources when [`VkController::set_unique_object_types`] is enabled.
+    pub fn get_object_type(&self, object: &dyn Renderable) -> ObjectType {
+        self.object_manager.get_object_type(object)
+    }
+
+    /// The hash of the object's type, as it is shown in the frame reports and resource errors.
+    pub fn get_object_type_hash(&self, object: &dyn Renderable) -> VerticesIndicesHash {
+        self.object_manager.get_object_type(object).get_hash()
+    }
+
+    /// Hides or shows the object without removing it, so none of its buffers are rebuilt. Hidden objects are left out of the draws, picking and raycasts.
+    /// The object keeps its slot while hidden, so hiding objects between visible ones of the same type adds draws, see [`VkController::instance_slot`].
+    pub fn set_object_visible(&mut self, object_id: ObjectID, is_visible: bool) -> Result<(), EngineError> {
+        self.object_manager.set_object_visible(object_id, is_visible)
+    }
+
+    /// Makes already added objects the levels of one instance, ordered from the most to the least detailed. Each level is drawn until the camera is further from the instance than its max distance, and past the last one nothing is drawn.
+    /// The level is chosen per group every frame, so the instances of the same LOD object types switch independently. Pass `f32::INFINITY` as the last distance to always draw the last level.
+    pub fn register_lod_group(&mut self, levels: Vec<(ObjectID, f32)>) -> Result<(), EngineError> {
+        if levels.is_empty() {
+            return Err(EngineError::from("A LOD group needs at least one level"));
+        }
+        let (object_ids, max_distances): (Vec<_>, Vec<_>) = levels.into_iter().unzip();
+        if max_distances.iter().any(|max_distance| max_distance.is_nan() || *max_distance <= 0.0) {
+            return Err(EngineError::from("The max distances of a LOD group have to be positive"));
+        }
+        if max_distances.windows(2).any(|distances| distances[0] > distances[1]) {
+            return Err(EngineError::from("The max distances of a LOD group have to be ascending"));
+        }
+        self.object_manager.add_lod_group(object_ids, max_distances, LodGroup::<SimpleVertex>::DEFAULT_HYSTERESIS)
+    }
+
+    /// Makes every LOD group draw the given level, or its last level if it has fewer. None goes back to choosing the level by distance.
+    pub fn set_forced_lod_level(&mut self, forced_lod_level: Option<usize>) {
+        self.object_manager.set_forced_lod_level(forced_lod_level);
+    }
+
+    /// The textures and pipelines that are drawn with a fallback, since the objects are still added when they fail. They are replaced when the resources work again.
+    pub fn get_resource_errors(&self) -> &[ResourceError] {
+        self.object_manager.get_resource_errors()
+    }
+
+    /// How many textures of the added objects are drawn with the placeholder while their images load, see [`crate::graphics_objects::TextureResource::from_path_async`]. It can be shown as a loading indicator.
+    /// A texture whose image has been read is swapped in before the next frame.
+    pub fn pending_texture_loads(&self) -> usize {
+        self.object_manager.get_num_pending_texture_loads()
+    }
+
+    /// Where the load started by [`VkControllerGraphicsObjectsControl::add_objects_async`] is. Loads also move on without being polled, every frame.
+    /// None when the handle is unknown, or when the load had finished and its status was already returned.
+    pub fn poll_load(&mut self, handle: LoadHandle) -> Option<LoadStatus> {
+        // Objects shown now are first drawn in the next frame
+        self.update_async_loads(self.time.frame_index() + 1);
+        self.async_loader.poll(handle)
+    }
+
+    /// For uploading objects from worker threads. The context can be cloned and sent to other threads, and its [`PreparedObject`]s are added with [`VkController::commit_prepared`].
+    /// It uses its own command pool on the graphics queue, since the mipmaps are made with blits. The controller's submits and presents take the allocator's queue lock, so they don't race with the workers.
+    pub fn create_upload_context(&self) -> UploadContext {
+        UploadContext::new(self.allocator.clone(), self.upload_command_pool, self.graphics_queue, self.object_manager.get_texture_quality(), self.object_manager.is_mesh_validation_enabled(), self.upload_allocations_to_free.clone())
+    }
+
+    /// Adds objects that were prepared on other threads and returns their ids, in the same order. Their meshes and textures are already on the GPU, so only the descriptor sets, the pipelines and the bookkeeping are made here.
+    /// The copies were submitted to the graphics queue before this, so the objects are drawn right away. Meshes and textures that another object already had uploaded are freed once their copies have finished.
+    pub fn commit_prepared(&mut self, prepared_objects: Vec<PreparedObject>) -> Result<Vec<ObjectID>, EngineError> {
+        let mut objects = Vec::with_capacity(prepared_objects.len());
+        let mut meshes = Vec::with_capacity(prepared_objects.len());
+        let mut textures = Vec::new();
+        let mut newest_upload_marker = None;
+        for prepared_object in prepared_objects {
+            let mesh = prepared_object.get_vertices_and_indices_hash();
+            let (object, mesh_buffers, prepared_textures, upload_marker) = prepared_object.into_parts();
+            objects.push(object);
+            meshes.push((mesh, mesh_buffers));
+            textures.extend(prepared_textures);
+            newest_upload_marker = newest_upload_marker.max(Some(upload_marker));
+        }
+        let Some(upload_marker) = newest_upload_marker else {
+            return Ok(Vec::new());
+        };
+
+        let mesh_hashes = meshes.iter().map(|(mesh, _)| *mesh).collect::<Vec<_>>();
+        let texture_keys = textures.iter().map(|texture| texture.key.clone()).collect::<Vec<_>>();
+        let mut allocations_to_free = self.object_manager.insert_prepared_resources(meshes, textures, &self.device, &self.instance, &self.physical_device, &mut self.sampler_manager, &self.allocator);
+        let object_ids = self.object_manager.generate_currently_unused_ids(objects.len()).and_then(|object_ids| {
+            self.object_manager.add_objects(object_ids.iter().copied().zip(objects).collect(), &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.descriptor_pool, &self.graphics_queue, &mut self.sampler_manager, self.msaa_samples, self.scene_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, self.current_frame, &mut self.graphics_pipeline_manager, &self.allocator)?;
+            Ok(object_ids)
+        });
+        allocations_to_free.extend(self.object_manager.release_unused_prepared_resources(&mesh_hashes, &texture_keys));
+        self.upload_allocations_to_free.lock().unwrap_or_else(PoisonError::into_inner).extend(allocations_to_free.into_iter().map(|allocation| (upload_marker, allocation)));
+        object_ids
+    }
+
+    fn free_finished_upload_allocations(&mut self) {
+        let mut upload_allocations_to_free = self.upload_allocations_to_free.lock().unwrap_or_else(PoisonError::into_inner);
+        let (finished, unfinished) = upload_allocations_to_free.drain(..).partition::<Vec<_>, _>(|(upload_marker, _)| self.allocator.is_upload_finished(*upload_marker).unwrap_or(true));
+        *upload_allocations_to_free = unfinished;
+        drop(upload_allocations_to_free);
+        for (_, allocation) in finished {
+            if let Err(err) = self.allocator.free_memory_allocation(allocation) {
+                log::error!(target: logging::ALLOCATOR, "Failed to free an allocation of an upload context: {}", err);
+            }
+        }
+    }
+
+    // Adds the objects whose meshes have been read and shows the ones whose uploads the GPU has finished. It's the only place the loads touch the allocator, so it stays on the main thread
+    fn update_async_loads(&mut self, frame_index: u64) {
+        for (handle, objects) in self.async_loader.take_prepared_loads() {
+            let object_ids = objects.and_then(|objects| {
+                let object_ids = self.object_manager.generate_currently_unused_ids(objects.len())?;
+                self.object_manager.add_objects(object_ids.iter().copied().zip(objects).collect(), &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.descriptor_pool, &self.graphics_queue, &mut self.sampler_manager, self.msaa_samples, self.scene_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, self.current_frame, &mut self.graphics_pipeline_manager, &self.allocator)?;
+                for object_id in object_ids.iter() {
+                    self.object_manager.set_object_visible(*object_id, false)?;
+                }
+                Ok(object_ids)
+            });
+            match object_ids {
+                Ok(object_ids) => self.async_loader.set_uploading(handle, object_ids, self.allocator.get_upload_marker()),
+                Err(err) => self.async_loader.set_failed(handle, err),
+            }
+        }
+
+        let allocator = &self.allocator;
+        let uploaded_objects = self.async_loader.take_uploaded_objects(frame_index, |upload_marker| allocator.is_upload_finished(upload_marker).unwrap_or_else(|err| {
+            log::error!(target: logging::RENDERER, "Failed to check if the uploads of a load have finished, so the objects are shown now: {}", err);
+            true
+        }));
+        for object_id in uploaded_objects {
+            // The objects can have been removed while they were uploading
+            let _ = self.object_manager.set_object_visible(object_id, true);
+        }
+    }
+
+    /// Checks that the meshes of the added objects have indices within their vertices and finite positions, so bad data fails when it's added instead of when it's drawn.
+    /// It's enabled by default in debug builds.
+    pub fn set_mesh_validation(&mut self, is_mesh_validation_enabled: bool) {
+        self.object_manager.set_mesh_validation(is_mesh_validation_enabled);
+    }
+
+    /// None when the object has not been added.
+    pub fn is_object_visible(&self, object_id: ObjectID) -> Option<bool> {
+        self.object_manager.is_object_visible(object_id)
+    }
+
+    /// The object's `gl_InstanceIndex` within its object type, which is where its instance data is in the storage buffers.
+    /// The slot stays the same until the object is removed, also when other objects of the type are added, removed, hidden or shown, so per-slot history on the GPU stays valid.
+    /// Removed objects' slots are reused by the next objects of the type. None when the object has not been added or its type has no storage buffers.
+    ///
+    /// Every run of visible slots is drawn with its own draw call, so the hidden objects and the free slots between visible ones split the draw of their type.
+    /// Hiding or removing every other object of a type gives one draw per remaining object, until new objects of the type fill the free slots again.
+    pub fn instance_slot(&self, object_id: ObjectID) -> Option<u32> {
+        self.object_manager.get_instance_slot(object_id)
+    }
+
+    /// Copies only the instances marked with [`VkController::mark_instance_dirty`] to the storage buffers every frame, instead of all of them.
+    /// Scenes with many instances of which few change each frame upload much less, but every change has to be marked. Sprite animations mark their sprites themselves. It's off by default.
+    pub fn set_partial_instance_upload(&mut self, is_partial_instance_upload_enabled: bool) {
+        self.object_manager.set_partial_instance_upload(is_partial_instance_upload_enabled);
+    }
+
+    pub fn is_partial_instance_upload_enabled(&self) -> bool {
+        self.object_manager.is_partial_instance_upload_enabled()
+    }
+
+    /// Gives objects with the same mesh their own object type when their type resources differ, instead of failing to add them unless they have their own [`crate::graphics_objects::MaterialKey`].
+    /// The resources are told apart by their `Arc`s, so objects only share a type when they share the same resources. It's off by default, and only changes the objects that are added after it.
+    /// The vertex and index buffers are still shared, but every object type has its own descriptor set, type uniform buffers, instance storage buffers and draw call.
+    /// So many objects with the same mesh and different textures use more memory and draw calls than instances of one type with a texture per instance, like with [`VkController::enable_bindless_textures`], and they count towards [`VkController::MAX_OBJECT_TYPES`].
+    pub fn set_unique_object_types(&mut self, are_object_types_unique: bool) {
+        self.object_manager.set_unique_object_types(are_object_types_unique);
+    }
+
+    pub fn are_object_types_unique(&self) -> bool {
+        self.object_manager.are_object_types_unique()
+    }
+
+    /// Copies the object's instance data to the storage buffers in the next frames, when partial instance uploads are enabled. Call it after writing the data.
+    pub fn mark_instance_dirty(&mut self, object_id: ObjectID) -> Result<(), EngineError> {
+        self.object_manager.mark_instance_dirty(object_id)
+    }
+
+    /// The version of the resource data the last frame was drawn with. Every frame clones the data of the resources once, after waiting for its frame in flight and before recording,
+    /// and only holds each resource's lock while cloning it. Writes made before that are in the frame whole, writes made after it are in the next one, and a resource whose lock is poisoned keeps the data it had.
+    pub fn renderable_data_version(&self) -> RenderableDataVersion {
+        self.object_manager.get_data_version()
+    }
+
+    /// The time of the last drawn frame.
+    pub fn time(&self) -> &Time {
+        &self.time
+    }
+
+    /// Defaults to [`Time::DEFAULT_MAX_DELTA_SECONDS`].
+    pub fn set_max_delta_time(&mut self, max_delta_seconds: f32) {
+        self.time.set_max_delta_seconds(max_delta_seconds);
+    }
+
+    /// See [`Time::set_paused`].
+    pub fn set_time_paused(&mut self, is_paused: bool) {
+        self.time.set_paused(is_paused);
+    }
+
+    /// Plays the animation on a [`crate::sprite::Sprite`] that has been added, replacing the animation it had. The sprite's UV rect is written every frame until the animation is removed with the sprite.
+    pub fn set_sprite_animation(&mut self, object_id: ObjectID, animation: SpriteAnimation) -> Result<(), EngineError> {
+        self.object_manager.set_sprite_animation(object_id, animation)
+    }
+
+    pub fn pause_sprite_animation(&mut self, object_id: ObjectID) -> Result<(), EngineError> {
+        self.object_manager.set_sprite_animation_paused(object_id, true)
+    }
+
+    pub fn resume_sprite_animation(&mut self, object_id: ObjectID) -> Result<(), EngineError> {
+        self.object_manager.set_sprite_animation_paused(object_id, false)
+    }
+
+    /// Poll this for non looping animations, they stay on their last frame when finished. None when the object has no sprite animation.
+    pub fn is_sprite_animation_finished(&self, object_id: ObjectID) -> Option<bool> {
+        self.object_manager.is_sprite_animation_finished(object_id)
+    }
+
+    /// The index into [`SpriteAnimation::frames`] that is drawn. None when the object has no sprite animation.
+    pub fn sprite_animation_frame(&self, object_id: ObjectID) -> Option<usize> {
+        self.object_manager.get_sprite_animation_frame(object_id)
+    }
+
+    /// The projection of [`Ortho2D`] for the current render area. It changes when the window is resized, so get it again every frame.
+    pub fn ortho_2d_projection(&self) -> glm::Mat4 {
+        Ortho2D::projection(Self::get_render_rect(self.render_rect, &self.swapchain_extent).extent)
+    }
+
+    /// Names the Vulkan resources used to render the object, so validation messages and captures in tools like RenderDoc show the name instead of only the raw handle.
+    /// The vertex and index buffers are shared by the whole pipeline, so they get the name of the last object that was named. Does nothing when the debug messenger is disabled.
+    pub fn set_object_debug_name(&self, object_id: ObjectID, name: &str) {
+        let debug_utils_loader = match self.debug_utils_loader.as_ref() {
+            Some(debug_utils_loader) => debug_utils_loader,
+            None => return,
+        };
+        let handles = match self.object_manager.get_object_debug_handles(object_id) {
+            Some(handles) => handles,
+            None => {
+                log::warn!(target: logging::OBJECTS, "Could not set the debug name \"{}\" because the object with ID {:?} has not been added", name, object_id);
+                return;
+            },
+        };
+        for (object_type, object_handle, resource_description) in handles {
+            Self::set_debug_utils_object_name(debug_utils_loader, &self.device, object_type, object_handle, &format!("{} {}", name, resource_description));
+        }
+    }
+
+    // Names the command buffers and the attachments, so the validation messages and captures say which frame and attachment they are about
+    fn set_frame_debug_names(&self) {
+        let debug_utils_loader = match self.debug_utils_loader.as_ref() {
+            Some(debug_utils_loader) => debug_utils_loader,
+            None => return,
+        };
+        for (frame, command_buffers) in self.command_buffers.iter().enumerate() {
+            for command_buffer in command_buffers.iter() {
+                Self::set_debug_utils_object_name(debug_utils_loader, &self.device, vk::ObjectType::COMMAND_BUFFER, command_buffer.as_raw(), &format!("frame {} command buffer", frame));
+            }
+        }
+        for (index, image) in self.swapchain_images.iter().enumerate() {
+            Self::set_debug_utils_object_name(debug_utils_loader, &self.device, vk::ObjectType::IMAGE, image.as_raw(), &format!("swapchain image {}", index));
+        }
+        let attachments = [
+            ("scene image", &self.scene_image_allocation),
+            ("multisampled color attachment", &self.color_image_allocation),
+            ("depth attachment", &self.depth_image_allocation),
+            ("depth resolve attachment", &self.depth_resolve_image_allocation),
+            ("object ID attachment", &self.object_id_image_allocation),
+            ("object ID resolve attachment", &self.object_id_resolve_image_allocation),
+        ];
+        for (name, allocation) in attachments {
+            if let Some(image) = allocation.as_ref().and_then(|allocation| allocation.get_image()) {
+                Self::set_debug_utils_object_name(debug_utils_loader, &self.device, vk::ObjectType::IMAGE, image.as_raw(), name);
+            }
+        }
+        for (index, (allocation, resolve_allocation)) in self.extra_color_attachment_allocations.iter().enumerate() {
+            Self::set_debug_utils_object_name(debug_utils_loader, &self.device, vk::ObjectType::IMAGE, allocation.get_image().unwrap().as_raw(), &format!("extra color attachment {}", index));
+            if let Some(resolve_allocation) = resolve_allocation {
+                Self::set_debug_utils_object_name(debug_utils_loader, &self.device, vk::ObjectType::IMAGE, resolve_allocation.get_image().unwrap().as_raw(), &format!("extra color resolve attachment {}", index));
+            }
+        }
+        Self::set_debug_utils_object_name(debug_utils_loader, &self.device, vk::ObjectType::FRAMEBUFFER, self.scene_framebuffer.as_raw(), "scene framebuffer");
+    }
+
+    /// Calls `recording` every frame inside the main render pass, after the objects and text, so custom draws like a UI can be composited over the scene.
+    /// The engine's pipeline, buffers, viewport and scissor are not kept, so the recording has to bind and set its own.
+    /// Its pipelines have to be compatible with [`VkController::render_pass`] and use [`VkController::msaa_samples`]. Replaces the previous recording.
+    pub fn with_extra_recording(&mut self, recording: impl FnMut(vk::CommandBuffer) + 'static) {
+        self.extra_recording = Some(Box::new(recording));
+    }
+
+    pub fn clear_extra_recording(&mut self) {
+        self.extra_recording = None;
+    }
+
+    /// The command buffer of the frame that is being drawn, or that is drawn next outside of [`VkController::with_extra_recording`].
+    pub fn current_command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffers[self.current_frame][0]
+    }
+
+    /// The framebuffer of the main pass. It changes when the swapchain or the render pass is recreated.
+    pub fn current_framebuffer(&self) -> vk::Framebuffer {
+        self.scene_framebuffer
+    }
+
+    /// The main pass, which is recreated when picking, the extra color attachments or the clear mode change. Pipelines stay compatible with the new one.
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.graphics_pipeline_manager.get_render_pass().unwrap()
+    }
+
+    /// Set with [`VkControllerBuilder::frames_in_flight`]. Removed objects' buffers are freed and instance changes are copied to every buffer after this many frames.
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    /// For creating the resources of custom recordings. It has to outlive them, so they have to be destroyed before the controller.
+    pub fn device(&self) -> Arc<Device> {
+        self.device.clone()
+    }
+
+    pub fn msaa_samples(&self) -> vk::SampleCountFlags {
+        self.msaa_samples
+    }
+
+    /// The format the main pass draws in, which the pipelines of [`VkController::with_extra_recording`] have to use for their first color attachment.
+    pub fn scene_format(&self) -> vk::Format {
+        self.scene_format
+    }
+
+    /// Captures each of the next `num_frames` drawn frames in RenderDoc. Returns false and does nothing when [`VkController::is_capture_available`] is false.
+    pub fn trigger_capture(&mut self, num_frames: u32) -> bool {
+        self.render_doc_capture.trigger(num_frames)
+    }
+
+    /// Whether the application was started from RenderDoc and built with the `renderdoc` feature.
+    pub fn is_capture_available(&self) -> bool {
+        self.render_doc_capture.is_available()
+    }
+
+    /// The number of commands recorded inside the render pass for the last drawn frame.
+    pub fn get_num_recorded_commands(&self) -> usize {
+        self.num_recorded_commands
+    }
+}
+
+// Debugging and validation
+impl VkController {
+    fn setup_debug_messenger(entry: &Entry, instance: &Instance, debug_utils_create_info: DebugUtilsMessengerCreateInfoEXT) -> Result<vk::DebugUtilsMessengerEXT, Cow<'static, str>> {
+        let debug_utils_loader = DebugUtils::new(entry, instance);
+        unsafe {
+            debug_utils_loader.create_debug_utils_messenger(&debug_utils_create_info, None)
+        }.map_err(|e| Cow::Owned(format!("Failed to set up debug messenger: {}", e)))
+    }
+
+    pub fn set_debug_utils_object_name(debug_utils_loader: &DebugUtils, device: &Device, object_type: vk::ObjectType, object_handle: u64, name: &str) {
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(e) => {
+                log::error!(target: logging::RENDERER, "Failed to create the debug name {:?}: {}", name, e);
+                return;
+            },
+        };
+        let name_info = vk::DebugUtilsObjectNameInfoEXT {
+            s_type: StructureType::DEBUG_UTILS_OBJECT_NAME_INFO_EXT,
+            object_type,
+            object_handle,
+            p_object_name: name.as_ptr(),
+            ..Default::default()
+        };
+        if let Err(e) = unsafe { debug_utils_loader.set_debug_utils_object_name(device.handle(), &name_info) } {
+            log::error!(target: logging::RENDERER, "Failed to set the debug name {:?}: {:?}", name, e);
+        }
+    }
+
+    fn get_debug_messenger_create_info() -> DebugUtilsMessengerCreateInfoEXT {
+        DebugUtilsMessengerCreateInfoEXT {
+            s_type: StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::INFO | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            pfn_user_callback: Some(Self::debug_callback),
+            ..Default::default()
+        }
+    }
+
+    unsafe extern "system" fn debug_callback(
         message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
         message_type: vk::DebugUtilsMessageTypeFlagsEXT,
         p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
@@ -1069,17 +4220,17 @@ impl VkController {
             _ => "Unknown",
         };
 
-        let debug_severity = match message_severity {
-            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "Verbose",
-            vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "Info",
-            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "Warning",
-            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "Error",
-            _ => "Unknown",
+        let level = match message_severity {
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => log::Level::Trace,
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::Level::Debug,
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Warn,
+            _ => log::Level::Error,
         };
 
-        if message_severity >= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING {
+        // The message is only read when it is logged, since the verbose messages come for almost every call
+        if log::log_enabled!(target: logging::VALIDATION, level) {
             let message = std::ffi::CStr::from_ptr((*p_callback_data).p_message).to_string_lossy();
-            println!("[Debug][{debug_type}][{debug_severity}]: {:?}", message);
+            log::log!(target: logging::VALIDATION, level, "[{}] {}", debug_type, message);
         }
 
         vk::FALSE
@@ -1088,11 +4239,16 @@ impl VkController {
 
 
 pub trait VkControllerGraphicsObjectsControl<T: Vertex + Clone> {
-    fn add_objects_to_render(&mut self, original_objects: Vec<Arc<RwLock<dyn GraphicsObject<T>>>>) -> Result<Vec<(ObjectID, Arc<RwLock<dyn GraphicsObject<T>>>)>, Cow<'static, str>>;
+    fn add_objects_to_render(&mut self, original_objects: Vec<Arc<RwLock<dyn GraphicsObject<T>>>>) -> Result<Vec<(ObjectID, Arc<RwLock<dyn GraphicsObject<T>>>)>, EngineError>;
+    /// Adds every level of the groups as objects and returns their ids, in the same order as the groups and their levels. The level of each group is chosen every frame before it's drawn.
+    fn add_lod_objects_to_render(&mut self, lod_groups: Vec<Arc<RwLock<LodGroup<T>>>>) -> Result<Vec<Vec<ObjectID>>, EngineError>;
+    /// Returns right away, the meshes are read and checked on a background thread and the objects are drawn once the GPU has their data. See [`VkController::poll_load`].
+    /// The uploads go through the graphics queue's staging ring like the other uploads, since the mipmaps are made with blits that need a graphics queue anyway.
+    fn add_objects_async(&mut self, original_objects: Vec<Arc<RwLock<dyn GraphicsObject<T> + Send + Sync>>>) -> LoadHandle;
 }
 
 impl<T: Vertex + Clone + 'static> VkControllerGraphicsObjectsControl<T> for VkController {
-    fn add_objects_to_render(&mut self, original_objects: Vec<Arc<RwLock<dyn GraphicsObject<T>>>>) -> Result<Vec<(ObjectID, Arc<RwLock<dyn GraphicsObject<T>>>)>, Cow<'static, str>> {
+    fn add_objects_to_render(&mut self, original_objects: Vec<Arc<RwLock<dyn GraphicsObject<T>>>>) -> Result<Vec<(ObjectID, Arc<RwLock<dyn GraphicsObject<T>>>)>, EngineError> {
         let object_ids = self.object_manager.generate_currently_unused_ids(original_objects.len())?;
         let mut object_id_to_object = Vec::with_capacity(original_objects.len());
         let mut objects_to_render = Vec::with_capacity(original_objects.len());
@@ -1104,9 +4260,675 @@ impl<T: Vertex + Clone + 'static> VkControllerGraphicsObjectsControl<T> for VkCo
             object_id_to_object.push((object_id, object.clone()));
             i += 1;
         }
-        dbg!("Adding objects to object manager!");
-        self.object_manager.add_objects(objects_to_render, &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.descriptor_pool, &self.graphics_queue, &mut self.sampler_manager, self.msaa_samples, self.swapchain_image_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, self.current_frame, &mut self.graphics_pipeline_manager, &mut self.allocator)?;
-        dbg!("Objects added to object manager!");
+        self.object_manager.add_objects(objects_to_render, &self.device, &self.instance, &self.physical_device, &self.command_pool, &self.descriptor_pool, &self.graphics_queue, &mut self.sampler_manager, self.msaa_samples, self.scene_format, Self::find_depth_format(&self.instance, &self.physical_device), &self.swapchain_extent, self.current_frame, &mut self.graphics_pipeline_manager, &self.allocator)?;
+        log::debug!(target: logging::RENDERER, "Added {} objects to the object manager", object_id_to_object.len());
         Ok(object_id_to_object)
     }
+
+    fn add_objects_async(&mut self, original_objects: Vec<Arc<RwLock<dyn GraphicsObject<T> + Send + Sync>>>) -> LoadHandle {
+        self.async_loader.start(original_objects, self.object_manager.is_mesh_validation_enabled())
+    }
+
+    fn add_lod_objects_to_render(&mut self, lod_groups: Vec<Arc<RwLock<LodGroup<T>>>>) -> Result<Vec<Vec<ObjectID>>, EngineError> {
+        let lod_groups = lod_groups.iter().map(|lod_group| lod_group.read().unwrap()).collect::<Vec<_>>();
+        for lod_group in lod_groups.iter() {
+            lod_group.validate()?;
+        }
+
+        let levels = lod_groups.iter().flat_map(|lod_group| lod_group.levels.iter().cloned()).collect::<Vec<_>>();
+        let mut object_ids = self.add_objects_to_render(levels)?.into_iter().map(|(object_id, _)| object_id);
+        let mut lod_group_object_ids = Vec::with_capacity(lod_groups.len());
+        for lod_group in lod_groups.iter() {
+            let level_object_ids = object_ids.by_ref().take(lod_group.levels.len()).collect::<Vec<_>>();
+            self.object_manager.add_lod_group(level_object_ids.clone(), lod_group.switch_distances.clone(), lod_group.hysteresis)?;
+            lod_group_object_ids.push(level_object_ids);
+        }
+        Ok(lod_group_object_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{gpu_layout::GpuLayout, graphics_objects::LitRenderableObject, lighting::LitVertex, logging::test_logger::{capture_logs, has_log}};
+
+    use super::*;
+
+    /// None when there is no Vulkan device with headless surfaces, the tests that need one are skipped then.
+    fn create_headless_controller(builder: VkControllerBuilder) -> Option<VkController> {
+        match builder.validation(false).try_build_headless(vk::Extent2D { width: 320, height: 240 }, "artewald-engine-2 tests") {
+            Ok(controller) => Some(controller),
+            Err(e) => {
+                eprintln!("Skipping the test, the headless renderer could not be created: {}", e);
+                None
+            },
+        }
+    }
+
+    fn get_object_draw(draw_index: usize, pipeline: u64, object_type: u64) -> ObjectDraw {
+        ObjectDraw {
+            draw_index,
+            pipeline: vk::Pipeline::from_raw(pipeline),
+            pipeline_layout: vk::PipelineLayout::from_raw(pipeline),
+            pipeline_label: Some(format!("pipeline {}", pipeline)),
+            draw_batch: DrawBatch {
+                object_type: ObjectType::from_hash(VerticesIndicesHash(object_type)),
+                vertex_buffer: vk::Buffer::from_raw(100 + pipeline),
+                first_vertex: 0,
+                num_vertices: 4,
+                index_buffer: Some(vk::Buffer::from_raw(200 + pipeline)),
+                index_type: vk::IndexType::UINT32,
+                first_index: 6 * draw_index as u32,
+                num_indices: 6,
+                first_instance: 0,
+                num_instances: 2,
+                descriptor_set: vk::DescriptorSet::from_raw(300 + object_type),
+                num_dynamic_uniform_buffers: 0,
+                dynamic_uniform_buffer_stride: 0,
+            },
+        }
+    }
+
+    fn record_trace(draws: &[ObjectDraw]) -> (Vec<DrawCommand>, usize) {
+        let mut trace = Vec::new();
+        let num_recorded_commands = VkController::record_object_draws(&mut trace, draws, vk::Rect2D::default(), vk::DescriptorSet::from_raw(1), 0, None, &mut BoundDrawState::default());
+        (trace, num_recorded_commands)
+    }
+
+    #[test]
+    fn recording_the_same_draws_twice_gives_the_same_trace() {
+        let draws = [get_object_draw(0, 1, 10), get_object_draw(1, 1, 11), get_object_draw(2, 2, 12), get_object_draw(3, 2, 13)];
+        let (first_trace, first_num_recorded_commands) = record_trace(&draws);
+        let (second_trace, second_num_recorded_commands) = record_trace(&draws);
+        assert!(first_trace == second_trace);
+        assert_eq!(first_num_recorded_commands, second_num_recorded_commands);
+        assert_eq!(first_num_recorded_commands, first_trace.len());
+    }
+
+    #[test]
+    fn draws_are_recorded_in_the_given_order() {
+        let draws = [get_object_draw(0, 2, 12), get_object_draw(1, 1, 10), get_object_draw(2, 1, 11)];
+        let (trace, _) = record_trace(&draws);
+        let draw_ids = trace.iter().filter_map(|command| match command {
+            DrawCommand::PushDrawId { draw_id, .. } => Some(*draw_id),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(draw_ids, vec![1, 2, 3]);
+        let labels = trace.iter().filter_map(|command| match command {
+            DrawCommand::BeginLabel(name) => Some(name.as_str()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["pipeline 2", "draw ObjectType(12) x2", "pipeline 1", "draw ObjectType(10) x2", "draw ObjectType(11) x2"]);
+        // Every label is closed
+        assert_eq!(trace.iter().filter(|command| **command == DrawCommand::EndLabel).count(), labels.len());
+    }
+
+    #[test]
+    fn swapchain_is_recreated_through_five_seconds_of_resizes() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        // Includes a minimized size, and resizes both between frames and several times before one frame
+        let extents = [(640, 480), (320, 200), (0, 0), (1024, 768), (200, 600), (200, 601)];
+        let start = Instant::now();
+        let mut num_resizes = 0;
+        while start.elapsed() < Duration::from_secs(5) {
+            let (width, height) = extents[num_resizes % extents.len()];
+            controller.set_window_extent(vk::Extent2D { width, height });
+            num_resizes += 1;
+            if num_resizes % 3 != 0 {
+                controller.draw_frame(u64::MAX);
+            }
+            assert!(controller.retired_swapchains.len() <= controller.frames_in_flight + 1);
+        }
+        controller.set_window_extent(vk::Extent2D { width: 320, height: 240 });
+        for _ in 0..controller.frames_in_flight + 2 {
+            controller.draw_frame(u64::MAX);
+        }
+        assert_ne!(controller.swapchain, SwapchainKHR::null());
+        assert!(controller.swapchain_extent == vk::Extent2D { width: 320, height: 240 });
+        assert!(controller.retired_swapchains.is_empty());
+        controller.cleanup();
+    }
+
+    fn get_lit_object() -> Arc<RwLock<dyn GraphicsObject<LitVertex>>> {
+        get_lit_object_at(glm::Mat4::identity())
+    }
+
+    // The objects share the mesh, so they are instances of the same object type
+    fn get_lit_object_at(model_matrix: glm::Mat4) -> Arc<RwLock<dyn GraphicsObject<LitVertex>>> {
+        let vertices = [glm::vec3(0.0, 0.5, 0.0), glm::vec3(-0.5, -0.5, 0.0), glm::vec3(0.5, -0.5, 0.0)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+        Arc::new(RwLock::new(LitRenderableObject::new(vertices.to_vec(), vec![0, 1, 2], image::DynamicImage::new_rgba8(2, 2), model_matrix)))
+    }
+
+    #[test]
+    fn upload_contexts_prepare_objects_on_several_threads_while_frames_are_drawn() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        const NUM_WORKERS: usize = 4;
+        const NUM_OBJECTS_PER_WORKER: usize = 8;
+        let upload_context = controller.create_upload_context();
+        let prepared_objects = std::thread::scope(|scope| {
+            // Every worker has its own mesh, which all of its objects share, so the commit also frees the duplicate uploads
+            let workers = (0..NUM_WORKERS).map(|worker_index| {
+                let upload_context = upload_context.clone();
+                scope.spawn(move || (0..NUM_OBJECTS_PER_WORKER).map(|_| {
+                    let vertices = [glm::vec3(0.0, 0.5, 0.0), glm::vec3(-0.5, -0.5, 0.0), glm::vec3(0.5, -0.5, worker_index as f32)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+                    let object = LitRenderableObject::new(vertices.to_vec(), vec![0, 1, 2], image::DynamicImage::new_rgba8(2, 2), glm::Mat4::identity());
+                    upload_context.prepare(Arc::new(RwLock::new(object)) as Arc<RwLock<dyn GraphicsObject<LitVertex> + Send + Sync>>).unwrap()
+                }).collect::<Vec<_>>())
+            }).collect::<Vec<_>>();
+            // The render thread keeps submitting while the workers upload
+            while !workers.iter().all(|worker| worker.is_finished()) {
+                controller.draw_frame(u64::MAX);
+            }
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert_eq!(prepared_objects.iter().map(|prepared_object| prepared_object.get_vertices_and_indices_hash()).collect::<HashSet<_>>().len(), NUM_WORKERS);
+
+        let object_ids = controller.commit_prepared(prepared_objects).unwrap();
+        assert_eq!(object_ids.len(), NUM_WORKERS * NUM_OBJECTS_PER_WORKER);
+        assert!(object_ids.iter().all(|object_id| controller.is_object_visible(*object_id) == Some(true)));
+        for _ in 0..=VkController::MAX_FRAMES_IN_FLIGHT {
+            controller.draw_frame(u64::MAX);
+        }
+        controller.remove_objects_to_render(object_ids).unwrap();
+        controller.draw_frame(u64::MAX);
+        controller.cleanup();
+    }
+
+    #[test]
+    fn async_load_and_queued_removal_move_on_with_the_frames() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let vertices = [glm::vec3(0.0, 0.5, 0.0), glm::vec3(-0.5, -0.5, 0.0), glm::vec3(0.5, -0.5, 0.0)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+        let object = LitRenderableObject::new(vertices.to_vec(), vec![0, 1, 2], image::DynamicImage::new_rgba8(2, 2), glm::Mat4::identity());
+        let handle = controller.add_objects_async(vec![Arc::new(RwLock::new(object)) as Arc<RwLock<dyn GraphicsObject<LitVertex> + Send + Sync>>]);
+        let mut object_ids = None;
+        for _ in 0..1000 {
+            match controller.poll_load(handle) {
+                Some(LoadStatus::Preparing | LoadStatus::Uploading) => {
+                    controller.draw_frame(u64::MAX);
+                },
+                Some(LoadStatus::Ready(ids, frame_index)) => {
+                    assert!(frame_index >= controller.time().frame_index());
+                    object_ids = Some(ids);
+                    break;
+                },
+                status => panic!("The load ended up {:?}", status),
+            }
+        }
+        let object_ids = object_ids.expect("The load was never ready");
+        assert_eq!(controller.is_object_visible(object_ids[0]), Some(true));
+        assert!(controller.poll_load(handle).is_none());
+
+        let removal = controller.queue_remove_objects(object_ids).unwrap();
+        for _ in 0..controller.frames_in_flight() - 1 {
+            assert_eq!(controller.poll_removal(removal), Some(RemovalStatus::Pending));
+            controller.draw_frame(u64::MAX);
+        }
+        assert_eq!(controller.poll_removal(removal), Some(RemovalStatus::Pending));
+        controller.draw_frame(u64::MAX);
+        assert_eq!(controller.poll_removal(removal), Some(RemovalStatus::Freed));
+        assert_eq!(controller.poll_removal(removal), None);
+        controller.cleanup();
+    }
+
+    #[test]
+    fn frames_in_flight_outside_the_supported_range_fail_to_build() {
+        assert_eq!(VkControllerBuilder::new().frames_in_flight, 2);
+        for frames_in_flight in 1..=VkController::MAX_FRAMES_IN_FLIGHT {
+            assert!(VkControllerBuilder::new().frames_in_flight(frames_in_flight).validate().is_ok());
+        }
+        for frames_in_flight in [0, VkController::MAX_FRAMES_IN_FLIGHT + 1, 8] {
+            let builder = VkControllerBuilder::new().frames_in_flight(frames_in_flight);
+            assert_eq!(builder.frames_in_flight, frames_in_flight);
+            assert!(matches!(builder.validate(), Err(EngineError::Other(_))));
+            assert!(matches!(builder.try_build_headless(vk::Extent2D { width: 320, height: 240 }, "artewald-engine-2 tests"), Err(EngineError::Other(_))));
+        }
+    }
+
+    // Draws an object through a few rounds of the frames in flight, then removes it and waits for its resources to be freed
+    fn draw_and_remove_with_frames_in_flight(frames_in_flight: usize) {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new().frames_in_flight(frames_in_flight)) else {
+            return;
+        };
+        assert_eq!(controller.frames_in_flight(), frames_in_flight);
+        assert_eq!(controller.in_flight_fences.len(), frames_in_flight);
+        assert_eq!(controller.command_buffers.len(), frames_in_flight);
+
+        let object_id = controller.add_objects_to_render(vec![get_lit_object()]).unwrap()[0].0;
+        let mut used_frames = HashSet::new();
+        for _ in 0..3 * frames_in_flight {
+            used_frames.insert(controller.current_frame);
+            controller.draw_frame(u64::MAX);
+            assert!(controller.current_frame < frames_in_flight);
+        }
+        assert_eq!(used_frames, (0..frames_in_flight).collect::<HashSet<_>>());
+
+        let removal = controller.queue_remove_objects(vec![object_id]).unwrap();
+        for _ in 0..frames_in_flight {
+            assert_eq!(controller.poll_removal(removal), Some(RemovalStatus::Pending));
+            controller.draw_frame(u64::MAX);
+        }
+        assert_eq!(controller.poll_removal(removal), Some(RemovalStatus::Freed));
+        controller.cleanup();
+    }
+
+    #[test]
+    fn renders_with_one_frame_in_flight() {
+        draw_and_remove_with_frames_in_flight(1);
+    }
+
+    #[test]
+    fn renders_with_three_frames_in_flight() {
+        draw_and_remove_with_frames_in_flight(3);
+    }
+
+    #[test]
+    fn samplers_of_removed_textured_objects_are_destroyed() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        // The texture is freed after the frames in flight, and then its sampler after another round of them
+        let draw_until_idle_samplers_are_destroyed = |controller: &mut VkController| {
+            for _ in 0..2 * controller.frames_in_flight() + 2 {
+                controller.draw_frame(u64::MAX);
+            }
+        };
+        draw_until_idle_samplers_are_destroyed(&mut controller);
+        let baseline = controller.get_sampler_stats().live_samplers;
+
+        for _ in 0..5 {
+            let object_ids = controller.add_objects_to_render(vec![get_lit_object(), get_lit_object()]).unwrap().into_iter().map(|(object_id, _)| object_id).collect::<Vec<_>>();
+            controller.draw_frame(u64::MAX);
+            assert!(controller.get_sampler_stats().live_samplers >= baseline);
+            controller.remove_objects_to_render(object_ids).unwrap();
+            draw_until_idle_samplers_are_destroyed(&mut controller);
+            assert_eq!(controller.get_sampler_stats().live_samplers, baseline);
+        }
+        controller.cleanup();
+    }
+
+    #[test]
+    fn every_frame_snapshots_the_last_write_before_it() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let vertices = [glm::vec3(0.0, 0.5, 0.0), glm::vec3(-0.5, -0.5, 0.0), glm::vec3(0.5, -0.5, 0.0)].map(|position| LitVertex { position, normal: glm::Vec3::z(), tex_coord: glm::Vec2::zeros() });
+        let object = LitRenderableObject::new(vertices.to_vec(), vec![0, 1, 2], image::DynamicImage::new_rgba8(2, 2), glm::Mat4::identity());
+        let model_matrix = object.model_matrix.clone();
+        let object_id = controller.add_objects_to_render(vec![Arc::new(RwLock::new(object)) as Arc<RwLock<dyn GraphicsObject<LitVertex>>>]).unwrap()[0].0;
+        let get_snapshot = |controller: &VkController| controller.object_manager.get_instance_data(object_id, ResourceID(0)).unwrap().to_vec();
+
+        for value in [1.0, 2.0] {
+            let version = controller.renderable_data_version();
+            // Only the last of the writes before the frame is drawn
+            model_matrix.write().unwrap().buffer = glm::Mat4::from_element(-value);
+            model_matrix.write().unwrap().buffer = glm::Mat4::from_element(value);
+            controller.draw_frame(u64::MAX);
+            assert_eq!(controller.renderable_data_version(), RenderableDataVersion(version.0 + 1));
+            assert_eq!(get_snapshot(&controller), glm::Mat4::from_element(value).std430_bytes());
+        }
+
+        // A write after the frame waits for the next snapshot
+        model_matrix.write().unwrap().buffer = glm::Mat4::from_element(3.0);
+        assert_eq!(get_snapshot(&controller), glm::Mat4::from_element(2.0).std430_bytes());
+        controller.cleanup();
+    }
+
+    #[test]
+    fn removing_the_middle_instance_keeps_the_slots_and_data_of_the_others() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let objects = [1.0, 2.0, 3.0].map(|x| get_lit_object_at(glm::translation(&glm::vec3(x, 0.0, 0.0))));
+        let object_ids = controller.add_objects_to_render(objects.to_vec()).unwrap().into_iter().map(|(object_id, _)| object_id).collect::<Vec<_>>();
+        let model_matrix_id = ResourceID(0);
+        let get_slot_and_data = |controller: &VkController, object_id: ObjectID| (controller.instance_slot(object_id).unwrap(), controller.object_manager.get_instance_data(object_id, model_matrix_id).unwrap().to_vec());
+        let first = get_slot_and_data(&controller, object_ids[0]);
+        let last = get_slot_and_data(&controller, object_ids[2]);
+        assert_ne!(first, last);
+        controller.draw_frame(u64::MAX);
+
+        controller.remove_objects_to_render(vec![object_ids[1]]).unwrap();
+        assert_eq!(get_slot_and_data(&controller, object_ids[0]), first);
+        assert_eq!(get_slot_and_data(&controller, object_ids[2]), last);
+        assert_eq!(controller.instance_slot(object_ids[1]), None);
+        controller.draw_frame(u64::MAX);
+        assert_eq!(get_slot_and_data(&controller, object_ids[0]), first);
+        assert_eq!(get_slot_and_data(&controller, object_ids[2]), last);
+        controller.cleanup();
+    }
+
+    #[test]
+    fn object_types_keep_their_descriptor_sets_while_the_slots_fit_in_the_buffers() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let first_id = controller.add_objects_to_render(vec![get_lit_object()]).unwrap()[0].0;
+        let get_descriptor_sets = |controller: &VkController| controller.object_manager.get_descriptor_sets(first_id).unwrap().to_vec();
+        let initial_descriptor_sets = get_descriptor_sets(&controller);
+        controller.draw_frame(u64::MAX);
+
+        // The buffers only have room for the first object, so the second one gives the object type new buffers and new sets that point at them
+        let second_id = controller.add_objects_to_render(vec![get_lit_object_at(glm::translation(&glm::vec3(1.0, 0.0, 0.0)))]).unwrap()[0].0;
+        let grown_descriptor_sets = get_descriptor_sets(&controller);
+        assert_ne!(grown_descriptor_sets, initial_descriptor_sets);
+        controller.draw_frame(u64::MAX);
+
+        // The next object gets the freed slot, which is still in the buffers
+        controller.remove_objects_to_render(vec![second_id]).unwrap();
+        assert_eq!(get_descriptor_sets(&controller), grown_descriptor_sets);
+        controller.add_objects_to_render(vec![get_lit_object()]).unwrap();
+        assert_eq!(get_descriptor_sets(&controller), grown_descriptor_sets);
+        controller.draw_frame(u64::MAX);
+        controller.cleanup();
+    }
+
+    #[test]
+    fn draw_views_only_replaces_the_viewport_regions_for_its_frame() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let camera = controller.add_camera(glm::Mat4::identity(), glm::Mat4::identity());
+        controller.set_viewports(vec![
+            ViewportRegion { rect: (0.0, 0.0, 0.5, 1.0), camera, object_filter: None },
+            ViewportRegion { rect: (0.5, 0.0, 0.5, 1.0), camera, object_filter: None },
+        ]).unwrap();
+        let get_render_rects = |controller: &VkController| controller.get_views().into_iter().map(|FrameView { render_rect, .. }| (render_rect.offset.x, render_rect.offset.y, render_rect.extent.width, render_rect.extent.height)).collect::<Vec<_>>();
+        let region_rects = get_render_rects(&controller);
+        assert_eq!(region_rects.len(), 2);
+
+        let render_rect = vk::Rect2D { offset: vk::Offset2D { x: 10, y: 20 }, extent: vk::Extent2D { width: 100, height: 50 } };
+        controller.draw_views(&[(render_rect, glm::Mat4::identity())]);
+        assert_eq!(get_render_rects(&controller), region_rects);
+        // The camera is still used by the regions
+        assert!(controller.remove_camera(camera).is_err());
+        controller.set_viewports(Vec::new()).unwrap();
+        assert_eq!(get_render_rects(&controller).len(), 1);
+        controller.remove_camera(camera).unwrap();
+        controller.cleanup();
+    }
+
+    #[test]
+    fn removing_an_unknown_object_fails_and_keeps_the_others() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let object_id = controller.add_objects_to_render(vec![get_lit_object()]).unwrap()[0].0;
+        let unknown_id = ObjectID(object_id.0.wrapping_add(1));
+
+        let (result, captured_logs) = capture_logs(|| controller.remove_objects_to_render(vec![object_id, unknown_id]));
+        assert!(matches!(result, Err(EngineError::ObjectNotFound(id)) if id == unknown_id));
+        assert!(has_log(&captured_logs, log::Level::Warn, logging::OBJECTS, &format!("Object with id {:?} not found", unknown_id)));
+        // Nothing was removed, so the known object is still drawn
+        assert_eq!(controller.is_object_visible(object_id), Some(true));
+        controller.draw_frame(u64::MAX);
+
+        controller.remove_objects_to_render(vec![object_id]).unwrap();
+        let result = controller.remove_objects_to_render(vec![object_id]);
+        assert!(matches!(result, Err(EngineError::ObjectNotFound(id)) if id == object_id));
+        controller.cleanup();
+    }
+
+    #[test]
+    fn allocations_past_the_memory_budget_fail_with_out_of_device_memory() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        controller.set_memory_budget_fraction(0.0);
+        let result = controller.allocator.create_buffer(MIB, vk::BufferUsageFlags::VERTEX_BUFFER, vk::MemoryPropertyFlags::DEVICE_LOCAL, true);
+        let error = result.unwrap_err();
+        assert!(matches!(error, EngineError::OutOfDeviceMemory { bytes_requested: MIB, .. }), "{}", error);
+        assert!(error.is_out_of_device_memory());
+
+        controller.set_memory_budget_fraction(VkAllocator::DEFAULT_MEMORY_BUDGET_FRACTION);
+        let allocation = controller.allocator.create_buffer(MIB, vk::BufferUsageFlags::VERTEX_BUFFER, vk::MemoryPropertyFlags::DEVICE_LOCAL, true).unwrap();
+        controller.allocator.free_memory_allocation(allocation).unwrap();
+        controller.cleanup();
+    }
+
+    #[test]
+    fn every_uniform_and_storage_pointer_is_aligned_to_the_device_limit() {
+        let Some(mut controller) = create_headless_controller(VkControllerBuilder::new()) else {
+            return;
+        };
+        let limits = unsafe { controller.instance.get_physical_device_properties(controller.physical_device) }.limits;
+        for buffer_size in [1, 4, 64, 100, 257, 4096] {
+            for num_buffers in 1..=4 {
+                let uniform_buffers = controller.allocator.create_uniform_buffers(buffer_size, num_buffers).unwrap();
+                let storage_buffers = controller.allocator.create_storage_buffers(buffer_size, num_buffers).unwrap();
+                for (allocation, alignment) in [(&uniform_buffers, limits.min_uniform_buffer_offset_alignment), (&storage_buffers, limits.min_storage_buffer_offset_alignment)] {
+                    let pointers = allocation.get_uniform_pointers();
+                    assert_eq!(pointers.len(), num_buffers);
+                    // The buffer starts at offset 0, so the offset of each part is its distance from the first one
+                    for &pointer in pointers {
+                        let offset = pointer as usize - pointers[0] as usize;
+                        assert_eq!(offset as vk::DeviceSize % alignment.max(1), 0, "The part at offset {} of {} byte buffers isn't aligned to {}", offset, buffer_size, alignment);
+                        assert!(offset as vk::DeviceSize + allocation.get_uniform_buffer_size() <= allocation.get_size());
+                    }
+                }
+                controller.allocator.free_memory_allocation(uniform_buffers).unwrap();
+                controller.allocator.free_memory_allocation(storage_buffers).unwrap();
+            }
+        }
+        controller.cleanup();
+    }
+
+    #[test]
+    fn state_shared_by_object_types_is_bound_once() {
+        // 100 object types with the same pipeline and buffers, each with its own descriptor set
+        let draws = (0..100).map(|draw_index| ObjectDraw { pipeline_label: None, ..get_object_draw(draw_index, 1, 10 + draw_index as u64) }).collect::<Vec<_>>();
+        let (trace, num_recorded_commands) = record_trace(&draws);
+        let count = |is_command: fn(&DrawCommand) -> bool| trace.iter().filter(|command| is_command(command)).count();
+        assert_eq!(count(|command| matches!(command, DrawCommand::BindPipeline(_))), 1);
+        assert_eq!(count(|command| matches!(command, DrawCommand::SetViewport(_))), 1);
+        assert_eq!(count(|command| matches!(command, DrawCommand::SetScissor(_))), 1);
+        assert_eq!(count(|command| matches!(command, DrawCommand::BindVertexBuffer(_))), 1);
+        assert_eq!(count(|command| matches!(command, DrawCommand::BindIndexBuffer(..))), 1);
+        assert_eq!(count(|command| matches!(command, DrawCommand::BindDescriptorSet { set_index: 0, .. })), 1);
+        assert_eq!(count(|command| matches!(command, DrawCommand::BindDescriptorSet { set_index: 1, .. })), 100);
+        assert_eq!(count(|command| matches!(command, DrawCommand::DrawIndexed { .. })), 100);
+        // The shared state once, then the descriptor set, draw id and draw of every object type. Binding everything for every object type would be 9 commands each
+        assert_eq!(num_recorded_commands, 6 + 3 * 100);
+        assert!(num_recorded_commands < 9 * 100);
+    }
+
+    #[test]
+    fn identical_descriptor_sets_are_not_bound_again() {
+        let draws = (0..3).map(|draw_index| ObjectDraw { pipeline_label: None, ..get_object_draw(draw_index, 1, 10) }).collect::<Vec<_>>();
+        let (trace, _) = record_trace(&draws);
+        assert_eq!(trace.iter().filter(|command| matches!(command, DrawCommand::BindDescriptorSet { set_index: 1, .. })).count(), 1);
+    }
+
+    fn get_surface_formats(formats: &[(vk::Format, vk::ColorSpaceKHR)]) -> Vec<vk::SurfaceFormatKHR> {
+        formats.iter().map(|&(format, color_space)| vk::SurfaceFormatKHR { format, color_space }).collect()
+    }
+
+    #[test]
+    fn surface_format_preference_comes_first() {
+        let available_formats = get_surface_formats(&[
+            (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+        ]);
+        let preference = [vk::Format::R16G16B16A16_SFLOAT, vk::Format::A2B10G10R10_UNORM_PACK32];
+        // The first preferred format is only available in another color space, so the second one is used
+        let chosen_format = VkController::choose_swap_surface_format(&available_formats, &preference);
+        assert!(chosen_format.format == vk::Format::A2B10G10R10_UNORM_PACK32);
+        assert!(chosen_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR);
+    }
+
+    #[test]
+    fn srgb_surface_formats_are_chosen_without_a_preference() {
+        let available_formats = get_surface_formats(&[
+            (vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        ]);
+        assert!(VkController::choose_swap_surface_format(&available_formats, &[]).format == vk::Format::B8G8R8A8_SRGB);
+        assert!(VkController::choose_swap_surface_format(&available_formats[..2], &[]).format == vk::Format::R8G8B8A8_SRGB);
+        // Any other sRGB format comes before the formats without sRGB
+        let available_formats = get_surface_formats(&[
+            (vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::A8B8G8R8_SRGB_PACK32, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        ]);
+        assert!(VkController::choose_swap_surface_format(&available_formats, &[]).format == vk::Format::A8B8G8R8_SRGB_PACK32);
+        // A preference that isn't available falls back to the same order
+        assert!(VkController::choose_swap_surface_format(&available_formats, &[vk::Format::R16G16B16A16_SFLOAT]).format == vk::Format::A8B8G8R8_SRGB_PACK32);
+    }
+
+    #[test]
+    fn first_surface_format_is_chosen_without_srgb() {
+        let available_formats = get_surface_formats(&[
+            (vk::Format::R8G8B8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT),
+        ]);
+        assert!(VkController::choose_swap_surface_format(&available_formats, &[]).format == vk::Format::R8G8B8A8_UNORM);
+    }
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn get_device_score(device_type: vk::PhysicalDeviceType, heaps: &[(u64, vk::MemoryHeapFlags)], num_optional_features: usize) -> i32 {
+        let device_properties = vk::PhysicalDeviceProperties {
+            device_type,
+            ..Default::default()
+        };
+        let is_supported = |index: usize| if index < num_optional_features { vk::TRUE } else { vk::FALSE };
+        let device_features = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: is_supported(0),
+            sample_rate_shading: is_supported(1),
+            fill_mode_non_solid: is_supported(2),
+            ..Default::default()
+        };
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (memory_heap, &(size, flags)) in memory_properties.memory_heaps.iter_mut().zip(heaps) {
+            *memory_heap = vk::MemoryHeap { size, flags };
+        }
+        VkController::get_physical_device_score(&device_properties, &device_features, &memory_properties)
+    }
+
+    #[test]
+    fn device_type_outweighs_memory_and_features() {
+        let huge_heap = [(1024 * 1024 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)];
+        let small_heap = [(256 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)];
+        let discrete_score = get_device_score(vk::PhysicalDeviceType::DISCRETE_GPU, &small_heap, 0);
+        let integrated_score = get_device_score(vk::PhysicalDeviceType::INTEGRATED_GPU, &huge_heap, 3);
+        let virtual_score = get_device_score(vk::PhysicalDeviceType::VIRTUAL_GPU, &huge_heap, 3);
+        let cpu_score = get_device_score(vk::PhysicalDeviceType::CPU, &huge_heap, 3);
+        let other_score = get_device_score(vk::PhysicalDeviceType::OTHER, &huge_heap, 3);
+        assert!(discrete_score > integrated_score);
+        assert!(integrated_score > virtual_score);
+        assert!(virtual_score > cpu_score);
+        assert!(cpu_score > other_score);
+    }
+
+    #[test]
+    fn largest_device_local_heap_is_scored_with_a_cap() {
+        let score = |heaps: &[(u64, vk::MemoryHeapFlags)]| get_device_score(vk::PhysicalDeviceType::DISCRETE_GPU, heaps, 0);
+        let base_score = score(&[]);
+        assert_eq!(score(&[(4096 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)]), base_score + 4096);
+        // Only the largest device local heap counts
+        assert_eq!(score(&[(1024 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL), (8192 * MIB, vk::MemoryHeapFlags::empty()), (2048 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)]), base_score + 2048);
+        assert_eq!(score(&[(1024 * 1024 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)]), base_score + 24_000);
+    }
+
+    #[test]
+    fn heaps_past_the_heap_count_are_ignored() {
+        let device_properties = vk::PhysicalDeviceProperties {
+            device_type: vk::PhysicalDeviceType::DISCRETE_GPU,
+            ..Default::default()
+        };
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties::default();
+        memory_properties.memory_heaps[0] = vk::MemoryHeap { size: 8192 * MIB, flags: vk::MemoryHeapFlags::DEVICE_LOCAL };
+        let score = VkController::get_physical_device_score(&device_properties, &vk::PhysicalDeviceFeatures::default(), &memory_properties);
+        assert_eq!(score, get_device_score(vk::PhysicalDeviceType::DISCRETE_GPU, &[], 0));
+    }
+
+    #[test]
+    fn optional_features_break_ties() {
+        let heap = [(2048 * MIB, vk::MemoryHeapFlags::DEVICE_LOCAL)];
+        let scores = (0..=3).map(|num_optional_features| get_device_score(vk::PhysicalDeviceType::INTEGRATED_GPU, &heap, num_optional_features)).collect::<Vec<_>>();
+        assert_eq!(scores.windows(2).map(|scores| scores[1] - scores[0]).collect::<Vec<_>>(), vec![100, 100, 100]);
+    }
+
+    #[test]
+    fn time_starts_at_zero() {
+        let time = Time::new(Instant::now());
+        assert_eq!(time.elapsed_seconds(), 0.0);
+        assert_eq!(time.delta_seconds(), 0.0);
+        assert_eq!(time.frame_index(), 0);
+        assert_eq!(time.max_delta_seconds(), Time::DEFAULT_MAX_DELTA_SECONDS);
+    }
+
+    #[test]
+    fn time_delta_is_the_time_since_the_last_update() {
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        time.update(start + Duration::from_millis(16));
+        assert!((time.delta_seconds() - 0.016).abs() < 1e-6);
+        assert_eq!(time.frame_index(), 1);
+        time.update(start + Duration::from_millis(48));
+        assert!((time.delta_seconds() - 0.032).abs() < 1e-6);
+        assert!((time.elapsed_seconds() - 0.048).abs() < 1e-6);
+        assert_eq!(time.frame_index(), 2);
+        // An instant before the last update gives no delta instead of a negative one
+        time.update(start);
+        assert_eq!(time.delta_seconds(), 0.0);
+        assert_eq!(time.frame_index(), 3);
+    }
+
+    #[test]
+    fn time_accumulates_fixed_steps() {
+        // 1/64 s is exact in binary, so the sum has no rounding error
+        let step = Duration::from_micros(15_625);
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        for frame in 1..=640 {
+            time.update(start + step * frame);
+            assert_eq!(time.delta_seconds(), 1.0 / 64.0);
+            assert_eq!(time.frame_index(), frame as u64);
+        }
+        assert_eq!(time.elapsed_seconds(), 10.0);
+    }
+
+    #[test]
+    fn time_delta_is_clamped_after_a_stall() {
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        time.update(start + Duration::from_secs(3));
+        assert_eq!(time.delta_seconds(), Time::DEFAULT_MAX_DELTA_SECONDS);
+        time.set_max_delta_seconds(0.5);
+        time.update(start + Duration::from_secs(6));
+        assert_eq!(time.delta_seconds(), 0.5);
+        assert!((time.elapsed_seconds() - (Time::DEFAULT_MAX_DELTA_SECONDS + 0.5)).abs() < 1e-6);
+        time.set_max_delta_seconds(-1.0);
+        assert_eq!(time.max_delta_seconds(), 0.0);
+    }
+
+    #[test]
+    fn paused_time_still_counts_frames() {
+        let start = Instant::now();
+        let mut time = Time::new(start);
+        time.update(start + Duration::from_millis(50));
+        time.set_paused(true);
+        time.update(start + Duration::from_millis(100));
+        time.update(start + Duration::from_millis(150));
+        assert_eq!(time.delta_seconds(), 0.0);
+        assert!((time.elapsed_seconds() - 0.05).abs() < 1e-6);
+        assert_eq!(time.frame_index(), 3);
+        // The paused time is not caught up on when the time is resumed
+        time.set_paused(false);
+        time.update(start + Duration::from_millis(175));
+        assert!((time.delta_seconds() - 0.025).abs() < 1e-6);
+        assert!((time.elapsed_seconds() - 0.075).abs() < 1e-6);
+    }
 }